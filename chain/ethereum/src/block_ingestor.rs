@@ -1,6 +1,8 @@
 use lazy_static;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use graph::components::server::health::{self, Subsystem};
+use graph::prelude::futures03::StreamExt;
 use graph::prelude::*;
 use web3::types::*;
 
@@ -9,6 +11,53 @@ lazy_static! {
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// How long we wait for the next new head before we consider a
+    /// `newHeads` subscription stalled and fall back to polling
+    static ref NEW_HEADS_TIMEOUT: Duration = std::env::var("GRAPH_ETHEREUM_NEW_HEADS_TIMEOUT")
+        .ok()
+        .map(|s| s.parse::<u64>().unwrap_or_else(|_| {
+            panic!("GRAPH_ETHEREUM_NEW_HEADS_TIMEOUT must be a number, but is `{}`", s)
+        }))
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(120));
+
+    /// How long we poll for new blocks after losing the `newHeads`
+    /// subscription before we try to subscribe again
+    static ref NEW_HEADS_RETRY_INTERVAL: Duration =
+        std::env::var("GRAPH_ETHEREUM_NEW_HEADS_RETRY_INTERVAL")
+            .ok()
+            .map(|s| s.parse::<u64>().unwrap_or_else(|_| {
+                panic!("GRAPH_ETHEREUM_NEW_HEADS_RETRY_INTERVAL must be a number, but is `{}`", s)
+            }))
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(60));
+
+    /// How often we poll for new blocks while we follow a `newHeads`
+    /// subscription, so that a subscription that stalls without ending
+    /// does not keep us from seeing new blocks for `NEW_HEADS_TIMEOUT`
+    static ref NEW_HEADS_POLLING_INTERVAL: Duration =
+        std::env::var("GRAPH_ETHEREUM_NEW_HEADS_POLLING_INTERVAL")
+            .ok()
+            .map(|s| s.parse::<u64>().unwrap_or_else(|_| {
+                panic!("GRAPH_ETHEREUM_NEW_HEADS_POLLING_INTERVAL must be a number, but is `{}`", s)
+            }))
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(10));
+}
+
+/// How often the block ingestor looks for new blocks
+#[derive(Clone, Copy, Debug)]
+struct Intervals {
+    /// How often we poll when we do not follow a subscription
+    polling: Duration,
+    /// How often we poll while we follow a subscription
+    new_heads_polling: Duration,
+    /// How long without a new head before a subscription counts as lost
+    new_heads_timeout: Duration,
+    /// How long we poll after losing a subscription before we subscribe
+    /// again
+    resubscribe: Duration,
 }
 
 pub struct BlockIngestorMetrics {
@@ -41,8 +90,11 @@ where
 {
     chain_store: Arc<S>,
     eth_adapter: Arc<dyn EthereumAdapter>,
+    /// The adapter whose `newHeads` subscription tells us when to look for
+    /// new blocks; without one, we only poll
+    heads_adapter: Option<Arc<dyn EthereumAdapter>>,
     ancestor_count: u64,
-    _network_name: String,
+    network_name: String,
     logger: Logger,
     polling_interval: Duration,
 }
@@ -54,6 +106,7 @@ where
     pub fn new(
         chain_store: Arc<S>,
         eth_adapter: Arc<dyn EthereumAdapter>,
+        heads_adapter: Option<Arc<dyn EthereumAdapter>>,
         ancestor_count: u64,
         network_name: String,
         logger_factory: &LoggerFactory,
@@ -73,38 +126,59 @@ where
         Ok(BlockIngestor {
             chain_store,
             eth_adapter,
+            heads_adapter,
             ancestor_count,
-            _network_name: network_name,
+            network_name,
             logger,
             polling_interval,
         })
     }
 
     pub async fn into_polling_stream(self) {
-        loop {
-            match self.do_poll().await {
-                // Some polls will fail due to transient issues
-                Err(err @ EthereumAdapterError::BlockUnavailable(_)) => {
-                    trace!(
-                        self.logger,
-                        "Trying again after block polling failed: {}",
-                        err
-                    );
-                }
-                Err(EthereumAdapterError::Unknown(inner_err)) => {
-                    warn!(
-                        self.logger,
-                        "Trying again after block polling failed: {}", inner_err
-                    );
-                }
-                Ok(()) => (),
-            }
+        let intervals = Intervals {
+            polling: self.polling_interval,
+            new_heads_polling: *NEW_HEADS_POLLING_INTERVAL,
+            new_heads_timeout: *NEW_HEADS_TIMEOUT,
+            resubscribe: *NEW_HEADS_RETRY_INTERVAL,
+        };
+        let subscribe = || {
+            self.heads_adapter
+                .as_ref()
+                .map(|adapter| adapter.new_heads(&self.logger).compat())
+        };
+        ingest_forever(&self.logger, subscribe, intervals, || {
+            self.ingest_latest_block()
+        })
+        .await
+    }
 
-            if *CLEANUP_BLOCKS {
-                self.cleanup_cached_blocks()
+    /// Ingest the latest block and its missing ancestors. Errors are only
+    /// logged and reported as the health of the provider since the next
+    /// attempt will retry anyway
+    async fn ingest_latest_block(&self) {
+        let status = match self.do_poll().await {
+            // Some polls will fail due to transient issues
+            Err(err @ EthereumAdapterError::BlockUnavailable(_)) => {
+                trace!(
+                    self.logger,
+                    "Trying again after block polling failed: {}",
+                    err
+                );
+                Ok(())
+            }
+            Err(EthereumAdapterError::Unknown(inner_err)) => {
+                warn!(
+                    self.logger,
+                    "Trying again after block polling failed: {}", inner_err
+                );
+                Err(format!("block polling failed: {}", inner_err))
             }
+            Ok(()) => Ok(()),
+        };
+        health::report(Subsystem::ChainProvider, &self.network_name, status);
 
-            tokio::time::delay_for(self.polling_interval).await;
+        if *CLEANUP_BLOCKS {
+            self.cleanup_cached_blocks()
         }
     }
 
@@ -267,3 +341,197 @@ where
         Box::new(stream::futures_unordered(block_futures))
     }
 }
+
+/// Ingest blocks forever. Follow the subscriptions to new heads that
+/// `subscribe` opens, and poll for new blocks whenever we have lost the
+/// subscription. If `subscribe` returns `None`, we can not subscribe at
+/// all and only poll
+async fn ingest_forever<H, S, I, F>(
+    logger: &Logger,
+    mut subscribe: S,
+    intervals: Intervals,
+    mut ingest: I,
+) where
+    H: futures03::Stream<Item = Result<H256, Error>> + Unpin,
+    S: FnMut() -> Option<H>,
+    I: FnMut() -> F,
+    F: futures03::Future<Output = ()>,
+{
+    loop {
+        let subscribed = match subscribe() {
+            Some(heads) => {
+                follow_new_heads(logger, heads, &intervals, &mut ingest).await;
+                true
+            }
+            None => false,
+        };
+
+        // Poll until it is time to try subscribing again, or forever
+        // if we can not subscribe at all
+        let resubscribe_at = Instant::now() + intervals.resubscribe;
+        while !subscribed || Instant::now() < resubscribe_at {
+            ingest().await;
+            tokio::time::delay_for(intervals.polling).await;
+        }
+    }
+}
+
+/// Ingest the latest block every time the node announces a new head on
+/// `heads`, and return once the subscription is lost so that the caller can
+/// fall back to polling. Since a subscription can stall without ending, we
+/// also keep polling at the slower `new_heads_polling` interval
+async fn follow_new_heads<H, I, F>(
+    logger: &Logger,
+    mut heads: H,
+    intervals: &Intervals,
+    ingest: &mut I,
+) where
+    H: futures03::Stream<Item = Result<H256, Error>> + Unpin,
+    I: FnMut() -> F,
+    F: futures03::Future<Output = ()>,
+{
+    // Catch up with blocks we missed while we were not subscribed
+    ingest().await;
+
+    let mut last_head = Instant::now();
+    loop {
+        match tokio::time::timeout(intervals.new_heads_polling, heads.next()).await {
+            Ok(Some(Ok(hash))) => {
+                trace!(logger, "Received new head"; "hash" => format!("{:x}", hash));
+                last_head = Instant::now();
+                ingest().await;
+            }
+            Ok(Some(Err(e))) => {
+                warn!(logger, "Falling back to polling for new blocks: {}", e);
+                return;
+            }
+            Ok(None) => {
+                warn!(
+                    logger,
+                    "Falling back to polling for new blocks since the \
+                     new heads subscription ended"
+                );
+                return;
+            }
+            Err(_) if last_head.elapsed() < intervals.new_heads_timeout => {
+                ingest().await;
+            }
+            Err(_) => {
+                warn!(
+                    logger,
+                    "Falling back to polling for new blocks since no new \
+                     head arrived for {}s",
+                    intervals.new_heads_timeout.as_secs()
+                );
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn counter() -> (
+    Arc<std::sync::atomic::AtomicUsize>,
+    impl FnMut() -> futures03::future::Ready<()>,
+) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count2 = count.clone();
+    let inc = move || {
+        count2.fetch_add(1, Ordering::SeqCst);
+        futures03::future::ready(())
+    };
+    (count, inc)
+}
+
+#[cfg(test)]
+const TEST_INTERVALS: Intervals = Intervals {
+    polling: Duration::from_millis(10),
+    new_heads_polling: Duration::from_millis(20),
+    new_heads_timeout: Duration::from_millis(200),
+    resubscribe: Duration::from_millis(50),
+};
+
+#[tokio::test]
+async fn follows_new_heads() {
+    use std::sync::atomic::Ordering;
+
+    let logger = Logger::root(slog::Discard, o!());
+    let intervals = Intervals {
+        new_heads_polling: Duration::from_secs(60),
+        ..TEST_INTERVALS
+    };
+
+    // One ingest to catch up, and one for each head
+    let (count, mut ingest) = counter();
+    let heads = futures03::stream::iter(vec![Ok(H256::zero()), Ok(H256::repeat_byte(1))]);
+    follow_new_heads(&logger, heads, &intervals, &mut ingest).await;
+    assert_eq!(3, count.load(Ordering::SeqCst));
+
+    // A failed subscription is given up right away
+    let (count, mut ingest) = counter();
+    let heads =
+        futures03::stream::iter(vec![Err(format_err!("connection reset")), Ok(H256::zero())]);
+    follow_new_heads(&logger, heads, &intervals, &mut ingest).await;
+    assert_eq!(1, count.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn polls_while_new_heads_stall() {
+    use std::sync::atomic::Ordering;
+
+    let logger = Logger::root(slog::Discard, o!());
+    let (count, mut ingest) = counter();
+
+    // A subscription that never delivers a head, but does not end either
+    let heads = futures03::stream::pending::<Result<H256, Error>>();
+    let start = Instant::now();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        follow_new_heads(&logger, heads, &TEST_INTERVALS, &mut ingest),
+    )
+    .await
+    .expect("a stalled subscription counts as lost");
+    assert!(start.elapsed() >= TEST_INTERVALS.new_heads_timeout);
+
+    // We kept polling at the slower interval in the meantime
+    assert!(count.load(Ordering::SeqCst) > 3);
+}
+
+#[tokio::test]
+async fn resubscribes_after_losing_new_heads() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let logger = Logger::root(slog::Discard, o!());
+
+    // Every subscription ends right away
+    let subscriptions = AtomicUsize::new(0);
+    let subscribe = || {
+        subscriptions.fetch_add(1, Ordering::SeqCst);
+        Some(futures03::stream::empty::<Result<H256, Error>>())
+    };
+    let (count, ingest) = counter();
+    let run = ingest_forever(&logger, subscribe, TEST_INTERVALS, ingest);
+    assert!(tokio::time::timeout(Duration::from_millis(300), run)
+        .await
+        .is_err());
+    // We subscribed again after each `resubscribe` interval, and polled
+    // in between
+    assert!(subscriptions.load(Ordering::SeqCst) >= 3);
+    assert!(count.load(Ordering::SeqCst) > subscriptions.load(Ordering::SeqCst));
+
+    // Without a way to subscribe, we only poll
+    let subscriptions = AtomicUsize::new(0);
+    let subscribe = || {
+        subscriptions.fetch_add(1, Ordering::SeqCst);
+        None::<futures03::stream::Empty<Result<H256, Error>>>
+    };
+    let (count, ingest) = counter();
+    let run = ingest_forever(&logger, subscribe, TEST_INTERVALS, ingest);
+    assert!(tokio::time::timeout(Duration::from_millis(100), run)
+        .await
+        .is_err());
+    assert_eq!(1, subscriptions.load(Ordering::SeqCst));
+    assert!(count.load(Ordering::SeqCst) > 1);
+}
@@ -1,8 +1,8 @@
 use std::cmp;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::mem;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use graph::components::ethereum::{
     blocks_with_triggers, triggers_in_block, EthereumNetworks, NodeCapabilities,
@@ -10,6 +10,8 @@ use graph::components::ethereum::{
 use graph::prelude::{
     BlockStream as BlockStreamTrait, BlockStreamBuilder as BlockStreamBuilderTrait, *,
 };
+use graph::util::shutdown;
+use web3::types::H256;
 
 lazy_static! {
     /// Maximum number of blocks to request in each chunk.
@@ -23,6 +25,16 @@ lazy_static! {
         .unwrap_or("100".into())
         .parse::<u64>()
         .expect("invalid GRAPH_ETHEREUM_TARGET_TRIGGERS_PER_BLOCK_RANGE");
+
+    /// How long to pause before processing new blocks when reverts happen
+    /// in quick succession. During a reorg storm, blocks close to the chain
+    /// head are likely to be reverted again, and it is cheaper to wait for
+    /// the chain to settle than to process them.
+    static ref REVERT_COOLDOWN: Duration = std::env::var("GRAPH_ETHEREUM_REVERT_COOLDOWN")
+        .ok()
+        .map(|s| s.parse::<u64>().expect("invalid GRAPH_ETHEREUM_REVERT_COOLDOWN"))
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(2));
 }
 
 enum BlockStreamState {
@@ -42,8 +54,8 @@ enum BlockStreamState {
     /// Valid next states: Reconciliation
     YieldingBlocks(VecDeque<EthereumBlockWithTriggers>),
 
-    /// The BlockStream experienced an error and is pausing before attempting to produce
-    /// blocks again.
+    /// The BlockStream experienced an error, or reverts happened in quick succession, and
+    /// it is pausing before attempting to produce blocks again.
     ///
     /// Valid next states: Reconciliation
     RetryAfterDelay(Box<dyn Future<Item = (), Error = Error> + Send>),
@@ -104,6 +116,7 @@ struct BlockStreamContext<S, C> {
     block_filter: EthereumBlockFilter,
     start_blocks: Vec<u64>,
     include_calls_in_blocks: bool,
+    source_deployments: BTreeMap<SubgraphDeploymentId, Vec<String>>,
     logger: Logger,
     metrics: Arc<BlockStreamMetrics>,
     previous_triggers_per_block: f64,
@@ -125,6 +138,7 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             block_filter: self.block_filter.clone(),
             start_blocks: self.start_blocks.clone(),
             include_calls_in_blocks: self.include_calls_in_blocks,
+            source_deployments: self.source_deployments.clone(),
             logger: self.logger.clone(),
             metrics: self.metrics.clone(),
             previous_triggers_per_block: self.previous_triggers_per_block,
@@ -137,6 +151,8 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
 pub struct BlockStream<S, C> {
     state: Mutex<BlockStreamState>,
     consecutive_err_count: u32,
+    /// When the last batch of reverts was processed
+    last_revert: Option<Instant>,
     chain_head_update_stream: ChainHeadUpdateStream,
     ctx: BlockStreamContext<S, C>,
 }
@@ -144,7 +160,8 @@ pub struct BlockStream<S, C> {
 enum NextBlocks {
     /// Blocks and range size
     Blocks(VecDeque<EthereumBlockWithTriggers>, u64),
-    Revert,
+    /// The number of consecutive blocks that were reverted
+    Revert(u64),
     Done,
 }
 
@@ -164,6 +181,7 @@ where
         block_filter: EthereumBlockFilter,
         start_blocks: Vec<u64>,
         include_calls_in_blocks: bool,
+        source_deployments: BTreeMap<SubgraphDeploymentId, Vec<String>>,
         reorg_threshold: u64,
         logger: Logger,
         metrics: Arc<BlockStreamMetrics>,
@@ -171,6 +189,7 @@ where
         BlockStream {
             state: Mutex::new(BlockStreamState::New),
             consecutive_err_count: 0,
+            last_revert: None,
             chain_head_update_stream: chain_store.chain_head_updates(),
             ctx: BlockStreamContext {
                 subgraph_store,
@@ -185,6 +204,7 @@ where
                 block_filter,
                 start_blocks,
                 include_calls_in_blocks,
+                source_deployments,
                 metrics,
 
                 // A high number here forces a slow start, with a range of 1.
@@ -202,36 +222,55 @@ where
     C: ChainStore,
 {
     /// Perform reconciliation steps until there are blocks to yield or we are up-to-date.
+    ///
+    /// Consecutive reverts are processed in one go, before any other step,
+    /// so that we never move forward onto blocks in between reverts.
     fn next_blocks(&self) -> Box<dyn Future<Item = NextBlocks, Error = Error> + Send> {
         let ctx = self.clone();
 
-        Box::new(future::loop_fn((), move |()| {
+        Box::new(future::loop_fn(0, move |reverted| {
             let ctx1 = ctx.clone();
             let ctx2 = ctx.clone();
             let ctx3 = ctx.clone();
 
-            ctx1.get_next_step()
-                .and_then(move |step| ctx2.do_step(step))
-                // Check outcome.
-                // Exit loop if done or there are blocks to process.
-                .and_then(move |outcome| match outcome {
-                    ReconciliationStepOutcome::YieldBlocks(next_blocks, range_size) => {
-                        Ok(future::Loop::Break(NextBlocks::Blocks(
-                            next_blocks.into_iter().collect(),
-                            range_size,
-                        )))
+            ctx1.get_next_step().and_then(
+                move |step| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                    // Report the reverts we just did before taking any
+                    // other step
+                    if let Some(reverted) = reverts_to_report(&step, reverted) {
+                        return Box::new(future::ok(future::Loop::Break(NextBlocks::Revert(
+                            reverted,
+                        ))));
                     }
-                    ReconciliationStepOutcome::MoreSteps => Ok(future::Loop::Continue(())),
-                    ReconciliationStepOutcome::Done => {
-                        // Reconciliation is complete, so try to mark subgraph as Synced
-                        ctx3.update_subgraph_synced_status()?;
 
-                        Ok(future::Loop::Break(NextBlocks::Done))
-                    }
-                    ReconciliationStepOutcome::Revert => {
-                        Ok(future::Loop::Break(NextBlocks::Revert))
-                    }
-                })
+                    Box::new(
+                        ctx2.do_step(step)
+                            // Check outcome.
+                            // Exit loop if done or there are blocks to process.
+                            .and_then(move |outcome| match outcome {
+                                ReconciliationStepOutcome::YieldBlocks(next_blocks, range_size) => {
+                                    Ok(future::Loop::Break(NextBlocks::Blocks(
+                                        next_blocks.into_iter().collect(),
+                                        range_size,
+                                    )))
+                                }
+                                ReconciliationStepOutcome::MoreSteps => {
+                                    Ok(future::Loop::Continue(reverted))
+                                }
+                                ReconciliationStepOutcome::Done => {
+                                    // Reconciliation is complete, so try to mark subgraph as Synced
+                                    ctx3.update_subgraph_synced_status()?;
+
+                                    Ok(future::Loop::Break(NextBlocks::Done))
+                                }
+                                ReconciliationStepOutcome::Revert => {
+                                    // Keep reverting until we are back on the main chain
+                                    Ok(future::Loop::Continue(reverted + 1))
+                                }
+                            }),
+                    )
+                },
+            )
         }))
     }
 
@@ -370,9 +409,19 @@ where
                             // It is only safe to use block numbers because we are beyond the reorg
                             // threshold.
 
+                            // It isn't safe to scan past the reorg threshold
+                            let head_limit = head_ptr.number - reorg_threshold;
+
                             // Start with first block after subgraph ptr; if the ptr is None,
-                            // then we start with the genesis block
-                            let from = subgraph_ptr.map_or(0, |ptr| ptr.number + 1);
+                            // then we start with the genesis block. Blocks before the earliest
+                            // start block of any data source can't contain triggers, so we skip
+                            // straight past them
+                            let earliest_start_block =
+                                start_blocks.iter().cloned().min().unwrap_or(0);
+                            let from = cmp::max(
+                                subgraph_ptr.map_or(0, |ptr| ptr.number + 1),
+                                cmp::min(earliest_start_block, head_limit),
+                            );
 
                             // Get the next subsequent data source start block to ensure the block range
                             // is aligned with data source.
@@ -385,8 +434,7 @@ where
                             // End either just before the the next data source start_block or
                             // just prior to the reorg threshold. It isn't safe to go any farther
                             // due to race conditions.
-                            let to_limit =
-                                cmp::min(head_ptr.number - reorg_threshold, next_start_block - 1);
+                            let to_limit = cmp::min(head_limit, next_start_block - 1);
 
                             // Calculate the range size according to the target number of triggers,
                             // respecting the global maximum and also not increasing too
@@ -415,6 +463,21 @@ where
                             };
                             let to = cmp::min(from + range_size - 1, to_limit);
 
+                            // Subgraph data sources can only process blocks that their
+                            // source deployments have processed, and need the blocks in
+                            // which those changed entities even if they have no triggers
+                            let (to, extra_blocks) = match ctx.source_deployment_changes(from, to) {
+                                Ok(Some(changes)) => changes,
+                                Ok(None) => {
+                                    debug!(
+                                        ctx.logger,
+                                        "Waiting for source deployments to process block {}", from
+                                    );
+                                    return Box::new(future::ok(ReconciliationStep::Done));
+                                }
+                                Err(e) => return Box::new(future::err(e)),
+                            };
+
                             let section = ctx.metrics.stopwatch.start_section("scan_blocks");
                             info!(
                                 ctx.logger,
@@ -429,9 +492,12 @@ where
                                     ctx.metrics.ethrpc_metrics.clone(),
                                     from,
                                     to,
-                                    log_filter.clone(),
-                                    call_filter.clone(),
-                                    block_filter.clone(),
+                                    // No data source starts after `from` and up to `to`, so only
+                                    // look for the triggers of data sources that have started
+                                    log_filter.active_at(from),
+                                    call_filter.active_at(from),
+                                    block_filter.active_at(from),
+                                    extra_blocks,
                                 )
                                 .map_ok(move |blocks| {
                                     section.end();
@@ -561,6 +627,13 @@ where
             ReconciliationStep::Retry => Box::new(future::ok(ReconciliationStepOutcome::MoreSteps)),
             ReconciliationStep::Done => Box::new(future::ok(ReconciliationStepOutcome::Done)),
             ReconciliationStep::RevertBlock(subgraph_ptr) => {
+                // A shutdown waits for reverts just like for blocks that are
+                // being processed. Once the node is shutting down, leave the
+                // revert for the next start
+                let in_flight = match shutdown::in_flight() {
+                    Some(in_flight) => in_flight,
+                    None => return Box::new(future::ok(ReconciliationStepOutcome::Done)),
+                };
                 let metrics = self.metrics.clone();
                 let reverted_block_number = subgraph_ptr.number as f64;
 
@@ -602,6 +675,7 @@ where
                                     )
                                     .map_err(Error::from)
                                     .map(|()| {
+                                        drop(in_flight);
                                         metrics.reverted_blocks.set(reverted_block_number);
                                         // At this point, the loop repeats, and we try to move
                                         // the subgraph ptr another step in the right direction.
@@ -624,6 +698,51 @@ where
 
     /// Set subgraph deployment entity synced flag if and only if the subgraph block pointer is
     /// caught up to the head block pointer.
+    /// Limit the range `from..=to` to the blocks that all source deployments
+    /// have processed and find the blocks in it in which they changed
+    /// entities. Return `None` if a source deployment has not processed
+    /// `from` yet
+    fn source_deployment_changes(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Result<Option<(u64, BTreeSet<u64>)>, Error> {
+        let mut to = to;
+        for deployment in self.source_deployments.keys() {
+            match self.subgraph_store.block_ptr(deployment.clone())? {
+                Some(ptr) if ptr.number >= from => to = cmp::min(to, ptr.number),
+                _ => return Ok(None),
+            }
+        }
+
+        let mut blocks = BTreeSet::new();
+        for (deployment, entity_types) in &self.source_deployments {
+            let changes = self.subgraph_store.entity_change_blocks(
+                deployment,
+                entity_types.clone(),
+                from as BlockNumber,
+                to as BlockNumber,
+            )?;
+            blocks.extend(changes.into_iter().map(|block| block as u64));
+        }
+        Ok(Some((to, blocks)))
+    }
+
+    /// Whether `block` is still on the chain that ends in the current chain
+    /// head. If we can not tell, the block counts as not being on it
+    fn is_on_main_chain(&self, block: EthereumBlockPointer) -> bool {
+        let head_ptr = match self.chain_store.chain_head_ptr() {
+            Ok(Some(head_ptr)) => head_ptr,
+            Ok(None) | Err(_) => return false,
+        };
+        on_chain_of(head_ptr, block, |offset| {
+            self.chain_store
+                .ancestor_block(head_ptr, offset)
+                .map(|ancestor| ancestor.and_then(|ancestor| ancestor.block.hash))
+        })
+        .unwrap_or(false)
+    }
+
     fn update_subgraph_synced_status(&self) -> Result<(), Error> {
         let head_ptr_opt = self.chain_store.chain_head_ptr()?;
         let subgraph_ptr = self.subgraph_store.block_ptr(self.subgraph_id.clone())?;
@@ -715,8 +834,29 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                             continue;
                         }
 
-                        Ok(Async::Ready(NextBlocks::Revert)) => {
-                            state = BlockStreamState::Reconciliation(self.ctx.next_blocks());
+                        Ok(Async::Ready(NextBlocks::Revert(reverted))) => {
+                            self.ctx.metrics.revert_batches.inc();
+                            self.ctx.metrics.reverts.inc_by(reverted as f64);
+
+                            // If the previous batch of reverts was not long ago, we are
+                            // probably in a reorg storm; wait for the chain to settle
+                            // before moving forward again
+                            let now = Instant::now();
+                            let storm = in_reorg_storm(self.last_revert, now, *REVERT_COOLDOWN);
+                            self.last_revert = Some(now);
+                            state = if storm {
+                                info!(
+                                    self.ctx.logger,
+                                    "Repeated reverts, pausing before processing new blocks";
+                                    "reverted_blocks" => reverted,
+                                    "pause_ms" => REVERT_COOLDOWN.as_millis() as u64,
+                                );
+                                BlockStreamState::RetryAfterDelay(Box::new(
+                                    tokio::time::delay_for(*REVERT_COOLDOWN).map(Ok).compat(),
+                                ))
+                            } else {
+                                BlockStreamState::Reconciliation(self.ctx.next_blocks())
+                            };
                             break Ok(Async::Ready(Some(BlockStreamEvent::Revert)));
                         }
 
@@ -749,6 +889,25 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                     match next_blocks.pop_front() {
                         // Yield one block
                         Some(next_block) => {
+                            // Blocks close to the chain head may have been reorged out since
+                            // we found them. If the chain head moved in the meantime and the
+                            // next block is not on the main chain anymore, reconcile again so
+                            // that reverts are processed before we continue with new blocks
+                            if let BlockFinality::NonFinal(_) = next_block.ethereum_block {
+                                if let Ok(Async::Ready(Some(()))) =
+                                    self.chain_head_update_stream.poll()
+                                {
+                                    let ptr =
+                                        EthereumBlockPointer::from(&next_block.ethereum_block);
+                                    if !self.ctx.is_on_main_chain(ptr) {
+                                        state = BlockStreamState::Reconciliation(
+                                            self.ctx.next_blocks(),
+                                        );
+                                        continue;
+                                    }
+                                }
+                            }
+
                             state = BlockStreamState::YieldingBlocks(next_blocks);
                             break Ok(Async::Ready(Some(BlockStreamEvent::Block(next_block))));
                         }
@@ -890,6 +1049,7 @@ where
         call_filter: EthereumCallFilter,
         block_filter: EthereumBlockFilter,
         include_calls_in_blocks: bool,
+        source_deployments: BTreeMap<SubgraphDeploymentId, Vec<String>>,
         metrics: Arc<BlockStreamMetrics>,
     ) -> Self::Stream {
         let logger = logger.new(o!(
@@ -930,9 +1090,96 @@ where
             block_filter,
             start_blocks,
             include_calls_in_blocks,
+            source_deployments,
             self.reorg_threshold,
             logger,
             metrics,
         )
     }
 }
+
+/// The number of reverts that `next_blocks` has to report before it takes
+/// `step`, after it already reverted `reverted` blocks in a row. Reverts are
+/// only reported once the next step is not another revert
+fn reverts_to_report(step: &ReconciliationStep, reverted: u64) -> Option<u64> {
+    match step {
+        ReconciliationStep::RevertBlock(_) => None,
+        _ if reverted > 0 => Some(reverted),
+        _ => None,
+    }
+}
+
+/// Whether reverts that happen at `now` come so soon after the previous
+/// batch of reverts at `last_revert` that we are probably in a reorg storm
+fn in_reorg_storm(last_revert: Option<Instant>, now: Instant, cooldown: Duration) -> bool {
+    last_revert.map_or(false, |last| now.duration_since(last) < cooldown)
+}
+
+/// Whether `block` is on the chain that ends in `head_ptr`. The function
+/// `ancestor_hash` returns the hash of the `offset`th ancestor of the head,
+/// or `None` if that ancestor is not known
+fn on_chain_of(
+    head_ptr: EthereumBlockPointer,
+    block: EthereumBlockPointer,
+    ancestor_hash: impl FnOnce(u64) -> Result<Option<H256>, Error>,
+) -> Result<bool, Error> {
+    if block.number > head_ptr.number {
+        // The chain head moved back past the block
+        return Ok(false);
+    }
+    Ok(ancestor_hash(head_ptr.number - block.number)? == Some(block.hash))
+}
+
+#[test]
+fn coalesces_consecutive_reverts() {
+    let revert = ReconciliationStep::RevertBlock((H256::repeat_byte(1), 1u64).into());
+
+    // Nothing is reported while one revert follows another
+    assert_eq!(None, reverts_to_report(&revert, 0));
+    assert_eq!(None, reverts_to_report(&revert, 3));
+
+    // The first other step reports all of them at once
+    let steps = vec![
+        ReconciliationStep::ProcessDescendantBlocks(vec![], 1),
+        ReconciliationStep::Retry,
+        ReconciliationStep::Done,
+    ];
+    for step in &steps {
+        assert_eq!(Some(3), reverts_to_report(step, 3));
+        assert_eq!(None, reverts_to_report(step, 0));
+    }
+}
+
+#[test]
+fn detects_reorg_storms() {
+    let cooldown = Duration::from_secs(2);
+    let now = Instant::now();
+    let later = now + Duration::from_secs(1);
+    let much_later = now + Duration::from_secs(3);
+
+    assert!(!in_reorg_storm(None, now, cooldown));
+    assert!(in_reorg_storm(Some(now), later, cooldown));
+    assert!(!in_reorg_storm(Some(now), much_later, cooldown));
+}
+
+#[test]
+fn checks_blocks_against_main_chain() {
+    let head = EthereumBlockPointer::from((H256::repeat_byte(10), 10u64));
+    let block = EthereumBlockPointer::from((H256::repeat_byte(8), 8u64));
+
+    let ancestor = |hash: Option<H256>| {
+        move |offset| -> Result<Option<H256>, Error> {
+            assert_eq!(2, offset);
+            Ok(hash)
+        }
+    };
+
+    assert!(on_chain_of(head, block, ancestor(Some(block.hash))).unwrap());
+    // The block was reorged out
+    assert!(!on_chain_of(head, block, ancestor(Some(H256::repeat_byte(9)))).unwrap());
+    // We do not know the ancestor of the head at the block's number
+    assert!(!on_chain_of(head, block, ancestor(None)).unwrap());
+    // The head moved back past the block
+    let block = EthereumBlockPointer::from((H256::repeat_byte(11), 11u64));
+    assert!(!on_chain_of(head, block, |_| unreachable!()).unwrap());
+}
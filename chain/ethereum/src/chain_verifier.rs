@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use graph::prelude::*;
+use web3::types::H256;
+
+lazy_static! {
+    /// How often to compare stored blocks with the canonical chain, in
+    /// seconds. Setting this to 0 turns the verification off
+    static ref CHAIN_VERIFICATION_INTERVAL: u64 =
+        std::env::var("GRAPH_CHAIN_VERIFICATION_INTERVAL")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_CHAIN_VERIFICATION_INTERVAL");
+
+    /// How many blocks below the reorg threshold to compare with the
+    /// canonical chain in each round
+    static ref CHAIN_VERIFICATION_DEPTH: u64 = std::env::var("GRAPH_CHAIN_VERIFICATION_DEPTH")
+        .unwrap_or("100".into())
+        .parse::<u64>()
+        .expect("invalid GRAPH_CHAIN_VERIFICATION_DEPTH");
+}
+
+/// Periodically compares the hashes of blocks that are older than the reorg
+/// threshold with the provider's canonical chain. Blocks that are that old
+/// are treated as final everywhere else, so a block that turns out not to
+/// be canonical after all is never reverted by the block stream. Such
+/// blocks are removed from the block cache, and deployments that processed
+/// such a block are marked as needing a rewind
+pub struct ChainVerifier<S> {
+    store: Arc<S>,
+    eth_adapter: Arc<dyn EthereumAdapter>,
+    reorg_threshold: u64,
+    network_name: String,
+    logger: Logger,
+}
+
+impl<S> ChainVerifier<S>
+where
+    S: Store + ChainStore,
+{
+    pub fn new(
+        store: Arc<S>,
+        eth_adapter: Arc<dyn EthereumAdapter>,
+        reorg_threshold: u64,
+        network_name: String,
+        logger_factory: &LoggerFactory,
+    ) -> Self {
+        let logger = logger_factory
+            .component_logger("ChainVerifier", None)
+            .new(o!("network_name" => network_name.clone()));
+
+        ChainVerifier {
+            store,
+            eth_adapter,
+            reorg_threshold,
+            network_name,
+            logger,
+        }
+    }
+
+    pub fn is_enabled() -> bool {
+        *CHAIN_VERIFICATION_INTERVAL > 0
+    }
+
+    pub async fn into_polling_stream(self) {
+        let interval = Duration::from_secs(*CHAIN_VERIFICATION_INTERVAL);
+        loop {
+            // Wait first so that the block ingestor has a chance to catch
+            // up with the chain head after a restart
+            tokio::time::delay_for(interval).await;
+
+            if let Err(e) = self.verify().await {
+                warn!(
+                    self.logger,
+                    "Trying again after verifying the canonical chain failed: {}", e
+                );
+            }
+        }
+    }
+
+    async fn verify(&self) -> Result<(), Error> {
+        let head = match self.store.chain_head_ptr()? {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        let last_final = match head.number.checked_sub(self.reorg_threshold) {
+            Some(number) => number,
+            None => return Ok(()),
+        };
+        let first = last_final.saturating_sub(*CHAIN_VERIFICATION_DEPTH);
+
+        let mut canonical: HashMap<u64, H256> = self
+            .eth_adapter
+            .block_range_to_ptrs(self.logger.clone(), first, last_final)
+            .compat()
+            .await?
+            .into_iter()
+            .map(|ptr| (ptr.number, ptr.hash))
+            .collect();
+
+        for number in first..=last_final {
+            let canonical_hash = match canonical.get(&number) {
+                Some(hash) => hash,
+                None => continue,
+            };
+            let stored = self.store.block_hashes_by_block_number(number)?;
+            if stored.iter().any(|hash| hash != canonical_hash) {
+                let removed = self.store.confirm_block_hash(number, canonical_hash)?;
+                warn!(
+                    self.logger,
+                    "Removed blocks that are not canonical from the block cache";
+                    "number" => number,
+                    "canonical_hash" => format!("{:x}", canonical_hash),
+                    "removed" => removed,
+                );
+            }
+        }
+
+        for (id, ptr) in self.store.deployment_block_ptrs(&self.network_name)? {
+            // The block stream takes care of reorgs above the threshold, but
+            // blocks the deployment processed before that must be checked
+            // individually since a deployment that is close to the chain
+            // head can have diverged below the threshold, too
+            let last = ptr.number.min(last_final);
+            let mut blocks = if last >= first {
+                self.store
+                    .indexed_block_ptrs(&id, first as BlockNumber, last as BlockNumber)?
+            } else {
+                vec![]
+            };
+            // Deployments only record the blocks they processed along with
+            // their proofs of indexing; always check the latest block
+            if ptr.number <= last_final && !blocks.contains(&ptr) {
+                blocks.push(ptr);
+            }
+            self.check_deployment(&id, blocks, &mut canonical).await?;
+        }
+        Ok(())
+    }
+
+    /// The hash of the canonical block with number `number`, looking it up
+    /// with the provider if it is not in `canonical` yet
+    async fn canonical_hash(
+        &self,
+        canonical: &mut HashMap<u64, H256>,
+        number: u64,
+    ) -> Result<Option<H256>, Error> {
+        if let Some(hash) = canonical.get(&number) {
+            return Ok(Some(*hash));
+        }
+        let hash = self
+            .eth_adapter
+            .block_range_to_ptrs(self.logger.clone(), number, number)
+            .compat()
+            .await?
+            .pop()
+            .map(|ptr| ptr.hash);
+        if let Some(hash) = hash {
+            canonical.insert(number, hash);
+        }
+        Ok(hash)
+    }
+
+    /// Flag the deployment `id` as needing a rewind if any of the `blocks`
+    /// it processed is not canonical, using the earliest such block so that
+    /// rewinding from it undoes everything the deployment did on the wrong
+    /// chain
+    async fn check_deployment(
+        &self,
+        id: &SubgraphDeploymentId,
+        blocks: Vec<EthereumBlockPointer>,
+        canonical: &mut HashMap<u64, H256>,
+    ) -> Result<(), Error> {
+        let flagged = self.store.non_canonical_block(id)?;
+        for ptr in &blocks {
+            let canonical_hash = self.canonical_hash(canonical, ptr.number).await?;
+            if canonical_hash == Some(ptr.hash) {
+                continue;
+            }
+
+            let block = NonCanonicalBlock {
+                block: *ptr,
+                canonical_hash,
+            };
+            if flagged != Some(block) {
+                error!(
+                    self.logger,
+                    "Deployment indexed a block that is not canonical and needs to be rewound";
+                    "subgraph_id" => id.as_str(),
+                    "block_number" => ptr.number,
+                    "block_hash" => format!("{:x}", ptr.hash),
+                    "canonical_hash" => canonical_hash
+                        .map(|hash| format!("{:x}", hash))
+                        .unwrap_or_else(|| "none".to_owned()),
+                );
+                self.store.set_non_canonical_block(id, Some(block))?;
+            }
+            return Ok(());
+        }
+
+        // Only clear the flag if we just checked the block that caused it
+        if let Some(flagged) = flagged {
+            if blocks.iter().any(|ptr| ptr.number == flagged.block.number) {
+                info!(
+                    self.logger,
+                    "Deployment is on the canonical chain again";
+                    "subgraph_id" => id.as_str(),
+                    "block_number" => flagged.block.number,
+                );
+                self.store.set_non_canonical_block(id, None)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -5,15 +5,15 @@ use lazy_static::lazy_static;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use ethabi::ParamType;
 use graph::components::ethereum::{EthereumAdapter as EthereumAdapterTrait, *};
 use graph::prelude::{
     debug, err_msg, error, ethabi, format_err,
     futures03::{self, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt},
-    hex, retry, stream, tiny_keccak, trace, warn, web3, ChainStore, CheapClone, DynTryFuture,
-    Error, EthereumCallCache, Logger, TimeoutError,
+    hex, info, retry, serde_json, stream, tiny_keccak, tokio, trace, warn, web3, ChainStore,
+    CheapClone, DynTryFuture, Error, EthereumCallCache, Logger, TimeoutError,
 };
 use web3::api::Web3;
 use web3::transports::batch::Batch;
@@ -25,6 +25,156 @@ pub struct EthereumAdapter<T: web3::Transport> {
     web3: Arc<Web3<T>>,
     metrics: Arc<ProviderEthRpcMetrics>,
     is_ganache: bool,
+    supports_new_heads: bool,
+    /// `None` if the node supports neither way of tracing
+    trace_method: Option<TraceMethod>,
+    is_archive: bool,
+    /// The largest block range the node accepts for `eth_getLogs`, if it
+    /// limits it
+    max_log_range: Option<u64>,
+}
+
+/// Block ranges, from largest to smallest, that we try `eth_getLogs` with
+/// to find out how large a range the node accepts
+const LOG_RANGE_PROBES: [u64; 4] = [100_000, 10_000, 1_000, 100];
+
+/// How often we send a log range probe that fails for reasons that have
+/// nothing to do with the range, like timeouts or rate limits
+const LOG_RANGE_PROBE_ATTEMPTS: usize = 3;
+
+/// How long we wait before sending such a probe again
+const LOG_RANGE_PROBE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Full nodes keep the state of at least this many recent blocks, so we
+/// can only tell whether a node is an archive node on longer chains
+const ARCHIVE_PROBE_DISTANCE: u64 = 1_000;
+
+/// How long we wait for the node to accept and cancel a test `newHeads`
+/// subscription before we give up on subscriptions for it
+const NEW_HEADS_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the node answered `e` itself, i.e., it understood the request
+/// and refused it, rather than the request not reaching the node
+fn rejected(e: &web3::error::Error) -> bool {
+    match e {
+        web3::error::Error::Rpc(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether the node rejected a request because it does not know the method
+fn method_missing(e: &web3::error::Error) -> bool {
+    match e {
+        web3::error::Error::Rpc(e) => {
+            e.code == jsonrpc_core::ErrorCode::MethodNotFound
+                || e.message.contains("does not exist")
+                || e.message.contains("not available")
+        }
+        _ => false,
+    }
+}
+
+/// Whether the node rejected a request because it does not have the state
+/// of the block the request is about. Nodes phrase this differently, but
+/// other errors, like a lagging node not knowing the block yet or rate
+/// limiting, do not tell us anything about the state the node keeps
+fn state_missing(e: &web3::error::Error) -> bool {
+    match e {
+        web3::error::Error::Rpc(e) => {
+            let message = e.message.to_lowercase();
+            message.contains("missing trie node")
+                || message.contains("state not available")
+                || message.contains("state is not available")
+                || message.contains("pruning")
+                || message.contains("pruned")
+        }
+        _ => false,
+    }
+}
+
+/// Whether the node rejected an `eth_getLogs` request because it scans too
+/// many blocks. Rate limits, timeouts and other failures do not tell us
+/// anything about the range the node accepts
+fn range_limited(e: &web3::error::Error) -> bool {
+    const FINGERPRINTS: &[&str] = &[
+        "block range",
+        "blocks range",
+        "range too large",
+        "range is too large",
+        "range too wide",
+        "range limit",
+        "limited to a",
+        "query returned more than",
+        "log response size exceeded",
+    ];
+
+    match e {
+        web3::error::Error::Rpc(e) => {
+            let message = e.message.to_lowercase();
+            FINGERPRINTS.iter().any(|f| message.contains(f))
+        }
+        _ => false,
+    }
+}
+
+/// Whether the answer to the archive probe says that the node is an archive
+/// node, or the error if the answer does not tell us
+fn archive_from_probe<V>(res: &Result<V, web3::error::Error>) -> Result<bool, &web3::error::Error> {
+    match res {
+        Ok(_) => Ok(true),
+        Err(e) if state_missing(e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Find the largest block range the node lets us scan with one
+/// `eth_getLogs` request by calling `probe` with ever smaller ranges that
+/// end in block `latest`. Returns `None` if the node accepts all the
+/// ranges we try. If a probe keeps failing for reasons other than the size
+/// of the range, we stop and go with what we know so far
+async fn probe_max_log_range<F, Fut>(latest: u64, probe: F) -> Option<u64>
+where
+    F: Fn(u64, u64) -> Fut,
+    Fut: std::future::Future<Output = Result<(), web3::error::Error>>,
+{
+    let mut rejected_any = false;
+    for range in LOG_RANGE_PROBES.iter().filter(|range| **range <= latest) {
+        let mut attempt = 1;
+        let accepted = loop {
+            match probe(latest - range + 1, latest).await {
+                Ok(()) => break Some(true),
+                Err(e) if range_limited(&e) => break Some(false),
+                Err(_) if attempt < LOG_RANGE_PROBE_ATTEMPTS => {
+                    attempt += 1;
+                    tokio::time::delay_for(LOG_RANGE_PROBE_RETRY_DELAY).await;
+                }
+                Err(_) => break None,
+            }
+        };
+        match accepted {
+            Some(false) => rejected_any = true,
+            // If we could not tell whether the node accepts this range,
+            // scanning logs reduces it further when it has to
+            Some(true) | None if rejected_any => return Some(*range),
+            Some(true) | None => return None,
+        }
+    }
+    if rejected_any {
+        LOG_RANGE_PROBES.last().cloned()
+    } else {
+        None
+    }
+}
+
+/// How we collect the calls that call handlers and call filters need
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TraceMethod {
+    /// Parity/OpenEthereum style `trace_filter`, which can filter by the
+    /// address that was called
+    TraceFilter,
+    /// Geth style `debug_traceBlockByHash` with the `callTracer`, which
+    /// traces all transactions of one block at a time
+    DebugTraceBlock,
 }
 
 lazy_static! {
@@ -78,17 +228,23 @@ impl<T: web3::Transport> CheapClone for EthereumAdapter<T> {
             web3: self.web3.cheap_clone(),
             metrics: self.metrics.cheap_clone(),
             is_ganache: self.is_ganache,
+            supports_new_heads: self.supports_new_heads,
+            trace_method: self.trace_method,
+            is_archive: self.is_archive,
+            max_log_range: self.max_log_range,
         }
     }
 }
 
 impl<T> EthereumAdapter<T>
 where
-    T: web3::BatchTransport + Send + Sync + 'static,
+    T: web3::BatchTransport + web3::DuplexTransport + Send + Sync + 'static,
     T::Batch: Send,
     T::Out: Send,
+    T::NotificationStream: Send,
 {
     pub async fn new(
+        logger: &Logger,
         url: &str,
         transport: T,
         provider_metrics: Arc<ProviderEthRpcMetrics>,
@@ -112,12 +268,133 @@ where
             .map(|s| s.contains("TestRPC"))
             .unwrap_or(false);
 
+        // Nodes only accept subscriptions over connections that let them
+        // push notifications, so we check whether subscribing works and
+        // cancel the test subscription right away. A node that does not
+        // answer in time is treated as not supporting subscriptions
+        let probe = async {
+            match web3.eth_subscribe().subscribe_new_heads().compat().await {
+                Ok(subscription) => subscription.unsubscribe().compat().await.is_ok(),
+                Err(_) => false,
+            }
+        };
+        let supports_new_heads = tokio::time::timeout(NEW_HEADS_PROBE_TIMEOUT, probe)
+            .await
+            .unwrap_or(false);
+
+        // Probe the capabilities of the node, and only treat a capability
+        // as missing if the node rejects the request that needs it
+        let latest = web3.eth().block_number().compat().await.ok();
+        let trace_method = match latest {
+            Some(latest) => Self::detect_trace_method(&web3, latest).await,
+            None => Some(TraceMethod::TraceFilter),
+        };
+        let is_archive = match latest {
+            Some(latest) => Self::detect_archive(logger, &hostname, &web3, latest).await,
+            None => true,
+        };
+        let max_log_range = match latest {
+            Some(latest) => Self::detect_max_log_range(&web3, latest).await,
+            None => None,
+        };
+        info!(
+            logger,
+            "Detected Ethereum node capabilities";
+            "url" => &hostname,
+            "archive" => is_archive,
+            "trace_method" => format!("{:?}", trace_method),
+            "max_log_range" => format!("{:?}", max_log_range),
+            "new_heads" => supports_new_heads,
+        );
+
         EthereumAdapter {
             url_hostname: Arc::new(hostname),
             web3,
             metrics: provider_metrics,
             is_ganache,
+            supports_new_heads,
+            trace_method,
+            is_archive,
+            max_log_range,
+        }
+    }
+
+    /// Use `trace_filter` if the node supports it, and fall back to
+    /// `debug_traceBlockByHash` if it does not. Return `None` if the node
+    /// rejects both.
+    async fn detect_trace_method(web3: &Web3<T>, latest: U64) -> Option<TraceMethod> {
+        // Filtering by the zero address keeps the response empty
+        let probe = TraceFilterBuilder::default()
+            .from_block(BlockNumber::Number(latest))
+            .to_block(BlockNumber::Number(latest))
+            .to_address(vec![H160::zero()])
+            .build();
+        match web3.trace().filter(probe).compat().await {
+            Ok(_) => return Some(TraceMethod::TraceFilter),
+            Err(e) if !rejected(&e) => return Some(TraceMethod::TraceFilter),
+            Err(_) => (),
+        }
+
+        // Tracing a block that does not exist tells us whether the method
+        // exists without making the node do any work
+        let probe = web3
+            .transport()
+            .execute(
+                "debug_traceBlockByHash",
+                vec![
+                    serde_json::to_value(H256::zero()).unwrap(),
+                    serde_json::json!({ "tracer": "callTracer" }),
+                ],
+            )
+            .compat()
+            .await;
+        match probe {
+            Err(e) if method_missing(&e) => None,
+            _ => Some(TraceMethod::DebugTraceBlock),
+        }
+    }
+
+    /// Check whether the node still has the state of block 1, which only
+    /// archive nodes keep once the chain has moved on. Only a node that
+    /// says it does not have that state is not an archive node; if the
+    /// check fails for any other reason, we assume that it is one
+    async fn detect_archive(logger: &Logger, hostname: &str, web3: &Web3<T>, latest: U64) -> bool {
+        if latest.as_u64() <= ARCHIVE_PROBE_DISTANCE {
+            return true;
         }
+        let res = web3
+            .eth()
+            .balance(H160::zero(), Some(BlockNumber::Number(1.into())))
+            .compat()
+            .await;
+        match archive_from_probe(&res) {
+            Ok(is_archive) => is_archive,
+            Err(e) => {
+                warn!(
+                    logger,
+                    "Could not check whether the Ethereum node is an archive node; \
+                     assuming that it is";
+                    "url" => hostname,
+                    "error" => e.to_string(),
+                );
+                true
+            }
+        }
+    }
+
+    /// Find the largest block range the node lets us scan with one
+    /// `eth_getLogs` request, or `None` if it accepts all the ranges we try
+    async fn detect_max_log_range(web3: &Web3<T>, latest: U64) -> Option<u64> {
+        probe_max_log_range(latest.as_u64(), |from, to| {
+            // Logs from the zero address keep the response empty
+            let probe = FilterBuilder::default()
+                .from_block(BlockNumber::Number(from.into()))
+                .to_block(BlockNumber::Number(to.into()))
+                .address(vec![H160::zero()])
+                .build();
+            web3.eth().logs(probe).compat().map(|res| res.map(|_| ()))
+        })
+        .await
     }
 
     fn traces(
@@ -207,6 +484,117 @@ where
             })
     }
 
+    /// Get the calls made in block `block_number` with
+    /// `debug_traceBlockByHash`. The block is looked up first so that the
+    /// traces are for the same block as its transaction hashes, even if
+    /// the block is reorged in the meantime.
+    fn debug_block_calls(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        block_number: u64,
+    ) -> impl Future<Item = (H256, Vec<EthereumCall>), Error = Error> {
+        let eth = self.clone();
+        let logger = logger.to_owned();
+
+        retry("debug_traceBlockByHash RPC call", &logger)
+            .limit(*REQUEST_RETRIES)
+            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .run(move || {
+                let web3 = eth.web3.clone();
+                let logger = logger.clone();
+                let start = Instant::now();
+                let subgraph_metrics = subgraph_metrics.clone();
+                let provider_metrics = eth.metrics.clone();
+                eth.web3
+                    .eth()
+                    .block(BlockId::Number(BlockNumber::Number(block_number.into())))
+                    .from_err::<Error>()
+                    .and_then(move |block| {
+                        block.ok_or_else(|| {
+                            format_err!("Ethereum node did not find block #{}", block_number)
+                        })
+                    })
+                    .and_then(move |block| {
+                        // Unwrap: blocks on the chain always have a hash
+                        let block_hash = block.hash.unwrap();
+                        web3.transport()
+                            .execute(
+                                "debug_traceBlockByHash",
+                                vec![
+                                    serde_json::to_value(block_hash).unwrap(),
+                                    serde_json::json!({ "tracer": "callTracer" }),
+                                ],
+                            )
+                            .from_err::<Error>()
+                            .and_then(move |traces| -> Result<_, Error> {
+                                let traces = serde_json::from_value(traces)?;
+                                let calls = EthereumCall::from_transaction_traces(&block, traces)?;
+                                if !calls.is_empty() {
+                                    debug!(
+                                        logger,
+                                        "Received {} calls for block {}",
+                                        calls.len(),
+                                        block_number
+                                    );
+                                }
+                                Ok((block_hash, calls))
+                            })
+                    })
+                    .then(move |result| {
+                        let elapsed = start.elapsed().as_secs_f64();
+                        provider_metrics.observe_request(elapsed, "debug_traceBlockByHash");
+                        subgraph_metrics.observe_request(elapsed, "debug_traceBlockByHash");
+                        if result.is_err() {
+                            provider_metrics.add_error("debug_traceBlockByHash");
+                            subgraph_metrics.add_error("debug_traceBlockByHash");
+                        }
+                        result
+                    })
+            })
+            .map_err(move |e| {
+                e.into_inner().unwrap_or_else(move || {
+                    format_err!(
+                        "Ethereum node took too long to respond to debug_traceBlockByHash \
+                         (block {})",
+                        block_number
+                    )
+                })
+            })
+    }
+
+    /// Stream the calls in blocks `from` to `to` with the method the node
+    /// supports. With `trace_filter`, only calls to `addresses` are
+    /// returned if there are any; `debug_traceBlockByHash` can not filter
+    /// and returns all calls.
+    fn call_stream(
+        self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        addresses: Vec<H160>,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        match self.trace_method.unwrap_or(TraceMethod::TraceFilter) {
+            TraceMethod::TraceFilter => Box::new(
+                self.trace_stream(logger, subgraph_metrics, from, to, addresses)
+                    .filter_map(|trace| EthereumCall::try_from_trace(&trace)),
+            ),
+            TraceMethod::DebugTraceBlock => {
+                let logger = logger.to_owned();
+                Box::new(
+                    stream::iter_ok::<_, Error>(from..=to)
+                        .map(move |block_number| {
+                            self.debug_block_calls(&logger, subgraph_metrics.clone(), block_number)
+                                .map(|(_, calls)| stream::iter_ok::<_, Error>(calls))
+                        })
+                        .buffered(*BLOCK_BATCH_SIZE)
+                        .flatten(),
+                )
+            }
+        }
+    }
+
     fn logs_with_sigs(
         &self,
         logger: &Logger,
@@ -336,6 +724,11 @@ where
             false => to - from,
             true => (to - from).min(*MAX_EVENT_ONLY_RANGE - 1),
         };
+        // Stay within the range the node accepts, as far as we know it
+        let step = match self.max_log_range {
+            Some(max_log_range) => step.min(max_log_range - 1),
+            None => step,
+        };
 
         // Typically this will loop only once and fetch the entire range in one request. But if the
         // node returns an error that signifies the request is to heavy to process, the range will
@@ -609,9 +1002,10 @@ where
 
 impl<T> EthereumAdapterTrait for EthereumAdapter<T>
 where
-    T: web3::BatchTransport + Send + Sync + 'static,
+    T: web3::BatchTransport + web3::DuplexTransport + Send + Sync + 'static,
     T::Batch: Send,
     T::Out: Send,
+    T::NotificationStream: Send,
 {
     fn url_hostname(&self) -> &str {
         &self.url_hostname
@@ -665,6 +1059,35 @@ where
         )
     }
 
+    fn detected_capabilities(&self) -> NodeCapabilities {
+        NodeCapabilities {
+            archive: self.is_archive,
+            traces: self.trace_method.is_some(),
+        }
+    }
+
+    fn supports_new_heads(&self) -> bool {
+        self.supports_new_heads
+    }
+
+    fn new_heads(&self, logger: &Logger) -> Box<dyn Stream<Item = H256, Error = Error> + Send> {
+        let logger = logger.clone();
+
+        Box::new(
+            self.web3
+                .eth_subscribe()
+                .subscribe_new_heads()
+                .map_err(|e| format_err!("failed to subscribe to new heads: {}", e))
+                .map(move |subscription| {
+                    debug!(logger, "Subscribed to new heads");
+                    subscription
+                        .map_err(|e| format_err!("new heads subscription failed: {}", e))
+                        .filter_map(|header: BlockHeader| header.hash)
+                })
+                .flatten_stream(),
+        )
+    }
+
     fn latest_block_header(
         &self,
         logger: &Logger,
@@ -1088,6 +1511,25 @@ where
         block_hash: H256,
     ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
         let eth = self.clone();
+        if eth.trace_method == Some(TraceMethod::DebugTraceBlock) {
+            return Box::new(
+                eth.debug_block_calls(&logger, subgraph_metrics, block_number)
+                    .and_then(move |(hash, calls)| {
+                        // The block with this number may have been replaced
+                        // since we were asked for its calls
+                        if hash != block_hash {
+                            return Err(format_err!(
+                                "Block traces are for an unexpected block: \
+                                 number = `{}`, hash = `{}`",
+                                block_number,
+                                block_hash,
+                            ));
+                        }
+                        Ok(calls)
+                    }),
+            );
+        }
+
         let addresses = Vec::new();
         let calls = eth
             .trace_stream(
@@ -1177,8 +1619,7 @@ where
             .into_iter()
             .collect::<Vec<H160>>();
         Box::new(
-            eth.trace_stream(&logger, subgraph_metrics, from, to, addresses)
-                .filter_map(|trace| EthereumCall::try_from_trace(&trace))
+            eth.call_stream(&logger, subgraph_metrics, from, to, addresses)
                 .filter(move |call| {
                     // `trace_filter` can only filter by calls `to` an address and
                     // a block range. Since subgraphs are subscribing to calls
@@ -1326,3 +1767,79 @@ where
         )
     }
 }
+
+#[cfg(test)]
+fn rpc_error(message: &str) -> web3::error::Error {
+    web3::error::Error::Rpc(jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::ServerError(-32000),
+        message: message.to_owned(),
+        data: None,
+    })
+}
+
+#[test]
+fn recognizes_range_limits() {
+    assert!(range_limited(&rpc_error(
+        "exceed maximum block range: 5000"
+    )));
+    assert!(range_limited(&rpc_error(
+        "query returned more than 10000 results"
+    )));
+    assert!(!range_limited(&rpc_error(
+        "daily request count exceeded, request rate limited"
+    )));
+    assert!(!range_limited(&web3::error::Error::Transport(
+        "request timed out".to_owned()
+    )));
+}
+
+#[test]
+fn archive_probe() {
+    assert_eq!(Some(true), archive_from_probe(&Ok(())).ok());
+    assert_eq!(
+        Some(false),
+        archive_from_probe::<()>(&Err(rpc_error("missing trie node 1234 (path )"))).ok()
+    );
+    assert!(archive_from_probe::<()>(&Err(rpc_error("rate limited"))).is_err());
+}
+
+#[tokio::test]
+async fn probes_log_range() {
+    use std::cell::Cell;
+
+    // A node that scans at most `limit` blocks and fails the first
+    // `flaky` requests for unrelated reasons
+    let node = |limit: u64, flaky: usize| {
+        let requests = Cell::new(0);
+        move |from: u64, to: u64| {
+            requests.set(requests.get() + 1);
+            let res = if requests.get() <= flaky {
+                Err(rpc_error("rate limited"))
+            } else if to - from + 1 > limit {
+                Err(rpc_error("block range is too wide"))
+            } else {
+                Ok(())
+            };
+            futures03::future::ready(res)
+        }
+    };
+
+    assert_eq!(
+        None,
+        probe_max_log_range(1_000_000, node(u64::MAX, 0)).await
+    );
+    assert_eq!(
+        Some(1_000),
+        probe_max_log_range(1_000_000, node(5_000, 0)).await
+    );
+    assert_eq!(Some(100), probe_max_log_range(1_000_000, node(10, 0)).await);
+    // Only ranges up to the chain head are tried
+    assert_eq!(Some(100), probe_max_log_range(5_000, node(500, 0)).await);
+    // Unrelated failures are retried
+    assert_eq!(
+        Some(1_000),
+        probe_max_log_range(1_000_000, node(5_000, 2)).await
+    );
+    // A node that keeps failing does not get its range limited
+    assert_eq!(None, probe_max_log_range(1_000_000, node(5_000, 100)).await);
+}
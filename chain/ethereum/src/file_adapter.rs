@@ -0,0 +1,621 @@
+//! An `EthereumAdapter` that serves block and log data from files instead
+//! of an Ethereum node.
+//!
+//! The files contain full blocks, i.e., blocks with their transaction
+//! receipts, in the same JSON format that the block cache in the database
+//! uses. That makes it possible to export blocks from the `data` column of
+//! the `ethereum_blocks` table of one installation, or to produce them with
+//! any other tool, and index subgraphs from them without access to an
+//! Ethereum node, for example, for backfills on machines without network
+//! access or for indexing tests that need to be reproducible.
+//!
+//! A block directory contains any number of files ending in `.json` or
+//! `.jsonl`, each with one block per line. The directory can also contain
+//! a file `net_version` with the network version that the blocks belong
+//! to; if it is missing, the network version is assumed to be `1`. The
+//! blocks must form one chain, and the genesis block must be among them.
+//! The files do not need to contain every block; blocks that are missing
+//! are skipped when ranges of blocks are scanned.
+//!
+//! When the blocks are read, only the hash, number and parent hash of each
+//! block are kept in memory, together with the position of the block in
+//! its file; the blocks themselves are read from the files when they are
+//! needed, and only a few recently used blocks are cached.
+//!
+//! The block files can also be kept in S3-compatible object storage, at a
+//! location `s3://<bucket>/<prefix>`. The objects below the prefix are then
+//! downloaded into a local directory once, and blocks are read from there.
+//! See `graph::util::object_store` for how the object storage is accessed.
+//!
+//! Only log triggers and block triggers for every block can be derived
+//! from this data. Subgraphs that need call traces or that make contract
+//! calls will fail.
+use ethabi::Token;
+use futures::future;
+use futures::prelude::*;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use graph::components::ethereum::{EthereumAdapter as EthereumAdapterTrait, *};
+use graph::prelude::{
+    debug, format_err, futures03, info, serde_json, stream, tokio, web3, ChainStore, DynTryFuture,
+    Error, EthereumCallCache, Logger,
+};
+use graph::util::object_store::ObjectStore;
+use web3::types::*;
+
+const DEFAULT_NET_VERSION: &str = "1";
+
+/// How many recently read blocks are kept in memory
+const BLOCK_CACHE_SIZE: usize = 64;
+
+lazy_static! {
+    /// The directory into which block files from object storage are
+    /// downloaded
+    static ref BLOCK_FILES_CACHE_DIR: PathBuf = std::env::var("GRAPH_BLOCK_FILES_CACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join("graph-node-block-files"));
+}
+
+/// The parts of a block that are needed to index the block files
+#[derive(Deserialize)]
+struct BlockHeader {
+    block: HeaderFields,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HeaderFields {
+    hash: Option<H256>,
+    number: Option<U64>,
+    parent_hash: H256,
+}
+
+/// Where to find a block in the block files
+struct IndexedBlock {
+    hash: H256,
+    parent_hash: H256,
+    /// Index into `FileEthereumAdapter.files`
+    file: usize,
+    /// Byte offset of the block's line in the file
+    offset: u64,
+    /// Length of the block's line in bytes
+    len: usize,
+}
+
+pub struct FileEthereumAdapter {
+    location: String,
+    net_version: String,
+    files: Vec<PathBuf>,
+    /// Where each block is stored, indexed by block number
+    blocks: BTreeMap<u64, IndexedBlock>,
+    /// Maps block hashes to block numbers
+    numbers: HashMap<H256, u64>,
+    /// The most recently read blocks, the most recent one last
+    cache: Mutex<VecDeque<(u64, Arc<EthereumBlock>)>>,
+}
+
+impl FileEthereumAdapter {
+    /// Index the blocks in the files in the directory `dir`
+    pub fn open(logger: &Logger, dir: &str) -> Result<Self, Error> {
+        let path = Path::new(dir);
+        let net_version = match fs::read_to_string(path.join("net_version")) {
+            Ok(version) => version.trim().to_owned(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DEFAULT_NET_VERSION.to_owned(),
+            Err(e) => return Err(format_err!("failed to read {}/net_version: {}", dir, e)),
+        };
+
+        let mut files = fs::read_dir(path)
+            .map_err(|e| format_err!("failed to read block directory {}: {}", dir, e))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|file| is_block_file(&file.to_string_lossy()));
+        files.sort();
+
+        let mut blocks = BTreeMap::new();
+        let mut numbers = HashMap::new();
+        for (file_idx, file) in files.iter().enumerate() {
+            let mut reader = BufReader::new(fs::File::open(file)?);
+            let mut line = String::new();
+            let mut offset = 0u64;
+            let mut line_no = 0;
+            loop {
+                line.clear();
+                let len = reader.read_line(&mut line)?;
+                if len == 0 {
+                    break;
+                }
+                line_no += 1;
+                let line_offset = offset;
+                offset += len as u64;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let header: BlockHeader = serde_json::from_str(&line).map_err(|e| {
+                    format_err!("invalid block in {}:{}: {}", file.display(), line_no, e)
+                })?;
+                let (hash, number) = match (header.block.hash, header.block.number) {
+                    (Some(hash), Some(number)) => (hash, number.as_u64()),
+                    _ => {
+                        return Err(format_err!(
+                            "block in {}:{} has no hash or number",
+                            file.display(),
+                            line_no
+                        ))
+                    }
+                };
+                if let Some(other) = blocks.get(&number).map(|b: &IndexedBlock| b.hash) {
+                    if other != hash {
+                        return Err(format_err!(
+                            "the block files contain two different blocks with number {}",
+                            number
+                        ));
+                    }
+                }
+                numbers.insert(hash, number);
+                blocks.insert(
+                    number,
+                    IndexedBlock {
+                        hash,
+                        parent_hash: header.block.parent_hash,
+                        file: file_idx,
+                        offset: line_offset,
+                        len,
+                    },
+                );
+            }
+        }
+
+        if !blocks.contains_key(&0) {
+            return Err(format_err!(
+                "the block files in {} do not contain the genesis block",
+                dir
+            ));
+        }
+
+        // Make sure the blocks form one chain, at least where they are
+        // contiguous
+        for (number, block) in blocks.iter() {
+            if let Some(parent) = number.checked_sub(1).and_then(|n| blocks.get(&n)) {
+                if parent.hash != block.parent_hash {
+                    return Err(format_err!(
+                        "block {} is not a child of block {} in the block files",
+                        number,
+                        number - 1
+                    ));
+                }
+            }
+        }
+
+        debug!(logger, "Indexed block files";
+               "location" => dir,
+               "blocks" => blocks.len(),
+               "net_version" => &net_version);
+
+        Ok(FileEthereumAdapter {
+            location: dir.to_owned(),
+            net_version,
+            files,
+            blocks,
+            numbers,
+            cache: Mutex::new(VecDeque::with_capacity(BLOCK_CACHE_SIZE)),
+        })
+    }
+
+    /// Download the block files at the object storage `location` of the
+    /// form `s3://<bucket>/<prefix>` into a local directory and index them.
+    /// Files that were downloaded before are not downloaded again
+    pub async fn from_object_store(logger: &Logger, location: &str) -> Result<Self, Error> {
+        let store = ObjectStore::from_location(location).map_err(|e| format_err!("{}", e))?;
+        let dir = BLOCK_FILES_CACHE_DIR.join(location["s3://".len()..].trim_matches('/'));
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| format_err!("failed to create {}: {}", dir.display(), e))?;
+
+        let names = store.list().await.map_err(|e| format_err!("{}", e))?;
+        let mut downloaded = 0;
+        for name in names
+            .into_iter()
+            .filter(|name| is_block_file(name) || name == "net_version")
+        {
+            // Objects in subdirectories are ignored, just like
+            // subdirectories of a local block directory
+            if name.contains('/') {
+                continue;
+            }
+            let path = dir.join(&name);
+            if name != "net_version" && tokio::fs::metadata(&path).await.is_ok() {
+                continue;
+            }
+            // Write to a temporary file first so that an interrupted
+            // download is not mistaken for a complete file later
+            let tmp = dir.join(format!("{}.partial", name));
+            if !store
+                .get_file(&name, &tmp)
+                .await
+                .map_err(|e| format_err!("{}", e))?
+            {
+                return Err(format_err!("object {}/{} disappeared", location, name));
+            }
+            tokio::fs::rename(&tmp, &path).await?;
+            downloaded += 1;
+        }
+        info!(logger, "Downloaded block files";
+              "location" => location,
+              "directory" => dir.display().to_string(),
+              "files" => downloaded);
+
+        // Indexing reads all the block files
+        let index_logger = logger.clone();
+        let mut adapter = graph::spawn_blocking_allow_panic(move || {
+            Self::open(&index_logger, &dir.to_string_lossy())
+        })
+        .await
+        .map_err(|e| format_err!("failed to index the block files: {}", e))??;
+        adapter.location = location.to_owned();
+        Ok(adapter)
+    }
+
+    /// Read the block with the given number from its file, or from the
+    /// cache if it was read recently
+    fn block(&self, number: u64) -> Result<Option<Arc<EthereumBlock>>, Error> {
+        let indexed = match self.blocks.get(&number) {
+            Some(indexed) => indexed,
+            None => return Ok(None),
+        };
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(pos) = cache.iter().position(|(n, _)| *n == number) {
+                let entry = cache.remove(pos).unwrap();
+                let block = entry.1.clone();
+                cache.push_back(entry);
+                return Ok(Some(block));
+            }
+        }
+
+        // Read the block without holding the lock so that reading one block
+        // does not keep other blocks from being served from the cache
+        let file = &self.files[indexed.file];
+        let mut reader = fs::File::open(file)?;
+        reader.seek(SeekFrom::Start(indexed.offset))?;
+        let mut line = vec![0u8; indexed.len];
+        reader.read_exact(&mut line)?;
+        let block: EthereumBlock = serde_json::from_slice(&line)
+            .map_err(|e| format_err!("invalid block #{} in {}: {}", number, file.display(), e))?;
+        if block.block.hash != Some(indexed.hash) {
+            return Err(format_err!(
+                "block #{} in {} changed since the block files were read",
+                number,
+                file.display()
+            ));
+        }
+
+        let block = Arc::new(block);
+        let mut cache = self.cache.lock().unwrap();
+        // Another caller might have read the same block in the meantime
+        if !cache.iter().any(|(n, _)| *n == number) {
+            if cache.len() >= BLOCK_CACHE_SIZE {
+                cache.pop_front();
+            }
+            cache.push_back((number, block.clone()));
+        }
+        Ok(Some(block))
+    }
+
+    fn by_hash(&self, hash: &H256) -> Result<Option<Arc<EthereumBlock>>, Error> {
+        match self.numbers.get(hash) {
+            Some(number) => self.block(*number),
+            None => Ok(None),
+        }
+    }
+
+    fn head(&self) -> Result<Arc<EthereumBlock>, Error> {
+        // Unwraps: `open` makes sure that there is at least one block
+        let number = *self.blocks.keys().next_back().unwrap();
+        Ok(self.block(number)?.unwrap())
+    }
+
+    fn ptr(&self, number: u64) -> Option<EthereumBlockPointer> {
+        self.blocks
+            .get(&number)
+            .map(|block| EthereumBlockPointer::from((block.hash, number)))
+    }
+
+    fn unsupported(what: &str) -> Error {
+        format_err!("{} can not be served from block files", what)
+    }
+}
+
+fn is_block_file(name: &str) -> bool {
+    name.ends_with(".json") || name.ends_with(".jsonl")
+}
+
+impl EthereumAdapterTrait for FileEthereumAdapter {
+    fn url_hostname(&self) -> &str {
+        &self.location
+    }
+
+    fn net_identifiers(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        // Unwrap: `open` makes sure that the genesis block exists and has a hash
+        let genesis_block_hash = self.blocks.get(&0).unwrap().hash;
+        Box::new(future::ok(EthereumNetworkIdentifier {
+            net_version: self.net_version.clone(),
+            genesis_block_hash,
+        }))
+    }
+
+    fn detected_capabilities(&self) -> NodeCapabilities {
+        // Block files have no call traces
+        NodeCapabilities {
+            archive: true,
+            traces: false,
+        }
+    }
+
+    fn supports_new_heads(&self) -> bool {
+        false
+    }
+
+    fn new_heads(&self, _: &Logger) -> Box<dyn Stream<Item = H256, Error = Error> + Send> {
+        Box::new(stream::once(Err(Self::unsupported(
+            "new head subscriptions",
+        ))))
+    }
+
+    fn latest_block_header(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = web3::types::Block<H256>, Error = EthereumAdapterError> + Send> {
+        let head = match self.head() {
+            Ok(head) => head,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+        // Turn the transactions into transaction hashes by going through
+        // JSON, which saves us from copying every field of the block
+        let header = serde_json::to_value(&head.block)
+            .map(|mut value| {
+                value["transactions"] = serde_json::to_value(
+                    head.block
+                        .transactions
+                        .iter()
+                        .map(|tx| tx.hash)
+                        .collect::<Vec<_>>(),
+                )
+                .unwrap();
+                value
+            })
+            .and_then(serde_json::from_value)
+            .map_err(|e| format_err!("failed to convert block header: {}", e).into());
+        Box::new(future::result(header))
+    }
+
+    fn latest_block(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        Box::new(future::result(
+            self.head()
+                .map(|head| head.block.clone())
+                .map_err(EthereumAdapterError::from),
+        ))
+    }
+
+    fn load_block(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        let block = self.by_hash(&block_hash).and_then(|block| {
+            block
+                .map(|block| block.block.clone())
+                .ok_or_else(|| format_err!("block files do not contain block {}", block_hash))
+        });
+        Box::new(future::result(block))
+    }
+
+    fn load_blocks(
+        &self,
+        _: Logger,
+        _: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        let mut blocks = Vec::new();
+        for hash in block_hashes {
+            match self.by_hash(&hash) {
+                Ok(Some(block)) => blocks.push(block.block.clone()),
+                Ok(None) => {
+                    return Box::new(stream::once(Err(format_err!(
+                        "block files do not contain block {}",
+                        hash
+                    ))))
+                }
+                Err(e) => return Box::new(stream::once(Err(e))),
+            }
+        }
+        blocks.sort_by_key(|block| block.number);
+        Box::new(stream::iter_ok(blocks))
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        _: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        // Block files may have gaps, for example, if they only contain the
+        // blocks that a subgraph needs; the blocks in the gaps are skipped
+        let ptrs = self
+            .blocks
+            .range(from..=to)
+            .map(|(number, block)| EthereumBlockPointer::from((block.hash, *number)))
+            .collect();
+        Box::new(future::ok(ptrs))
+    }
+
+    fn block_by_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.by_hash(&block_hash)
+                .map(|block| block.map(|block| block.block.clone())),
+        ))
+    }
+
+    fn block_by_number(
+        &self,
+        _: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.block(block_number)
+                .map(|block| block.map(|block| block.block.clone())),
+        ))
+    }
+
+    fn load_full_block(
+        &self,
+        _: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let block_hash = match block.hash {
+            Some(hash) => hash,
+            None => {
+                return Box::new(future::err(EthereumAdapterError::BlockUnavailable(
+                    H256::zero(),
+                )))
+            }
+        };
+        Box::new(future::result(
+            self.by_hash(&block_hash)
+                .map_err(EthereumAdapterError::from)
+                .and_then(|block| {
+                    block
+                        .map(|block| block.as_ref().clone())
+                        .ok_or(EthereumAdapterError::BlockUnavailable(block_hash))
+                }),
+        ))
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        Box::new(future::result(self.ptr(block_number).ok_or_else(|| {
+            format_err!("block files do not contain block #{}", block_number).into()
+        })))
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+        _: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        Box::new(future::ok(
+            self.blocks.get(&block_number).map(|block| block.hash),
+        ))
+    }
+
+    fn uncles(
+        &self,
+        _: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        // The block files only contain blocks on the main chain
+        Box::new(future::ok(vec![None; block.uncles.len()]))
+    }
+
+    fn is_on_main_chain(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: Arc<dyn ChainStore>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        Box::new(future::result(
+            self.blocks
+                .get(&block_ptr.number)
+                .map(|block| block.hash == block_ptr.hash)
+                .ok_or_else(|| {
+                    format_err!("block files do not contain block #{}", block_ptr.number)
+                }),
+        ))
+    }
+
+    fn calls_in_block(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: u64,
+        _: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        Box::new(future::err(Self::unsupported("call traces")))
+    }
+
+    fn logs_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        let mut logs = Vec::new();
+        for number in self.blocks.range(from..=to).map(|(number, _)| *number) {
+            let block = match self.block(number) {
+                Ok(block) => block,
+                Err(e) => return Box::pin(futures03::future::err(e)),
+            };
+            logs.extend(
+                block
+                    .iter()
+                    .flat_map(|block| block.transaction_receipts.iter())
+                    .flat_map(|receipt| receipt.logs.iter())
+                    .filter(|log| log_filter.matches(log))
+                    .cloned(),
+            );
+        }
+        Box::pin(futures03::future::ok(logs))
+    }
+
+    fn calls_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: u64,
+        _: u64,
+        _: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        Box::new(stream::once(Err(Self::unsupported("call traces"))))
+    }
+
+    fn contract_call(
+        &self,
+        _: &Logger,
+        call: EthereumContractCall,
+        _: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        Box::new(future::err(EthereumContractCallError::Unavailable(
+            format!(
+                "the call to {} at block {} can not be served from block files",
+                call.address, call.block_ptr.number
+            ),
+        )))
+    }
+}
@@ -3,12 +3,16 @@ extern crate lazy_static;
 
 mod block_ingestor;
 mod block_stream;
+mod chain_verifier;
 mod config;
 mod ethereum_adapter;
+mod file_adapter;
 pub mod network_indexer;
 mod transport;
 
 pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics};
 pub use self::block_stream::{BlockStream, BlockStreamBuilder};
+pub use self::chain_verifier::ChainVerifier;
 pub use self::ethereum_adapter::EthereumAdapter;
-pub use self::transport::{EventLoopHandle, Transport};
+pub use self::file_adapter::FileEthereumAdapter;
+pub use self::transport::{set_rate_limit, EventLoopHandle, Transport};
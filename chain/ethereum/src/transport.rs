@@ -1,7 +1,12 @@
 use jsonrpc_core::types::Call;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::env;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use web3::api::SubscriptionId;
 pub use web3::transports::EventLoopHandle;
 use web3::transports::{http, ipc, ws};
 use web3::RequestId;
@@ -10,26 +15,92 @@ use graph::prelude::*;
 
 use super::config::ETHEREUM_CONFIG;
 
+lazy_static! {
+    /// The rate limits of all providers, keyed by their URL. Transports
+    /// share the limit for their URL with this map, so that changing a
+    /// limit with `set_rate_limit` affects transports that already exist
+    static ref RATE_LIMITS: Mutex<HashMap<String, Arc<RateLimit>>> = Mutex::new(HashMap::new());
+}
+
+/// Limit the requests to the provider at `url` to `per_second` requests
+/// per second, or remove the limit if `per_second` is `None`
+pub fn set_rate_limit(url: &str, per_second: Option<u32>) {
+    rate_limit(url)
+        .per_second
+        .store(per_second.unwrap_or(0), Ordering::SeqCst);
+}
+
+fn rate_limit(url: &str) -> Arc<RateLimit> {
+    RATE_LIMITS
+        .lock()
+        .unwrap()
+        .entry(url.to_owned())
+        .or_default()
+        .clone()
+}
+
+/// Spaces requests out evenly so that there are at most `per_second` of
+/// them in any second. A limit of 0 means that there is no limit
+#[derive(Debug, Default)]
+struct RateLimit {
+    per_second: AtomicU32,
+    /// The earliest time at which the next request can be sent
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimit {
+    /// Reserve `count` requests and return how long the caller has to
+    /// wait before sending them, if at all
+    fn reserve(&self, count: u32) -> Option<Duration> {
+        let per_second = self.per_second.load(Ordering::SeqCst);
+        if per_second == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap();
+        let start = next.map_or(now, |next| next.max(now));
+        *next = Some(start + Duration::from_secs(count as u64) / per_second);
+        Some(start - now).filter(|wait| *wait > Duration::from_secs(0))
+    }
+}
+
 /// Abstraction over the different web3 transports.
 #[derive(Clone, Debug)]
-pub enum Transport {
+pub struct Transport {
+    connection: Connection,
+    rate_limit: Arc<RateLimit>,
+}
+
+#[derive(Clone, Debug)]
+enum Connection {
     RPC(http::Http),
     IPC(ipc::Ipc),
     WS(ws::WebSocket),
 }
 
 impl Transport {
+    fn new(url: &str, connection: Connection) -> Self {
+        Transport {
+            connection,
+            rate_limit: rate_limit(url),
+        }
+    }
+
     /// Creates an IPC transport.
     pub fn new_ipc(ipc: &str) -> (EventLoopHandle, Self) {
         ipc::Ipc::new(ipc)
-            .map(|(event_loop, transport)| (event_loop, Transport::IPC(transport)))
+            .map(|(event_loop, transport)| {
+                (event_loop, Transport::new(ipc, Connection::IPC(transport)))
+            })
             .expect("Failed to connect to Ethereum IPC")
     }
 
     /// Creates a WebSocket transport.
     pub fn new_ws(ws: &str) -> (EventLoopHandle, Self) {
         ws::WebSocket::new(ws)
-            .map(|(event_loop, transport)| (event_loop, Transport::WS(transport)))
+            .map(|(event_loop, transport)| {
+                (event_loop, Transport::new(ws, Connection::WS(transport)))
+            })
             .expect("Failed to connect to Ethereum WS")
     }
 
@@ -46,28 +117,58 @@ impl Transport {
         let headers = cfg.map(|cfg| cfg.http_headers.clone()).unwrap_or_default();
 
         http::Http::with_max_parallel_and_headers(rpc, max_parallel_http, headers)
-            .map(|(event_loop, transport)| (event_loop, Transport::RPC(transport)))
+            .map(|(event_loop, transport)| {
+                (event_loop, Transport::new(rpc, Connection::RPC(transport)))
+            })
             .expect("Failed to connect to Ethereum RPC")
     }
+
+    /// Call `send` once the rate limit allows sending `count` more
+    /// requests. The transports start a request as soon as they are asked
+    /// to send it, and not when the future they return is first polled,
+    /// which is why we can not simply delay that future
+    fn limited<T, F>(
+        &self,
+        count: u32,
+        send: F,
+    ) -> Box<dyn Future<Item = T, Error = web3::error::Error> + Send>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Box<dyn Future<Item = T, Error = web3::error::Error> + Send>
+            + Send
+            + 'static,
+    {
+        match self.rate_limit.reserve(count) {
+            None => send(),
+            Some(wait) => Box::new(
+                tokio::time::delay_for(wait)
+                    .map(Ok::<(), web3::error::Error>)
+                    .boxed()
+                    .compat()
+                    .and_then(move |()| send()),
+            ),
+        }
+    }
 }
 
 impl web3::Transport for Transport {
     type Out = Box<dyn Future<Item = Value, Error = web3::error::Error> + Send>;
 
     fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
-        match self {
-            Transport::RPC(http) => http.prepare(method, params),
-            Transport::IPC(ipc) => ipc.prepare(method, params),
-            Transport::WS(ws) => ws.prepare(method, params),
+        match &self.connection {
+            Connection::RPC(http) => http.prepare(method, params),
+            Connection::IPC(ipc) => ipc.prepare(method, params),
+            Connection::WS(ws) => ws.prepare(method, params),
         }
     }
 
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
-        match self {
-            Transport::RPC(http) => Box::new(http.send(id, request)),
-            Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
-            Transport::WS(ws) => Box::new(ws.send(id, request)),
-        }
+        let connection = self.connection.clone();
+        self.limited(1, move || match connection {
+            Connection::RPC(http) => Box::new(http.send(id, request)) as Self::Out,
+            Connection::IPC(ipc) => Box::new(ipc.send(id, request)),
+            Connection::WS(ws) => Box::new(ws.send(id, request)),
+        })
     }
 }
 
@@ -81,10 +182,51 @@ impl web3::BatchTransport for Transport {
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
-        match self {
-            Transport::RPC(http) => Box::new(http.send_batch(requests)),
-            Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
-            Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+        let requests: Vec<_> = requests.into_iter().collect();
+        let connection = self.connection.clone();
+        self.limited(requests.len() as u32, move || match connection {
+            Connection::RPC(http) => Box::new(http.send_batch(requests)) as Self::Batch,
+            Connection::IPC(ipc) => Box::new(ipc.send_batch(requests)),
+            Connection::WS(ws) => Box::new(ws.send_batch(requests)),
+        })
+    }
+}
+
+impl web3::DuplexTransport for Transport {
+    type NotificationStream =
+        Box<dyn Stream<Item = Value, Error = web3::error::Error> + Send + 'static>;
+
+    fn subscribe(&self, id: &SubscriptionId) -> Self::NotificationStream {
+        match &self.connection {
+            Connection::RPC(_) => Box::new(stream::once(Err(web3::error::Error::Transport(
+                "subscriptions are not supported over HTTP".to_owned(),
+            )))),
+            Connection::IPC(ipc) => Box::new(ipc.subscribe(id)),
+            Connection::WS(ws) => Box::new(ws.subscribe(id)),
+        }
+    }
+
+    fn unsubscribe(&self, id: &SubscriptionId) {
+        match &self.connection {
+            Connection::RPC(_) => (),
+            Connection::IPC(ipc) => ipc.unsubscribe(id),
+            Connection::WS(ws) => ws.unsubscribe(id),
         }
     }
 }
+
+#[test]
+fn rate_limit_spaces_requests() {
+    let limit = RateLimit::default();
+    assert_eq!(None, limit.reserve(100));
+
+    limit.per_second.store(10, Ordering::SeqCst);
+    assert_eq!(None, limit.reserve(1));
+    let wait = limit.reserve(5).unwrap();
+    assert!(wait > Duration::from_millis(50) && wait <= Duration::from_millis(100));
+    let wait = limit.reserve(1).unwrap();
+    assert!(wait > Duration::from_millis(550) && wait <= Duration::from_millis(600));
+
+    limit.per_second.store(0, Ordering::SeqCst);
+    assert_eq!(None, limit.reserve(1));
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::PathBuf;
+
+use graph::components::ethereum::{EthereumAdapter as _, EthereumBlock, LightEthereumBlock};
+use graph::log::logger;
+use graph::prelude::*;
+use graph_chain_ethereum::FileEthereumAdapter;
+use web3::types::{H256, U64};
+
+fn block(number: u64, parent: H256) -> EthereumBlock {
+    let mut block = LightEthereumBlock::default();
+    block.number = Some(U64::from(number));
+    block.hash = Some(H256::from_low_u64_be(number + 1000));
+    block.parent_hash = parent;
+    EthereumBlock {
+        block,
+        transaction_receipts: vec![],
+    }
+}
+
+/// Write a chain of `len` blocks to a fresh directory, split over two files
+fn block_dir(name: &str, len: u64) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("graph-node-block-files-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut parent = H256::zero();
+    let mut lines = vec![String::new(), String::new()];
+    for number in 0..len {
+        let block = block(number, parent);
+        parent = block.block.hash.unwrap();
+        let line = &mut lines[(number % 2) as usize];
+        line.push_str(&serde_json::to_string(&block).unwrap());
+        line.push('\n');
+    }
+    fs::write(dir.join("even.jsonl"), &lines[0]).unwrap();
+    fs::write(dir.join("odd.json"), &lines[1]).unwrap();
+    fs::write(dir.join("net_version"), "42\n").unwrap();
+    dir
+}
+
+#[test]
+fn serves_blocks_from_files() {
+    let logger = logger(false);
+    let dir = block_dir("serve", 5);
+    let adapter = FileEthereumAdapter::open(&logger, dir.to_str().unwrap()).unwrap();
+
+    let ident = adapter.net_identifiers(&logger).wait().unwrap();
+    assert_eq!("42", ident.net_version);
+    assert_eq!(H256::from_low_u64_be(1000), ident.genesis_block_hash);
+
+    let head = adapter.latest_block(&logger).wait().unwrap();
+    assert_eq!(Some(U64::from(4)), head.number);
+
+    let header = adapter.latest_block_header(&logger).wait().unwrap();
+    assert_eq!(head.hash, header.hash);
+
+    let block = adapter.block_by_number(&logger, 2).wait().unwrap();
+    assert_eq!(
+        Some(H256::from_low_u64_be(1002)),
+        block.and_then(|block| block.hash)
+    );
+    assert!(adapter
+        .block_by_number(&logger, 5)
+        .wait()
+        .unwrap()
+        .is_none());
+
+    let ptrs = adapter
+        .block_range_to_ptrs(logger.clone(), 1, 3)
+        .wait()
+        .unwrap();
+    let numbers: Vec<_> = ptrs.iter().map(|ptr| ptr.number).collect();
+    assert_eq!(vec![1, 2, 3], numbers);
+
+    let ptrs = adapter
+        .block_range_to_ptrs(logger.clone(), 3, 7)
+        .wait()
+        .unwrap();
+    let numbers: Vec<_> = ptrs.iter().map(|ptr| ptr.number).collect();
+    assert_eq!(vec![3, 4], numbers);
+}
+
+#[test]
+fn skips_gaps_in_the_block_files() {
+    let logger = logger(false);
+    let dir = block_dir("gaps", 10);
+    // Drop the odd blocks below 7 so that the files only contain blocks
+    // 0, 2, 4, 6, 7, 8 and 9
+    let odd: Vec<_> = fs::read_to_string(dir.join("odd.json"))
+        .unwrap()
+        .lines()
+        .skip(3)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(dir.join("odd.json"), odd.concat()).unwrap();
+    let adapter = FileEthereumAdapter::open(&logger, dir.to_str().unwrap()).unwrap();
+
+    let ptrs = adapter
+        .block_range_to_ptrs(logger.clone(), 1, 8)
+        .wait()
+        .unwrap();
+    let numbers: Vec<_> = ptrs.iter().map(|ptr| ptr.number).collect();
+    assert_eq!(vec![2, 4, 6, 7, 8], numbers);
+
+    assert!(adapter
+        .block_by_number(&logger, 3)
+        .wait()
+        .unwrap()
+        .is_none());
+    let block = adapter.block_by_number(&logger, 7).wait().unwrap();
+    assert_eq!(
+        Some(H256::from_low_u64_be(1007)),
+        block.and_then(|block| block.hash)
+    );
+}
+
+#[test]
+fn rejects_broken_chain() {
+    let logger = logger(false);
+    let dir = block_dir("broken", 3);
+    let orphan = block(3, H256::from_low_u64_be(7));
+    fs::write(
+        dir.join("orphan.json"),
+        serde_json::to_string(&orphan).unwrap(),
+    )
+    .unwrap();
+
+    assert!(FileEthereumAdapter::open(&logger, dir.to_str().unwrap()).is_err());
+}
+
+#[test]
+fn reads_blocks_beyond_the_cache() {
+    let logger = logger(false);
+    let dir = block_dir("lazy", 200);
+    let adapter = FileEthereumAdapter::open(&logger, dir.to_str().unwrap()).unwrap();
+
+    // Read more blocks than are cached, going back and forth, so that
+    // blocks have to be read from the files again after being evicted
+    for number in (0..200).chain((0..200).rev()).step_by(3) {
+        let block = adapter
+            .block_by_number(&logger, number)
+            .wait()
+            .unwrap()
+            .expect("the block files contain the block");
+        assert_eq!(Some(U64::from(number)), block.number);
+        assert_eq!(Some(H256::from_low_u64_be(number + 1000)), block.hash);
+    }
+
+    let head = adapter.latest_block(&logger).wait().unwrap();
+    assert_eq!(Some(U64::from(199)), head.number);
+}
@@ -0,0 +1,117 @@
+use graph::components::ens::EnsLookup;
+use graph::prelude::*;
+use graph::url::Url;
+
+/// Looks ENS names up with an HTTP endpoint that serves a rainbow table.
+/// A `GET` of `<endpoint>/<hash>` must respond with the name as the body,
+/// or with `404 Not Found` if the name is not known
+pub struct EnsEndpoint {
+    endpoint: Url,
+    http_client: reqwest::Client,
+}
+
+impl EnsEndpoint {
+    /// Panics if `endpoint` is not a valid URL.
+    pub fn new(mut endpoint: String) -> Self {
+        // Make sure the endpoint has a trailing slash so `Url::join` works.
+        if !endpoint.ends_with('/') {
+            endpoint.push('/')
+        }
+
+        EnsEndpoint {
+            endpoint: Url::parse(&endpoint).expect("Invalid ENS endpoint URL"),
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    async fn fetch(&self, hash: &str) -> Result<Option<String>, Error> {
+        let res = self
+            .http_client
+            .get(self.endpoint.join(hash).unwrap())
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let name = res.error_for_status()?.text().await?;
+        Ok(Some(name.trim().to_owned()))
+    }
+}
+
+impl EnsLookup for EnsEndpoint {
+    fn find_name(&self, hash: &str) -> Result<Option<String>, StoreError> {
+        // Only ask for well-formed hashes so that the hash is safe to put
+        // into the URL; nothing else can have a name
+        let valid = hash.len() == 66
+            && hash.starts_with("0x")
+            && hash[2..].chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Ok(None);
+        }
+
+        graph::block_on(self.fetch(hash)).map_err(|e| {
+            StoreError::Unknown(format_err!(
+                "error looking up ens_name for hash {}: {}",
+                hash,
+                e
+            ))
+        })
+    }
+}
+
+#[tokio::test]
+async fn looks_names_up_with_the_endpoint() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    const ETH: &str = "0x4f5b812789fc606be1b3b16908db13fc7a9adf7ca72641f84d75b47069d3d7f0";
+
+    // A rainbow table that only knows `eth`
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/names", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let target = line.split_whitespace().nth(1).unwrap_or("").to_owned();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                let (status, body) = if target == format!("/names/{}", ETH) {
+                    ("200 OK", "eth\n")
+                } else {
+                    ("404 Not Found", "")
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        }
+    });
+
+    let ens = Arc::new(EnsEndpoint::new(endpoint));
+    let find = |hash: &'static str| {
+        let ens = ens.clone();
+        tokio::task::spawn_blocking(move || ens.find_name(hash).unwrap())
+    };
+    assert_eq!(Some("eth".to_owned()), find(ETH).await.unwrap());
+    let unknown = "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470";
+    assert_eq!(None, find(unknown).await.unwrap());
+    // Malformed hashes never reach the endpoint
+    assert_eq!(None, find("../admin").await.unwrap());
+}
@@ -1,3 +1,4 @@
+pub mod ens;
 mod link_resolver;
 mod metrics;
 mod subgraph;
@@ -6,5 +7,6 @@ pub mod three_box;
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{
-    DataSourceLoader, SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar,
+    DataSourceLoader, NodeCoordinator, SubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar, TriggerLog, TriggerLogRecord,
 };
@@ -0,0 +1,148 @@
+use std::env;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use graph::prelude::*;
+use graph::util::shutdown;
+
+lazy_static! {
+    /// How often each node records that it is alive, in seconds
+    static ref HEARTBEAT_INTERVAL: Duration = Duration::from_secs(
+        env::var("GRAPH_NODE_HEARTBEAT_INTERVAL")
+            .unwrap_or("15".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_NODE_HEARTBEAT_INTERVAL")
+    );
+
+    /// How long a node may go without recording a heartbeat before its
+    /// deployments are moved to other nodes, in seconds. Without it, nodes
+    /// never take deployments away from other nodes
+    static ref FAILOVER_TIMEOUT: Option<Duration> = env::var("GRAPH_FAILOVER_TIMEOUT")
+        .ok()
+        .map(|s| Duration::from_secs(s.parse::<u64>().expect("invalid GRAPH_FAILOVER_TIMEOUT")));
+
+    /// How long a node that shut down cleanly may go without recording a
+    /// heartbeat before its deployments are moved, in seconds. This leaves
+    /// time for planned downtime like upgrades
+    static ref FAILOVER_PLANNED_DOWNTIME: Duration = Duration::from_secs(
+        env::var("GRAPH_FAILOVER_PLANNED_DOWNTIME")
+            .unwrap_or("3600".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_FAILOVER_PLANNED_DOWNTIME")
+    );
+
+    /// Whether deployments of dead nodes may be assigned to this node
+    static ref FAILOVER_TARGET: bool = env::var("GRAPH_FAILOVER_TARGET")
+        .map(|s| s != "false")
+        .unwrap_or(true);
+}
+
+/// Records heartbeats for this node in the primary and, if automatic
+/// failover is turned on, assigns the deployments of nodes that stopped
+/// recording heartbeats to nodes that are alive. The reassignments go out
+/// as store events, which makes the new nodes start the deployments in the
+/// same way as a manual `subgraph_reassign`. Deployments only move to
+/// nodes that index their network and that the placement rules allow
+pub struct NodeCoordinator<S> {
+    logger: Logger,
+    node_id: NodeId,
+    store: Arc<S>,
+    networks: Vec<String>,
+}
+
+impl<S> NodeCoordinator<S>
+where
+    S: Store,
+{
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        node_id: NodeId,
+        store: Arc<S>,
+        networks: Vec<String>,
+    ) -> Self {
+        let logger = logger_factory.component_logger("NodeCoordinator", None);
+        NodeCoordinator {
+            logger,
+            node_id,
+            store,
+            networks,
+        }
+    }
+
+    pub async fn run(self) {
+        // Hold off the shutdown until the clean stop has been recorded
+        let in_flight = match shutdown::in_flight() {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+
+        if let Some(timeout) = *FAILOVER_TIMEOUT {
+            info!(self.logger, "Moving deployments off dead nodes";
+                  "timeout_secs" => timeout.as_secs());
+            if timeout < *HEARTBEAT_INTERVAL * 2 {
+                warn!(
+                    self.logger,
+                    "GRAPH_FAILOVER_TIMEOUT is less than twice the heartbeat interval; \
+                     nodes that are merely slow will lose their deployments"
+                );
+            }
+        }
+
+        let mut interval = tokio::time::interval(*HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown::initiated() => break,
+            }
+
+            let store = self.store.clone();
+            let node_id = self.node_id.clone();
+            let networks = self.networks.clone();
+            let result = graph::spawn_blocking_allow_panic(move || {
+                store.record_heartbeat(&node_id, *FAILOVER_TARGET, &networks)?;
+                match *FAILOVER_TIMEOUT {
+                    Some(timeout) => {
+                        store.fail_over_dead_nodes(timeout, *FAILOVER_PLANNED_DOWNTIME)
+                    }
+                    None => Ok(vec![]),
+                }
+            })
+            .await
+            .unwrap(); // Propagate panics.
+
+            match result {
+                Ok(failovers) => {
+                    for failover in failovers {
+                        match &failover.to {
+                            Some(to) => warn!(self.logger, "Reassigned deployment of dead node";
+                                              "subgraph_id" => failover.deployment.as_str(),
+                                              "from" => failover.from.as_str(),
+                                              "to" => to.as_str()),
+                            None => {
+                                warn!(self.logger, "No live node can take deployment of dead node";
+                                          "subgraph_id" => failover.deployment.as_str(),
+                                          "from" => failover.from.as_str())
+                            }
+                        }
+                    }
+                }
+                Err(e) => error!(self.logger, "Failed to record heartbeat or fail over nodes";
+                                 "error" => e.to_string()),
+            }
+        }
+
+        // Let the other nodes know that this node stopped on purpose so
+        // that they give it time to come back before taking its deployments
+        let store = self.store.clone();
+        let node_id = self.node_id.clone();
+        let result = graph::spawn_blocking_allow_panic(move || store.record_stop(&node_id))
+            .await
+            .unwrap(); // Propagate panics.
+        if let Err(e) = result {
+            error!(self.logger, "Failed to record that the node stopped";
+                   "error" => e.to_string());
+        }
+        drop(in_flight);
+    }
+}
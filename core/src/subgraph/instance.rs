@@ -29,8 +29,17 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     /// stream events are processed by the mappings in this same order.
     hosts: Vec<Arc<T::Host>>,
 
-    /// Maps the hash of a module to a channel to the thread in which the module is instantiated.
-    module_cache: HashMap<[u8; 32], Sender<T::Req>>,
+    /// The groups of independent data sources, empty unless the manifest
+    /// declares `independentDataSources`
+    groups: Vec<Arc<DataSourceGroup>>,
+
+    /// The index into `groups` for each of the `hosts`
+    host_groups: Vec<Option<usize>>,
+
+    /// Maps the hash of a module and the group that uses it to a channel
+    /// to the thread in which the module is instantiated. Each group gets
+    /// its own thread so that groups can be processed in parallel
+    module_cache: HashMap<([u8; 32], Option<usize>), Sender<T::Req>>,
 }
 
 impl<T> SubgraphInstance<T>
@@ -45,6 +54,7 @@ where
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
+        let groups = manifest.data_source_groups();
         let templates = Arc::new(manifest.templates);
 
         let mut this = SubgraphInstance {
@@ -52,6 +62,8 @@ where
             subgraph_id,
             network,
             hosts: Vec::new(),
+            groups,
+            host_groups: Vec::new(),
             module_cache: HashMap::new(),
         };
 
@@ -77,11 +89,9 @@ where
             ));
         }
 
-        this.hosts = hosts
-            .into_iter()
-            .map(Result::unwrap)
-            .map(Arc::new)
-            .collect();
+        let (hosts, host_groups): (Vec<_>, Vec<_>) = hosts.into_iter().map(Result::unwrap).unzip();
+        this.hosts = hosts;
+        this.host_groups = host_groups;
 
         Ok(this)
     }
@@ -92,11 +102,15 @@ where
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         host_metrics: Arc<HostMetrics>,
-    ) -> Result<T::Host, anyhow::Error> {
+    ) -> Result<(Arc<T::Host>, Option<usize>), anyhow::Error> {
+        let group = self
+            .groups
+            .iter()
+            .position(|group| group.members.contains(&data_source.name));
         let mapping_request_sender = {
             let module_bytes = data_source.mapping.runtime.as_ref();
-            let module_hash = tiny_keccak::keccak256(module_bytes);
-            if let Some(sender) = self.module_cache.get(&module_hash) {
+            let cache_key = (tiny_keccak::keccak256(module_bytes), group);
+            if let Some(sender) = self.module_cache.get(&cache_key) {
                 sender.clone()
             } else {
                 let sender = T::spawn_mapping(
@@ -105,20 +119,50 @@ where
                     self.subgraph_id.clone(),
                     host_metrics.clone(),
                 )?;
-                self.module_cache.insert(module_hash, sender.clone());
+                self.module_cache.insert(cache_key, sender.clone());
                 sender
             }
         };
-        self.host_builder
+        let host = self
+            .host_builder
             .build(
                 self.network.clone(),
                 self.subgraph_id.clone(),
                 data_source,
+                group.map(|group| self.groups[group].cheap_clone()),
                 top_level_templates,
                 mapping_request_sender,
                 host_metrics,
             )
-            .compat_err()
+            .compat_err()?;
+        Ok((Arc::new(host), group))
+    }
+
+    pub(crate) fn hosts(&self) -> &[Arc<T::Host>] {
+        &self.hosts
+    }
+
+    /// Split the runtime hosts into the groups of independent data sources,
+    /// keeping the order of hosts within each group. Returns `None` if
+    /// the hosts can not be split, in which case all triggers need to be
+    /// processed one after the other
+    pub(crate) fn host_groups(&self) -> Option<Vec<(Arc<DataSourceGroup>, Vec<Arc<T::Host>>)>> {
+        if self.groups.len() < 2 || self.host_groups.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let mut groups: Vec<_> = self
+            .groups
+            .iter()
+            .map(|group| (group.cheap_clone(), Vec::new()))
+            .collect();
+        for (host, group) in self.hosts.iter().zip(self.host_groups.iter()) {
+            if let Some(group) = group {
+                groups[*group].1.push(host.cheap_clone());
+            }
+        }
+        groups.retain(|(_, hosts)| !hosts.is_empty());
+        Some(groups)
     }
 }
 
@@ -266,6 +310,40 @@ where
         Ok(state)
     }
 
+    async fn process_entity_trigger(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        deployment: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity: &Entity,
+        mut state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let block_number = block.number.unwrap().as_u64();
+        let matching_hosts = self
+            .hosts
+            .iter()
+            .filter(|host| host.matches_entity(deployment, entity_type, block_number));
+        let hosts_count = matching_hosts.clone().count();
+
+        for (i, host) in matching_hosts.enumerate() {
+            let host_context = format!("{}/{}", i + 1, hosts_count);
+            let logger = logger.new(o!("runtime_host" => host_context));
+            state = host
+                .process_entity(
+                    &logger,
+                    block,
+                    entity_type,
+                    entity,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await?;
+        }
+        Ok(state)
+    }
+
     fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
@@ -283,17 +361,18 @@ where
             }
         }
 
-        let host = Arc::new(self.new_host(
+        let (host, group) = self.new_host(
             logger.clone(),
             data_source,
             top_level_templates,
             metrics.clone(),
-        )?);
+        )?;
 
         Ok(if self.hosts.contains(&host) {
             None
         } else {
             self.hosts.push(host.clone());
+            self.host_groups.push(group);
             Some(host)
         })
     }
@@ -1,20 +1,25 @@
 use atomic_refcell::AtomicRefCell;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use graph::components::ethereum::{triggers_in_block, EthereumNetworks};
+use graph::components::server::health::{self, Subsystem};
 use graph::components::store::ModificationsAndCache;
 use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
-    DynamicEthereumContractDataSourceEntity, SubgraphError, POI_OBJECT,
+    DynamicEthereumContractDataSourceEntity, SubgraphDeploymentEntity, SubgraphError,
+    SubgraphHealth, POI_OBJECT,
 };
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use graph::util::lfu_cache::LfuCache;
+use graph::util::shutdown;
 
+use super::trigger_log::{TriggerLog, TriggerLogRecord, TriggerReplay};
 use super::SubgraphInstance;
 
 lazy_static! {
@@ -25,8 +30,23 @@ lazy_static! {
             .unwrap_or("10000".into())
             .parse::<usize>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
+
+    /// How much memory, in bytes, the entities that a deployment reads
+    /// from the store may use while it processes a block. It is never less
+    /// than `ENTITY_CACHE_SIZE`, since the entity cache is that large when
+    /// processing starts. Multiplied by 1000 because the env var is in KB.
+    static ref ENTITY_CACHE_BLOCK_LIMIT: Option<usize> =
+        std::env::var("GRAPH_ENTITY_CACHE_BLOCK_LIMIT")
+            .ok()
+            .map(|s| 1000 * s.parse::<usize>()
+                .expect("invalid GRAPH_ENTITY_CACHE_BLOCK_LIMIT"))
+            .map(|limit| limit.max(*ENTITY_CACHE_SIZE));
 }
 
+/// How long to wait for the upstream deployment of a subgraph data source
+/// to change its block pointer before checking it again anyway
+const SOURCE_DEPLOYMENT_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
 struct IndexingInputs<B, S> {
@@ -38,6 +58,9 @@ struct IndexingInputs<B, S> {
     stream_builder: B,
     include_calls_in_blocks: bool,
     top_level_templates: Arc<Vec<DataSourceTemplate>>,
+    /// The upstream deployments of subgraph data sources, and the entity
+    /// types whose changes trigger handlers
+    source_deployments: BTreeMap<SubgraphDeploymentId, Vec<String>>,
 }
 
 struct IndexingState<T: RuntimeHostBuilder> {
@@ -48,6 +71,11 @@ struct IndexingState<T: RuntimeHostBuilder> {
     call_filter: EthereumCallFilter,
     block_filter: EthereumBlockFilter,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    /// Where to record the triggers this deployment processes, if at all
+    trigger_log: Option<TriggerLog>,
+    /// The recorded triggers to process instead of the ones found on the
+    /// chain when replaying a trigger log
+    trigger_replay: Option<TriggerReplay>,
 }
 
 struct IndexingContext<B, T: RuntimeHostBuilder, S> {
@@ -95,6 +123,7 @@ enum TriggerType {
     Event,
     Call,
     Block,
+    Entity,
 }
 
 impl TriggerType {
@@ -103,6 +132,7 @@ impl TriggerType {
             TriggerType::Event => "event",
             TriggerType::Call => "call",
             TriggerType::Block => "block",
+            TriggerType::Entity => "entity",
         }
     }
 }
@@ -111,6 +141,8 @@ struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
     pub block_ops_transaction_duration: Box<Histogram>,
+    pub entity_cache_weight: Box<Gauge>,
+    pub entity_cache_evicted_weight: Box<Counter>,
 
     trigger_processing_duration: Box<HistogramVec>,
 }
@@ -150,12 +182,28 @@ impl SubgraphInstanceMetrics {
                 vec![0.01, 0.05, 0.1, 0.3, 0.7, 2.0],
             )
             .expect("failed to create `deployment_transact_block_operations_duration_{}");
+        let entity_cache_weight = registry
+            .new_deployment_gauge(
+                "deployment_entity_cache_weight",
+                "Estimated size in bytes of the entities cached between blocks for a subgraph deployment",
+                subgraph_hash,
+            )
+            .expect("failed to create `deployment_entity_cache_weight` gauge");
+        let entity_cache_evicted_weight = registry
+            .new_deployment_counter(
+                "deployment_entity_cache_evicted_weight",
+                "Estimated size in bytes of the entities evicted from the entity cache of a subgraph deployment",
+                subgraph_hash,
+            )
+            .expect("failed to create `deployment_entity_cache_evicted_weight` counter");
 
         Self {
             block_trigger_count,
             block_processing_duration,
             trigger_processing_duration,
             block_ops_transaction_duration,
+            entity_cache_weight,
+            entity_cache_evicted_weight,
         }
     }
 
@@ -170,6 +218,8 @@ impl SubgraphInstanceMetrics {
         registry.unregister(self.block_trigger_count.clone());
         registry.unregister(self.trigger_processing_duration.clone());
         registry.unregister(self.block_ops_transaction_duration.clone());
+        registry.unregister(self.entity_cache_weight.clone());
+        registry.unregister(self.entity_cache_evicted_weight.clone());
     }
 }
 
@@ -249,6 +299,7 @@ impl SubgraphInstanceManager {
                             "data_sources" => manifest.data_sources.len()
                         );
                         let network = manifest.network_name();
+                        let id = manifest.id.clone();
 
                         match Self::start_subgraph(
                             logger.clone(),
@@ -262,19 +313,32 @@ impl SubgraphInstanceManager {
                         )
                         .await
                         {
-                            Ok(()) => manager_metrics.subgraph_count.inc(),
-                            Err(err) => error!(
-                                logger,
-                                "Failed to start subgraph";
-                                "error" => format!("{}", err),
-                                "code" => LogCode::SubgraphStartFailure
-                            ),
+                            Ok(()) => {
+                                health::report(Subsystem::InstanceManager, id.as_str(), Ok(()));
+                                manager_metrics.subgraph_count.inc()
+                            }
+                            Err(err) => {
+                                health::report(
+                                    Subsystem::InstanceManager,
+                                    id.as_str(),
+                                    Err(format!("failed to start: {}", err)),
+                                );
+                                error!(
+                                    logger,
+                                    "Failed to start subgraph";
+                                    "error" => format!("{}", err),
+                                    "code" => LogCode::SubgraphStartFailure
+                                )
+                            }
                         }
                     }
                     SubgraphStop(id) => {
                         let logger = logger_factory.subgraph_logger(&id);
                         info!(logger, "Stop subgraph");
 
+                        health::clear(Subsystem::InstanceManager, id.as_str());
+                        HandlerError::clear(id.as_str());
+                        HandlerProfile::clear(id.as_str());
                         Self::stop_subgraph(instances.clone(), id);
                         manager_metrics.subgraph_count.dec();
                     }
@@ -305,12 +369,32 @@ impl SubgraphInstanceManager {
             format_err!("expected store that matches subgraph network: {}", &network)
         })?;
 
-        let eth_adapter = eth_networks
-            .adapter_with_capabilities(network.clone(), &required_capabilities).map_err(|e|
-                format_err!(
-                "expected eth adapter that matches subgraph network {} with required capabilities: {}: {}",
-                &network,
-                &required_capabilities, e))?.clone();
+        let eth_adapter =
+            match eth_networks.adapter_with_capabilities(network.clone(), &required_capabilities) {
+                Ok(eth_adapter) => eth_adapter.clone(),
+                Err(_) => {
+                    // The nodes for the network may have lost capabilities
+                    // since the subgraph was deployed. Record that in the
+                    // deployment so that it does not look like it is syncing
+                    let message = SubgraphRegistrarError::SubgraphNetworkRequirementsNotSupported(
+                        network.clone(),
+                        eth_networks.explain_unsupported(&network, &required_capabilities),
+                    )
+                    .to_string();
+                    let error = SubgraphError {
+                        subgraph_id: manifest.id.clone(),
+                        message: message.clone(),
+                        block_ptr: None,
+                        handler: None,
+                        deterministic: false,
+                    };
+                    store.apply_metadata_operations(
+                        &manifest.id,
+                        SubgraphDeploymentEntity::fail_operations(&manifest.id, error),
+                    )?;
+                    return Err(format_err!("{}", message));
+                }
+            };
 
         store.start_subgraph_deployment(&logger, &manifest.id)?;
 
@@ -331,6 +415,10 @@ impl SubgraphInstanceManager {
         let block_filter = EthereumBlockFilter::from_data_sources(&manifest.data_sources);
         let start_blocks = manifest.start_blocks();
 
+        // The block stream also yields the blocks in which upstream
+        // deployments changed entities
+        let source_deployments = manifest.source_deployments();
+
         // Identify whether there are mappings with call handlers or
         // block handlers with call filters; in this case, we need to
         // include calls in all blocks
@@ -361,6 +449,8 @@ impl SubgraphInstanceManager {
         ));
         let instance =
             SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        let trigger_log = TriggerLog::from_env(&deployment_id)?;
+        let trigger_replay = TriggerReplay::from_env(&deployment_id)?;
 
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
@@ -373,6 +463,7 @@ impl SubgraphInstanceManager {
                 stream_builder,
                 include_calls_in_blocks,
                 top_level_templates,
+                source_deployments,
             },
             state: IndexingState {
                 logger,
@@ -382,6 +473,8 @@ impl SubgraphInstanceManager {
                 call_filter,
                 block_filter,
                 entity_lfu_cache: LfuCache::new(),
+                trigger_log,
+                trigger_replay,
             },
             subgraph_metrics,
             host_metrics,
@@ -453,6 +546,7 @@ where
                 ctx.state.call_filter.clone(),
                 ctx.state.block_filter.clone(),
                 ctx.inputs.include_calls_in_blocks,
+                ctx.inputs.source_deployments.clone(),
                 ctx.block_stream_metrics.clone(),
             )
             .map_err(CancelableError::Error)
@@ -476,6 +570,17 @@ where
                 Some(Ok(BlockStreamEvent::Revert)) => {
                     // On revert, clear the entity cache.
                     ctx.state.entity_lfu_cache = LfuCache::new();
+
+                    if let Some(trigger_log) = ctx.state.trigger_log.as_mut() {
+                        let res = ctx
+                            .inputs
+                            .store
+                            .block_ptr(ctx.inputs.deployment_id.clone())
+                            .and_then(|to| trigger_log.append(&TriggerLogRecord::Revert { to }));
+                        if let Err(e) = res {
+                            error!(&logger, "Failed to record revert in trigger log: {}", e);
+                        }
+                    }
                     continue;
                 }
                 // Log and drop the errors from the block_stream
@@ -491,6 +596,16 @@ where
                 None => unreachable!("The block stream stopped producing blocks"),
             };
 
+            // Once the node is shutting down, leave the block for the next
+            // start; the shutdown waits for `_in_flight` to be dropped
+            let _in_flight = match shutdown::in_flight() {
+                Some(in_flight) => in_flight,
+                None => {
+                    info!(&logger, "Stopping subgraph since the node is shutting down");
+                    return Ok(());
+                }
+            };
+
             let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
 
             if block.triggers.len() > 0 {
@@ -513,6 +628,13 @@ where
             let elapsed = start.elapsed().as_secs_f64();
             subgraph_metrics.block_processing_duration.observe(elapsed);
 
+            // Store what the mappings logged even if the block failed, since
+            // that is when the messages are most useful
+            let handler_logs = HandlerLog::take(id_for_err.as_str());
+            if let Err(e) = store_for_err.record_handler_logs(&id_for_err, handler_logs) {
+                warn!(&logger, "Failed to store handler logs: {}", e);
+            }
+
             match res {
                 Ok((c, needs_restart)) => {
                     ctx = c;
@@ -545,6 +667,19 @@ where
                         "code" => LogCode::SubgraphSyncingFailure
                     );
 
+                    // A subgraph that fails deterministically is broken,
+                    // but other failures mean that this node could not
+                    // index it
+                    if e.is_deterministic() {
+                        health::clear(Subsystem::InstanceManager, id_for_err.as_str());
+                    } else {
+                        health::report(
+                            Subsystem::InstanceManager,
+                            id_for_err.as_str(),
+                            Err(format!("failed: {}", e)),
+                        );
+                    }
+
                     let error = SubgraphError {
                         subgraph_id: id_for_err.clone(),
                         message: e.to_string(),
@@ -616,6 +751,22 @@ where
         "block_hash" => format!("{:?}", block_ptr.hash)
     ));
 
+    // When replaying a trigger log, process the triggers that the log
+    // recorded for this block instead of the ones found on the chain
+    let (triggers, mut replayed_rounds) = match ctx.state.trigger_replay.as_ref() {
+        Some(replay) => {
+            let previous = ctx
+                .inputs
+                .store
+                .block_ptr(ctx.inputs.deployment_id.clone())?;
+            let (triggers, rounds) = replay
+                .triggers(previous.as_ref(), &block_ptr)
+                .map_err(|e| format_err!("Failed to replay the trigger log: {}", e))?;
+            (triggers, Some(rounds.into_iter()))
+        }
+        None => (triggers, None),
+    };
+
     if triggers.len() == 1 {
         info!(&logger, "1 trigger found in this block for this subgraph");
     } else if triggers.len() > 1 {
@@ -626,6 +777,13 @@ where
         );
     }
 
+    // Keep a copy of the triggers for the trigger log
+    let mut logged_triggers = ctx
+        .state
+        .trigger_log
+        .as_ref()
+        .map(|_| (triggers.clone(), Vec::new()));
+
     // Obtain current and new block pointer (after this block is processed)
     let light_block = Arc::new(block.light_block());
     let block_ptr_after = EthereumBlockPointer::from(&block);
@@ -646,22 +804,82 @@ where
         None
     };
 
-    // Process events one after the other, passing in entity operations
-    // collected previously to every new event being processed
-    let mut block_state = match process_triggers(
+    // Changes to upstream entities can only be processed once the
+    // upstream deployments have processed this block themselves
+    if !wait_for_source_deployments(
         &logger,
-        BlockState::new(
-            ctx.inputs.store.clone(),
-            std::mem::take(&mut ctx.state.entity_lfu_cache),
-        ),
-        proof_of_indexing.cheap_clone(),
-        ctx.subgraph_metrics.clone(),
-        &ctx.state.instance,
-        &light_block,
-        triggers,
+        ctx.inputs.store.as_ref(),
+        ctx.inputs.source_deployments.keys(),
+        &block_ptr,
+        &block_stream_cancel_handle,
     )
-    .await
+    .await?
     {
+        info!(ctx.state.logger,
+                "Source deployment processed a different block, restarting block stream";
+                "id" => ctx.inputs.deployment_id.to_string(),
+        );
+
+        // Like for a possible reorg below, restart the block stream so it
+        // has a chance to detect the reorg
+        return Ok((ctx, true));
+    }
+
+    // Process events one after the other, passing in entity operations
+    // collected previously to every new event being processed. Groups of
+    // independent data sources each process the events on their own.
+    // Changes to upstream entities are processed after all Ethereum triggers
+    let mut block_state = BlockState::new(
+        ctx.inputs.store.clone(),
+        std::mem::take(&mut ctx.state.entity_lfu_cache),
+    );
+    if let Some(limit) = *ENTITY_CACHE_BLOCK_LIMIT {
+        block_state.entity_cache.set_block_limit(limit);
+    }
+    let block_state = match ctx.state.instance.host_groups() {
+        Some(groups) => {
+            process_triggers_in_groups::<T>(
+                &logger,
+                block_state,
+                proof_of_indexing.cheap_clone(),
+                ctx.subgraph_metrics.clone(),
+                groups,
+                &light_block,
+                triggers,
+            )
+            .await
+        }
+        None => {
+            process_triggers::<T>(
+                &logger,
+                block_state,
+                proof_of_indexing.cheap_clone(),
+                ctx.subgraph_metrics.clone(),
+                ctx.state.instance.hosts(),
+                &light_block,
+                triggers,
+            )
+            .await
+        }
+    };
+    let block_state = match block_state {
+        Ok(block_state) => {
+            process_entity_triggers(
+                &logger,
+                block_state,
+                proof_of_indexing.cheap_clone(),
+                ctx.subgraph_metrics.clone(),
+                ctx.inputs.store.as_ref(),
+                &ctx.inputs.source_deployments,
+                &ctx.state.instance,
+                &light_block,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    let mut block_state = match block_state {
         Ok(block_state) => block_state,
         Err(MappingError::Unknown(e)) => {
             return Err(CancelableError::Error(BlockProcessingError::Unknown(e)))
@@ -712,20 +930,25 @@ where
         )
         .compat_err()?;
 
-        // Reprocess the triggers from this block that match the new data sources
-        let block_with_triggers = triggers_in_block(
-            eth_adapter.clone(),
-            logger.cheap_clone(),
-            ctx.inputs.store.clone(),
-            ctx.ethrpc_metrics.clone(),
-            EthereumLogFilter::from_data_sources(data_sources.iter()),
-            EthereumCallFilter::from_data_sources(data_sources.iter()),
-            EthereumBlockFilter::from_data_sources(data_sources.iter()),
-            block.clone(),
-        )
-        .await?;
-
-        let triggers = block_with_triggers.triggers;
+        // Reprocess the triggers from this block that match the new data
+        // sources, or take the ones recorded for this round
+        let triggers = match replayed_rounds.as_mut() {
+            Some(rounds) => rounds.next().unwrap_or_default(),
+            None => {
+                triggers_in_block(
+                    eth_adapter.clone(),
+                    logger.cheap_clone(),
+                    ctx.inputs.store.clone(),
+                    ctx.ethrpc_metrics.clone(),
+                    EthereumLogFilter::from_data_sources(data_sources.iter()),
+                    EthereumCallFilter::from_data_sources(data_sources.iter()),
+                    EthereumBlockFilter::from_data_sources(data_sources.iter()),
+                    block.clone(),
+                )
+                .await?
+                .triggers
+            }
+        };
 
         if triggers.len() == 1 {
             info!(
@@ -740,6 +963,10 @@ where
             );
         }
 
+        if let Some((_, data_source_triggers)) = logged_triggers.as_mut() {
+            data_source_triggers.push(triggers.clone());
+        }
+
         // Add entity operations for the new data sources to the block state
         // and add runtimes for the data sources to the subgraph instance.
         persist_dynamic_data_sources(
@@ -800,6 +1027,7 @@ where
     let ModificationsAndCache {
         modifications: mods,
         entity_lfu_cache: mut cache,
+        evicted_weight,
     } = block_state
         .entity_cache
         .as_modifications(ctx.inputs.store.as_ref())
@@ -815,7 +1043,17 @@ where
         .host_metrics
         .stopwatch
         .start_section("entity_cache_evict");
-    cache.evict(*ENTITY_CACHE_SIZE);
+    let evicted_weight = evicted_weight
+        + cache
+            .evict(*ENTITY_CACHE_SIZE)
+            .map(|(evicted, _, _)| evicted)
+            .unwrap_or(0);
+    ctx.subgraph_metrics
+        .entity_cache_evicted_weight
+        .inc_by(evicted_weight as f64);
+    ctx.subgraph_metrics
+        .entity_cache_weight
+        .set(cache.total_weight() as f64);
     section.end();
 
     // Put the cache back in the ctx, asserting that the placeholder cache was not used.
@@ -826,6 +1064,21 @@ where
         info!(&logger, "Applying {} entity operation(s)", mods.len());
     }
 
+    // Record the block in the trigger log before it is committed so that a
+    // crash can not leave a gap in the log. If the commit fails, the block
+    // is processed and recorded again, and that record replaces this one
+    if let (Some(trigger_log), Some((triggers, data_source_triggers))) =
+        (ctx.state.trigger_log.as_mut(), logged_triggers)
+    {
+        trigger_log
+            .append(&TriggerLogRecord::Block {
+                block: block_ptr_after,
+                triggers,
+                data_source_triggers,
+            })
+            .map_err(|e| format_err!("Failed to write trigger log: {}", e))?;
+    }
+
     // Transact entity operations into the store and update the
     // subgraph's block stream pointer
     let _section = ctx.host_metrics.stopwatch.start_section("transact_block");
@@ -903,12 +1156,12 @@ async fn update_proof_of_indexing(
     Ok(())
 }
 
-async fn process_triggers(
+async fn process_triggers<T: RuntimeHostBuilder>(
     logger: &Logger,
     mut block_state: BlockState,
     proof_of_indexing: SharedProofOfIndexing,
     subgraph_metrics: Arc<SubgraphInstanceMetrics>,
-    instance: &SubgraphInstance<impl RuntimeHostBuilder>,
+    hosts: &[Arc<T::Host>],
     block: &Arc<LightEthereumBlock>,
     triggers: Vec<EthereumTrigger>,
 ) -> Result<BlockState, MappingError> {
@@ -925,30 +1178,320 @@ async fn process_triggers(
             EthereumTrigger::Block(..) => None,
         };
         let start = Instant::now();
-        block_state = instance
-            .process_trigger(
-                &logger,
-                &block,
-                trigger,
-                block_state,
-                proof_of_indexing.cheap_clone(),
-            )
-            .await
-            .map_err(move |e| {
-                e.context(match transaction_id {
-                    Some(tx_hash) => format!(
-                        "Failed to process trigger in block {}, transaction {:x}",
-                        block_ptr, tx_hash
-                    ),
-                    None => "Failed to process trigger".to_string(),
-                })
-            })?;
+        block_state = SubgraphInstance::<T>::process_trigger_in_runtime_hosts(
+            &logger,
+            hosts,
+            &block,
+            trigger,
+            block_state,
+            proof_of_indexing.cheap_clone(),
+        )
+        .await
+        .map_err(move |e| {
+            e.context(match transaction_id {
+                Some(tx_hash) => format!(
+                    "Failed to process trigger in block {}, transaction {:x}",
+                    block_ptr, tx_hash
+                ),
+                None => "Failed to process trigger".to_string(),
+            })
+        })?;
         let elapsed = start.elapsed().as_secs_f64();
         subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
     }
     Ok(block_state)
 }
 
+/// Process `triggers` for each group of independent data sources
+/// concurrently. Every group works on the entities of its own types and
+/// writes to its own proof of indexing; the results of all groups are
+/// merged in the order of the groups
+async fn process_triggers_in_groups<T: RuntimeHostBuilder>(
+    logger: &Logger,
+    mut block_state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    groups: Vec<(Arc<DataSourceGroup>, Vec<Arc<T::Host>>)>,
+    block: &Arc<LightEthereumBlock>,
+    triggers: Vec<EthereumTrigger>,
+) -> Result<BlockState, MappingError> {
+    let block_number = block.number.unwrap().as_u64();
+
+    let runs: Vec<_> = groups
+        .into_iter()
+        .map(|(group, hosts)| {
+            let state = BlockState {
+                entity_cache: block_state.entity_cache.split_off(&group.entities),
+                created_data_sources: Vec::new(),
+            };
+            let group_proof = proof_of_indexing
+                .as_ref()
+                .map(|_| Arc::new(AtomicRefCell::new(ProofOfIndexing::new(block_number))));
+            let logger = logger.new(o!("data_source_group" => group.name.clone()));
+            let subgraph_metrics = subgraph_metrics.cheap_clone();
+            let triggers = triggers.clone();
+            async move {
+                let state = process_triggers::<T>(
+                    &logger,
+                    state,
+                    group_proof.cheap_clone(),
+                    subgraph_metrics,
+                    &hosts,
+                    block,
+                    triggers,
+                )
+                .await?;
+                Ok::<_, MappingError>((state, group_proof))
+            }
+        })
+        .collect();
+
+    // Look at the groups in order so that the error we report does not
+    // depend on which group happened to fail first
+    for result in futures03::future::join_all(runs).await {
+        let (state, group_proof) = result?;
+        block_state
+            .entity_cache
+            .extend(state.entity_cache)
+            .map_err(|e| anyhow::anyhow!("Failed to merge entity changes: {}", e))?;
+        block_state
+            .created_data_sources
+            .extend(state.created_data_sources);
+        if let (Some(proof_of_indexing), Some(group_proof)) = (&proof_of_indexing, group_proof) {
+            let group_proof = Arc::try_unwrap(group_proof).unwrap().into_inner();
+            proof_of_indexing.borrow_mut().merge(group_proof);
+        }
+    }
+    Ok(block_state)
+}
+
+/// How far a source deployment is in relation to the block we are about
+/// to process
+#[derive(Debug, PartialEq)]
+enum SourceProgress {
+    /// The source deployment has processed the block
+    Ready,
+    /// The source deployment has not reached the block yet
+    Behind,
+    /// The source deployment has processed a different block at that
+    /// height, i.e., it or we are on a block that has been reorged away
+    Diverged,
+}
+
+/// Fail if the source deployment `deployment` has failed or was removed
+/// since it will then never get to the block we are about to process
+fn check_source_health(
+    store: &impl Store,
+    deployment: &SubgraphDeploymentId,
+) -> Result<(), CancelableError<BlockProcessingError>> {
+    let query_error = |e: QueryExecutionError| {
+        CancelableError::Error(BlockProcessingError::Unknown(anyhow::anyhow!(
+            "Failed to check health of source deployment `{}`: {}",
+            deployment,
+            e
+        )))
+    };
+
+    let entity = store
+        .get(SubgraphDeploymentEntity::key(deployment.clone()))
+        .map_err(query_error)?
+        .ok_or_else(|| {
+            CancelableError::Error(BlockProcessingError::Unknown(anyhow::anyhow!(
+                "source deployment `{}` does not exist anymore",
+                deployment
+            )))
+        })?;
+    let health = match entity.get("health") {
+        Some(Value::String(health)) => SubgraphHealth::from_str(health)?,
+        _ => SubgraphHealth::Healthy,
+    };
+    if health != SubgraphHealth::Failed {
+        return Ok(());
+    }
+
+    let message = match entity.get("fatalError") {
+        Some(Value::String(error_id)) => store
+            .get(SubgraphError::key(error_id.clone()))
+            .map_err(query_error)?
+            .and_then(|error| error.get("message").cloned()),
+        _ => None,
+    };
+    Err(CancelableError::Error(BlockProcessingError::Unknown(
+        anyhow::anyhow!(
+            "source deployment `{}` failed: {}",
+            deployment,
+            message
+                .and_then(|message| message.as_string())
+                .unwrap_or_default()
+        ),
+    )))
+}
+
+fn source_progress(
+    store: &(impl Store + ChainStore),
+    deployment: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+) -> Result<SourceProgress, CancelableError<BlockProcessingError>> {
+    let source_ptr = match store.block_ptr(deployment.clone())? {
+        Some(source_ptr) if source_ptr.number >= block_ptr.number => source_ptr,
+        _ => return Ok(SourceProgress::Behind),
+    };
+    if source_ptr.number == block_ptr.number {
+        return Ok(if source_ptr.hash == block_ptr.hash {
+            SourceProgress::Ready
+        } else {
+            SourceProgress::Diverged
+        });
+    }
+
+    // The source deployment is ahead of us; check that it went through the
+    // block we are about to process. If that block is not in the block
+    // store anymore, it is so far behind the chain head that it is final
+    let offset = source_ptr.number - block_ptr.number;
+    match store.ancestor_block(source_ptr, offset)? {
+        Some(ancestor) if ancestor.block.hash != Some(block_ptr.hash) => {
+            Ok(SourceProgress::Diverged)
+        }
+        _ => Ok(SourceProgress::Ready),
+    }
+}
+
+/// Wait until all `deployments` have processed the block `block_ptr`,
+/// checking again whenever the block pointer of a deployment changes.
+/// Return `false` if a source deployment stays on a different block at
+/// that height, in which case we might be on a block that was reorged away
+/// and need to restart the block stream
+async fn wait_for_source_deployments<'a>(
+    logger: &Logger,
+    store: &(impl Store + ChainStore),
+    deployments: impl Iterator<Item = &'a SubgraphDeploymentId>,
+    block_ptr: &EthereumBlockPointer,
+    cancel_handle: &CancelHandle,
+) -> Result<bool, CancelableError<BlockProcessingError>> {
+    for deployment in deployments {
+        let mut events = None;
+        let mut diverged_since = None;
+        loop {
+            check_source_health(store, deployment)?;
+            match source_progress(store, deployment, block_ptr)? {
+                SourceProgress::Ready => break,
+                SourceProgress::Behind => diverged_since = None,
+                SourceProgress::Diverged => match diverged_since {
+                    Some(since) if since.elapsed() >= SOURCE_DEPLOYMENT_RECHECK_INTERVAL => {
+                        return Ok(false)
+                    }
+                    Some(_) => (),
+                    None => diverged_since = Some(Instant::now()),
+                },
+            }
+
+            // Subscribe to changes to the deployment and check once more so
+            // that we do not miss an update that happens in between
+            if events.is_none() {
+                info!(
+                    logger,
+                    "Waiting for source deployment to process block";
+                    "source_deployment" => deployment.to_string(),
+                );
+                events = Some(
+                    store
+                        .subscribe(vec![SubgraphDeploymentEntity::subgraph_entity_pair()])
+                        .cancelable(cancel_handle, || ())
+                        .compat(),
+                );
+                continue;
+            }
+            let stream = events.as_mut().unwrap();
+
+            // Wait for the block pointer of the deployment to change, but
+            // check again after a while in case we missed an event
+            let changed = async {
+                while let Some(event) = stream.next().await {
+                    let event = event?;
+                    if event
+                        .changes
+                        .iter()
+                        .any(|change| change.entity_id == deployment.as_str())
+                    {
+                        return Ok(());
+                    }
+                }
+                Err(())
+            };
+            match tokio::time::timeout(SOURCE_DEPLOYMENT_RECHECK_INTERVAL, changed).await {
+                Ok(Ok(())) | Err(_) => (),
+                Ok(Err(())) => {
+                    cancel_handle.check_cancel()?;
+                    return Err(CancelableError::Error(BlockProcessingError::Unknown(
+                        anyhow::anyhow!("store event stream ended"),
+                    )));
+                }
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Run the entity handlers of subgraph data sources for all entities that
+/// their upstream deployments changed in `block`. Entities are processed
+/// grouped by deployment and entity type, and in the order of their id
+async fn process_entity_triggers(
+    logger: &Logger,
+    mut block_state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    store: &impl Store,
+    source_deployments: &BTreeMap<SubgraphDeploymentId, Vec<String>>,
+    instance: &SubgraphInstance<impl RuntimeHostBuilder>,
+    block: &Arc<LightEthereumBlock>,
+) -> Result<BlockState, MappingError> {
+    let block_ptr = EthereumBlockPointer::from(block.as_ref());
+    for (deployment, entity_types) in source_deployments {
+        let changes = store
+            .changed_entities(
+                deployment,
+                entity_types.clone(),
+                block_ptr.number as BlockNumber,
+            )
+            .map_err(|e| {
+                MappingError::Unknown(anyhow::anyhow!(
+                    "Failed to load entity changes of source deployment {}: {}",
+                    deployment,
+                    e
+                ))
+            })?;
+
+        for (entity_type, entities) in changes {
+            for entity in entities {
+                let start = Instant::now();
+                block_state = instance
+                    .process_entity_trigger(
+                        logger,
+                        block,
+                        deployment,
+                        &entity_type,
+                        &entity,
+                        block_state,
+                        proof_of_indexing.cheap_clone(),
+                    )
+                    .await
+                    .map_err(|e| {
+                        e.context(format!(
+                            "Failed to process change to {}[{}] of source deployment {} in block {}",
+                            entity_type,
+                            entity.id().unwrap_or_default(),
+                            deployment,
+                            block_ptr
+                        ))
+                    })?;
+                let elapsed = start.elapsed().as_secs_f64();
+                subgraph_metrics.observe_trigger_processing_duration(elapsed, TriggerType::Entity);
+            }
+        }
+    }
+    Ok(block_state)
+}
+
 fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
     logger: Logger,
     ctx: &mut IndexingContext<B, T, S>,
@@ -1050,3 +1593,364 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::subgraph::ProofOfIndexingEvent;
+    use graph::prelude::web3::types::{Log, Transaction, H256};
+    use graph_mock::{MockMetricsRegistry, MockStore};
+
+    /// A host that counts the block triggers it sees in an entity of its
+    /// own type, and records each change in the proof of indexing
+    #[derive(Debug, PartialEq)]
+    struct CountingHost {
+        subgraph_id: SubgraphDeploymentId,
+        entity_type: String,
+        causality_region: String,
+    }
+
+    #[async_trait]
+    impl RuntimeHost for CountingHost {
+        fn matches_log(&self, _: &Log) -> bool {
+            false
+        }
+
+        fn matches_call(&self, _: &EthereumCall) -> bool {
+            false
+        }
+
+        fn matches_block(&self, _: &EthereumBlockTriggerType, _: u64) -> bool {
+            true
+        }
+
+        fn matches_entity(&self, _: &SubgraphDeploymentId, _: &str, _: u64) -> bool {
+            false
+        }
+
+        async fn process_log(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<Log>,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unimplemented!()
+        }
+
+        async fn process_call(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<EthereumCall>,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unimplemented!()
+        }
+
+        async fn process_block(
+            &self,
+            logger: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &EthereumBlockTriggerType,
+            mut state: BlockState,
+            proof_of_indexing: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            let key = EntityKey {
+                subgraph_id: self.subgraph_id.clone(),
+                entity_type: self.entity_type.clone(),
+                entity_id: "counter".to_owned(),
+            };
+            let count = match state.entity_cache.get(&key).unwrap() {
+                Some(entity) => match entity.get("count") {
+                    Some(Value::Int(count)) => *count,
+                    _ => unreachable!("counters have a count"),
+                },
+                None => 0,
+            };
+            let entity = Entity::from(vec![
+                ("id", Value::from("counter")),
+                ("count", Value::Int(count + 1)),
+            ]);
+            if let Some(proof_of_indexing) = &proof_of_indexing {
+                proof_of_indexing.borrow_mut().write(
+                    logger,
+                    &self.causality_region,
+                    &ProofOfIndexingEvent::SetEntity {
+                        entity_type: &key.entity_type,
+                        id: &key.entity_id,
+                        data: &entity,
+                    },
+                );
+            }
+            state.entity_cache.set(key, entity).unwrap();
+            Ok(state)
+        }
+
+        async fn process_entity(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &str,
+            _: &Entity,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unimplemented!()
+        }
+    }
+
+    #[derive(Clone)]
+    struct CountingHostBuilder;
+
+    impl RuntimeHostBuilder for CountingHostBuilder {
+        type Host = CountingHost;
+        type Req = ();
+
+        fn build(
+            &self,
+            _: String,
+            _: SubgraphDeploymentId,
+            _: DataSource,
+            _: Option<Arc<DataSourceGroup>>,
+            _: Arc<Vec<DataSourceTemplate>>,
+            _: Sender<()>,
+            _: Arc<HostMetrics>,
+        ) -> Result<CountingHost, Error> {
+            unimplemented!()
+        }
+
+        fn spawn_mapping(
+            _: Vec<u8>,
+            _: Logger,
+            _: SubgraphDeploymentId,
+            _: Arc<HostMetrics>,
+        ) -> Result<Sender<()>, anyhow::Error> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn groups_match_sequential_processing() {
+        let logger = Logger::root(slog::Discard, o!());
+        let subgraph_id = SubgraphDeploymentId::new("groupsMatchSequential").unwrap();
+        let mut store = MockStore::new();
+        store.expect_get_mock().returning(|_| Ok(None));
+        let store: Arc<dyn Store> = Arc::new(store);
+        let metrics = Arc::new(SubgraphInstanceMetrics::new(
+            Arc::new(MockMetricsRegistry::new()),
+            subgraph_id.as_str(),
+        ));
+
+        let group = |name: &str| {
+            Arc::new(DataSourceGroup {
+                name: name.to_owned(),
+                members: vec![name.to_owned()].into_iter().collect(),
+                entities: vec![name.to_uppercase()].into_iter().collect(),
+            })
+        };
+        let host = |group: &str| {
+            Arc::new(CountingHost {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: group.to_uppercase(),
+                causality_region: format!("ethereum/mainnet/{}", group),
+            })
+        };
+        // The hosts of the two groups alternate, and the first group has
+        // two hosts that change the same entity
+        let hosts = vec![host("a"), host("b"), host("a")];
+        let groups = vec![
+            (
+                group("a"),
+                vec![hosts[0].cheap_clone(), hosts[2].cheap_clone()],
+            ),
+            (group("b"), vec![hosts[1].cheap_clone()]),
+        ];
+
+        let mut block = LightEthereumBlock::default();
+        block.number = Some(1u64.into());
+        block.hash = Some(H256::from_low_u64_be(1));
+        let block = Arc::new(block);
+        let ptr = EthereumBlockPointer::from(block.as_ref());
+        let triggers: Vec<_> = (0..3)
+            .map(|_| EthereumTrigger::Block(ptr.clone(), EthereumBlockTriggerType::Every))
+            .collect();
+
+        // Start from a state where the counter for `A` is cached, but the
+        // one for `B` still needs to be loaded
+        let block_state = || {
+            let mut cache = LfuCache::new();
+            cache.insert(
+                EntityKey {
+                    subgraph_id: subgraph_id.clone(),
+                    entity_type: "A".to_owned(),
+                    entity_id: "counter".to_owned(),
+                },
+                Some(Entity::from(vec![
+                    ("id", Value::from("counter")),
+                    ("count", Value::Int(10)),
+                ])),
+            );
+            BlockState::new(store.cheap_clone(), cache)
+        };
+        let finish = |state: BlockState, proof_of_indexing: SharedProofOfIndexing| {
+            let mods = state
+                .entity_cache
+                .as_modifications(store.as_ref())
+                .unwrap()
+                .modifications;
+            let digests: BTreeMap<_, _> = Arc::try_unwrap(proof_of_indexing.unwrap())
+                .unwrap()
+                .into_inner()
+                .take()
+                .into_iter()
+                .map(|(causality_region, stream)| (causality_region, stream.pause(None)))
+                .collect();
+            (mods, digests)
+        };
+
+        let proof_of_indexing = Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::new(1))));
+        let state = process_triggers::<CountingHostBuilder>(
+            &logger,
+            block_state(),
+            proof_of_indexing.cheap_clone(),
+            metrics.cheap_clone(),
+            &hosts,
+            &block,
+            triggers.clone(),
+        )
+        .await
+        .unwrap();
+        let sequential = finish(state, proof_of_indexing);
+
+        let proof_of_indexing = Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::new(1))));
+        let state = process_triggers_in_groups::<CountingHostBuilder>(
+            &logger,
+            block_state(),
+            proof_of_indexing.cheap_clone(),
+            metrics,
+            groups,
+            &block,
+            triggers,
+        )
+        .await
+        .unwrap();
+        let grouped = finish(state, proof_of_indexing);
+
+        assert_eq!(2, sequential.0.len());
+        assert_eq!(2, sequential.1.len());
+        assert_eq!(sequential, grouped);
+    }
+
+    fn block_ptr(number: u64) -> EthereumBlockPointer {
+        EthereumBlockPointer {
+            hash: H256::from_low_u64_be(number),
+            number,
+        }
+    }
+
+    /// A store in which source deployments are healthy, or failed with
+    /// the message `error`
+    fn source_store(error: Option<&'static str>) -> MockStore {
+        let mut store = MockStore::new();
+        store
+            .expect_get_mock()
+            .returning(move |key| match (key.entity_type.as_str(), error) {
+                ("SubgraphDeployment", None) => {
+                    Ok(Some(Entity::from(vec![("health", Value::from("healthy"))])))
+                }
+                ("SubgraphDeployment", Some(_)) => Ok(Some(Entity::from(vec![
+                    ("health", Value::from("failed")),
+                    ("fatalError", Value::from("error1")),
+                ]))),
+                ("SubgraphError", Some(error)) => {
+                    Ok(Some(Entity::from(vec![("message", Value::from(error))])))
+                }
+                _ => unreachable!("unexpected lookup of {:?}", key),
+            });
+        store
+    }
+
+    #[tokio::test]
+    async fn waits_for_source_deployment_events() {
+        let logger = Logger::root(slog::Discard, o!());
+        let source = SubgraphDeploymentId::new("source").unwrap();
+        let other = SubgraphDeploymentId::new("other").unwrap();
+        let mut store = source_store(None);
+
+        // The source deployment is behind us before and after we subscribe,
+        // and two blocks ahead of us once it changes
+        let mut calls = 0;
+        store.expect_block_ptr_mock().returning(move |_| {
+            calls += 1;
+            Ok(Some(block_ptr(if calls <= 2 { 0 } else { 3 })))
+        });
+        store
+            .expect_ancestor_block()
+            .returning(|source_ptr, offset| {
+                assert_eq!((3, 2), (source_ptr.number, offset));
+                let mut block = EthereumBlock::default();
+                block.block.hash = Some(block_ptr(1).hash);
+                Ok(Some(block))
+            });
+
+        // Only the change to the source deployment makes us check again
+        let change = |id: &SubgraphDeploymentId| {
+            let (subgraph_id, entity_type) = SubgraphDeploymentEntity::subgraph_entity_pair();
+            Arc::new(StoreEvent::new(vec![EntityChange {
+                subgraph_id,
+                entity_type,
+                entity_id: id.to_string(),
+                operation: EntityChangeOperation::Set,
+            }]))
+        };
+        let (mut sender, receiver) = channel(10);
+        sender.try_send(change(&other)).unwrap();
+        sender.try_send(change(&source)).unwrap();
+        let mut receiver = Some(receiver);
+        store.expect_subscribe_mock().times(1).returning(move |_| {
+            let events: Box<dyn Stream<Item = Arc<StoreEvent>, Error = ()> + Send> =
+                Box::new(receiver.take().unwrap());
+            StoreEventStream::new(events)
+        });
+
+        let guard = CancelGuard::new();
+        let result = wait_for_source_deployments(
+            &logger,
+            &store,
+            vec![source].iter(),
+            &block_ptr(1),
+            &guard.handle(),
+        )
+        .await;
+        assert!(matches!(result, Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn fails_when_source_deployment_failed() {
+        let logger = Logger::root(slog::Discard, o!());
+        let source = SubgraphDeploymentId::new("source").unwrap();
+        let store = source_store(Some("mapping aborted"));
+
+        let guard = CancelGuard::new();
+        let result = wait_for_source_deployments(
+            &logger,
+            &store,
+            vec![source].iter(),
+            &block_ptr(1),
+            &guard.handle(),
+        )
+        .await;
+        match result {
+            Err(CancelableError::Error(BlockProcessingError::Unknown(e))) => assert_eq!(
+                "source deployment `source` failed: mapping aborted",
+                e.to_string()
+            ),
+            _ => panic!("waiting for a failed source deployment must fail"),
+        }
+    }
+}
@@ -1,11 +1,15 @@
+mod coordinator;
 mod instance;
 mod instance_manager;
 mod loader;
 mod provider;
 mod registrar;
+mod trigger_log;
 
+pub use self::coordinator::NodeCoordinator;
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
+pub use self::trigger_log::{TriggerLog, TriggerLogRecord};
@@ -5,7 +5,7 @@ use std::sync::Mutex;
 use async_trait::async_trait;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 
-use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphManifestEntity};
 use graph::prelude::{
     DataSourceLoader as _, GraphQlRunner,
     SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait, *,
@@ -94,8 +94,6 @@ where
             self.graphql_runner.clone(),
         ));
 
-        let link = format!("/ipfs/{}", id);
-
         let logger = self.logger_factory.subgraph_logger(id);
         let logger_for_resolve = logger.clone();
         let logger_for_err = logger.clone();
@@ -103,13 +101,19 @@ where
         info!(logger, "Resolve subgraph files using IPFS");
 
         async move {
-            let mut subgraph = SubgraphManifest::resolve(
-                Link { link },
-                self.resolver.deref(),
-                &logger_for_resolve,
-            )
-            .map_err(SubgraphAssignmentProviderError::ResolveError)
-            .await?;
+            let link = SubgraphManifestEntity::location(store.as_ref(), id)
+                .map_err(|e| SubgraphAssignmentProviderError::Unknown(e.into()))?;
+            let mut subgraph =
+                SubgraphManifest::resolve_as(link, id, self.resolver.deref(), &logger_for_resolve)
+                    .map_err(SubgraphAssignmentProviderError::ResolveError)
+                    .await?;
+            if let Some(overrides) = SubgraphManifestEntity::overrides(store.as_ref(), id)
+                .map_err(|e| SubgraphAssignmentProviderError::Unknown(e.into()))?
+            {
+                overrides
+                    .apply(&mut subgraph)
+                    .map_err(|e| SubgraphAssignmentProviderError::Unknown(e.into()))?;
+            }
 
             let data_sources = loader
                 .load_dynamic_data_sources(id.clone(), logger.clone())
@@ -5,9 +5,10 @@ use std::time::{Duration, Instant};
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 
-use graph::components::ethereum::EthereumNetworks;
+use graph::components::ethereum::{EthereumNetworks, REORG_THRESHOLD};
 use graph::data::subgraph::schema::{
-    SubgraphDeploymentAssignmentEntity, SubgraphDeploymentEntity, SubgraphEntity, TypedEntity,
+    SubgraphDeploymentAssignmentEntity, SubgraphDeploymentEntity, SubgraphEntity,
+    SubgraphManifestEntity, TypedEntity,
 };
 use graph::prelude::{
     CreateSubgraphResult, SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
@@ -279,45 +280,43 @@ where
     }
 }
 
-#[async_trait]
-impl<L, P, S, CS> SubgraphRegistrarTrait for SubgraphRegistrar<L, P, S, CS>
+impl<L, P, S, CS> SubgraphRegistrar<L, P, S, CS>
 where
     L: LinkResolver,
     P: SubgraphAssignmentProviderTrait,
     S: Store + SubgraphDeploymentStore,
     CS: ChainStore,
 {
-    async fn create_subgraph(
-        &self,
-        name: SubgraphName,
-    ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
-        let id = self.store.create_subgraph(name.clone())?;
-
-        debug!(self.logger, "Created subgraph"; "subgraph_name" => name.to_string());
-
-        Ok(CreateSubgraphResult { id })
-    }
-
-    async fn create_subgraph_version(
+    /// Validate `unvalidated` and create a new version of the subgraph
+    /// `name` for it. If the subgraph is grafted, return a preview of what
+    /// the graft will do
+    async fn deploy(
         &self,
+        logger: &Logger,
         name: SubgraphName,
-        hash: SubgraphDeploymentId,
-        node_id: NodeId,
-    ) -> Result<(), SubgraphRegistrarError> {
-        let logger = self.logger_factory.subgraph_logger(&hash);
-
-        let unvalidated = UnvalidatedSubgraphManifest::resolve(
-            hash.to_ipfs_link(),
-            self.resolver.clone(),
-            &logger,
-        )
-        .map_err(SubgraphRegistrarError::ResolveError)
-        .await?;
-
+        unvalidated: UnvalidatedSubgraphManifest,
+        node_id: Option<NodeId>,
+    ) -> Result<Option<GraftPreview>, SubgraphRegistrarError> {
         let (manifest, validation_warnings) = unvalidated
             .validate(self.store.clone())
             .map_err(SubgraphRegistrarError::ManifestValidationError)?;
 
+        let graft_preview = match &manifest.graft {
+            Some(graft) => Some(self.store.graft_preview(&graft.base, &manifest.schema)?),
+            None => None,
+        };
+        if let Some(preview) = &graft_preview {
+            if !preview.errors.is_empty() {
+                return Err(SubgraphRegistrarError::ManifestValidationError(vec![
+                    SubgraphManifestValidationError::GraftBaseInvalid(format!(
+                        "the schema is incompatible with the schema of `{}`: {}",
+                        preview.base,
+                        preview.errors.join("; ")
+                    )),
+                ]));
+            }
+        }
+
         let network_name = manifest.network_name();
 
         let chain_store = self.chain_stores.get(&network_name).ok_or(
@@ -331,14 +330,26 @@ where
             .adapter_with_capabilities(network_name.clone(), &subgraph_eth_requirements)
             .map_err(|_| {
                 SubgraphRegistrarError::SubgraphNetworkRequirementsNotSupported(
-                    network_name,
-                    subgraph_eth_requirements,
+                    network_name.clone(),
+                    self.ethereum_networks
+                        .explain_unsupported(&network_name, &subgraph_eth_requirements),
                 )
             })?;
 
+        let node_id = match node_id {
+            Some(node_id) => node_id,
+            None => self.place(&name, &manifest.network_name())?,
+        };
+        info!(
+            logger,
+            "Assigning deployment to node";
+            "subgraph_name" => name.to_string(),
+            "node_id" => node_id.to_string(),
+        );
+
         let manifest_id = manifest.id.clone();
         create_subgraph_version(
-            &logger,
+            logger,
             self.store.clone(),
             chain_store.clone(),
             ethereum_adapter.clone(),
@@ -351,14 +362,203 @@ where
         .await?;
 
         debug!(
-            &logger,
+            logger,
             "Wrote new subgraph version to store";
             "subgraph_name" => name.to_string(),
             "subgraph_hash" => manifest_id.to_string(),
             "validation_warnings" => format!("{:?}", validation_warnings),
+            "graft_preview" => format!("{:?}", graft_preview),
         );
 
-        Ok(())
+        Ok(graft_preview)
+    }
+
+    /// Choose the node for a new deployment of the subgraph `name` on
+    /// `network` from the placement rules that are in effect
+    fn place(&self, name: &SubgraphName, network: &str) -> Result<NodeId, SubgraphRegistrarError> {
+        choose_node(
+            &PlacementRules::current(),
+            name,
+            network,
+            &self.node_id,
+            || self.store.deployment_infos(None, None),
+        )
+    }
+}
+
+#[async_trait]
+impl<L, P, S, CS> SubgraphRegistrarTrait for SubgraphRegistrar<L, P, S, CS>
+where
+    L: LinkResolver,
+    P: SubgraphAssignmentProviderTrait,
+    S: Store + SubgraphDeploymentStore,
+    CS: ChainStore,
+{
+    async fn create_subgraph(
+        &self,
+        name: SubgraphName,
+    ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
+        let id = self.store.create_subgraph(name.clone())?;
+
+        debug!(self.logger, "Created subgraph"; "subgraph_name" => name.to_string());
+
+        Ok(CreateSubgraphResult { id })
+    }
+
+    async fn create_subgraph_version(
+        &self,
+        name: SubgraphName,
+        hash: SubgraphDeploymentId,
+        node_id: Option<NodeId>,
+        overrides: Option<DataSourceOverrides>,
+    ) -> Result<Option<GraftPreview>, SubgraphRegistrarError> {
+        // Overridden data sources lead to different data, which must not
+        // end up in the deployment for `hash`
+        let overrides = overrides.filter(|overrides| !overrides.is_empty());
+        let id = match &overrides {
+            Some(overrides) => overrides.deployment_id(&hash),
+            None => hash.clone(),
+        };
+        let logger = self.logger_factory.subgraph_logger(&id);
+
+        // Deploying an existing deployment again only assigns it to a new
+        // version, which would silently ignore different overrides
+        if self
+            .store
+            .get(SubgraphDeploymentEntity::key(id.clone()))?
+            .is_some()
+        {
+            let existing = SubgraphManifestEntity::overrides(self.store.as_ref(), &id)?
+                .filter(|overrides| !overrides.is_empty());
+            if existing != overrides {
+                return Err(SubgraphRegistrarError::DeploymentExists(format!(
+                    "{} with different data source overrides",
+                    id
+                )));
+            }
+        }
+
+        let mut unvalidated = UnvalidatedSubgraphManifest::resolve_as(
+            hash.to_ipfs_link(),
+            &id,
+            self.resolver.clone(),
+            &logger,
+        )
+        .map_err(SubgraphRegistrarError::ResolveError)
+        .await?;
+        if let Some(overrides) = overrides {
+            info!(
+                logger,
+                "Override data sources";
+                "manifest" => hash.as_str(),
+                "deployment" => id.as_str(),
+                "overrides" => format!("{:?}", overrides),
+            );
+            unvalidated = unvalidated
+                .with_overrides(&overrides)
+                .map_err(|e| SubgraphRegistrarError::ManifestValidationError(vec![e]))?;
+        }
+
+        self.deploy(&logger, name, unvalidated, node_id).await
+    }
+
+    /// Create a new deployment `target` with the same manifest as the
+    /// deployment `source`, grafted onto `source` at the most recent block
+    /// that `source` has processed and that is final
+    async fn clone_subgraph_deployment(
+        &self,
+        target_name: SubgraphName,
+        source: SubgraphDeploymentId,
+        target: SubgraphDeploymentId,
+        node_id: Option<NodeId>,
+    ) -> Result<(), SubgraphRegistrarError> {
+        let logger = self.logger_factory.subgraph_logger(&target);
+
+        if self
+            .store
+            .get(SubgraphDeploymentEntity::key(target.clone()))?
+            .is_some()
+        {
+            return Err(SubgraphRegistrarError::DeploymentExists(target.to_string()));
+        }
+        // The clone would replace `source` as the version of the subgraph
+        let clone_error = |msg: String| {
+            SubgraphRegistrarError::ManifestValidationError(vec![
+                SubgraphManifestValidationError::GraftBaseInvalid(msg),
+            ])
+        };
+        if self
+            .store
+            .deployment_infos(None, Some(&source))?
+            .iter()
+            .any(|info| info.subgraph.as_deref() == Some(target_name.as_str()))
+        {
+            return Err(clone_error(format!(
+                "can not clone `{}` as a version of `{}` since it already is a version of it",
+                source, target_name
+            )));
+        }
+
+        let head = self.store.block_ptr(source.clone())?.ok_or_else(|| {
+            clone_error(format!(
+                "failed to clone `{}` since it has not processed any blocks",
+                source
+            ))
+        })?;
+        // Blocks that are not final yet might still be reverted in
+        // `source`, but the clone would keep them
+        let network_name = self
+            .store
+            .network_name(&source)
+            .map_err(SubgraphRegistrarError::Unknown)?
+            .ok_or_else(|| SubgraphRegistrarError::DeploymentNotFound(source.to_string()))?;
+        let chain_head = self
+            .chain_stores
+            .get(&network_name)
+            .ok_or(SubgraphRegistrarError::NetworkNotSupported(network_name))?
+            .chain_head_ptr()
+            .map_err(SubgraphRegistrarError::Unknown)?;
+        let graft_block = clone_block(
+            head.number,
+            chain_head.map(|chain_head| chain_head.number),
+            *REORG_THRESHOLD,
+        )
+        .ok_or_else(|| {
+            clone_error(format!(
+                "failed to clone `{}` since none of the blocks it processed is final yet",
+                source
+            ))
+        })?;
+        let location = SubgraphManifestEntity::location(self.store.as_ref(), &source)?;
+        let overrides = SubgraphManifestEntity::overrides(self.store.as_ref(), &source)?;
+
+        info!(
+            logger,
+            "Clone subgraph deployment";
+            "source" => source.to_string(),
+            "block" => graft_block,
+        );
+
+        let unvalidated = UnvalidatedSubgraphManifest::resolve_as(
+            location,
+            &target,
+            self.resolver.clone(),
+            &logger,
+        )
+        .map_err(SubgraphRegistrarError::ResolveError)
+        .await?
+        .graft(source, graft_block as BlockNumber);
+        // The clone indexes the same data sources as the original
+        let unvalidated = match overrides {
+            Some(overrides) => unvalidated
+                .with_overrides(&overrides)
+                .map_err(|e| SubgraphRegistrarError::ManifestValidationError(vec![e]))?,
+            None => unvalidated,
+        };
+
+        self.deploy(&logger, target_name, unvalidated, node_id)
+            .await
+            .map(|_| ())
     }
 
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError> {
@@ -382,6 +582,130 @@ where
 
         Ok(())
     }
+
+    async fn unassign_subgraph(
+        &self,
+        id: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError> {
+        self.store.unassign_subgraph(&id)?;
+
+        debug!(self.logger, "Unassigned subgraph"; "subgraph_id" => id.to_string());
+
+        Ok(())
+    }
+
+    async fn retry_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        blocks: u64,
+    ) -> Result<EthereumBlockPointer, SubgraphRegistrarError> {
+        if blocks == 0 {
+            return Err(SubgraphRegistrarError::RewindError(
+                "the number of blocks to go back must be at least 1".to_owned(),
+            ));
+        }
+
+        let network_name = self
+            .store
+            .network_name(&hash)
+            .map_err(SubgraphRegistrarError::Unknown)?
+            .ok_or_else(|| SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))?;
+        let chain_store = self
+            .chain_stores
+            .get(&network_name)
+            .ok_or(SubgraphRegistrarError::NetworkNotSupported(network_name))?;
+
+        let non_canonical = self
+            .store
+            .non_canonical_block(&hash)
+            .map_err(SubgraphRegistrarError::from)?;
+        let block_ptr_to = match non_canonical {
+            // The deployment's latest block is not canonical and was removed
+            // from the chain store, so we can't walk back from it; instead,
+            // use the canonical block `blocks` blocks before it
+            Some(non_canonical) => {
+                let number = non_canonical
+                    .block
+                    .number
+                    .checked_sub(blocks)
+                    .ok_or_else(|| {
+                        SubgraphRegistrarError::RewindError(format!(
+                            "can not go back {} blocks from block {}",
+                            blocks, non_canonical.block.number
+                        ))
+                    })?;
+                match chain_store
+                    .block_hashes_by_block_number(number)
+                    .map_err(|e| SubgraphRegistrarError::RewindError(e.to_string()))?
+                    .as_slice()
+                {
+                    [block_hash] => EthereumBlockPointer::from((*block_hash, number)),
+                    _ => {
+                        return Err(SubgraphRegistrarError::RewindError(format!(
+                            "the chain store does not know which block {} is canonical",
+                            number
+                        )))
+                    }
+                }
+            }
+            None => {
+                // A block that fails is not recorded, so the deployment's block
+                // pointer is the block right before the one it failed on
+                let latest = self
+                    .store
+                    .block_ptr(hash.clone())
+                    .map_err(SubgraphRegistrarError::Unknown)?
+                    .ok_or_else(|| {
+                        SubgraphRegistrarError::RewindError(format!(
+                            "`{}` has not processed any blocks yet",
+                            hash
+                        ))
+                    })?;
+                chain_store
+                    .ancestor_block(latest, blocks - 1)
+                    .map_err(|e| SubgraphRegistrarError::RewindError(e.to_string()))?
+                    .map(|block| EthereumBlockPointer::from(&block))
+                    .ok_or_else(|| {
+                        SubgraphRegistrarError::RewindError(format!(
+                            "the chain store is missing some of the {} blocks before block {}",
+                            blocks - 1,
+                            latest.number
+                        ))
+                    })?
+            }
+        };
+
+        self.store.rewind_failed_deployment(&hash, block_ptr_to)?;
+
+        info!(self.logger, "Rewound failed subgraph to retry it";
+              "subgraph_id" => hash.to_string(),
+              "block_number" => block_ptr_to.number);
+
+        Ok(block_ptr_to)
+    }
+
+    async fn list_subgraphs(&self) -> Result<Vec<DeploymentInfo>, SubgraphRegistrarError> {
+        Ok(self.store.deployment_infos(None, None)?)
+    }
+
+    async fn subgraph_info(
+        &self,
+        name: Option<SubgraphName>,
+        deployment: Option<SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, SubgraphRegistrarError> {
+        let infos = self
+            .store
+            .deployment_infos(name.as_ref(), deployment.as_ref())?;
+        match (name, deployment) {
+            (Some(name), _) if infos.is_empty() => {
+                Err(SubgraphRegistrarError::NameNotFound(name.to_string()))
+            }
+            (None, Some(id)) if infos.is_empty() => {
+                Err(SubgraphRegistrarError::DeploymentNotFound(id.to_string()))
+            }
+            _ => Ok(infos),
+        }
+    }
 }
 
 async fn handle_assignment_event(
@@ -445,6 +769,49 @@ async fn start_subgraph(
     }
 }
 
+/// Choose the node for a new deployment of the subgraph `name` on
+/// `network` from `rules`. When a rule lists several nodes, use the one
+/// with the fewest of the deployments that `deployments` returns. Without
+/// a matching rule, the deployment goes to `default`
+fn choose_node(
+    rules: &PlacementRules,
+    name: &SubgraphName,
+    network: &str,
+    default: &NodeId,
+    deployments: impl FnOnce() -> Result<Vec<DeploymentInfo>, StoreError>,
+) -> Result<NodeId, SubgraphRegistrarError> {
+    let nodes = match rules.nodes(name, network) {
+        Some(nodes) => nodes,
+        None => return Ok(default.clone()),
+    };
+    if nodes.len() == 1 {
+        return Ok(nodes[0].clone());
+    }
+
+    let mut counts: HashMap<String, HashSet<String>> = HashMap::new();
+    for info in deployments()? {
+        if let Some(node_id) = info.node_id {
+            counts.entry(node_id).or_default().insert(info.deployment);
+        }
+    }
+    // Unwrap: rules always list at least one node
+    Ok(nodes
+        .iter()
+        .min_by_key(|node| counts.get(node.as_str()).map(HashSet::len).unwrap_or(0))
+        .unwrap()
+        .clone())
+}
+
+/// The block at which a clone of a deployment that has processed blocks up
+/// to `head` is grafted onto that deployment: the most recent block that
+/// is final, given that the chain head is at `chain_head`, but not after
+/// `head`. Returns `None` if no block is final yet
+fn clone_block(head: u64, chain_head: Option<u64>, reorg_threshold: u64) -> Option<u64> {
+    chain_head
+        .and_then(|chain_head| chain_head.checked_sub(reorg_threshold))
+        .map(|last_final| head.min(last_final))
+}
+
 /// Resolves the subgraph's earliest block and the manifest's graft base block
 fn resolve_subgraph_chain_blocks(
     manifest: SubgraphManifest,
@@ -583,3 +950,89 @@ fn create_subgraph_version(
             })
     )
 }
+
+#[test]
+fn clone_block_is_final() {
+    // Without a chain head, or before enough blocks are mined, nothing is final
+    assert_eq!(None, clone_block(10, None, 50));
+    assert_eq!(None, clone_block(10, Some(49), 50));
+    // Blocks that are not final yet are left to the clone to index
+    assert_eq!(Some(0), clone_block(10, Some(50), 50));
+    assert_eq!(Some(50), clone_block(80, Some(100), 50));
+    // A deployment that is behind is cloned at its most recent block
+    assert_eq!(Some(10), clone_block(10, Some(100), 50));
+}
+
+#[test]
+fn choose_node_by_rules_and_load() {
+    let node = |id: &str| NodeId::new(id).unwrap();
+    let name = |name: &str| SubgraphName::new(name).unwrap();
+    let info = |deployment: &str, subgraph: &str, node_id: &str| DeploymentInfo {
+        subgraph: Some(subgraph.to_owned()),
+        version: Some("current".to_owned()),
+        deployment: deployment.to_owned(),
+        node_id: Some(node_id.to_owned()),
+        synced: true,
+        health: "healthy".to_owned(),
+        latest_block_number: None,
+        fatal_error: None,
+        needs_rewind: false,
+    };
+    let deployments = || {
+        Ok(vec![
+            info("Qm1", "uniswap/v1", "heavy_1"),
+            info("Qm3", "uniswap/v3", "heavy_1"),
+            // A deployment that several subgraphs use only counts once
+            info("Qm2", "uniswap/v2", "heavy_2"),
+            info("Qm2", "uniswap/v2-pending", "heavy_2"),
+        ])
+    };
+    let unused = || -> Result<Vec<DeploymentInfo>, StoreError> {
+        panic!("the deployments are only needed to choose among several nodes")
+    };
+
+    let mut rules = PlacementRules::default();
+    rules
+        .add(
+            "uniswap/*",
+            "mainnet",
+            vec![node("heavy_1"), node("heavy_2")],
+        )
+        .unwrap();
+    rules.add("*", "rinkeby", vec![node("testnets")]).unwrap();
+    let default = node("default");
+
+    assert_eq!(
+        node("heavy_2"),
+        choose_node(
+            &rules,
+            &name("uniswap/v4"),
+            "mainnet",
+            &default,
+            deployments
+        )
+        .unwrap()
+    );
+    assert_eq!(
+        node("testnets"),
+        choose_node(&rules, &name("ens"), "rinkeby", &default, unused).unwrap()
+    );
+    assert_eq!(
+        default,
+        choose_node(&rules, &name("ens"), "mainnet", &default, unused).unwrap()
+    );
+
+    // Nodes without any deployments are preferred
+    rules = PlacementRules::default();
+    rules
+        .add(
+            "*",
+            "*",
+            vec![node("heavy_2"), node("heavy_1"), node("idle")],
+        )
+        .unwrap();
+    assert_eq!(
+        node("idle"),
+        choose_node(&rules, &name("ens"), "mainnet", &default, deployments).unwrap()
+    );
+}
@@ -0,0 +1,401 @@
+//! A log of the exact triggers that a deployment processed, in the order in
+//! which it processed them.
+//!
+//! When `GRAPH_TRIGGER_LOG_DIR` is set, every deployment writes its log to
+//! the directory `$GRAPH_TRIGGER_LOG_DIR/<deployment>`. The log consists of
+//! segments, files named `segment-<sequence number>.jsonl.zst`, and a new
+//! segment is started whenever the deployment starts and after every
+//! `GRAPH_TRIGGER_LOG_SEGMENT_BLOCKS` blocks. Each record in a segment is
+//! one line of JSON compressed into its own zstd frame so that a crash can
+//! at most lose the record that was being written; the segments can be read
+//! with `zstdcat` or `graphman trigger-log`.
+//!
+//! A block is recorded before it is committed so that a crash can not leave
+//! a gap in the log. A block can therefore appear more than once, e.g.,
+//! when its commit failed and it was processed again; the last record for a
+//! block replaces the records for it and all later blocks.
+//!
+//! When `GRAPH_TRIGGER_LOG_REPLAY_DIR` is set, a deployment that has a log
+//! in `$GRAPH_TRIGGER_LOG_REPLAY_DIR/<deployment>` processes the triggers
+//! from that log instead of the ones it finds on the chain, and fails if the
+//! chain has different blocks than the log.
+//!
+//! Triggers from upstream subgraph data sources are not part of the log
+//! since they are derived from data in the store and not from the chain.
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use graph::prelude::{
+    format_err, serde_json, Deserialize, Error, EthereumBlockPointer, EthereumTrigger, Serialize,
+    SubgraphDeploymentId,
+};
+use graph::util::shutdown;
+
+lazy_static! {
+    static ref TRIGGER_LOG_DIR: Option<PathBuf> = std::env::var("GRAPH_TRIGGER_LOG_DIR")
+        .ok()
+        .map(PathBuf::from);
+    static ref TRIGGER_LOG_REPLAY_DIR: Option<PathBuf> =
+        std::env::var("GRAPH_TRIGGER_LOG_REPLAY_DIR")
+            .ok()
+            .map(PathBuf::from);
+    static ref TRIGGER_LOG_SEGMENT_BLOCKS: u64 = std::env::var("GRAPH_TRIGGER_LOG_SEGMENT_BLOCKS")
+        .unwrap_or("10000".into())
+        .parse::<u64>()
+        .expect("invalid GRAPH_TRIGGER_LOG_SEGMENT_BLOCKS");
+}
+
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_SUFFIX: &str = ".jsonl.zst";
+
+/// One entry in the trigger log
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum TriggerLogRecord {
+    /// The deployment processed `block`. The `triggers` were processed by
+    /// the data sources that existed at the start of the block; every
+    /// entry in `data_source_triggers` holds the triggers processed by
+    /// the data sources that the previous round of triggers created
+    #[serde(rename_all = "camelCase")]
+    Block {
+        block: EthereumBlockPointer,
+        triggers: Vec<EthereumTrigger>,
+        data_source_triggers: Vec<Vec<EthereumTrigger>>,
+    },
+    /// The deployment reverted its latest block and is now at block `to`
+    Revert { to: Option<EthereumBlockPointer> },
+}
+
+pub struct TriggerLog {
+    dir: PathBuf,
+    segment_blocks: u64,
+    next_segment: u64,
+    current: Option<(File, u64)>,
+}
+
+impl TriggerLog {
+    /// Open the trigger log for `deployment` if trigger logs are turned on
+    pub fn from_env(deployment: &SubgraphDeploymentId) -> Result<Option<Self>, Error> {
+        TRIGGER_LOG_DIR
+            .as_ref()
+            .map(|dir| Self::open(dir, deployment, *TRIGGER_LOG_SEGMENT_BLOCKS))
+            .transpose()
+    }
+
+    pub fn open(
+        dir: &Path,
+        deployment: &SubgraphDeploymentId,
+        segment_blocks: u64,
+    ) -> Result<Self, Error> {
+        let dir = dir.join(deployment.as_str());
+        fs::create_dir_all(&dir)
+            .map_err(|e| format_err!("failed to create trigger log {}: {}", dir.display(), e))?;
+        let next_segment = segments(&dir)?.last().map(|(seq, _)| seq + 1).unwrap_or(0);
+        Ok(TriggerLog {
+            dir,
+            segment_blocks,
+            next_segment,
+            current: None,
+        })
+    }
+
+    pub fn append(&mut self, record: &TriggerLogRecord) -> Result<(), Error> {
+        let rotate = match &self.current {
+            None => true,
+            Some((_, blocks)) => *blocks >= self.segment_blocks,
+        };
+        if rotate {
+            let path = self.dir.join(format!(
+                "{}{:08}{}",
+                SEGMENT_PREFIX, self.next_segment, SEGMENT_SUFFIX
+            ));
+            let file = OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| format_err!("failed to create {}: {}", path.display(), e))?;
+            self.next_segment += 1;
+            self.current = Some((file, 0));
+        }
+
+        // Unwrap: we just made sure there is a current segment
+        let (file, blocks) = self.current.as_mut().unwrap();
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let frame = zstd::encode_all(line.as_slice(), 0)?;
+        file.write_all(&frame)?;
+        file.flush()?;
+        // The node is about to exit; make sure the last records of the
+        // segment are on disk and not just in the page cache
+        if shutdown::is_shutting_down() {
+            file.sync_data()?;
+        }
+        if let TriggerLogRecord::Block { .. } = record {
+            *blocks += 1;
+        }
+        Ok(())
+    }
+
+    /// Read all records in the trigger log of `deployment` in `dir`
+    pub fn read(
+        dir: &Path,
+        deployment: &SubgraphDeploymentId,
+    ) -> Result<Vec<TriggerLogRecord>, Error> {
+        let dir = dir.join(deployment.as_str());
+        let mut records = Vec::new();
+        for (_, path) in segments(&dir)? {
+            let data = zstd::decode_all(File::open(&path)?)
+                .map_err(|e| format_err!("failed to decompress {}: {}", path.display(), e))?;
+            for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                records.push(serde_json::from_slice(line).map_err(|e| {
+                    format_err!("invalid trigger log record in {}: {}", path.display(), e)
+                })?);
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// The triggers of a block and the triggers for the data sources that
+/// each round of triggers created, as they were recorded
+type BlockTriggers = (Vec<EthereumTrigger>, Vec<Vec<EthereumTrigger>>);
+
+/// The blocks of a trigger log that the deployment had processed when the
+/// log ended, with their triggers, so that they can be processed again
+pub struct TriggerReplay {
+    blocks: BTreeMap<u64, (EthereumBlockPointer, BlockTriggers)>,
+}
+
+impl TriggerReplay {
+    /// Load the trigger log of `deployment` for replaying if trigger log
+    /// replays are turned on
+    pub fn from_env(deployment: &SubgraphDeploymentId) -> Result<Option<Self>, Error> {
+        TRIGGER_LOG_REPLAY_DIR
+            .as_ref()
+            .map(|dir| Self::load(dir, deployment))
+            .transpose()
+    }
+
+    pub fn load(dir: &Path, deployment: &SubgraphDeploymentId) -> Result<Self, Error> {
+        Ok(Self::from_records(TriggerLog::read(dir, deployment)?))
+    }
+
+    /// Replay the records in order. A record for a block replaces the ones
+    /// for the same and all later blocks, just like a revert removes the
+    /// blocks after the one it reverted to
+    fn from_records(records: Vec<TriggerLogRecord>) -> Self {
+        let mut blocks = BTreeMap::new();
+        for record in records {
+            match record {
+                TriggerLogRecord::Block {
+                    block,
+                    triggers,
+                    data_source_triggers,
+                } => {
+                    blocks.split_off(&block.number);
+                    blocks.insert(block.number, (block, (triggers, data_source_triggers)));
+                }
+                TriggerLogRecord::Revert { to } => {
+                    blocks.split_off(&to.map(|to| to.number + 1).unwrap_or(0));
+                }
+            }
+        }
+        TriggerReplay { blocks }
+    }
+
+    /// The recorded triggers to process for `block` in place of the ones
+    /// found on the chain, given that the deployment is at `previous`.
+    /// Blocks that are not in the log have no triggers. Fails if the log
+    /// has a different block at the same height, or if it has triggers for
+    /// blocks between `previous` and `block`, which the replay would skip
+    pub fn triggers(
+        &self,
+        previous: Option<&EthereumBlockPointer>,
+        block: &EthereumBlockPointer,
+    ) -> Result<BlockTriggers, Error> {
+        let start = previous.map(|previous| previous.number + 1).unwrap_or(0);
+        let skipped =
+            self.blocks
+                .range(start..block.number)
+                .find(|(_, (_, (triggers, rounds)))| {
+                    !triggers.is_empty() || rounds.iter().any(|round| !round.is_empty())
+                });
+        if let Some((_, (skipped, _))) = skipped {
+            return Err(format_err!(
+                "the trigger log has triggers for block {} but the block stream skipped it",
+                skipped
+            ));
+        }
+        match self.blocks.get(&block.number) {
+            Some((recorded, _)) if recorded != block => Err(format_err!(
+                "the trigger log has block {} but the chain has block {}",
+                recorded,
+                block
+            )),
+            Some((_, triggers)) => Ok(triggers.clone()),
+            None => Ok((vec![], vec![])),
+        }
+    }
+}
+
+/// The segments in `dir`, ordered by their sequence number
+fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)
+        .map_err(|e| format_err!("failed to read trigger log {}: {}", dir.display(), e))?
+    {
+        let path = entry?.path();
+        let seq = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.starts_with(SEGMENT_PREFIX) && name.ends_with(SEGMENT_SUFFIX))
+            .and_then(|name| {
+                name[SEGMENT_PREFIX.len()..name.len() - SEGMENT_SUFFIX.len()]
+                    .parse::<u64>()
+                    .ok()
+            });
+        if let Some(seq) = seq {
+            segments.push((seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::web3::types::{Address, H256};
+    use graph::prelude::EthereumBlockTriggerType;
+
+    fn ptr(number: u64) -> EthereumBlockPointer {
+        EthereumBlockPointer {
+            hash: H256::from_low_u64_be(number),
+            number,
+        }
+    }
+
+    fn block(number: u64) -> TriggerLogRecord {
+        TriggerLogRecord::Block {
+            block: ptr(number),
+            triggers: vec![EthereumTrigger::Block(
+                ptr(number),
+                EthereumBlockTriggerType::WithCallTo(Address::from_low_u64_be(7)),
+            )],
+            data_source_triggers: vec![vec![EthereumTrigger::Block(
+                ptr(number),
+                EthereumBlockTriggerType::Every,
+            )]],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!(
+            "graph-node-trigger-log-test-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let deployment = SubgraphDeploymentId::new("triggerLog").unwrap();
+
+        let mut records = vec![block(1), block(2), block(3)];
+        records.push(TriggerLogRecord::Revert { to: Some(ptr(2)) });
+        records.push(block(3));
+
+        // Write the records with a restart in between; that, and the
+        // segment size, make sure we read across several segments
+        let mut log = TriggerLog::open(&dir, &deployment, 2).unwrap();
+        for record in &records[0..3] {
+            log.append(record).unwrap();
+        }
+        let mut log = TriggerLog::open(&dir, &deployment, 2).unwrap();
+        for record in &records[3..] {
+            log.append(record).unwrap();
+        }
+
+        assert_eq!(3, segments(&dir.join("triggerLog")).unwrap().len());
+        assert_eq!(records, TriggerLog::read(&dir, &deployment).unwrap());
+
+        let replay = TriggerReplay::load(&dir, &deployment).unwrap();
+        assert_eq!(
+            vec![1, 2, 3],
+            replay.blocks.keys().cloned().collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay() {
+        let triggers = |record: TriggerLogRecord| match record {
+            TriggerLogRecord::Block {
+                triggers,
+                data_source_triggers,
+                ..
+            } => (triggers, data_source_triggers),
+            TriggerLogRecord::Revert { .. } => unreachable!(),
+        };
+        let other = |number: u64| EthereumBlockPointer {
+            hash: H256::from_low_u64_be(number + 100),
+            number,
+        };
+        let mut empty = block(6);
+        if let TriggerLogRecord::Block {
+            triggers,
+            data_source_triggers,
+            ..
+        } = &mut empty
+        {
+            triggers.clear();
+            data_source_triggers.clear();
+        }
+
+        // Block 3 was recorded twice since its first commit failed, and the
+        // revert of block 5 was not recorded before block 5 was processed
+        // again on a different chain
+        let replay = TriggerReplay::from_records(vec![
+            block(1),
+            block(3),
+            block(3),
+            block(4),
+            TriggerLogRecord::Revert { to: Some(ptr(3)) },
+            block(4),
+            block(5),
+            TriggerLogRecord::Block {
+                block: other(5),
+                triggers: vec![],
+                data_source_triggers: vec![],
+            },
+            empty,
+            block(8),
+        ]);
+        assert_eq!(
+            vec![1, 3, 4, 5, 6, 8],
+            replay.blocks.keys().cloned().collect::<Vec<_>>()
+        );
+
+        assert_eq!(triggers(block(1)), replay.triggers(None, &ptr(1)).unwrap());
+        assert_eq!(
+            triggers(block(4)),
+            replay.triggers(Some(&ptr(3)), &ptr(4)).unwrap()
+        );
+        // Blocks without a record have no triggers
+        assert_eq!(
+            (vec![], vec![]),
+            replay.triggers(Some(&ptr(8)), &ptr(9)).unwrap()
+        );
+        // Skipping blocks without triggers is fine, skipping ones with
+        // triggers is not
+        assert!(replay.triggers(Some(&ptr(5)), &ptr(8)).is_ok());
+        assert!(replay.triggers(Some(&ptr(1)), &ptr(4)).is_err());
+        // The chain has to have the same blocks as the log
+        assert!(replay.triggers(Some(&ptr(4)), &ptr(5)).is_err());
+        assert!(replay.triggers(Some(&ptr(4)), &other(5)).is_ok());
+    }
+}
@@ -0,0 +1,111 @@
+//! Tests for the checks the subgraph registrar makes before it clones a
+//! deployment
+use std::collections::HashMap;
+
+use graph::components::ethereum::EthereumNetworks;
+use graph::prelude::{SubgraphRegistrar as SubgraphRegistrarTrait, *};
+use graph_core::{LinkResolver, SubgraphRegistrar};
+use test_store::*;
+
+/// A provider that never has any subgraphs to start or stop
+struct NoopProvider;
+
+impl EventProducer<SubgraphAssignmentProviderEvent> for NoopProvider {
+    fn take_event_stream(
+        &mut self,
+    ) -> Option<Box<dyn Stream<Item = SubgraphAssignmentProviderEvent, Error = ()> + Send>> {
+        None
+    }
+}
+
+#[async_trait]
+impl SubgraphAssignmentProvider for NoopProvider {
+    async fn start(&self, _: &SubgraphDeploymentId) -> Result<(), SubgraphAssignmentProviderError> {
+        Ok(())
+    }
+
+    async fn stop(&self, _: SubgraphDeploymentId) -> Result<(), SubgraphAssignmentProviderError> {
+        Ok(())
+    }
+}
+
+fn registrar<S>(store: Arc<S>) -> impl SubgraphRegistrarTrait
+where
+    S: Store + SubgraphDeploymentStore + ChainStore,
+{
+    SubgraphRegistrar::new(
+        &LoggerFactory::new(LOGGER.clone(), None),
+        Arc::new(LinkResolver::from(ipfs_api::IpfsClient::default())),
+        Arc::new(NoopProvider),
+        store,
+        HashMap::<String, Arc<S>>::new(),
+        EthereumNetworks::new(),
+        NodeId::new("test").unwrap(),
+        SubgraphVersionSwitchingMode::Instant,
+    )
+}
+
+const SCHEMA: &str = "type Thing @entity { id: ID! }";
+
+fn setup() -> (SubgraphDeploymentId, SubgraphDeploymentId) {
+    remove_subgraphs();
+    let source = SubgraphDeploymentId::new("cloneSource").unwrap();
+    let existing = SubgraphDeploymentId::new("cloneExisting").unwrap();
+    create_test_subgraph(&source, SCHEMA);
+    create_test_subgraph(&existing, SCHEMA);
+    (source, existing)
+}
+
+fn assert_graft_base_invalid(res: Result<(), SubgraphRegistrarError>, expected: &str) {
+    match res {
+        Err(SubgraphRegistrarError::ManifestValidationError(errors)) => match errors.as_slice() {
+            [SubgraphManifestValidationError::GraftBaseInvalid(msg)] => {
+                assert!(msg.contains(expected), "unexpected error: {}", msg)
+            }
+            _ => panic!("unexpected validation errors: {:?}", errors),
+        },
+        other => panic!("expected an invalid graft base but got {:?}", other),
+    }
+}
+
+#[test]
+fn clone_refuses_existing_target() {
+    run_test_sequentially(setup, |store, (source, existing)| async move {
+        let name = SubgraphName::new("clone/target").unwrap();
+        let res = registrar(store)
+            .clone_subgraph_deployment(name, source, existing.clone(), None)
+            .await;
+        match res {
+            Err(SubgraphRegistrarError::DeploymentExists(id)) => {
+                assert_eq!(existing.to_string(), id)
+            }
+            other => panic!("expected the target to exist but got {:?}", other),
+        }
+    })
+}
+
+#[test]
+fn clone_refuses_to_replace_source() {
+    run_test_sequentially(setup, |store, (source, _)| async move {
+        // `create_test_subgraph` makes the deployment the current version
+        // of a subgraph with the same name
+        let name = SubgraphName::new(source.to_string()).unwrap();
+        let target = SubgraphDeploymentId::new("cloneTarget").unwrap();
+        let res = registrar(store)
+            .clone_subgraph_deployment(name, source, target, None)
+            .await;
+        assert_graft_base_invalid(res, "since it already is a version of it");
+    })
+}
+
+#[test]
+fn clone_needs_processed_blocks() {
+    run_test_sequentially(setup, |store, (source, _)| async move {
+        let name = SubgraphName::new("clone/target").unwrap();
+        let target = SubgraphDeploymentId::new("cloneTarget").unwrap();
+        let res = registrar(store)
+            .clone_subgraph_deployment(name, source, target, None)
+            .await;
+        assert_graft_base_invalid(res, "since it has not processed any blocks");
+    })
+}
@@ -0,0 +1,9 @@
+use crate::prelude::StoreError;
+
+/// Finds the names that ENS name hashes stand for. Since hashes can not be
+/// reversed, implementations look them up in a rainbow table of known names
+pub trait EnsLookup: Send + Sync + 'static {
+    /// Find the name whose hash is `hash`, given as a `0x`-prefixed hex
+    /// string. Returns `None` if the name is not known
+    fn find_name(&self, hash: &str) -> Result<Option<String>, StoreError>;
+}
@@ -6,12 +6,13 @@ use mockall::predicate::*;
 use mockall::*;
 use petgraph::graphmap::GraphMap;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::marker::Unpin;
 use tiny_keccak::keccak256;
 use web3::types::*;
 
+use super::network::NodeCapabilities;
 use super::types::*;
 use crate::components::metrics::{CounterVec, GaugeVec, HistogramVec};
 use crate::prelude::*;
@@ -65,6 +66,10 @@ pub enum EthereumContractCallError {
     Revert(String),
     #[fail(display = "ethereum node took too long to perform call")]
     Timeout,
+    /// The adapter has no way to perform calls, e.g., because it does
+    /// not talk to an Ethereum node
+    #[fail(display = "contract calls are not available: {}", _0)]
+    Unavailable(String),
 }
 
 impl From<ABIError> for EthereumContractCallError {
@@ -138,6 +143,10 @@ pub struct EthereumLogFilter {
 
     // Event sigs with no associated address, matching on all addresses.
     wildcard_events: HashSet<EventSignature>,
+
+    /// The earliest start block of the data sources for each contract, and
+    /// for each wildcard event
+    start_blocks: HashMap<LogFilterNode, u64>,
 }
 
 impl EthereumLogFilter {
@@ -175,34 +184,46 @@ impl EthereumLogFilter {
         let mut this = EthereumLogFilter::default();
         for ds in iter {
             for event_sig in ds.mapping.event_handlers.iter().map(|e| e.topic0()) {
-                match ds.source.address {
+                let node = match ds.source.address {
                     Some(contract) => {
                         this.contracts_and_events_graph.add_edge(
                             LogFilterNode::Contract(contract),
                             LogFilterNode::Event(event_sig),
                             (),
                         );
+                        LogFilterNode::Contract(contract)
                     }
                     None => {
                         this.wildcard_events.insert(event_sig);
+                        LogFilterNode::Event(event_sig)
                     }
-                }
+                };
+                this.add_start_block(node, ds.source.start_block);
             }
         }
         this
     }
 
+    fn add_start_block(&mut self, node: LogFilterNode, start_block: u64) {
+        let start = self.start_blocks.entry(node).or_insert(start_block);
+        *start = cmp::min(*start, start_block);
+    }
+
     /// Extends this log filter with another one.
     pub fn extend(&mut self, other: EthereumLogFilter) {
         // Destructure to make sure we're checking all fields.
         let EthereumLogFilter {
             contracts_and_events_graph,
             wildcard_events,
+            start_blocks,
         } = other;
         for (s, t, ()) in contracts_and_events_graph.all_edges() {
             self.contracts_and_events_graph.add_edge(s, t, ());
         }
         self.wildcard_events.extend(wildcard_events);
+        for (node, start_block) in start_blocks {
+            self.add_start_block(node, start_block);
+        }
     }
 
     /// An empty filter is one that never matches.
@@ -211,10 +232,46 @@ impl EthereumLogFilter {
         let EthereumLogFilter {
             contracts_and_events_graph,
             wildcard_events,
+            start_blocks: _,
         } = self;
         contracts_and_events_graph.edge_count() == 0 && wildcard_events.is_empty()
     }
 
+    /// The part of this filter that belongs to data sources that have
+    /// started at `block`
+    pub fn active_at(&self, block: u64) -> Self {
+        let started = |node: &LogFilterNode| {
+            self.start_blocks
+                .get(node)
+                .map_or(true, |start_block| *start_block <= block)
+        };
+
+        let mut filter = self.clone();
+        let pending: Vec<_> = filter
+            .contracts_and_events_graph
+            .nodes()
+            .filter(|node| match node {
+                LogFilterNode::Contract(_) => !started(node),
+                LogFilterNode::Event(_) => false,
+            })
+            .collect();
+        for node in pending {
+            filter.contracts_and_events_graph.remove_node(node);
+        }
+        let orphans: Vec<_> = filter
+            .contracts_and_events_graph
+            .nodes()
+            .filter(|node| filter.contracts_and_events_graph.neighbors(*node).count() == 0)
+            .collect();
+        for node in orphans {
+            filter.contracts_and_events_graph.remove_node(node);
+        }
+        filter
+            .wildcard_events
+            .retain(|sig| started(&LogFilterNode::Event(*sig)));
+        filter
+    }
+
     /// Filters for `eth_getLogs` calls. The filters will not return false positives. This attempts
     /// to balance between having granular filters but too many calls and having few calls but too
     /// broad filters causing the Ethereum endpoint to timeout.
@@ -370,6 +427,19 @@ impl EthereumCallFilter {
             .map(|(start_block, _fn_sigs)| *start_block)
             .collect()
     }
+
+    /// The part of this filter that belongs to data sources that have
+    /// started at `block`
+    pub fn active_at(&self, block: u64) -> Self {
+        EthereumCallFilter {
+            contract_addresses_function_signatures: self
+                .contract_addresses_function_signatures
+                .iter()
+                .filter(|(_addr, (start_block, _fn_sigs))| *start_block <= block)
+                .map(|(addr, value)| (*addr, value.clone()))
+                .collect(),
+        }
+    }
 }
 
 impl FromIterator<(u64, Address, [u8; 4])> for EthereumCallFilter {
@@ -413,6 +483,9 @@ impl From<EthereumBlockFilter> for EthereumCallFilter {
 pub struct EthereumBlockFilter {
     pub contract_addresses: HashSet<(u64, Address)>,
     pub trigger_every_block: bool,
+    /// The first block that needs to trigger handlers for every block; only
+    /// meaningful if `trigger_every_block` is set
+    pub trigger_every_block_from: u64,
 }
 
 impl EthereumBlockFilter {
@@ -439,6 +512,7 @@ impl EthereumBlockFilter {
 
                 filter_opt.extend(Self {
                     trigger_every_block: has_block_handler_without_filter,
+                    trigger_every_block_from: data_source.source.start_block,
                     contract_addresses: if has_block_handler_with_call_filter {
                         vec![(
                             data_source.source.start_block,
@@ -455,6 +529,15 @@ impl EthereumBlockFilter {
     }
 
     pub fn extend(&mut self, other: EthereumBlockFilter) {
+        self.trigger_every_block_from = match (self.trigger_every_block, other.trigger_every_block)
+        {
+            (true, true) => cmp::min(
+                self.trigger_every_block_from,
+                other.trigger_every_block_from,
+            ),
+            (false, true) => other.trigger_every_block_from,
+            (_, false) => self.trigger_every_block_from,
+        };
         self.trigger_every_block = self.trigger_every_block || other.trigger_every_block;
         self.contract_addresses = self.contract_addresses.iter().cloned().fold(
             HashSet::new(),
@@ -485,6 +568,21 @@ impl EthereumBlockFilter {
             .map(|(start_block, _fn_sigs)| start_block)
             .collect()
     }
+
+    /// The part of this filter that belongs to data sources that have
+    /// started at `block`
+    pub fn active_at(&self, block: u64) -> Self {
+        EthereumBlockFilter {
+            contract_addresses: self
+                .contract_addresses
+                .iter()
+                .filter(|(start_block, _address)| *start_block <= block)
+                .cloned()
+                .collect(),
+            trigger_every_block: self.trigger_every_block && self.trigger_every_block_from <= block,
+            trigger_every_block_from: self.trigger_every_block_from,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -573,6 +671,8 @@ pub struct BlockStreamMetrics {
     pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
     pub blocks_behind: Box<Gauge>,
     pub reverted_blocks: Box<Gauge>,
+    pub revert_batches: Box<Counter>,
+    pub reverts: Box<Counter>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -597,10 +697,26 @@ impl BlockStreamMetrics {
                 deployment_id.as_str(),
             )
             .expect("Failed to create `deployment_reverted_blocks` gauge");
+        let revert_batches = registry
+            .new_deployment_counter(
+                "deployment_revert_batches",
+                "Counts the batches of consecutive reverts for a subgraph deployment",
+                deployment_id.as_str(),
+            )
+            .expect("Failed to create `deployment_revert_batches` counter");
+        let reverts = registry
+            .new_deployment_counter(
+                "deployment_reverts",
+                "Counts the blocks that were reverted for a subgraph deployment",
+                deployment_id.as_str(),
+            )
+            .expect("Failed to create `deployment_reverts` counter");
         Self {
             ethrpc_metrics,
             blocks_behind,
             reverted_blocks,
+            revert_batches,
+            reverts,
             stopwatch,
         }
     }
@@ -627,6 +743,20 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         logger: &Logger,
     ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>;
 
+    /// The capabilities that the node showed when we connected to it. A
+    /// capability is only missing if the node rejected the requests that
+    /// need it.
+    fn detected_capabilities(&self) -> NodeCapabilities;
+
+    /// Whether the node pushes new blocks to us through an `eth_subscribe`
+    /// subscription, which needs a WebSocket or IPC connection.
+    fn supports_new_heads(&self) -> bool;
+
+    /// Subscribe to `newHeads` and stream the hashes of new chain heads as
+    /// the node announces them. The stream fails or ends when the
+    /// subscription is lost.
+    fn new_heads(&self, logger: &Logger) -> Box<dyn Stream<Item = H256, Error = Error> + Send>;
+
     /// Get the latest block, with only the header and transaction hashes.
     fn latest_block_header(
         &self,
@@ -842,6 +972,7 @@ pub async fn triggers_in_block(
                 log_filter,
                 call_filter,
                 block_filter,
+                BTreeSet::new(),
             )
             .await?;
             assert!(blocks.len() <= 1);
@@ -865,7 +996,8 @@ pub async fn triggers_in_block(
 
 /// Returns blocks with triggers, corresponding to the specified range and filters.
 /// If a block contains no triggers, there may be no corresponding item in the stream.
-/// However the `to` block will always be present, even if triggers are empty.
+/// However the `to` block and the blocks in `extra_blocks` that lie in the range will
+/// always be present, even if triggers are empty.
 ///
 /// Careful: don't use this function without considering race conditions.
 /// Chain reorgs could happen at any time, and could affect the answer received.
@@ -886,6 +1018,7 @@ pub async fn blocks_with_triggers(
     log_filter: EthereumLogFilter,
     call_filter: EthereumCallFilter,
     block_filter: EthereumBlockFilter,
+    extra_blocks: BTreeSet<u64>,
 ) -> Result<Vec<EthereumBlockWithTriggers>, Error> {
     // Each trigger filter needs to be queried for the same block range
     // and the blocks yielded need to be deduped. If any error occurs
@@ -939,26 +1072,35 @@ pub async fn blocks_with_triggers(
         ));
     }
 
+    // Look up the hashes of the blocks that need to be included even if
+    // they have no triggers
+    let mut required_blocks: BTreeSet<u64> = extra_blocks
+        .into_iter()
+        .filter(|number| *number >= from && *number <= to)
+        .collect();
+    required_blocks.insert(to);
+    let required_hashes = required_blocks.into_iter().map(|number| {
+        let logger2 = logger.cheap_clone();
+        let eth_clone = eth.cheap_clone();
+        adapter
+            .clone()
+            .block_hash_by_block_number(&logger, chain_store.clone(), number, true)
+            .then(move |hash| match hash {
+                Ok(n) => n.map(|hash| (number, hash)).ok_or_else(|| {
+                    warn!(logger2,
+                            "Ethereum endpoint is behind";
+                            "url" => eth_clone.url_hostname()
+                    );
+                    format_err!("Block {} not found in the chain", number)
+                }),
+                Err(e) => Err(e),
+            })
+    });
+
     let logger1 = logger.cheap_clone();
-    let logger2 = logger.cheap_clone();
-    let eth_clone = eth.cheap_clone();
-    let (triggers, to_hash) = trigger_futs
+    let (triggers, required_hashes) = trigger_futs
         .concat2()
-        .join(
-            adapter
-                .clone()
-                .block_hash_by_block_number(&logger, chain_store.clone(), to, true)
-                .then(move |to_hash| match to_hash {
-                    Ok(n) => n.ok_or_else(|| {
-                        warn!(logger2,
-                                "Ethereum endpoint is behind";
-                                "url" => eth_clone.url_hostname()
-                        );
-                        format_err!("Block {} not found in the chain", to)
-                    }),
-                    Err(e) => Err(e),
-                }),
-        )
+        .join(future::join_all(required_hashes))
         .compat()
         .await?;
 
@@ -972,9 +1114,11 @@ pub async fn blocks_with_triggers(
 
     debug!(logger, "Found {} relevant block(s)", block_hashes.len());
 
-    // Make sure `to` is included, even if empty.
-    block_hashes.insert(to_hash);
-    triggers_by_block.entry(to).or_insert(Vec::new());
+    // Make sure `to` and the extra blocks are included, even if empty.
+    for (number, hash) in required_hashes {
+        block_hashes.insert(hash);
+        triggers_by_block.entry(number).or_insert(Vec::new());
+    }
 
     let mut blocks = adapter
         .load_blocks(logger1, chain_store, block_hashes)
@@ -1020,7 +1164,7 @@ pub async fn blocks_with_triggers(
 
 #[cfg(test)]
 mod tests {
-    use super::EthereumCallFilter;
+    use super::{EthereumBlockFilter, EthereumCallFilter};
 
     use web3::types::Address;
 
@@ -1071,4 +1215,35 @@ mod tests {
             Some(&(1, HashSet::from_iter(vec![[1u8; 4]])))
         );
     }
+
+    #[test]
+    fn block_filter_active_at() {
+        let mut filter = EthereumBlockFilter {
+            contract_addresses: HashSet::from_iter(vec![
+                (10, Address::from_low_u64_be(0)),
+                (20, Address::from_low_u64_be(1)),
+            ]),
+            trigger_every_block: false,
+            trigger_every_block_from: 0,
+        };
+        filter.extend(EthereumBlockFilter {
+            contract_addresses: HashSet::new(),
+            trigger_every_block: true,
+            trigger_every_block_from: 15,
+        });
+
+        let active = filter.active_at(5);
+        assert!(active.contract_addresses.is_empty());
+        assert!(!active.trigger_every_block);
+
+        let active = filter.active_at(15);
+        assert_eq!(
+            active.contract_addresses,
+            HashSet::from_iter(vec![(10, Address::from_low_u64_be(0))])
+        );
+        assert!(active.trigger_every_block);
+
+        let active = filter.active_at(20);
+        assert_eq!(active.contract_addresses, filter.contract_addresses);
+    }
 }
@@ -13,10 +13,11 @@ pub use self::adapter::{
 };
 pub use self::listener::{ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream};
 pub use self::network::{EthereumNetworkAdapters, EthereumNetworks, NodeCapabilities};
-pub use self::stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent};
+pub use self::stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent, REORG_THRESHOLD};
 pub use self::types::{
-    BlockFinality, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
-    EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall,
-    EthereumCallData, EthereumEventData, EthereumTransactionData, EthereumTrigger,
-    LightEthereumBlock, LightEthereumBlockExt,
+    BlockFinality, CallFrame, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
+    EthereumBlockTransactionData, EthereumBlockTriggerType, EthereumBlockWithCalls,
+    EthereumBlockWithTriggers, EthereumCall, EthereumCallData, EthereumEventData,
+    EthereumTransactionData, EthereumTrigger, LightEthereumBlock, LightEthereumBlockExt,
+    TransactionTrace,
 };
@@ -40,6 +40,24 @@ impl PartialOrd for NodeCapabilities {
     }
 }
 
+impl NodeCapabilities {
+    /// Explain which features that need the capabilities in `self` can not
+    /// be served by nodes that only have the `available` capabilities
+    fn unsupported_features(&self, available: &NodeCapabilities) -> Vec<&'static str> {
+        let mut features = vec![];
+        if self.traces && !available.traces {
+            features.push(
+                "call handlers and block handlers with a call filter need \
+                 an Ethereum node that supports traces",
+            );
+        }
+        if self.archive && !available.archive {
+            features.push("`ethereum.call` needs an Ethereum archive node");
+        }
+        features
+    }
+}
+
 impl FromStr for NodeCapabilities {
     type Err = anyhow::Error;
 
@@ -86,6 +104,12 @@ pub struct EthereumNetworkAdapter {
     adapter: Arc<dyn EthereumAdapter>,
 }
 
+impl EthereumNetworkAdapter {
+    pub fn adapter(&self) -> &Arc<dyn EthereumAdapter> {
+        &self.adapter
+    }
+}
+
 #[derive(Clone)]
 pub struct EthereumNetworkAdapters {
     pub adapters: Vec<EthereumNetworkAdapter>,
@@ -181,6 +205,33 @@ impl EthereumNetworks {
         }
     }
 
+    /// The capabilities that at least one node for `network_name` has
+    pub fn capabilities(&self, network_name: &str) -> NodeCapabilities {
+        let adapters = self
+            .networks
+            .get(network_name)
+            .map(|adapters| adapters.adapters.as_slice())
+            .unwrap_or(&[]);
+        NodeCapabilities {
+            archive: adapters.iter().any(|adapter| adapter.capabilities.archive),
+            traces: adapters.iter().any(|adapter| adapter.capabilities.traces),
+        }
+    }
+
+    /// Explain why no node for `network_name` can serve a subgraph that
+    /// needs the `required` capabilities
+    pub fn explain_unsupported(&self, network_name: &str, required: &NodeCapabilities) -> String {
+        let features = required.unsupported_features(&self.capabilities(network_name));
+        if features.is_empty() {
+            format!(
+                "no single Ethereum node has all of the capabilities `{}`",
+                required
+            )
+        } else {
+            features.join("; ")
+        }
+    }
+
     pub fn adapter_with_capabilities(
         &self,
         network_name: String,
@@ -195,7 +246,33 @@ impl EthereumNetworks {
 
 #[cfg(test)]
 mod tests {
-    use super::NodeCapabilities;
+    use std::sync::Arc;
+
+    use super::{EthereumNetworks, NodeCapabilities};
+    use crate::components::ethereum::MockEthereumAdapter;
+
+    #[test]
+    fn explain_unsupported_capabilities() {
+        let mut networks = EthereumNetworks::new();
+        for capabilities in &["archive", "traces"] {
+            networks.insert(
+                "mainnet".to_owned(),
+                capabilities.parse().unwrap(),
+                Arc::new(MockEthereumAdapter::new()),
+            );
+        }
+
+        let archive_traces: NodeCapabilities = "archive,traces".parse().unwrap();
+        assert_eq!(
+            "no single Ethereum node has all of the capabilities `archive, trace`",
+            networks.explain_unsupported("mainnet", &archive_traces)
+        );
+        assert_eq!(
+            "call handlers and block handlers with a call filter need an Ethereum node \
+             that supports traces; `ethereum.call` needs an Ethereum archive node",
+            networks.explain_unsupported("goerli", &archive_traces)
+        );
+    }
 
     #[test]
     fn ethereum_capabilities_comparison() {
@@ -1,12 +1,26 @@
 use failure::Error;
 use futures::Stream;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
 
 use crate::prelude::*;
 
+lazy_static! {
+    /// How many blocks behind the chain head a block has to be before we
+    /// consider it final. Defaults to 50 blocks
+    pub static ref REORG_THRESHOLD: u64 = std::env::var("ETHEREUM_REORG_THRESHOLD")
+        .ok()
+        .map(|s| s
+            .parse::<u64>()
+            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
+        .unwrap_or(50);
+}
+
 pub enum BlockStreamEvent {
     Block(EthereumBlockWithTriggers),
 
-    /// Signals that a revert happened and was processed.
+    /// Signals that one or more blocks were reverted. Consecutive reverts
+    /// are processed together and signalled only once.
     Revert,
 }
 
@@ -25,6 +39,7 @@ pub trait BlockStreamBuilder: Clone + Send + Sync + 'static {
         call_filter: EthereumCallFilter,
         block_filter: EthereumBlockFilter,
         include_calls_in_blocks: bool,
+        source_deployments: BTreeMap<SubgraphDeploymentId, Vec<String>>,
         ethrpc_metrics: Arc<BlockStreamMetrics>,
     ) -> Self::Stream;
 }
@@ -6,7 +6,7 @@ use std::cmp::Ordering;
 use std::fmt;
 use web3::types::*;
 
-use crate::prelude::{EntityKey, SubgraphDeploymentId, ToEntityKey};
+use crate::prelude::{format_err, EntityKey, Error, SubgraphDeploymentId, ToEntityKey};
 
 pub type LightEthereumBlock = Block<Transaction>;
 
@@ -114,7 +114,7 @@ pub struct EthereumBlock {
     pub transaction_receipts: Vec<TransactionReceipt>,
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EthereumCall {
     pub from: Address,
     pub to: Address,
@@ -164,9 +164,106 @@ impl EthereumCall {
             transaction_index,
         })
     }
+
+    /// Turn the result of geth's `debug_traceBlockByHash` with the
+    /// `callTracer` into calls. The traces must be for `block`, in the
+    /// order of its transactions. Like with `try_from_trace`, failed calls
+    /// and calls without a function selector are left out.
+    pub fn from_transaction_traces(
+        block: &Block<H256>,
+        traces: Vec<TransactionTrace>,
+    ) -> Result<Vec<Self>, Error> {
+        if traces.len() != block.transactions.len() {
+            return Err(format_err!(
+                "block {:?} has {} transactions but {} traces",
+                block.hash,
+                block.transactions.len(),
+                traces.len()
+            ));
+        }
+
+        let block_number = block.number.map(|number| number.as_u64()).unwrap_or(0);
+        let block_hash = block.hash.unwrap_or_default();
+        let mut calls = Vec::new();
+        for (index, (trace, tx_hash)) in traces.into_iter().zip(&block.transactions).enumerate() {
+            let frame = match (trace.result, trace.error) {
+                (Some(frame), _) => frame,
+                (None, error) => {
+                    return Err(format_err!(
+                        "tracing transaction {:?} failed: {}",
+                        tx_hash,
+                        error.unwrap_or_default()
+                    ))
+                }
+            };
+            let mut frames = vec![frame];
+            // Visit calls depth-first in the order they were made, which is
+            // the order in which `trace_filter` returns them
+            while let Some(frame) = frames.pop() {
+                frames.extend(frame.calls.iter().rev().cloned());
+                if frame.error.is_some() || !frame.is_call() {
+                    continue;
+                }
+                let input = frame.input.unwrap_or_default();
+                let to = match frame.to {
+                    Some(to) if input.0.len() >= 4 => to,
+                    _ => continue,
+                };
+                calls.push(EthereumCall {
+                    from: frame.from,
+                    to,
+                    value: frame.value.unwrap_or_default(),
+                    gas_used: frame.gas_used.unwrap_or_default(),
+                    input,
+                    output: frame.output.unwrap_or_default(),
+                    block_number,
+                    block_hash,
+                    transaction_hash: Some(*tx_hash),
+                    transaction_index: index as u64,
+                });
+            }
+        }
+        Ok(calls)
+    }
 }
 
-#[derive(Clone, Debug)]
+/// The trace of one transaction as returned by geth's
+/// `debug_traceBlockByHash` with the `callTracer`
+#[derive(Clone, Debug, Deserialize)]
+pub struct TransactionTrace {
+    pub result: Option<CallFrame>,
+    pub error: Option<String>,
+}
+
+/// A call made by a transaction, together with the calls it made in turn
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    #[serde(rename = "type")]
+    pub call_type: String,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub value: Option<U256>,
+    pub gas_used: Option<U256>,
+    pub input: Option<Bytes>,
+    pub output: Option<Bytes>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub calls: Vec<CallFrame>,
+}
+
+impl CallFrame {
+    /// Whether this frame is for one of the `CALL` opcodes rather than for
+    /// creating or destroying a contract
+    fn is_call(&self) -> bool {
+        match self.call_type.as_str() {
+            "CALL" | "CALLCODE" | "DELEGATECALL" | "STATICCALL" => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EthereumTrigger {
     Block(EthereumBlockPointer, EthereumBlockTriggerType),
     Call(EthereumCall),
@@ -193,7 +290,7 @@ impl PartialEq for EthereumTrigger {
 
 impl Eq for EthereumTrigger {}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EthereumBlockTriggerType {
     Every,
     WithCallTo(Address),
@@ -331,6 +428,61 @@ impl From<&'_ Transaction> for EthereumTransactionData {
     }
 }
 
+/// A transaction in the current block, as seen by handlers that look at
+/// all transactions of a block. Only the function selector, i.e., the
+/// first four bytes, of the transaction input is included
+#[derive(Clone, Debug)]
+pub struct EthereumBlockTransactionData {
+    pub hash: H256,
+    pub index: U128,
+    pub from: H160,
+    pub to: Option<H160>,
+    pub value: U256,
+    pub selector: Bytes,
+}
+
+impl EthereumBlockTransactionData {
+    /// The transactions root of a block without transactions, i.e., the
+    /// root of an empty trie
+    pub const EMPTY_TRANSACTIONS_ROOT: [u8; 32] = [
+        0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8,
+        0x6e, 0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63,
+        0xb4, 0x21,
+    ];
+
+    /// Convert all transactions of `block`. Transactions that do not have
+    /// an index, like pending ones, use their position in the block instead
+    pub fn from_block(block: &LightEthereumBlock) -> Vec<Self> {
+        block
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(position, tx)| {
+                let index = tx
+                    .transaction_index
+                    .map(|index| index.as_u64())
+                    .unwrap_or(position as u64);
+                let selector = tx.input.0.iter().take(4).cloned().collect::<Vec<u8>>();
+                EthereumBlockTransactionData {
+                    hash: tx.hash,
+                    index: index.into(),
+                    from: tx.from,
+                    to: tx.to,
+                    value: tx.value,
+                    selector: Bytes(selector),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether `block` only lacks transactions because they were not
+    /// loaded with it, and not because it has none
+    pub fn missing_from(block: &LightEthereumBlock) -> bool {
+        block.transactions.is_empty()
+            && block.transactions_root.as_bytes() != &Self::EMPTY_TRANSACTIONS_ROOT[..]
+    }
+}
+
 /// An Ethereum event logged from a specific contract address and block.
 #[derive(Debug)]
 pub struct EthereumEventData {
@@ -405,7 +557,7 @@ impl Clone for EthereumCallData {
 /// A block hash and block number from a specific Ethereum block.
 ///
 /// Maximum block number supported: 2^63 - 1
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct EthereumBlockPointer {
     pub hash: H256,
     pub number: u64,
@@ -536,9 +688,105 @@ impl ToEntityKey for EthereumBlockPointer {
 
 #[cfg(test)]
 mod test {
-    use super::{EthereumBlockPointer, EthereumBlockTriggerType, EthereumCall, EthereumTrigger};
+    use super::{
+        EthereumBlockPointer, EthereumBlockTransactionData, EthereumBlockTriggerType, EthereumCall,
+        EthereumTrigger, TransactionTrace,
+    };
     use web3::types::*;
 
+    #[test]
+    fn calls_from_transaction_traces() {
+        let mut block = Block::<H256>::default();
+        block.hash = Some(H256::from_low_u64_be(7));
+        block.number = Some(12u64.into());
+        block.transactions = vec![H256::from_low_u64_be(1), H256::from_low_u64_be(2)];
+
+        let traces: Vec<TransactionTrace> = serde_json::from_value(serde_json::json!([
+            {
+                "result": {
+                    "type": "CALL",
+                    "from": "0x0000000000000000000000000000000000000001",
+                    "to": "0x0000000000000000000000000000000000000002",
+                    "value": "0x0",
+                    "gasUsed": "0x10",
+                    "input": "0xa9059cbb",
+                    "output": "0x",
+                    "calls": [
+                        {
+                            "type": "STATICCALL",
+                            "from": "0x0000000000000000000000000000000000000002",
+                            "to": "0x0000000000000000000000000000000000000003",
+                            "gasUsed": "0x5",
+                            "input": "0x70a08231",
+                            "output": "0x01"
+                        },
+                        {
+                            "type": "CALL",
+                            "from": "0x0000000000000000000000000000000000000002",
+                            "to": "0x0000000000000000000000000000000000000004",
+                            "gasUsed": "0x5",
+                            "input": "0x70a08231",
+                            "error": "execution reverted"
+                        }
+                    ]
+                }
+            },
+            {
+                "result": {
+                    "type": "CALL",
+                    "from": "0x0000000000000000000000000000000000000001",
+                    "to": "0x0000000000000000000000000000000000000005",
+                    "value": "0x1",
+                    "gasUsed": "0x0",
+                    "input": "0x"
+                }
+            }
+        ]))
+        .unwrap();
+
+        let calls = EthereumCall::from_transaction_traces(&block, traces).unwrap();
+        let callees: Vec<_> = calls.iter().map(|call| call.to).collect();
+        assert_eq!(
+            vec![H160::from_low_u64_be(2), H160::from_low_u64_be(3)],
+            callees
+        );
+        assert!(calls.iter().all(|call| call.block_number == 12
+            && call.block_hash == H256::from_low_u64_be(7)
+            && call.transaction_hash == Some(H256::from_low_u64_be(1))
+            && call.transaction_index == 0));
+        assert_eq!(U256::from(5), calls[1].gas_used);
+        assert_eq!(Bytes(vec![1]), calls[1].output);
+
+        // A trace for every transaction is required
+        let traces = vec![];
+        assert!(EthereumCall::from_transaction_traces(&block, traces).is_err());
+    }
+
+    #[test]
+    fn block_transaction_selector() {
+        let mut tx = Transaction::default();
+        tx.transaction_index = Some(3u64.into());
+        tx.input = Bytes(vec![0xa9, 0x05, 0x9c, 0xbb, 0x00, 0x01]);
+        // Plain value transfers have no selector, and pending transactions
+        // have no index
+        let mut transfer = Transaction::default();
+        transfer.transaction_index = None;
+
+        let mut block = Block::<Transaction>::default();
+        block.transactions = vec![tx, transfer];
+        let data = EthereumBlockTransactionData::from_block(&block);
+        assert_eq!(vec![0xa9, 0x05, 0x9c, 0xbb], data[0].selector.0);
+        assert_eq!(U128::from(3u64), data[0].index);
+        assert!(data[1].selector.0.is_empty());
+        assert_eq!(U128::from(1u64), data[1].index);
+        assert!(!EthereumBlockTransactionData::missing_from(&block));
+
+        block.transactions = vec![];
+        assert!(EthereumBlockTransactionData::missing_from(&block));
+        block.transactions_root = H256::from(EthereumBlockTransactionData::EMPTY_TRANSACTIONS_ROOT);
+        assert!(!EthereumBlockTransactionData::missing_from(&block));
+    }
+
     #[test]
     fn test_trigger_ordering() {
         let block1 = EthereumTrigger::Block(
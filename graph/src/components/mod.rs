@@ -45,6 +45,8 @@ pub mod arweave;
 
 pub mod three_box;
 
+pub mod ens;
+
 /// Components dealing with processing GraphQL.
 pub mod graphql;
 
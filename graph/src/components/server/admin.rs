@@ -2,7 +2,6 @@ use std::io;
 use std::sync::Arc;
 
 use crate::prelude::Logger;
-use crate::prelude::NodeId;
 
 /// Common trait for JSON-RPC admin server implementations.
 pub trait JsonRpcServer<P> {
@@ -13,7 +12,6 @@ pub trait JsonRpcServer<P> {
         http_port: u16,
         ws_port: u16,
         provider: Arc<P>,
-        node_id: NodeId,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
 }
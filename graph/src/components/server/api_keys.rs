@@ -0,0 +1,219 @@
+//! API keys for queries. When `GRAPH_REQUIRE_API_KEYS` is set, every
+//! query and every subscription has to carry an API key in an
+//! `Authorization: Bearer <secret>` header, the key has to be allowed to
+//! query the deployment, and the key must not have exceeded its rate limit.
+//! The number of queries made with each key is accumulated in memory and
+//! periodically added to the usage stored in the database, and once more
+//! when the server stops. Keys are managed with `graphman api-key`
+use http::StatusCode;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::components::store::{ApiKey, ApiKeyUsage, Store, StoreError};
+use crate::data::subgraph::SubgraphDeploymentId;
+
+lazy_static! {
+    pub static ref REQUIRE_API_KEYS: bool = std::env::var("GRAPH_REQUIRE_API_KEYS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    /// How long we remember what we found when we looked up a key, in
+    /// seconds. Revoking a key takes up to this long to take effect
+    static ref API_KEY_CACHE_TTL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_API_KEY_CACHE_TTL")
+            .unwrap_or("60".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_API_KEY_CACHE_TTL")
+    );
+}
+
+/// How often the usage of API keys is written to the database
+pub const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most keys we keep in the cache. Only keys that exist are cached, so
+/// that clients sending made-up keys can not fill it; when it is full, we
+/// drop expired keys, and then the key we looked up longest ago
+const MAX_CACHED_KEYS: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub enum ApiKeyError {
+    Missing,
+    Invalid,
+    NotAllowed(SubgraphDeploymentId),
+    RateLimited(u32),
+    Store(String),
+}
+
+impl ApiKeyError {
+    /// The HTTP status with which a request that failed the check is
+    /// rejected
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiKeyError::Missing | ApiKeyError::Invalid => StatusCode::UNAUTHORIZED,
+            ApiKeyError::NotAllowed(_) => StatusCode::FORBIDDEN,
+            ApiKeyError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiKeyError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiKeyError::Missing => write!(
+                f,
+                "queries require an API key in an `Authorization: Bearer` header"
+            ),
+            ApiKeyError::Invalid => write!(f, "invalid API key"),
+            ApiKeyError::NotAllowed(id) => {
+                write!(f, "the API key can not be used to query `{}`", id)
+            }
+            ApiKeyError::RateLimited(limit) => write!(
+                f,
+                "the API key is limited to {} queries per minute; try again later",
+                limit
+            ),
+            ApiKeyError::Store(e) => write!(f, "failed to look up API key: {}", e),
+        }
+    }
+}
+
+/// The queries a key made during one minute
+struct Window {
+    minute: u64,
+    queries: u32,
+}
+
+#[derive(Default)]
+pub struct ApiKeys {
+    /// The keys we looked up by their secret, and when we did that
+    cache: Mutex<HashMap<String, (Instant, ApiKey)>>,
+    /// The queries each key made in the current minute
+    windows: Mutex<HashMap<String, Window>>,
+    /// The queries each key made against each deployment that have not
+    /// been written to the database yet
+    usage: Mutex<HashMap<(String, SubgraphDeploymentId), u64>>,
+}
+
+impl ApiKeys {
+    /// Check that the client that sent the `authorization` header may query
+    /// `deployment` right now, and count the query towards its usage
+    pub async fn check<S: Store>(
+        &self,
+        store: Arc<S>,
+        authorization: Option<&str>,
+        deployment: &SubgraphDeploymentId,
+    ) -> Result<(), ApiKeyError> {
+        let secret = authorization
+            .and_then(|value| value.trim().strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(ApiKeyError::Missing)?;
+
+        // A revoked key is as good as one that never existed
+        let key = self
+            .lookup(store, secret)
+            .await?
+            .filter(|key| !key.revoked)
+            .ok_or(ApiKeyError::Invalid)?;
+        if !key.allows(deployment) {
+            return Err(ApiKeyError::NotAllowed(deployment.clone()));
+        }
+
+        if let Some(limit) = key.queries_per_minute {
+            let minute = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                / 60;
+            let mut windows = self.windows.lock().unwrap();
+            let window = windows
+                .entry(key.id.clone())
+                .or_insert(Window { minute, queries: 0 });
+            if window.minute != minute {
+                window.minute = minute;
+                window.queries = 0;
+            }
+            if window.queries >= limit {
+                return Err(ApiKeyError::RateLimited(limit));
+            }
+            window.queries += 1;
+        }
+
+        *self
+            .usage
+            .lock()
+            .unwrap()
+            .entry((key.id, deployment.clone()))
+            .or_default() += 1;
+        Ok(())
+    }
+
+    async fn lookup<S: Store>(
+        &self,
+        store: Arc<S>,
+        secret: &str,
+    ) -> Result<Option<ApiKey>, ApiKeyError> {
+        if let Some((looked_up, key)) = self.cache.lock().unwrap().get(secret) {
+            if looked_up.elapsed() < *API_KEY_CACHE_TTL {
+                return Ok(Some(key.clone()));
+            }
+        }
+
+        let owned_secret = secret.to_owned();
+        let key = tokio::task::spawn_blocking(move || store.api_key(&owned_secret))
+            .await
+            .unwrap() // Propagate panics.
+            .map_err(|e| ApiKeyError::Store(e.to_string()))?;
+
+        let mut cache = self.cache.lock().unwrap();
+        match &key {
+            Some(key) => {
+                if cache.len() >= MAX_CACHED_KEYS && !cache.contains_key(secret) {
+                    cache.retain(|_, (looked_up, _)| looked_up.elapsed() < *API_KEY_CACHE_TTL);
+                }
+                if cache.len() >= MAX_CACHED_KEYS && !cache.contains_key(secret) {
+                    let oldest = cache
+                        .iter()
+                        .min_by_key(|(_, (looked_up, _))| *looked_up)
+                        .map(|(secret, _)| secret.clone());
+                    if let Some(oldest) = oldest {
+                        cache.remove(&oldest);
+                    }
+                }
+                cache.insert(secret.to_owned(), (Instant::now(), key.clone()));
+            }
+            None => {
+                // The key may have been deleted since we cached it
+                cache.remove(secret);
+            }
+        }
+        Ok(key)
+    }
+
+    /// Write the usage that was accumulated since the last call to the
+    /// database. If that fails, the usage is kept for the next attempt
+    pub fn flush(&self, store: &impl Store) -> Result<(), StoreError> {
+        let usage = std::mem::take(&mut *self.usage.lock().unwrap());
+        if usage.is_empty() {
+            return Ok(());
+        }
+
+        let records = usage
+            .iter()
+            .map(|((api_key, deployment), queries)| ApiKeyUsage {
+                api_key: api_key.clone(),
+                deployment: deployment.clone(),
+                queries: *queries,
+            })
+            .collect();
+        store.record_api_key_usage(records).map_err(|e| {
+            let mut pending = self.usage.lock().unwrap();
+            for (key, queries) in usage {
+                *pending.entry(key).or_default() += queries;
+            }
+            e
+        })
+    }
+}
@@ -0,0 +1,190 @@
+//! Access control for the JSON-RPC admin server and the index node server.
+//!
+//! Clients authenticate with static bearer tokens. When
+//! `GRAPH_AUTH_TOKENS_FILE` is set, it must name a file with one
+//! `<role> <token>` pair per line; empty lines and lines starting with `#`
+//! are ignored. Requests have to carry one of these tokens in an
+//! `Authorization: Bearer <token>` header, and the role of the token
+//! decides what they may do. Without that file, every request is treated
+//! as coming from an admin, which is how these servers always behaved.
+//!
+//! Only the Keccak-256 hashes of the tokens are kept in memory, and the
+//! hash of a token a client sends is compared with all of them in constant
+//! time so that response times do not reveal anything about valid tokens.
+use lazy_static::lazy_static;
+use std::fmt;
+use std::str::FromStr;
+use tiny_keccak::keccak256;
+
+lazy_static! {
+    pub static ref AUTH_TOKENS: AuthTokens = std::env::var("GRAPH_AUTH_TOKENS_FILE")
+        .ok()
+        .map(|path| {
+            let contents = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("can not read GRAPH_AUTH_TOKENS_FILE {}: {}", path, e));
+            contents
+                .parse()
+                .unwrap_or_else(|e| panic!("invalid GRAPH_AUTH_TOKENS_FILE {}: {}", path, e))
+        })
+        .unwrap_or_default();
+}
+
+/// What a client is allowed to do. Roles are ordered, and each role can do
+/// everything the roles before it can do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Look at indexing statuses and the list of deployments
+    Read,
+    /// Create, deploy, remove and (re)assign subgraphs
+    Admin,
+}
+
+impl Role {
+    pub fn allows(&self, required: Role) -> bool {
+        *self >= required
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Role::Read),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!(
+                "unknown role `{}`, must be one of `read` or `admin`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::Read => write!(f, "read"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// The tokens a node accepts, and the role each of them grants
+#[derive(Clone, Debug, Default)]
+pub struct AuthTokens {
+    /// The hashes of the tokens
+    tokens: Vec<([u8; 32], Role)>,
+}
+
+impl AuthTokens {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// The role of a client that sent the given `Authorization` header, or
+    /// `None` if the client is not allowed to do anything
+    pub fn role(&self, authorization: Option<&str>) -> Option<Role> {
+        if !self.is_enabled() {
+            return Some(Role::Admin);
+        }
+        let token = authorization?.trim().strip_prefix("Bearer ")?.trim();
+        let hash = keccak256(token.as_bytes());
+        // Look at every token, even after finding a match, so that the time
+        // this takes does not depend on which token matched
+        self.tokens.iter().fold(None, |found, (candidate, role)| {
+            if constant_time_eq(&hash, candidate) {
+                Some(*role)
+            } else {
+                found
+            }
+        })
+    }
+
+    /// Check that a client that sent the given `Authorization` header may
+    /// perform an operation that requires `required`
+    pub fn authorize(&self, authorization: Option<&str>, required: Role) -> Result<(), String> {
+        match self.role(authorization) {
+            Some(role) if role.allows(required) => Ok(()),
+            Some(role) => Err(format!(
+                "the `{}` role is not allowed to perform this operation",
+                role
+            )),
+            None => Err("missing or invalid bearer token".to_owned()),
+        }
+    }
+}
+
+impl FromStr for AuthTokens {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens: Vec<([u8; 32], Role)> = Vec::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (role, token) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(role), Some(token), None) => (role, token),
+                _ => return Err(format!("line {}: expected `<role> <token>`", number + 1)),
+            };
+            let role = role
+                .parse::<Role>()
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+            let hash = keccak256(token.as_bytes());
+            if tokens.iter().any(|(other, _)| *other == hash) {
+                return Err(format!("line {}: duplicate token", number + 1));
+            }
+            tokens.push((hash, role));
+        }
+        Ok(AuthTokens { tokens })
+    }
+}
+
+/// Compare `a` and `b` in time that does not depend on where they differ
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+#[test]
+fn roles_of_tokens() {
+    let open = AuthTokens::default();
+    assert_eq!(Some(Role::Admin), open.role(None));
+
+    let tokens: AuthTokens = "# operators\nadmin s3cr3t\n\nread  dashboard\n"
+        .parse()
+        .unwrap();
+    assert_eq!(Some(Role::Admin), tokens.role(Some("Bearer s3cr3t")));
+    assert_eq!(Some(Role::Read), tokens.role(Some("Bearer dashboard")));
+    assert_eq!(None, tokens.role(Some("Bearer guess")));
+    assert_eq!(None, tokens.role(Some("s3cr3t")));
+    assert_eq!(None, tokens.role(None));
+
+    assert!(tokens.authorize(Some("Bearer s3cr3t"), Role::Read).is_ok());
+    assert!(tokens
+        .authorize(Some("Bearer dashboard"), Role::Admin)
+        .is_err());
+
+    assert!("owner s3cr3t".parse::<AuthTokens>().is_err());
+    assert!("admin".parse::<AuthTokens>().is_err());
+    assert!("admin a\nread a".parse::<AuthTokens>().is_err());
+
+    // Only hashes of the tokens are kept
+    assert!(!format!("{:?}", tokens).contains("s3cr3t"));
+}
+
+#[test]
+fn compares_in_constant_time() {
+    let a = keccak256(b"s3cr3t");
+    let mut b = a;
+    assert!(constant_time_eq(&a, &b));
+    b[0] ^= 1;
+    assert!(!constant_time_eq(&a, &b));
+    b = a;
+    b[31] ^= 0x80;
+    assert!(!constant_time_eq(&a, &b));
+}
@@ -0,0 +1,197 @@
+//! The health of the subsystems of a node, which the index node server
+//! reports under `/healthz` and `/readyz`.
+//!
+//! Subsystems either register a `HealthCheck` that is run every time the
+//! health of the node is requested, like the connection pools, or report
+//! changes to their health as they happen, like the block ingestor after
+//! each attempt to get the latest block from its provider. Subsystems only
+//! report their health if the node uses them, e.g., block ingestion only on
+//! nodes that ingest blocks, so that the node is ready when all of them are
+//! healthy.
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    /// How long a health check waits for a database connection
+    pub static ref HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(
+        std::env::var("GRAPH_HEALTH_CHECK_TIMEOUT")
+            .unwrap_or("5".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_HEALTH_CHECK_TIMEOUT")
+    );
+
+    /// How often checks that need the database refresh their results
+    pub static ref HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_HEALTH_CHECK_INTERVAL")
+            .unwrap_or("10".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_HEALTH_CHECK_INTERVAL")
+    );
+
+    /// How old the head block of a chain may be before we consider block
+    /// ingestion for that chain stuck
+    pub static ref CHAIN_HEAD_MAX_AGE: Duration = Duration::from_secs(
+        std::env::var("GRAPH_HEALTH_CHAIN_HEAD_MAX_AGE")
+            .unwrap_or("600".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_HEALTH_CHAIN_HEAD_MAX_AGE")
+    );
+
+    static ref CHECKS: Mutex<Vec<Arc<dyn HealthCheck>>> = Mutex::new(Vec::new());
+    static ref REPORTS: Mutex<BTreeMap<(Subsystem, String), SubsystemHealth>> =
+        Mutex::new(BTreeMap::new());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Subsystem {
+    /// A connection pool for the main database or one of its replicas
+    Store,
+    /// The Ethereum nodes of a network
+    ChainProvider,
+    /// Ingestion of new blocks for a network
+    BlockIngestor,
+    /// Starting and running the subgraphs assigned to the node
+    InstanceManager,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubsystemHealth {
+    pub subsystem: Subsystem,
+    /// Which part of the subsystem this is about, e.g., the name of a
+    /// network or a connection pool
+    pub name: String,
+    pub healthy: bool,
+    /// Why the subsystem is unhealthy, or details about its state
+    pub message: Option<String>,
+}
+
+impl SubsystemHealth {
+    pub fn healthy(subsystem: Subsystem, name: impl Into<String>) -> Self {
+        SubsystemHealth {
+            subsystem,
+            name: name.into(),
+            healthy: true,
+            message: None,
+        }
+    }
+
+    pub fn unhealthy(subsystem: Subsystem, name: impl Into<String>, message: String) -> Self {
+        SubsystemHealth {
+            subsystem,
+            name: name.into(),
+            healthy: false,
+            message: Some(message),
+        }
+    }
+
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = Some(message);
+        self
+    }
+}
+
+/// A check that determines the health of a subsystem on demand. Anybody
+/// can request the health of the node, and checks must not block or put
+/// load on the database; checks that need the database keep results that
+/// they refresh every `HEALTH_CHECK_INTERVAL`
+pub trait HealthCheck: Send + Sync {
+    fn check(&self) -> Vec<SubsystemHealth>;
+}
+
+/// The health of a node and all its subsystems
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeHealth {
+    /// Whether all subsystems are healthy
+    pub ready: bool,
+    pub subsystems: Vec<SubsystemHealth>,
+}
+
+/// Run `check` every time the health of the node is requested
+pub fn register(check: Arc<dyn HealthCheck>) {
+    CHECKS.lock().unwrap().push(check);
+}
+
+/// Record the health of the part `name` of `subsystem`, replacing what was
+/// reported for it before
+pub fn report(subsystem: Subsystem, name: &str, status: Result<(), String>) {
+    let health = match status {
+        Ok(()) => SubsystemHealth::healthy(subsystem, name),
+        Err(message) => SubsystemHealth::unhealthy(subsystem, name, message),
+    };
+    REPORTS
+        .lock()
+        .unwrap()
+        .insert((subsystem, name.to_owned()), health);
+}
+
+/// Forget what was reported for the part `name` of `subsystem`, e.g.,
+/// because the node stopped using it
+pub fn clear(subsystem: Subsystem, name: &str) {
+    REPORTS
+        .lock()
+        .unwrap()
+        .remove(&(subsystem, name.to_owned()));
+}
+
+/// Run all registered checks and combine them with what subsystems have
+/// reported
+pub fn node_health() -> NodeHealth {
+    // Do not hold the lock while the checks run
+    let checks = CHECKS.lock().unwrap().clone();
+    let mut subsystems: Vec<_> = checks.iter().flat_map(|check| check.check()).collect();
+    subsystems.extend(REPORTS.lock().unwrap().values().cloned());
+    subsystems.sort_by(|a, b| (a.subsystem, &a.name).cmp(&(b.subsystem, &b.name)));
+
+    let ready = subsystems.iter().all(|health| health.healthy);
+    NodeHealth { ready, subsystems }
+}
+
+#[test]
+fn combines_checks_and_reports() {
+    struct Pools;
+
+    impl HealthCheck for Pools {
+        fn check(&self) -> Vec<SubsystemHealth> {
+            vec![SubsystemHealth::healthy(Subsystem::Store, "main")]
+        }
+    }
+
+    register(Arc::new(Pools));
+    report(Subsystem::InstanceManager, "QmSubgraph", Ok(()));
+    report(Subsystem::BlockIngestor, "mainnet", Err("stuck".to_owned()));
+
+    let health = node_health();
+    assert!(!health.ready);
+    let names: Vec<_> = health
+        .subsystems
+        .iter()
+        .map(|health| (health.subsystem, health.name.as_str(), health.healthy))
+        .collect();
+    assert_eq!(
+        vec![
+            (Subsystem::Store, "main", true),
+            (Subsystem::BlockIngestor, "mainnet", false),
+            (Subsystem::InstanceManager, "QmSubgraph", true),
+        ],
+        names
+    );
+
+    // Later reports replace earlier ones, and cleared parts are gone
+    report(Subsystem::BlockIngestor, "mainnet", Ok(()));
+    report(
+        Subsystem::InstanceManager,
+        "QmSubgraph",
+        Err("failed".to_owned()),
+    );
+    assert!(!node_health().ready);
+    clear(Subsystem::InstanceManager, "QmSubgraph");
+    let health = node_health();
+    assert!(health.ready);
+    assert_eq!(2, health.subsystems.len());
+}
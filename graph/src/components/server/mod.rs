@@ -12,3 +12,12 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Access control for the admin and index node servers.
+pub mod auth;
+
+/// API keys for the query and subscription servers.
+pub mod api_keys;
+
+/// Health of the subsystems of a node for liveness and readiness probes.
+pub mod health;
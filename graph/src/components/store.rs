@@ -6,7 +6,7 @@ use mockall::predicate::*;
 use mockall::*;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fmt;
 use std::str::FromStr;
@@ -29,6 +29,14 @@ lazy_static! {
             )))
             .map(Duration::from_millis)
             .unwrap_or_else(|| Duration::from_millis(1000));
+    pub static ref LIVE_QUERY_DEBOUNCE_INTERVAL: Duration =
+        env::var("GRAPH_LIVE_QUERY_DEBOUNCE_INTERVAL")
+            .ok()
+            .map(|s| u64::from_str(&s).unwrap_or_else(|_| panic!(
+                "failed to parse env var GRAPH_LIVE_QUERY_DEBOUNCE_INTERVAL"
+            )))
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| Duration::from_millis(100));
 }
 
 // Note: Do not modify fields without making a backward compatible change to
@@ -76,6 +84,21 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    Child(ChildFilter),
+}
+
+/// A filter on the entities that an entity refers to through the field
+/// `attr`. An entity matches if at least one of the entities of type
+/// `entity_type` that it refers to matches `filter`. When `derived` is
+/// `true`, the reference is stored on the child: `attr` is then the field
+/// of `entity_type` that is named in the `@derivedFrom` directive and that
+/// points back to the parent
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildFilter {
+    pub attr: Attribute,
+    pub entity_type: String,
+    pub filter: Box<EntityFilter>,
+    pub derived: bool,
 }
 
 // Define some convenience methods
@@ -283,6 +306,13 @@ pub struct EntityQuery {
 
     pub query_id: Option<String>,
 
+    /// The database aborts the query if it is still running at this time
+    pub deadline: Option<Instant>,
+
+    /// Canceling this handle aborts the query in the database, e.g. when
+    /// the client that is waiting for its result goes away
+    pub cancel: Option<CancelHandle>,
+
     _force_use_of_new: (),
 }
 
@@ -301,6 +331,8 @@ impl EntityQuery {
             range: EntityRange::first(100),
             logger: None,
             query_id: None,
+            deadline: None,
+            cancel: None,
             _force_use_of_new: (),
         }
     }
@@ -651,6 +683,69 @@ where
         }));
         StoreEventStream::new(source)
     }
+
+    /// Combine bursts of events into one event. The first event that
+    /// arrives starts a timer; all events that arrive before `interval`
+    /// has passed are merged into it, and the combined event is reported
+    /// once the timer fires. Unlike `throttle_while_syncing`, this applies
+    /// regardless of whether the deployment is synced, and a quiet stream
+    /// is not delayed by a timer that is already running.
+    pub fn debounce(self, logger: &Logger, interval: Duration) -> StoreEventStreamBox {
+        let mut pending_event: Option<StoreEvent> = None;
+        let mut source = self.source.fuse();
+        let mut had_err = false;
+        let mut delay = None;
+        let logger = logger.clone();
+
+        let source = Box::new(poll_fn(move || -> Poll<Option<Arc<StoreEvent>>, ()> {
+            if had_err {
+                had_err = false;
+                return Err(());
+            }
+
+            loop {
+                match source.poll() {
+                    Ok(Async::NotReady) => break,
+                    Ok(Async::Ready(None)) => {
+                        let event = pending_event.take().map(|event| Arc::new(event));
+                        return Ok(Async::Ready(event));
+                    }
+                    Ok(Async::Ready(Some(event))) => {
+                        if pending_event.is_none() {
+                            delay = Some(tokio::time::delay_for(interval).unit_error().compat());
+                        }
+                        StoreEvent::accumulate(&logger, &mut pending_event, (*event).clone());
+                    }
+                    Err(()) => {
+                        if pending_event.is_some() {
+                            had_err = true;
+                            delay = None;
+                            let event = pending_event.take().map(|event| Arc::new(event));
+                            return Ok(Async::Ready(event));
+                        } else {
+                            return Err(());
+                        }
+                    }
+                }
+            }
+
+            let fired = match delay.as_mut() {
+                None => false,
+                Some(delay) => match futures::future::Future::poll(delay) {
+                    Ok(Async::NotReady) => false,
+                    // Timer errors are harmless; treat them like the timer firing
+                    Ok(Async::Ready(())) | Err(_) => true,
+                },
+            };
+            if fired {
+                delay = None;
+                let event = pending_event.take().map(|event| Arc::new(event));
+                return Ok(Async::Ready(event));
+            }
+            Ok(Async::NotReady)
+        }));
+        StoreEventStream::new(source)
+    }
 }
 
 /// An entity operation that can be transacted into the store.
@@ -701,6 +796,62 @@ impl MetadataOperation {
     }
 }
 
+/// The number of rows in the table for one entity type of a deployment
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntityTypeCount {
+    pub entity_type: String,
+    /// The number of entities as of the latest block
+    pub current: u64,
+    /// The number of versions of these entities across all blocks
+    pub versions: u64,
+}
+
+/// How often this node read entities of one type of a deployment since it
+/// started, and how long those reads took. A read that fetches entities of
+/// several types at once counts, with its full time, for each of them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityAccessStats {
+    pub entity_type: String,
+    /// Reads for GraphQL queries
+    pub query_reads: u64,
+    pub query_time: Duration,
+    /// Reads for mappings
+    pub mapping_reads: u64,
+    pub mapping_time: Duration,
+}
+
+/// One version of an entity: the values it had from block `block_start`
+/// up to, but not including, block `block_end`. The current version of an
+/// entity has no `block_end`
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityVersion {
+    pub block_start: BlockNumber,
+    pub block_end: Option<BlockNumber>,
+    pub data: Entity,
+}
+
+/// How quickly a deployment is processing blocks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeploymentSyncRate {
+    /// The number of blocks per minute that the deployment processed over
+    /// the last minute or so. This is `None` until the deployment has been
+    /// processing blocks for at least a minute, and `0` if it has not
+    /// processed a block in the last few minutes
+    pub blocks_per_minute: Option<f64>,
+    /// The timestamp of the latest block that the deployment processed,
+    /// if that block is in the block cache
+    pub latest_block_timestamp: Option<u64>,
+}
+
+/// The chain head that block ingestion has reached for a network
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainHeadStatus {
+    pub network: String,
+    pub head_block: Option<EthereumBlockPointer>,
+    /// The timestamp of the head block, if that block is in the block cache
+    pub head_block_timestamp: Option<u64>,
+}
+
 #[derive(Fail, Debug)]
 pub enum StoreError {
     #[fail(display = "store transaction failed, need to retry: {}", _0)]
@@ -736,6 +887,13 @@ pub enum StoreError {
     ConstraintViolation(String),
     #[fail(display = "deployment not found: {}", _0)]
     DeploymentNotFound(String),
+    #[fail(
+        display = "block {} with number {} is not on the main chain anymore",
+        _0, _1
+    )]
+    BlockReorged(String, BlockNumber),
+    #[fail(display = "deployment has not failed: {}", _0)]
+    DeploymentNotFailed(String),
 }
 
 impl From<TransactionAbortError> for StoreError {
@@ -808,6 +966,15 @@ pub trait Store: Send + Sync + 'static {
         block_hash: H256,
     ) -> DynTryFuture<'a, Option<[u8; 32]>>;
 
+    /// The proof of indexing that was recorded when the deployment
+    /// processed the block with number `block_number`, or the most recent
+    /// block before it that it processed
+    fn get_block_proof_of_indexing(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockProofOfIndexing>, StoreError>;
+
     /// Looks up an entity using the given store key at the latest block.
     fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
 
@@ -825,10 +992,6 @@ pub trait Store: Send + Sync + 'static {
     /// Queries the store for a single entity matching the store query.
     fn find_one(&self, query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError>;
 
-    /// Find the reverse of keccak256 for `hash` through looking it up in the
-    /// rainbow table.
-    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError>;
-
     /// Transact the entity changes from a single block atomically into the store, and update the
     /// subgraph block pointer to `block_ptr_to`.
     ///
@@ -944,6 +1107,96 @@ pub trait Store: Send + Sync + 'static {
         node_id: &NodeId,
     ) -> Result<(), StoreError>;
 
+    /// Remove the assignment of the deployment `id` so that no node indexes
+    /// it anymore. If there is no assignment for the deployment, report an
+    /// error
+    fn unassign_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError>;
+
+    /// Roll the failed deployment `id` back to `block_ptr_to`, clear its
+    /// failure, and make the node it is assigned to restart it. Deployments
+    /// that need a rewind because they indexed a block that is no longer
+    /// canonical can be rolled back the same way. Report
+    /// `StoreError::DeploymentNotFailed` if the deployment has neither
+    /// failed nor needs a rewind
+    fn rewind_failed_deployment(
+        &self,
+        id: &SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError>;
+
+    /// List deployments together with the subgraphs that use them. If
+    /// `name` is given, only list the versions of the subgraph with that
+    /// name; if `deployment` is given, only list that deployment
+    fn deployment_infos(
+        &self,
+        name: Option<&SubgraphName>,
+        deployment: Option<&SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, StoreError>;
+
+    /// Find the API key whose secret part is `secret`
+    fn api_key(&self, secret: &str) -> Result<Option<ApiKey>, StoreError>;
+
+    /// Add `usage` to the usage that is recorded for the current day
+    fn record_api_key_usage(&self, usage: Vec<ApiKeyUsage>) -> Result<(), StoreError>;
+
+    /// The latest block of every deployment that indexes `network`, for
+    /// deployments that have processed at least one block
+    fn deployment_block_ptrs(
+        &self,
+        network: &str,
+    ) -> Result<Vec<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError>;
+
+    /// The blocks from `first` to `last`, inclusive, that the deployment
+    /// `id` processed and recorded a proof of indexing for, ordered by
+    /// block number
+    fn indexed_block_ptrs(
+        &self,
+        id: &SubgraphDeploymentId,
+        first: BlockNumber,
+        last: BlockNumber,
+    ) -> Result<Vec<EthereumBlockPointer>, StoreError>;
+
+    /// Record that the deployment `id` indexed a block that is no longer
+    /// part of the canonical chain, or, if `block` is `None`, that it does
+    /// not need a rewind anymore
+    fn set_non_canonical_block(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: Option<NonCanonicalBlock>,
+    ) -> Result<(), StoreError>;
+
+    /// The block that made the deployment `id` need a rewind, if any
+    fn non_canonical_block(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Option<NonCanonicalBlock>, StoreError>;
+
+    /// Record that the node `node_id`, which indexes `networks`, is alive.
+    /// If `failover_target` is `true`, deployments of nodes that died may
+    /// be assigned to it
+    fn record_heartbeat(
+        &self,
+        node_id: &NodeId,
+        failover_target: bool,
+        networks: &[String],
+    ) -> Result<(), StoreError>;
+
+    /// Record that the node `node_id` shut down cleanly, which gives it
+    /// more time to come back before its deployments are moved
+    fn record_stop(&self, node_id: &NodeId) -> Result<(), StoreError>;
+
+    /// Assign the deployments of nodes that have not recorded a heartbeat
+    /// for `timeout`, or for `planned_downtime` if they shut down cleanly,
+    /// to live failover targets that index their network and that the
+    /// placement rules allow, spreading them so that the nodes with the
+    /// fewest deployments get them first. Only one node can do that at a
+    /// time; if another node is already at it, nothing happens
+    fn fail_over_dead_nodes(
+        &self,
+        timeout: Duration,
+        planned_downtime: Duration,
+    ) -> Result<Vec<Failover>, StoreError>;
+
     /// Start an existing subgraph deployment. This will reset the state of
     /// the subgraph to a known good state. `ops` needs to contain all the
     /// operations on the subgraph of subgraphs to reset the metadata of the
@@ -969,7 +1222,8 @@ pub trait Store: Send + Sync + 'static {
     );
 
     /// Return the number of the block with the given hash for the given
-    /// subgraph
+    /// subgraph. Return `StoreError::BlockReorged` if we know that the
+    /// block is not on the chain that the subgraph has indexed
     fn block_number(
         &self,
         subgraph_id: &SubgraphDeploymentId,
@@ -981,10 +1235,88 @@ pub trait Store: Send + Sync + 'static {
     ///
     /// If `for_subscription` is true, the main replica will always be used.
     fn query_store(self: Arc<Self>, for_subscription: bool) -> Arc<dyn QueryStore + Send + Sync>;
+
+    /// Return the number of current entities and of entity versions for
+    /// each entity type in the deployment `subgraph_id`. Counting is
+    /// expensive, and the result may therefore be slightly out of date
+    fn entity_counts(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityTypeCount>, StoreError>;
+
+    /// Return how often this node read entities of each type of the
+    /// deployment `subgraph_id` for queries and for mappings
+    fn entity_access_stats(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityAccessStats>, StoreError>;
+
+    /// Return the versions of the entity of type `entity_type` with id
+    /// `entity_id` in the deployment `subgraph_id` that fall into `range`,
+    /// the oldest first. If the entity was deleted and created again, there
+    /// is a gap between the block ranges of its versions
+    fn entity_history(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity_id: &str,
+        range: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError>;
+
+    /// Return how quickly the deployment `subgraph_id` is processing blocks
+    fn deployment_sync_rate(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<DeploymentSyncRate, StoreError>;
+
+    /// Return the chain head of every network that blocks are ingested for
+    fn chain_head_statuses(&self) -> Result<Vec<ChainHeadStatus>, StoreError>;
+
+    /// Store the messages that the mappings of `subgraph_id` logged, and
+    /// only keep the most recent `HANDLER_LOG_RETENTION` of them
+    fn record_handler_logs(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        logs: Vec<HandlerLog>,
+    ) -> Result<(), StoreError>;
+
+    /// Return the `first` most recent messages that the mappings of
+    /// `subgraph_id` logged, newest first, optionally only those from
+    /// `block` or with one of the given `levels`
+    fn handler_logs(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        levels: Vec<String>,
+        first: usize,
+    ) -> Result<Vec<HandlerLog>, StoreError>;
+
+    /// Return the entities of the given types that were created or updated
+    /// in the deployment `subgraph_id` at exactly `block`, grouped by
+    /// entity type and sorted by id. Entities that were removed at that
+    /// block are not included
+    fn changed_entities(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError>;
+
+    /// Return the numbers of the blocks from `from` to `to`, inclusive, for
+    /// which `changed_entities` would return at least one entity
+    fn entity_change_blocks(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError>;
 }
 
 mock! {
     pub Store {
+        fn get_mock(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
+
         fn get_many_mock<'a>(
             &self,
             _subgraph_id: &SubgraphDeploymentId,
@@ -1022,8 +1354,16 @@ impl Store for MockStore {
         unimplemented!();
     }
 
-    fn get(&self, _key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        unimplemented!()
+    fn get_block_proof_of_indexing(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_number: BlockNumber,
+    ) -> Result<Option<BlockProofOfIndexing>, StoreError> {
+        unimplemented!();
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        self.get_mock(key)
     }
 
     fn get_many(
@@ -1042,10 +1382,6 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
-        unimplemented!()
-    }
-
     fn transact_block_operations(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -1111,6 +1447,77 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn unassign_subgraph(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn rewind_failed_deployment(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_infos(
+        &self,
+        _: Option<&SubgraphName>,
+        _: Option<&SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, StoreError> {
+        unimplemented!()
+    }
+
+    fn api_key(&self, _: &str) -> Result<Option<ApiKey>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_api_key_usage(&self, _: Vec<ApiKeyUsage>) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_block_ptrs(
+        &self,
+        _: &str,
+    ) -> Result<Vec<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError> {
+        unimplemented!()
+    }
+
+    fn indexed_block_ptrs(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: BlockNumber,
+        _: BlockNumber,
+    ) -> Result<Vec<EthereumBlockPointer>, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_non_canonical_block(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: Option<NonCanonicalBlock>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn non_canonical_block(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Option<NonCanonicalBlock>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_heartbeat(&self, _: &NodeId, _: bool, _: &[String]) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn record_stop(&self, _: &NodeId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn fail_over_dead_nodes(&self, _: Duration, _: Duration) -> Result<Vec<Failover>, StoreError> {
+        unimplemented!()
+    }
+
     fn start_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -1140,11 +1547,203 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn entity_counts(&self, _: &SubgraphDeploymentId) -> Result<Vec<EntityTypeCount>, StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_access_stats(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityAccessStats>, StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_history(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+        _: &str,
+        _: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_sync_rate(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<DeploymentSyncRate, StoreError> {
+        unimplemented!()
+    }
+
+    fn chain_head_statuses(&self) -> Result<Vec<ChainHeadStatus>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_handler_logs(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _logs: Vec<HandlerLog>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn handler_logs(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block: Option<BlockNumber>,
+        _levels: Vec<String>,
+        _first: usize,
+    ) -> Result<Vec<HandlerLog>, StoreError> {
+        unimplemented!()
+    }
+
+    fn changed_entities(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: Vec<String>,
+        _: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_change_blocks(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: Vec<String>,
+        _: BlockNumber,
+        _: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError> {
+        unimplemented!()
+    }
+
     fn deployment_synced(&self, _: &SubgraphDeploymentId) -> Result<(), Error> {
         unimplemented!()
     }
 }
 
+/// What grafting onto a base deployment does with the data of one entity
+/// type
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "action")]
+pub enum GraftTableAction {
+    /// The data is copied as-is
+    Copy,
+    /// The data is copied, but some attributes are dropped, added, or
+    /// converted to a different type
+    Transform {
+        dropped: Vec<String>,
+        added: Vec<String>,
+        converted: Vec<String>,
+    },
+    /// The entity type is not in the new schema and its data is not copied
+    Drop,
+    /// The entity type only exists in the new schema
+    Create,
+}
+
+/// A report of what grafting a schema onto a base deployment will do,
+/// produced before any data is copied
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraftPreview {
+    pub base: SubgraphDeploymentId,
+    pub entity_types: BTreeMap<String, GraftTableAction>,
+    /// The reasons why the graft is not possible. If this is empty, the
+    /// graft can proceed
+    pub errors: Vec<String>,
+}
+
+/// A deployment together with the subgraph version that uses it. A
+/// deployment that is used by several subgraphs is reported once for each
+/// of them
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeploymentInfo {
+    /// The name of the subgraph, or `None` if the deployment is neither the
+    /// current nor the pending version of any subgraph
+    pub subgraph: Option<String>,
+    /// Either `current` or `pending`
+    pub version: Option<String>,
+    pub deployment: String,
+    /// The node the deployment is assigned to, if any
+    pub node_id: Option<String>,
+    pub synced: bool,
+    pub health: String,
+    pub latest_block_number: Option<i64>,
+    /// The message of the error that made the deployment fail
+    pub fatal_error: Option<String>,
+    /// Whether the deployment indexed a block that is no longer part of the
+    /// canonical chain and has to be rewound
+    pub needs_rewind: bool,
+}
+
+/// A block that a deployment indexed and that, once it was older than the
+/// reorg threshold, turned out not to be part of the canonical chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NonCanonicalBlock {
+    pub block: EthereumBlockPointer,
+    /// The hash of the canonical block with the same number, or `None` if
+    /// the provider does not know of such a block
+    pub canonical_hash: Option<H256>,
+}
+
+/// The proof of indexing that a deployment recorded for a block it
+/// processed, computed without an indexer address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockProofOfIndexing {
+    pub block: EthereumBlockPointer,
+    /// The version of the algorithm that computed the proof
+    pub version: i32,
+    pub digest: [u8; 32],
+}
+
+/// A deployment that was taken away from a node that stopped recording
+/// heartbeats and assigned to a node that is alive
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failover {
+    pub deployment: SubgraphDeploymentId,
+    pub from: NodeId,
+    /// `None` if no live node can take the deployment, because none of
+    /// them indexes its network or the placement rules rule them out. The
+    /// deployment then stays with `from`
+    pub to: Option<NodeId>,
+}
+
+/// A key that clients of the GraphQL server present to query deployments.
+/// Only a hash of the secret part of the key is stored
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiKey {
+    pub id: String,
+    pub description: Option<String>,
+    /// The deployments the key can be used for, or `None` if it can be used
+    /// for all deployments
+    pub deployments: Option<Vec<String>>,
+    /// How many queries per minute the key can be used for, or `None` if
+    /// there is no limit
+    pub queries_per_minute: Option<u32>,
+    pub revoked: bool,
+}
+
+impl ApiKey {
+    pub fn allows(&self, deployment: &SubgraphDeploymentId) -> bool {
+        !self.revoked
+            && self
+                .deployments
+                .as_ref()
+                .map(|deployments| deployments.iter().any(|id| id == deployment.as_str()))
+                .unwrap_or(true)
+    }
+}
+
+/// The number of queries that were made with an API key against a
+/// deployment
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiKeyUsage {
+    pub api_key: String,
+    pub deployment: SubgraphDeploymentId,
+    pub queries: u64,
+}
+
 #[automock]
 pub trait SubgraphDeploymentStore: Send + Sync + 'static {
     /// Return the GraphQL schema supplied by the user
@@ -1157,6 +1756,14 @@ pub trait SubgraphDeploymentStore: Send + Sync + 'static {
     /// Return the name of the network that the subgraph is indexing from. The
     /// names returned are things like `mainnet` or `ropsten`
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+    /// Describe what grafting a deployment with `schema` onto the
+    /// deployment `base` would do with the data of `base`
+    fn graft_preview(
+        &self,
+        base: &SubgraphDeploymentId,
+        schema: &Schema,
+    ) -> Result<GraftPreview, Error>;
 }
 
 /// Common trait for blockchain store implementations.
@@ -1332,9 +1939,68 @@ pub struct EntityCache {
     /// means that the entity should be deleted
     updates: BTreeMap<EntityKey, Option<Entity>>,
 
+    /// How much memory the entities in `current` may use; see
+    /// `set_block_limit`
+    block_limit: Option<usize>,
+
+    /// The order in which the entities in `current` were used, which we
+    /// only track when there is a `block_limit`
+    use_order: UseOrder,
+
+    /// The weight of the entities that were evicted from `current` to
+    /// stay within `block_limit`
+    evicted_weight: usize,
+
     pub store: Arc<dyn Store>,
 }
 
+/// The order in which an `EntityCache` used the entities it read from the
+/// store, so that it can evict the least recently used ones first
+#[derive(Clone, Debug, Default)]
+struct UseOrder {
+    last_use: HashMap<EntityKey, i64>,
+    by_last_use: BTreeMap<i64, EntityKey>,
+    /// The time of the most recent use
+    newest: i64,
+    /// The time we gave the entity from an earlier block that we added last
+    oldest: i64,
+    /// Whether the entities from earlier blocks have been added
+    complete: bool,
+}
+
+impl UseOrder {
+    fn touch(&mut self, key: &EntityKey) {
+        self.newest += 1;
+        self.set(key.clone(), self.newest);
+    }
+
+    fn set(&mut self, key: EntityKey, time: i64) {
+        if let Some(previous) = self.last_use.insert(key.clone(), time) {
+            self.by_last_use.remove(&previous);
+        }
+        self.by_last_use.insert(time, key);
+    }
+
+    /// Add the entities in `keys` that have not been used yet as if they
+    /// had been used before all others. They are left over from earlier
+    /// blocks, and we know nothing about the order in which they were used
+    fn add_unused<'a>(&mut self, keys: impl Iterator<Item = &'a EntityKey>) {
+        for key in keys {
+            if !self.last_use.contains_key(key) {
+                self.oldest -= 1;
+                self.set(key.clone(), self.oldest);
+            }
+        }
+    }
+
+    fn pop_least_recent(&mut self) -> Option<EntityKey> {
+        let time = *self.by_last_use.keys().next()?;
+        let key = self.by_last_use.remove(&time)?;
+        self.last_use.remove(&key);
+        Some(key)
+    }
+}
+
 impl Debug for EntityCache {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("EntityCache")
@@ -1347,6 +2013,9 @@ impl Debug for EntityCache {
 pub struct ModificationsAndCache {
     pub modifications: Vec<EntityModification>,
     pub entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    /// The weight of the entities that had to be evicted from the cache
+    /// while the block was processed
+    pub evicted_weight: usize,
 }
 
 impl EntityCache {
@@ -1354,6 +2023,9 @@ impl EntityCache {
         Self {
             current: LfuCache::new(),
             updates: BTreeMap::new(),
+            block_limit: None,
+            use_order: UseOrder::default(),
+            evicted_weight: 0,
             store,
         }
     }
@@ -1365,12 +2037,59 @@ impl EntityCache {
         EntityCache {
             current,
             updates: BTreeMap::new(),
+            block_limit: None,
+            use_order: UseOrder::default(),
+            evicted_weight: 0,
             store,
         }
     }
 
+    /// Limit the memory that the entities read from the store may use to
+    /// `block_limit` bytes. Once they use more, the least recently used
+    /// ones are evicted; since the store does not change while a block is
+    /// processed, they can simply be read again. Pending changes are never
+    /// evicted
+    pub fn set_block_limit(&mut self, block_limit: usize) {
+        self.block_limit = Some(block_limit);
+        self.limit_current();
+    }
+
+    /// Note that `key` was just used
+    fn touch(&mut self, key: &EntityKey) {
+        if self.block_limit.is_some() {
+            self.use_order.touch(key);
+        }
+    }
+
+    fn limit_current(&mut self) {
+        let limit = match self.block_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        if self.current.total_weight() <= limit {
+            return;
+        }
+        if !self.use_order.complete {
+            self.use_order.add_unused(self.current.keys());
+            self.use_order.complete = true;
+        }
+        let before = self.current.total_weight();
+        while self.current.total_weight() > limit {
+            // Keys that are not in `current` anymore are skipped
+            match self.use_order.pop_least_recent() {
+                Some(key) => {
+                    self.current.remove(&key);
+                }
+                None => break,
+            }
+        }
+        self.evicted_weight += before - self.current.total_weight();
+    }
+
     pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
         let current = self.current.get_entity(&*self.store, &key)?;
+        self.touch(key);
+        self.limit_current();
         let updates = self.updates.get(&key).cloned();
         match (current, updates) {
             // Entity is unchanged
@@ -1409,7 +2128,11 @@ impl EntityCache {
 
                 // Previous change was a removal, clear fields in `current`.
                 None => {
-                    if let Some(current) = self.current.get_entity(&*self.store, &key)? {
+                    let current = self.current.get_entity(&*self.store, &key)?;
+                    if self.block_limit.is_some() {
+                        self.use_order.touch(&key);
+                    }
+                    if let Some(current) = current {
                         // Entity was removed so the fields not updated need to be unset.
                         for field in current.keys().cloned() {
                             entity.entry(field).or_insert(Value::Null);
@@ -1420,6 +2143,8 @@ impl EntityCache {
                 }
             },
         }
+        // Evict only once `entry` is gone since it borrows `self.updates`
+        self.limit_current();
         Ok(())
     }
 
@@ -1437,8 +2162,39 @@ impl EntityCache {
         Ok(())
     }
 
+    /// Move the entities of the types in `entity_types`, and all changes
+    /// to them, into a new cache. Use `extend` to combine the caches again
+    pub fn split_off(&mut self, entity_types: &BTreeSet<String>) -> EntityCache {
+        let current = self
+            .current
+            .split_off(|key| entity_types.contains(&key.entity_type));
+        let (updates, rest) = std::mem::take(&mut self.updates)
+            .into_iter()
+            .partition(|(key, _)| entity_types.contains(&key.entity_type));
+        self.updates = rest;
+        EntityCache {
+            current,
+            updates,
+            block_limit: self.block_limit,
+            use_order: UseOrder::default(),
+            evicted_weight: 0,
+            store: self.store.cheap_clone(),
+        }
+    }
+
     pub fn extend(&mut self, other: EntityCache) -> Result<(), QueryExecutionError> {
+        if self.block_limit.is_some() {
+            // Entities from earlier blocks have times before 1
+            for key in other.use_order.by_last_use.range(1..).map(|(_, key)| key) {
+                self.use_order.touch(key);
+            }
+            if self.use_order.complete {
+                self.use_order.add_unused(other.current.keys());
+            }
+        }
         self.current.extend(other.current);
+        self.evicted_weight += other.evicted_weight;
+        self.limit_current();
         for (key, update) in other.updates {
             match update {
                 Some(update) => self.set(key, update)?,
@@ -1526,6 +2282,7 @@ impl EntityCache {
         Ok(ModificationsAndCache {
             modifications: mods,
             entity_lfu_cache: self.current,
+            evicted_weight: self.evicted_weight,
         })
     }
 }
@@ -1551,3 +2308,42 @@ impl LfuCache<EntityKey, Option<Entity>> {
         }
     }
 }
+
+#[tokio::test]
+async fn debounce_combines_bursts_of_events() {
+    fn event(entity_id: &str) -> Arc<StoreEvent> {
+        Arc::new(StoreEvent::new(vec![EntityChange {
+            subgraph_id: SubgraphDeploymentId::new("debounce").unwrap(),
+            entity_type: "Thing".to_owned(),
+            entity_id: entity_id.to_owned(),
+            operation: EntityChangeOperation::Set,
+        }]))
+    }
+
+    let logger = Logger::root(slog::Discard, o!());
+    let (sender, receiver) = futures::sync::mpsc::unbounded();
+    let mut events = StoreEventStream::new(receiver)
+        .debounce(&logger, Duration::from_millis(100))
+        .compat();
+
+    // Events that arrive in quick succession are reported together once
+    // the interval has passed
+    let start = Instant::now();
+    sender.unbounded_send(event("1")).unwrap();
+    sender.unbounded_send(event("2")).unwrap();
+    let combined = events.next().await.unwrap().unwrap();
+    assert_eq!(2, combined.changes.len());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // An event that arrives later is reported by itself
+    sender.unbounded_send(event("3")).unwrap();
+    let single = events.next().await.unwrap().unwrap();
+    assert_eq!(1, single.changes.len());
+
+    // Pending events are reported right away when the source ends
+    sender.unbounded_send(event("4")).unwrap();
+    drop(sender);
+    let last = events.next().await.unwrap().unwrap();
+    assert_eq!(1, last.changes.len());
+    assert!(events.next().await.is_none());
+}
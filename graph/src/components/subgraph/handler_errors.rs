@@ -0,0 +1,97 @@
+//! The most recent handler errors of each deployment, kept in memory so
+//! that the index node can serve them together with the context in which
+//! they happened.
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use web3::types::H256;
+
+use crate::components::ethereum::EthereumBlockPointer;
+
+lazy_static! {
+    /// How many errors to keep per deployment
+    static ref RECENT_HANDLER_ERRORS: usize = std::env::var("GRAPH_RECENT_HANDLER_ERRORS")
+        .unwrap_or("50".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_RECENT_HANDLER_ERRORS");
+    static ref ERRORS: Mutex<HashMap<String, VecDeque<HandlerError>>> = Mutex::new(HashMap::new());
+}
+
+/// An error that a handler raised, and the trigger it was processing
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandlerError {
+    /// The block the handler processed, if it had a hash and a number
+    pub block: Option<EthereumBlockPointer>,
+    pub data_source: String,
+    pub handler: String,
+    /// What kind of trigger the handler processed, e.g. `log`
+    pub trigger: String,
+    pub transaction_hash: Option<H256>,
+    pub log_index: Option<u64>,
+    pub message: String,
+    /// The WASM stack at the time of the error, innermost frame first,
+    /// if the error was a trap
+    pub wasm_backtrace: Option<Vec<String>>,
+    pub deterministic: bool,
+    /// When the error happened, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+impl HandlerError {
+    /// Remember `error` as the most recent error of `deployment`
+    pub fn record(deployment: &str, error: HandlerError) {
+        if *RECENT_HANDLER_ERRORS == 0 {
+            return;
+        }
+        let mut errors = ERRORS.lock().unwrap();
+        let errors = errors.entry(deployment.to_owned()).or_default();
+        if errors.len() >= *RECENT_HANDLER_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    /// The most recent errors of `deployment`, newest first
+    pub fn recent(deployment: &str) -> Vec<HandlerError> {
+        ERRORS
+            .lock()
+            .unwrap()
+            .get(deployment)
+            .map(|errors| errors.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forget the errors of `deployment`, e.g., because it was unassigned
+    pub fn clear(deployment: &str) {
+        ERRORS.lock().unwrap().remove(deployment);
+    }
+}
+
+#[test]
+fn keeps_most_recent_errors() {
+    let error = |block_number: u64| HandlerError {
+        block: Some(EthereumBlockPointer::from((H256::zero(), block_number))),
+        data_source: "Token".to_owned(),
+        handler: "handleTransfer".to_owned(),
+        trigger: "log".to_owned(),
+        transaction_hash: None,
+        log_index: Some(0),
+        message: "oops".to_owned(),
+        wasm_backtrace: None,
+        deterministic: true,
+        timestamp: 0,
+    };
+
+    let limit = *RECENT_HANDLER_ERRORS as u64;
+    for block_number in 0..limit + 5 {
+        HandlerError::record("keepsMostRecentErrors", error(block_number));
+    }
+    let recent = HandlerError::recent("keepsMostRecentErrors");
+    assert_eq!(limit as usize, recent.len());
+    assert_eq!(limit + 4, recent[0].block.as_ref().unwrap().number);
+    assert_eq!(5, recent.last().unwrap().block.as_ref().unwrap().number);
+    assert!(HandlerError::recent("noErrors").is_empty());
+
+    HandlerError::clear("keepsMostRecentErrors");
+    assert!(HandlerError::recent("keepsMostRecentErrors").is_empty());
+}
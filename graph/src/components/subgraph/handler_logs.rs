@@ -0,0 +1,95 @@
+//! The messages that mappings log with `log.log`. They are collected while
+//! a deployment processes a block and then stored with the deployment, so
+//! that they can be looked at through the index node server without access
+//! to the logs of the node.
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use web3::types::H256;
+
+lazy_static! {
+    /// How many messages to keep per deployment; 0 turns storing them off.
+    /// Storing them costs a separate write after every block that logged,
+    /// and is therefore off by default
+    pub static ref HANDLER_LOG_RETENTION: usize = std::env::var("GRAPH_HANDLER_LOG_RETENTION")
+        .unwrap_or("0".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_HANDLER_LOG_RETENTION");
+    static ref LOGS: Mutex<HashMap<String, VecDeque<HandlerLog>>> = Mutex::new(HashMap::new());
+}
+
+/// A message that a mapping logged
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandlerLog {
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub data_source: String,
+    /// One of `critical`, `error`, `warn`, `info`, or `debug`
+    pub level: String,
+    pub message: String,
+    /// When the message was logged, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+impl HandlerLog {
+    /// Remember `log` until the block that `deployment` is processing has
+    /// been processed
+    pub fn record(deployment: &str, log: HandlerLog) {
+        Self::record_up_to(deployment, log, *HANDLER_LOG_RETENTION)
+    }
+
+    /// Remember `log`, but no more than `retain` messages per deployment
+    fn record_up_to(deployment: &str, log: HandlerLog, retain: usize) {
+        if retain == 0 {
+            return;
+        }
+        let mut logs = LOGS.lock().unwrap();
+        let logs = logs.entry(deployment.to_owned()).or_default();
+        // Older messages would not be kept in the store anyway
+        if logs.len() >= retain {
+            logs.pop_front();
+        }
+        logs.push_back(log);
+    }
+
+    /// Take the messages that `deployment` logged since the last call,
+    /// oldest first
+    pub fn take(deployment: &str) -> Vec<HandlerLog> {
+        LOGS.lock()
+            .unwrap()
+            .remove(deployment)
+            .map(Vec::from)
+            .unwrap_or_default()
+    }
+
+    /// Take the messages of all deployments, for example, to store them
+    /// before the node exits
+    pub fn take_all() -> Vec<(String, Vec<HandlerLog>)> {
+        std::mem::take(&mut *LOGS.lock().unwrap())
+            .into_iter()
+            .map(|(deployment, logs)| (deployment, Vec::from(logs)))
+            .collect()
+    }
+}
+
+#[test]
+fn takes_recorded_logs() {
+    let log = |block_number: u64| HandlerLog {
+        block_number,
+        block_hash: H256::zero(),
+        data_source: "Token".to_owned(),
+        level: "info".to_owned(),
+        message: format!("block {}", block_number),
+        timestamp: 0,
+    };
+
+    let limit = 10;
+    for block_number in 0..limit + 5 {
+        HandlerLog::record_up_to("takesRecordedLogs", log(block_number), limit as usize);
+    }
+    let logs = HandlerLog::take("takesRecordedLogs");
+    assert_eq!(limit as usize, logs.len());
+    assert_eq!(5, logs[0].block_number);
+    assert_eq!(limit + 4, logs.last().unwrap().block_number);
+    assert!(HandlerLog::take("takesRecordedLogs").is_empty());
+}
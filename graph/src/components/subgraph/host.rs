@@ -7,7 +7,7 @@ use failure::Error;
 use futures::sync::mpsc;
 
 use crate::components::metrics::HistogramVec;
-use crate::components::subgraph::SharedProofOfIndexing;
+use crate::components::subgraph::{HandlerProfile, SharedProofOfIndexing};
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
@@ -57,6 +57,15 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
     /// Returns true if the RuntimeHost has a handler for an Ethereum block.
     fn matches_block(&self, call: &EthereumBlockTriggerType, block_number: u64) -> bool;
 
+    /// Returns true if the RuntimeHost has a handler for changes to entities
+    /// of type `entity_type` in the upstream deployment `deployment`.
+    fn matches_entity(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        entity_type: &str,
+        block_number: u64,
+    ) -> bool;
+
     /// Process an Ethereum event and return a vector of entity operations.
     async fn process_log(
         &self,
@@ -88,12 +97,26 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError>;
+
+    /// Process a change to an entity in an upstream deployment and return
+    /// a vector of entity operations
+    async fn process_entity(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        entity_type: &str,
+        entity: &Entity,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>;
 }
 
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
     pub stopwatch: StopwatchMetrics,
+    /// Where handlers spend their time, if handler profiling is turned on
+    pub profile: Option<Arc<HandlerProfile>>,
 }
 
 impl fmt::Debug for HostMetrics {
@@ -131,6 +154,7 @@ impl HostMetrics {
             handler_execution_time,
             host_fn_execution_time,
             stopwatch,
+            profile: HandlerProfile::for_deployment(subgraph),
         }
     }
 
@@ -151,12 +175,14 @@ pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
     type Host: RuntimeHost + PartialEq;
     type Req: 'static + Send;
 
-    /// Build a new runtime host for a subgraph data source.
+    /// Build a new runtime host for a subgraph data source. The `group` is
+    /// the group of independent data sources that `data_source` belongs to
     fn build(
         &self,
         network_name: String,
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
+        group: Option<Arc<DataSourceGroup>>,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         mapping_request_sender: mpsc::Sender<Self::Req>,
         metrics: Arc<HostMetrics>,
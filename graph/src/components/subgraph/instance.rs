@@ -54,6 +54,20 @@ pub trait SubgraphInstance<H: RuntimeHost> {
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError>;
 
+    /// Process the new version of an entity that the upstream deployment
+    /// `deployment` wrote in `block` in all hosts with a matching entity
+    /// handler.
+    async fn process_entity_trigger(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        deployment: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity: &Entity,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>;
+
     /// Adds dynamic data sources to the subgraph.
     fn add_dynamic_data_source(
         &mut self,
@@ -1,20 +1,28 @@
+mod handler_errors;
+mod handler_logs;
 mod host;
 mod instance;
 mod instance_manager;
 mod loader;
+mod placement;
+mod profile;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
 
 pub use crate::prelude::Entity;
 
+pub use self::handler_errors::HandlerError;
+pub use self::handler_logs::{HandlerLog, HANDLER_LOG_RETENTION};
 pub use self::host::{HostMetrics, MappingError, RuntimeHost, RuntimeHostBuilder};
 pub use self::instance::{BlockState, DataSourceTemplateInfo, SubgraphInstance};
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
+pub use self::placement::PlacementRules;
+pub use self::profile::{HandlerProfile, HANDLER_PROFILING};
 pub use self::proof_of_indexing::{
     BlockEventStream, ProofOfIndexing, ProofOfIndexingEvent, ProofOfIndexingFinisher,
-    SharedProofOfIndexing,
+    SharedProofOfIndexing, PROOF_OF_INDEXING_VERSION,
 };
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
@@ -0,0 +1,189 @@
+//! Rules that decide which node indexes a new deployment.
+//!
+//! When `GRAPH_DEPLOYMENT_RULES_FILE` is set, it must name a file with one
+//! rule per line of the form `<name> <network> <node>[,<node>...]`; empty
+//! lines and lines starting with `#` are ignored. `<name>` and `<network>`
+//! are patterns in which `*` matches any sequence of characters. A new
+//! deployment is assigned to one of the nodes of the first rule whose
+//! patterns match the name of the subgraph and the network of the
+//! deployment. Deployments that no rule matches, and all deployments when
+//! there is no rules file, go to the node that received the deploy request.
+//! The rules can also come from the node's configuration file, which
+//! replaces them when it is reloaded
+use lazy_static::lazy_static;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::data::store::NodeId;
+use crate::data::subgraph::SubgraphName;
+
+lazy_static! {
+    static ref PLACEMENT_RULES: RwLock<Arc<PlacementRules>> = {
+        let rules = std::env::var("GRAPH_DEPLOYMENT_RULES_FILE")
+            .ok()
+            .map(|path| {
+                let contents = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                    panic!("can not read GRAPH_DEPLOYMENT_RULES_FILE {}: {}", path, e)
+                });
+                contents.parse().unwrap_or_else(|e| {
+                    panic!("invalid GRAPH_DEPLOYMENT_RULES_FILE {}: {}", path, e)
+                })
+            })
+            .unwrap_or_default();
+        RwLock::new(Arc::new(rules))
+    };
+}
+
+#[derive(Clone, Debug)]
+struct PlacementRule {
+    name: String,
+    network: String,
+    nodes: Vec<NodeId>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PlacementRules {
+    rules: Vec<PlacementRule>,
+}
+
+impl PlacementRules {
+    /// The rules that are in effect right now
+    pub fn current() -> Arc<PlacementRules> {
+        PLACEMENT_RULES.read().unwrap().clone()
+    }
+
+    /// Put `rules` into effect for all deployments that are created from
+    /// now on
+    pub fn replace(rules: PlacementRules) {
+        *PLACEMENT_RULES.write().unwrap() = Arc::new(rules);
+    }
+
+    /// Add a rule that assigns deployments of subgraphs whose name matches
+    /// `name` on networks that match `network` to one of `nodes`. The rule
+    /// only applies to deployments that no earlier rule matches
+    pub fn add(&mut self, name: &str, network: &str, nodes: Vec<NodeId>) -> Result<(), String> {
+        if nodes.is_empty() {
+            return Err(format!(
+                "the rule for `{}` on `{}` must list at least one node",
+                name, network
+            ));
+        }
+        self.rules.push(PlacementRule {
+            name: name.to_owned(),
+            network: network.to_owned(),
+            nodes,
+        });
+        Ok(())
+    }
+
+    /// The nodes that a new deployment of the subgraph `name` on `network`
+    /// may be assigned to, or `None` if no rule matches it
+    pub fn nodes(&self, name: &SubgraphName, network: &str) -> Option<&[NodeId]> {
+        self.rules
+            .iter()
+            .find(|rule| matches(&rule.name, name.as_str()) && matches(&rule.network, network))
+            .map(|rule| rule.nodes.as_slice())
+    }
+}
+
+/// Whether `s` matches `pattern`, where `*` in the pattern matches any
+/// sequence of characters, including an empty one
+fn matches(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // Unwrap: `split` always produces at least one part
+    let first = parts.next().unwrap();
+    if !s.starts_with(first) {
+        return false;
+    }
+    let mut rest = &s[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // No `*` in the pattern
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(pos) => rest = &rest[pos + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+impl FromStr for PlacementRules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = PlacementRules::default();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (name, network, nodes) =
+                match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                    (Some(name), Some(network), Some(nodes), None) => (name, network, nodes),
+                    _ => {
+                        return Err(format!(
+                            "line {}: expected `<name> <network> <node>[,<node>...]`",
+                            number + 1
+                        ))
+                    }
+                };
+            let nodes = nodes
+                .split(',')
+                .map(|node| {
+                    NodeId::new(node)
+                        .map_err(|_| format!("line {}: invalid node id `{}`", number + 1, node))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            rules.add(name, network, nodes)?;
+        }
+        Ok(rules)
+    }
+}
+
+#[test]
+fn placement_rules() {
+    let name = |s: &str| SubgraphName::new(s).unwrap();
+    let nodes = |ids: &[&str]| {
+        ids.iter()
+            .map(|id| NodeId::new(*id).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let rules: PlacementRules = "# heavy subgraphs get their own nodes\n\
+                                 uniswap/* mainnet heavy_1,heavy_2\n\
+                                 \n\
+                                 * rinkeby testnets\n\
+                                 *  *  default\n"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        Some(nodes(&["heavy_1", "heavy_2"]).as_slice()),
+        rules.nodes(&name("uniswap/v2"), "mainnet")
+    );
+    assert_eq!(
+        Some(nodes(&["testnets"]).as_slice()),
+        rules.nodes(&name("uniswap/v2"), "rinkeby")
+    );
+    assert_eq!(
+        Some(nodes(&["default"]).as_slice()),
+        rules.nodes(&name("ens"), "mainnet")
+    );
+    assert_eq!(
+        None,
+        PlacementRules::default().nodes(&name("ens"), "mainnet")
+    );
+
+    assert!(matches("a*b*c", "abc"));
+    assert!(matches("a*b*c", "a-b-b-c"));
+    assert!(!matches("a*b*c", "a-c"));
+    assert!(!matches("uniswap", "uniswap/v2"));
+
+    assert!("* mainnet".parse::<PlacementRules>().is_err());
+    assert!("* mainnet bad-node".parse::<PlacementRules>().is_err());
+}
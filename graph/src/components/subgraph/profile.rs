@@ -0,0 +1,162 @@
+//! Profiles of where mapping handlers spend their time.
+//!
+//! When `GRAPH_HANDLER_PROFILING` is set, the WASM runtime measures every
+//! handler invocation and splits its duration into the time spent in each
+//! host function and the time spent running WASM code. The durations are
+//! accumulated per deployment in folded stack format, i.e., one line
+//! `<handler>;<frame> <microseconds>` per frame, which tools like
+//! `flamegraph.pl` and `inferno-flamegraph` can turn into a flamegraph.
+//!
+//! To keep the overhead low, only one in every
+//! `GRAPH_HANDLER_PROFILING_SAMPLE_RATE` handler invocations of a deployment
+//! is measured, 10 by default. When profiling is turned off, handlers are
+//! not measured at all.
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    pub static ref HANDLER_PROFILING: bool = std::env::var("GRAPH_HANDLER_PROFILING")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    static ref HANDLER_PROFILING_SAMPLE_RATE: u64 =
+        std::env::var("GRAPH_HANDLER_PROFILING_SAMPLE_RATE")
+            .map(|s| {
+                s.parse::<u64>()
+                    .ok()
+                    .filter(|rate| *rate > 0)
+                    .expect("invalid GRAPH_HANDLER_PROFILING_SAMPLE_RATE")
+            })
+            .unwrap_or(10);
+    static ref PROFILES: Mutex<HashMap<String, Arc<HandlerProfile>>> = Mutex::new(HashMap::new());
+}
+
+/// The frame for the time a handler spends running WASM code, as opposed
+/// to time spent in host functions
+pub const WASM_FRAME: &str = "wasm";
+
+#[derive(Debug, Default)]
+pub struct HandlerProfile {
+    /// Accumulated microseconds for each folded stack
+    stacks: Mutex<BTreeMap<String, u64>>,
+    /// How many handler invocations were considered for sampling
+    invocations: AtomicU64,
+}
+
+impl HandlerProfile {
+    /// The profile for `deployment` if handler profiling is turned on. All
+    /// callers get the same profile for the same deployment
+    pub fn for_deployment(deployment: &str) -> Option<Arc<HandlerProfile>> {
+        if !*HANDLER_PROFILING {
+            return None;
+        }
+        Some(
+            PROFILES
+                .lock()
+                .unwrap()
+                .entry(deployment.to_owned())
+                .or_default()
+                .clone(),
+        )
+    }
+
+    /// The profile of `deployment` in folded stack format, or `None` if no
+    /// profile was collected for it
+    pub fn folded_for(deployment: &str) -> Option<String> {
+        PROFILES
+            .lock()
+            .unwrap()
+            .get(deployment)
+            .map(|profile| profile.folded())
+    }
+
+    /// Forget the profile of `deployment`, e.g., because it was unassigned
+    pub fn clear(deployment: &str) {
+        PROFILES.lock().unwrap().remove(deployment);
+    }
+
+    /// Whether the handler invocation that is about to start should be
+    /// measured
+    pub fn sample(&self) -> bool {
+        self.sample_every(*HANDLER_PROFILING_SAMPLE_RATE)
+    }
+
+    fn sample_every(&self, rate: u64) -> bool {
+        self.invocations.fetch_add(1, Ordering::Relaxed) % rate == 0
+    }
+
+    /// Record one invocation of `handler` that took `total` and spent
+    /// `host_calls` of that in host functions
+    pub fn record(
+        &self,
+        handler: &str,
+        total: Duration,
+        host_calls: impl IntoIterator<Item = (&'static str, Duration)>,
+    ) {
+        let mut stacks = self.stacks.lock().unwrap();
+        let mut host_total = Duration::from_secs(0);
+        for (host_fn, duration) in host_calls {
+            host_total += duration;
+            *stacks
+                .entry(format!("{};{}", handler, host_fn))
+                .or_default() += duration.as_micros() as u64;
+        }
+        *stacks
+            .entry(format!("{};{}", handler, WASM_FRAME))
+            .or_default() += total
+            .checked_sub(host_total)
+            .unwrap_or_default()
+            .as_micros() as u64;
+    }
+
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, micros) in self.stacks.lock().unwrap().iter() {
+            // Writing to a `String` can not fail
+            writeln!(out, "{} {}", stack, micros).unwrap();
+        }
+        out
+    }
+}
+
+#[test]
+fn folded_stacks() {
+    let profile = HandlerProfile::default();
+    let ms = Duration::from_millis;
+    profile.record("handleTransfer", ms(10), vec![("store.set", ms(3))]);
+    profile.record(
+        "handleTransfer",
+        ms(5),
+        vec![("store.get", ms(1)), ("store.set", ms(1))],
+    );
+    profile.record("handleBlock", ms(2), vec![]);
+
+    assert_eq!(
+        "handleBlock;wasm 2000\n\
+         handleTransfer;store.get 1000\n\
+         handleTransfer;store.set 4000\n\
+         handleTransfer;wasm 10000\n",
+        profile.folded()
+    );
+}
+
+#[test]
+fn samples_invocations() {
+    let profile = HandlerProfile::default();
+    let sampled: Vec<_> = (0..7).map(|_| profile.sample_every(3)).collect();
+    assert_eq!(vec![true, false, false, true, false, false, true], sampled);
+}
+
+#[test]
+fn clears_profiles() {
+    PROFILES.lock().unwrap().insert(
+        "clearsProfiles".to_owned(),
+        Arc::new(HandlerProfile::default()),
+    );
+    assert!(HandlerProfile::folded_for("clearsProfiles").is_some());
+    HandlerProfile::clear("clearsProfiles");
+    assert!(HandlerProfile::folded_for("clearsProfiles").is_none());
+}
@@ -25,6 +25,13 @@ use std::sync::Arc;
 /// lives here for lack of a better choice.
 pub type SharedProofOfIndexing = Option<Arc<AtomicRefCell<ProofOfIndexing>>>;
 
+/// The version of the algorithm that computes proofs of indexing. Each
+/// deployment records the version it was created with, since proofs that
+/// were computed with different versions can not be compared with each
+/// other. Any change to `ProofOfIndexing` or `ProofOfIndexingFinisher` that
+/// changes the resulting digests must increase it
+pub const PROOF_OF_INDEXING_VERSION: i32 = 1;
+
 #[cfg(test)]
 mod tests {
     use super::*;
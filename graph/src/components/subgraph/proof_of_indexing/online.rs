@@ -148,6 +148,15 @@ impl ProofOfIndexing {
                 .insert(causality_region.to_owned(), entry);
         }
     }
+    /// Add the causality regions of `other` to this proof of indexing.
+    /// The two proofs must not have any causality regions in common
+    pub fn merge(&mut self, other: ProofOfIndexing) {
+        for (causality_region, stream) in other.per_causality_region {
+            let prev = self.per_causality_region.insert(causality_region, stream);
+            assert!(prev.is_none(), "causality regions of merged proofs overlap");
+        }
+    }
+
     pub fn take(self) -> HashMap<String, BlockEventStream> {
         self.per_causality_region
     }
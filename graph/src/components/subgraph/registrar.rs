@@ -26,11 +26,30 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         name: SubgraphName,
     ) -> Result<CreateSubgraphResult, SubgraphRegistrarError>;
 
+    /// Deploy `hash` as a new version of the subgraph `name`. If the
+    /// deployment is grafted, return a preview of what the graft will do
+    /// with the data of the graft base. Without an `assignment_node_id`,
+    /// the node is chosen by the placement rules. Any `overrides` change
+    /// the data sources of the manifest for this deployment; the deployment
+    /// then gets its own id, see `DataSourceOverrides::deployment_id`
     async fn create_subgraph_version(
         &self,
         name: SubgraphName,
         hash: SubgraphDeploymentId,
-        assignment_node_id: NodeId,
+        assignment_node_id: Option<NodeId>,
+        overrides: Option<DataSourceOverrides>,
+    ) -> Result<Option<GraftPreview>, SubgraphRegistrarError>;
+
+    /// Copy the deployment `source` to a new deployment `target` that is
+    /// assigned to `node_id`, or to a node chosen by the placement rules,
+    /// and becomes a version of the subgraph `target_name`, which must not
+    /// have `source` as one of its versions
+    async fn clone_subgraph_deployment(
+        &self,
+        target_name: SubgraphName,
+        source: SubgraphDeploymentId,
+        target: SubgraphDeploymentId,
+        node_id: Option<NodeId>,
     ) -> Result<(), SubgraphRegistrarError>;
 
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError>;
@@ -40,4 +59,34 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: SubgraphDeploymentId,
         node_id: NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Stop indexing the deployment `hash` anywhere by removing its
+    /// assignment
+    async fn unassign_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Roll the failed deployment `hash` back to the block `blocks` blocks
+    /// before the one it failed on, clear the failure and index it again
+    /// from there. A deployment that needs a rewind because it indexed a
+    /// block that is not canonical is rolled back to the canonical block
+    /// `blocks` blocks before that block. Return the block the deployment
+    /// was rolled back to
+    async fn retry_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        blocks: u64,
+    ) -> Result<EthereumBlockPointer, SubgraphRegistrarError>;
+
+    /// List all deployments and the subgraphs that use them
+    async fn list_subgraphs(&self) -> Result<Vec<DeploymentInfo>, SubgraphRegistrarError>;
+
+    /// Describe the deployments of the subgraph called `name`, or the
+    /// deployment `deployment`
+    async fn subgraph_info(
+        &self,
+        name: Option<SubgraphName>,
+        deployment: Option<SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, SubgraphRegistrarError>;
 }
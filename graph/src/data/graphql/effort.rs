@@ -7,7 +7,7 @@ use std::collections::{HashMap, HashSet};
 use std::env;
 use std::iter::FromIterator;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 use crate::components::metrics::{Counter, Gauge, MetricsRegistry};
@@ -254,10 +254,19 @@ pub struct LoadManager {
 
     // Limits the number of graphql queries that may execute concurrently.
     query_semaphore: Arc<tokio::sync::Semaphore>,
+    /// The number of permits `query_semaphore` should have
+    max_concurrent_queries: Mutex<usize>,
     semaphore_wait_stats: RwLock<MovingStats>,
     semaphore_wait_gauge: Box<Gauge>,
 }
 
+// A query is always consuming a CPU core, or a DB connection, or both.
+// So if more than `store_conn_pool_size + num_cpus::get()` queries are executing,
+// there will be contention for resources.
+fn max_concurrent_queries(store_conn_pool_size: usize) -> usize {
+    store_conn_pool_size + num_cpus::get() + *EXTRA_QUERY_PERMITS
+}
+
 impl LoadManager {
     pub fn new(
         logger: &Logger,
@@ -309,10 +318,7 @@ impl LoadManager {
             )
             .expect("failed to create `query_effort_ms` counter");
 
-        // A query is always consuming a CPU core, or a DB connection, or both.
-        // So if more than `store_conn_pool_size + num_cpus::get()` queries are executing,
-        // there will be contention for resources.
-        let max_concurrent_queries = store_conn_pool_size + num_cpus::get() + *EXTRA_QUERY_PERMITS;
+        let max_concurrent_queries = max_concurrent_queries(store_conn_pool_size);
         let query_semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_queries));
         Self {
             logger,
@@ -323,11 +329,33 @@ impl LoadManager {
             effort_gauge,
             query_counters,
             query_semaphore,
+            max_concurrent_queries: Mutex::new(max_concurrent_queries),
             semaphore_wait_stats: RwLock::new(MovingStats::default()),
             semaphore_wait_gauge,
         }
     }
 
+    /// Change how many queries may run at the same time to suit a
+    /// connection pool with `store_conn_pool_size` connections. When that
+    /// lowers the limit, queries that are running are not interrupted;
+    /// instead, we take permits out of circulation as they are returned
+    pub fn resize(&self, store_conn_pool_size: usize) {
+        let target = max_concurrent_queries(store_conn_pool_size);
+        let mut current = self.max_concurrent_queries.lock().unwrap();
+        if target > *current {
+            self.query_semaphore.add_permits(target - *current);
+        } else if target < *current {
+            let semaphore = self.query_semaphore.cheap_clone();
+            let excess = *current - target;
+            crate::spawn(async move {
+                for _ in 0..excess {
+                    semaphore.acquire().await.forget();
+                }
+            });
+        }
+        *current = target;
+    }
+
     /// Record that we spent `duration` amount of work for the query
     /// `shape_hash`, where `cache_status` indicates whether the query
     /// was cached or had to actually run
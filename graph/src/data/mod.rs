@@ -15,3 +15,6 @@ pub mod subscription;
 
 /// Data types for dealing with GraphQL values.
 pub mod graphql;
+
+/// The version of this node and the features it supports.
+pub mod version;
@@ -11,7 +11,10 @@ use std::sync::Arc;
 
 use crate::data::graphql::SerializableValue;
 use crate::data::subgraph::*;
-use crate::{components::store::StoreError, prelude::CacheWeight};
+use crate::{
+    components::store::{BlockNumber, StoreError},
+    prelude::CacheWeight,
+};
 
 #[derive(Debug)]
 pub struct CloneableFailureError(Arc<failure::Error>);
@@ -78,6 +81,7 @@ pub enum QueryExecutionError {
     EventStreamError,
     FulltextQueryRequiresFilter,
     DeploymentReverted,
+    BlockReorged(String, BlockNumber),
 }
 
 impl Error for QueryExecutionError {
@@ -218,6 +222,7 @@ impl fmt::Display for QueryExecutionError {
             TooExpensive => write!(f, "query is too expensive"),
             Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes"),
             DeploymentReverted => write!(f, "the chain was reorganized while executing the query"),
+            BlockReorged(hash, number) => write!(f, "block {} with number {} was removed from the chain by a reorganization", hash, number),
         }
     }
 }
@@ -248,7 +253,12 @@ impl From<bigdecimal::ParseBigDecimalError> for QueryExecutionError {
 
 impl From<StoreError> for QueryExecutionError {
     fn from(e: StoreError) -> Self {
-        QueryExecutionError::StoreError(CloneableFailureError(Arc::new(e.into())))
+        match e {
+            StoreError::BlockReorged(hash, number) => {
+                QueryExecutionError::BlockReorged(hash, number)
+            }
+            e => QueryExecutionError::StoreError(CloneableFailureError(Arc::new(e.into()))),
+        }
     }
 }
 
@@ -4,6 +4,7 @@ use serde::Deserialize;
 use std::collections::{BTreeMap, HashMap};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::data::graphql::shape_hash::shape_hash;
 use crate::data::schema::ApiSchema;
@@ -114,6 +115,8 @@ pub struct Query {
     pub network: Option<String>,
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
+    /// How long it took to parse the query; only used for logging
+    pub parse_time: Duration,
     _force_use_of_new: (),
 }
 
@@ -146,6 +149,7 @@ impl Query {
             network,
             query_text: Arc::new(query_text),
             variables_text: Arc::new(variables_text),
+            parse_time: Duration::from_secs(0),
             _force_use_of_new: (),
         }
     }
@@ -1,5 +1,7 @@
 use crate::components::store::{Store, SubgraphDeploymentStore};
-use crate::data::graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt};
+use crate::data::graphql::ext::{
+    DirectiveExt, DirectiveFinder, DocumentExt, ObjectTypeExt, TypeExt, ValueExt,
+};
 use crate::data::store::ValueType;
 use crate::data::subgraph::{SubgraphDeploymentId, SubgraphName};
 use crate::prelude::Fail;
@@ -112,6 +114,18 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[fail(display = "Fulltext entity field, {}, not found or not a string", _0)]
     FulltextIncludedFieldInvalid(String),
+    #[fail(
+        display = "Timeseries type `{}` must have a `timestamp: Int!` or `timestamp: BigInt!` field",
+        _0
+    )]
+    TimeseriesTimestampMissing(String),
+    #[fail(display = "Type `{}` has an invalid @aggregation: {}", _0, _1)]
+    InvalidAggregation(String, String), // (type, reason)
+    #[fail(
+        display = "Field `{}` in type `{}` has an invalid @aggregate: {}",
+        _1, _0, _2
+    )]
+    InvalidAggregate(String, String, String), // (type, field, reason)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -265,6 +279,185 @@ impl From<&Directive> for FulltextDefinition {
         }
     }
 }
+/// The time intervals for which aggregations of timeseries entities can be
+/// maintained
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AggregationInterval {
+    Hour,
+    Day,
+}
+
+impl AggregationInterval {
+    /// The length of the interval in seconds
+    pub fn seconds(&self) -> i64 {
+        match self {
+            AggregationInterval::Hour => 3600,
+            AggregationInterval::Day => 86400,
+        }
+    }
+
+    /// The start of the bucket that `timestamp` (in seconds since the
+    /// epoch) falls into
+    pub fn bucket_start(&self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.seconds())
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregationInterval::Hour => "hour",
+            AggregationInterval::Day => "day",
+        }
+    }
+}
+
+impl TryFrom<&str> for AggregationInterval {
+    type Error = String;
+    fn try_from(interval: &str) -> Result<Self, Self::Error> {
+        match interval {
+            "hour" => Ok(AggregationInterval::Hour),
+            "day" => Ok(AggregationInterval::Day),
+            invalid => Err(format!(
+                "the interval `{}` is invalid. It must be one of: hour, day",
+                invalid
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AggregationInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregateFn {
+    Sum,
+    Count,
+    Min,
+    Max,
+}
+
+impl TryFrom<&str> for AggregateFn {
+    type Error = String;
+    fn try_from(func: &str) -> Result<Self, Self::Error> {
+        match func {
+            "sum" => Ok(AggregateFn::Sum),
+            "count" => Ok(AggregateFn::Count),
+            "min" => Ok(AggregateFn::Min),
+            "max" => Ok(AggregateFn::Max),
+            invalid => Err(format!(
+                "the function `{}` is invalid. It must be one of: sum, count, min, max",
+                invalid
+            )),
+        }
+    }
+}
+
+/// A single aggregated field, declared with `@aggregate(fn: .., arg: ..)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    /// The name of the field in the aggregation type
+    pub name: String,
+    pub func: AggregateFn,
+    /// The field of the source type that is aggregated; `None` for `count`
+    pub arg: Option<String>,
+    /// The type of the aggregated field
+    pub value_type: ValueType,
+}
+
+/// An aggregation type, declared with
+/// `@aggregation(source: "Type", intervals: ["hour", "day"])`. The store
+/// keeps one entity per interval and time bucket up to date whenever
+/// entities of the timeseries `source` are inserted
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregation {
+    pub name: String,
+    pub source: String,
+    /// The type of the `timestamp` field, either `Int` or `BigInt`
+    pub timestamp_type: ValueType,
+    pub intervals: Vec<AggregationInterval>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+impl Aggregation {
+    /// The field in timeseries and aggregation types that holds the
+    /// timestamp in seconds since the epoch
+    pub const TIMESTAMP: &'static str = "timestamp";
+    /// The field in aggregation types that holds the interval
+    pub const INTERVAL: &'static str = "interval";
+    /// The types that the `timestamp` field can have
+    pub const TIMESTAMP_TYPES: &'static [&'static str] = &["Int", "BigInt"];
+
+    /// The id of the aggregation entity for the bucket starting at `start`
+    pub fn bucket_id(interval: AggregationInterval, start: i64) -> String {
+        format!("{}-{}", interval, start)
+    }
+}
+
+impl From<&ObjectType> for Aggregation {
+    // Assumes the object type has already been validated and has an
+    // @aggregation directive
+    fn from(object_type: &ObjectType) -> Self {
+        let directive = object_type
+            .find_directive(String::from("aggregation"))
+            .unwrap();
+        let source = directive
+            .argument("source")
+            .unwrap()
+            .as_string()
+            .unwrap()
+            .clone();
+        let intervals = directive
+            .argument("intervals")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|interval| {
+                AggregationInterval::try_from(interval.as_string().unwrap().as_str()).unwrap()
+            })
+            .collect();
+        let aggregates = object_type
+            .fields
+            .iter()
+            .filter_map(|field| {
+                field
+                    .find_directive(String::from("aggregate"))
+                    .map(|aggregate| Aggregate {
+                        name: field.name.clone(),
+                        func: AggregateFn::try_from(
+                            aggregate
+                                .argument("fn")
+                                .unwrap()
+                                .as_string()
+                                .unwrap()
+                                .as_str(),
+                        )
+                        .unwrap(),
+                        arg: aggregate
+                            .argument("arg")
+                            .and_then(|arg| arg.as_string())
+                            .cloned(),
+                        value_type: ValueType::from_str(field.field_type.get_base_type()).unwrap(),
+                    })
+            })
+            .collect();
+        let timestamp_type = object_type
+            .field(&Aggregation::TIMESTAMP.to_owned())
+            .map(|field| ValueType::from_str(field.field_type.get_base_type()).unwrap())
+            .unwrap();
+
+        Aggregation {
+            name: object_type.name.clone(),
+            source,
+            timestamp_type,
+            intervals,
+            aggregates,
+        }
+    }
+}
+
 #[derive(Debug, Fail, PartialEq, Eq, Clone)]
 pub enum SchemaImportError {
     #[fail(display = "Schema for imported subgraph `{}` was not found", _0)]
@@ -672,6 +865,7 @@ impl Schema {
         errors.append(&mut self.validate_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
+        errors.append(&mut self.validate_aggregations());
         errors.append(&mut self.validate_imported_types(schemas));
         if errors.is_empty() {
             Ok(())
@@ -1306,6 +1500,200 @@ impl Schema {
             .map(|directive| FulltextDefinition::from(directive))
             .collect()
     }
+
+    /// Return `true` if `object_type` is declared with
+    /// `@entity(timeseries: true)`
+    pub fn is_timeseries(object_type: &ObjectType) -> bool {
+        object_type
+            .find_directive(String::from("entity"))
+            .and_then(|entity| entity.argument("timeseries"))
+            .map_or(false, |timeseries| timeseries == &Value::Boolean(true))
+    }
+
+    /// All aggregation types declared in this schema. The schema must
+    /// have been validated
+    pub fn aggregations(&self) -> Vec<Aggregation> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|object_type| {
+                object_type
+                    .find_directive(String::from("aggregation"))
+                    .is_some()
+            })
+            .map(Aggregation::from)
+            .collect()
+    }
+
+    fn validate_aggregations(&self) -> Vec<SchemaValidationError> {
+        let object_types = self.document.get_object_type_definitions();
+        let mut errors = vec![];
+
+        for object_type in &object_types {
+            if Self::is_timeseries(object_type) {
+                let has_timestamp = object_type
+                    .field(&Aggregation::TIMESTAMP.to_owned())
+                    .map_or(false, |field| match &field.field_type {
+                        Type::NonNullType(typ) => match typ.as_ref() {
+                            Type::NamedType(name) => {
+                                Aggregation::TIMESTAMP_TYPES.contains(&name.as_str())
+                            }
+                            _ => false,
+                        },
+                        _ => false,
+                    });
+                if !has_timestamp {
+                    errors.push(SchemaValidationError::TimeseriesTimestampMissing(
+                        object_type.name.clone(),
+                    ));
+                }
+            }
+
+            if let Some(aggregation) = object_type.find_directive(String::from("aggregation")) {
+                errors.extend(Self::validate_aggregation(
+                    &object_types,
+                    object_type,
+                    aggregation,
+                ));
+            }
+        }
+        errors
+    }
+
+    fn validate_aggregation(
+        object_types: &[&ObjectType],
+        object_type: &ObjectType,
+        aggregation: &Directive,
+    ) -> Vec<SchemaValidationError> {
+        let invalid = |reason: &str| {
+            SchemaValidationError::InvalidAggregation(object_type.name.clone(), reason.to_owned())
+        };
+
+        let source = match aggregation.argument("source") {
+            Some(Value::String(source)) => source,
+            _ => return vec![invalid("the `source` argument must be a string")],
+        };
+        let source = match object_types.iter().find(|typ| &typ.name == source) {
+            Some(source) if Self::is_timeseries(source) => source,
+            _ => {
+                return vec![invalid(&format!(
+                    "the source `{}` must be a type declared with @entity(timeseries: true)",
+                    source
+                ))]
+            }
+        };
+
+        let mut errors = vec![];
+        match aggregation.argument("intervals") {
+            Some(Value::List(intervals)) if !intervals.is_empty() => {
+                for interval in intervals {
+                    match interval {
+                        Value::String(interval) => {
+                            if let Err(e) = AggregationInterval::try_from(interval.as_str()) {
+                                errors.push(invalid(&e))
+                            }
+                        }
+                        _ => errors.push(invalid("intervals must be strings")),
+                    }
+                }
+            }
+            _ => errors.push(invalid("the `intervals` argument must be a non-empty list")),
+        }
+
+        // Aggregations use the same timestamp type as their source
+        let timestamp_type = source
+            .field(&Aggregation::TIMESTAMP.to_owned())
+            .map(|field| field.field_type.get_base_type().as_str())
+            .unwrap_or("Int");
+        for (name, typ) in &[
+            (Aggregation::TIMESTAMP, timestamp_type),
+            (Aggregation::INTERVAL, "String"),
+        ] {
+            let expected = Type::NonNullType(Box::new(Type::NamedType(typ.to_string())));
+            match object_type.field(&name.to_string()) {
+                Some(field) if field.field_type == expected => {}
+                _ => errors.push(invalid(&format!(
+                    "the type must have a `{}: {}!` field",
+                    name, typ
+                ))),
+            }
+        }
+
+        for field in &object_type.fields {
+            if field.name == "id"
+                || field.name == Aggregation::TIMESTAMP
+                || field.name == Aggregation::INTERVAL
+            {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                SchemaValidationError::InvalidAggregate(
+                    object_type.name.clone(),
+                    field.name.clone(),
+                    reason.to_owned(),
+                )
+            };
+            let aggregate = match field.find_directive(String::from("aggregate")) {
+                Some(aggregate) => aggregate,
+                None => {
+                    errors.push(invalid("fields of aggregation types must use @aggregate"));
+                    continue;
+                }
+            };
+            let func = match aggregate.argument("fn") {
+                Some(Value::String(func)) => match AggregateFn::try_from(func.as_str()) {
+                    Ok(func) => func,
+                    Err(e) => {
+                        errors.push(invalid(&e));
+                        continue;
+                    }
+                },
+                _ => {
+                    errors.push(invalid(
+                        "the @aggregate directive must have a `fn` argument",
+                    ));
+                    continue;
+                }
+            };
+            let field_type = field.field_type.get_base_type();
+            if func == AggregateFn::Count {
+                if field_type != "Int" && field_type != "BigInt" {
+                    errors.push(invalid("a count must have type Int or BigInt"));
+                }
+                continue;
+            }
+            let arg = match aggregate.argument("arg") {
+                Some(Value::String(arg)) => arg,
+                _ => {
+                    errors.push(invalid(
+                        "the @aggregate directive must have a string `arg` argument",
+                    ));
+                    continue;
+                }
+            };
+            match source.field(arg) {
+                None => errors.push(invalid(&format!(
+                    "field `{}` does not exist on type `{}`",
+                    arg, source.name
+                ))),
+                Some(arg_field) => {
+                    let arg_type = arg_field.field_type.get_base_type();
+                    if !["Int", "BigInt", "BigDecimal"].contains(&arg_type.as_str()) {
+                        errors.push(invalid(&format!(
+                            "field `{}` on type `{}` must be an Int, BigInt or BigDecimal",
+                            arg, source.name
+                        )));
+                    } else if arg_type != field_type {
+                        errors.push(invalid(&format!(
+                            "the field must have the same type as `{}.{}`, i.e., {}",
+                            source.name, arg, arg_type
+                        )));
+                    }
+                }
+            }
+        }
+        errors
+    }
 }
 
 #[test]
@@ -1577,3 +1965,95 @@ type Gravatar @entity {
 
     assert_eq!(schema.validate_fulltext_directives(), vec![]);
 }
+
+#[test]
+fn test_aggregation_validation() {
+    const SOURCE: &str = "
+type Swap @entity(timeseries: true) { id: ID!, timestamp: Int!, amount: BigDecimal!, fee: Int! }
+type Token @entity { id: ID!, name: String! }";
+
+    fn validate(aggregation: &str, errmsg: &str) {
+        let raw = format!("{}\n{}", aggregation, SOURCE);
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        let errors = schema.validate_aggregations();
+        if errmsg == "ok" {
+            assert_eq!(Vec::<SchemaValidationError>::new(), errors);
+        } else {
+            assert_eq!(1, errors.len(), "expected one error for `{}`", aggregation);
+            assert_eq!(errmsg, errors[0].to_string());
+        }
+    }
+
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"hour\", \"day\"]) {
+           id: ID!, timestamp: Int!, interval: String!,
+           volume: BigDecimal! @aggregate(fn: \"sum\", arg: \"amount\")
+           maxFee: Int! @aggregate(fn: \"max\", arg: \"fee\")
+           swaps: Int! @aggregate(fn: \"count\") }",
+        "ok",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Token\", intervals: [\"hour\"]) {
+           id: ID!, timestamp: Int!, interval: String! }",
+        "Type `Stats` has an invalid @aggregation: the source `Token` must be a type \
+         declared with @entity(timeseries: true)",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"week\"]) {
+           id: ID!, timestamp: Int!, interval: String! }",
+        "Type `Stats` has an invalid @aggregation: the interval `week` is invalid. \
+         It must be one of: hour, day",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"hour\"]) {
+           id: ID!, timestamp: Int! }",
+        "Type `Stats` has an invalid @aggregation: the type must have a `interval: String!` field",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"hour\"]) {
+           id: ID!, timestamp: Int!, interval: String!, volume: BigDecimal! }",
+        "Field `volume` in type `Stats` has an invalid @aggregate: \
+         fields of aggregation types must use @aggregate",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"hour\"]) {
+           id: ID!, timestamp: Int!, interval: String!,
+           volume: BigInt! @aggregate(fn: \"sum\", arg: \"amount\") }",
+        "Field `volume` in type `Stats` has an invalid @aggregate: \
+         the field must have the same type as `Swap.amount`, i.e., BigDecimal",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"hour\"]) {
+           id: ID!, timestamp: Int!, interval: String!,
+           volume: BigDecimal! @aggregate(fn: \"avg\", arg: \"amount\") }",
+        "Field `volume` in type `Stats` has an invalid @aggregate: \
+         the function `avg` is invalid. It must be one of: sum, count, min, max",
+    );
+    validate(
+        "type Stats @entity @aggregation(source: \"Swap\", intervals: [\"hour\"]) {
+           id: ID!, timestamp: BigInt!, interval: String! }",
+        "Type `Stats` has an invalid @aggregation: the type must have a `timestamp: Int!` field",
+    );
+    validate(
+        "type Tick @entity(timeseries: true) { id: ID!, timestamp: BigInt! }
+         type TickStats @entity @aggregation(source: \"Tick\", intervals: [\"day\"]) {
+           id: ID!, timestamp: BigInt!, interval: String! }",
+        "ok",
+    );
+    validate(
+        "type Tick @entity(timeseries: true) { id: ID!, timestamp: String! }",
+        "Timeseries type `Tick` must have a `timestamp: Int!` or `timestamp: BigInt!` field",
+    );
+}
+
+#[test]
+fn test_aggregation_bucket_start() {
+    assert_eq!(3600, AggregationInterval::Hour.bucket_start(7199));
+    assert_eq!(7200, AggregationInterval::Hour.bucket_start(7200));
+    assert_eq!(0, AggregationInterval::Day.bucket_start(86399));
+    assert_eq!(
+        "day-86400",
+        Aggregation::bucket_id(AggregationInterval::Day, 86400)
+    );
+}
@@ -241,6 +241,26 @@ impl<'a> TryFrom<&'a BigInt> for u64 {
     }
 }
 
+impl<'a> TryFrom<&'a BigInt> for i64 {
+    type Error = BigIntOutOfRangeError;
+    fn try_from(value: &'a BigInt) -> Result<i64, BigIntOutOfRangeError> {
+        let bytes = value.to_signed_bytes_le();
+
+        if bytes.len() > 8 {
+            return Err(BigIntOutOfRangeError::Overflow);
+        }
+
+        // Sign-extend to 8 bytes
+        let fill = match bytes.last() {
+            Some(b) if b & 0x80 != 0 => 0xff,
+            _ => 0,
+        };
+        let mut buf = [fill; 8];
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        Ok(i64::from_le_bytes(buf))
+    }
+}
+
 impl TryFrom<BigInt> for u64 {
     type Error = BigIntOutOfRangeError;
     fn try_from(value: BigInt) -> Result<u64, BigIntOutOfRangeError> {
@@ -528,6 +548,18 @@ mod test {
         }
     }
 
+    #[test]
+    fn bigint_to_i64() {
+        use std::convert::TryFrom;
+
+        let ns = vec![0, 1, -1, 255, -256, 1 << 40, std::i64::MIN, std::i64::MAX];
+        for n in ns {
+            assert_eq!(n, i64::try_from(&BigInt::from(n)).unwrap());
+        }
+        let too_large = BigInt::from(std::i64::MAX) + BigInt::from(1);
+        assert!(i64::try_from(&too_large).is_err());
+    }
+
     fn crypto_stable_hash(value: impl StableHash) -> <SetHasher as StableHasher>::Out {
         stable_hash::<SetHasher, _>(&value)
     }
@@ -0,0 +1,96 @@
+//! Features that a subgraph declares in the `features` section of its
+//! manifest. Not every node supports every feature; checking them when a
+//! subgraph is deployed keeps nodes that are configured differently from
+//! silently producing different results for the same subgraph
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::prelude::anyhow;
+
+use super::DISABLE_GRAFTS;
+
+lazy_static! {
+    /// Whether mappings may use `ipfs.cat`
+    pub static ref ALLOW_NON_DETERMINISTIC_IPFS: bool =
+        std::env::var("GRAPH_ALLOW_NON_DETERMINISTIC_IPFS").is_ok();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SubgraphFeature {
+    /// `@fulltext` directives in the schema
+    FullTextSearch,
+    /// Starting from the data of another deployment
+    Grafting,
+    /// Calls to `ipfs.cat` from mappings; their results depend on which
+    /// files the IPFS node can find
+    NonDeterministicIpfs,
+    /// Call handlers and block handlers with a `call` filter
+    CallHandlers,
+}
+
+impl SubgraphFeature {
+    pub const ALL: &'static [SubgraphFeature] = &[
+        SubgraphFeature::FullTextSearch,
+        SubgraphFeature::Grafting,
+        SubgraphFeature::NonDeterministicIpfs,
+        SubgraphFeature::CallHandlers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubgraphFeature::FullTextSearch => "fullTextSearch",
+            SubgraphFeature::Grafting => "grafting",
+            SubgraphFeature::NonDeterministicIpfs => "nonDeterministicIpfs",
+            SubgraphFeature::CallHandlers => "callHandlers",
+        }
+    }
+
+    /// Whether this node is configured to index subgraphs that use this
+    /// feature
+    pub fn is_supported(&self) -> bool {
+        match self {
+            SubgraphFeature::FullTextSearch | SubgraphFeature::CallHandlers => true,
+            SubgraphFeature::Grafting => !*DISABLE_GRAFTS,
+            SubgraphFeature::NonDeterministicIpfs => *ALLOW_NON_DETERMINISTIC_IPFS,
+        }
+    }
+
+    /// All features that this node supports
+    pub fn supported() -> Vec<SubgraphFeature> {
+        Self::ALL
+            .iter()
+            .filter(|feature| feature.is_supported())
+            .cloned()
+            .collect()
+    }
+}
+
+impl fmt::Display for SubgraphFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SubgraphFeature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|feature| feature.as_str() == s)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("unknown subgraph feature `{}`", s))
+    }
+}
+
+#[test]
+fn feature_names() {
+    for feature in SubgraphFeature::ALL {
+        let json = serde_json::to_string(feature).unwrap();
+        assert_eq!(format!("\"{}\"", feature), json);
+        assert_eq!(*feature, feature.as_str().parse().unwrap());
+    }
+}
@@ -18,16 +18,18 @@ use web3::types::{Address, H256};
 use crate::components::link_resolver::LinkResolver;
 use crate::components::store::{Store, StoreError, SubgraphDeploymentStore};
 use crate::components::subgraph::DataSourceTemplateInfo;
-use crate::data::graphql::{TryFromValue, ValueMap};
+use crate::data::graphql::{DocumentExt, TryFromValue, ValueMap};
 use crate::data::query::QueryExecutionError;
 use crate::data::schema::{Schema, SchemaImportError, SchemaValidationError};
 use crate::data::store::Entity;
+use crate::data::subgraph::features::SubgraphFeature;
 use crate::data::subgraph::schema::{
     EthereumBlockHandlerEntity, EthereumCallHandlerEntity, EthereumContractAbiEntity,
     EthereumContractDataSourceTemplateEntity, EthereumContractDataSourceTemplateSourceEntity,
     EthereumContractEventHandlerEntity, EthereumContractMappingEntity,
     EthereumContractSourceEntity, SUBGRAPHS_ID,
 };
+use crate::data::version::MAX_SPEC_VERSION;
 use crate::prelude::{
     anyhow::{self, Context},
     format_err, impl_slog_value, BlockNumber, Deserialize, Fail, Serialize, BLOCK_NUMBER_MAX,
@@ -36,6 +38,7 @@ use crate::util::ethereum::string_to_h256;
 use graphql_parser::query as q;
 
 use crate::components::ethereum::NodeCapabilities;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
@@ -52,6 +55,9 @@ lazy_static! {
 /// Rust representation of the GraphQL schema for a `SubgraphManifest`.
 pub mod schema;
 
+/// Features that subgraphs declare in their manifest.
+pub mod features;
+
 /// Deserialize an Address (with or without '0x' prefix).
 fn deserialize_address<'de, D>(deserializer: D) -> Result<Option<Address>, D::Error>
 where
@@ -282,14 +288,20 @@ pub enum SubgraphRegistrarError {
     #[fail(display = "Ethereum network not supported by registrar: {}", _0)]
     NetworkNotSupported(String),
     #[fail(
-        display = "Ethereum nodes for network {} are missing the following capabilities: {}",
+        display = "Ethereum nodes for network {} can not serve this subgraph: {}",
         _0, _1
     )]
-    SubgraphNetworkRequirementsNotSupported(String, NodeCapabilities),
+    SubgraphNetworkRequirementsNotSupported(String, String),
     #[fail(display = "deployment not found: {}", _0)]
     DeploymentNotFound(String),
+    #[fail(display = "deployment already exists: {}", _0)]
+    DeploymentExists(String),
     #[fail(display = "deployment assignment unchanged: {}", _0)]
     DeploymentAssignmentUnchanged(String),
+    #[fail(display = "deployment has not failed: {}", _0)]
+    DeploymentNotFailed(String),
+    #[fail(display = "can not rewind deployment: {}", _0)]
+    RewindError(String),
     #[fail(display = "subgraph registrar internal query error: {}", _0)]
     QueryExecutionError(QueryExecutionError),
     #[fail(display = "subgraph registrar error with store: {}", _0)]
@@ -312,6 +324,7 @@ impl From<StoreError> for SubgraphRegistrarError {
     fn from(e: StoreError) -> Self {
         match e {
             StoreError::DeploymentNotFound(id) => SubgraphRegistrarError::DeploymentNotFound(id),
+            StoreError::DeploymentNotFailed(id) => SubgraphRegistrarError::DeploymentNotFailed(id),
             e => SubgraphRegistrarError::StoreError(e),
         }
     }
@@ -399,6 +412,17 @@ pub enum SubgraphManifestValidationError {
     SchemaValidationError(Vec<SchemaValidationError>),
     #[fail(display = "the graft base is invalid: {}", _0)]
     GraftBaseInvalid(String),
+    #[fail(display = "subgraph data source `{}` is invalid: {}", _0, _1)]
+    SubgraphDataSourceInvalid(String, String), // (data source, reason)
+    #[fail(display = "the data source overrides are invalid: {}", _0)]
+    OverridesInvalid(String),
+    #[fail(
+        display = "subgraph uses the feature `{}` but does not declare it in `features`",
+        _0
+    )]
+    FeatureNotDeclared(SubgraphFeature),
+    #[fail(display = "the feature `{}` is not supported by this node", _0)]
+    FeatureNotSupported(SubgraphFeature),
 }
 
 #[derive(Fail, Debug)]
@@ -454,13 +478,22 @@ impl UnresolvedSchema {
     }
 }
 
+/// The `kind` of data sources whose triggers are the entity changes of
+/// another deployment rather than Ethereum events, calls or blocks
+pub const SUBGRAPH_DATA_SOURCE_KIND: &str = "subgraph";
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct Source {
     #[serde(default, deserialize_with = "deserialize_address")]
     pub address: Option<Address>,
+    /// The ABI of the contract; not used by subgraph data sources
+    #[serde(default)]
     pub abi: String,
     #[serde(rename = "startBlock", default)]
     pub start_block: u64,
+    /// The upstream deployment for subgraph data sources
+    #[serde(default)]
+    pub deployment: Option<SubgraphDeploymentId>,
 }
 
 impl From<EthereumContractSourceEntity> for Source {
@@ -469,6 +502,7 @@ impl From<EthereumContractSourceEntity> for Source {
             address: entity.address,
             abi: entity.abi,
             start_block: entity.start_block,
+            deployment: None,
         }
     }
 }
@@ -591,6 +625,14 @@ impl From<EthereumContractEventHandlerEntity> for MappingEventHandler {
     }
 }
 
+/// A handler that is called with the new version of every entity of type
+/// `entity` that the upstream deployment of a subgraph data source changes
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingEntityHandler {
+    pub entity: String,
+    pub handler: String,
+}
+
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UnresolvedMapping {
@@ -605,6 +647,8 @@ pub struct UnresolvedMapping {
     pub call_handlers: Vec<MappingCallHandler>,
     #[serde(default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(default)]
+    pub entity_handlers: Vec<MappingEntityHandler>,
     pub file: Link,
 }
 
@@ -618,6 +662,7 @@ pub struct Mapping {
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub entity_handlers: Vec<MappingEntityHandler>,
     pub runtime: Arc<Vec<u8>>,
     pub link: Link,
 }
@@ -681,6 +726,7 @@ impl UnresolvedMapping {
             block_handlers,
             call_handlers,
             event_handlers,
+            entity_handlers,
             file: link,
         } = self;
 
@@ -708,6 +754,7 @@ impl UnresolvedMapping {
             block_handlers: block_handlers.clone(),
             call_handlers: call_handlers.clone(),
             event_handlers: event_handlers.clone(),
+            entity_handlers,
             runtime,
             link,
         })
@@ -725,6 +772,7 @@ impl From<EthereumContractMappingEntity> for UnresolvedMapping {
             event_handlers: entity.event_handlers.into_iter().map(Into::into).collect(),
             call_handlers: entity.call_handlers.into_iter().map(Into::into).collect(),
             block_handlers: entity.block_handlers.into_iter().map(Into::into).collect(),
+            entity_handlers: vec![],
             file: entity.file.into(),
         }
     }
@@ -823,6 +871,7 @@ impl TryFrom<DataSourceTemplateInfo> for DataSource {
                 address: Some(address),
                 abi: template.source.abi,
                 start_block: 0,
+                deployment: None,
             },
             mapping: template.mapping,
             context,
@@ -945,6 +994,81 @@ impl Graft {
     }
 }
 
+/// Changes to the data sources of a manifest that are requested when a
+/// deployment is created, e.g., because a contract is known to be broken in
+/// some block range. They are recorded with the deployment and applied
+/// whenever its manifest is loaded; the manifest itself stays unchanged
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataSourceOverrides {
+    /// The block at which to start indexing, by data source name
+    #[serde(default)]
+    pub start_blocks: BTreeMap<String, u64>,
+    /// Data sources for contracts with one of these addresses are not
+    /// indexed at all
+    #[serde(default)]
+    pub excluded_addresses: Vec<Address>,
+}
+
+impl DataSourceOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.start_blocks.is_empty() && self.excluded_addresses.is_empty()
+    }
+
+    /// The id of the deployment of the manifest `hash` with these
+    /// overrides. It differs from `hash`, and from the ids for any other
+    /// overrides, so that the deployment does not share its data with a
+    /// deployment of the same manifest that indexes different data sources
+    pub fn deployment_id(&self, hash: &SubgraphDeploymentId) -> SubgraphDeploymentId {
+        let mut overrides = self.clone();
+        overrides.excluded_addresses.sort();
+        overrides.excluded_addresses.dedup();
+        let json = serde_json::to_string(&overrides).expect("overrides can be serialized to JSON");
+        let digest = tiny_keccak::keccak256(format!("{}:{}", hash, json).as_bytes());
+        // Deployment ids can be at most 46 characters long; the prefix
+        // keeps them apart from IPFS hashes
+        SubgraphDeploymentId::new(format!("ovr{}", &hex::encode(digest)[..43]))
+            .expect("the id for overrides is a valid deployment id")
+    }
+
+    /// Change the data sources of `manifest` according to these overrides
+    /// and remember them in the manifest. Overrides that do not refer to
+    /// any data source in the manifest are an error
+    pub fn apply(
+        &self,
+        manifest: &mut SubgraphManifest,
+    ) -> Result<(), SubgraphManifestValidationError> {
+        let invalid = |msg: String| Err(SubgraphManifestValidationError::OverridesInvalid(msg));
+
+        for name in self.start_blocks.keys() {
+            if !manifest.data_sources.iter().any(|ds| &ds.name == name) {
+                return invalid(format!("there is no data source named `{}`", name));
+            }
+        }
+        for address in &self.excluded_addresses {
+            if !manifest
+                .data_sources
+                .iter()
+                .any(|ds| ds.source.address.as_ref() == Some(address))
+            {
+                return invalid(format!("no data source has the address {:?}", address));
+            }
+        }
+
+        manifest.data_sources.retain(|ds| match &ds.source.address {
+            Some(address) => !self.excluded_addresses.contains(address),
+            None => true,
+        });
+        for ds in manifest.data_sources.iter_mut() {
+            if let Some(block) = self.start_blocks.get(&ds.name) {
+                ds.source.start_block = *block;
+            }
+        }
+        manifest.overrides = Some(self.clone());
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BaseSubgraphManifest<S, D, T> {
@@ -958,6 +1082,19 @@ pub struct BaseSubgraphManifest<S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    /// Whether the mappings of different data sources promise to only
+    /// change the entity types they list in `entities`. When they do,
+    /// triggers for unrelated data sources are processed concurrently
+    #[serde(default)]
+    pub independent_data_sources: bool,
+    /// The features the subgraph uses. Validation adds the ones that the
+    /// manifest uses without declaring them if it declares none at all
+    #[serde(default)]
+    pub features: BTreeSet<SubgraphFeature>,
+    /// The overrides that were applied to the data sources; they never
+    /// come from the manifest file
+    #[serde(skip)]
+    pub overrides: Option<DataSourceOverrides>,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
@@ -975,7 +1112,11 @@ type UnresolvedSubgraphManifest =
 pub type SubgraphManifest = BaseSubgraphManifest<Schema, DataSource, DataSourceTemplate>;
 
 /// Unvalidated SubgraphManifest
-pub struct UnvalidatedSubgraphManifest(SubgraphManifest);
+pub struct UnvalidatedSubgraphManifest {
+    manifest: SubgraphManifest,
+    /// Whether `graft` replaced the graft from the manifest
+    grafted: bool,
+}
 
 impl UnvalidatedSubgraphManifest {
     /// Entry point for resolving a subgraph definition.
@@ -986,9 +1127,42 @@ impl UnvalidatedSubgraphManifest {
         resolver: Arc<impl LinkResolver>,
         logger: &Logger,
     ) -> Result<Self, SubgraphManifestResolveError> {
-        Ok(Self(
-            SubgraphManifest::resolve(link, resolver.deref(), logger).await?,
-        ))
+        Ok(Self {
+            manifest: SubgraphManifest::resolve(link, resolver.deref(), logger).await?,
+            grafted: false,
+        })
+    }
+
+    /// Like `resolve`, but resolve the manifest as the manifest of the
+    /// deployment `id`
+    pub async fn resolve_as(
+        link: Link,
+        id: &SubgraphDeploymentId,
+        resolver: Arc<impl LinkResolver>,
+        logger: &Logger,
+    ) -> Result<Self, SubgraphManifestResolveError> {
+        Ok(Self {
+            manifest: SubgraphManifest::resolve_as(link, id, resolver.deref(), logger).await?,
+            grafted: false,
+        })
+    }
+
+    /// Graft the subgraph onto `base` at `block`, replacing any graft
+    /// that the manifest declares. The manifest does not have to declare
+    /// `grafting` for this graft
+    pub fn graft(mut self, base: SubgraphDeploymentId, block: BlockNumber) -> Self {
+        self.manifest.graft = Some(Graft { base, block });
+        self.grafted = true;
+        self
+    }
+
+    /// Apply `overrides` to the data sources of the manifest
+    pub fn with_overrides(
+        mut self,
+        overrides: &DataSourceOverrides,
+    ) -> Result<Self, SubgraphManifestValidationError> {
+        overrides.apply(&mut self.manifest)?;
+        Ok(self)
     }
 
     pub fn validate<S: Store + SubgraphDeploymentStore>(
@@ -998,7 +1172,10 @@ impl UnvalidatedSubgraphManifest {
         (SubgraphManifest, Vec<SubgraphManifestValidationWarning>),
         Vec<SubgraphManifestValidationError>,
     > {
-        let (schemas, import_errors) = self.0.schema.resolve_schema_references(store.clone());
+        let (schemas, import_errors) = self
+            .manifest
+            .schema
+            .resolve_schema_references(store.clone());
         let validation_warnings = import_errors
             .into_iter()
             .map(SubgraphManifestValidationWarning::SchemaValidationWarning)
@@ -1007,13 +1184,13 @@ impl UnvalidatedSubgraphManifest {
         let mut errors: Vec<SubgraphManifestValidationError> = vec![];
 
         // Validate that the manifest has at least one data source
-        if self.0.data_sources.is_empty() {
+        if self.manifest.data_sources.is_empty() {
             errors.push(SubgraphManifestValidationError::NoDataSources);
         }
 
         // Validate that the manifest has a `source` address in each data source
         // which has call or block handlers
-        if self.0.data_sources.iter().any(|data_source| {
+        if self.manifest.data_sources.iter().any(|data_source| {
             let no_source_address = data_source.source.address.is_none();
             let has_call_handlers = !data_source.mapping.call_handlers.is_empty();
             let has_block_handlers = !data_source.mapping.block_handlers.is_empty();
@@ -1025,7 +1202,7 @@ impl UnvalidatedSubgraphManifest {
 
         // Validate that there are no more than one of each type of
         // block_handler in each data source.
-        let has_too_many_block_handlers = self.0.data_sources.iter().any(|data_source| {
+        let has_too_many_block_handlers = self.manifest.data_sources.iter().any(|data_source| {
             if data_source.mapping.block_handlers.is_empty() {
                 return false;
             }
@@ -1049,12 +1226,54 @@ impl UnvalidatedSubgraphManifest {
             errors.push(SubgraphManifestValidationError::DataSourceBlockHandlerLimitExceeded)
         }
 
+        // Validate that subgraph data sources name an existing upstream
+        // deployment and only have entity handlers
+        for data_source in self
+            .0
+            .data_sources
+            .iter()
+            .filter(|data_source| data_source.kind == SUBGRAPH_DATA_SOURCE_KIND)
+        {
+            let invalid = |reason: &str| {
+                SubgraphManifestValidationError::SubgraphDataSourceInvalid(
+                    data_source.name.clone(),
+                    reason.to_owned(),
+                )
+            };
+            let mapping = &data_source.mapping;
+            match &data_source.source.deployment {
+                None => errors.push(invalid("`source.deployment` is required")),
+                Some(deployment) if deployment == &self.manifest.id => {
+                    errors.push(invalid("a subgraph can not use itself as a data source"))
+                }
+                Some(deployment) => match store.is_deployed(deployment) {
+                    Ok(true) => (),
+                    Ok(false) => errors.push(invalid(&format!(
+                        "the deployment {} does not exist",
+                        deployment
+                    ))),
+                    Err(e) => errors.push(invalid(&e.to_string())),
+                },
+            }
+            if mapping.entity_handlers.is_empty() {
+                errors.push(invalid("at least one entity handler is required"));
+            }
+            if !mapping.event_handlers.is_empty()
+                || !mapping.call_handlers.is_empty()
+                || !mapping.block_handlers.is_empty()
+            {
+                errors.push(invalid(
+                    "only entity handlers are allowed in subgraph data sources",
+                ));
+            }
+        }
+
         let mut networks = self
             .0
             .data_sources
             .iter()
             .cloned()
-            .filter(|d| d.kind.eq("ethereum/contract"))
+            .filter(|d| d.kind == "ethereum/contract" || d.kind == SUBGRAPH_DATA_SOURCE_KIND)
             .filter_map(|d| d.network)
             .collect::<Vec<String>>();
         networks.sort();
@@ -1065,7 +1284,7 @@ impl UnvalidatedSubgraphManifest {
             _ => errors.push(SubgraphManifestValidationError::MultipleEthereumNetworks),
         }
 
-        self.0
+        self.manifest
             .schema
             .validate(&schemas)
             .err()
@@ -1076,17 +1295,39 @@ impl UnvalidatedSubgraphManifest {
                 ));
             });
 
-        if let Some(graft) = &self.0.graft {
-            if *DISABLE_GRAFTS {
-                errors.push(SubgraphManifestValidationError::GraftBaseInvalid(
-                    "Grafting of subgraphs is currently disabled".to_owned(),
-                ));
-            }
+        if let Some(graft) = &self.manifest.graft {
             errors.extend(graft.validate(store));
         }
 
+        // Manifests that declare features must declare all the ones they
+        // use, except for a graft that `graft` added; for older manifests,
+        // we work out what they use
+        let mut manifest = self.manifest;
+        let used_features = manifest.used_features();
+        if manifest.features.is_empty() {
+            manifest.features = used_features;
+        } else {
+            if self.grafted {
+                manifest.features.insert(SubgraphFeature::Grafting);
+            }
+            errors.extend(
+                used_features
+                    .difference(&manifest.features)
+                    .cloned()
+                    .map(SubgraphManifestValidationError::FeatureNotDeclared),
+            );
+        }
+        errors.extend(
+            manifest
+                .features
+                .iter()
+                .filter(|feature| !feature.is_supported())
+                .cloned()
+                .map(SubgraphManifestValidationError::FeatureNotSupported),
+        );
+
         match errors.is_empty() {
-            true => Ok((self.0, validation_warnings)),
+            true => Ok((manifest, validation_warnings)),
             false => Err(errors),
         }
     }
@@ -1101,7 +1342,29 @@ impl SubgraphManifest {
         resolver: &impl LinkResolver,
         logger: &Logger,
     ) -> Result<Self, SubgraphManifestResolveError> {
-        info!(logger, "Resolve manifest"; "link" => &link.link);
+        let id = link.link.trim_start_matches("/ipfs/").to_owned();
+        Self::resolve_with_id(link, id, resolver, logger).await
+    }
+
+    /// Resolve the manifest at `link` as the manifest of the deployment
+    /// `id`, which can be different from the IPFS hash in `link`. That is
+    /// used for deployments that are clones of another deployment
+    pub async fn resolve_as(
+        link: Link,
+        id: &SubgraphDeploymentId,
+        resolver: &impl LinkResolver,
+        logger: &Logger,
+    ) -> Result<Self, SubgraphManifestResolveError> {
+        Self::resolve_with_id(link, id.to_string(), resolver, logger).await
+    }
+
+    async fn resolve_with_id(
+        link: Link,
+        id: String,
+        resolver: &impl LinkResolver,
+        logger: &Logger,
+    ) -> Result<Self, SubgraphManifestResolveError> {
+        info!(logger, "Resolve manifest"; "link" => &link.link, "id" => &id);
 
         let file_bytes = resolver
             .cat(logger, &link)
@@ -1116,12 +1379,8 @@ impl SubgraphManifest {
             .as_mapping_mut()
             .ok_or(SubgraphManifestResolveError::InvalidFormat)?;
 
-        // Inject the IPFS hash as the ID of the subgraph
-        // into the definition.
-        raw_mapping.insert(
-            serde_yaml::Value::from("id"),
-            serde_yaml::Value::from(link.link.trim_start_matches("/ipfs/")),
-        );
+        // Inject the ID of the subgraph into the definition
+        raw_mapping.insert(serde_yaml::Value::from("id"), serde_yaml::Value::from(id));
 
         // Inject the IPFS link as the location of the data
         // source into the definition
@@ -1144,7 +1403,7 @@ impl SubgraphManifest {
         self.data_sources
             .iter()
             .cloned()
-            .filter(|d| &d.kind == "ethereum/contract")
+            .filter(|d| &d.kind == "ethereum/contract" || d.kind == SUBGRAPH_DATA_SOURCE_KIND)
             .filter_map(|d| d.network)
             .next()
             .expect("Validated manifest does not have a network defined on any datasource")
@@ -1157,6 +1416,27 @@ impl SubgraphManifest {
             .collect()
     }
 
+    /// The upstream deployments of all subgraph data sources, together
+    /// with the entity types that have handlers for each of them
+    pub fn source_deployments(&self) -> BTreeMap<SubgraphDeploymentId, Vec<String>> {
+        let mut deployments: BTreeMap<_, Vec<String>> = BTreeMap::new();
+        for data_source in self
+            .data_sources
+            .iter()
+            .filter(|data_source| data_source.kind == SUBGRAPH_DATA_SOURCE_KIND)
+        {
+            if let Some(deployment) = &data_source.source.deployment {
+                let entity_types = deployments.entry(deployment.clone()).or_default();
+                for handler in &data_source.mapping.entity_handlers {
+                    if !entity_types.contains(&handler.entity) {
+                        entity_types.push(handler.entity.clone());
+                    }
+                }
+            }
+        }
+        deployments
+    }
+
     pub fn mappings(&self) -> Vec<Mapping> {
         self.templates
             .iter()
@@ -1192,6 +1472,92 @@ impl SubgraphManifest {
             }),
         }
     }
+
+    /// The features that the manifest, its schema, and its mappings use
+    pub fn used_features(&self) -> BTreeSet<SubgraphFeature> {
+        let mut features = BTreeSet::new();
+        if !self.schema.document.get_fulltext_directives().is_empty() {
+            features.insert(SubgraphFeature::FullTextSearch);
+        }
+        if self.graft.is_some() {
+            features.insert(SubgraphFeature::Grafting);
+        }
+        let mappings = self.mappings();
+        if mappings
+            .iter()
+            .any(|mapping| mapping.calls_host_fn("ipfs.cat"))
+        {
+            features.insert(SubgraphFeature::NonDeterministicIpfs);
+        }
+        if self.requires_traces() {
+            features.insert(SubgraphFeature::CallHandlers);
+        }
+        features
+    }
+
+    /// Divide the data sources and templates into groups such that no two
+    /// groups list the same entity type in their mappings. Returns no
+    /// groups unless the manifest declares `independentDataSources`
+    pub fn data_source_groups(&self) -> Vec<Arc<DataSourceGroup>> {
+        if !self.independent_data_sources {
+            return vec![];
+        }
+
+        let mappings = self
+            .data_sources
+            .iter()
+            .map(|ds| (&ds.name, &ds.mapping.entities))
+            .chain(
+                self.templates
+                    .iter()
+                    .chain(self.data_sources.iter().flat_map(|ds| ds.templates.iter()))
+                    .map(|template| (&template.name, &template.mapping.entities)),
+            );
+
+        let mut groups: Vec<DataSourceGroup> = vec![];
+        for (name, entities) in mappings {
+            let mut group = DataSourceGroup {
+                name: name.clone(),
+                members: BTreeSet::new(),
+                entities: entities.iter().cloned().collect(),
+            };
+            group.members.insert(name.clone());
+
+            // Merge all groups that overlap with this mapping into the
+            // first of them so that groups keep the manifest order
+            let mut pos = None;
+            let mut i = 0;
+            while i < groups.len() {
+                let other = &groups[i];
+                if other.members.contains(name) || !other.entities.is_disjoint(&group.entities) {
+                    let other = groups.remove(i);
+                    if pos.is_none() {
+                        group.name = other.name;
+                        pos = Some(i);
+                    }
+                    group.members.extend(other.members);
+                    group.entities.extend(other.entities);
+                } else {
+                    i += 1;
+                }
+            }
+            groups.insert(pos.unwrap_or(groups.len()), group);
+        }
+        groups.into_iter().map(Arc::new).collect()
+    }
+}
+
+/// Data sources and templates whose mappings may change the same entity
+/// types. Triggers for data sources in different groups can be processed
+/// independently of each other
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataSourceGroup {
+    /// The name of the first data source or template in the group
+    pub name: String,
+    /// The names of all data sources and templates in the group
+    pub members: BTreeSet<String>,
+    /// The entity types the mappings in the group may change
+    pub entities: BTreeSet<String>,
 }
 
 impl UnresolvedSubgraphManifest {
@@ -1210,6 +1576,9 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            independent_data_sources,
+            features,
+            overrides,
         } = self;
 
         match semver::Version::parse(&spec_version) {
@@ -1218,7 +1587,7 @@ impl UnresolvedSubgraphManifest {
             // version. To avoid breaking those, we accept 0.0.3 though it
             // doesn't exist. In the future we should not use 0.0.3 as version
             // and skip to 0.0.4 to avoid ambiguity.
-            Ok(ref ver) if *ver <= semver::Version::new(0, 0, 3) => {}
+            Ok(ref ver) if *ver <= *MAX_SPEC_VERSION => {}
             _ => {
                 return Err(format_err!(
                     "This Graph Node only supports manifest spec versions <= 0.0.2,
@@ -1254,6 +1623,9 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            independent_data_sources,
+            features,
+            overrides,
         })
     }
 }
@@ -34,7 +34,7 @@ use crate::components::store::{
 };
 use crate::data::graphql::{TryFromValue, ValueMap};
 use crate::data::store::{Entity, NodeId, SubgraphEntityPair, Value};
-use crate::data::subgraph::{SubgraphManifest, SubgraphName};
+use crate::data::subgraph::{Link, SubgraphManifest, SubgraphName};
 use crate::prelude::*;
 
 lazy_static! {
@@ -453,12 +453,17 @@ impl SubgraphDeploymentAssignmentEntity {
 
 #[derive(Debug)]
 pub struct SubgraphManifestEntity {
+    location: String,
     spec_version: String,
     description: Option<String>,
     repository: Option<String>,
     schema: String,
     data_sources: Vec<EthereumContractDataSourceEntity>,
     templates: Vec<EthereumContractDataSourceTemplateEntity>,
+    /// The `DataSourceOverrides` of the deployment as JSON
+    overrides: Option<String>,
+    /// The names of the features the subgraph uses
+    features: Vec<String>,
 }
 
 impl TypedEntity for SubgraphManifestEntity {
@@ -471,6 +476,48 @@ impl SubgraphManifestEntity {
         format!("{}-manifest", subgraph_id)
     }
 
+    /// Return the link to the manifest of the deployment `subgraph_id`.
+    /// That is usually the IPFS hash of the deployment itself, but clones
+    /// of a deployment reuse the manifest of the original deployment
+    pub fn location<S: Store + ?Sized>(
+        store: &S,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Link, QueryExecutionError> {
+        let location = store
+            .get(Self::key(Self::id(subgraph_id)))?
+            .and_then(|manifest| match manifest.get("location") {
+                Some(Value::String(link)) => Some(Link { link: link.clone() }),
+                _ => None,
+            });
+        Ok(location.unwrap_or_else(|| subgraph_id.to_ipfs_link()))
+    }
+
+    /// Return the overrides for the data sources that were given when the
+    /// deployment `subgraph_id` was created, if there were any
+    pub fn overrides<S: Store + ?Sized>(
+        store: &S,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<DataSourceOverrides>, QueryExecutionError> {
+        let overrides = store
+            .get(Self::key(Self::id(subgraph_id)))?
+            .and_then(|manifest| match manifest.get("overrides") {
+                Some(Value::String(overrides)) => Some(overrides.clone()),
+                _ => None,
+            });
+        overrides
+            .map(|overrides| {
+                serde_json::from_str(&overrides).map_err(|e| {
+                    StoreError::Unknown(format_err!(
+                        "invalid data source overrides for {}: {}",
+                        subgraph_id,
+                        e
+                    ))
+                    .into()
+                })
+            })
+            .transpose()
+    }
+
     fn write_operations(self, id: &str) -> Vec<MetadataOperation> {
         let mut ops = vec![];
 
@@ -494,12 +541,15 @@ impl SubgraphManifestEntity {
 
         let entity = entity! {
             id: id,
+            location: self.location,
             specVersion: self.spec_version,
             description: self.description,
             repository: self.repository,
             schema: self.schema,
             dataSources: data_source_ids,
             templates: template_ids,
+            overrides: self.overrides,
+            features: self.features,
         };
 
         ops.push(set_metadata_operation(Self::TYPENAME, id, entity));
@@ -511,6 +561,7 @@ impl SubgraphManifestEntity {
 impl<'a> From<&'a super::SubgraphManifest> for SubgraphManifestEntity {
     fn from(manifest: &'a super::SubgraphManifest) -> Self {
         Self {
+            location: manifest.location.clone(),
             spec_version: manifest.spec_version.clone(),
             description: manifest.description.clone(),
             repository: manifest.repository.clone(),
@@ -521,6 +572,14 @@ impl<'a> From<&'a super::SubgraphManifest> for SubgraphManifestEntity {
                 .iter()
                 .map(EthereumContractDataSourceTemplateEntity::from)
                 .collect(),
+            overrides: manifest.overrides.as_ref().map(|overrides| {
+                serde_json::to_string(overrides).expect("overrides can be serialized to JSON")
+            }),
+            features: manifest
+                .features
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
         }
     }
 }
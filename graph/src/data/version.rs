@@ -0,0 +1,61 @@
+use lazy_static::lazy_static;
+use semver::Version;
+use serde::Serialize;
+
+use crate::data::subgraph::features::SubgraphFeature;
+
+/// The manifest spec versions this node can deploy, oldest first. Version
+/// `0.0.3` does not exist, but subgraphs in the wild use it because they
+/// confused it with the mapping API version, and we keep accepting them
+pub const SPEC_VERSIONS: &[&str] = &["0.0.1", "0.0.2", "0.0.3"];
+
+/// The mapping API versions this node can run, oldest first. Version
+/// `0.0.5` adds `ethereum.blockTransactions`
+pub const API_VERSIONS: &[&str] = &["0.0.1", "0.0.2", "0.0.3", "0.0.4", "0.0.5"];
+
+lazy_static! {
+    pub static ref MAX_SPEC_VERSION: Version =
+        Version::parse(SPEC_VERSIONS[SPEC_VERSIONS.len() - 1]).unwrap();
+    pub static ref MAX_API_VERSION: Version =
+        Version::parse(API_VERSIONS[API_VERSIONS.len() - 1]).unwrap();
+}
+
+/// What a node reports about its version and capabilities, so that tooling
+/// can decide which features it can use with it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeVersion {
+    /// The version of the `graph-node` crate
+    pub version: String,
+    /// The git commit the node was built from, if it is known
+    pub commit: Option<String>,
+    pub spec_versions: Vec<String>,
+    pub api_versions: Vec<String>,
+    /// The networks this node is configured to index
+    pub chains: Vec<String>,
+    /// The subgraph features this node is configured to support
+    pub features: Vec<String>,
+}
+
+impl NodeVersion {
+    pub fn new(version: &str, commit: Option<&str>, chains: Vec<String>) -> Self {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        NodeVersion {
+            version: version.to_owned(),
+            commit: commit.map(str::to_owned),
+            spec_versions: strings(SPEC_VERSIONS),
+            api_versions: strings(API_VERSIONS),
+            chains,
+            features: SubgraphFeature::supported()
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn max_versions() {
+    assert_eq!(Version::new(0, 0, 3), *MAX_SPEC_VERSION);
+    assert_eq!(Version::new(0, 0, 5), *MAX_API_VERSION);
+}
@@ -77,11 +77,11 @@ pub mod prelude {
         BlockFinality, BlockStream, BlockStreamBuilder, BlockStreamEvent, BlockStreamMetrics,
         ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream, EthereumAdapter,
         EthereumAdapterError, EthereumBlock, EthereumBlockData, EthereumBlockFilter,
-        EthereumBlockPointer, EthereumBlockTriggerType, EthereumBlockWithCalls,
-        EthereumBlockWithTriggers, EthereumCall, EthereumCallData, EthereumCallFilter,
-        EthereumContractCall, EthereumContractCallError, EthereumEventData, EthereumLogFilter,
-        EthereumNetworkIdentifier, EthereumTransactionData, EthereumTrigger, LightEthereumBlock,
-        LightEthereumBlockExt, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+        EthereumBlockPointer, EthereumBlockTransactionData, EthereumBlockTriggerType,
+        EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall, EthereumCallData,
+        EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumEventData,
+        EthereumLogFilter, EthereumNetworkIdentifier, EthereumTransactionData, EthereumTrigger,
+        LightEthereumBlock, LightEthereumBlockExt, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
     };
     pub use crate::components::graphql::{
         GraphQlRunner, QueryLoadManager, SubscriptionResultFuture,
@@ -93,22 +93,28 @@ pub mod prelude {
         Registry,
     };
     pub use crate::components::server::admin::JsonRpcServer;
+    pub use crate::components::server::api_keys::{ApiKeyError, ApiKeys, REQUIRE_API_KEYS};
+    pub use crate::components::server::auth::{AuthTokens, Role, AUTH_TOKENS};
     pub use crate::components::server::index_node::IndexNodeServer;
     pub use crate::components::server::metrics::MetricsServer;
     pub use crate::components::server::query::GraphQLServer;
     pub use crate::components::server::subscription::SubscriptionServer;
     pub use crate::components::store::{
-        BlockNumber, ChainStore, ChildMultiplicity, EntityCache, EntityChange,
-        EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityLink,
-        EntityModification, EntityOperation, EntityOrder, EntityQuery, EntityRange, EntityWindow,
-        EthereumCallCache, MetadataOperation, ParentLink, PoolWaitStats, QueryStore, Store,
-        StoreError, StoreEvent, StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore,
-        TransactionAbortError, WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL,
+        ApiKey, ApiKeyUsage, BlockNumber, BlockProofOfIndexing, ChainHeadStatus, ChainStore,
+        ChildFilter, ChildMultiplicity, DeploymentInfo, DeploymentSyncRate, EntityAccessStats,
+        EntityCache, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
+        EntityKey, EntityLink, EntityModification, EntityOperation, EntityOrder, EntityQuery,
+        EntityRange, EntityTypeCount, EntityVersion, EntityWindow, EthereumCallCache, Failover,
+        GraftPreview, GraftTableAction, MetadataOperation, NonCanonicalBlock, ParentLink,
+        PoolWaitStats, QueryStore, Store, StoreError, StoreEvent, StoreEventStream,
+        StoreEventStreamBox, SubgraphDeploymentStore, TransactionAbortError, WindowAttribute,
+        BLOCK_NUMBER_MAX, LIVE_QUERY_DEBOUNCE_INTERVAL, SUBSCRIPTION_THROTTLE_INTERVAL,
     };
     pub use crate::components::subgraph::{
-        BlockState, DataSourceLoader, DataSourceTemplateInfo, HostMetrics, RuntimeHost,
-        RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager,
-        SubgraphRegistrar, SubgraphVersionSwitchingMode,
+        BlockState, DataSourceLoader, DataSourceTemplateInfo, HandlerError, HandlerLog,
+        HandlerProfile, HostMetrics, PlacementRules, RuntimeHost, RuntimeHostBuilder,
+        SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager, SubgraphRegistrar,
+        SubgraphVersionSwitchingMode,
     };
     pub use crate::components::{EventConsumer, EventProducer};
 
@@ -128,12 +134,12 @@ pub mod prelude {
     };
     pub use crate::data::subgraph::schema::{SubgraphDeploymentEntity, TypedEntity};
     pub use crate::data::subgraph::{
-        BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext,
-        DataSourceTemplate, DeploymentState, Link, MappingABI, MappingBlockHandler,
-        MappingCallHandler, MappingEventHandler, SubgraphAssignmentProviderError,
-        SubgraphAssignmentProviderEvent, SubgraphDeploymentId, SubgraphManifest,
-        SubgraphManifestResolveError, SubgraphManifestValidationError, SubgraphName,
-        SubgraphRegistrarError, UnvalidatedSubgraphManifest,
+        BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext, DataSourceGroup,
+        DataSourceOverrides, DataSourceTemplate, DeploymentState, Link, MappingABI,
+        MappingBlockHandler, MappingCallHandler, MappingEntityHandler, MappingEventHandler,
+        SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent, SubgraphDeploymentId,
+        SubgraphManifest, SubgraphManifestResolveError, SubgraphManifestValidationError,
+        SubgraphName, SubgraphRegistrarError, UnvalidatedSubgraphManifest,
     };
     pub use crate::data::subscription::{
         QueryResultStream, Subscription, SubscriptionError, SubscriptionResult,
@@ -23,6 +23,8 @@ use slog::*;
 use slog_async;
 use slog_envlogger;
 use slog_term::*;
+use std::str::FromStr;
+use std::time::Duration;
 use std::{env, fmt, io, result};
 
 pub mod codes;
@@ -386,4 +388,19 @@ lazy_static! {
     pub static ref LOG_SQL_TIMING: bool = log_query_timing("sql");
     pub static ref LOG_GQL_TIMING: bool = log_query_timing("gql");
     pub static ref LOG_GQL_CACHE_TIMING: bool = *LOG_GQL_TIMING && log_query_timing("cache");
+
+    /// GraphQL queries that take at least this long are logged as slow
+    /// queries. Slow queries are not logged if this is not set
+    pub static ref SLOW_QUERY_THRESHOLD: Option<Duration> =
+        env::var("GRAPH_LOG_SLOW_QUERY_THRESHOLD")
+            .ok()
+            .map(|s| Duration::from_millis(u64::from_str(&s).unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_LOG_SLOW_QUERY_THRESHOLD")
+            })));
+
+    /// Leave the values of variables out of the slow query log
+    pub static ref SLOW_QUERY_REDACT_VARIABLES: bool =
+        env::var("GRAPH_LOG_SLOW_QUERY_REDACT_VARIABLES")
+            .map(|s| s == "true")
+            .unwrap_or(false);
 }
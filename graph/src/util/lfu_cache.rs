@@ -163,6 +163,32 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
         self.queue.len()
     }
 
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.queue.iter().map(|(entry, _)| &entry.key)
+    }
+
+    /// The estimated size of all entries in the cache, in bytes
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Move the entries whose key satisfies `pred` into a new cache. The
+    /// entries keep their weight and frequency
+    pub fn split_off(&mut self, pred: impl Fn(&K) -> bool) -> Self {
+        let mut other = LfuCache::new();
+        let queue = std::mem::replace(&mut self.queue, PriorityQueue::new());
+        for (entry, priority) in queue {
+            if pred(&entry.key) {
+                self.total_weight -= entry.weight;
+                other.total_weight += entry.weight;
+                other.queue.push(entry, priority);
+            } else {
+                self.queue.push(entry, priority);
+            }
+        }
+        other
+    }
+
     /// Same as `evict_with_period(max_weight, STALE_PERIOD)`
     pub fn evict(&mut self, max_weight: usize) -> Option<(usize, usize, usize)> {
         self.evict_with_period(max_weight, STALE_PERIOD)
@@ -223,9 +249,17 @@ impl<K: Ord + Eq + Hash + 'static, V: 'static> IntoIterator for LfuCache<K, V> {
     }
 }
 
-impl<K: Ord + Eq + Hash, V> Extend<(CacheEntry<K, V>, Priority)> for LfuCache<K, V> {
+impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
+    Extend<(CacheEntry<K, V>, Priority)> for LfuCache<K, V>
+{
     fn extend<T: IntoIterator<Item = (CacheEntry<K, V>, Priority)>>(&mut self, iter: T) {
-        self.queue.extend(iter);
+        for (entry, priority) in iter {
+            // Replace entries we already have so that `total_weight` stays
+            // the sum of the weights of the entries in the queue
+            self.remove(&entry.key);
+            self.total_weight += entry.weight;
+            self.queue.push(entry, priority);
+        }
     }
 }
 
@@ -294,3 +328,42 @@ fn entity_lru_cache() {
     assert!(cache.get(&"alligator").is_none());
     assert_eq!(cache.get(&"lion"), Some(&Weight(lion_inner_weight)));
 }
+
+#[test]
+fn extend_keeps_weight() {
+    let mut cache: LfuCache<&'static str, usize> = LfuCache::new();
+    cache.insert("cow", 1);
+    cache.insert("panda", 2);
+
+    let mut other: LfuCache<&'static str, usize> = LfuCache::new();
+    other.insert("panda", 3);
+    other.insert("lion", 4);
+    let expected = cache.weight("cow") + other.total_weight();
+
+    cache.extend(other);
+    assert_eq!(3, cache.len());
+    assert_eq!(expected, cache.total_weight());
+    assert_eq!(Some(&3), cache.get(&"panda"));
+
+    // Evicting everything must not underflow the total weight
+    cache.evict(0);
+    assert_eq!(0, cache.total_weight());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn split_off_keeps_weight() {
+    let mut cache: LfuCache<&'static str, usize> = LfuCache::new();
+    cache.insert("cow", 1);
+    cache.insert("panda", 2);
+    cache.insert("lion", 3);
+    let total = cache.total_weight();
+
+    let other = cache.split_off(|key| key.starts_with('p') || key.starts_with('l'));
+    assert_eq!(1, cache.len());
+    assert_eq!(2, other.len());
+    assert_eq!(total, cache.total_weight() + other.total_weight());
+    assert_eq!(cache.weight("cow"), cache.total_weight());
+    assert!(!cache.contains_key(&"panda"));
+    assert!(other.contains_key(&"lion"));
+}
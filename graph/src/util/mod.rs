@@ -14,3 +14,9 @@ pub mod error;
 pub mod stats;
 
 pub mod cache_weight;
+
+/// Coordination of shutting down a node.
+pub mod shutdown;
+
+/// A client for S3-compatible object storage.
+pub mod object_store;
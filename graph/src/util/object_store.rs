@@ -0,0 +1,377 @@
+//! A small client for S3-compatible object storage. It can list, read and
+//! write the objects below a prefix of a bucket, which is all that reading
+//! block files from and exporting entities to object storage needs.
+//!
+//! Locations have the form `s3://<bucket>/<prefix>`. Requests go to the
+//! endpoint in `GRAPH_S3_ENDPOINT`, or to AWS if it is not set, and use
+//! path-style addressing so that other S3-compatible services like MinIO
+//! work, too. When `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` are set,
+//! requests are signed with them; otherwise, they are sent unsigned, which
+//! works for public buckets.
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use futures03::SinkExt;
+use rusoto_core::credential::StaticProvider;
+use rusoto_core::{ByteStream, Client, HttpClient, Region, RusotoError};
+use rusoto_s3::{
+    GetObjectError, GetObjectRequest, ListObjectsV2Request, PutObjectRequest, S3Client, S3,
+};
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// How much of a file we read at a time when we upload it
+const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+
+pub struct ObjectStore {
+    client: S3Client,
+    bucket: String,
+    /// The prefix of all objects, either empty or ending in `/`
+    prefix: String,
+}
+
+impl ObjectStore {
+    /// Whether `location` refers to object storage rather than to a local
+    /// path
+    pub fn is_object_store(location: &str) -> bool {
+        location.starts_with("s3://")
+    }
+
+    /// Create a client for the objects at `location`, which must be of the
+    /// form `s3://<bucket>/<prefix>`
+    pub fn from_location(location: &str) -> Result<Self, Error> {
+        let rest = location.strip_prefix("s3://").ok_or_else(|| {
+            anyhow!(
+                "object storage location `{}` must start with s3://",
+                location
+            )
+        })?;
+        let (bucket, prefix) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], rest[pos + 1..].trim_matches('/')),
+            None => (rest, ""),
+        };
+        if bucket.is_empty() {
+            return Err(anyhow!(
+                "object storage location `{}` has no bucket",
+                location
+            ));
+        }
+
+        let name = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| DEFAULT_REGION.to_owned());
+        let region = match std::env::var("GRAPH_S3_ENDPOINT") {
+            Ok(endpoint) => Region::Custom { name, endpoint },
+            Err(_) => name
+                .parse::<Region>()
+                .map_err(|e| anyhow!("invalid AWS region `{}`: {}", name, e))?,
+        };
+        let credentials = match (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key), Ok(secret_key)) => Some(StaticProvider::new(
+                access_key,
+                secret_key,
+                std::env::var("AWS_SESSION_TOKEN").ok(),
+                None,
+            )),
+            _ => None,
+        };
+
+        Self::new(region, credentials, bucket, prefix)
+    }
+
+    fn new(
+        region: Region,
+        credentials: Option<StaticProvider>,
+        bucket: &str,
+        prefix: &str,
+    ) -> Result<Self, Error> {
+        let http = HttpClient::new().map_err(|e| anyhow!("{}", e))?;
+        let client = match credentials {
+            Some(credentials) => Client::new_with(credentials, http),
+            None => Client::new_not_signing(http),
+        };
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        };
+
+        Ok(ObjectStore {
+            client: S3Client::new_with_client(client, region),
+            bucket: bucket.to_owned(),
+            prefix,
+        })
+    }
+
+    /// The names of all objects below the prefix, relative to the prefix
+    pub async fn list(&self) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let request = ListObjectsV2Request {
+                bucket: self.bucket.clone(),
+                prefix: Some(self.prefix.clone()),
+                continuation_token: token.take(),
+                ..Default::default()
+            };
+            let output = self
+                .client
+                .list_objects_v2(request)
+                .await
+                .map_err(|e| self.error("", e))?;
+
+            names.extend(
+                output
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key)
+                    .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_owned))
+                    .filter(|name| !name.is_empty() && !name.ends_with('/')),
+            );
+
+            match output.next_continuation_token {
+                Some(next) if output.is_truncated == Some(true) => token = Some(next),
+                _ => break,
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Read the object `name`, or return `None` if it does not exist
+    pub async fn get(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        let body = match self.get_object(name).await? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let mut buf = Vec::new();
+        body.into_async_read().read_to_end(&mut buf).await?;
+        Ok(Some(Bytes::from(buf)))
+    }
+
+    /// Write `body` to the object `name`, replacing it if it exists
+    pub async fn put(&self, name: &str, body: Vec<u8>) -> Result<(), Error> {
+        self.put_object(name, ByteStream::from(body)).await
+    }
+
+    /// Write the object `name` to the file at `path` as it arrives, and
+    /// return `false` if the object does not exist
+    pub async fn get_file(&self, name: &str, path: &Path) -> Result<bool, Error> {
+        let body = match self.get_object(name).await? {
+            Some(body) => body,
+            None => return Ok(false),
+        };
+        let mut file = tokio::fs::File::create(path).await?;
+        tokio::io::copy(&mut body.into_async_read(), &mut file).await?;
+        file.flush().await?;
+        Ok(true)
+    }
+
+    /// Write the file at `path` to the object `name` as it is read,
+    /// replacing the object if it exists
+    pub async fn put_file(&self, name: &str, path: &Path) -> Result<(), Error> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+
+        // The body has to be `Sync`, which reading the file in a separate
+        // task and passing the chunks through a channel makes it
+        let (mut sender, receiver) =
+            futures03::channel::mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+        crate::task_spawn::spawn(async move {
+            loop {
+                let mut buf = vec![0; UPLOAD_CHUNK_SIZE];
+                let chunk = match file.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        buf.truncate(n);
+                        Ok(Bytes::from(buf))
+                    }
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        // Giving the body its length keeps the request from using chunked
+        // encoding, which S3 does not accept
+        self.put_object(name, ByteStream::new_with_size(receiver, len as usize))
+            .await
+    }
+
+    /// The body of the object `name`, or `None` if it does not exist
+    async fn get_object(&self, name: &str) -> Result<Option<ByteStream>, Error> {
+        let request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: format!("{}{}", self.prefix, name),
+            ..Default::default()
+        };
+        match self.client.get_object(request).await {
+            Ok(output) => Ok(Some(
+                output.body.unwrap_or_else(|| ByteStream::from(Vec::new())),
+            )),
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => Ok(None),
+            // Some S3-compatible services answer requests for missing
+            // objects without an error document
+            Err(RusotoError::Unknown(response)) if response.status.as_u16() == 404 => Ok(None),
+            Err(e) => Err(self.error(name, e)),
+        }
+    }
+
+    async fn put_object(&self, name: &str, body: ByteStream) -> Result<(), Error> {
+        let request = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: format!("{}{}", self.prefix, name),
+            body: Some(body),
+            ..Default::default()
+        };
+        self.client
+            .put_object(request)
+            .await
+            .map(|_| ())
+            .map_err(|e| self.error(name, e))
+    }
+
+    fn error<E: std::error::Error + 'static>(&self, name: &str, e: RusotoError<E>) -> Error {
+        anyhow!(
+            "object storage request for `{}{}` in bucket `{}` failed: {}",
+            self.prefix,
+            name,
+            self.bucket,
+            e
+        )
+    }
+}
+
+/// Serve a bucket `bucket` from memory that only accepts signed requests
+/// and uploads with a known length, and return its endpoint
+#[cfg(test)]
+fn serve_bucket() -> String {
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let objects = Arc::new(Mutex::new(BTreeMap::<String, Vec<u8>>::new()));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let objects = objects.clone();
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let mut parts = line.split_whitespace();
+                    let method = parts.next().unwrap_or("").to_owned();
+                    let target = parts.next().unwrap_or("").to_owned();
+                    let mut length = None;
+                    let mut signed = false;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        let header = header.trim_end().to_lowercase();
+                        if header.is_empty() {
+                            break;
+                        }
+                        if let Some(value) = header.strip_prefix("content-length:") {
+                            length = value.trim().parse::<usize>().ok();
+                        }
+                        signed |= header.starts_with("authorization: aws4-hmac-sha256");
+                    }
+                    let mut body = vec![0; length.unwrap_or(0)];
+                    reader.read_exact(&mut body).unwrap();
+
+                    let mut objects = objects.lock().unwrap();
+                    let (status, response) = match (method.as_str(), target.find('?')) {
+                        _ if !signed => ("403 Forbidden", Vec::new()),
+                        ("PUT", _) if length.is_none() => ("411 Length Required", Vec::new()),
+                        ("PUT", None) => {
+                            objects.insert(target, body);
+                            ("200 OK", Vec::new())
+                        }
+                        ("GET", Some(_)) => {
+                            let keys: String = objects
+                                .keys()
+                                .filter_map(|path| path.strip_prefix("/bucket/"))
+                                .map(|key| format!("<Contents><Key>{}</Key></Contents>", key))
+                                .collect();
+                            let list = format!(
+                                "<ListBucketResult><IsTruncated>false</IsTruncated>{}\
+                                 </ListBucketResult>",
+                                keys
+                            );
+                            ("200 OK", list.into_bytes())
+                        }
+                        ("GET", None) => match objects.get(&target) {
+                            Some(object) => ("200 OK", object.clone()),
+                            None => ("404 Not Found", Vec::new()),
+                        },
+                        _ => ("405 Method Not Allowed", Vec::new()),
+                    };
+                    write!(
+                        stream,
+                        "HTTP/1.1 {}\r\ncontent-length: {}\r\n\r\n",
+                        status,
+                        response.len()
+                    )
+                    .unwrap();
+                    stream.write_all(&response).unwrap();
+                }
+            });
+        }
+    });
+    endpoint
+}
+
+#[tokio::test]
+async fn streams_files() {
+    use std::fs;
+
+    let region = Region::Custom {
+        name: DEFAULT_REGION.to_owned(),
+        endpoint: serve_bucket(),
+    };
+    let credentials = StaticProvider::new_minimal("access".to_owned(), "secret".to_owned());
+    let store = ObjectStore::new(region, Some(credentials), "bucket", "export").unwrap();
+    let dir = std::env::temp_dir().join(format!("graph-object-store-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // Span several chunks, the last one partial
+    let data: Vec<u8> = (0..3 * UPLOAD_CHUNK_SIZE + 17)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    fs::write(dir.join("upload.csv"), &data).unwrap();
+    store
+        .put_file("data.csv", &dir.join("upload.csv"))
+        .await
+        .unwrap();
+    assert_eq!(vec!["data.csv"], store.list().await.unwrap());
+    assert_eq!(
+        Some(Bytes::from(data.clone())),
+        store.get("data.csv").await.unwrap()
+    );
+
+    assert!(store
+        .get_file("data.csv", &dir.join("download.csv"))
+        .await
+        .unwrap());
+    assert_eq!(data, fs::read(dir.join("download.csv")).unwrap());
+    assert!(!store
+        .get_file("missing.csv", &dir.join("missing.csv"))
+        .await
+        .unwrap());
+    assert!(!dir.join("missing.csv").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,90 @@
+//! Coordinate shutting down a node. Once a shutdown has been initiated,
+//! subgraphs do not start processing any more blocks, and the node waits
+//! for the blocks they are in the middle of to be committed before it
+//! exits, so that a restart does not find half-processed blocks.
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// How long a node waits for in-flight work when it shuts down before
+    /// it exits anyway
+    pub static ref SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(
+        std::env::var("GRAPH_SHUTDOWN_TIMEOUT")
+            .unwrap_or("60".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_SHUTDOWN_TIMEOUT")
+    );
+}
+
+/// How often `drain` checks whether all in-flight work has finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks a unit of work, like processing a block, that a shutdown waits
+/// for. The work is finished when the guard is dropped
+pub struct InFlight(());
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Start shutting down; from now on, `in_flight` refuses to start new work
+pub fn initiate() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Start a unit of work that a shutdown has to wait for. Returns `None`
+/// if the node is shutting down and the work should not be started
+pub fn in_flight() -> Option<InFlight> {
+    // Count the work before checking so that `drain` can not miss it
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    let guard = InFlight(());
+    if is_shutting_down() {
+        return None;
+    }
+    Some(guard)
+}
+
+/// Resolve once a shutdown has been initiated, for example to stop
+/// servers from accepting more requests
+pub async fn initiated() {
+    while !is_shutting_down() {
+        tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// The number of units of work that have not finished yet
+pub fn in_flight_count() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+/// Wait until all in-flight work has finished, but not past `deadline`.
+/// Returns `false` if there was still work in flight at the deadline
+pub async fn drain(deadline: Instant) -> bool {
+    while in_flight_count() > 0 {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+    }
+    true
+}
+
+#[test]
+fn refuses_work_while_shutting_down() {
+    let guard = in_flight().expect("work can start before shutting down");
+    initiate();
+    assert!(in_flight().is_none());
+    assert_eq!(1, in_flight_count());
+    drop(guard);
+    assert_eq!(0, in_flight_count());
+}
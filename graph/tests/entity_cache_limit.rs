@@ -0,0 +1,124 @@
+//! Tests for an `EntityCache` that evicts entities it read from the store
+//! once they use more memory than its block limit
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use graph::mock::MockStore;
+use graph::prelude::{
+    Entity, EntityCache, EntityKey, EntityModification, SubgraphDeploymentId, Value,
+};
+
+const BANDS: usize = 50;
+
+/// Much less than what all the bands together need, but enough for a few
+const LIMIT: usize = 5_000;
+
+fn band_key(id: &str) -> EntityKey {
+    EntityKey {
+        subgraph_id: SubgraphDeploymentId::new("entity_cache_limit").unwrap(),
+        entity_type: "Band".into(),
+        entity_id: id.into(),
+    }
+}
+
+/// A band with a long name so that a few of them exceed the cache limit
+fn band(id: &str) -> Entity {
+    Entity::from(vec![
+        ("id", Value::from(id)),
+        ("name", Value::from(id.repeat(50))),
+    ])
+}
+
+/// A store with all the bands that counts how often each of them is read
+fn band_store() -> (Arc<MockStore>, Arc<Mutex<HashMap<String, usize>>>) {
+    let reads = Arc::new(Mutex::new(HashMap::new()));
+    let mut store = MockStore::new();
+    let counter = reads.clone();
+    store.expect_get_mock().returning(move |key| {
+        *counter
+            .lock()
+            .unwrap()
+            .entry(key.entity_id.clone())
+            .or_insert(0) += 1;
+        Ok(Some(band(&key.entity_id)))
+    });
+    store.expect_get_many_mock().returning(|_, ids_for_type| {
+        let mut map = BTreeMap::new();
+        for (entity_type, ids) in ids_for_type {
+            map.insert(
+                entity_type.to_owned(),
+                ids.into_iter().map(band).collect::<Vec<_>>(),
+            );
+        }
+        Ok(map)
+    });
+    (Arc::new(store), reads)
+}
+
+fn reads_of(reads: &Mutex<HashMap<String, usize>>, id: &str) -> usize {
+    reads.lock().unwrap().get(id).cloned().unwrap_or(0)
+}
+
+#[test]
+fn evicted_entities_are_read_again() {
+    let (store, reads) = band_store();
+    let mut cache = EntityCache::new(store.clone());
+    cache.set_block_limit(LIMIT);
+
+    let ids: Vec<String> = (0..BANDS).map(|i| format!("band{}", i)).collect();
+    for _ in 0..2 {
+        for id in &ids {
+            assert_eq!(Some(band(id)), cache.get(&band_key(id)).unwrap());
+        }
+    }
+    // Entities that were evicted had to be read from the store again
+    let total: usize = reads.lock().unwrap().values().sum();
+    assert!(total > BANDS);
+
+    // Setting an entity after removing it reads the current version to
+    // unset the fields that are not set again, even if it was evicted
+    let first = band_key(&ids[0]);
+    cache.remove(first.clone());
+    cache
+        .set(
+            first.clone(),
+            Entity::from(vec![
+                ("id", Value::from(ids[0].as_str())),
+                ("founded", Value::from(1995)),
+            ]),
+        )
+        .unwrap();
+
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(
+        vec![EntityModification::Overwrite {
+            key: first,
+            data: Entity::from(vec![
+                ("id", Value::from(ids[0].as_str())),
+                ("founded", Value::from(1995)),
+            ]),
+        }],
+        result.modifications
+    );
+}
+
+#[test]
+fn least_recently_used_entities_are_evicted() {
+    let (store, reads) = band_store();
+    let mut cache = EntityCache::new(store);
+    cache.set_block_limit(LIMIT);
+
+    // `popular` is used often, but not recently, when the other bands are
+    // read; `recent` is used right before each of them
+    for _ in 0..10 {
+        cache.get(&band_key("popular")).unwrap();
+    }
+    for i in 0..BANDS {
+        cache.get(&band_key("recent")).unwrap();
+        cache.get(&band_key(&format!("band{}", i))).unwrap();
+    }
+    cache.get(&band_key("popular")).unwrap();
+
+    assert_eq!(1, reads_of(&reads, "recent"));
+    assert_eq!(2, reads_of(&reads, "popular"));
+}
@@ -5,9 +5,10 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
+use graph::data::subgraph::features::SubgraphFeature;
 use graph::prelude::{
-    Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
-    UnvalidatedSubgraphManifest,
+    serde_json, DataSourceOverrides, Entity, Link, SubgraphDeploymentId, SubgraphManifest,
+    SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
 };
 
 use test_store::LOGGER;
@@ -55,6 +56,10 @@ const ABI: &str = "[{\"type\":\"function\", \"inputs\": [{\"name\": \"i\",\"type
 
 const MAPPING: &str = "export function handleGet(call: getCall): void {}";
 
+/// A WASM module without any sections, for tests that look at the imports
+/// of mappings
+const EMPTY_MODULE: &str = "\0asm\x01\0\0\0";
+
 async fn resolve_manifest(text: &str) -> SubgraphManifest {
     let mut resolver = TextResolver::default();
     let link = Link::from("/ipfs/Qmmanifest".to_owned());
@@ -75,6 +80,8 @@ async fn resolve_unvalidated(text: &str) -> UnvalidatedSubgraphManifest {
 
     resolver.add(link.link.as_str(), text);
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+    resolver.add("/ipfs/Qmabi", ABI);
+    resolver.add("/ipfs/Qmmodule", EMPTY_MODULE);
 
     UnvalidatedSubgraphManifest::resolve(link, Arc::new(resolver), &LOGGER)
         .await
@@ -118,6 +125,31 @@ specVersion: 0.0.1
     assert_eq!(12345, graft.block);
 }
 
+#[tokio::test]
+async fn clone_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let mut resolver = TextResolver::default();
+    let link = Link::from("/ipfs/Qmmanifest".to_owned());
+    resolver.add(link.link.as_str(), YAML);
+    resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+
+    let clone = SubgraphDeploymentId::new("Qmclone").unwrap();
+    let manifest = SubgraphManifest::resolve_as(link, &clone, &resolver, &LOGGER)
+        .await
+        .expect("Parsing simple manifest works");
+
+    assert_eq!("Qmclone", manifest.id.as_str());
+    assert_eq!("/ipfs/Qmmanifest", manifest.location);
+    assert_eq!(&clone, &manifest.schema.id);
+}
+
 #[test]
 fn graft_invalid_manifest() {
     const YAML: &str = "
@@ -179,6 +211,106 @@ specVersion: 0.0.1
     })
 }
 
+#[test]
+fn undeclared_features() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+graft:
+  base: Qmbase
+  block: 1
+features:
+  - fullTextSearch
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        let manifest = resolve_manifest(YAML).await;
+        assert_eq!(
+            vec![SubgraphFeature::FullTextSearch],
+            manifest.features.into_iter().collect::<Vec<_>>()
+        );
+
+        // The manifest has other problems, too; we only care about the
+        // graft that it does not declare
+        let errors = resolve_unvalidated(YAML)
+            .await
+            .validate(store)
+            .expect_err("Validation must fail");
+        assert_eq!(vec![SubgraphFeature::Grafting], undeclared(errors));
+    })
+}
+
+fn undeclared(errors: Vec<SubgraphManifestValidationError>) -> Vec<SubgraphFeature> {
+    errors
+        .into_iter()
+        .filter_map(|e| match e {
+            SubgraphManifestValidationError::FeatureNotDeclared(feature) => Some(feature),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn clone_with_call_handlers() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Factory
+    network: mainnet
+    source:
+      address: \"0x22843e74c59580b3eaf6c233fa67d8b7c561a835\"
+      abi: Factory
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmodule
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      callHandlers:
+        - function: get(uint256)
+          handler: handleGet
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+    let base = SubgraphDeploymentId::new("Qmbase").unwrap();
+
+    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+        // Cloning grafts the manifest onto the original deployment. A
+        // manifest that does not declare its features still gets all the
+        // ones it uses, and one that does need not declare the graft. The
+        // base does not exist, which we ignore here
+        let errors = resolve_unvalidated(YAML)
+            .await
+            .graft(base.clone(), 1)
+            .validate(store.clone())
+            .expect_err("the graft base does not exist");
+        assert_eq!(Vec::<SubgraphFeature>::new(), undeclared(errors));
+
+        let declared = YAML.replace("specVersion", "features:\n  - callHandlers\nspecVersion");
+        let errors = resolve_unvalidated(&declared)
+            .await
+            .graft(base, 1)
+            .validate(store)
+            .expect_err("the graft base does not exist");
+        assert_eq!(Vec::<SubgraphFeature>::new(), undeclared(errors));
+    })
+}
+
 #[tokio::test]
 async fn parse_call_handlers() {
     const YAML: &str = "
@@ -216,3 +348,192 @@ specVersion: 0.0.1
     assert_eq!("Qmmanifest", manifest.id.as_str());
     assert_eq!(true, requires_traces);
 }
+
+#[tokio::test]
+async fn data_source_overrides() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    network: mainnet
+    source:
+      address: \"0x22843e74c59580b3eaf6c233fa67d8b7c561a835\"
+      abi: Token
+      startBlock: 100
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmabi
+      callHandlers:
+        - function: get(address)
+          handler: handleget
+  - kind: ethereum/contract
+    name: Broken
+    network: mainnet
+    source:
+      address: \"0x0000000000000000000000000000000000000001\"
+      abi: Token
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmabi
+      callHandlers:
+        - function: get(address)
+          handler: handleget
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let overrides: DataSourceOverrides = serde_json::from_str(
+        r#"{
+             "startBlocks": { "Token": 200 },
+             "excludedAddresses": ["0x0000000000000000000000000000000000000001"]
+           }"#,
+    )
+    .expect("overrides are valid JSON");
+
+    let mut manifest = resolve_manifest(YAML).await;
+    overrides
+        .apply(&mut manifest)
+        .expect("overrides refer to existing data sources");
+    assert_eq!(1, manifest.data_sources.len());
+    assert_eq!("Token", manifest.data_sources[0].name);
+    assert_eq!(200, manifest.data_sources[0].source.start_block);
+    assert_eq!(Some(overrides), manifest.overrides);
+
+    let unknown: DataSourceOverrides =
+        serde_json::from_str(r#"{ "startBlocks": { "Unknown": 1 } }"#)
+            .expect("overrides are valid JSON");
+    let mut manifest = resolve_manifest(YAML).await;
+    assert!(matches!(
+        unknown.apply(&mut manifest),
+        Err(SubgraphManifestValidationError::OverridesInvalid(_))
+    ));
+    assert_eq!(2, manifest.data_sources.len());
+    assert_eq!(None, manifest.overrides);
+}
+
+#[test]
+fn data_source_overrides_get_their_own_deployment_id() {
+    let hash = SubgraphDeploymentId::new("QmUmg7BZC1YP1ca66rRtWKxpXp77WgVHrnv263JtDuvs2k").unwrap();
+    let overrides = |json: &str| -> DataSourceOverrides {
+        serde_json::from_str(json).expect("overrides are valid JSON")
+    };
+    let both = overrides(
+        r#"{ "excludedAddresses": ["0x0000000000000000000000000000000000000001",
+                                   "0x0000000000000000000000000000000000000002"] }"#,
+    );
+    let both_reversed = overrides(
+        r#"{ "excludedAddresses": ["0x0000000000000000000000000000000000000002",
+                                   "0x0000000000000000000000000000000000000001"] }"#,
+    );
+    let one =
+        overrides(r#"{ "excludedAddresses": ["0x0000000000000000000000000000000000000001"] }"#);
+
+    let id = both.deployment_id(&hash);
+    assert_ne!(hash, id);
+    assert_eq!(id, both_reversed.deployment_id(&hash));
+    assert_ne!(id, one.deployment_id(&hash));
+    let other = SubgraphDeploymentId::new("QmOther").unwrap();
+    assert_ne!(id, both.deployment_id(&other));
+}
+
+#[tokio::test]
+async fn independent_data_source_groups() {
+    const YAML: &str = "
+independentDataSources: true
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    network: mainnet
+    source:
+      abi: Token
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Token
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmabi
+  - kind: ethereum/contract
+    name: Pool
+    network: mainnet
+    source:
+      abi: Token
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Pool
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmabi
+  - kind: ethereum/contract
+    name: Swap
+    network: mainnet
+    source:
+      abi: Token
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - Pool
+        - Swap
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Token
+          file:
+            /: /ipfs/Qmabi
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let groups = manifest.data_source_groups();
+    assert_eq!(2, groups.len());
+    assert_eq!("Token", groups[0].name);
+    assert_eq!(vec!["Token"], groups[0].members.iter().collect::<Vec<_>>());
+    assert_eq!("Pool", groups[1].name);
+    assert_eq!(
+        vec!["Pool", "Swap"],
+        groups[1].members.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec!["Pool", "Swap"],
+        groups[1].entities.iter().collect::<Vec<_>>()
+    );
+
+    let manifest = resolve_manifest(&YAML.replace("independentDataSources: true", "")).await;
+    assert!(manifest.data_source_groups().is_empty());
+}
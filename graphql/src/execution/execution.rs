@@ -293,6 +293,10 @@ where
     /// Time at which the query times out.
     pub deadline: Option<Instant>,
 
+    /// Canceled when nobody is waiting for the result of the query anymore;
+    /// the store uses it to abort database queries that are still running
+    pub cancel: Option<CancelHandle>,
+
     /// Max value for `first`.
     pub max_first: u32,
 
@@ -354,6 +358,7 @@ where
             resolver: introspection_resolver,
             query: self.query.as_introspection_query(),
             deadline: self.deadline,
+            cancel: self.cancel.clone(),
             max_first: std::u32::MAX,
             max_skip: std::u32::MAX,
 
@@ -421,12 +426,15 @@ pub fn execute_root_selection_set_uncached(
     Ok(values)
 }
 
-/// Executes the root selection set of a query.
+/// Executes the root selection set of a query. The `cancel_guard` is
+/// dropped once the query has run, or when this future is dropped; it
+/// should be the guard for `ctx.cancel`
 pub async fn execute_root_selection_set<R: Resolver>(
     ctx: Arc<ExecutionContext<R>>,
     selection_set: Arc<q::SelectionSet>,
     root_type: Arc<s::ObjectType>,
     block_ptr: Option<EthereumBlockPointer>,
+    cancel_guard: CancelGuard,
 ) -> Arc<QueryResult> {
     // Cache the cache key to not have to calculate it twice - once for lookup
     // and once for insert.
@@ -468,6 +476,10 @@ pub async fn execute_root_selection_set<R: Resolver>(
     let execute_root_type = root_type.cheap_clone();
     let nested_resolver = ctx.nested_resolver;
     let run_query = async move {
+        // Queries shared through the herd cache only get canceled once all
+        // the clients waiting for them went away and this future is dropped
+        let _cancel_guard = cancel_guard;
+
         // Limiting the cuncurrent queries prevents increase in resource usage when the DB is
         // contended and queries start queing up. This semaphore organizes the queueing so that
         // waiting queries consume few resources.
@@ -2,8 +2,9 @@ use graphql_parser::{query as q, schema as s};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use graph::data::graphql::{
     ext::{DocumentExt, TypeExt},
@@ -12,7 +13,9 @@ use graph::data::graphql::{
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
 use graph::data::schema::ApiSchema;
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
-use graph::prelude::{info, o, BlockNumber, CheapClone, Logger, QueryExecutionError};
+use graph::prelude::{
+    info, o, serde_json, warn, BlockNumber, CheapClone, Logger, QueryExecutionError,
+};
 
 use crate::execution::{get_field, get_named_type, object_or_interface};
 use crate::introspection::introspection_schema;
@@ -67,6 +70,85 @@ impl<'a> std::fmt::Display for SelectedFields<'a> {
     }
 }
 
+/// What the slow query log shows instead of literal values when it is
+/// redacted
+const REDACTED: &str = "?";
+
+/// Replace the literal scalars in `value` with a placeholder
+fn redact_value(value: &mut q::Value) {
+    match value {
+        q::Value::Int(_) | q::Value::Float(_) | q::Value::String(_) | q::Value::Boolean(_) => {
+            *value = q::Value::Enum(REDACTED.to_owned())
+        }
+        q::Value::List(values) => values.iter_mut().for_each(redact_value),
+        q::Value::Object(object) => object.values_mut().for_each(redact_value),
+        q::Value::Variable(_) | q::Value::Enum(_) | q::Value::Null => (),
+    }
+}
+
+fn redact_arguments(arguments: &mut Vec<(q::Name, q::Value)>) {
+    arguments
+        .iter_mut()
+        .for_each(|(_, value)| redact_value(value))
+}
+
+fn redact_selection_set(selection_set: &mut q::SelectionSet) {
+    for selection in &mut selection_set.items {
+        match selection {
+            q::Selection::Field(field) => {
+                redact_arguments(&mut field.arguments);
+                for directive in &mut field.directives {
+                    redact_arguments(&mut directive.arguments);
+                }
+                redact_selection_set(&mut field.selection_set);
+            }
+            q::Selection::FragmentSpread(spread) => {
+                for directive in &mut spread.directives {
+                    redact_arguments(&mut directive.arguments);
+                }
+            }
+            q::Selection::InlineFragment(fragment) => {
+                for directive in &mut fragment.directives {
+                    redact_arguments(&mut directive.arguments);
+                }
+                redact_selection_set(&mut fragment.selection_set);
+            }
+        }
+    }
+}
+
+/// The text of the query made of `selection_set` and `fragments` on one
+/// line. With `redact`, literal values are replaced with a placeholder so
+/// that the text only shows the shape of the query
+fn query_text(
+    selection_set: &q::SelectionSet,
+    fragments: &HashMap<String, q::FragmentDefinition>,
+    redact: bool,
+) -> String {
+    let mut selection_set = selection_set.clone();
+    let mut fragments: Vec<_> = fragments.values().cloned().collect();
+    fragments.sort_by(|a, b| a.name.cmp(&b.name));
+    if redact {
+        redact_selection_set(&mut selection_set);
+        for fragment in &mut fragments {
+            for directive in &mut fragment.directives {
+                redact_arguments(&mut directive.arguments);
+            }
+            redact_selection_set(&mut fragment.selection_set);
+        }
+    }
+
+    let operation = q::Definition::Operation(q::OperationDefinition::SelectionSet(selection_set));
+    let document = q::Document {
+        definitions: std::iter::once(operation)
+            .chain(fragments.into_iter().map(q::Definition::Fragment))
+            .collect(),
+    };
+    document
+        .format(&graphql_parser::Style::default().indent(0))
+        .replace('\n', " ")
+}
+
 /// A GraphQL query that has been preprocessed and checked and is ready
 /// for execution. Checking includes validating all query fields and, if
 /// desired, checking the query's complexity
@@ -95,6 +177,18 @@ pub struct Query {
     pub variables_text: Arc<String>,
     pub query_id: String,
     pub(crate) complexity: u64,
+
+    /// Timing information for the slow query log
+    parse_time: Duration,
+    validation_time: Duration,
+    store_stats: StoreStats,
+}
+
+/// How many store queries a GraphQL query caused, and how long they took
+#[derive(Default)]
+struct StoreStats {
+    queries: AtomicU64,
+    micros: AtomicU64,
 }
 
 impl Query {
@@ -152,6 +246,7 @@ impl Query {
             "query_id" => query_id.clone()
         ));
 
+        let parse_time = query.parse_time;
         let mut query = Self {
             schema: query.schema,
             variables,
@@ -166,10 +261,14 @@ impl Query {
             variables_text: query.variables_text.cheap_clone(),
             query_id,
             complexity: 0,
+            parse_time,
+            validation_time: Duration::from_secs(0),
+            store_stats: StoreStats::default(),
         };
 
         query.validate_fields()?;
         query.check_complexity(max_complexity, max_depth)?;
+        query.validation_time = query.start.elapsed();
 
         Ok(Arc::new(query))
     }
@@ -224,6 +323,10 @@ impl Query {
             variables_text: self.variables_text.clone(),
             query_id: self.query_id.clone(),
             complexity: self.complexity,
+            parse_time: self.parse_time,
+            validation_time: self.validation_time,
+            // Introspection does not use the store
+            store_stats: StoreStats::default(),
         })
     }
 
@@ -252,16 +355,56 @@ impl Query {
 
     /// Log details about the overall execution of the query
     pub fn log_execution(&self, block: BlockNumber) {
+        let query_time = self.start.elapsed();
         if *graph::log::LOG_GQL_TIMING {
             info!(
                 &self.logger,
                 "Query timing (GraphQL)";
                 "query" => &self.query_text,
                 "variables" => &self.variables_text,
-                "query_time_ms" => self.start.elapsed().as_millis(),
+                "query_time_ms" => query_time.as_millis(),
                 "block" => block,
             );
         }
+        match *graph::log::SLOW_QUERY_THRESHOLD {
+            Some(threshold) if query_time >= threshold => self.log_slow_query(query_time, block),
+            _ => (),
+        }
+    }
+
+    /// Build the log message for a slow query. The query and variables
+    /// text is only put together here since formatting it for every query
+    /// would be wasted on the ones that are not slow
+    fn log_slow_query(&self, query_time: Duration, block: BlockNumber) {
+        let redact = *graph::log::SLOW_QUERY_REDACT_VARIABLES;
+        let variables = if redact {
+            let mut names: Vec<_> = self.variables.keys().map(String::as_str).collect();
+            names.sort();
+            format!("(redacted: {})", names.join(";"))
+        } else {
+            serde_json::to_string(&QueryVariables::new(self.variables.clone())).unwrap_or_default()
+        };
+        warn!(
+            &self.logger,
+            "Slow query";
+            "fingerprint" => format!("{:x}", self.shape_hash),
+            "query_time_ms" => query_time.as_millis(),
+            "parse_time_ms" => self.parse_time.as_millis(),
+            "validation_time_ms" => self.validation_time.as_millis(),
+            "store_time_ms" => self.store_stats.micros.load(Ordering::SeqCst) / 1000,
+            "store_queries" => self.store_stats.queries.load(Ordering::SeqCst),
+            "block" => block,
+            "query" => query_text(&self.selection_set, &self.fragments, redact),
+            "variables" => variables,
+        );
+    }
+
+    /// Record that running part of this query in the store took `duration`
+    pub(crate) fn record_store_query(&self, duration: Duration) {
+        self.store_stats.queries.fetch_add(1, Ordering::SeqCst);
+        self.store_stats
+            .micros
+            .fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
     }
 
     /// Log details about how the part of the query corresponding to
@@ -571,3 +714,59 @@ fn coerce_variable(
         )]
     })
 }
+
+#[cfg(test)]
+fn slow_query_text(query: &str, redact: bool) -> String {
+    let mut selection_set = None;
+    let mut fragments = HashMap::new();
+    for defn in graphql_parser::parse_query(query).unwrap().definitions {
+        match defn {
+            q::Definition::Operation(q::OperationDefinition::Query(query)) => {
+                selection_set = Some(query.selection_set)
+            }
+            q::Definition::Fragment(fragment) => {
+                fragments.insert(fragment.name.clone(), fragment);
+            }
+            _ => unreachable!("the test queries are plain queries"),
+        }
+    }
+    query_text(&selection_set.unwrap(), &fragments, redact)
+}
+
+#[test]
+fn redacts_literals_in_slow_queries() {
+    const QUERY: &str = "query users($skip: Int) { \
+        users(first: 10, skip: $skip, where: { name_in: [\"alice\", \"bob\"], active: true }, \
+              orderBy: name, orderDirection: desc) { \
+          id @include(if: false) ...Balances \
+          ... on User { age(scale: 1.5) } \
+        } \
+      } \
+      fragment Balances on User { balance(token: \"0xabc\", min: null) }";
+
+    let redacted = slow_query_text(QUERY, true);
+    for part in &[
+        "first: ?",
+        "skip: $skip",
+        "active: ?",
+        "name_in: [?, ?]",
+        "orderBy: name",
+        "orderDirection: desc",
+        "@include(if: ?)",
+        "...Balances",
+        "age(scale: ?)",
+        "fragment Balances on User",
+        "balance(token: ?, min: null)",
+    ] {
+        assert!(redacted.contains(part), "`{}` is in `{}`", part, redacted);
+    }
+    assert!(!redacted.contains('\n'));
+    for literal in &["10", "alice", "bob", "true", "false", "1.5", "0xabc"] {
+        assert!(!redacted.contains(literal), "`{}` is redacted", literal);
+    }
+
+    let full = slow_query_text(QUERY, false);
+    for literal in &["10", "\"alice\"", "true", "false", "1.5", "\"0xabc\""] {
+        assert!(full.contains(literal), "`{}` is kept", literal);
+    }
+}
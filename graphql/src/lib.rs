@@ -32,7 +32,7 @@ pub mod prelude {
     pub use super::introspection::{introspection_schema, IntrospectionResolver};
     pub use super::query::{execute_query, ext::BlockConstraint, QueryExecutionOptions};
     pub use super::schema::{api_schema, ast::validate_entity, APISchemaError};
-    pub use super::store::{build_query, StoreResolver};
+    pub use super::store::{build_query, build_query_with_schema, StoreResolver};
     pub use super::subscription::{execute_subscription, SubscriptionExecutionOptions};
     pub use super::values::{object_value, IntoValue, MaybeCoercible};
 
@@ -1,4 +1,6 @@
-use graph::prelude::{CheapClone, EthereumBlockPointer, QueryExecutionError, QueryResult};
+use graph::prelude::{
+    CancelGuard, CheapClone, EthereumBlockPointer, QueryExecutionError, QueryResult,
+};
 use graphql_parser::query as q;
 use std::sync::Arc;
 use std::time::Instant;
@@ -43,11 +45,13 @@ where
     R: Resolver,
 {
     // Create a fresh execution context
+    let cancel_guard = CancelGuard::new();
     let ctx = Arc::new(ExecutionContext {
         logger: query.logger.clone(),
         resolver: options.resolver,
         query: query.clone(),
         deadline: options.deadline,
+        cancel: Some(cancel_guard.handle()),
         max_first: options.max_first,
         max_skip: options.max_skip,
         cache_status: Default::default(),
@@ -72,6 +76,7 @@ where
         selection_set.cheap_clone(),
         query_type,
         block_ptr,
+        cancel_guard,
     )
     .await;
     let elapsed = start.elapsed();
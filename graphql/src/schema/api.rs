@@ -255,7 +255,7 @@ fn add_filter_type(
     let filter_type_name = format!("{}_filter", type_name).to_string();
     match ast::get_named_type(schema, &filter_type_name) {
         None => {
-            let input_values = field_input_values(schema, fields)?;
+            let mut input_values = field_input_values(schema, fields)?;
 
             // Don't generate an input object with no fields, this makes the JS
             // graphql library, which graphiql uses, very confused and graphiql
@@ -264,12 +264,26 @@ fn add_filter_type(
             if input_values.is_empty() {
                 return Ok(());
             }
+
+            // Filters can be combined with `and` and `or`, unless the type
+            // has fields with these names, whose filters take precedence
+            for combinator in &["and", "or"] {
+                if input_values.iter().any(|value| &value.name == combinator) {
+                    continue;
+                }
+                input_values.push(input_value(
+                    &combinator.to_string(),
+                    "",
+                    Type::ListType(Box::new(Type::NamedType(filter_type_name.clone()))),
+                ));
+            }
+
             let typedef = TypeDefinition::InputObject(InputObjectType {
                 position: Pos::default(),
                 description: None,
                 name: filter_type_name,
                 directives: vec![],
-                fields: field_input_values(schema, fields)?,
+                fields: input_values,
             });
             let def = Definition::TypeDefinition(typedef);
             schema.definitions.push(def);
@@ -292,10 +306,30 @@ fn field_input_values(
             &field,
             &field.field_type,
         )?);
+        // A field that is literally called `<field>_` takes precedence
+        // over the child filter for `field`
+        let child_filter_name = format!("{}_", field.name);
+        if fields.iter().all(|field| field.name != child_filter_name) {
+            input_values.extend(field_child_filter_input_value(schema, &field));
+        }
     }
     Ok(input_values)
 }
 
+/// Generates a `<field>_` input value for fields that refer to entities of
+/// an object type. It makes it possible to filter by the fields of the
+/// referenced entities with `where: { <field>_: { ... } }`
+fn field_child_filter_input_value(schema: &Document, field: &Field) -> Option<InputValue> {
+    match ast::get_type_definition_from_type(schema, &field.field_type)? {
+        TypeDefinition::Object(object_type) => Some(input_value(
+            &format!("{}_", field.name),
+            "",
+            Type::NamedType(format!("{}_filter", object_type.name)),
+        )),
+        _ => None,
+    }
+}
+
 /// Generates `*_filter` input values for the given field.
 fn field_filter_input_values(
     schema: &Document,
@@ -937,6 +971,7 @@ mod tests {
                 "pets_not",
                 "pets_contains",
                 "pets_not_contains",
+                "pets_",
                 "favoritePet",
                 "favoritePet_not",
                 "favoritePet_gt",
@@ -951,11 +986,74 @@ mod tests {
                 "favoritePet_not_starts_with",
                 "favoritePet_ends_with",
                 "favoritePet_not_ends_with",
+                "favoritePet_",
+                "leastFavoritePet_",
+                "mostFavoritePets_",
+                "and",
+                "or",
             ]
             .iter()
             .map(|name| name.to_string())
             .collect::<Vec<String>>()
         );
+
+        let pets_filter = filter_type
+            .fields
+            .iter()
+            .find(|field| field.name == "pets_")
+            .expect("User_filter does not have a `pets_` field");
+        assert_eq!(
+            pets_filter.value_type,
+            Type::NamedType("Pet_filter".to_owned())
+        );
+    }
+
+    #[test]
+    fn api_schema_omits_filter_combinators_named_like_fields() {
+        let input_schema = parse_schema("type Gate { id: ID!, and: Boolean!, name: String! }")
+            .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let filter_type = match ast::get_named_type(&schema, &"Gate_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Gate_filter is missing or not an input object"),
+        };
+        let types: Vec<_> = filter_type
+            .fields
+            .iter()
+            .filter(|field| field.name == "and" || field.name == "or")
+            .map(|field| (field.name.as_str(), &field.value_type))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("and", &Type::NamedType("Boolean".to_owned())),
+                (
+                    "or",
+                    &Type::ListType(Box::new(Type::NamedType("Gate_filter".to_owned())))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn api_schema_omits_child_filters_named_like_fields() {
+        let input_schema =
+            parse_schema("type Pet { id: ID! } type User { id: ID!, pet: Pet!, pet_: String! }")
+                .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let filter_type = match ast::get_named_type(&schema, &"User_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("User_filter is missing or not an input object"),
+        };
+        let types: Vec<_> = filter_type
+            .fields
+            .iter()
+            .filter(|field| field.name == "pet_")
+            .map(|field| &field.value_type)
+            .collect();
+        assert_eq!(types, vec![&Type::NamedType("String".to_owned())]);
     }
 
     #[test]
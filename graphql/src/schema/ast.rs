@@ -24,10 +24,13 @@ pub(crate) enum FilterOp {
     EndsWith,
     NotEndsWith,
     Equal,
+    Child,
 }
 
 /// Split a "name_eq" style name into an attribute ("name") and a filter op (`Equal`).
-pub(crate) fn parse_field_as_filter(key: &Name) -> (Name, FilterOp) {
+/// Since field names can end in `_`, a name like `owner_` is only a child
+/// filter if `owner` is a field of `entity` and `owner_` is not
+pub(crate) fn parse_field_as_filter(entity: ObjectOrInterface, key: &Name) -> (Name, FilterOp) {
     let (suffix, op) = match key {
         k if k.ends_with("_not") => ("_not", FilterOp::Not),
         k if k.ends_with("_gt") => ("_gt", FilterOp::GreaterThan),
@@ -42,11 +45,17 @@ pub(crate) fn parse_field_as_filter(key: &Name) -> (Name, FilterOp) {
         k if k.ends_with("_not_ends_with") => ("_not_ends_with", FilterOp::NotEndsWith),
         k if k.ends_with("_starts_with") => ("_starts_with", FilterOp::StartsWith),
         k if k.ends_with("_ends_with") => ("_ends_with", FilterOp::EndsWith),
+        k if k.ends_with("_")
+            && get_field(entity, &k[..k.len() - 1].to_owned()).is_some()
+            && get_field(entity, k).is_none() =>
+        {
+            ("_", FilterOp::Child)
+        }
         _ => ("", FilterOp::Equal),
     };
 
     // Strip the operator suffix to get the attribute.
-    (key.trim_end_matches(suffix).to_owned(), op)
+    (key[..key.len() - suffix.len()].to_owned(), op)
 }
 
 pub fn get_root_query_type_def(schema: &Document) -> Option<&TypeDefinition> {
@@ -12,15 +12,15 @@ use std::time::Instant;
 
 use graph::data::graphql::*;
 use graph::prelude::{
-    ApiSchema, BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink,
-    EntityOrder, EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore,
+    ApiSchema, BlockNumber, CancelHandle, ChildMultiplicity, EntityCollection, EntityFilter,
+    EntityLink, EntityOrder, EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore,
     Value as StoreValue, WindowAttribute,
 };
 
 use crate::execution::{ExecutionContext, Resolver};
 use crate::query::ast as qast;
 use crate::schema::ast as sast;
-use crate::store::{build_query, StoreResolver};
+use crate::store::{build_query_with_schema, StoreResolver};
 
 lazy_static! {
     static ref ARG_FIRST: String = String::from("first");
@@ -795,20 +795,25 @@ fn execute_field(
     } else {
         ChildMultiplicity::Single
     };
-    fetch(
+    let start = Instant::now();
+    let result = fetch(
         ctx.logger.clone(),
         resolver.store.as_ref(),
         parents,
         &join,
         argument_values,
         multiplicity,
+        ctx.query.schema.document(),
         ctx.query.schema.types_for_interface(),
         resolver.block_number(),
         ctx.max_first,
         ctx.max_skip,
         ctx.query.query_id.clone(),
-    )
-    .map_err(|e| vec![e])
+        ctx.deadline,
+        ctx.cancel.clone(),
+    );
+    ctx.query.record_store_query(start.elapsed());
+    result.map_err(|e| vec![e])
 }
 
 /// Query child entities for `parents` from the store. The `join` indicates
@@ -821,21 +826,27 @@ fn fetch(
     join: &Join<'_>,
     arguments: HashMap<&q::Name, q::Value>,
     multiplicity: ChildMultiplicity,
+    schema: &s::Document,
     types_for_interface: &BTreeMap<s::Name, Vec<s::ObjectType>>,
     block: BlockNumber,
     max_first: u32,
     max_skip: u32,
     query_id: String,
+    deadline: Option<Instant>,
+    cancel: Option<CancelHandle>,
 ) -> Result<Vec<Node>, QueryExecutionError> {
-    let mut query = build_query(
+    let mut query = build_query_with_schema(
         join.child_type,
         block,
         &arguments,
+        schema,
         types_for_interface,
         max_first,
         max_skip,
     )?;
     query.query_id = Some(query_id);
+    query.deadline = deadline;
+    query.cancel = cancel;
 
     if multiplicity == ChildMultiplicity::Single {
         // Suppress 'order by' in lookups of scalar values since
@@ -13,13 +13,40 @@ enum OrderDirection {
     Descending,
 }
 
-/// Builds a EntityQuery from GraphQL arguments.
+/// Builds a EntityQuery from GraphQL arguments. Filters on the fields of
+/// referenced entities need the schema and are rejected; use
+/// `build_query_with_schema` to allow them.
+pub fn build_query<'a>(
+    entity: impl Into<ObjectOrInterface<'a>>,
+    block: BlockNumber,
+    arguments: &HashMap<&q::Name, q::Value>,
+    types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
+    max_first: u32,
+    max_skip: u32,
+) -> Result<EntityQuery, QueryExecutionError> {
+    let schema = s::Document {
+        definitions: vec![],
+    };
+    build_query_with_schema(
+        entity,
+        block,
+        arguments,
+        &schema,
+        types_for_interface,
+        max_first,
+        max_skip,
+    )
+}
+
+/// Builds a EntityQuery from GraphQL arguments, looking up the entities
+/// that filters on the fields of referenced entities apply to in `schema`.
 ///
 /// Panics if `entity` is not present in `schema`.
-pub fn build_query<'a>(
+pub fn build_query_with_schema<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
     block: BlockNumber,
     arguments: &HashMap<&q::Name, q::Value>,
+    schema: &s::Document,
     types_for_interface: &BTreeMap<Name, Vec<ObjectType>>,
     max_first: u32,
     max_skip: u32,
@@ -34,7 +61,7 @@ pub fn build_query<'a>(
     });
     let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
         .range(build_range(arguments, max_first, max_skip)?);
-    if let Some(filter) = build_filter(entity, arguments)? {
+    if let Some(filter) = build_filter(entity, arguments, schema)? {
         query = query.filter(filter);
     }
     let order = match (
@@ -99,9 +126,10 @@ fn build_range(
 fn build_filter(
     entity: ObjectOrInterface,
     arguments: &HashMap<&q::Name, q::Value>,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     match arguments.get(&"where".to_string()) {
-        Some(q::Value::Object(object)) => build_filter_from_object(entity, object),
+        Some(q::Value::Object(object)) => build_filter_from_object(entity, object, schema),
         Some(q::Value::Null) => Ok(None),
         None => match arguments.get(&"text".to_string()) {
             Some(q::Value::Object(filter)) => build_fulltext_filter_from_object(filter),
@@ -134,6 +162,7 @@ fn build_fulltext_filter_from_object(
 fn build_filter_from_object(
     entity: ObjectOrInterface,
     object: &BTreeMap<q::Name, q::Value>,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     Ok(Some(EntityFilter::And({
         object
@@ -141,7 +170,19 @@ fn build_filter_from_object(
             .map(|(key, value)| {
                 use self::sast::FilterOp::*;
 
-                let (field_name, op) = sast::parse_field_as_filter(key);
+                // Entities with fields called `and` or `or` do not have
+                // these combinators
+                match key.as_str() {
+                    "and" if sast::get_field(entity, key).is_none() => {
+                        return Ok(EntityFilter::And(build_filter_list(entity, value, schema)?))
+                    }
+                    "or" if sast::get_field(entity, key).is_none() => {
+                        return Ok(EntityFilter::Or(build_filter_list(entity, value, schema)?))
+                    }
+                    _ => (),
+                }
+
+                let (field_name, op) = sast::parse_field_as_filter(entity, key);
 
                 let field = sast::get_field(entity, &field_name).ok_or_else(|| {
                     QueryExecutionError::EntityFieldError(
@@ -150,6 +191,10 @@ fn build_filter_from_object(
                     )
                 })?;
 
+                if let Child = op {
+                    return build_child_filter(field, value, schema);
+                }
+
                 let ty = &field.field_type;
                 let store_value = Value::from_query_value(value, &ty)?;
 
@@ -168,12 +213,65 @@ fn build_filter_from_object(
                     EndsWith => EntityFilter::EndsWith(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
                     Equal => EntityFilter::Equal(field_name, store_value),
+                    Child => unreachable!("child filters are handled above"),
                 })
             })
             .collect::<Result<Vec<EntityFilter>, QueryExecutionError>>()?
     })))
 }
 
+/// Parses the value of an `and` or `or` filter, a list of filters on
+/// `entity`, into the filters that should be combined
+fn build_filter_list(
+    entity: ObjectOrInterface,
+    value: &q::Value,
+    schema: &s::Document,
+) -> Result<Vec<EntityFilter>, QueryExecutionError> {
+    match value {
+        q::Value::List(values) => values
+            .iter()
+            .map(|value| match value {
+                q::Value::Object(object) => Ok(build_filter_from_object(entity, object, schema)?
+                    .unwrap_or(EntityFilter::And(vec![]))),
+                _ => Err(QueryExecutionError::InvalidFilterError),
+            })
+            .collect(),
+        _ => Err(QueryExecutionError::InvalidFilterError),
+    }
+}
+
+/// Parses the value of a `<field>_` filter into a filter on the entities
+/// that `field` refers to. Only fields whose type is an object type can be
+/// filtered that way
+fn build_child_filter(
+    field: &s::Field,
+    value: &q::Value,
+    schema: &s::Document,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let child_type = match sast::get_type_definition_from_type(schema, &field.field_type) {
+        Some(s::TypeDefinition::Object(child_type)) => child_type,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let object = match value {
+        q::Value::Object(object) => object,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+
+    let (attr, derived) = match sast::get_derived_from_field(child_type, field) {
+        Some(derived_from) => (derived_from.name.clone(), true),
+        None => (field.name.clone(), false),
+    };
+    let filter = build_filter_from_object(child_type.into(), object, schema)?
+        .unwrap_or(EntityFilter::And(vec![]));
+
+    Ok(EntityFilter::Child(ChildFilter {
+        attr,
+        entity_type: child_type.name.clone(),
+        filter: Box::new(filter),
+        derived,
+    }))
+}
+
 /// Parses a list of GraphQL values into a vector of entity field values.
 fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecutionError> {
     match value {
@@ -284,6 +382,8 @@ pub fn parse_subgraph_id<'a>(
 }
 
 /// Recursively collects entities involved in a query field as `(subgraph ID, name)` tuples.
+/// Fields of interface type involve all entities that implement the interface. Fields
+/// selected through fragments are only seen if the fragments are inline fragments
 pub fn collect_entities_from_query_field(
     schema: &s::Document,
     object_type: &s::ObjectType,
@@ -300,29 +400,35 @@ pub fn collect_entities_from_query_field(
         // Check if the field exists on the object type
         if let Some(field_type) = sast::get_field(object_type, &field.name) {
             // Check if the field type corresponds to a type definition (in a valid schema,
-            // this should always be the case)
-            if let Some(type_definition) = sast::get_type_definition_from_field(schema, field_type)
-            {
-                // If the field's type definition is an object type, extract that type
-                if let s::TypeDefinition::Object(object_type) = type_definition {
-                    // Only collect whether the field's type has an @entity directive
-                    if sast::get_object_type_directive(object_type, String::from("entity"))
-                        .is_some()
-                    {
-                        // Obtain the subgraph ID from the object type
-                        if let Ok(subgraph_id) = parse_subgraph_id(object_type) {
-                            // Add the (subgraph_id, entity_name) tuple to the result set
-                            entities.insert((subgraph_id, object_type.name.to_owned()));
-                        }
+            // this should always be the case), and find the object types the field can
+            // resolve to
+            let object_types = match sast::get_type_definition_from_field(schema, field_type) {
+                Some(s::TypeDefinition::Object(object_type)) => vec![object_type],
+                Some(s::TypeDefinition::Interface(interface)) => {
+                    sast::get_object_type_definitions(schema)
+                        .into_iter()
+                        .filter(|object_type| {
+                            object_type.implements_interfaces.contains(&interface.name)
+                        })
+                        .collect()
+                }
+                _ => vec![],
+            };
+
+            for object_type in object_types {
+                // Only collect whether the field's type has an @entity directive
+                if sast::get_object_type_directive(object_type, String::from("entity")).is_some() {
+                    // Obtain the subgraph ID from the object type
+                    if let Ok(subgraph_id) = parse_subgraph_id(object_type) {
+                        // Add the (subgraph_id, entity_name) tuple to the result set
+                        entities.insert((subgraph_id, object_type.name.to_owned()));
                     }
+                }
 
-                    // If the query field has a non-empty selection set, this means we
-                    // need to recursively process it
-                    for selection in field.selection_set.items.iter() {
-                        if let q::Selection::Field(sub_field) = selection {
-                            queue.push_back((&object_type, sub_field))
-                        }
-                    }
+                // If the query field has a non-empty selection set, this means we
+                // need to recursively process it
+                for sub_field in selected_fields(&field.selection_set) {
+                    queue.push_back((object_type, sub_field))
                 }
             }
         }
@@ -331,6 +437,21 @@ pub fn collect_entities_from_query_field(
     entities.into_iter().collect()
 }
 
+/// The fields in `selection_set`, including the ones selected by inline fragments
+fn selected_fields(selection_set: &q::SelectionSet) -> Vec<&q::Field> {
+    let mut fields = Vec::new();
+    for selection in &selection_set.items {
+        match selection {
+            q::Selection::Field(field) => fields.push(field),
+            q::Selection::InlineFragment(fragment) => {
+                fields.extend(selected_fields(&fragment.selection_set))
+            }
+            q::Selection::FragmentSpread(_) => (),
+        }
+    }
+    fields
+}
+
 #[cfg(test)]
 mod tests {
     use graphql_parser::{
@@ -724,4 +845,80 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_yields_equal_filter_for_field_ending_in_underscore() {
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "name_".to_string(),
+                q::Value::String("hello".to_string()),
+            )])),
+        );
+        assert_eq!(
+            build_query(
+                &ObjectType {
+                    fields: vec![field("name_", Type::NamedType("string".to_owned()))],
+                    ..default_object()
+                },
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::Equal(
+                "name_".to_string(),
+                Value::String("hello".to_string()),
+            )]))
+        )
+    }
+
+    #[test]
+    fn build_query_yields_or_filters() {
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        let name_is = |name: &str| {
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "name".to_string(),
+                q::Value::String(name.to_string()),
+            )]))
+        };
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "or".to_string(),
+                q::Value::List(vec![name_is("Alice"), name_is("Bob")]),
+            )])),
+        );
+        let name_eq = |name: &str| {
+            EntityFilter::And(vec![EntityFilter::Equal(
+                "name".to_string(),
+                Value::String(name.to_string()),
+            )])
+        };
+        assert_eq!(
+            build_query(
+                &ObjectType {
+                    fields: vec![field("name", Type::NamedType("string".to_owned()))],
+                    ..default_object()
+                },
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::Or(vec![
+                name_eq("Alice"),
+                name_eq("Bob")
+            ])]))
+        )
+    }
 }
@@ -66,6 +66,7 @@ where
         resolver: options.resolver,
         query: query.clone(),
         deadline: None,
+        cancel: None,
         max_first: options.max_first,
         max_skip: options.max_skip,
         cache_status: Default::default(),
@@ -73,9 +74,11 @@ where
         nested_resolver: false,
     };
 
-    if !query.is_subscription() {
+    // Queries are run as live queries: they are re-executed whenever one
+    // of the entities they touch changes
+    if !query.is_subscription() && !query.is_query() {
         return Err(SubscriptionError::from(QueryExecutionError::NotSupported(
-            "Only subscriptions are supported".to_string(),
+            "Only subscriptions and live queries are supported".to_string(),
         )));
     }
 
@@ -83,6 +86,7 @@ where
         ctx.logger,
         "Execute subscription";
         "query" => &query.query_text,
+        "live" => query.is_query(),
     );
 
     let source_stream = create_source_event_stream(&ctx)?;
@@ -93,6 +97,10 @@ where
 fn create_source_event_stream(
     ctx: &ExecutionContext<impl Resolver>,
 ) -> Result<StoreEventStreamBox, SubscriptionError> {
+    if ctx.query.is_query() {
+        return create_live_query_event_stream(ctx);
+    }
+
     let subscription_type = ctx
         .query
         .schema
@@ -121,6 +129,93 @@ fn create_source_event_stream(
     resolve_field_stream(ctx, &subscription_type, field, argument_values)
 }
 
+/// Merge the change streams of all top-level fields of a live query into
+/// one stream. Bursts of changes are debounced so that the query is not
+/// re-executed for every single block while a subgraph is busy
+fn create_live_query_event_stream(
+    ctx: &ExecutionContext<impl Resolver>,
+) -> Result<StoreEventStreamBox, SubscriptionError> {
+    let query_type = ctx.query.schema.query_type.as_ref();
+
+    let grouped_field_set = collect_fields(
+        ctx,
+        query_type,
+        iter::once(ctx.query.selection_set.as_ref()),
+    );
+
+    if grouped_field_set.is_empty() {
+        return Err(SubscriptionError::from(QueryExecutionError::EmptyQuery));
+    }
+
+    let mut merged: Box<dyn Stream<Item = Arc<StoreEvent>, Error = ()> + Send> =
+        Box::new(stream::empty());
+    // All fields with the same response key are merged into one, and we
+    // need to watch what each of them selects
+    for field in grouped_field_set.values().flatten().copied() {
+        // Introspection fields never change
+        if field.name.starts_with("__") {
+            continue;
+        }
+        let argument_values = coerce_argument_values(&ctx, query_type, field)?;
+        // The resolver can only see nested fields that fragments select if
+        // the fragments are inline fragments
+        let field = q::Field {
+            selection_set: inline_fragment_spreads(ctx, &field.selection_set, &mut vec![]),
+            ..field.clone()
+        };
+        let field_stream = resolve_field_stream(ctx, query_type, &field, argument_values)?;
+        merged = Box::new(merged.select(field_stream));
+    }
+
+    Ok(StoreEventStream::new(merged).debounce(&ctx.logger, *LIVE_QUERY_DEBOUNCE_INTERVAL))
+}
+
+/// Replace the fragment spreads in `selection_set` with inline fragments
+/// that select the same fields. `inlining` holds the fragments whose spreads
+/// we are replacing already; a fragment that spreads itself, directly or
+/// through other fragments, is only inlined once
+fn inline_fragment_spreads<'a>(
+    ctx: &'a ExecutionContext<impl Resolver>,
+    selection_set: &'a q::SelectionSet,
+    inlining: &mut Vec<&'a q::Name>,
+) -> q::SelectionSet {
+    let items = selection_set
+        .items
+        .iter()
+        .filter_map(|selection| match selection {
+            q::Selection::Field(field) => Some(q::Selection::Field(q::Field {
+                selection_set: inline_fragment_spreads(ctx, &field.selection_set, inlining),
+                ..field.clone()
+            })),
+            q::Selection::InlineFragment(fragment) => {
+                Some(q::Selection::InlineFragment(q::InlineFragment {
+                    selection_set: inline_fragment_spreads(ctx, &fragment.selection_set, inlining),
+                    ..fragment.clone()
+                }))
+            }
+            q::Selection::FragmentSpread(spread) => {
+                if inlining.contains(&&spread.fragment_name) {
+                    return None;
+                }
+                let fragment = ctx.query.get_fragment(&spread.fragment_name);
+                inlining.push(&spread.fragment_name);
+                let selection_set = inline_fragment_spreads(ctx, &fragment.selection_set, inlining);
+                inlining.pop();
+                Some(q::Selection::InlineFragment(q::InlineFragment {
+                    position: spread.position,
+                    type_condition: Some(fragment.type_condition.clone()),
+                    directives: spread.directives.clone(),
+                    selection_set,
+                }))
+            }
+        })
+        .collect();
+    q::SelectionSet {
+        span: selection_set.span,
+        items,
+    }
+}
+
 fn resolve_field_stream(
     ctx: &ExecutionContext<impl Resolver>,
     object_type: &s::ObjectType,
@@ -154,6 +249,12 @@ fn map_source_to_response_stream(
         changes: Default::default(),
     }))]);
 
+    // Live queries only send a response when it differs from the one that
+    // was sent last; a change to an entity the query touches does not
+    // necessarily change the result of the query
+    let live = query.is_query();
+    let mut last_response: Option<String> = None;
+
     Box::new(
         trigger_stream
             .chain(source_stream.compat())
@@ -173,6 +274,20 @@ fn map_source_to_response_stream(
                     load_manager.cheap_clone(),
                 )
                 .boxed(),
+            })
+            .filter(move |result: &Arc<QueryResult>| {
+                let changed = if live {
+                    let response = serde_json::to_string(result.as_ref()).ok();
+                    if response.is_some() && response == last_response {
+                        false
+                    } else {
+                        last_response = response;
+                        true
+                    }
+                } else {
+                    true
+                };
+                futures03::future::ready(changed)
             }),
     )
 }
@@ -190,11 +305,13 @@ async fn execute_subscription_event(
     debug!(logger, "Execute subscription event"; "event" => format!("{:?}", event));
 
     // Create a fresh execution context with deadline.
+    let cancel_guard = CancelGuard::new();
     let ctx = Arc::new(ExecutionContext {
         logger,
         resolver,
         query,
         deadline: timeout.map(|t| Instant::now() + t),
+        cancel: Some(cancel_guard.handle()),
         max_first,
         max_skip,
         cache_status: Default::default(),
@@ -202,16 +319,21 @@ async fn execute_subscription_event(
         nested_resolver: false,
     });
 
-    let subscription_type = match ctx.query.schema.subscription_type.as_ref() {
-        Some(t) => t.cheap_clone(),
-        None => return Arc::new(QueryExecutionError::NoRootSubscriptionObjectType.into()),
+    let root_type = if ctx.query.is_query() {
+        ctx.query.schema.query_type.cheap_clone()
+    } else {
+        match ctx.query.schema.subscription_type.as_ref() {
+            Some(t) => t.cheap_clone(),
+            None => return Arc::new(QueryExecutionError::NoRootSubscriptionObjectType.into()),
+        }
     };
 
     execute_root_selection_set(
         ctx.cheap_clone(),
         ctx.query.selection_set.cheap_clone(),
-        subscription_type,
+        root_type,
         None,
+        cancel_guard,
     )
     .await
 }
@@ -2,7 +2,7 @@
 extern crate pretty_assertions;
 
 use graphql_parser::{query as q, Pos};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -11,9 +11,9 @@ use graph::prelude::{
     async_trait, futures03::stream::StreamExt, futures03::FutureExt, futures03::TryFutureExt, o,
     slog, tokio, ApiSchema, DeploymentState, Entity, EntityKey, EntityOperation,
     EthereumBlockPointer, FutureExtension, GraphQlRunner as _, Logger, Query, QueryError,
-    QueryExecutionError, QueryLoadManager, QueryResult, QueryVariables, Schema, Store,
-    SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphManifest, SubgraphVersionSwitchingMode,
-    Subscription, SubscriptionError, Value, BLOCK_NUMBER_MAX,
+    QueryExecutionError, QueryLoadManager, QueryResult, QueryResultStream, QueryVariables, Schema,
+    Store, SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphManifest,
+    SubgraphVersionSwitchingMode, Subscription, SubscriptionError, Value, BLOCK_NUMBER_MAX,
 };
 use graph::{
     data::query::CacheStatus,
@@ -97,6 +97,9 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        independent_data_sources: false,
+        features: BTreeSet::new(),
+        overrides: None,
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
@@ -1223,6 +1226,43 @@ fn can_filter_by_relationship_fields() {
     })
 }
 
+#[test]
+fn can_combine_filters_with_or() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            musicians(orderBy: id, where: { or: [{ name: \"John\" }, { mainBand: \"b2\" }] }) {
+                id name
+            }
+        }
+        ",
+            )
+            .expect("invalid test query"),
+        )
+        .await;
+
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![(
+                "musicians",
+                q::Value::List(vec![
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("m1"))),
+                        ("name", q::Value::String(String::from("John"))),
+                    ]),
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("m3"))),
+                        ("name", q::Value::String(String::from("Tom"))),
+                    ]),
+                ])
+            )]))
+        );
+    })
+}
+
 #[test]
 fn cannot_filter_by_derved_relationship_fields() {
     run_test_sequentially(setup, |_, id| async move {
@@ -1318,6 +1358,181 @@ fn subscription_gets_result_even_without_events() {
     })
 }
 
+#[test]
+fn live_query_gets_result_even_without_events() {
+    run_test_sequentially(setup, |_, id| async move {
+        let logger = Logger::root(slog::Discard, o!());
+        let store = STORE.clone().query_store(true);
+        let store_resolver = StoreResolver::for_subscription(&logger, id.clone(), store);
+
+        let query = Query::new(
+            Arc::new(api_test_schema(&id)),
+            graphql_parser::parse_query(
+                "query {
+              musicians(orderBy: id, first: 2) {
+                name
+              }
+              bands(orderBy: id, first: 1) {
+                name
+              }
+            }",
+            )
+            .unwrap(),
+            None,
+            None,
+        );
+
+        let options = SubscriptionExecutionOptions {
+            logger: logger.clone(),
+            resolver: store_resolver,
+            timeout: None,
+            max_complexity: None,
+            max_depth: 100,
+            max_first: std::u32::MAX,
+            max_skip: std::u32::MAX,
+            load_manager: mock_query_load_manager(),
+        };
+
+        // A plain query is executed as a live query and produces its
+        // current result right away
+        let stream = execute_subscription(Subscription { query }, options).unwrap();
+        let results: Vec<_> = stream
+            .take(1)
+            .collect()
+            .map(Result::<_, ()>::Ok)
+            .compat()
+            .timeout(Duration::from_secs(3))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+        assert_eq!(
+            extract_data!(result.as_ref().clone()),
+            Some(object_value(vec![
+                (
+                    "musicians",
+                    q::Value::List(vec![
+                        object_value(vec![("name", q::Value::String(String::from("John")))]),
+                        object_value(vec![("name", q::Value::String(String::from("Lisa")))])
+                    ])
+                ),
+                (
+                    "bands",
+                    q::Value::List(vec![object_value(vec![(
+                        "name",
+                        q::Value::String(String::from("The Musicians"))
+                    )])])
+                )
+            ])),
+        );
+    })
+}
+
+/// Wait for the next result of a live query and return its data
+async fn next_live_data(stream: &mut QueryResultStream) -> Option<q::Value> {
+    let result = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("timed out waiting for a live query result")
+        .expect("live query stream ended");
+    extract_data!(result.as_ref().clone())
+}
+
+#[test]
+fn live_query_follows_nested_entities() {
+    run_test_sequentially(setup, |_, id| async move {
+        use test_store::block_store::{BLOCK_THREE, BLOCK_TWO};
+
+        let logger = Logger::root(slog::Discard, o!());
+        let store = STORE.clone().query_store(true);
+        let store_resolver = StoreResolver::for_subscription(&logger, id.clone(), store);
+
+        // The band is only selected through a fragment
+        let query = Query::new(
+            Arc::new(api_test_schema(&id)),
+            graphql_parser::parse_query(
+                "query {
+              musician(id: \"m1\") {
+                name
+                ...band
+              }
+            }
+
+            fragment band on Musician {
+              mainBand {
+                name
+              }
+            }",
+            )
+            .unwrap(),
+            None,
+            None,
+        );
+
+        let options = SubscriptionExecutionOptions {
+            logger: logger.clone(),
+            resolver: store_resolver,
+            timeout: None,
+            max_complexity: None,
+            max_depth: 100,
+            max_first: std::u32::MAX,
+            max_skip: std::u32::MAX,
+            load_manager: mock_query_load_manager(),
+        };
+
+        let set_name = |entity_type: &str, entity_id: &str, name: &str| EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: id.clone(),
+                entity_type: entity_type.to_owned(),
+                entity_id: entity_id.to_owned(),
+            },
+            data: Entity::from(vec![
+                ("id", Value::from(entity_id)),
+                ("name", Value::from(name)),
+            ]),
+        };
+        let block_two = (BLOCK_TWO.block_hash(), BLOCK_TWO.number).into();
+        let block_three = (BLOCK_THREE.block_hash(), BLOCK_THREE.number).into();
+
+        let mut stream = execute_subscription(Subscription { query }, options).unwrap();
+        assert_eq!(
+            next_live_data(&mut stream).await,
+            Some(object!(musician: object!(
+                name: "John",
+                mainBand: object!(name: "The Musicians")
+            )))
+        );
+
+        // Changing another musician makes the query run again, but since
+        // its result stays the same, nothing is sent
+        transact_entity_operations(
+            &*STORE,
+            id.clone(),
+            block_two,
+            vec![set_name("Musician", "m2", "Lisa Marie")],
+        )
+        .unwrap();
+        tokio::time::delay_for(Duration::from_millis(1500)).await;
+
+        // Changing the band of the musician changes the result
+        transact_entity_operations(
+            &*STORE,
+            id.clone(),
+            block_three,
+            vec![set_name("Band", "b1", "The Professionals")],
+        )
+        .unwrap();
+        assert_eq!(
+            next_live_data(&mut stream).await,
+            Some(object!(musician: object!(
+                name: "John",
+                mainBand: object!(name: "The Professionals")
+            )))
+        );
+    })
+}
+
 #[test]
 fn can_use_nested_filter() {
     run_test_sequentially(setup, |_, id| async move {
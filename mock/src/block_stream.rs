@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use futures::sync::mpsc::{channel, Receiver, Sender};
 
 use graph::prelude::*;
@@ -57,6 +59,7 @@ impl BlockStreamBuilder for MockBlockStreamBuilder {
         _: EthereumCallFilter,
         _: EthereumBlockFilter,
         _: bool,
+        _: BTreeMap<SubgraphDeploymentId, Vec<String>>,
         _: Arc<BlockStreamMetrics>,
     ) -> Self::Stream {
         MockBlockStream::new()
@@ -1,6 +1,6 @@
 use mockall::predicate::*;
 use mockall::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use graph::components::store::*;
 use graph::data::subgraph::schema::*;
@@ -11,6 +11,13 @@ use web3::types::{Address, H256};
 mock! {
     pub Store {
         fn get_mock(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
+
+        fn block_ptr_mock(
+            &self,
+            subgraph_id: SubgraphDeploymentId,
+        ) -> Result<Option<EthereumBlockPointer>, Error>;
+
+        fn subscribe_mock(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox;
     }
 
     trait SubgraphDeploymentStore: Send + Sync + 'static {
@@ -57,9 +64,9 @@ mock! {
 impl Store for MockStore {
     fn block_ptr(
         &self,
-        _subgraph_id: SubgraphDeploymentId,
+        subgraph_id: SubgraphDeploymentId,
     ) -> Result<Option<EthereumBlockPointer>, Error> {
-        unimplemented!()
+        self.block_ptr_mock(subgraph_id)
     }
 
     fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
@@ -90,15 +97,19 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+    fn get_block_proof_of_indexing(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_number: BlockNumber,
+    ) -> Result<Option<BlockProofOfIndexing>, StoreError> {
         unimplemented!()
     }
 
-    fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
         unimplemented!()
     }
 
-    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
+    fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
         unimplemented!()
     }
 
@@ -129,8 +140,8 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
-        unimplemented!()
+    fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        self.subscribe_mock(entities)
     }
 
     fn deployment_state_from_name(&self, _: SubgraphName) -> Result<DeploymentState, StoreError> {
@@ -172,6 +183,77 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn unassign_subgraph(&self, _: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn rewind_failed_deployment(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_infos(
+        &self,
+        _: Option<&SubgraphName>,
+        _: Option<&SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, StoreError> {
+        unimplemented!()
+    }
+
+    fn api_key(&self, _: &str) -> Result<Option<ApiKey>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_api_key_usage(&self, _: Vec<ApiKeyUsage>) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_block_ptrs(
+        &self,
+        _: &str,
+    ) -> Result<Vec<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError> {
+        unimplemented!()
+    }
+
+    fn indexed_block_ptrs(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: BlockNumber,
+        _: BlockNumber,
+    ) -> Result<Vec<EthereumBlockPointer>, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_non_canonical_block(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: Option<NonCanonicalBlock>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn non_canonical_block(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Option<NonCanonicalBlock>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_heartbeat(&self, _: &NodeId, _: bool, _: &[String]) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn record_stop(&self, _: &NodeId) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn fail_over_dead_nodes(&self, _: Duration, _: Duration) -> Result<Vec<Failover>, StoreError> {
+        unimplemented!()
+    }
+
     fn start_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -201,6 +283,75 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn entity_counts(&self, _: &SubgraphDeploymentId) -> Result<Vec<EntityTypeCount>, StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_access_stats(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityAccessStats>, StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_history(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+        _: &str,
+        _: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_sync_rate(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<DeploymentSyncRate, StoreError> {
+        unimplemented!()
+    }
+
+    fn chain_head_statuses(&self) -> Result<Vec<ChainHeadStatus>, StoreError> {
+        unimplemented!()
+    }
+
+    fn record_handler_logs(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _logs: Vec<HandlerLog>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn handler_logs(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block: Option<BlockNumber>,
+        _levels: Vec<String>,
+        _first: usize,
+    ) -> Result<Vec<HandlerLog>, StoreError> {
+        unimplemented!()
+    }
+
+    fn changed_entities(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: Vec<String>,
+        _: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_change_blocks(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: Vec<String>,
+        _: BlockNumber,
+        _: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError> {
+        unimplemented!()
+    }
+
     fn deployment_synced(&self, _: &SubgraphDeploymentId) -> Result<(), Error> {
         unimplemented!()
     }
@@ -0,0 +1,411 @@
+use structopt::StructOpt;
+
+use graph::log::logger;
+use graph::prelude::{anyhow, info, tokio, BlockNumber, SubgraphDeploymentId};
+use graph_node::manager::{
+    self, api_key, compare, deployment, ens, export, graft, jobs, trigger_log,
+};
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "graphman",
+    about = "Management tool for a Graph Node installation",
+    author = "Graph Protocol, Inc."
+)]
+pub struct Opt {
+    #[structopt(long, help = "Show debug output")]
+    pub debug: bool,
+    #[structopt(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Compare the proof of indexing of a deployment in two installations
+    ///
+    /// Finds the first block at which the proofs of indexing diverge, and
+    /// lists the entities that were written differently in that block
+    /// together with the handlers that can write them
+    ComparePoi {
+        /// The id of the deployment
+        deployment: String,
+        /// The Postgres URL of the first installation
+        left: String,
+        /// The Postgres URL of the second installation
+        right: String,
+        /// Only look for divergences from this block on
+        #[structopt(long)]
+        start_block: Option<BlockNumber>,
+        /// Only look for divergences up to this block
+        #[structopt(long)]
+        end_block: Option<BlockNumber>,
+    },
+    /// Show what grafting a new schema onto a deployment will do
+    ///
+    /// Lists which entity types of the graft base are copied as-is, which
+    /// are copied with changes to their attributes, and which are dropped
+    GraftPreview {
+        /// The id of the deployment to graft onto
+        base: String,
+        /// The file with the GraphQL schema of the new deployment
+        schema: String,
+        /// The Postgres URL of the installation
+        postgres_url: String,
+    },
+    /// Show the triggers that a deployment recorded in its trigger log
+    ///
+    /// Trigger logs are written when `GRAPH_TRIGGER_LOG_DIR` is set
+    TriggerLog {
+        /// The directory that `GRAPH_TRIGGER_LOG_DIR` pointed to
+        dir: String,
+        /// The id of the deployment
+        deployment: String,
+        /// Print the full records as JSON, one per line
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Manage the API keys that are needed for queries when
+    /// `GRAPH_REQUIRE_API_KEYS` is set
+    ApiKey {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        #[structopt(subcommand)]
+        cmd: ApiKeyCommand,
+    },
+    /// Show the most recent runs of the background jobs that maintain the
+    /// database
+    Jobs {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// Only show runs of this job
+        #[structopt(long)]
+        job: Option<String>,
+        /// How many runs to show
+        #[structopt(long, default_value = "20")]
+        limit: u32,
+    },
+    /// Show the deployments of a subgraph, or a deployment by its id
+    Info {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The name of a subgraph, or the id of a deployment with `--deployment`
+        name_or_id: String,
+        /// Look up a deployment by its id instead of a subgraph by its name
+        #[structopt(long)]
+        deployment: bool,
+    },
+    /// Show how many entities a deployment has and how fast it is syncing
+    Stats {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+    },
+    /// Remove a subgraph name
+    ///
+    /// The deployments of the subgraph are kept, but they are unassigned
+    /// unless another subgraph uses them
+    Remove {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The name of the subgraph
+        name: String,
+    },
+    /// Stop indexing a deployment
+    Unassign {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+    },
+    /// Assign a deployment to a different node
+    Reassign {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+        /// The id of the node that should index the deployment
+        node: String,
+    },
+    /// Rewind a deployment that failed or that needs a rewind to an
+    /// earlier block
+    ///
+    /// The block must be on the canonical chain
+    Rewind {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+        /// The hash of the block to rewind to
+        block_hash: String,
+        /// The number of the block to rewind to
+        block_number: u64,
+    },
+    /// Move the tables and indexes of a deployment into a different
+    /// tablespace, or into a schema with a different name, or both
+    ///
+    /// The deployment keeps indexing and serving queries while its data is
+    /// copied; writes are only paused briefly at the end
+    Move {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+        /// The name of the tablespace, which must already exist. The tables
+        /// stay in their current tablespace if this is not given
+        tablespace: Option<String>,
+        /// The new name of the deployment's schema, which must not exist yet
+        #[structopt(long)]
+        schema: Option<String>,
+    },
+    /// Manage deployments that are neither the current nor the pending
+    /// version of any subgraph and that are not assigned to a node
+    Unused {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        #[structopt(subcommand)]
+        cmd: UnusedCommand,
+    },
+    /// Write the entities of a deployment as of a block into CSV files
+    ///
+    /// The directory gets one file per entity type and an `export.json`
+    /// that describes the export
+    Export {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+        /// The directory to write the files into, or an object storage
+        /// location of the form `s3://<bucket>/<prefix>`
+        dir: String,
+        /// Export the entities as of this block instead of the latest block
+        /// of the deployment
+        #[structopt(long)]
+        block: Option<BlockNumber>,
+    },
+    /// Load an export into a deployment so that it does not have to index
+    /// the blocks up to the block of the export
+    ///
+    /// The deployment must have the same schema as the exported one, must
+    /// not have processed any blocks and must be unassigned
+    Import {
+        /// The Postgres URL of the installation
+        postgres_url: String,
+        /// The id of the deployment
+        deployment: String,
+        /// The directory with the export, or an object storage location
+        /// of the form `s3://<bucket>/<prefix>`
+        dir: String,
+    },
+    /// Add names to the rainbow table that the `ens.nameByHash` host
+    /// function looks names up in
+    ///
+    /// The table is created if it does not exist yet, which makes it
+    /// possible to fill a separate database for `GRAPH_ENS_POSTGRES_URL`
+    EnsImport {
+        /// The Postgres URL of the database with the rainbow table
+        postgres_url: String,
+        /// A file with one name per line
+        file: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum UnusedCommand {
+    /// List unused deployments
+    List,
+    /// Remove unused deployments together with all their data. This can
+    /// not be undone
+    Remove {
+        /// Only remove this deployment
+        #[structopt(long)]
+        deployment: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ApiKeyCommand {
+    /// Create a new API key and print its secret
+    Create {
+        /// A description of who the key is for
+        #[structopt(long)]
+        description: Option<String>,
+        /// Only allow querying this deployment; can be given several times.
+        /// Without it, the key can query all deployments
+        #[structopt(long = "deployment")]
+        deployments: Vec<String>,
+        /// The maximum number of queries per minute
+        #[structopt(long)]
+        queries_per_minute: Option<u32>,
+    },
+    /// List all API keys
+    List,
+    /// Revoke an API key so that it can not be used anymore
+    Revoke {
+        /// The id of the key
+        id: String,
+    },
+    /// Show how many queries were made with API keys on each day
+    Usage {
+        /// Only show the usage of this key
+        id: Option<String>,
+        /// How many days to go back
+        #[structopt(long, default_value = "30")]
+        days: u32,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+    let logger = logger(opt.debug);
+
+    let result = match opt.cmd {
+        Command::ComparePoi {
+            deployment,
+            left,
+            right,
+            start_block,
+            end_block,
+        } => SubgraphDeploymentId::new(deployment.clone())
+            .map_err(|_| anyhow::anyhow!("invalid deployment id `{}`", deployment))
+            .and_then(|deployment| {
+                info!(logger, "Comparing proofs of indexing"; "deployment" => deployment.as_str());
+                let left = compare::Side {
+                    name: "left".to_owned(),
+                    store: manager::open_store(&logger, "left", &left),
+                };
+                let right = compare::Side {
+                    name: "right".to_owned(),
+                    store: manager::open_store(&logger, "right", &right),
+                };
+                compare::run(left, right, deployment, start_block, end_block)
+            }),
+        Command::GraftPreview {
+            base,
+            schema,
+            postgres_url,
+        } => SubgraphDeploymentId::new(base.clone())
+            .map_err(|_| anyhow::anyhow!("invalid deployment id `{}`", base))
+            .and_then(|base| {
+                let store = manager::open_store(&logger, "graft-preview", &postgres_url);
+                graft::preview(store, base, &schema)
+            }),
+        Command::TriggerLog {
+            dir,
+            deployment,
+            json,
+        } => SubgraphDeploymentId::new(deployment.clone())
+            .map_err(|_| anyhow::anyhow!("invalid deployment id `{}`", deployment))
+            .and_then(|deployment| trigger_log::dump(&dir, deployment, json)),
+        Command::ApiKey { postgres_url, cmd } => {
+            let store = manager::open_store(&logger, "api-key", &postgres_url);
+            match cmd {
+                ApiKeyCommand::Create {
+                    description,
+                    deployments,
+                    queries_per_minute,
+                } => api_key::create(store, description, deployments, queries_per_minute),
+                ApiKeyCommand::List => api_key::list(store),
+                ApiKeyCommand::Revoke { id } => api_key::revoke(store, &id),
+                ApiKeyCommand::Usage { id, days } => api_key::usage(store, id.as_deref(), days),
+            }
+        }
+        Command::Jobs {
+            postgres_url,
+            job,
+            limit,
+        } => {
+            let store = manager::open_store(&logger, "jobs", &postgres_url);
+            jobs::list(store, job.as_deref(), limit)
+        }
+        Command::Info {
+            postgres_url,
+            name_or_id,
+            deployment,
+        } => {
+            let store = manager::open_store(&logger, "info", &postgres_url);
+            deployment::info(store, &name_or_id, deployment)
+        }
+        Command::Stats {
+            postgres_url,
+            deployment,
+        } => {
+            let store = manager::open_store(&logger, "stats", &postgres_url);
+            deployment::stats(store, &deployment)
+        }
+        Command::Remove { postgres_url, name } => {
+            let store = manager::open_store(&logger, "remove", &postgres_url);
+            deployment::remove(store, &name)
+        }
+        Command::Unassign {
+            postgres_url,
+            deployment,
+        } => {
+            let store = manager::open_store(&logger, "unassign", &postgres_url);
+            deployment::unassign(store, &deployment)
+        }
+        Command::Reassign {
+            postgres_url,
+            deployment,
+            node,
+        } => {
+            let store = manager::open_store(&logger, "reassign", &postgres_url);
+            deployment::reassign(store, &deployment, &node)
+        }
+        Command::Rewind {
+            postgres_url,
+            deployment,
+            block_hash,
+            block_number,
+        } => {
+            let store = manager::open_store(&logger, "rewind", &postgres_url);
+            deployment::rewind(store, &deployment, &block_hash, block_number)
+        }
+        Command::Move {
+            postgres_url,
+            deployment,
+            tablespace,
+            schema,
+        } => {
+            let store = manager::open_store(&logger, "move", &postgres_url);
+            deployment::move_to(store, &deployment, tablespace.as_deref(), schema.as_deref())
+        }
+        Command::Unused { postgres_url, cmd } => {
+            let store = manager::open_store(&logger, "unused", &postgres_url);
+            match cmd {
+                UnusedCommand::List => deployment::list_unused(store),
+                UnusedCommand::Remove { deployment } => {
+                    deployment::remove_unused(store, deployment.as_deref())
+                }
+            }
+        }
+        Command::Export {
+            postgres_url,
+            deployment,
+            dir,
+            block,
+        } => {
+            let store = manager::open_store(&logger, "export", &postgres_url);
+            export::export(store, &deployment, block, &dir).await
+        }
+        Command::Import {
+            postgres_url,
+            deployment,
+            dir,
+        } => {
+            let store = manager::open_store(&logger, "import", &postgres_url);
+            export::import(store, &deployment, &dir).await
+        }
+        Command::EnsImport { postgres_url, file } => ens::import(
+            &logger,
+            manager::open_ens_names(&logger, &postgres_url),
+            &file,
+        ),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
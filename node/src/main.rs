@@ -1,4 +1,4 @@
-use git_testament::{git_testament, render_testament};
+use git_testament::{git_testament, render_testament, CommitKind};
 use ipfs_api::IpfsClient;
 use lazy_static::lazy_static;
 use prometheus::Registry;
@@ -7,20 +7,26 @@ use std::env;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 use tokio::sync::mpsc;
 
-use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+use graph::components::ens::EnsLookup;
+use graph::components::ethereum::{EthereumNetworks, NodeCapabilities, REORG_THRESHOLD};
 use graph::components::forward;
+use graph::components::server::health::{self, Subsystem};
 use graph::data::graphql::effort::LoadManager;
+use graph::data::version::NodeVersion;
 use graph::log::logger;
 use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
 use graph::util::security::SafeDisplay;
+use graph::util::shutdown::{self, SHUTDOWN_TIMEOUT};
 use graph_chain_arweave::adapter::ArweaveAdapter;
-use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
+use graph_chain_ethereum::{
+    network_indexer, BlockIngestor, BlockStreamBuilder, ChainVerifier, Transport,
+};
 use graph_core::{
-    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
+    ens::EnsEndpoint, three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry, NodeCoordinator,
     SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
     SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
@@ -31,22 +37,19 @@ use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_metrics::PrometheusMetricsServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::NetworkStore as DieselNetworkStore;
+use graph_store_postgres::{
+    NetworkStore as DieselNetworkStore, Store as DieselStore, StoreHealthCheck,
+};
 use graphql_parser::query as q;
 
+mod config;
 mod opt;
 mod store_builder;
 
+use config::Config;
 use store_builder::StoreBuilder;
 
 lazy_static! {
-    // Default to an Ethereum reorg threshold to 50 blocks
-    static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
-        .ok()
-        .map(|s| u64::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
-        .unwrap_or(50);
-
     // Default to an ancestor count of 50 blocks
     static ref ANCESTOR_COUNT: u64 = env::var("ETHEREUM_ANCESTOR_COUNT")
         .ok()
@@ -57,6 +60,50 @@ lazy_static! {
 
 git_testament!(TESTAMENT);
 
+/// The version information that `--version --json` and the index node
+/// server report for a node that indexes `chains`
+fn node_version(chains: Vec<String>) -> NodeVersion {
+    let commit = match TESTAMENT.commit {
+        CommitKind::NoTags(commit, _) | CommitKind::FromTag(_, commit, _, _) => Some(commit),
+        CommitKind::NoRepository(..) | CommitKind::NoCommit(..) => None,
+    };
+    NodeVersion::new(env!("CARGO_PKG_VERSION"), commit, chains)
+}
+
+/// The names of the networks that the node is configured to index, either
+/// in the configuration file or with `--ethereum-rpc`, `--ethereum-ws` and
+/// `--ethereum-ipc`, whose values have the form `NETWORK_NAME:FEATURES:URL`
+fn configured_chains(opt: &opt::Opt, config: Option<&Config>) -> Vec<String> {
+    let providers = match config {
+        Some(config) => vec![
+            config.providers(config::Transport::Rpc),
+            config.providers(config::Transport::Ipc),
+            config.providers(config::Transport::Ws),
+        ]
+        .concat(),
+        None => vec![
+            opt.ethereum_rpc.clone(),
+            opt.ethereum_ipc.clone(),
+            opt.ethereum_ws.clone(),
+        ]
+        .concat(),
+    };
+    let mut chains: Vec<String> = providers
+        .iter()
+        // A bare URL has no network name; the node refuses to start with it
+        .filter(|provider| match provider.find("://") {
+            Some(scheme) => provider[..scheme].contains(':'),
+            None => true,
+        })
+        .filter_map(|provider| provider.split(':').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect();
+    chains.sort();
+    chains.dedup();
+    chains
+}
+
 #[derive(Debug, Clone)]
 enum ConnectionType {
     IPC,
@@ -98,7 +145,50 @@ fn read_expensive_queries() -> Result<Vec<Arc<q::Document>>, std::io::Error> {
 async fn main() {
     env_logger::init();
 
+    // Clap prints the plain version and exits as soon as it sees
+    // `--version`, and we therefore need to check for `--json` first
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "--version" || arg == "-V")
+        && args.iter().any(|arg| arg == "--json")
+    {
+        // Report the chains that the remaining arguments configure, if they
+        // are complete enough to start a node with
+        let rest = args
+            .iter()
+            .filter(|arg| !["--version", "-V", "--json"].contains(&arg.as_str()));
+        let chains = match opt::Opt::from_iter_safe(rest) {
+            Ok(opt) => {
+                let config = opt.config.as_ref().and_then(|path| Config::load(path).ok());
+                configured_chains(&opt, config.as_ref())
+            }
+            Err(_) => vec![],
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&node_version(chains)).expect("version is serializable")
+        );
+        std::process::exit(0);
+    }
+
     let opt = opt::Opt::from_args();
+    if opt.json {
+        eprintln!("--json can only be used together with --version");
+        std::process::exit(1);
+    }
+
+    let config = opt.config.as_ref().map(|path| {
+        Config::load(path).unwrap_or_else(|e| {
+            eprintln!("{:#}", e);
+            std::process::exit(1)
+        })
+    });
+    if opt.check_config {
+        // Safe to unwrap because `--check-config` requires `--config`
+        println!("{} is a valid configuration file", opt.config.unwrap());
+        std::process::exit(0);
+    }
+
+    let chains = configured_chains(&opt, config.as_ref());
 
     // Set up logger
     let logger = logger(opt.debug);
@@ -110,8 +200,26 @@ async fn main() {
         render_testament!(TESTAMENT)
     );
 
-    // Safe to unwrap because a value is required by CLI
-    let postgres_url = opt.postgres_url.clone();
+    // Obtain the store settings from the configuration file or from the
+    // command line
+    let (postgres_url, store_conn_pool_size, secondary_hosts, host_weights) = match &config {
+        Some(config) => {
+            let (hosts, weights) = config.replicas();
+            (
+                config.store.connection.clone(),
+                config.store.pool_size,
+                hosts,
+                weights,
+            )
+        }
+        // Safe to unwrap because a value is required by CLI
+        None => (
+            opt.postgres_url.clone().unwrap(),
+            opt.store_connection_pool_size,
+            opt.postgres_secondary_hosts.clone(),
+            opt.postgres_host_weights.clone(),
+        ),
+    };
 
     let node_id =
         NodeId::new(opt.node_id.clone()).expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
@@ -132,9 +240,6 @@ async fn main() {
     // Obtain metrics server port
     let metrics_port = opt.metrics_port;
 
-    // Obtain STORE_CONNECTION_POOL_SIZE setting
-    let store_conn_pool_size: u32 = opt.store_connection_pool_size;
-
     let arweave_adapter = Arc::new(ArweaveAdapter::new(opt.arweave_api.clone()));
 
     let three_box_adapter = Arc::new(ThreeBoxAdapter::new(opt.three_box_api.clone()));
@@ -170,15 +275,42 @@ async fn main() {
         PrometheusMetricsServer::new(&logger_factory, prometheus_registry.clone());
 
     // Ethereum clients
+    let (ethereum_rpc, ethereum_ipc, ethereum_ws) = match &config {
+        Some(config) => {
+            config.apply_rate_limits();
+            (
+                config.providers(config::Transport::Rpc),
+                config.providers(config::Transport::Ipc),
+                config.providers(config::Transport::Ws),
+            )
+        }
+        None => (
+            opt.ethereum_rpc.clone(),
+            opt.ethereum_ipc.clone(),
+            opt.ethereum_ws.clone(),
+        ),
+    };
     let eth_networks = create_ethereum_networks(
         &logger,
         metrics_registry.clone(),
-        &opt.ethereum_rpc,
-        &opt.ethereum_ipc,
-        &opt.ethereum_ws,
+        &ethereum_rpc,
+        &ethereum_ipc,
+        &ethereum_ws,
     )
     .await;
 
+    // Placement rules from the configuration file replace the ones from
+    // `GRAPH_DEPLOYMENT_RULES_FILE`. They are reloaded on SIGHUP together
+    // with the pool sizes and rate limits once the store is set up
+    let reloadable_config = match (&opt.config, &config) {
+        (Some(path), Some(config)) => {
+            // Unwrap: `Config::load` checks the rules
+            PlacementRules::replace(config.placement_rules().unwrap());
+            Some((path.clone(), config.clone()))
+        }
+        _ => None,
+    };
+
     // Set up Store
     info!(
         logger,
@@ -199,12 +331,28 @@ async fn main() {
     let store_builder = Arc::new(StoreBuilder::new(
         &logger,
         &postgres_url,
-        opt.store_connection_pool_size,
-        opt.postgres_secondary_hosts.clone(),
-        opt.postgres_host_weights.clone(),
+        store_conn_pool_size,
+        secondary_hosts,
+        host_weights,
         metrics_registry.cheap_clone(),
     ));
     let store_builder2 = store_builder.clone();
+    graph::spawn(shutdown_on_terminate(
+        logger.clone(),
+        store_builder.store().store(),
+    ));
+    let ens_lookup: Arc<dyn EnsLookup> = match &opt.ens_endpoint {
+        Some(endpoint) => {
+            info!(logger, "Looking up ENS names with an external endpoint");
+            Arc::new(EnsEndpoint::new(endpoint.clone()))
+        }
+        None => store_builder.ens_names(
+            &logger,
+            opt.ens_postgres_url.as_deref(),
+            opt.ens_connection_pool_size,
+            metrics_registry.cheap_clone(),
+        ),
+    };
 
     graph::spawn(
         futures::stream::FuturesOrdered::from_iter(stores_eth_networks.flatten().into_iter().map(
@@ -233,6 +381,7 @@ async fn main() {
                 "network_version" => &network_identifier.net_version,
                 "capabilities" => &capabilities
             );
+            health::report(Subsystem::ChainProvider, &network_name, Ok(()));
             let network_store =
                 store_builder2.network_store(network_name.clone(), network_identifier);
             (network_name.to_string(), network_store)
@@ -240,12 +389,32 @@ async fn main() {
         .collect()
         .map(|stores| HashMap::from_iter(stores.into_iter()))
         .and_then(move |network_stores| {
+            // Only nodes that ingest blocks check that the chain heads move
+            let ingested_networks = if opt.disable_block_ingestor {
+                vec![]
+            } else {
+                eth_networks.networks.keys().cloned().collect()
+            };
+            health::register(Arc::new(StoreHealthCheck::new(
+                store_builder.store().store(),
+                ingested_networks,
+            )));
+
             let load_manager = Arc::new(LoadManager::new(
                 &logger,
                 expensive_queries,
                 metrics_registry.clone(),
                 store_conn_pool_size as usize,
             ));
+            if let Some((path, config)) = reloadable_config {
+                graph::spawn(config::reload_on_hangup(
+                    logger.clone(),
+                    path,
+                    config,
+                    store_builder.cheap_clone(),
+                    load_manager.cheap_clone(),
+                ));
+            }
             let graphql_runner = Arc::new(GraphQlRunner::new(
                 &logger,
                 store_builder.store(),
@@ -262,6 +431,7 @@ async fn main() {
                 &logger,
                 graphql_runner.clone(),
                 store_builder.store(),
+                graphql_server.api_keys(),
             );
 
             let mut index_node_server = IndexNodeServer::new(
@@ -269,6 +439,7 @@ async fn main() {
                 graphql_runner.clone(),
                 store_builder.store(),
                 node_id.clone(),
+                node_version(chains),
             );
 
             // Spawn Ethereum network indexers for all networks that are to be indexed
@@ -320,6 +491,7 @@ async fn main() {
                     &network_stores,
                     &logger_factory,
                 );
+                start_chain_verifiers(&logger, &eth_networks, &network_stores, &logger_factory);
             }
 
             let block_stream_builder = BlockStreamBuilder::new(
@@ -336,6 +508,7 @@ async fn main() {
                 network_stores.clone(),
                 arweave_adapter,
                 three_box_adapter,
+                ens_lookup,
             );
 
             let subgraph_instance_manager = SubgraphInstanceManager::new(
@@ -381,20 +554,39 @@ async fn main() {
                 node_id.clone(),
                 version_switching_mode,
             ));
+            health::report(
+                Subsystem::InstanceManager,
+                "subgraphs",
+                Err("starting the subgraphs assigned to this node".to_owned()),
+            );
             graph::spawn(
                 subgraph_registrar
                     .start()
+                    .map(|()| health::report(Subsystem::InstanceManager, "subgraphs", Ok(())))
                     .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
                     .compat(),
             );
 
+            // Record heartbeats and move deployments off dead nodes
+            let coordinator = NodeCoordinator::new(
+                &logger_factory,
+                node_id.clone(),
+                store_builder.store(),
+                eth_networks.networks.keys().cloned().collect(),
+            );
+            graph::spawn(coordinator.run());
+
+            // Run the background jobs that maintain the database and the
+            // state of this node
+            let job_runner = store_builder.store().store().job_runner(node_id.clone());
+            graph::spawn(job_runner.run());
+
             // Start admin JSON-RPC server.
             let json_rpc_server = JsonRpcServer::serve(
                 json_rpc_port,
                 http_port,
                 ws_port,
                 subgraph_registrar.clone(),
-                node_id.clone(),
                 logger.clone(),
             )
             .expect("failed to start JSON-RPC admin server");
@@ -420,7 +612,7 @@ async fn main() {
                     async move {
                         subgraph_registrar.create_subgraph(name.clone()).await?;
                         subgraph_registrar
-                            .create_subgraph_version(name, subgraph_id, node_id)
+                            .create_subgraph_version(name, subgraph_id, Some(node_id), None)
                             .await
                     }
                     .map_err(|e| panic!("Failed to deploy subgraph from `--subgraph` flag: {}", e)),
@@ -492,6 +684,89 @@ async fn main() {
     futures::future::pending::<()>().await;
 }
 
+/// Shut down when the node receives a `SIGTERM`: stop the servers from
+/// accepting requests and subgraphs from processing new blocks, wait for
+/// the requests, blocks and reverts that are in flight, pass the store
+/// events of the committed blocks on to subscriptions, store the messages
+/// that mappings logged, and close the connection pools before exiting.
+/// The node exits when `GRAPH_SHUTDOWN_TIMEOUT` has passed even if that
+/// has not all happened by then
+async fn shutdown_on_terminate(logger: Logger, store: Arc<DieselStore>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!(logger, "Can not shut down cleanly on SIGTERM"; "error" => e.to_string());
+            return;
+        }
+    };
+    if terminate.recv().await.is_none() {
+        return;
+    }
+
+    let deadline = Instant::now() + *SHUTDOWN_TIMEOUT;
+    info!(logger, "Shutting down"; "timeout_s" => SHUTDOWN_TIMEOUT.as_secs());
+    shutdown::initiate();
+    health::report(
+        Subsystem::InstanceManager,
+        "subgraphs",
+        Err("shutting down".to_owned()),
+    );
+
+    // The servers record the API key usage of the last queries they
+    // answered before they finish
+    if !shutdown::drain(deadline).await {
+        warn!(
+            logger,
+            "Exiting while subgraphs are still processing blocks, they will be \
+             reprocessed when the subgraphs are started again";
+            "in_flight" => shutdown::in_flight_count()
+        );
+    }
+
+    let logger2 = logger.clone();
+    let closed = graph::spawn_blocking_allow_panic(move || {
+        let logger = logger2;
+        match store.flush_store_events(deadline) {
+            Ok(true) => (),
+            Ok(false) => warn!(
+                logger,
+                "Exiting before subscriptions received all store events"
+            ),
+            Err(e) => warn!(logger, "Failed to flush store events"; "error" => e.to_string()),
+        }
+        for (deployment, logs) in HandlerLog::take_all() {
+            let res = match SubgraphDeploymentId::new(deployment.as_str()) {
+                Ok(id) => store
+                    .record_handler_logs(&id, logs)
+                    .map_err(|e| e.to_string()),
+                Err(_) => Err("invalid deployment id".to_owned()),
+            };
+            if let Err(e) = res {
+                warn!(logger, "Failed to store handler logs";
+                      "deployment" => &deployment, "error" => e);
+            }
+        }
+        // Closing the pools blocks until their connections are returned
+        store.close(deadline)
+    })
+    .await
+    .unwrap_or(false);
+    if !closed {
+        warn!(
+            logger,
+            "Exiting while database connections are still in use"
+        );
+    }
+    info!(logger, "Shut down");
+
+    // Background tasks like the block ingestor and the store event
+    // listener never finish, and the runtime would wait for them forever.
+    // Everything that needs to be cleaned up has been by now
+    std::process::exit(0);
+}
+
 /// Parses an Ethereum connection string and returns the network name and Ethereum adapter.
 async fn parse_ethereum_networks(
     logger: Logger,
@@ -536,18 +811,26 @@ async fn parse_ethereum_networks(
             })?;
 
             let (capabilities_str, url_str) = rest.split_at(url_split_at);
-            let (url, capabilities) =
-                if vec!["http", "https", "ws", "wss"].contains(&capabilities_str) {
-                    (
-                        rest,
-                        NodeCapabilities {
-                            archive: true,
-                            traces: true,
-                        },
-                    )
-                } else {
-                    (&url_str[1..], capabilities_str.parse()?)
-                };
+            let (url, capabilities) = if capabilities_str == "file" || capabilities_str == "s3" {
+                // Block files have no call traces
+                (
+                    rest,
+                    NodeCapabilities {
+                        archive: true,
+                        traces: false,
+                    },
+                )
+            } else if vec!["http", "https", "ws", "wss"].contains(&capabilities_str) {
+                (
+                    rest,
+                    NodeCapabilities {
+                        archive: true,
+                        traces: true,
+                    },
+                )
+            } else {
+                (&url_str[1..], capabilities_str.parse()?)
+            };
 
             if rest.is_empty() {
                 return Err(anyhow::anyhow!(
@@ -555,6 +838,31 @@ async fn parse_ethereum_networks(
                 ));
             }
 
+            if url.starts_with("file://") || url.starts_with("s3://") {
+                info!(
+                    logger,
+                    "Reading blocks from files";
+                    "network" => &name,
+                    "location" => &url,
+                    "capabilities" => capabilities
+                );
+                let adapter = if url.starts_with("s3://") {
+                    graph_chain_ethereum::FileEthereumAdapter::from_object_store(&logger, url).await
+                } else {
+                    graph_chain_ethereum::FileEthereumAdapter::open(
+                        &logger,
+                        &url["file://".len()..],
+                    )
+                }
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+                parsed_networks.insert(
+                    name.to_string(),
+                    capabilities,
+                    Arc::new(adapter) as Arc<dyn EthereumAdapter>,
+                );
+                continue;
+            }
+
             info!(
                 logger,
                 "Creating transport";
@@ -573,18 +881,38 @@ async fn parse_ethereum_networks(
             // For now it's fine to just leak it.
             std::mem::forget(transport_event_loop);
 
-            parsed_networks.insert(
-                name.to_string(),
-                capabilities,
-                Arc::new(
-                    graph_chain_ethereum::EthereumAdapter::new(
-                        url,
-                        transport,
-                        eth_rpc_metrics.clone(),
-                    )
-                    .await,
-                ) as Arc<dyn EthereumAdapter>,
-            );
+            let adapter = Arc::new(
+                graph_chain_ethereum::EthereumAdapter::new(
+                    &logger,
+                    url,
+                    transport,
+                    eth_rpc_metrics.clone(),
+                )
+                .await,
+            ) as Arc<dyn EthereumAdapter>;
+
+            // Only use the capabilities that the node actually has so that
+            // subgraphs that need others are rejected when they are deployed.
+            // Detection only reports a capability as missing when the node
+            // says so; when a probe fails for any other reason, we keep what
+            // was configured
+            let detected = adapter.detected_capabilities();
+            let supported = NodeCapabilities {
+                archive: capabilities.archive && detected.archive,
+                traces: capabilities.traces && detected.traces,
+            };
+            if supported != capabilities {
+                warn!(
+                    logger,
+                    "Ethereum node lacks some of the capabilities it was configured with";
+                    "network" => &name,
+                    "url" => &url,
+                    "configured" => capabilities,
+                    "supported" => supported
+                );
+            }
+
+            parsed_networks.insert(name.to_string(), supported, adapter);
         }
     }
     Ok(parsed_networks)
@@ -716,12 +1044,25 @@ fn start_block_ingestor(
                 "network_name" => &network_name
             );
             let eth_adapter = eth_adapters.cheapest().unwrap(); //Safe to unwrap since it cannot be empty
+            let heads_adapter = eth_adapters
+                .adapters
+                .iter()
+                .map(|adapter| adapter.adapter())
+                .find(|adapter| adapter.supports_new_heads());
+            if heads_adapter.is_some() {
+                info!(
+                    logger,
+                    "Subscribing to new heads";
+                    "network_name" => &network_name
+                );
+            }
             let block_ingestor = BlockIngestor::new(
                 network_stores
                     .get(network_name)
                     .expect("network with name")
                     .clone(),
                 eth_adapter.clone(),
+                heads_adapter.cloned(),
                 *ANCESTOR_COUNT,
                 network_name.to_string(),
                 logger_factory,
@@ -734,9 +1075,37 @@ fn start_block_ingestor(
         });
 }
 
+fn start_chain_verifiers(
+    logger: &Logger,
+    eth_networks: &EthereumNetworks,
+    network_stores: &HashMap<String, Arc<DieselNetworkStore>>,
+    logger_factory: &LoggerFactory,
+) {
+    if !ChainVerifier::<DieselNetworkStore>::is_enabled() {
+        return;
+    }
+
+    info!(logger, "Starting canonical chain verifiers");
+
+    for (network_name, eth_adapters) in eth_networks.networks.iter() {
+        let eth_adapter = eth_adapters.cheapest().unwrap(); //Safe to unwrap since it cannot be empty
+        let verifier = ChainVerifier::new(
+            network_stores
+                .get(network_name)
+                .expect("network with name")
+                .clone(),
+            eth_adapter.clone(),
+            *REORG_THRESHOLD,
+            network_name.to_string(),
+            logger_factory,
+        );
+        graph::spawn(verifier.into_polling_stream());
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::parse_ethereum_networks;
+    use super::{configured_chains, parse_ethereum_networks};
     use crate::ConnectionType;
     use graph::components::ethereum::NodeCapabilities;
     use graph::log::logger;
@@ -744,6 +1113,7 @@ mod test {
     use graph_core::MetricsRegistry;
     use prometheus::Registry;
     use std::sync::Arc;
+    use structopt::StructOpt;
 
     #[tokio::test]
     async fn correctly_parse_ethereum_networks() {
@@ -818,4 +1188,24 @@ mod test {
         assert_eq!(goerli_capability, archive);
         assert_eq!(mainnet_capability, traces);
     }
+
+    #[test]
+    fn reports_configured_chains() {
+        let opt = crate::opt::Opt::from_iter(&[
+            "graph-node",
+            "--postgres-url",
+            "postgresql://localhost/graph",
+            "--ipfs",
+            "localhost:5001",
+            "--ethereum-rpc",
+            "mainnet:traces:http://localhost:8545/",
+            "goerli:archive:http://localhost:8546/",
+            "mainnet::http://localhost:8547/",
+            "http://localhost:8548/",
+        ]);
+        assert_eq!(
+            vec!["goerli".to_string(), "mainnet".to_string()],
+            configured_chains(&opt, None)
+        );
+    }
 }
@@ -0,0 +1,98 @@
+//! Manage the API keys that clients use to query deployments when
+//! `GRAPH_REQUIRE_API_KEYS` is set
+use std::sync::Arc;
+
+use graph::prelude::{anyhow, ApiKey, SubgraphDeploymentId};
+use graph_store_postgres::Store;
+
+fn print_key(key: &ApiKey) {
+    let deployments = key
+        .deployments
+        .as_ref()
+        .map(|deployments| deployments.join(", "))
+        .unwrap_or_else(|| "all".to_owned());
+    let limit = key
+        .queries_per_minute
+        .map(|limit| format!("{}/min", limit))
+        .unwrap_or_else(|| "none".to_owned());
+    println!("id:          {}", key.id);
+    println!(
+        "description: {}",
+        key.description.as_deref().unwrap_or_default()
+    );
+    println!("deployments: {}", deployments);
+    println!("rate limit:  {}", limit);
+    println!("revoked:     {}", key.revoked);
+}
+
+pub fn create(
+    store: Arc<Store>,
+    description: Option<String>,
+    deployments: Vec<String>,
+    queries_per_minute: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    let deployments = if deployments.is_empty() {
+        None
+    } else {
+        Some(
+            deployments
+                .into_iter()
+                .map(|id| {
+                    SubgraphDeploymentId::new(id.clone())
+                        .map_err(|_| anyhow::anyhow!("invalid deployment id `{}`", id))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+    let (key, secret) = store
+        .create_api_key(description, deployments, queries_per_minute)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    print_key(&key);
+    println!("secret:      {}", secret);
+    println!();
+    println!("The secret is not stored and can not be shown again");
+    Ok(())
+}
+
+pub fn list(store: Arc<Store>) -> Result<(), anyhow::Error> {
+    let keys = store.api_keys().map_err(|e| anyhow::anyhow!("{}", e))?;
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_key(key);
+    }
+    Ok(())
+}
+
+pub fn revoke(store: Arc<Store>, id: &str) -> Result<(), anyhow::Error> {
+    if store
+        .revoke_api_key(id)
+        .map_err(|e| anyhow::anyhow!("{}", e))?
+    {
+        println!("revoked API key {}", id);
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "there is no API key `{}` or it was already revoked",
+            id
+        ))
+    }
+}
+
+pub fn usage(store: Arc<Store>, id: Option<&str>, days: u32) -> Result<(), anyhow::Error> {
+    let usage = store
+        .api_key_usage(id, days)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!(
+        "{:<10} {:<12} {:<46} {:>10}",
+        "day", "api key", "deployment", "queries"
+    );
+    for row in usage {
+        println!(
+            "{:<10} {:<12} {:<46} {:>10}",
+            row.day, row.api_key, row.deployment, row.queries
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,405 @@
+//! Compare the proof of indexing of a deployment between two installations
+//! of Graph Node and, if they disagree, find the first block at which they
+//! diverge and the entities that were written differently in that block.
+//!
+//! The digests of the proof of indexing are cumulative: once they differ
+//! at some block, they differ at all later blocks, too. That makes it
+//! possible to find the first divergent block with a binary search.
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use graph::data::graphql::ext::DocumentExt;
+use graph::data::store::scalar::Bytes;
+use graph::data::subgraph::schema::{MetadataType, SUBGRAPHS_ID};
+use graph::prelude::{
+    anyhow, BlockNumber, Entity, EntityCollection, EntityFilter, EntityKey, EntityQuery,
+    EntityRange, Store as _, SubgraphDeploymentId, SubgraphDeploymentStore as _, Value,
+    BLOCK_NUMBER_MAX,
+};
+use graph_store_postgres::Store;
+
+/// One of the two installations that are being compared
+pub struct Side {
+    pub name: String,
+    pub store: Arc<Store>,
+}
+
+impl Side {
+    fn digests(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Bytes>, anyhow::Error> {
+        self.store
+            .poi_digests(deployment, block)
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.name, e))?
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}: deployment {} does not have a proof of indexing",
+                    self.name,
+                    deployment
+                )
+            })
+    }
+
+    fn head(&self, deployment: &SubgraphDeploymentId) -> Result<BlockNumber, anyhow::Error> {
+        let ptr = self
+            .store
+            .block_ptr(deployment.clone())
+            .map_err(|e| anyhow::anyhow!("{}: {}", self.name, e))?
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}: deployment {} has not processed any blocks",
+                    self.name,
+                    deployment
+                )
+            })?;
+        Ok(ptr.number as BlockNumber)
+    }
+}
+
+/// A difference between the entities that the two sides wrote in a block
+#[derive(Debug, PartialEq)]
+enum EntityDiff {
+    Missing { side: String },
+    Different { attributes: Vec<String> },
+}
+
+pub fn run(
+    left: Side,
+    right: Side,
+    deployment: SubgraphDeploymentId,
+    start_block: Option<BlockNumber>,
+    end_block: Option<BlockNumber>,
+) -> Result<(), anyhow::Error> {
+    let head = left.head(&deployment)?.min(right.head(&deployment)?);
+    let end = end_block.map_or(head, |end| end.min(head));
+    let start = start_block.unwrap_or(0);
+    if start > end {
+        anyhow::bail!(
+            "the start block {} is after the end block {}; both sides have only processed blocks up to {}",
+            start,
+            end,
+            head
+        );
+    }
+
+    let differ = |block| -> Result<bool, anyhow::Error> {
+        Ok(left.digests(&deployment, block)? != right.digests(&deployment, block)?)
+    };
+
+    if !differ(end)? {
+        println!(
+            "The proofs of indexing of {} and {} agree up to block {}",
+            left.name, right.name, end
+        );
+        return Ok(());
+    }
+
+    let block = first_divergent_block(start, end, differ)?;
+
+    if block == start && start > 0 {
+        println!(
+            "The proofs of indexing already differ at the start block {}; \
+             the divergence might have happened earlier",
+            block
+        );
+    } else {
+        println!("The proofs of indexing first differ at block {}", block);
+    }
+
+    let left_digests = left.digests(&deployment, block)?;
+    let right_digests = right.digests(&deployment, block)?;
+    let regions: BTreeSet<_> = left_digests.keys().chain(right_digests.keys()).collect();
+    for region in regions {
+        if left_digests.get(region) != right_digests.get(region) {
+            println!("  causality region `{}` differs", region);
+        }
+    }
+
+    let diffs = entity_diffs(&left, &right, &deployment, block)?;
+    if diffs.is_empty() {
+        println!(
+            "Both sides wrote the same entities in block {}; the difference must \
+             come from entities that were removed in that block",
+            block
+        );
+        return Ok(());
+    }
+
+    println!("Entities that differ in block {}:", block);
+    let mut entity_types = BTreeSet::new();
+    for ((entity_type, id), diff) in &diffs {
+        entity_types.insert(entity_type.as_str());
+        match diff {
+            EntityDiff::Missing { side } => {
+                println!("  {}[{}]: only written by {}", entity_type, id, side)
+            }
+            EntityDiff::Different { attributes } => println!(
+                "  {}[{}]: attributes differ: {}",
+                entity_type,
+                id,
+                attributes.join(", ")
+            ),
+        }
+    }
+
+    println!("Handlers that can write these entities:");
+    for entity_type in entity_types {
+        for (data_source, handler) in handlers_for_entity_type(&left, &deployment, entity_type)? {
+            println!(
+                "  {}: {} in data source {}",
+                entity_type, handler, data_source
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Find the first block in `[start, end]` for which `differ` is true,
+/// assuming that `differ` is true for `end` and for every block after the
+/// first one for which it is true
+fn first_divergent_block(
+    start: BlockNumber,
+    end: BlockNumber,
+    mut differ: impl FnMut(BlockNumber) -> Result<bool, anyhow::Error>,
+) -> Result<BlockNumber, anyhow::Error> {
+    let (mut lo, mut hi) = (start, end);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if differ(mid)? {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Ok(lo)
+}
+
+/// Compare the entities that `left` and `right` wrote in `block`
+fn entity_diffs(
+    left: &Side,
+    right: &Side,
+    deployment: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<BTreeMap<(String, String), EntityDiff>, anyhow::Error> {
+    let schema = left
+        .store
+        .input_schema(deployment)
+        .map_err(|e| anyhow::anyhow!("{}: {}", left.name, e))?;
+    let entity_types: Vec<String> = schema
+        .document
+        .get_object_type_definitions()
+        .into_iter()
+        .map(|object_type| object_type.name.clone())
+        .collect();
+
+    let changes = |side: &Side| -> Result<BTreeMap<(String, String), Entity>, anyhow::Error> {
+        let mut changes = BTreeMap::new();
+        let changed = side
+            .store
+            .changed_entities(deployment, entity_types.clone(), block)
+            .map_err(|e| anyhow::anyhow!("{}: {}", side.name, e))?;
+        for (entity_type, entities) in changed {
+            for entity in entities {
+                let id = entity.id().map_err(|e| anyhow::anyhow!("{}", e))?;
+                changes.insert((entity_type.clone(), id), entity);
+            }
+        }
+        Ok(changes)
+    };
+    Ok(diff_entities(
+        &left.name,
+        changes(left)?,
+        &right.name,
+        changes(right)?,
+    ))
+}
+
+/// Pair up the entities that both sides wrote by their type and id and
+/// report the ones that only one side wrote or whose attributes differ
+fn diff_entities(
+    left: &str,
+    left_changes: BTreeMap<(String, String), Entity>,
+    right: &str,
+    mut right_changes: BTreeMap<(String, String), Entity>,
+) -> BTreeMap<(String, String), EntityDiff> {
+    let mut diffs = BTreeMap::new();
+    for (key, left_entity) in left_changes {
+        match right_changes.remove(&key) {
+            None => {
+                diffs.insert(
+                    key,
+                    EntityDiff::Missing {
+                        side: left.to_owned(),
+                    },
+                );
+            }
+            Some(right_entity) => {
+                let attributes: BTreeSet<_> =
+                    left_entity.keys().chain(right_entity.keys()).collect();
+                let attributes: Vec<_> = attributes
+                    .into_iter()
+                    .filter(|attr| left_entity.get(*attr) != right_entity.get(*attr))
+                    .cloned()
+                    .collect();
+                if !attributes.is_empty() {
+                    diffs.insert(key, EntityDiff::Different { attributes });
+                }
+            }
+        }
+    }
+    for (key, _) in right_changes {
+        diffs.insert(
+            key,
+            EntityDiff::Missing {
+                side: right.to_owned(),
+            },
+        );
+    }
+    diffs
+}
+
+/// Find the handlers of all mappings in the manifest of `deployment` that
+/// declare that they write entities of type `entity_type`. Returns pairs
+/// of data source (or template) name and handler name
+fn handlers_for_entity_type(
+    side: &Side,
+    deployment: &SubgraphDeploymentId,
+    entity_type: &str,
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let store = &side.store;
+    let err = |e: graph::prelude::QueryExecutionError| anyhow::anyhow!("{}: {}", side.name, e);
+    let get = |entity_type: MetadataType, id: &str| {
+        store
+            .get(EntityKey {
+                subgraph_id: SUBGRAPHS_ID.clone(),
+                entity_type: entity_type.to_string(),
+                entity_id: id.to_owned(),
+            })
+            .map_err(err)
+    };
+    let string = |entity: &Entity, attr: &str| match entity.get(attr) {
+        Some(Value::String(s)) => s.clone(),
+        _ => String::new(),
+    };
+
+    let query = EntityQuery::new(
+        SUBGRAPHS_ID.clone(),
+        BLOCK_NUMBER_MAX,
+        EntityCollection::All(vec![MetadataType::EthereumContractMapping.to_string()]),
+    )
+    .filter(EntityFilter::And(vec![
+        EntityFilter::StartsWith("id".to_owned(), format!("{}-manifest-", deployment).into()),
+        EntityFilter::Contains(
+            "entities".to_owned(),
+            Value::List(vec![Value::from(entity_type)]),
+        ),
+    ]))
+    .range(EntityRange {
+        first: None,
+        skip: 0,
+    });
+
+    let mut handlers = Vec::new();
+    for mapping in store.find(query).map_err(err)? {
+        let mapping_id = string(&mapping, "id");
+        let owner_id = mapping_id.trim_end_matches("-mapping");
+        let data_source = match get(MetadataType::EthereumContractDataSource, owner_id)? {
+            Some(data_source) => Some(data_source),
+            None => get(MetadataType::EthereumContractDataSourceTemplate, owner_id)?,
+        }
+        .map(|data_source| string(&data_source, "name"))
+        .unwrap_or(mapping_id.clone());
+
+        for (attr, handler_type) in &[
+            ("eventHandlers", MetadataType::EthereumContractEventHandler),
+            ("callHandlers", MetadataType::EthereumCallHandlerEntity),
+            ("blockHandlers", MetadataType::EthereumBlockHandlerEntity),
+        ] {
+            let ids = match mapping.get(*attr) {
+                Some(Value::List(ids)) => ids.clone(),
+                _ => vec![],
+            };
+            for id in ids {
+                if let Value::String(id) = id {
+                    if let Some(handler) = get(handler_type.clone(), &id)? {
+                        handlers.push((data_source.clone(), string(&handler, "handler")));
+                    }
+                }
+            }
+        }
+    }
+    Ok(handlers)
+}
+
+#[test]
+fn finds_first_divergent_block() {
+    let first = |start, end, diverged| {
+        let mut probes = 0;
+        let block = first_divergent_block(start, end, |block| {
+            probes += 1;
+            Ok(block >= diverged)
+        })
+        .unwrap();
+        (block, probes)
+    };
+
+    assert_eq!((0, 0), first(0, 0, 0));
+    assert_eq!((0, 10), first(0, 1000, 0));
+    assert_eq!((517, 10), first(0, 1000, 517));
+    assert_eq!((1000, 9), first(0, 1000, 1000));
+    // Blocks before `start` are never looked at
+    assert_eq!((100, 4), first(100, 110, 3));
+}
+
+#[test]
+fn diffs_entities_by_type_and_id() {
+    let entity = |id: &str, name: &str| {
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity.set("name", name);
+        ((String::from("User"), id.to_owned()), entity)
+    };
+
+    let left = vec![entity("1", "one"), entity("2", "two"), entity("3", "three")];
+    let mut right = vec![entity("2", "two"), entity("3", "drei"), entity("4", "four")];
+    right[0].1.set("age", 2);
+    let diffs = diff_entities(
+        "left",
+        left.into_iter().collect(),
+        "right",
+        right.into_iter().collect(),
+    );
+
+    let key = |id: &str| (String::from("User"), id.to_owned());
+    let expected: BTreeMap<_, _> = vec![
+        (
+            key("1"),
+            EntityDiff::Missing {
+                side: "left".to_owned(),
+            },
+        ),
+        (
+            key("2"),
+            EntityDiff::Different {
+                attributes: vec!["age".to_owned()],
+            },
+        ),
+        (
+            key("3"),
+            EntityDiff::Different {
+                attributes: vec!["name".to_owned()],
+            },
+        ),
+        (
+            key("4"),
+            EntityDiff::Missing {
+                side: "right".to_owned(),
+            },
+        ),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(expected, diffs);
+}
@@ -0,0 +1,215 @@
+//! Inspect deployments and change which subgraphs and nodes they belong to.
+//! All changes go through the store so that running nodes are notified of
+//! them just like of changes made through the JSON-RPC admin server
+use std::str::FromStr;
+use std::sync::Arc;
+
+use graph::prelude::{
+    anyhow, web3::types::H256, DeploymentInfo, EthereumBlockPointer, NodeId, Store as _,
+    SubgraphDeploymentId, SubgraphName,
+};
+use graph_store_postgres::Store;
+
+fn deployment_id(id: &str) -> Result<SubgraphDeploymentId, anyhow::Error> {
+    SubgraphDeploymentId::new(id).map_err(|_| anyhow::anyhow!("invalid deployment id `{}`", id))
+}
+
+fn print_info(info: &DeploymentInfo) {
+    println!("name:         {}", info.subgraph.as_deref().unwrap_or("-"));
+    println!("version:      {}", info.version.as_deref().unwrap_or("-"));
+    println!("deployment:   {}", info.deployment);
+    println!("node:         {}", info.node_id.as_deref().unwrap_or("-"));
+    println!("synced:       {}", info.synced);
+    println!("health:       {}", info.health);
+    println!(
+        "latest block: {}",
+        info.latest_block_number
+            .map(|number| number.to_string())
+            .unwrap_or_else(|| "-".to_owned())
+    );
+    if let Some(error) = &info.fatal_error {
+        println!("fatal error:  {}", error);
+    }
+    if info.needs_rewind {
+        println!("needs rewind: true");
+    }
+}
+
+/// Format a number of bytes for humans
+fn human_size(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Show the deployments of the subgraph `name_or_id`, or the deployment
+/// with that id if `by_id` is set
+pub fn info(store: Arc<Store>, name_or_id: &str, by_id: bool) -> Result<(), anyhow::Error> {
+    let infos = if by_id {
+        store.deployment_infos(None, Some(&deployment_id(name_or_id)?))
+    } else {
+        let name = SubgraphName::new(name_or_id)
+            .map_err(|_| anyhow::anyhow!("invalid subgraph name `{}`", name_or_id))?;
+        store.deployment_infos(Some(&name), None)
+    }
+    .map_err(|e| anyhow::anyhow!("{}", e))?;
+    if infos.is_empty() {
+        return Err(anyhow::anyhow!(
+            "there is no subgraph or deployment `{}`",
+            name_or_id
+        ));
+    }
+    for (i, info) in infos.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_info(info);
+    }
+    Ok(())
+}
+
+pub fn stats(store: Arc<Store>, id: &str) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    let rate = store
+        .deployment_sync_rate(&id)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    let counts = store
+        .entity_counts(&id)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    match rate.blocks_per_minute {
+        Some(rate) => println!("blocks/minute: {:.1}", rate),
+        None => println!("blocks/minute: -"),
+    }
+    println!();
+    println!(
+        "{:<32} {:>12} {:>12}",
+        "entity type", "entities", "versions"
+    );
+    for count in counts {
+        println!(
+            "{:<32} {:>12} {:>12}",
+            count.entity_type, count.current, count.versions
+        );
+    }
+    Ok(())
+}
+
+/// Remove the subgraph `name`. Its deployments are kept, but they are
+/// unassigned if no other subgraph uses them
+pub fn remove(store: Arc<Store>, name: &str) -> Result<(), anyhow::Error> {
+    let name =
+        SubgraphName::new(name).map_err(|_| anyhow::anyhow!("invalid subgraph name `{}`", name))?;
+    store
+        .remove_subgraph(name.clone())
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!("Removed subgraph {}", name);
+    Ok(())
+}
+
+pub fn unassign(store: Arc<Store>, id: &str) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    store
+        .unassign_subgraph(&id)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!("Unassigned deployment {}", id);
+    Ok(())
+}
+
+pub fn reassign(store: Arc<Store>, id: &str, node: &str) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    let node = NodeId::new(node).map_err(|_| anyhow::anyhow!("invalid node id `{}`", node))?;
+    store
+        .reassign_subgraph(&id, &node)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!("Assigned deployment {} to node {}", id, node);
+    Ok(())
+}
+
+/// Rewind a deployment that failed or that needs a rewind to the block
+/// with the given hash and number. The block is not checked against the
+/// chain, and must therefore be a block on the canonical chain
+pub fn rewind(
+    store: Arc<Store>,
+    id: &str,
+    block_hash: &str,
+    block_number: u64,
+) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    let hash = H256::from_str(block_hash.trim_start_matches("0x"))
+        .map_err(|e| anyhow::anyhow!("invalid block hash `{}`: {}", block_hash, e))?;
+    let block = EthereumBlockPointer::from((hash, block_number));
+    store
+        .rewind_failed_deployment(&id, block)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!("Rewound deployment {} to block {}", id, block_number);
+    Ok(())
+}
+
+pub fn move_to(
+    store: Arc<Store>,
+    id: &str,
+    tablespace: Option<&str>,
+    schema: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    store
+        .move_deployment_schema(&id, tablespace, schema)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    if let Some(tablespace) = tablespace {
+        println!("Moved deployment {} to tablespace {}", id, tablespace);
+    }
+    if let Some(schema) = schema {
+        println!("Moved deployment {} to schema {}", id, schema);
+    }
+    Ok(())
+}
+
+pub fn list_unused(store: Arc<Store>) -> Result<(), anyhow::Error> {
+    let unused = store
+        .unused_deployments()
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!("{:<48} {:<19} {:>10}", "deployment", "unused since", "size");
+    for unused in unused {
+        println!(
+            "{:<48} {:<19} {:>10}",
+            unused.deployment,
+            unused.unused_at,
+            human_size(unused.size)
+        );
+    }
+    Ok(())
+}
+
+/// Remove the deployment `id`, or all unused deployments if `id` is
+/// `None`, together with their data
+pub fn remove_unused(store: Arc<Store>, id: Option<&str>) -> Result<(), anyhow::Error> {
+    let ids = match id {
+        Some(id) => vec![deployment_id(id)?],
+        None => store
+            .unused_deployments()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .into_iter()
+            .map(|unused| deployment_id(&unused.deployment))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    for id in ids {
+        store
+            .remove_deployment(&id)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        println!("Removed deployment {}", id);
+    }
+    Ok(())
+}
+
+#[test]
+fn format_human_size() {
+    assert_eq!("512.0 B", human_size(512));
+    assert_eq!("1.5 kB", human_size(1536));
+    assert_eq!("2.0 GB", human_size(2 * 1024 * 1024 * 1024));
+}
@@ -0,0 +1,44 @@
+//! Import names into the rainbow table that ENS names are looked up in
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use graph::prelude::{anyhow, info, Logger};
+use graph_store_postgres::EnsNames;
+
+/// How many names we read from the file before we add them to the table
+const CHUNK_SIZE: usize = 100_000;
+
+/// Add the names in `file`, one per line, to the rainbow table
+pub fn import(logger: &Logger, ens: EnsNames, file: &str) -> Result<(), anyhow::Error> {
+    let reader = BufReader::new(
+        File::open(file).map_err(|e| anyhow::anyhow!("can not open `{}`: {}", file, e))?,
+    );
+
+    let mut read = 0;
+    let mut added = 0;
+    let mut names = Vec::with_capacity(CHUNK_SIZE);
+    let mut add = |names: &mut Vec<String>| -> Result<(), anyhow::Error> {
+        read += names.len();
+        added += ens.import(names).map_err(|e| anyhow::anyhow!("{}", e))?;
+        names.clear();
+        info!(logger, "Imported names"; "read" => read, "added" => added);
+        Ok(())
+    };
+
+    for line in reader.lines() {
+        let line = line?;
+        let name = line.trim();
+        if name.is_empty() {
+            continue;
+        }
+        names.push(name.to_owned());
+        if names.len() == CHUNK_SIZE {
+            add(&mut names)?;
+        }
+    }
+    if !names.is_empty() {
+        add(&mut names)?;
+    }
+    println!("read {} names, added {}", read, added);
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Export the entities of a deployment into CSV files for other tools, and
+//! import them into a new deployment to bootstrap it. The files can be kept
+//! in a local directory or in object storage at a location of the form
+//! `s3://<bucket>/<prefix>`; for object storage, they are staged in a
+//! temporary local directory and streamed from and to it.
+//!
+//! CSV is the only format; writing Parquet or other columnar formats is not
+//! supported
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use graph::prelude::{anyhow, serde_json, BlockNumber, SubgraphDeploymentId};
+use graph::util::object_store::ObjectStore;
+use graph_store_postgres::{Export, Store, EXPORT_FILE};
+
+fn deployment_id(id: &str) -> Result<SubgraphDeploymentId, anyhow::Error> {
+    SubgraphDeploymentId::new(id).map_err(|_| anyhow::anyhow!("invalid deployment id `{}`", id))
+}
+
+/// A local directory for a temporary copy of an export in object storage
+fn staging_dir(id: &SubgraphDeploymentId) -> Result<PathBuf, anyhow::Error> {
+    let dir = std::env::temp_dir().join(format!("graphman-export-{}-{}", id, std::process::id()));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// The names of all files that make up `export`
+fn export_files(export: &Export) -> Vec<String> {
+    export
+        .entity_types
+        .iter()
+        .map(|entity_type| entity_type.file.clone())
+        .chain(std::iter::once(EXPORT_FILE.to_owned()))
+        .collect()
+}
+
+async fn upload(store: &ObjectStore, dir: &Path, export: &Export) -> Result<(), anyhow::Error> {
+    for file in export_files(export) {
+        store.put_file(&file, &dir.join(&file)).await?;
+    }
+    Ok(())
+}
+
+async fn download(store: &ObjectStore, location: &str, dir: &Path) -> Result<(), anyhow::Error> {
+    let get = |name: String| async move {
+        match store.get_file(&name, &dir.join(&name)).await? {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("{}/{} does not exist", location, name)),
+        }
+    };
+    get(EXPORT_FILE.to_owned()).await?;
+    let export: Export = serde_json::from_slice(&fs::read(dir.join(EXPORT_FILE))?)
+        .map_err(|e| anyhow::anyhow!("invalid export in {}: {}", location, e))?;
+    for file in export_files(&export) {
+        if file != EXPORT_FILE {
+            get(file).await?;
+        }
+    }
+    Ok(())
+}
+
+fn print_export(export: &Export) {
+    for entity_type in &export.entity_types {
+        println!(
+            "{:<40} {:>12} {}",
+            entity_type.name, entity_type.count, entity_type.file
+        );
+    }
+}
+
+pub async fn export(
+    store: Arc<Store>,
+    id: &str,
+    block: Option<BlockNumber>,
+    location: &str,
+) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    let object_store = if ObjectStore::is_object_store(location) {
+        Some(ObjectStore::from_location(location)?)
+    } else {
+        None
+    };
+    let dir = match object_store {
+        Some(_) => staging_dir(&id)?,
+        None => PathBuf::from(location),
+    };
+
+    let export = store
+        .export_entities(&id, block, &dir)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    if let Some(object_store) = &object_store {
+        let uploaded = upload(object_store, &dir, &export).await;
+        fs::remove_dir_all(&dir)?;
+        uploaded?;
+    }
+
+    print_export(&export);
+    println!(
+        "Exported deployment {} at block {} into {}",
+        id, export.block_number, location
+    );
+    if export.dynamic_data_sources > 0 {
+        println!(
+            "The deployment has {} dynamic data sources; the export can not be imported",
+            export.dynamic_data_sources
+        );
+    }
+    Ok(())
+}
+
+pub async fn import(store: Arc<Store>, id: &str, location: &str) -> Result<(), anyhow::Error> {
+    let id = deployment_id(id)?;
+    let (dir, staged) = if ObjectStore::is_object_store(location) {
+        let dir = staging_dir(&id)?;
+        let downloaded = download(&ObjectStore::from_location(location)?, location, &dir).await;
+        if let Err(e) = downloaded {
+            fs::remove_dir_all(&dir)?;
+            return Err(e);
+        }
+        (dir, true)
+    } else {
+        (PathBuf::from(location), false)
+    };
+
+    let export = store
+        .import_entities(&id, &dir)
+        .map_err(|e| anyhow::anyhow!("{}", e));
+    if staged {
+        fs::remove_dir_all(&dir)?;
+    }
+    let export = export?;
+
+    print_export(&export);
+    println!(
+        "Imported the entities of {} at block {} into {}; reassign it to continue indexing",
+        export.deployment, export.block_number, id
+    );
+    Ok(())
+}
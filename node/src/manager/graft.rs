@@ -0,0 +1,56 @@
+//! Preview what grafting a deployment with a new schema onto an existing
+//! deployment will do with the data of the existing deployment
+use std::fs;
+use std::sync::Arc;
+
+use graph::prelude::{
+    anyhow, GraftTableAction, Schema, SubgraphDeploymentId, SubgraphDeploymentStore as _,
+};
+use graph_store_postgres::Store;
+
+pub fn preview(
+    store: Arc<Store>,
+    base: SubgraphDeploymentId,
+    schema_file: &str,
+) -> Result<(), anyhow::Error> {
+    let raw = fs::read_to_string(schema_file)
+        .map_err(|e| anyhow::anyhow!("can not read {}: {}", schema_file, e))?;
+    let schema = Schema::parse(&raw, base.clone())
+        .map_err(|e| anyhow::anyhow!("invalid schema in {}: {}", schema_file, e))?;
+    let preview = store
+        .graft_preview(&base, &schema)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    println!("Grafting onto {}:", preview.base);
+    for (entity_type, action) in &preview.entity_types {
+        match action {
+            GraftTableAction::Copy => println!("  {}: copied as-is", entity_type),
+            GraftTableAction::Transform {
+                dropped,
+                added,
+                converted,
+            } => {
+                println!("  {}: copied with changes", entity_type);
+                for (what, attrs) in &[
+                    ("dropped", dropped),
+                    ("added", added),
+                    ("converted", converted),
+                ] {
+                    if !attrs.is_empty() {
+                        println!("      {}: {}", what, attrs.join(", "));
+                    }
+                }
+            }
+            GraftTableAction::Drop => println!("  {}: not copied", entity_type),
+            GraftTableAction::Create => println!("  {}: new, starts out empty", entity_type),
+        }
+    }
+
+    if !preview.errors.is_empty() {
+        println!("The graft is not possible:");
+        for error in &preview.errors {
+            println!("  - {}", error);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! Show the runs of the background jobs that maintain the database
+use std::sync::Arc;
+
+use graph::prelude::anyhow;
+use graph_store_postgres::Store;
+
+pub fn list(store: Arc<Store>, job: Option<&str>, limit: u32) -> Result<(), anyhow::Error> {
+    let runs = store
+        .job_runs(job, limit)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    println!(
+        "{:<8} {:<20} {:<10} {:<16} {:<19} {:<19} {}",
+        "id", "job", "database", "node", "started", "finished", "error"
+    );
+    for run in runs {
+        println!(
+            "{:<8} {:<20} {:<10} {:<16} {:<19} {:<19} {}",
+            run.id,
+            run.job,
+            run.database,
+            run.node_id,
+            run.started_at,
+            run.finished_at.as_deref().unwrap_or("running"),
+            run.error.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
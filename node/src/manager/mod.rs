@@ -0,0 +1,57 @@
+//! Commands for `graphman`, the tool that operators use to inspect and
+//! manage the data of a Graph Node installation
+use prometheus::Registry;
+use std::sync::Arc;
+
+use graph::prelude::Logger;
+use graph_core::MetricsRegistry;
+use graph_store_postgres::connection_pool::ConnectionPool;
+use graph_store_postgres::{EnsNames, Store, SubscriptionManager};
+
+pub mod api_key;
+pub mod compare;
+pub mod deployment;
+pub mod ens;
+pub mod export;
+pub mod graft;
+pub mod jobs;
+pub mod trigger_log;
+
+/// Connect to the database at `postgres_url` and return a store for it.
+/// The store only uses a small connection pool since it is only meant for
+/// administrative tasks
+pub fn open_store(logger: &Logger, name: &str, postgres_url: &str) -> Arc<Store> {
+    let registry = Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ));
+    let conn_pool =
+        ConnectionPool::create(name, postgres_url.to_owned(), 2, logger, registry.clone());
+    let subscriptions = Arc::new(SubscriptionManager::new(
+        logger.clone(),
+        postgres_url.to_owned(),
+    ));
+    Arc::new(Store::new(
+        logger,
+        subscriptions,
+        conn_pool,
+        vec![],
+        vec![],
+        registry,
+    ))
+}
+
+/// Connect to the database at `postgres_url` to manage the ENS names in it
+pub fn open_ens_names(logger: &Logger, postgres_url: &str) -> EnsNames {
+    let registry = Arc::new(MetricsRegistry::new(
+        logger.clone(),
+        Arc::new(Registry::new()),
+    ));
+    EnsNames::new(ConnectionPool::create(
+        "ens",
+        postgres_url.to_owned(),
+        2,
+        logger,
+        registry,
+    ))
+}
@@ -0,0 +1,35 @@
+//! Print the trigger log that a deployment recorded while it was indexing
+use std::path::Path;
+
+use graph::prelude::{anyhow, serde_json, SubgraphDeploymentId};
+use graph_core::{TriggerLog, TriggerLogRecord};
+
+pub fn dump(dir: &str, deployment: SubgraphDeploymentId, json: bool) -> Result<(), anyhow::Error> {
+    let records =
+        TriggerLog::read(Path::new(dir), &deployment).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    for record in records {
+        if json {
+            println!("{}", serde_json::to_string(&record)?);
+            continue;
+        }
+        match record {
+            TriggerLogRecord::Block {
+                block,
+                triggers,
+                data_source_triggers,
+            } => {
+                let dynamic: usize = data_source_triggers.iter().map(Vec::len).sum();
+                println!(
+                    "block {}: {} triggers, {} triggers for new data sources",
+                    block,
+                    triggers.len(),
+                    dynamic
+                );
+            }
+            TriggerLogRecord::Revert { to: Some(to) } => println!("revert to {}", to),
+            TriggerLogRecord::Revert { to: None } => println!("revert to the start"),
+        }
+    }
+    Ok(())
+}
@@ -15,6 +15,26 @@ lazy_static! {
     version = RENDERED_TESTAMENT.as_str()
 )]
 pub struct Opt {
+    #[structopt(
+        long,
+        value_name = "FILE",
+        env = "GRAPH_NODE_CONFIG",
+        help = "TOML file with the settings for the store, the Ethereum providers and the \
+                placement of deployments. Takes the place of the flags for these settings"
+    )]
+    pub config: Option<String>,
+    #[structopt(
+        long,
+        requires = "config",
+        help = "Check the configuration file and exit"
+    )]
+    pub check_config: bool,
+    #[structopt(
+        long,
+        help = "Together with --version, print the version and the supported \
+                spec versions, API versions and chains as JSON"
+    )]
+    pub json: bool,
     #[structopt(
         long,
         value_name = "[NAME:]IPFS_HASH",
@@ -26,9 +46,10 @@ pub struct Opt {
         long,
         value_name = "URL",
         env = "POSTGRES_URL",
+        required_unless = "config",
         help = "Location of the Postgres database used for storing entities"
     )]
-    pub postgres_url: String,
+    pub postgres_url: Option<String>,
     #[structopt(
         long,
         value_name = "URL,",
@@ -53,15 +74,16 @@ pub struct Opt {
     #[structopt(
         long,
         min_values=0,
-        required_unless_one = &["ethereum-ws", "ethereum-ipc"],
+        required_unless_one = &["ethereum-ws", "ethereum-ipc", "config"],
         conflicts_with_all = &["ethereum-ws", "ethereum-ipc"],
         value_name="NETWORK_NAME:URL",
         env="ETHEREUM_RPC",
-        help= "Ethereum network name (e.g. 'mainnet') and Ethereum RPC URL, separated by a ':'",
+        help= "Ethereum network name (e.g. 'mainnet') and Ethereum RPC URL, separated by a ':'. \
+    A URL of the form 'file:///path/to/dir' reads blocks from the files in that directory",
     )]
     pub ethereum_rpc: Vec<String>,
     #[structopt(long, min_values=0,
-        required_unless_one = &["ethereum-rpc", "ethereum-ipc"],
+        required_unless_one = &["ethereum-rpc", "ethereum-ipc", "config"],
         conflicts_with_all = &["ethereum-rpc", "ethereum-ipc"],
         value_name="NETWORK_NAME:URL",
         env="ETHEREUM_WS",
@@ -69,7 +91,7 @@ pub struct Opt {
     )]
     pub ethereum_ws: Vec<String>,
     #[structopt(long, min_values=0,
-        required_unless_one = &["ethereum-rpc", "ethereum-ws"],
+        required_unless_one = &["ethereum-rpc", "ethereum-ws", "config"],
         conflicts_with_all = &["ethereum-rpc", "ethereum-ws"],
         value_name="NETWORK_NAME:FILE",
         env="ETHEREUM_IPC",
@@ -197,4 +219,29 @@ pub struct Opt {
         help = "HTTP endpoint for 3box profiles"
     )]
     pub three_box_api: String,
+    #[structopt(
+        long,
+        value_name = "URL",
+        env = "GRAPH_ENS_POSTGRES_URL",
+        help = "Location of a Postgres database with the `ens_names` table that ENS names \
+                are looked up in. Defaults to the database at --postgres-url"
+    )]
+    pub ens_postgres_url: Option<String>,
+    #[structopt(
+        long,
+        value_name = "ENS_CONNECTION_POOL_SIZE",
+        default_value = "2",
+        env = "GRAPH_ENS_CONNECTION_POOL_SIZE",
+        help = "Limits the number of connections to the database at --ens-postgres-url"
+    )]
+    pub ens_connection_pool_size: u32,
+    #[structopt(
+        long,
+        value_name = "URL",
+        env = "GRAPH_ENS_ENDPOINT",
+        conflicts_with = "ens_postgres_url",
+        help = "HTTP endpoint that ENS names are looked up with instead of a database. \
+                A GET of <URL>/<hash> must return the name, or 404 if it is not known"
+    )]
+    pub ens_endpoint: Option<String>,
 }
@@ -6,7 +6,7 @@ use graph_core::MetricsRegistry;
 use graph_store_postgres::connection_pool::ConnectionPool;
 use graph_store_postgres::{
     ChainHeadUpdateListener as PostgresChainHeadUpdateListener, ChainStore as DieselChainStore,
-    NetworkStore as DieselNetworkStore, Store as DieselStore, SubscriptionManager,
+    EnsNames, NetworkStore as DieselNetworkStore, Store as DieselStore, SubscriptionManager,
 };
 
 /// Replace the host portion of `url` and return a new URL with `host`
@@ -29,6 +29,7 @@ fn replace_host(url: &str, host: &str) -> String {
 pub struct StoreBuilder {
     store: Arc<DieselStore>,
     conn_pool: ConnectionPool,
+    read_only_conn_pools: Vec<ConnectionPool>,
     chain_head_update_listener: Arc<PostgresChainHeadUpdateListener>,
 }
 
@@ -93,6 +94,7 @@ impl StoreBuilder {
         Self {
             store,
             conn_pool,
+            read_only_conn_pools,
             chain_head_update_listener,
         }
     }
@@ -117,4 +119,33 @@ impl StoreBuilder {
     pub fn store(&self) -> Arc<DieselStore> {
         self.store.cheap_clone()
     }
+
+    /// Change the size of the pools for the main database and the
+    /// replicas to `pool_size`
+    pub fn resize_pools(&self, pool_size: u32) {
+        self.conn_pool.resize(pool_size);
+        for pool in &self.read_only_conn_pools {
+            pool.resize(pool_size);
+        }
+    }
+
+    /// Return the lookup for ENS names. The names come from the database
+    /// at `postgres_url` if one is given, through a pool with `pool_size`
+    /// connections, and from the main database otherwise
+    pub fn ens_names(
+        &self,
+        logger: &Logger,
+        postgres_url: Option<&str>,
+        pool_size: u32,
+        registry: Arc<MetricsRegistry>,
+    ) -> Arc<EnsNames> {
+        let pool = match postgres_url {
+            Some(url) => {
+                info!(logger, "Looking up ENS names in a separate database");
+                ConnectionPool::create("ens", url.to_owned(), pool_size, logger, registry)
+            }
+            None => self.conn_pool.clone(),
+        };
+        Arc::new(EnsNames::new(pool))
+    }
 }
@@ -443,6 +443,19 @@ pub(crate) struct AscEthereumTransaction_0_0_2 {
     pub input: AscPtr<Bytes>,
 }
 
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumBlockTransaction {
+    pub hash: AscPtr<AscH256>,
+    pub index: AscPtr<AscBigInt>,
+    pub from: AscPtr<AscH160>,
+    pub to: AscPtr<AscH160>,
+    pub value: AscPtr<AscBigInt>,
+    pub selector: AscPtr<Bytes>,
+}
+
+pub(crate) type AscEthereumBlockTransactionArray = Array<AscPtr<AscEthereumBlockTransaction>>;
+
 #[repr(C)]
 #[derive(AscType)]
 pub(crate) struct AscEthereumEvent<T>
@@ -1,24 +1,27 @@
 use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use ethabi::{LogParam, RawLog};
 use futures::sync::mpsc::Sender;
 use futures03::channel::oneshot::channel;
 use graph::ensure;
-use semver::{Version, VersionReq};
+use semver::Version;
 use slog::{o, OwnedKV};
 use strum::AsStaticRef as _;
 use tiny_keccak::keccak256;
 
 use graph::components::arweave::ArweaveAdapter;
+use graph::components::ens::EnsLookup;
 use graph::components::ethereum::*;
 use graph::components::store::Store;
-use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::components::subgraph::{HandlerError, MappingError, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
+use graph::data::subgraph::features::ALLOW_NON_DETERMINISTIC_IPFS;
 use graph::data::subgraph::{Mapping, Source};
+use graph::data::version::MAX_API_VERSION;
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
@@ -33,8 +36,7 @@ lazy_static! {
         .ok()
         .map(|s| u64::from_str(&s).expect("Invalid value for GRAPH_MAPPING_HANDLER_TIMEOUT"))
         .map(Duration::from_secs);
-    static ref ALLOW_NON_DETERMINISTIC_IPFS: bool =
-        std::env::var("GRAPH_ALLOW_NON_DETERMINISTIC_IPFS").is_ok();
+    static ref REUSE_WASM_INSTANCES: bool = std::env::var("GRAPH_REUSE_WASM_INSTANCES").is_ok();
 }
 
 struct RuntimeHostConfig {
@@ -43,6 +45,7 @@ struct RuntimeHostConfig {
     data_source_network: String,
     data_source_name: String,
     data_source_context: Option<DataSourceContext>,
+    group: Option<Arc<DataSourceGroup>>,
     contract: Source,
     templates: Arc<Vec<DataSourceTemplate>>,
 }
@@ -53,6 +56,7 @@ pub struct RuntimeHostBuilder<S> {
     stores: HashMap<String, Arc<S>>,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+    ens_lookup: Arc<dyn EnsLookup>,
 }
 
 impl<S> Clone for RuntimeHostBuilder<S>
@@ -66,6 +70,7 @@ where
             stores: self.stores.clone(),
             arweave_adapter: self.arweave_adapter.cheap_clone(),
             three_box_adapter: self.three_box_adapter.cheap_clone(),
+            ens_lookup: self.ens_lookup.cheap_clone(),
         }
     }
 }
@@ -80,6 +85,7 @@ where
         stores: HashMap<String, Arc<S>>,
         arweave_adapter: Arc<dyn ArweaveAdapter>,
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+        ens_lookup: Arc<dyn EnsLookup>,
     ) -> Self {
         RuntimeHostBuilder {
             ethereum_networks,
@@ -87,6 +93,7 @@ where
             stores,
             arweave_adapter,
             three_box_adapter,
+            ens_lookup,
         }
     }
 }
@@ -112,6 +119,7 @@ where
             tokio::runtime::Handle::current(),
             *TIMEOUT,
             *ALLOW_NON_DETERMINISTIC_IPFS,
+            *REUSE_WASM_INSTANCES,
         )
     }
 
@@ -120,6 +128,7 @@ where
         network_name: String,
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
+        group: Option<Arc<DataSourceGroup>>,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         mapping_request_sender: Sender<MappingRequest>,
         metrics: Arc<HostMetrics>,
@@ -155,6 +164,7 @@ where
                 data_source_network: network_name,
                 data_source_name: data_source.name,
                 data_source_context: data_source.context,
+                group,
                 contract: data_source.source,
                 templates,
             },
@@ -162,6 +172,7 @@ where
             metrics,
             self.arweave_adapter.cheap_clone(),
             self.three_box_adapter.cheap_clone(),
+            self.ens_lookup.cheap_clone(),
         )
     }
 }
@@ -170,10 +181,12 @@ where
 pub struct RuntimeHost {
     data_source_name: String,
     data_source_contract: Source,
-    data_source_contract_abi: MappingABI,
+    // Subgraph data sources do not have a contract, and therefore no ABI
+    data_source_contract_abi: Option<MappingABI>,
     data_source_event_handlers: Vec<MappingEventHandler>,
     data_source_call_handlers: Vec<MappingCallHandler>,
     data_source_block_handlers: Vec<MappingBlockHandler>,
+    data_source_entity_handlers: Vec<MappingEntityHandler>,
     mapping_request_sender: Sender<MappingRequest>,
     host_exports: Arc<HostExports>,
     metrics: Arc<HostMetrics>,
@@ -190,29 +203,36 @@ impl RuntimeHost {
         metrics: Arc<HostMetrics>,
         arweave_adapter: Arc<dyn ArweaveAdapter>,
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+        ens_lookup: Arc<dyn EnsLookup>,
     ) -> Result<Self, Error> {
         let api_version = Version::parse(&config.mapping.api_version)?;
-        if !VersionReq::parse("<= 0.0.4").unwrap().matches(&api_version) {
+        if api_version > *MAX_API_VERSION {
             return Err(format_err!(
-                "This Graph Node only supports mapping API versions <= 0.0.4, but subgraph `{}` uses `{}`",
+                "This Graph Node only supports mapping API versions <= {}, but subgraph `{}` uses `{}`",
+                *MAX_API_VERSION,
                 config.subgraph_id,
                 api_version
             ));
         }
 
-        let data_source_contract_abi = config
-            .mapping
-            .abis
-            .iter()
-            .find(|abi| abi.name == config.contract.abi)
-            .ok_or_else(|| {
-                format_err!(
-                    "No ABI entry found for the main contract of data source \"{}\": {}",
-                    &config.data_source_name,
-                    config.contract.abi,
-                )
-            })?
-            .clone();
+        let data_source_contract_abi = match config.contract.deployment {
+            Some(_) => None,
+            None => Some(
+                config
+                    .mapping
+                    .abis
+                    .iter()
+                    .find(|abi| abi.name == config.contract.abi)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "No ABI entry found for the main contract of data source \"{}\": {}",
+                            &config.data_source_name,
+                            config.contract.abi,
+                        )
+                    })?
+                    .clone(),
+            ),
+        };
 
         let data_source_name = config.data_source_name;
 
@@ -225,6 +245,7 @@ impl RuntimeHost {
             config.contract.address.clone(),
             config.data_source_network,
             config.data_source_context,
+            config.group,
             config.templates,
             config.mapping.abis,
             ethereum_adapter,
@@ -233,6 +254,7 @@ impl RuntimeHost {
             call_cache,
             arweave_adapter,
             three_box_adapter,
+            ens_lookup,
         ));
 
         Ok(RuntimeHost {
@@ -242,6 +264,7 @@ impl RuntimeHost {
             data_source_event_handlers: config.mapping.event_handlers,
             data_source_call_handlers: config.mapping.call_handlers,
             data_source_block_handlers: config.mapping.block_handlers,
+            data_source_entity_handlers: config.mapping.entity_handlers,
             mapping_request_sender,
             host_exports,
             metrics,
@@ -380,6 +403,29 @@ impl RuntimeHost {
         }
     }
 
+    fn contract_abi(&self) -> Result<&MappingABI, anyhow::Error> {
+        self.data_source_contract_abi.as_ref().with_context(|| {
+            format_err!(
+                "Data source \"{}\" has no contract ABI",
+                self.data_source_name
+            )
+        })
+    }
+
+    fn handler_for_entity(&self, entity_type: &str) -> Result<MappingEntityHandler, anyhow::Error> {
+        self.data_source_entity_handlers
+            .iter()
+            .find(|handler| handler.entity == entity_type)
+            .cloned()
+            .with_context(|| {
+                format_err!(
+                    "No entity handler found for entity type `{}` in data source \"{}\"",
+                    entity_type,
+                    self.data_source_name,
+                )
+            })
+    }
+
     /// Sends a MappingRequest to the thread which owns the host,
     /// and awaits the result.
     async fn send_mapping_request<T: slog::SendSyncRefUnwindSafeKV>(
@@ -393,6 +439,7 @@ impl RuntimeHost {
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError> {
         let trigger_type = trigger.as_static();
+        let (transaction_hash, log_index) = trigger.origin();
         debug!(
             logger, "Start processing Ethereum trigger";
             &extra,
@@ -447,10 +494,55 @@ impl RuntimeHost {
                 .as_millis(),
         );
 
+        if let Err(e) = &result {
+            let (e, deterministic) = match e {
+                MappingError::Deterministic(e) => (e, true),
+                MappingError::PossibleReorg(e) | MappingError::Unknown(e) => (e, false),
+            };
+            HandlerError::record(
+                self.host_exports.subgraph_id.as_str(),
+                HandlerError {
+                    block: match (block.hash, block.number) {
+                        (Some(hash), Some(number)) => {
+                            Some(EthereumBlockPointer::from((hash, number.as_u64())))
+                        }
+                        _ => None,
+                    },
+                    data_source: self.data_source_name.clone(),
+                    handler: handler.to_owned(),
+                    trigger: trigger_type.to_lowercase(),
+                    transaction_hash,
+                    log_index,
+                    message: format!("{:#}", e),
+                    wasm_backtrace: wasm_backtrace(e),
+                    deterministic,
+                    timestamp: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|now| now.as_secs())
+                        .unwrap_or(0),
+                },
+            );
+        }
+
         result
     }
 }
 
+/// The names of the functions on the WASM stack when `e` was caused by a
+/// trap, innermost first.
+fn wasm_backtrace(e: &anyhow::Error) -> Option<Vec<String>> {
+    let trap = e.chain().find_map(|e| e.downcast_ref::<wasmtime::Trap>())?;
+    Some(
+        trap.trace()
+            .iter()
+            .map(|frame| match frame.func_name() {
+                Some(name) => name.to_owned(),
+                None => format!("<wasm function {}>", frame.func_index()),
+            })
+            .collect(),
+    )
+}
+
 #[async_trait]
 impl RuntimeHostTrait for RuntimeHost {
     fn matches_log(&self, log: &Log) -> bool {
@@ -474,6 +566,20 @@ impl RuntimeHostTrait for RuntimeHost {
             && self.data_source_contract.start_block <= block_number
     }
 
+    fn matches_entity(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        entity_type: &str,
+        block_number: u64,
+    ) -> bool {
+        self.data_source_contract.deployment.as_ref() == Some(deployment)
+            && self
+                .data_source_entity_handlers
+                .iter()
+                .any(|handler| handler.entity == entity_type)
+            && self.data_source_contract.start_block <= block_number
+    }
+
     async fn process_call(
         &self,
         logger: &Logger,
@@ -486,9 +592,11 @@ impl RuntimeHostTrait for RuntimeHost {
         // Identify the call handler for this call
         let call_handler = self.handler_for_call(&call)?;
 
+        let contract_abi = self.contract_abi()?;
+
         // Identify the function ABI in the contract
         let function_abi = util::ethereum::contract_function_with_signature(
-            &self.data_source_contract_abi.contract,
+            &contract_abi.contract,
             call_handler.function.as_str(),
         )
         .with_context(|| {
@@ -496,7 +604,7 @@ impl RuntimeHostTrait for RuntimeHost {
                 "Function with the signature \"{}\" not found in \
                     contract \"{}\" of data source \"{}\"",
                 call_handler.function,
-                self.data_source_contract_abi.name,
+                contract_abi.name,
                 self.data_source_name
             )
         })?;
@@ -608,6 +716,34 @@ impl RuntimeHostTrait for RuntimeHost {
         .await
     }
 
+    async fn process_entity(
+        &self,
+        logger: &Logger,
+        block: &Arc<LightEthereumBlock>,
+        entity_type: &str,
+        entity: &Entity,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let entity_handler = self.handler_for_entity(entity_type)?;
+        self.send_mapping_request(
+            logger,
+            o! {
+                "entity" => entity_type.to_owned(),
+                "id" => entity.id().unwrap_or_default(),
+            },
+            state,
+            &entity_handler.handler,
+            MappingTrigger::Entity {
+                entity: entity.clone(),
+                handler: entity_handler.clone(),
+            },
+            block,
+            proof_of_indexing,
+        )
+        .await
+    }
+
     async fn process_log(
         &self,
         logger: &Logger,
@@ -618,8 +754,9 @@ impl RuntimeHostTrait for RuntimeHost {
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError> {
         let data_source_name = &self.data_source_name;
-        let abi_name = &self.data_source_contract_abi.name;
-        let contract = &self.data_source_contract_abi.contract;
+        let contract_abi = self.contract_abi()?;
+        let abi_name = &contract_abi.name;
+        let contract = &contract_abi.contract;
 
         // If there are no matching handlers, fail processing the event
         let potential_handlers = self.handlers_for_log(&log)?;
@@ -733,6 +870,7 @@ impl PartialEq for RuntimeHost {
             data_source_event_handlers,
             data_source_call_handlers,
             data_source_block_handlers,
+            data_source_entity_handlers,
             host_exports,
             mapping_request_sender: _,
             metrics: _,
@@ -747,6 +885,7 @@ impl PartialEq for RuntimeHost {
             && data_source_event_handlers == &other.data_source_event_handlers
             && data_source_call_handlers == &other.data_source_call_handlers
             && data_source_block_handlers == &other.data_source_block_handlers
+            && data_source_entity_handlers == &other.data_source_entity_handlers
             && host_exports.data_source_context() == other.host_exports.data_source_context()
     }
 }
@@ -2,6 +2,7 @@ use crate::UnresolvedContractCall;
 use bytes::Bytes;
 use ethabi::{Address, Token};
 use graph::components::arweave::ArweaveAdapter;
+use graph::components::ens::EnsLookup;
 use graph::components::ethereum::*;
 use graph::components::store::EntityKey;
 use graph::components::subgraph::{ProofOfIndexingEvent, SharedProofOfIndexing};
@@ -13,8 +14,9 @@ use semver::Version;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::str::FromStr;
-use std::time::{Duration, Instant};
-use web3::types::H160;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use web3::types::{H160, H256};
 
 use graph_graphql::prelude::validate_entity;
 
@@ -48,7 +50,7 @@ impl From<failure::Error> for HostExportError {
 }
 
 pub(crate) struct HostExports {
-    subgraph_id: SubgraphDeploymentId,
+    pub(crate) subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
     data_source_name: String,
     data_source_address: Option<Address>,
@@ -59,6 +61,9 @@ pub(crate) struct HostExports {
     /// and merge the results later. Right now, this is just the ethereum
     /// networks but will be expanded for ipfs and the availability chain.
     causality_region: String,
+    /// The group of independent data sources this data source belongs to,
+    /// if the manifest declares `independentDataSources`
+    group: Option<Arc<DataSourceGroup>>,
     templates: Arc<Vec<DataSourceTemplate>>,
     abis: Vec<MappingABI>,
     ethereum_adapter: Arc<dyn EthereumAdapter>,
@@ -67,6 +72,10 @@ pub(crate) struct HostExports {
     store: Arc<dyn crate::RuntimeStore>,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+    ens_lookup: Arc<dyn EnsLookup>,
+    /// The transactions we last fetched for a block that did not come
+    /// with its transactions, keyed by the block hash
+    block_transactions: Mutex<Option<(H256, Arc<Vec<EthereumBlockTransactionData>>)>>,
 }
 
 // Not meant to be useful, only to allow deriving.
@@ -84,6 +93,7 @@ impl HostExports {
         data_source_address: Option<Address>,
         data_source_network: String,
         data_source_context: Option<DataSourceContext>,
+        group: Option<Arc<DataSourceGroup>>,
         templates: Arc<Vec<DataSourceTemplate>>,
         abis: Vec<MappingABI>,
         ethereum_adapter: Arc<dyn EthereumAdapter>,
@@ -92,8 +102,15 @@ impl HostExports {
         call_cache: Arc<dyn EthereumCallCache>,
         arweave_adapter: Arc<dyn ArweaveAdapter>,
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
+        ens_lookup: Arc<dyn EnsLookup>,
     ) -> Self {
-        let causality_region = format!("ethereum/{}", data_source_network);
+        // Independent groups are processed concurrently, and each of them
+        // needs its own causality region to keep the proof of indexing
+        // independent of the order in which groups finish
+        let causality_region = match &group {
+            Some(group) => format!("ethereum/{}/{}", data_source_network, group.name),
+            None => format!("ethereum/{}", data_source_network),
+        };
 
         Self {
             subgraph_id,
@@ -103,6 +120,7 @@ impl HostExports {
             data_source_network,
             data_source_context,
             causality_region,
+            group,
             templates,
             abis,
             ethereum_adapter,
@@ -111,6 +129,8 @@ impl HostExports {
             store,
             arweave_adapter,
             three_box_adapter,
+            ens_lookup,
+            block_transactions: Mutex::new(None),
         }
     }
 
@@ -143,6 +163,25 @@ impl HostExports {
         )))
     }
 
+    /// Make sure that the data source only reads and changes entity types
+    /// that its group declares. Handlers that run concurrently could
+    /// otherwise change the same entities, and a group does not see the
+    /// changes that other groups make in the same block
+    pub(crate) fn check_entity_type(&self, entity_type: &str) -> Result<(), HostExportError> {
+        match &self.group {
+            Some(group) if !group.entities.contains(entity_type) => {
+                Err(HostExportError::Deterministic(anyhow::anyhow!(
+                    "data source `{}` accessed an entity of type `{}` which is not listed \
+                     in the `entities` of its mapping; this is required when the \
+                     manifest sets `independentDataSources`",
+                    self.data_source_name,
+                    entity_type
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub(crate) fn store_set(
         &self,
         logger: &Logger,
@@ -602,13 +641,66 @@ impl HostExports {
         Ok(())
     }
 
+    /// The transactions of `block`. Blocks normally come with their
+    /// transactions; if one does not, we fetch them from the Ethereum node
+    /// the first time a handler asks for them, and keep them for the other
+    /// handlers of the same block
+    pub(crate) fn ethereum_block_transactions(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Result<Arc<Vec<EthereumBlockTransactionData>>, HostExportError> {
+        if self.api_version < Version::new(0, 0, 5) {
+            return Err(HostExportError::Deterministic(anyhow::anyhow!(
+                "`ethereum.blockTransactions` requires apiVersion 0.0.5 or later, \
+                 but the mapping uses apiVersion {}",
+                self.api_version
+            )));
+        }
+
+        if !EthereumBlockTransactionData::missing_from(block) {
+            return Ok(Arc::new(EthereumBlockTransactionData::from_block(block)));
+        }
+
+        let hash = block.hash.ok_or_else(|| {
+            HostExportError::Unknown(anyhow::anyhow!("the current block has no hash"))
+        })?;
+        let mut cache = self.block_transactions.lock().unwrap();
+        if let Some((cached, transactions)) = &*cache {
+            if *cached == hash {
+                return Ok(transactions.clone());
+            }
+        }
+
+        let eth_adapter = self.ethereum_adapter.clone();
+        let logger = logger.clone();
+        let full_block = block_on(future::lazy(move || {
+            eth_adapter.block_by_hash(&logger, hash)
+        }))?
+        .ok_or_else(|| {
+            HostExportError::Unknown(anyhow::anyhow!(
+                "the Ethereum node does not have block {:x}",
+                hash
+            ))
+        })?;
+        let transactions = Arc::new(EthereumBlockTransactionData::from_block(&full_block));
+        *cache = Some((hash, transactions.clone()));
+        Ok(transactions)
+    }
+
     pub(crate) fn ens_name_by_hash(&self, hash: &str) -> Result<Option<String>, anyhow::Error> {
         use graph::prelude::failure::ResultExt;
 
-        Ok(self.store.find_ens_name(hash).compat()?)
+        Ok(self.ens_lookup.find_name(hash).compat()?)
     }
 
-    pub(crate) fn log_log(&self, logger: &Logger, level: slog::Level, msg: String) {
+    pub(crate) fn log_log(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        level: slog::Level,
+        msg: String,
+    ) {
         let rs = record_static!(level, self.data_source_name.as_str());
 
         logger.log(&slog::Record::new(
@@ -617,6 +709,21 @@ impl HostExports {
             b!("data_source" => &self.data_source_name),
         ));
 
+        HandlerLog::record(
+            self.subgraph_id.as_str(),
+            HandlerLog {
+                block_number: block.number(),
+                block_hash: block.hash.unwrap_or_default(),
+                data_source: self.data_source_name.clone(),
+                level: level.as_str().to_lowercase(),
+                message: msg,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|now| now.as_secs())
+                    .unwrap_or(0),
+            },
+        );
+
         if level == slog::Level::Critical {
             panic!("Critical error logged in mapping");
         }
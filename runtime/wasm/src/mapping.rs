@@ -1,3 +1,4 @@
+use crate::module::globals::export_mutable_globals;
 use crate::module::WasmInstance;
 use ethabi::LogParam;
 use futures::sync::mpsc;
@@ -5,12 +6,87 @@ use futures03::channel::oneshot::Sender;
 use graph::components::ethereum::*;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::prelude::*;
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Instant;
 use strum_macros::AsStaticStr;
-use web3::types::{Log, Transaction};
+use web3::types::{Log, Transaction, H256};
+
+lazy_static! {
+    /// How many compiled modules we keep in memory
+    static ref MODULE_CACHE_SIZE: usize = env::var("GRAPH_WASM_MODULE_CACHE_SIZE")
+        .ok()
+        .map(|s| {
+            usize::from_str(&s).unwrap_or_else(|_| {
+                panic!("GRAPH_WASM_MODULE_CACHE_SIZE must be a number, but is `{}`", s)
+            })
+        })
+        .unwrap_or(100);
+
+    /// A wasmtime cache configuration file. When it is set, compiled modules
+    /// are also cached on disk and survive restarts of the node
+    static ref COMPILATION_CACHE_CONFIG: Option<String> =
+        env::var("GRAPH_WASM_COMPILATION_CACHE_CONFIG").ok();
+
+    static ref MODULE_CACHE: Mutex<ModuleCache> = Mutex::new(ModuleCache::default());
+}
+
+/// Compiled modules, keyed by the hash of their code and whether their
+/// instances can be reused. Restarting a subgraph, or starting several
+/// deployments that use the same mappings, therefore compiles each
+/// module only once
+#[derive(Default)]
+struct ModuleCache {
+    modules: HashMap<([u8; 32], bool), Arc<ValidModule>>,
+    /// The keys of `modules`, least recently used first
+    order: VecDeque<([u8; 32], bool)>,
+}
+
+impl ModuleCache {
+    fn get(&mut self, key: &([u8; 32], bool)) -> Option<Arc<ValidModule>> {
+        let module = self.modules.get(key)?.cheap_clone();
+        self.order.retain(|k| k != key);
+        self.order.push_back(*key);
+        Some(module)
+    }
+
+    fn insert(&mut self, key: ([u8; 32], bool), module: Arc<ValidModule>) {
+        if self.modules.insert(key, module).is_none() {
+            self.order.push_back(key);
+        }
+        while self.modules.len() > *MODULE_CACHE_SIZE {
+            match self.order.pop_front() {
+                Some(key) => self.modules.remove(&key),
+                None => break,
+            };
+        }
+    }
+}
+
+/// Return the compiled version of `raw_module`, compiling it only if it
+/// is not in the cache yet
+fn compiled_module(raw_module: &[u8], reusable: bool) -> Result<Arc<ValidModule>, anyhow::Error> {
+    let key = (tiny_keccak::keccak256(raw_module), reusable);
+    if let Some(module) = MODULE_CACHE.lock().unwrap().get(&key) {
+        return Ok(module);
+    }
+
+    // Compile without holding the lock so that compiling a large module
+    // does not hold up other subgraphs
+    let module = Arc::new(match reusable {
+        true => ValidModule::new_reusable(raw_module)?,
+        false => ValidModule::new(raw_module)?,
+    });
+    MODULE_CACHE
+        .lock()
+        .unwrap()
+        .insert(key, module.cheap_clone());
+    Ok(module)
+}
 
 /// Spawn a wasm module in its own thread.
 pub fn spawn_module(
@@ -21,8 +97,9 @@ pub fn spawn_module(
     runtime: tokio::runtime::Handle,
     timeout: Option<Duration>,
     allow_non_deterministic_ipfs: bool,
+    reuse_instances: bool,
 ) -> Result<mpsc::Sender<MappingRequest>, anyhow::Error> {
-    let valid_module = Arc::new(ValidModule::new(&raw_module)?);
+    let valid_module = compiled_module(&raw_module, reuse_instances)?;
 
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
@@ -37,6 +114,10 @@ pub fn spawn_module(
         thread::Builder::new().name(format!("mapping-{}-{}", &subgraph_id, uuid::Uuid::new_v4()));
     conf.spawn(move || {
         runtime.enter(|| {
+            // An instance that handled the previous trigger successfully and
+            // can be reset for the next one
+            let mut idle_instance: Option<WasmInstance> = None;
+
             // Pass incoming triggers to the WASM module and return entity changes;
             // Stop when canceled because all RuntimeHosts and their senders were dropped.
             match mapping_request_receiver
@@ -48,15 +129,27 @@ pub fn spawn_module(
                         result_sender,
                     } = request;
 
-                    // Start the WASM module runtime.
+                    // Start the WASM module runtime, or reuse the previous instance
                     let section = host_metrics.stopwatch.start_section("module_init");
-                    let module = WasmInstance::from_valid_module_with_ctx(
-                        valid_module.clone(),
-                        ctx,
-                        host_metrics.clone(),
-                        timeout,
-                        allow_non_deterministic_ipfs,
-                    )?;
+                    let mut module = match idle_instance.take() {
+                        Some(mut module) if module.can_reset() => {
+                            module.reset(ctx)?;
+                            module
+                        }
+                        _ => {
+                            let mut module = WasmInstance::from_valid_module_with_ctx(
+                                valid_module.clone(),
+                                ctx,
+                                host_metrics.clone(),
+                                timeout,
+                                allow_non_deterministic_ipfs,
+                            )?;
+                            if reuse_instances {
+                                module.snapshot();
+                            }
+                            module
+                        }
+                    };
                     section.end();
 
                     let section = host_metrics.stopwatch.start_section("run_handler");
@@ -88,9 +181,22 @@ pub fn spawn_module(
                         MappingTrigger::Block { handler } => {
                             module.handle_ethereum_block(handler.handler.as_str())
                         }
+                        MappingTrigger::Entity { entity, handler } => {
+                            module.handle_entity(handler.handler.as_str(), entity)
+                        }
                     };
                     section.end();
 
+                    // Instances whose handler failed may be in any state and are
+                    // never reused. The instance must let go of the proof of
+                    // indexing before the result is sent
+                    if reuse_instances && result.is_ok() {
+                        module.pause();
+                        idle_instance = Some(module);
+                    } else {
+                        drop(module);
+                    }
+
                     result_sender
                         .send((result, future::ok(Instant::now())))
                         .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
@@ -127,6 +233,25 @@ pub(crate) enum MappingTrigger {
     Block {
         handler: MappingBlockHandler,
     },
+    Entity {
+        entity: Entity,
+        handler: MappingEntityHandler,
+    },
+}
+
+impl MappingTrigger {
+    /// The hash of the transaction and the index of the log that caused the
+    /// trigger, as far as they are known.
+    pub(crate) fn origin(&self) -> (Option<H256>, Option<u64>) {
+        match self {
+            MappingTrigger::Log { log, .. } => (
+                log.transaction_hash,
+                log.log_index.map(|index| index.as_u64()),
+            ),
+            MappingTrigger::Call { call, .. } => (call.transaction_hash, None),
+            MappingTrigger::Block { .. } | MappingTrigger::Entity { .. } => (None, None),
+        }
+    }
 }
 
 type MappingResponse = (
@@ -175,6 +300,10 @@ pub(crate) struct ValidModule {
     // AS now has an `@external("module", "name")` decorator which would make things cleaner, but
     // the ship has sailed.
     pub(super) import_name_to_modules: BTreeMap<String, Vec<String>>,
+
+    // Whether all mutable globals of the module are exported so that instances can be reset
+    // to their initial state and reused.
+    pub(super) reusable: bool,
 }
 
 impl ValidModule {
@@ -188,6 +317,9 @@ impl ValidModule {
         config.interruptable(true); // For timeouts.
         config.cranelift_nan_canonicalization(true); // For NaN determinism.
         config.cranelift_opt_level(wasmtime::OptLevel::None);
+        if let Some(cache_config) = &*COMPILATION_CACHE_CONFIG {
+            config.cache_config_load(cache_config)?;
+        }
         let engine = &wasmtime::Engine::new(&config);
         let module = wasmtime::Module::from_binary(&engine, raw_module)?;
 
@@ -205,6 +337,21 @@ impl ValidModule {
         Ok(ValidModule {
             module,
             import_name_to_modules,
+            reusable: false,
         })
     }
+
+    /// Like `new`, but export all mutable globals of the module first so that
+    /// its instances can be reused. If that is not possible, the module is
+    /// used as is and its instances are not reused
+    pub fn new_reusable(raw_module: &[u8]) -> Result<Self, anyhow::Error> {
+        match export_mutable_globals(raw_module)? {
+            Some(raw_module) => {
+                let mut module = Self::new(&raw_module)?;
+                module.reusable = true;
+                Ok(module)
+            }
+            None => Self::new(raw_module),
+        }
+    }
 }
@@ -0,0 +1,48 @@
+//! Rewrite WASM modules so that all their mutable globals are exported.
+//! Instances of such a module can be reset to the state they had right
+//! after instantiation, which makes it possible to reuse them for more
+//! than one handler.
+use graph::prelude::anyhow;
+use walrus::{GlobalKind, ModuleConfig};
+
+/// The name under which the mutable global with index `index` is exported
+pub(crate) fn export_name(index: u32) -> String {
+    format!("__graph_global_{}", index)
+}
+
+/// Return a copy of `raw_module` that exports every mutable global that
+/// it defines under `export_name`. Returns `None` if that is not possible
+/// because the module imports mutable globals, whose state we can not
+/// reset, or already uses one of the export names
+pub(crate) fn export_mutable_globals(raw_module: &[u8]) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let mut module = ModuleConfig::new()
+        .generate_producers_section(false)
+        .parse(raw_module)?;
+
+    let mut mutable_globals = Vec::new();
+    for (index, global) in module.globals.iter().enumerate() {
+        if !global.mutable {
+            continue;
+        }
+        if let GlobalKind::Import(_) = global.kind {
+            return Ok(None);
+        }
+        mutable_globals.push((export_name(index as u32), global.id()));
+    }
+
+    if mutable_globals.is_empty() {
+        return Ok(Some(raw_module.to_vec()));
+    }
+    if module
+        .exports
+        .iter()
+        .any(|export| mutable_globals.iter().any(|(name, _)| name == &export.name))
+    {
+        return Ok(None);
+    }
+
+    for (name, global) in mutable_globals {
+        module.exports.add(&name, global);
+    }
+    Ok(Some(module.emit_wasm()))
+}
@@ -26,6 +26,7 @@ use crate::host_exports::{EthereumCallError, HostExports};
 use crate::mapping::ValidModule;
 use crate::UnresolvedContractCall;
 
+pub(crate) mod globals;
 mod into_wasm_ret;
 mod stopwatch;
 
@@ -37,6 +38,9 @@ mod test;
 
 const TRAP_TIMEOUT: &str = "trap: interrupt";
 
+/// The size of a page of WASM memory
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
 macro_rules! try_host_export {
     ($this:ident, $e:expr) => {
         match $e {
@@ -60,6 +64,48 @@ pub(crate) struct WasmInstance {
     // Also this is the only strong reference, so the instance will be dropped once this is dropped.
     // The weak references are circulary held by instance itself through host exports.
     instance_ctx: Rc<RefCell<Option<WasmInstanceContext>>>,
+
+    // The state of the instance right after instantiation, if it can be reset to it and
+    // reused for another handler.
+    snapshot: Option<Snapshot>,
+
+    // Where handler profiles go; `None` unless handler profiling is turned on.
+    profile: Option<Arc<HandlerProfile>>,
+}
+
+/// The contents of memory and the values of all mutable globals of an instance.
+/// Memory past the last WASM page that is not all zeros is not kept
+struct Snapshot {
+    memory: Vec<u8>,
+    memory_size: usize,
+    globals: Vec<(wasmtime::Global, wasmtime::Val)>,
+}
+
+/// Set all of `memory` to zero. On Linux, the kernel drops the pages of
+/// `memory` instead, so that this only costs as much as the number of
+/// pages that were actually written. `memory` must start at a page boundary
+fn zero_memory(memory: &mut [u8]) {
+    if memory.is_empty() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // Safety: WASM memory is a private anonymous mapping, whose pages
+        // read as zeros after `MADV_DONTNEED`
+        let res = unsafe {
+            libc::madvise(
+                memory.as_mut_ptr() as *mut libc::c_void,
+                memory.len(),
+                libc::MADV_DONTNEED,
+            )
+        };
+        if res == 0 {
+            return;
+        }
+    }
+    for byte in memory.iter_mut() {
+        *byte = 0;
+    }
 }
 
 impl Drop for WasmInstance {
@@ -104,7 +150,7 @@ impl WasmInstance {
     }
 
     pub(crate) fn handle_ethereum_log(
-        mut self,
+        &mut self,
         handler_name: &str,
         transaction: Arc<Transaction>,
         log: Arc<Log>,
@@ -143,11 +189,11 @@ impl WasmInstance {
         self.invoke_handler(handler_name, event)?;
 
         // Return the output state
-        Ok(self.take_ctx().ctx.state)
+        Ok(self.take_state())
     }
 
     pub(crate) fn handle_ethereum_call(
-        mut self,
+        &mut self,
         handler_name: &str,
         transaction: Arc<Transaction>,
         call: Arc<EthereumCall>,
@@ -170,11 +216,11 @@ impl WasmInstance {
 
         self.invoke_handler(handler_name, arg)?;
 
-        Ok(self.take_ctx().ctx.state)
+        Ok(self.take_state())
     }
 
     pub(crate) fn handle_ethereum_block(
-        mut self,
+        &mut self,
         handler_name: &str,
     ) -> Result<BlockState, MappingError> {
         let block = EthereumBlockData::from(self.instance_ctx().ctx.block.as_ref());
@@ -184,13 +230,118 @@ impl WasmInstance {
 
         self.invoke_handler(handler_name, arg)?;
 
-        Ok(self.take_ctx().ctx.state)
+        Ok(self.take_state())
+    }
+
+    pub(crate) fn handle_entity(
+        &mut self,
+        handler_name: &str,
+        entity: Entity,
+    ) -> Result<BlockState, MappingError> {
+        let arg = self.asc_new(&entity);
+
+        self.invoke_handler(handler_name, arg)?;
+
+        Ok(self.take_state())
     }
 
     pub(crate) fn take_ctx(&mut self) -> WasmInstanceContext {
         self.instance_ctx.borrow_mut().take().unwrap()
     }
 
+    /// Take the block state that the last handler produced, leaving an empty
+    /// state in its place
+    fn take_state(&mut self) -> BlockState {
+        let mut ctx = RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap());
+        let empty = BlockState::new(ctx.ctx.state.entity_cache.store.clone(), Default::default());
+        std::mem::replace(&mut ctx.ctx.state, empty)
+    }
+
+    /// Remember the current state of the instance so that it can be reset to
+    /// it later. This only works for modules whose mutable globals are all
+    /// exported; for other modules, the instance can not be reused
+    pub(crate) fn snapshot(&mut self) {
+        if !self.instance_ctx().valid_module.reusable {
+            return;
+        }
+
+        let memory = self.instance_ctx().memory.clone();
+        let memory_size = memory.data_size();
+        // Safety: no reference into memory outlives this block.
+        // See also 2155cdca-dfaa-4fba-86e4-289e7683c1bf
+        let memory = unsafe {
+            let data = memory.data_unchecked();
+            let used = data
+                .iter()
+                .rposition(|byte| *byte != 0)
+                .map(|last| (last / WASM_PAGE_SIZE + 1) * WASM_PAGE_SIZE)
+                .unwrap_or(0);
+            data[..used].to_vec()
+        };
+        let globals = self
+            .instance
+            .exports()
+            .filter_map(|export| export.into_global())
+            .filter(|global| global.ty().mutability() == wasmtime::Mutability::Var)
+            .map(|global| {
+                let value = global.get();
+                (global, value)
+            })
+            .collect();
+        self.snapshot = Some(Snapshot {
+            memory,
+            memory_size,
+            globals,
+        });
+    }
+
+    /// Whether the instance can be reset with `reset`. Memory can not
+    /// shrink, so an instance whose memory grew can not be reused
+    pub(crate) fn can_reset(&self) -> bool {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.memory_size == self.instance_ctx().memory.data_size(),
+            None => false,
+        }
+    }
+
+    /// Put the instance back into the state it had when `snapshot` was
+    /// called, and prepare it for handling a trigger with `ctx`. Must only
+    /// be called if `can_reset` returns `true`
+    pub(crate) fn reset(&mut self, ctx: MappingContext) -> Result<(), anyhow::Error> {
+        let snapshot = self.snapshot.as_ref().unwrap();
+        for (global, value) in &snapshot.globals {
+            global.set(value.clone())?;
+        }
+
+        let mut instance_ctx = RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap());
+        // Safety: no reference into memory outlives this block.
+        // See also 2155cdca-dfaa-4fba-86e4-289e7683c1bf
+        unsafe {
+            let data = instance_ctx.memory.data_unchecked_mut();
+            let (used, unused) = data.split_at_mut(snapshot.memory.len());
+            used.copy_from_slice(&snapshot.memory);
+            zero_memory(unused);
+        }
+        instance_ctx.ctx = ctx;
+        instance_ctx.arena_start_ptr = 0;
+        instance_ctx.arena_free_size = 0;
+        instance_ctx.possible_reorg = false;
+        instance_ctx.deterministic_host_trap = false;
+        instance_ctx.profile_host_calls = false;
+        instance_ctx.host_call_times.clear();
+        instance_ctx.timeout_stopwatch.lock().unwrap().start();
+        Ok(())
+    }
+
+    /// Stop the handler timeout and let go of everything that belongs to
+    /// the block that was just processed, so that the instance can wait for
+    /// the next trigger
+    pub(crate) fn pause(&mut self) {
+        let mut ctx = RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap());
+        *ctx.timeout_stopwatch.lock().unwrap() = TimeoutStopwatch::new();
+        ctx.ctx.proof_of_indexing = None;
+    }
+
     pub(crate) fn instance_ctx(&self) -> std::cell::Ref<'_, WasmInstanceContext> {
         std::cell::Ref::map(self.instance_ctx.borrow(), |i| i.as_ref().unwrap())
     }
@@ -205,13 +356,39 @@ impl WasmInstance {
         self.instance.get_func(func_name).unwrap()
     }
 
+    /// Start profiling a handler if profiling is turned on and the
+    /// handler is sampled, and return when it started
+    fn start_handler_profile(&self) -> Option<Instant> {
+        if !self.profile.as_ref()?.sample() {
+            return None;
+        }
+        let mut ctx = RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap());
+        ctx.profile_host_calls = true;
+        Some(Instant::now())
+    }
+
+    fn record_handler_profile(&self, handler: &str, start: Instant) {
+        let total = start.elapsed();
+        let mut ctx = RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap());
+        ctx.profile_host_calls = false;
+        if let Some(profile) = &self.profile {
+            profile.record(handler, total, ctx.host_call_times.drain());
+        }
+    }
+
     fn invoke_handler<C>(&mut self, handler: &str, arg: AscPtr<C>) -> Result<(), MappingError> {
         let func = self
             .instance
             .get_func(handler)
             .with_context(|| format!("function {} not found", handler))?;
 
-        func.get1()?(arg.wasm_ptr()).map_err(|trap: Trap| {
+        let start = self.start_handler_profile();
+        let result = func.get1()?(arg.wasm_ptr());
+        if let Some(start) = start {
+            self.record_handler_profile(handler, start);
+        }
+
+        result.map_err(|trap: Trap| {
             if self.instance_ctx().possible_reorg {
                 MappingError::PossibleReorg(trap.into())
             } else if trap.to_string().contains(TRAP_TIMEOUT) {
@@ -279,6 +456,13 @@ pub(crate) struct WasmInstanceContext {
     deterministic_host_trap: bool,
 
     pub(crate) allow_non_determinstic_ipfs: bool,
+
+    // Whether the current handler is profiled, i.e., whether the time spent
+    // in host functions should be recorded in `host_call_times`.
+    profile_host_calls: bool,
+
+    // Time spent in each host function during the current handler.
+    host_call_times: HashMap<&'static str, Duration>,
 }
 
 impl WasmInstance {
@@ -303,14 +487,20 @@ impl WasmInstance {
         // Start the timeout watchdog task.
         let timeout_stopwatch = Arc::new(std::sync::Mutex::new(TimeoutStopwatch::start_new()));
         if let Some(timeout) = timeout {
-            // This task is likely to outlive the instance, which is fine.
+            // This task is likely to outlive the instance, which is fine. It ends once the
+            // instance is dropped, since a reused instance might otherwise keep it alive forever.
             let interrupt_handle = linker.store().interrupt_handle().unwrap();
-            let timeout_stopwatch = timeout_stopwatch.clone();
+            let timeout_stopwatch = Arc::downgrade(&timeout_stopwatch);
             graph::spawn_allow_panic(async move {
                 let minimum_wait = Duration::from_secs(1);
                 loop {
+                    let timeout_stopwatch = match timeout_stopwatch.upgrade() {
+                        Some(timeout_stopwatch) => timeout_stopwatch,
+                        None => break,
+                    };
                     let time_left =
                         timeout.checked_sub(timeout_stopwatch.lock().unwrap().elapsed());
+                    drop(timeout_stopwatch);
                     match time_left {
                         None => break interrupt_handle.interrupt(), // Timed out.
 
@@ -362,9 +552,12 @@ impl WasmInstance {
 
                             let instance = instance.as_mut().unwrap();
                             let _section = instance.host_metrics.stopwatch.start_section($section);
-                            instance.$rust_name(
+                            let start = instance.host_call_start();
+                            let ret = instance.$rust_name(
                                 $($param.into()),*
-                            ).into_wasm_ret()
+                            ).into_wasm_ret();
+                            instance.record_host_call($wasm_name, start);
+                            ret
                         }
                     )?;
                 }
@@ -408,6 +601,7 @@ impl WasmInstance {
                     }
 
                     let instance = instance.as_mut().unwrap();
+                    let profile_start = instance.host_call_start();
                     let stopwatch = &instance.host_metrics.stopwatch;
                     let _section = stopwatch.start_section("host_export_ethereum_call");
 
@@ -425,6 +619,7 @@ impl WasmInstance {
                         start.elapsed().as_secs_f64(),
                         "ethereum_call",
                     );
+                    instance.record_host_call("ethereum.call", profile_start);
                     Ok(ret)
                 },
             )?;
@@ -499,6 +694,8 @@ impl WasmInstance {
         link!("dataSource.network", data_source_network,);
         link!("dataSource.context", data_source_context,);
 
+        link!("ethereum.blockTransactions", ethereum_block_transactions,);
+
         link!("ens.nameByHash", ens_name_by_hash, ptr);
 
         link!("log.log", log_log, level, msg_ptr);
@@ -510,6 +707,7 @@ impl WasmInstance {
         let instance = linker.instantiate(&valid_module.module)?;
 
         // Usually `shared_ctx` is still `None` because no host fns were called during start.
+        let profile = host_metrics.profile.clone();
         if shared_ctx.borrow().is_none() {
             *shared_ctx.borrow_mut() = Some(WasmInstanceContext::from_instance(
                 &instance,
@@ -525,6 +723,8 @@ impl WasmInstance {
         Ok(WasmInstance {
             instance,
             instance_ctx: shared_ctx,
+            snapshot: None,
+            profile,
         })
     }
 }
@@ -610,6 +810,8 @@ impl WasmInstanceContext {
             possible_reorg: false,
             deterministic_host_trap: false,
             allow_non_determinstic_ipfs,
+            profile_host_calls: false,
+            host_call_times: HashMap::new(),
         })
     }
 
@@ -648,10 +850,30 @@ impl WasmInstanceContext {
             possible_reorg: false,
             deterministic_host_trap: false,
             allow_non_determinstic_ipfs,
+            profile_host_calls: false,
+            host_call_times: HashMap::new(),
         })
     }
 }
 
+impl WasmInstanceContext {
+    /// When a host function call started, if the current handler is
+    /// profiled
+    fn host_call_start(&self) -> Option<Instant> {
+        if self.profile_host_calls {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    fn record_host_call(&mut self, name: &'static str, start: Option<Instant>) {
+        if let Some(start) = start {
+            *self.host_call_times.entry(name).or_default() += start.elapsed();
+        }
+    }
+}
+
 // Implementation of externals.
 impl WasmInstanceContext {
     /// function abort(message?: string | null, fileName?: string | null, lineNumber?: u32, columnNumber?: u32): void
@@ -704,6 +926,7 @@ impl WasmInstanceContext {
         let entity = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
         let data = self.try_asc_get(data_ptr)?;
+        try_host_export!(self, self.ctx.host_exports.check_entity_type(&entity));
         self.ctx.host_exports.store_set(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -716,9 +939,14 @@ impl WasmInstanceContext {
     }
 
     /// function store.remove(entity: string, id: string): void
-    fn store_remove(&mut self, entity_ptr: AscPtr<AscString>, id_ptr: AscPtr<AscString>) {
-        let entity = self.asc_get(entity_ptr);
+    fn store_remove(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<(), Trap> {
+        let entity: String = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
+        try_host_export!(self, self.ctx.host_exports.check_entity_type(&entity));
         self.ctx.host_exports.store_remove(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -726,6 +954,7 @@ impl WasmInstanceContext {
             entity,
             id,
         );
+        Ok(())
     }
 
     /// function store.get(entity: string, id: string): Entity | null
@@ -735,8 +964,9 @@ impl WasmInstanceContext {
         id_ptr: AscPtr<AscString>,
     ) -> Result<AscPtr<AscEntity>, Trap> {
         let start = Instant::now();
-        let entity_ptr = self.asc_get(entity_ptr);
+        let entity_ptr: String = self.asc_get(entity_ptr);
         let id_ptr = self.asc_get(id_ptr);
+        try_host_export!(self, self.ctx.host_exports.check_entity_type(&entity_ptr));
         let entity_option =
             self.ctx
                 .host_exports
@@ -1249,6 +1479,23 @@ impl WasmInstanceContext {
         self.asc_new(&self.ctx.host_exports.data_source_context())
     }
 
+    /// function ethereum.blockTransactions(): Array<ethereum.BlockTransaction>
+    ///
+    /// The transactions are only converted, and fetched if the block does
+    /// not have them, when a handler asks for them, so that handlers that do
+    /// not need them do not pay for them
+    fn ethereum_block_transactions(
+        &mut self,
+    ) -> Result<AscPtr<AscEthereumBlockTransactionArray>, Trap> {
+        let transactions = try_host_export!(
+            self,
+            self.ctx
+                .host_exports
+                .ethereum_block_transactions(&self.ctx.logger, &self.ctx.block)
+        );
+        Ok(self.asc_new(transactions.as_slice()))
+    }
+
     fn ens_name_by_hash(&mut self, hash_ptr: AscPtr<AscString>) -> Result<AscPtr<AscString>, Trap> {
         let hash: String = self.asc_get(hash_ptr);
         let name = self.ctx.host_exports.ens_name_by_hash(&*hash)?;
@@ -1261,7 +1508,9 @@ impl WasmInstanceContext {
     fn log_log(&mut self, level: u32, msg: AscPtr<AscString>) {
         let level = LogLevel::from(level).into();
         let msg: String = self.asc_get(msg);
-        self.ctx.host_exports.log_log(&self.ctx.logger, level, msg);
+        self.ctx
+            .host_exports
+            .log_log(&self.ctx.logger, &self.ctx.block, level, msg);
     }
 
     /// function arweave.transactionData(txId: string): Bytes | null
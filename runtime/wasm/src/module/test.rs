@@ -15,7 +15,7 @@ use graph_core::three_box::ThreeBoxAdapter;
 use graph_mock::MockMetricsRegistry;
 use test_store::STORE;
 
-use web3::types::{Address, H160};
+use web3::types::{Address, H160, H256};
 
 use super::*;
 
@@ -38,9 +38,21 @@ fn test_valid_module_and_store_with_timeout(
 ) -> (
     WasmInstance,
     Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+) {
+    let valid_module = ValidModule::new(data_source.mapping.runtime.as_ref()).unwrap();
+    test_instance_and_store(subgraph_id, data_source, Arc::new(valid_module), timeout)
+}
+
+fn test_instance_and_store(
+    subgraph_id: &str,
+    data_source: DataSource,
+    valid_module: Arc<ValidModule>,
+    timeout: Option<Duration>,
+) -> (
+    WasmInstance,
+    Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
 ) {
     let store = STORE.clone();
-    let metrics_registry = Arc::new(MockMetricsRegistry::new());
     let deployment_id = SubgraphDeploymentId::new(subgraph_id).unwrap();
     test_store::create_test_subgraph(
         &deployment_id,
@@ -55,27 +67,35 @@ fn test_valid_module_and_store_with_timeout(
             extra: String
         }",
     );
+    let ctx = mock_context(deployment_id.clone(), data_source, store.clone());
+    let module = test_instance_with_ctx(deployment_id, valid_module, ctx, timeout);
+
+    (module, store)
+}
+
+fn test_instance_with_ctx(
+    deployment_id: SubgraphDeploymentId,
+    valid_module: Arc<ValidModule>,
+    ctx: MappingContext,
+    timeout: Option<Duration>,
+) -> WasmInstance {
+    let host_metrics = test_host_metrics(&deployment_id);
+    WasmInstance::from_valid_module_with_ctx(valid_module, ctx, host_metrics, timeout, true)
+        .unwrap()
+}
+
+fn test_host_metrics(deployment_id: &SubgraphDeploymentId) -> Arc<HostMetrics> {
+    let metrics_registry = Arc::new(MockMetricsRegistry::new());
     let stopwatch_metrics = StopwatchMetrics::new(
         Logger::root(slog::Discard, o!()),
         deployment_id.clone(),
         metrics_registry.clone(),
     );
-    let host_metrics = Arc::new(HostMetrics::new(
+    Arc::new(HostMetrics::new(
         metrics_registry,
         deployment_id.as_str(),
         stopwatch_metrics,
-    ));
-
-    let module = WasmInstance::from_valid_module_with_ctx(
-        Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref()).unwrap()),
-        mock_context(deployment_id, data_source, store.clone()),
-        host_metrics,
-        timeout,
-        true,
-    )
-    .unwrap();
-
-    (module, store)
+    ))
 }
 
 fn test_module(subgraph_id: &str, data_source: DataSource) -> WasmInstance {
@@ -93,6 +113,7 @@ fn mock_data_source(path: &str) -> DataSource {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
             abi: String::from("123123"),
             start_block: 0,
+            deployment: None,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
@@ -103,6 +124,7 @@ fn mock_data_source(path: &str) -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -124,6 +146,7 @@ fn mock_data_source(path: &str) -> DataSource {
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                entity_handlers: vec![],
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -138,8 +161,8 @@ fn mock_host_exports(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    ethereum_adapter: Arc<dyn EthereumAdapter>,
 ) -> HostExports {
-    let mock_ethereum_adapter = Arc::new(MockEthereumAdapter::default());
     let arweave_adapter = Arc::new(ArweaveAdapter::new("https://arweave.net".to_string()));
     let three_box_adapter = Arc::new(ThreeBoxAdapter::new("https://ipfs.3box.io/".to_string()));
 
@@ -150,9 +173,10 @@ fn mock_host_exports(
         data_source.source.address,
         data_source.network.unwrap(),
         data_source.context,
+        None,
         Arc::new(data_source.templates),
         data_source.mapping.abis,
-        mock_ethereum_adapter,
+        ethereum_adapter,
         Arc::new(graph_core::LinkResolver::from(
             ipfs_api::IpfsClient::default(),
         )),
@@ -160,6 +184,7 @@ fn mock_host_exports(
         store,
         arweave_adapter,
         three_box_adapter,
+        test_store::ENS_LOOKUP.clone(),
     )
 }
 
@@ -171,7 +196,12 @@ fn mock_context(
     MappingContext {
         logger: test_store::LOGGER.clone(),
         block: Default::default(),
-        host_exports: Arc::new(mock_host_exports(subgraph_id, data_source, store.clone())),
+        host_exports: Arc::new(mock_host_exports(
+            subgraph_id,
+            data_source,
+            store.clone(),
+            Arc::new(MockEthereumAdapter::default()),
+        )),
         state: BlockState::new(store, Default::default()),
         proof_of_indexing: None,
     }
@@ -738,3 +768,315 @@ async fn detect_contract_calls() {
         true
     );
 }
+
+#[test]
+fn export_mutable_globals() {
+    // (module (global (mut i32) (i32.const 7)))
+    let raw = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x07,
+        0x0b,
+    ];
+    let rewritten = globals::export_mutable_globals(&raw).unwrap().unwrap();
+
+    let store = wasmtime::Store::default();
+    let module = wasmtime::Module::new(store.engine(), &rewritten).unwrap();
+    let instance = wasmtime::Instance::new(&store, &module, &[]).unwrap();
+    let global = instance.get_global(&globals::export_name(0)).unwrap();
+    assert_eq!(7, global.get().unwrap_i32());
+
+    // (module
+    //   (global (mut i32) (i32.const 7))
+    //   (global i32 (i32.const 1))
+    //   (global (mut i32) (i32.const 9))
+    //   (func (export "get") (result i32) global.get 0))
+    let raw = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00, 0x06, 0x10, 0x03, 0x7f, 0x01, 0x41, 0x07, 0x0b, 0x7f, 0x00, 0x41,
+        0x01, 0x0b, 0x7f, 0x01, 0x41, 0x09, 0x0b, 0x07, 0x07, 0x01, 0x03, 0x67, 0x65, 0x74, 0x00,
+        0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x23, 0x00, 0x0b,
+    ];
+    let rewritten = globals::export_mutable_globals(&raw).unwrap().unwrap();
+
+    // The existing exports are kept, and only the mutable globals are
+    // added to them
+    let module = wasmtime::Module::new(store.engine(), &rewritten).unwrap();
+    let instance = wasmtime::Instance::new(&store, &module, &[]).unwrap();
+    let get = instance.get_func("get").unwrap().get0::<i32>().unwrap();
+    assert_eq!(7, get().unwrap());
+    let global = instance.get_global(&globals::export_name(0)).unwrap();
+    assert_eq!(7, global.get().unwrap_i32());
+    assert!(instance.get_global(&globals::export_name(1)).is_none());
+    let global = instance.get_global(&globals::export_name(2)).unwrap();
+    assert_eq!(9, global.get().unwrap_i32());
+    assert_eq!(3, module.exports().count());
+
+    // Rewriting a module twice would clash with the names we export
+    assert!(globals::export_mutable_globals(&rewritten)
+        .unwrap()
+        .is_none());
+}
+
+/// The memory and the values of the exported globals of `module`
+fn instance_state(module: &WasmInstance) -> (Vec<u8>, Vec<Option<i32>>) {
+    let memory = module.instance_ctx().memory.clone();
+    // Safety: no reference into memory outlives this function
+    let memory = unsafe { memory.data_unchecked().to_vec() };
+    let globals = module
+        .instance
+        .exports()
+        .filter_map(|export| export.into_global())
+        .map(|global| global.get().i32())
+        .collect();
+    (memory, globals)
+}
+
+#[tokio::test]
+async fn reset_reused_instance() {
+    let data_source = mock_data_source("wasm_test/store.wasm");
+    let valid_module = ValidModule::new_reusable(data_source.mapping.runtime.as_ref()).unwrap();
+    assert!(valid_module.reusable);
+    let (mut module, store) = test_instance_and_store(
+        "resetReusedInstance",
+        data_source.clone(),
+        Arc::new(valid_module),
+        None,
+    );
+
+    let mut steve = Entity::new();
+    steve.set("id", "steve");
+    steve.set("name", "Steve");
+    let subgraph_id = SubgraphDeploymentId::new("resetReusedInstance").unwrap();
+    test_store::insert_entities(subgraph_id.clone(), vec![("User", steve)]).unwrap();
+
+    let get_user = |module: &mut WasmInstance| -> AscPtr<AscEntity> {
+        let id = module.asc_new("steve");
+        module.invoke_export("getUser", id)
+    };
+
+    module.snapshot();
+    let snapshot = instance_state(&module);
+
+    // Running the handler allocates memory and changes globals
+    let first = get_user(&mut module);
+    let after_first = instance_state(&module);
+    assert!(snapshot != after_first);
+
+    // Resetting puts everything back the way it was when the snapshot was
+    // taken, so that running the handler again does exactly the same
+    module.pause();
+    assert!(module.can_reset());
+    module
+        .reset(mock_context(subgraph_id, data_source, store))
+        .unwrap();
+    assert!(snapshot == instance_state(&module));
+
+    let second = get_user(&mut module);
+    assert_eq!(first.wasm_ptr(), second.wasm_ptr());
+    assert!(after_first == instance_state(&module));
+}
+
+impl FromAscObj<AscEthereumBlockTransaction> for EthereumBlockTransactionData {
+    fn from_asc_obj<H: AscHeap>(tx: AscEthereumBlockTransaction, heap: &H) -> Self {
+        EthereumBlockTransactionData {
+            hash: heap.asc_get(tx.hash),
+            index: heap.asc_get::<BigInt, _>(tx.index).to_u64().into(),
+            from: heap.asc_get(tx.from),
+            to: if tx.to.is_null() {
+                None
+            } else {
+                Some(heap.asc_get(tx.to))
+            },
+            value: heap.asc_get::<BigInt, _>(tx.value).to_unsigned_u256(),
+            selector: web3::types::Bytes(heap.asc_get(tx.selector)),
+        }
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn ethereum_block_transactions() {
+    let data_source = mock_data_source("wasm_test/block_transactions.wasm");
+    let valid_module = Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref()).unwrap());
+    let subgraph_id = SubgraphDeploymentId::new("blockTransactions").unwrap();
+    let store = STORE.clone();
+
+    let mut tx = Transaction::default();
+    tx.hash = H256::repeat_byte(1);
+    tx.transaction_index = Some(0u64.into());
+    tx.to = Some(H160::repeat_byte(2));
+    tx.value = 5u64.into();
+    tx.input = web3::types::Bytes(vec![0xa9, 0x05, 0x9c, 0xbb, 0x00, 0x01]);
+    let mut full_block = LightEthereumBlock::default();
+    full_block.hash = Some(H256::repeat_byte(0xb));
+    full_block.transactions = vec![tx];
+    let mut light_block = full_block.clone();
+    light_block.transactions = vec![];
+
+    // The block is fetched from the Ethereum node only once, no matter how
+    // many handlers ask for its transactions
+    let mut ethereum_adapter = MockEthereumAdapter::default();
+    let fetched_block = full_block.clone();
+    ethereum_adapter
+        .expect_block_by_hash()
+        .times(1)
+        .returning(move |_, _| Box::new(future::ok(Some(fetched_block.clone()))));
+    let fetching_host_exports = Arc::new(mock_host_exports(
+        subgraph_id.clone(),
+        data_source.clone(),
+        store.clone(),
+        Arc::new(ethereum_adapter),
+    ));
+
+    // Host functions use `block_on` which must be called from a sync context,
+    // so we replicate what we do `spawn_module`.
+    let runtime = tokio::runtime::Handle::current();
+    std::thread::spawn(move || {
+        runtime.enter(|| {
+            let block_transactions =
+                |data_source: DataSource,
+                 host_exports: Option<Arc<HostExports>>,
+                 block: &LightEthereumBlock| {
+                    let mut ctx = mock_context(subgraph_id.clone(), data_source, store.clone());
+                    ctx.block = Arc::new(block.clone());
+                    if let Some(host_exports) = host_exports {
+                        ctx.host_exports = host_exports;
+                    }
+                    let module = test_instance_with_ctx(
+                        subgraph_id.clone(),
+                        valid_module.clone(),
+                        ctx,
+                        None,
+                    );
+                    let func = module.get_func("blockTransactions").get0::<u32>().unwrap();
+                    func().map(|ptr| {
+                        let ptr: AscPtr<AscEthereumBlockTransactionArray> = ptr.into();
+                        module.asc_get::<Vec<EthereumBlockTransactionData>, _>(ptr)
+                    })
+                };
+
+            let transactions = block_transactions(data_source.clone(), None, &full_block).unwrap();
+            assert_eq!(1, transactions.len());
+            assert_eq!(H256::repeat_byte(1), transactions[0].hash);
+            assert_eq!(Some(H160::repeat_byte(2)), transactions[0].to);
+            assert_eq!(U256::from(5), transactions[0].value);
+            assert_eq!(vec![0xa9, 0x05, 0x9c, 0xbb], transactions[0].selector.0);
+
+            for _ in 0..2 {
+                let transactions = block_transactions(
+                    data_source.clone(),
+                    Some(fetching_host_exports.clone()),
+                    &light_block,
+                )
+                .unwrap();
+                assert_eq!(1, transactions.len());
+                assert_eq!(H256::repeat_byte(1), transactions[0].hash);
+            }
+
+            // Mappings for older API versions can not use the host function
+            let mut old_data_source = data_source.clone();
+            old_data_source.mapping.api_version = String::from("0.0.4");
+            let err = block_transactions(old_data_source, None, &full_block).unwrap_err();
+            assert!(err.to_string().contains("requires apiVersion 0.0.5"));
+        })
+    })
+    .join()
+    .unwrap()
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn records_handler_errors() {
+    let deployment_id = SubgraphDeploymentId::new("recordsHandlerErrors").unwrap();
+    test_store::create_test_subgraph(&deployment_id, "type Thing @entity { id: ID! }");
+    let store = STORE.clone();
+
+    let mut data_source = mock_data_source("wasm_test/handler_error.wasm");
+    data_source.mapping.api_version = "0.0.4".to_owned();
+    data_source.mapping.abis = vec![MappingABI {
+        name: data_source.source.abi.clone(),
+        contract: ethabi::Contract::load(&b"[]"[..]).unwrap(),
+        link: Link {
+            link: "link".to_owned(),
+        },
+    }];
+    data_source.mapping.block_handlers = vec![MappingBlockHandler {
+        handler: "handleBlock".to_owned(),
+        filter: None,
+    }];
+
+    let mut ethereum_networks = EthereumNetworks::new();
+    ethereum_networks.insert(
+        "mainnet".to_owned(),
+        NodeCapabilities {
+            archive: false,
+            traces: false,
+        },
+        Arc::new(MockEthereumAdapter::default()),
+    );
+    let mut stores = HashMap::new();
+    stores.insert("mainnet".to_owned(), store.clone());
+    let builder = crate::RuntimeHostBuilder::new(
+        ethereum_networks,
+        Arc::new(graph_core::LinkResolver::from(
+            ipfs_api::IpfsClient::default(),
+        )),
+        stores,
+        Arc::new(ArweaveAdapter::new("https://arweave.net".to_string())),
+        Arc::new(ThreeBoxAdapter::new("https://ipfs.3box.io/".to_string())),
+        test_store::ENS_LOOKUP.clone(),
+    );
+
+    let metrics = test_host_metrics(&deployment_id);
+    let mapping_request_sender = crate::mapping::spawn_module(
+        data_source.mapping.runtime.as_ref().clone(),
+        test_store::LOGGER.clone(),
+        deployment_id.clone(),
+        metrics.clone(),
+        tokio::runtime::Handle::current(),
+        None,
+        false,
+        false,
+    )
+    .unwrap();
+    let host = builder
+        .build(
+            "mainnet".to_owned(),
+            deployment_id.clone(),
+            data_source,
+            None,
+            Arc::new(vec![]),
+            mapping_request_sender,
+            metrics,
+        )
+        .unwrap();
+
+    let mut block = LightEthereumBlock::default();
+    block.number = Some(7u64.into());
+    block.hash = Some(H256::repeat_byte(7));
+    let result = host
+        .process_block(
+            &test_store::LOGGER,
+            &Arc::new(block),
+            &EthereumBlockTriggerType::Every,
+            BlockState::new(store, Default::default()),
+            None,
+        )
+        .await;
+    assert!(result.is_err());
+
+    // The error is kept together with the context in which it happened
+    let errors = HandlerError::recent(deployment_id.as_str());
+    assert_eq!(1, errors.len());
+    let error = &errors[0];
+    assert_eq!(
+        Some(EthereumBlockPointer::from((H256::repeat_byte(7), 7))),
+        error.block
+    );
+    assert_eq!("example data source", error.data_source);
+    assert_eq!("handleBlock", error.handler);
+    assert_eq!("block", error.trigger);
+    assert!(error.deterministic);
+    assert!(error.message.contains("handleBlock"));
+    assert!(!error.wasm_backtrace.as_ref().unwrap().is_empty());
+
+    HandlerError::clear(deployment_id.as_str());
+    assert!(HandlerError::recent(deployment_id.as_str()).is_empty());
+}
@@ -2,7 +2,8 @@ use ethabi;
 use std::collections::HashMap;
 
 use graph::components::ethereum::{
-    EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
+    EthereumBlockData, EthereumBlockTransactionData, EthereumCallData, EthereumEventData,
+    EthereumTransactionData,
 };
 use graph::data::store;
 use graph::prelude::anyhow::{ensure, Error};
@@ -359,6 +360,22 @@ impl ToAscObj<AscEthereumTransaction_0_0_2> for EthereumTransactionData {
     }
 }
 
+impl ToAscObj<AscEthereumBlockTransaction> for EthereumBlockTransactionData {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumBlockTransaction {
+        AscEthereumBlockTransaction {
+            hash: heap.asc_new(&self.hash),
+            index: heap.asc_new(&BigInt::from(self.index)),
+            from: heap.asc_new(&self.from),
+            to: self
+                .to
+                .map(|to| heap.asc_new(&to))
+                .unwrap_or_else(|| AscPtr::null()),
+            value: heap.asc_new(&BigInt::from_unsigned_u256(&self.value)),
+            selector: heap.asc_new(&*self.selector.0),
+        }
+    }
+}
+
 impl<T: AscType> ToAscObj<AscEthereumEvent<T>> for EthereumEventData
 where
     EthereumTransactionData: ToAscObj<T>,
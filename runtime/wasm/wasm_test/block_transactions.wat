@@ -0,0 +1,14 @@
+;; A minimal module that calls `ethereum.blockTransactions`. It is written
+;; by hand instead of with AssemblyScript so that it does not need a newer
+;; version of graph-ts
+(module
+  (import "env" "ethereum.blockTransactions" (func $blockTransactions (result i32)))
+  (memory (export "memory") 4)
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "memory.allocate") (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $size)))
+    (local.get $ptr))
+  (func (export "blockTransactions") (result i32)
+    (call $blockTransactions)))
@@ -0,0 +1,12 @@
+;; A minimal module with a block handler that traps. It is written by hand
+;; instead of with AssemblyScript so that the handler does nothing but trap
+(module
+  (memory (export "memory") 4)
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "memory.allocate") (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $size)))
+    (local.get $ptr))
+  (func (export "handleBlock") (param $block i32)
+    unreachable))
@@ -1,6 +1,7 @@
 use graph::prelude::serde_json;
 use graphql_parser;
 use hyper::body::Bytes;
+use std::time::Instant;
 
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
@@ -53,6 +54,7 @@ impl Future for GraphQLRequest {
         })?;
 
         // Parse the "query" field of the JSON body
+        let parse_start = Instant::now();
         let document = graphql_parser::parse_query(query_string).map_err(|e| {
             GraphQLServerError::from(QueryError::ParseError(Arc::new(e.compat().into())))
         })?;
@@ -70,12 +72,9 @@ impl Future for GraphQLRequest {
             )),
         }?;
 
-        Ok(Async::Ready(Query::new(
-            schema,
-            document,
-            variables,
-            self.network.clone(),
-        )))
+        let mut query = Query::new(schema, document, variables, self.network.clone());
+        query.parse_time = parse_start.elapsed();
+        Ok(Async::Ready(query))
     }
 }
 
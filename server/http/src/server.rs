@@ -5,7 +5,9 @@ use hyper::service::make_service_fn;
 use hyper::Server;
 
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use graph::components::server::api_keys::USAGE_FLUSH_INTERVAL;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
+use graph::util::shutdown;
 
 /// Errors that may occur when starting the server.
 #[derive(Debug, Fail)]
@@ -20,12 +22,24 @@ impl From<hyper::Error> for GraphQLServeError {
     }
 }
 
+/// Add the API key usage that `api_keys` accumulated to the usage in the
+/// database
+async fn flush_api_key_usage<S: Store>(logger: &Logger, api_keys: Arc<ApiKeys>, store: Arc<S>) {
+    let res = tokio::task::spawn_blocking(move || api_keys.flush(store.as_ref()))
+        .await
+        .unwrap(); // Propagate panics.
+    if let Err(e) = res {
+        warn!(logger, "Failed to record API key usage"; "error" => e.to_string());
+    }
+}
+
 /// A GraphQL server based on Hyper.
 pub struct GraphQLServer<Q, S> {
     logger: Logger,
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    api_keys: Arc<ApiKeys>,
     node_id: NodeId,
 }
 
@@ -52,9 +66,17 @@ impl<Q, S> GraphQLServer<Q, S> {
             metrics,
             graphql_runner,
             store,
+            api_keys: Arc::new(ApiKeys::default()),
             node_id,
         }
     }
+
+    /// The API keys this server checks queries against. Other servers that
+    /// answer queries should use the same keys so that rate limits and
+    /// usage cover all queries
+    pub fn api_keys(&self) -> Arc<ApiKeys> {
+        self.api_keys.clone()
+    }
 }
 
 impl<Q, S> GraphQLServerTrait for GraphQLServer<Q, S>
@@ -78,12 +100,29 @@ where
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
+        if *REQUIRE_API_KEYS {
+            info!(logger, "Requiring API keys for queries");
+
+            let api_keys = self.api_keys.clone();
+            let store = self.store.clone();
+            let logger = logger.clone();
+            graph::spawn(
+                tokio::time::interval(USAGE_FLUSH_INTERVAL).for_each(move |_| {
+                    let api_keys = api_keys.clone();
+                    let store = store.clone();
+                    let logger = logger.clone();
+                    async move { flush_api_key_usage(&logger, api_keys, store).await }
+                }),
+            );
+        }
+
         // On every incoming request, launch a new GraphQL service that writes
         // incoming queries to the query sink.
         let logger_for_service = self.logger.clone();
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
         let store = self.store.clone();
+        let api_keys = self.api_keys.clone();
         let node_id = self.node_id.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(GraphQLService::new(
@@ -91,16 +130,32 @@ where
                 metrics.clone(),
                 graphql_runner.clone(),
                 store.clone(),
+                api_keys.clone(),
                 ws_port,
                 node_id.clone(),
             ))
         });
 
-        // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        // Create a task to run the server and handle HTTP requests until the
+        // node shuts down. The shutdown waits for the server to stop and to
+        // record the API key usage of the last queries it answered
+        let server = Server::try_bind(&addr.into())?
             .serve(new_service)
-            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+            .with_graceful_shutdown(shutdown::initiated());
+        let store = self.store.clone();
+        let api_keys = self.api_keys.clone();
+        let guard = shutdown::in_flight();
+        let task = async move {
+            if let Err(e) = server.await {
+                error!(logger, "Server error"; "error" => format!("{}", e));
+            }
+            if *REQUIRE_API_KEYS {
+                flush_api_key_usage(&logger, api_keys, store).await;
+            }
+            drop(guard);
+            Ok(())
+        };
 
-        Ok(Box::new(task.compat()))
+        Ok(Box::new(task.boxed().compat()))
     }
 }
@@ -76,6 +76,7 @@ pub struct GraphQLService<Q, S> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    api_keys: Arc<ApiKeys>,
     ws_port: u16,
     node_id: NodeId,
 }
@@ -87,6 +88,7 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
             metrics: self.metrics.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            api_keys: self.api_keys.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
         }
@@ -104,6 +106,7 @@ where
         metrics: Arc<GraphQLServiceMetrics>,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        api_keys: Arc<ApiKeys>,
         ws_port: u16,
         node_id: NodeId,
     ) -> Self {
@@ -112,6 +115,7 @@ where
             metrics,
             graphql_runner,
             store,
+            api_keys,
             ws_port,
             node_id,
         }
@@ -198,7 +202,7 @@ where
                 .unwrap() // Propagate panics.
                 .map_err(|e| GraphQLServerError::from(e))?;
 
-        self.handle_graphql_query(state, request.into_body()).await
+        self.handle_graphql_query(state, request).await
     }
 
     fn handle_graphql_query_by_id(
@@ -215,17 +219,31 @@ where
             });
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(state) => self
-                .handle_graphql_query(state, request.into_body())
-                .boxed(),
+            Ok(state) => self.handle_graphql_query(state, request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         state: DeploymentState,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
+        if *REQUIRE_API_KEYS {
+            let authorization = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned);
+            if let Err(e) = self
+                .api_keys
+                .check(self.store.clone(), authorization.as_deref(), &state.id)
+                .await
+            {
+                return Ok(api_key_error_response(&e));
+            }
+        }
+        let request_body = request.into_body();
+
         let service = self.clone();
         let service_metrics = self.metrics.clone();
         let sd_id = state.id.clone();
@@ -268,7 +286,10 @@ where
             Ok(Response::builder()
                 .status(200)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+                .header(
+                    "Access-Control-Allow-Headers",
+                    "Content-Type, User-Agent, Authorization",
+                )
                 .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap())
@@ -424,6 +445,18 @@ where
     }
 }
 
+fn api_key_error_response(e: &ApiKeyError) -> Response<Body> {
+    let status = e.status();
+    let mut builder = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .header("Access-Control-Allow-Origin", "*");
+    if status == StatusCode::UNAUTHORIZED {
+        builder = builder.header(header::WWW_AUTHENTICATE, "Bearer");
+    }
+    builder.body(Body::from(e.to_string())).unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use http::status::StatusCode;
@@ -438,8 +471,8 @@ mod tests {
 
     use crate::test_utils;
 
-    use super::GraphQLService;
     use super::GraphQLServiceMetrics;
+    use super::{ApiKeys, GraphQLService};
 
     /// A simple stupid query runner for testing.
     pub struct TestGraphQlRunner;
@@ -495,8 +528,16 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let api_keys = Arc::new(ApiKeys::default());
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            api_keys,
+            8001,
+            node_id,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -528,8 +569,16 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let api_keys = Arc::new(ApiKeys::default());
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            api_keys,
+            8001,
+            node_id,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
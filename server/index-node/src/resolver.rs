@@ -3,11 +3,26 @@ use std::collections::HashMap;
 
 use graph::data::graphql::{ObjectOrInterface, TryFromValue, ValueList, ValueMap};
 use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth, SUBGRAPHS_ID};
+use graph::data::version::NodeVersion;
 use graph::prelude::*;
 use graph_graphql::prelude::{object, ExecutionContext, IntoValue, Resolver};
 use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
 use web3::types::{Address, H256};
 
+lazy_static! {
+    /// Block ingestion for a network is considered unhealthy when its chain
+    /// head block is older than this many seconds
+    static ref BLOCK_INGESTOR_MAX_HEAD_AGE: u64 =
+        std::env::var("GRAPH_BLOCK_INGESTOR_MAX_HEAD_AGE")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_BLOCK_INGESTOR_MAX_HEAD_AGE");
+}
+
+/// The most versions of an entity that `entityHistory` returns at once
+const ENTITY_HISTORY_MAX_FIRST: u64 = 1000;
+
 static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
     fragment deploymentStatus on SubgraphDeploymentDetail {
         id
@@ -36,8 +51,12 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
         latestEthereumBlockHash
         latestEthereumBlockNumber
         manifest {
-            dataSources(first: 1) {
+            features
+            dataSources(first: 1000) {
                 network
+                source {
+                    startBlock
+                }
             }
         }
     }
@@ -48,6 +67,7 @@ pub struct IndexNodeResolver<R, S> {
     logger: Logger,
     graphql_runner: Arc<R>,
     store: Arc<S>,
+    version: Arc<NodeVersion>,
 }
 
 /// The ID of a subgraph deployment assignment.
@@ -99,6 +119,28 @@ struct EthereumIndexingStatus {
     earliest_block: Option<EthereumBlock>,
     /// The latest block that the subgraph has synced to.
     latest_block: Option<EthereumBlock>,
+    /// The timestamp of the latest block that the subgraph has synced to.
+    latest_block_timestamp: Option<u64>,
+    /// The number of blocks per minute that the subgraph processed recently.
+    blocks_per_minute: Option<f64>,
+    /// The earliest start block of the subgraph's data sources; no blocks
+    /// before it are scanned for triggers.
+    start_block: u64,
+}
+
+impl EthereumIndexingStatus {
+    /// Estimates how many seconds it will take the subgraph to catch up with
+    /// the chain head at its current sync rate.
+    fn seconds_to_chain_head(&self) -> Option<u64> {
+        let head = self.chain_head_block.as_ref()?.0.number;
+        let latest = self.latest_block.as_ref()?.0.number;
+        let behind = head.saturating_sub(latest);
+        match self.blocks_per_minute {
+            _ if behind == 0 => Some(0),
+            Some(rate) if rate > 0.0 => Some((behind as f64 / rate * 60.0).ceil() as u64),
+            _ => None,
+        }
+    }
 }
 
 /// Indexing status information for different chains (only Ethereum right now).
@@ -114,10 +156,14 @@ impl From<ChainIndexingStatus> for q::Value {
                 // `__typename` is needed for the `ChainIndexingStatus` interface
                 // in GraphQL to work.
                 __typename: "EthereumIndexingStatus",
+                secondsToChainHead: inner.seconds_to_chain_head(),
                 network: inner.network,
                 chainHeadBlock: inner.chain_head_block,
                 earliestBlock: inner.earliest_block,
                 latestBlock: inner.latest_block,
+                latestBlockTimestamp: inner.latest_block_timestamp,
+                blocksPerMinute: inner.blocks_per_minute,
+                startBlock: inner.start_block,
             },
         }
     }
@@ -137,6 +183,9 @@ struct IndexingStatusWithoutNode {
 
     /// Indexing status on different chains involved in the subgraph's data sources.
     chains: Vec<ChainIndexingStatus>,
+
+    /// The features the subgraph uses.
+    features: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -153,8 +202,14 @@ struct IndexingStatus {
     /// Indexing status on different chains involved in the subgraph's data sources.
     chains: Vec<ChainIndexingStatus>,
 
+    /// The features the subgraph uses.
+    features: Vec<String>,
+
     /// ID of the Graph Node that the subgraph is indexed by.
     node: String,
+
+    /// The block that makes the subgraph need a rewind, if any.
+    non_canonical_block: Option<NonCanonicalBlock>,
 }
 
 impl IndexingStatusWithoutNode {
@@ -167,7 +222,9 @@ impl IndexingStatusWithoutNode {
             fatal_error: self.fatal_error,
             non_fatal_errors: self.non_fatal_errors,
             chains: self.chains,
+            features: self.features,
             node,
+            non_canonical_block: None,
         }
     }
 
@@ -197,6 +254,20 @@ impl IndexingStatusWithoutNode {
 
 impl TryFromValue for IndexingStatusWithoutNode {
     fn try_from_value(value: &q::Value) -> Result<Self, Error> {
+        let manifest = value.get_required::<q::Value>("manifest")?;
+        let data_sources = manifest
+            .get_required::<q::Value>("dataSources")?
+            .get_values::<q::Value>()?;
+        let start_blocks = data_sources
+            .iter()
+            .map(|data_source| {
+                Ok(data_source
+                    .get_required::<q::Value>("source")?
+                    .get_optional::<BigInt>("startBlock")?
+                    .map_or(0, |n| n.to_u64()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
         Ok(Self {
             subgraph: value.get_required("id")?,
             synced: value.get_required("synced")?,
@@ -204,15 +275,18 @@ impl TryFromValue for IndexingStatusWithoutNode {
             fatal_error: value.get_optional("fatalError")?,
             non_fatal_errors: value.get_required("nonFatalErrors")?,
             chains: vec![ChainIndexingStatus::Ethereum(EthereumIndexingStatus {
-                network: value
-                    .get_required::<q::Value>("manifest")?
-                    .get_required::<q::Value>("dataSources")?
-                    .get_values::<q::Value>()?[0]
-                    .get_required("network")?,
+                network: data_sources[0].get_required("network")?,
                 chain_head_block: Self::block_from_value(value, "ethereumHeadBlock")?,
                 earliest_block: Self::block_from_value(value, "earliestEthereumBlock")?,
                 latest_block: Self::block_from_value(value, "latestEthereumBlock")?,
+                // These are not part of the deployment metadata; they get
+                // filled in by `IndexNodeResolver::add_store_details`
+                latest_block_timestamp: None,
+                blocks_per_minute: None,
+                start_block: start_blocks.into_iter().min().unwrap_or(0),
             })],
+            // Deployments created before features were recorded have none
+            features: manifest.get_optional("features")?.unwrap_or_default(),
         })
     }
 }
@@ -222,11 +296,13 @@ impl From<IndexingStatus> for q::Value {
         let IndexingStatus {
             subgraph,
             chains,
+            features,
             fatal_error,
             health,
             node,
             non_fatal_errors,
             synced,
+            non_canonical_block,
         } = status;
 
         fn subgraph_error_to_value(subgraph_error: SubgraphError) -> q::Value {
@@ -266,7 +342,14 @@ impl From<IndexingStatus> for q::Value {
             fatalError: fatal_error_val,
             nonFatalErrors: non_fatal_errors,
             chains: chains.into_iter().map(q::Value::from).collect::<Vec<_>>(),
+            features: features,
             node: node,
+            needsRewind: non_canonical_block.is_some(),
+            nonCanonicalBlock: non_canonical_block.map_or(q::Value::Null, |block| object! {
+                __typename: "NonCanonicalBlock",
+                block: EthereumBlock(block.block),
+                canonicalHash: block.canonical_hash.map(|hash| format!("{:x}", hash)),
+            }),
         }
     }
 }
@@ -312,15 +395,71 @@ where
     R: GraphQlRunner,
     S: Store + SubgraphDeploymentStore,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<R>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<R>,
+        store: Arc<S>,
+        version: Arc<NodeVersion>,
+    ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
         Self {
             logger,
             graphql_runner,
             store,
+            version,
+        }
+    }
+
+    fn resolve_version(&self) -> q::Value {
+        let version = self.version.as_ref().clone();
+        object! {
+            __typename: "Version",
+            version: version.version,
+            commit: version.commit,
+            specVersions: version.spec_versions,
+            apiVersions: version.api_versions,
+            chains: version.chains,
+            features: version.features,
         }
     }
 
+    /// Fills in how quickly each deployment is syncing and whether it needs
+    /// a rewind. Since this is only informational, errors are logged but
+    /// otherwise ignored
+    fn add_store_details(&self, mut statuses: IndexingStatuses) -> IndexingStatuses {
+        for status in statuses.0.iter_mut() {
+            let deployment = match SubgraphDeploymentId::new(status.subgraph.clone()) {
+                Ok(deployment) => deployment,
+                Err(_) => continue,
+            };
+            match self.store.deployment_sync_rate(&deployment) {
+                Ok(rate) => {
+                    for chain in status.chains.iter_mut() {
+                        let ChainIndexingStatus::Ethereum(chain) = chain;
+                        chain.blocks_per_minute = rate.blocks_per_minute;
+                        chain.latest_block_timestamp = rate.latest_block_timestamp;
+                    }
+                }
+                Err(e) => error!(
+                    self.logger,
+                    "Failed to get sync rate";
+                    "subgraph" => deployment.as_str(),
+                    "error" => format!("{:?}", e)
+                ),
+            }
+            match self.store.non_canonical_block(&deployment) {
+                Ok(block) => status.non_canonical_block = block,
+                Err(e) => error!(
+                    self.logger,
+                    "Failed to check whether deployment needs a rewind";
+                    "subgraph" => deployment.as_str(),
+                    "error" => format!("{:?}", e)
+                ),
+            }
+        }
+        statuses
+    }
+
     fn resolve_indexing_statuses(
         &self,
         arguments: &HashMap<&q::Name, q::Value>,
@@ -408,7 +547,7 @@ where
             Ok(Some(data)) => data,
         };
 
-        Ok(IndexingStatuses::from(data).into())
+        Ok(self.add_store_details(IndexingStatuses::from(data)).into())
     }
 
     fn resolve_indexing_statuses_for_subgraph_name(
@@ -534,7 +673,9 @@ where
                     .expect("missing deployment assignments"),
         };
 
-        Ok(IndexingStatuses::from(transformed_data).into())
+        Ok(self
+            .add_store_details(IndexingStatuses::from(transformed_data))
+            .into())
     }
 
     fn resolve_proof_of_indexing(
@@ -575,6 +716,304 @@ where
         Ok(poi)
     }
 
+    fn resolve_proof_of_indexing_at_block(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+        let block_number = arguments
+            .get_required::<BigInt>("blockNumber")
+            .expect("Valid blockNumber required")
+            .to_u64()
+            .min(BLOCK_NUMBER_MAX as u64) as BlockNumber;
+        let indexer = arguments
+            .get_optional::<Address>("indexer")
+            .expect("Invalid indexer");
+
+        let poi = match self
+            .store
+            .get_block_proof_of_indexing(&deployment_id, block_number)?
+        {
+            Some(poi) => poi,
+            None => return Ok(q::Value::Null),
+        };
+
+        // The recorded proof does not use an indexer address; with one, we
+        // have to compute the proof from scratch
+        let digest = match indexer {
+            None => poi.digest,
+            Some(_) => {
+                let poi_fut =
+                    self.store
+                        .get_proof_of_indexing(&deployment_id, &indexer, poi.block.hash);
+                match futures::executor::block_on(poi_fut) {
+                    Ok(Some(digest)) => digest,
+                    Ok(None) => return Ok(q::Value::Null),
+                    Err(e) => {
+                        error!(
+                            self.logger,
+                            "Failed to query proof of indexing";
+                            "subgraph" => deployment_id,
+                            "error" => format!("{:?}", e)
+                        );
+                        return Ok(q::Value::Null);
+                    }
+                }
+            }
+        };
+
+        Ok(object! {
+            __typename: "BlockProofOfIndexing",
+            block: EthereumBlock(poi.block),
+            version: poi.version,
+            proofOfIndexing: format!("0x{}", hex::encode(&digest)),
+        })
+    }
+
+    fn resolve_entity_counts(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+
+        let counts = self.store.entity_counts(&deployment_id).map_err(|e| {
+            error!(
+                self.logger,
+                "Failed to count entities";
+                "subgraph" => deployment_id.as_str(),
+                "error" => format!("{:?}", e)
+            );
+            QueryExecutionError::from(e)
+        })?;
+
+        Ok(q::Value::List(
+            counts
+                .into_iter()
+                .map(|count| {
+                    object! {
+                        __typename: "EntityTypeCount",
+                        entityType: count.entity_type,
+                        current: count.current,
+                        versions: count.versions,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_entity_access_stats(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+
+        let stats = self.store.entity_access_stats(&deployment_id)?;
+
+        Ok(q::Value::List(
+            stats
+                .into_iter()
+                .map(|stats| {
+                    object! {
+                        __typename: "EntityAccessStats",
+                        entityType: stats.entity_type,
+                        queryReads: stats.query_reads,
+                        queryTimeMs: stats.query_time.as_millis() as u64,
+                        mappingReads: stats.mapping_reads,
+                        mappingTimeMs: stats.mapping_time.as_millis() as u64,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_entity_history(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+        let entity_type = arguments
+            .get_required::<String>("entityType")
+            .expect("Valid entity type required");
+        let entity_id = arguments
+            .get_required::<String>("id")
+            .expect("Valid entity id required");
+        let first = arguments
+            .get_optional::<u64>("first")
+            .expect("Invalid first")
+            .unwrap_or(100)
+            .min(ENTITY_HISTORY_MAX_FIRST);
+        let skip = arguments
+            .get_optional::<u64>("skip")
+            .expect("Invalid skip")
+            .unwrap_or(0)
+            .min(u32::MAX as u64);
+        let range = EntityRange {
+            first: Some(first as u32),
+            skip: skip as u32,
+        };
+
+        let versions = self
+            .store
+            .entity_history(&deployment_id, &entity_type, &entity_id, range)
+            .map_err(|e| {
+                error!(
+                    self.logger,
+                    "Failed to load entity history";
+                    "subgraph" => deployment_id.as_str(),
+                    "entity_type" => &entity_type,
+                    "entity_id" => &entity_id,
+                    "error" => format!("{:?}", e)
+                );
+                QueryExecutionError::from(e)
+            })?;
+
+        Ok(q::Value::List(
+            versions
+                .into_iter()
+                .map(|version| {
+                    let mut attributes: Vec<_> = version.data.iter().collect();
+                    attributes.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    let attributes = attributes
+                        .into_iter()
+                        .map(|(name, value)| {
+                            object! {
+                                __typename: "EntityAttribute",
+                                name: name.clone(),
+                                value: match value {
+                                    Value::Null => None,
+                                    value => Some(value.to_string()),
+                                },
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    object! {
+                        __typename: "EntityVersion",
+                        blockStart: version.block_start as u64,
+                        blockEnd: version.block_end.map(|block| block as u64),
+                        attributes: attributes,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_recent_handler_errors(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+
+        Ok(q::Value::List(
+            HandlerError::recent(deployment_id.as_str())
+                .into_iter()
+                .map(|error| {
+                    object! {
+                        __typename: "HandlerError",
+                        block: error.block.map(EthereumBlock),
+                        dataSource: error.data_source,
+                        handler: error.handler,
+                        trigger: error.trigger,
+                        transactionHash: error.transaction_hash.map(|hash| format!("{:#x}", hash)),
+                        logIndex: error.log_index,
+                        message: error.message,
+                        wasmBacktrace: error.wasm_backtrace,
+                        deterministic: error.deterministic,
+                        timestamp: error.timestamp,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_handler_logs(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let deployment_id = arguments
+            .get_required::<SubgraphDeploymentId>("subgraph")
+            .expect("Valid subgraph required");
+        let block = arguments
+            .get_optional::<u64>("block")
+            .expect("Invalid block")
+            .map(|block| block.min(BLOCK_NUMBER_MAX as u64) as BlockNumber);
+        let levels = arguments
+            .get_optional::<Vec<String>>("levels")
+            .expect("Invalid levels")
+            .unwrap_or_default();
+        let first = arguments
+            .get_optional::<u64>("first")
+            .expect("Invalid first")
+            .unwrap_or(100) as usize;
+
+        let logs = self
+            .store
+            .handler_logs(&deployment_id, block, levels, first)?;
+        Ok(q::Value::List(
+            logs.into_iter()
+                .map(|log| {
+                    object! {
+                        __typename: "HandlerLog",
+                        block: EthereumBlock(EthereumBlockPointer::from((
+                            log.block_hash,
+                            log.block_number,
+                        ))),
+                        dataSource: log.data_source,
+                        level: log.level,
+                        message: log.message,
+                        timestamp: log.timestamp,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_block_ingestor_statuses(&self) -> Result<q::Value, QueryExecutionError> {
+        let statuses = self.store.chain_head_statuses().map_err(|e| {
+            error!(
+                self.logger,
+                "Failed to get chain head statuses";
+                "error" => format!("{:?}", e)
+            );
+            QueryExecutionError::from(e)
+        })?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+
+        Ok(q::Value::List(
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let healthy = status
+                        .head_block_timestamp
+                        .map(|timestamp| {
+                            now.saturating_sub(timestamp) <= *BLOCK_INGESTOR_MAX_HEAD_AGE
+                        })
+                        .unwrap_or(false);
+                    object! {
+                        __typename: "BlockIngestorStatus",
+                        network: status.network,
+                        chainHeadBlock: status.head_block.map(EthereumBlock),
+                        chainHeadBlockTimestamp: status.head_block_timestamp,
+                        healthy: healthy,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
     fn resolve_indexing_status_for_version(
         &self,
         arguments: &HashMap<&q::Name, q::Value>,
@@ -702,7 +1141,8 @@ where
                     .expect("missing deployment assignments"),
         );
 
-        Ok(IndexingStatuses::from(transformed_data)
+        Ok(self
+            .add_store_details(IndexingStatuses::from(transformed_data))
             .0
             .into_iter()
             .next()
@@ -721,6 +1161,7 @@ where
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            version: self.version.clone(),
         }
     }
 }
@@ -783,6 +1224,28 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `entityCounts` field
+            (None, "EntityTypeCount", "entityCounts") => self.resolve_entity_counts(arguments),
+            // The top-level `entityAccessStats` field
+            (None, "EntityAccessStats", "entityAccessStats") => {
+                self.resolve_entity_access_stats(arguments)
+            }
+            // The top-level `entityHistory` field
+            (None, "EntityVersion", "entityHistory") => self.resolve_entity_history(arguments),
+
+            // The top-level `recentHandlerErrors` field
+            (None, "HandlerError", "recentHandlerErrors") => {
+                self.resolve_recent_handler_errors(arguments)
+            }
+
+            // The top-level `handlerLogs` field
+            (None, "HandlerLog", "handlerLogs") => self.resolve_handler_logs(arguments),
+
+            // The top-level `blockIngestorStatuses` field
+            (None, "BlockIngestorStatus", "blockIngestorStatuses") => {
+                self.resolve_block_ingestor_statuses()
+            }
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
@@ -807,8 +1270,42 @@ where
                 self.resolve_indexing_status_for_version(arguments, false)
             }
 
+            // The top-level `version` field
+            (None, "version") => Ok(self.resolve_version()),
+
+            // The top-level `proofOfIndexingAtBlock` field
+            (None, "proofOfIndexingAtBlock") => self.resolve_proof_of_indexing_at_block(arguments),
+
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
     }
 }
+
+#[test]
+fn estimates_seconds_to_chain_head() {
+    fn eta(latest: u64, head: u64, blocks_per_minute: Option<f64>) -> Option<u64> {
+        let block = |number: u64| Some(EthereumBlock((H256::zero(), number).into()));
+        EthereumIndexingStatus {
+            network: "mainnet".to_owned(),
+            chain_head_block: block(head),
+            earliest_block: block(0),
+            latest_block: block(latest),
+            latest_block_timestamp: None,
+            blocks_per_minute,
+            start_block: 0,
+        }
+        .seconds_to_chain_head()
+    }
+
+    // 100 blocks at 30 blocks per minute take 200 seconds
+    assert_eq!(Some(200), eta(900, 1000, Some(30.0)));
+    // Partial seconds are rounded up
+    assert_eq!(Some(2), eta(999, 1000, Some(40.0)));
+    // A deployment at or past the chain head is done
+    assert_eq!(Some(0), eta(1000, 1000, None));
+    assert_eq!(Some(0), eta(1001, 1000, Some(0.0)));
+    // Without progress, there is no estimate
+    assert_eq!(None, eta(900, 1000, Some(0.0)));
+    assert_eq!(None, eta(900, 1000, None));
+}
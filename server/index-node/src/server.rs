@@ -3,7 +3,9 @@ use hyper::service::make_service_fn;
 use hyper::Server;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
+use graph::data::version::NodeVersion;
 use graph::prelude::{IndexNodeServer as IndexNodeServerTrait, *};
+use graph::util::shutdown;
 
 use crate::service::IndexNodeService;
 
@@ -26,6 +28,7 @@ pub struct IndexNodeServer<Q, S> {
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     node_id: NodeId,
+    version: Arc<NodeVersion>,
 }
 
 impl<Q, S> IndexNodeServer<Q, S> {
@@ -35,6 +38,7 @@ impl<Q, S> IndexNodeServer<Q, S> {
         graphql_runner: Arc<Q>,
         store: Arc<S>,
         node_id: NodeId,
+        version: NodeVersion,
     ) -> Self {
         let logger = logger_factory.component_logger(
             "IndexNodeServer",
@@ -50,6 +54,7 @@ impl<Q, S> IndexNodeServer<Q, S> {
             graphql_runner,
             store,
             node_id,
+            version: Arc::new(version),
         }
     }
 }
@@ -72,6 +77,10 @@ where
             "Starting index node server at: http://localhost:{}", port
         );
 
+        if AUTH_TOKENS.is_enabled() {
+            info!(logger, "Requiring bearer tokens for the index node server");
+        }
+
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
         // On every incoming request, launch a new GraphQL service that writes
@@ -80,20 +89,32 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let store = self.store.clone();
         let node_id = self.node_id.clone();
+        let version = self.version.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(IndexNodeService::new(
                 logger_for_service.clone(),
                 graphql_runner.clone(),
                 store.clone(),
                 node_id.clone(),
+                version.clone(),
             ))
         });
 
-        // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        // Create a task to run the server and handle HTTP requests until the
+        // node shuts down. The shutdown waits for the requests that are
+        // being handled
+        let server = Server::try_bind(&addr.into())?
             .serve(new_service)
-            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+            .with_graceful_shutdown(shutdown::initiated());
+        let guard = shutdown::in_flight();
+        let task = async move {
+            if let Err(e) = server.await {
+                error!(logger, "Server error"; "error" => format!("{}", e));
+            }
+            drop(guard);
+            Ok(())
+        };
 
-        Ok(Box::new(task.compat()))
+        Ok(Box::new(task.boxed().compat()))
     }
 }
@@ -4,7 +4,9 @@ use hyper::{Body, Method, Request, Response, StatusCode};
 use std::task::Context;
 use std::task::Poll;
 
+use graph::components::server::health;
 use graph::components::server::query::GraphQLServerError;
+use graph::data::version::NodeVersion;
 use graph::prelude::*;
 use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
 
@@ -22,6 +24,7 @@ pub struct IndexNodeService<Q, S> {
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     node_id: NodeId,
+    version: Arc<NodeVersion>,
 }
 
 impl<Q, S> Clone for IndexNodeService<Q, S> {
@@ -31,6 +34,7 @@ impl<Q, S> Clone for IndexNodeService<Q, S> {
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
             node_id: self.node_id.clone(),
+            version: self.version.clone(),
         }
     }
 }
@@ -43,12 +47,19 @@ where
     S: SubgraphDeploymentStore + Store,
 {
     /// Creates a new GraphQL service.
-    pub fn new(logger: Logger, graphql_runner: Arc<Q>, store: Arc<S>, node_id: NodeId) -> Self {
+    pub fn new(
+        logger: Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        node_id: NodeId,
+        version: Arc<NodeVersion>,
+    ) -> Self {
         IndexNodeService {
             logger,
             graphql_runner,
             store,
             node_id,
+            version,
         }
     }
 
@@ -103,7 +114,12 @@ where
         let logger = self.logger.cheap_clone();
         let result = {
             let options = QueryExecutionOptions {
-                resolver: IndexNodeResolver::new(&logger, graphql_runner, store),
+                resolver: IndexNodeResolver::new(
+                    &logger,
+                    graphql_runner,
+                    store,
+                    self.version.cheap_clone(),
+                ),
                 deadline: None,
                 max_first: std::u32::MAX,
                 max_skip: std::u32::MAX,
@@ -125,7 +141,10 @@ where
         Response::builder()
             .status(200)
             .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+            .header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, User-Agent, Authorization",
+            )
             .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
             .body(Body::from(""))
             .unwrap()
@@ -146,6 +165,58 @@ where
             })
     }
 
+    /// Serves the handler profile of a deployment in folded stack format.
+    fn handle_profile(deployment: &str) -> Response<Body> {
+        match HandlerProfile::folded_for(deployment) {
+            Some(profile) => Response::builder()
+                .status(200)
+                .header(header::CONTENT_TYPE, "text/plain")
+                .body(Body::from(profile))
+                .unwrap(),
+            None => Self::handle_not_found(),
+        }
+    }
+
+    /// Serves the health of the subsystems of the node. Health checks do
+    /// not need a token so that probes can use them. `/healthz` always
+    /// responds with a 200 so that liveness probes do not restart a node
+    /// just because its database or an Ethereum node is down; `/readyz`
+    /// responds with a 503 while a subsystem is unhealthy
+    async fn handle_health(readiness: bool) -> Result<Response<Body>, GraphQLServerError> {
+        let health = health::node_health();
+        let status = if readiness && !health.ready {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::OK
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&health).expect("health is serializable"),
+            ))
+            .unwrap())
+    }
+
+    /// Checks that the request carries a token that may read indexing
+    /// statuses, and responds with a 401 if it does not.
+    fn authorize(req: &Request<Body>) -> Result<(), Response<Body>> {
+        let authorization = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        AUTH_TOKENS
+            .authorize(authorization, Role::Read)
+            .map_err(|message| {
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, "Bearer")
+                    .header(header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from(message))
+                    .unwrap()
+            })
+    }
+
     /// Handles 404s.
     fn handle_not_found() -> Response<Body> {
         Response::builder()
@@ -182,9 +253,20 @@ where
             }
             (Method::GET, ["graphql", "playground"]) => Ok(Self::handle_graphiql()),
 
-            (Method::POST, ["graphql"]) => self.handle_graphql_query(req.into_body()).await,
+            (Method::POST, ["graphql"]) => match Self::authorize(&req) {
+                Ok(()) => self.handle_graphql_query(req.into_body()).await,
+                Err(response) => Ok(response),
+            },
             (Method::OPTIONS, ["graphql"]) => Ok(Self::handle_graphql_options(req)),
 
+            (Method::GET, ["healthz"]) => Self::handle_health(false).await,
+            (Method::GET, ["readyz"]) => Self::handle_health(true).await,
+
+            (Method::GET, ["profile", deployment]) => match Self::authorize(&req) {
+                Ok(()) => Ok(Self::handle_profile(deployment)),
+                Err(response) => Ok(response),
+            },
+
             _ => Ok(Self::handle_not_found()),
         }
     }
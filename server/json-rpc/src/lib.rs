@@ -8,7 +8,8 @@ use graph::prelude::futures03::SinkExt;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
+    hyper,
+    jsonrpc_core::{self, Compatibility, MetaIoHandler, Metadata, Params, Value},
     RestApi, Server, ServerBuilder,
 };
 use lazy_static::lazy_static;
@@ -29,6 +30,32 @@ const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_CLONE_ERROR: i64 = 4;
+const JSON_RPC_UNASSIGN_ERROR: i64 = 5;
+const JSON_RPC_LIST_ERROR: i64 = 6;
+const JSON_RPC_INFO_ERROR: i64 = 7;
+const JSON_RPC_UNAUTHORIZED_ERROR: i64 = 8;
+const JSON_RPC_RETRY_ERROR: i64 = 9;
+
+/// The `Authorization` header of a request
+#[derive(Clone, Debug, Default)]
+struct AuthMeta {
+    authorization: Option<String>,
+}
+
+impl Metadata for AuthMeta {}
+
+impl AuthMeta {
+    fn authorize(&self, required: Role) -> Result<(), jsonrpc_core::Error> {
+        AUTH_TOKENS
+            .authorize(self.authorization.as_deref(), required)
+            .map_err(|message| jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_UNAUTHORIZED_ERROR),
+                message,
+                data: None,
+            })
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -40,6 +67,8 @@ struct SubgraphDeployParams {
     name: SubgraphName,
     ipfs_hash: SubgraphDeploymentId,
     node_id: Option<NodeId>,
+    /// Changes to the data sources of the manifest for this deployment
+    overrides: Option<DataSourceOverrides>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -53,11 +82,38 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphCloneParams {
+    /// The subgraph the clone becomes a version of
+    target_name: SubgraphName,
+    ipfs_hash: SubgraphDeploymentId,
+    clone_id: SubgraphDeploymentId,
+    node_id: Option<NodeId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeploymentUnassignParams {
+    ipfs_hash: SubgraphDeploymentId,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphRetryParams {
+    ipfs_hash: SubgraphDeploymentId,
+    /// How many blocks before the failed block to restart from
+    blocks: Option<u64>,
+}
+
+/// Exactly one of `name` and `ipfs_hash` must be given
+#[derive(Debug, Deserialize)]
+struct SubgraphInfoParams {
+    name: Option<SubgraphName>,
+    ipfs_hash: Option<SubgraphDeploymentId>,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
     http_port: u16,
     ws_port: u16,
-    node_id: NodeId,
     logger: Logger,
 }
 
@@ -90,14 +146,26 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
     ) -> Result<Value, jsonrpc_core::Error> {
         info!(&self.logger, "Received subgraph_deploy request"; "params" => format!("{:?}", params));
 
-        let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
-        let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
+        let mut routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
         match self
             .registrar
-            .create_subgraph_version(params.name.clone(), params.ipfs_hash.clone(), node_id)
+            .create_subgraph_version(
+                params.name.clone(),
+                params.ipfs_hash.clone(),
+                params.node_id.clone(),
+                params.overrides.clone(),
+            )
             .await
         {
-            Ok(_) => Ok(routes),
+            Ok(graft_preview) => {
+                if let (Some(preview), Value::Object(map)) = (graft_preview, &mut routes) {
+                    map.insert(
+                        "graft".to_owned(),
+                        jsonrpc_core::to_value(preview).expect("invalid graft preview"),
+                    );
+                }
+                Ok(routes)
+            }
             Err(e) => Err(json_rpc_error(
                 &self.logger,
                 "subgraph_deploy",
@@ -108,6 +176,35 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         }
     }
 
+    /// Handler for the `subgraph_clone` endpoint.
+    async fn clone_handler(
+        &self,
+        params: SubgraphCloneParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_clone request"; "params" => format!("{:?}", params));
+
+        let routes = subgraph_routes(&params.target_name, self.http_port, self.ws_port);
+        match self
+            .registrar
+            .clone_subgraph_deployment(
+                params.target_name.clone(),
+                params.ipfs_hash.clone(),
+                params.clone_id.clone(),
+                params.node_id.clone(),
+            )
+            .await
+        {
+            Ok(_) => Ok(routes),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_clone",
+                e,
+                JSON_RPC_CLONE_ERROR,
+                params,
+            )),
+        }
+    }
+
     /// Handler for the `subgraph_remove` endpoint.
     async fn remove_handler(
         &self,
@@ -151,6 +248,90 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `deployment_unassign` endpoint.
+    async fn unassign_handler(
+        &self,
+        params: DeploymentUnassignParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received deployment_unassign request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .unassign_subgraph(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "deployment_unassign",
+                e,
+                JSON_RPC_UNASSIGN_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_retry` endpoint.
+    async fn retry_handler(
+        &self,
+        params: SubgraphRetryParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_retry request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .retry_subgraph(params.ipfs_hash.clone(), params.blocks.unwrap_or(1))
+            .await
+        {
+            Ok(block_ptr) => Ok(jsonrpc_core::to_value(block_ptr).expect("invalid block pointer")),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_retry",
+                e,
+                JSON_RPC_RETRY_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_list` endpoint.
+    async fn list_handler(&self) -> Result<Value, jsonrpc_core::Error> {
+        match self.registrar.list_subgraphs().await {
+            Ok(infos) => Ok(jsonrpc_core::to_value(infos).expect("invalid deployment infos")),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_list",
+                e,
+                JSON_RPC_LIST_ERROR,
+                (),
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_info` endpoint.
+    async fn info_handler(&self, params: SubgraphInfoParams) -> Result<Value, jsonrpc_core::Error> {
+        if params.name.is_some() == params.ipfs_hash.is_some() {
+            return Err(jsonrpc_core::Error::invalid_params(
+                "exactly one of `name` and `ipfs_hash` must be given",
+            ));
+        }
+
+        match self
+            .registrar
+            .subgraph_info(params.name.clone(), params.ipfs_hash.clone())
+            .await
+        {
+            Ok(infos) => Ok(jsonrpc_core::to_value(infos).expect("invalid deployment infos")),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_info",
+                e,
+                JSON_RPC_INFO_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -164,7 +345,6 @@ where
         http_port: u16,
         ws_port: u16,
         registrar: Arc<R>,
-        node_id: NodeId,
         logger: Logger,
     ) -> Result<Self::Server, io::Error> {
         let logger = logger.new(o!("component" => "JsonRpcServer"));
@@ -176,13 +356,19 @@ where
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        if AUTH_TOKENS.is_enabled() {
+            info!(
+                logger,
+                "Requiring bearer tokens for the JSON-RPC admin server"
+            );
+        }
+
+        let mut handler = MetaIoHandler::with_compatibility(Compatibility::Both);
 
         let arc_self = Arc::new(JsonRpcServer {
             registrar,
             http_port,
             ws_port,
-            node_id,
             logger,
         });
 
@@ -217,11 +403,12 @@ where
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
+        handler.add_method_with_meta("subgraph_create", move |params: Params, meta: AuthMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    meta.authorize(Role::Admin)?;
                     let params = params.parse()?;
                     me.create_handler(params).await
                 }
@@ -233,11 +420,12 @@ where
         let me = arc_self.clone();
         let sender = task_sender.clone();
 
-        handler.add_method("subgraph_deploy", move |params: Params| {
+        handler.add_method_with_meta("subgraph_deploy", move |params: Params, meta: AuthMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    meta.authorize(Role::Admin)?;
                     let params = params.parse()?;
                     me.deploy_handler(params).await
                 }
@@ -248,11 +436,12 @@ where
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
+        handler.add_method_with_meta("subgraph_remove", move |params: Params, meta: AuthMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    meta.authorize(Role::Admin)?;
                     let params = params.parse()?;
                     me.remove_handler(params).await
                 }
@@ -263,24 +452,117 @@ where
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_reassign", move |params: Params| {
+        handler.add_method_with_meta(
+            "subgraph_reassign",
+            move |params: Params, meta: AuthMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        meta.authorize(Role::Admin)?;
+                        let params = params.parse()?;
+                        me.reassign_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_clone", move |params: Params, meta: AuthMeta| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    meta.authorize(Role::Admin)?;
+                    let params = params.parse()?;
+                    me.clone_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "deployment_unassign",
+            move |params: Params, meta: AuthMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        meta.authorize(Role::Admin)?;
+                        let params = params.parse()?;
+                        me.unassign_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_retry", move |params: Params, meta: AuthMeta| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    meta.authorize(Role::Admin)?;
+                    let params = params.parse()?;
+                    me.retry_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_list", move |params: Params, meta: AuthMeta| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    meta.authorize(Role::Read)?;
+                    params.expect_no_params()?;
+                    me.list_handler().await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta("subgraph_info", move |params: Params, meta: AuthMeta| {
             let me = me.clone();
             Box::pin(tokio02_spawn(
                 sender.clone(),
                 async move {
+                    meta.authorize(Role::Read)?;
                     let params = params.parse()?;
-                    me.reassign_handler(params).await
+                    me.info_handler(params).await
                 }
                 .boxed(),
             ))
             .compat()
         });
 
-        ServerBuilder::new(handler)
-            // Enable REST API:
-            // POST /<method>/<param1>/<param2>
-            .rest_api(RestApi::Secure)
-            .start_http(&addr.into())
+        ServerBuilder::with_meta_extractor(handler, |req: &hyper::Request<hyper::Body>| AuthMeta {
+            authorization: req
+                .headers()
+                .get(hyper::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_owned()),
+        })
+        // Enable REST API:
+        // POST /<method>/<param1>/<param2>
+        .rest_api(RestApi::Secure)
+        .start_http(&addr.into())
     }
 }
 
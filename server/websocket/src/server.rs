@@ -1,6 +1,7 @@
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
 use graph::prelude::{SubscriptionServer as SubscriptionServerTrait, *};
-use http::{HeaderValue, Response, StatusCode};
+use graph::util::shutdown;
+use http::{header, HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Mutex;
 use tokio::net::TcpListener;
@@ -14,6 +15,7 @@ pub struct SubscriptionServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    api_keys: Arc<ApiKeys>,
 }
 
 impl<Q, S> SubscriptionServer<Q, S>
@@ -21,11 +23,17 @@ where
     Q: GraphQlRunner,
     S: SubgraphDeploymentStore + Store,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<Q>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        api_keys: Arc<ApiKeys>,
+    ) -> Self {
         SubscriptionServer {
             logger: logger.new(o!("component" => "SubscriptionServer")),
             graphql_runner,
             store,
+            api_keys,
         }
     }
 
@@ -89,8 +97,18 @@ where
             .await
             .expect("Failed to bind WebSocket port");
 
+        // Stop accepting connections once the node shuts down. Open
+        // subscriptions keep receiving the events of the blocks that are
+        // committed while the node shuts down
         let mut incoming = socket.incoming();
-        while let Some(stream_res) = incoming.next().await {
+        loop {
+            let stream_res = tokio::select! {
+                stream_res = incoming.next() => match stream_res {
+                    Some(stream_res) => stream_res,
+                    None => break,
+                },
+                _ = shutdown::initiated() => break,
+            };
             let stream = match stream_res {
                 Ok(stream) => stream,
                 Err(e) => {
@@ -103,6 +121,7 @@ where
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
             let store2 = self.store.clone();
+            let api_keys = self.api_keys.clone();
 
             // Subgraph that the request is resolved to (if any)
             let subgraph_id = Arc::new(Mutex::new(None));
@@ -138,6 +157,26 @@ where
                     Ok(true) => (),
                 }
 
+                if *REQUIRE_API_KEYS {
+                    let authorization = request
+                        .headers()
+                        .get(header::AUTHORIZATION)
+                        .and_then(|value| value.to_str().ok());
+                    // The handshake callback can not be async; checking the
+                    // key may have to wait for the store, and we let the
+                    // runtime move other tasks off this thread meanwhile
+                    let checked = tokio::task::block_in_place(|| {
+                        graph::block_on(api_keys.check(store.clone(), authorization, &subgraph_id))
+                    });
+                    if let Err(e) = checked {
+                        let mut response = Response::builder().status(e.status());
+                        if e.status() == StatusCode::UNAUTHORIZED {
+                            response = response.header(header::WWW_AUTHENTICATE, "Bearer");
+                        }
+                        return Err(response.body(Some(e.to_string())).unwrap());
+                    }
+                }
+
                 *accept_subgraph_id.lock().unwrap() = Some(subgraph_id);
                 response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("graphql-ws"));
                 Ok(response)
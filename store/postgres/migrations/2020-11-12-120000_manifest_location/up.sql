@@ -0,0 +1,4 @@
+-- Deployments that were created before this column existed have their
+-- manifest at /ipfs/<deployment id>
+alter table subgraphs.subgraph_manifest
+  add column location text;
@@ -0,0 +1,4 @@
+alter table subgraphs.subgraph_deployment
+  drop column sync_rate_block_number,
+  drop column sync_rate_sampled_at,
+  drop column blocks_per_minute;
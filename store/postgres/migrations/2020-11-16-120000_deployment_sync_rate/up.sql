@@ -0,0 +1,7 @@
+-- Every minute or so, forward_block_ptr records the deployment's latest
+-- block together with the time, and computes how many blocks per minute
+-- the deployment processed since the previous sample
+alter table subgraphs.subgraph_deployment
+  add column sync_rate_block_number numeric,
+  add column sync_rate_sampled_at timestamptz,
+  add column blocks_per_minute float8;
@@ -0,0 +1,2 @@
+drop table subgraphs.api_key_usage;
+drop table subgraphs.api_key;
@@ -0,0 +1,20 @@
+-- API keys for the GraphQL server. We only store a hash of the secret
+-- part of each key; a key without deployments can be used for all of them
+create table subgraphs.api_key (
+  id                  text primary key,
+  secret_hash         text not null unique,
+  description         text,
+  deployments         text[],
+  queries_per_minute  int,
+  created_at          timestamptz not null default now(),
+  revoked_at          timestamptz
+);
+
+-- How many queries were made with each key against each deployment per day
+create table subgraphs.api_key_usage (
+  api_key     text not null references subgraphs.api_key(id),
+  deployment  text not null,
+  day         date not null,
+  queries     int8 not null,
+  primary key (api_key, deployment, day)
+);
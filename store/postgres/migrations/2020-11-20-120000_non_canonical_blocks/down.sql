@@ -0,0 +1 @@
+drop table subgraphs.non_canonical_block;
@@ -0,0 +1,10 @@
+-- Deployments that indexed a block that turned out not to be part of the
+-- canonical chain after it had become older than the reorg threshold.
+-- Such deployments do not recover on their own and need to be rewound
+create table subgraphs.non_canonical_block (
+  deployment      text primary key,
+  block_number    int8 not null,
+  block_hash      bytea not null,
+  canonical_hash  bytea,
+  detected_at     timestamptz not null default now()
+);
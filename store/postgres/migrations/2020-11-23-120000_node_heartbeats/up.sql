@@ -0,0 +1,8 @@
+-- When each node was last seen alive. Nodes whose heartbeat is too old are
+-- considered dead, and their deployments are assigned to other nodes
+create table subgraphs.node_heartbeat (
+  node_id          text primary key,
+  last_seen        timestamptz not null default now(),
+  -- Whether deployments of dead nodes may be assigned to this node
+  failover_target  bool not null
+);
@@ -0,0 +1 @@
+drop table subgraphs.job_run;
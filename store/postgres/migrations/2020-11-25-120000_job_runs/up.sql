@@ -0,0 +1,15 @@
+-- The runs of the background jobs that maintain the database. The runs
+-- that have not finished yet also limit how many jobs run at the same time
+-- across all nodes
+create table subgraphs.job_run (
+  id           bigserial primary key,
+  job          text not null,
+  node_id      text not null,
+  started_at   timestamptz not null default now(),
+  finished_at  timestamptz,
+  -- Why the run failed, or null if it succeeded or has not finished
+  error        text
+);
+
+create index job_run_job_started_at
+    on subgraphs.job_run(job, started_at desc);
@@ -0,0 +1 @@
+drop table subgraphs.unused_deployment;
@@ -0,0 +1,10 @@
+-- Deployments that are neither the current nor the pending version of any
+-- subgraph and that are not assigned to a node, and since when that is the
+-- case. Background jobs keep this table up to date
+create table subgraphs.unused_deployment (
+  deployment  text primary key,
+  unused_at   timestamptz not null default now(),
+  -- The size of the deployment's tables and indexes in bytes when it was
+  -- found to be unused
+  size        int8 not null
+);
@@ -0,0 +1,3 @@
+-- The data source overrides given when a deployment was created, as JSON
+alter table subgraphs.subgraph_manifest
+  add column overrides text;
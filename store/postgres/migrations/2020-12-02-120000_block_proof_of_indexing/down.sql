@@ -0,0 +1,4 @@
+drop table subgraphs.block_proof_of_indexing;
+
+alter table subgraphs.subgraph_deployment
+  drop column poi_version;
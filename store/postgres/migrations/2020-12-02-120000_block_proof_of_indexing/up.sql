@@ -0,0 +1,18 @@
+-- The version of the algorithm that computes the proofs of indexing of a
+-- deployment. All deployments that exist already use the first version
+alter table subgraphs.subgraph_deployment
+  add column poi_version int4 not null default 1;
+
+-- The proof of indexing for each block that a deployment processed,
+-- computed without an indexer address
+create table subgraphs.block_proof_of_indexing (
+  deployment    text not null,
+  block_number  int4 not null,
+  block_hash    bytea not null,
+  poi_version   int4 not null,
+  digest        bytea not null,
+  primary key(deployment, block_number)
+);
+
+create index block_proof_of_indexing_hash
+    on subgraphs.block_proof_of_indexing(deployment, block_hash);
@@ -0,0 +1,3 @@
+-- The features a subgraph uses; not known for deployments created before
+alter table subgraphs.subgraph_manifest
+  add column features text[];
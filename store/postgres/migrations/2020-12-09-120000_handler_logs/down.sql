@@ -0,0 +1 @@
+drop table subgraphs.handler_log;
@@ -0,0 +1,15 @@
+-- The most recent messages that the mappings of each deployment logged
+create table subgraphs.handler_log (
+  vid           bigserial primary key,
+  deployment    text not null,
+  block_number  int4 not null,
+  block_hash    bytea not null,
+  data_source   text not null,
+  level         text not null,
+  message       text not null,
+  -- Seconds since the epoch
+  logged_at     int8 not null
+);
+
+create index handler_log_deployment
+    on subgraphs.handler_log(deployment, vid);
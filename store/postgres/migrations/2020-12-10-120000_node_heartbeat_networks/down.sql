@@ -0,0 +1,3 @@
+alter table subgraphs.node_heartbeat
+  drop column networks,
+  drop column stopped_at;
@@ -0,0 +1,7 @@
+-- The networks that each node indexes, so that deployments of dead nodes
+-- only go to nodes that index their network, and when a node last shut
+-- down cleanly. Nodes that are down on purpose keep their deployments for
+-- a while longer
+alter table subgraphs.node_heartbeat
+  add column networks text[] not null default '{}',
+  add column stopped_at timestamptz;
@@ -0,0 +1,3 @@
+alter table subgraphs.job_run
+  drop column database,
+  drop column heartbeat_at;
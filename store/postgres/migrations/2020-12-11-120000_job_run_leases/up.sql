@@ -0,0 +1,7 @@
+-- A run keeps its lease by updating `heartbeat_at` while it is going; a
+-- run whose lease ran out is considered abandoned, e.g., because the node
+-- running it died. Runs also record the database they maintain so that
+-- the number of jobs running at the same time is limited per database
+alter table subgraphs.job_run
+  add column database text not null default 'main',
+  add column heartbeat_at timestamptz not null default now();
@@ -0,0 +1,21 @@
+//! The keys of the advisory locks that `graph-node` takes. Advisory locks
+//! are shared by everything that connects to the same database, and keeping
+//! all keys in one place makes sure that unrelated uses never end up with
+//! the same lock.
+//!
+//! Schema migrations do not use an advisory lock. They are serialized by
+//! locking the table `__graph_node_global_lock` since that is what older
+//! versions of `graph-node` do.
+
+/// The key of the lock that nodes take while they move deployments off
+/// dead nodes
+pub(crate) const FAILOVER_LOCK: i64 = 1;
+
+/// The key of the lock that nodes take while they decide whether to start
+/// a background job
+pub(crate) const JOB_LOCK: i64 = 2;
+
+/// The first key of the lock that is held while the schema of a deployment
+/// is moved; the second key is the id of the deployment's entry in
+/// `deployment_schemas`
+pub(crate) const MOVE_LOCK: i32 = 3;
@@ -0,0 +1,192 @@
+//! Maintain the rollups for aggregations over timeseries entities.
+//!
+//! Entities of a timeseries type can only ever be inserted. That makes it
+//! possible to update the aggregations incrementally: for every inserted
+//! timeseries entity, we fold its values into the aggregation entity for
+//! the time bucket that its `timestamp` falls into. Aggregation entities
+//! are ordinary versioned entities, written at the same block as the
+//! timeseries entities they summarize, so that reverting a block also
+//! reverts the rollups
+use graph::data::schema::{AggregateFn, Aggregation, AggregationInterval};
+use graph::prelude::{
+    format_err, Entity, EntityKey, EntityModification, StoreError, SubgraphDeploymentId, Value,
+    ValueType, BLOCK_NUMBER_MAX,
+};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use crate::entities as e;
+
+/// Return the modifications needed to bring all aggregations that are
+/// affected by `mods` up to date
+pub(crate) fn rollups(
+    conn: &e::Connection,
+    aggregations: &[Aggregation],
+    subgraph_id: &SubgraphDeploymentId,
+    mods: &[EntityModification],
+) -> Result<Vec<EntityModification>, StoreError> {
+    if aggregations.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Maps (aggregation, interval, bucket start) to the updated
+    // aggregation entity and whether that entity already existed
+    let mut buckets: BTreeMap<(String, AggregationInterval, i64), (Entity, bool)> = BTreeMap::new();
+
+    for modification in mods {
+        let key = modification.entity_key();
+        let sources: Vec<_> = aggregations
+            .iter()
+            .filter(|aggregation| aggregation.source == key.entity_type)
+            .collect();
+        if sources.is_empty() {
+            continue;
+        }
+
+        let data = match modification {
+            EntityModification::Insert { data, .. } => data,
+            EntityModification::Overwrite { .. } | EntityModification::Remove { .. } => {
+                return Err(StoreError::Unknown(format_err!(
+                    "timeseries entity {}[{}] can not be changed or removed",
+                    key.entity_type,
+                    key.entity_id
+                )))
+            }
+        };
+        let timestamp = match data.get(Aggregation::TIMESTAMP) {
+            Some(Value::Int(timestamp)) => Some(*timestamp as i64),
+            Some(Value::BigInt(timestamp)) => i64::try_from(timestamp).ok(),
+            _ => None,
+        };
+        let timestamp = timestamp.ok_or_else(|| {
+            StoreError::Unknown(format_err!(
+                "timeseries entity {}[{}] must have an Int or BigInt `{}` that fits into 64 bits",
+                key.entity_type,
+                key.entity_id,
+                Aggregation::TIMESTAMP
+            ))
+        })?;
+
+        for aggregation in sources {
+            for interval in &aggregation.intervals {
+                let start = interval.bucket_start(timestamp);
+                let bucket_key = (aggregation.name.clone(), *interval, start);
+                if !buckets.contains_key(&bucket_key) {
+                    let id = Aggregation::bucket_id(*interval, start);
+                    let bucket = match conn.find(&aggregation.name, &id, BLOCK_NUMBER_MAX)? {
+                        Some(entity) => (entity, true),
+                        None => (empty_bucket(aggregation, *interval, start)?, false),
+                    };
+                    buckets.insert(bucket_key.clone(), bucket);
+                }
+                let (entity, _) = buckets.get_mut(&bucket_key).unwrap();
+                fold(aggregation, entity, data)?;
+            }
+        }
+    }
+
+    Ok(buckets
+        .into_iter()
+        .map(|((name, interval, start), (data, exists))| {
+            let key = EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: name,
+                entity_id: Aggregation::bucket_id(interval, start),
+            };
+            if exists {
+                EntityModification::Overwrite { key, data }
+            } else {
+                EntityModification::Insert { key, data }
+            }
+        })
+        .collect())
+}
+
+fn empty_bucket(
+    aggregation: &Aggregation,
+    interval: AggregationInterval,
+    start: i64,
+) -> Result<Entity, StoreError> {
+    let timestamp = match aggregation.timestamp_type {
+        ValueType::BigInt => Value::BigInt(start.into()),
+        _ => Value::Int(i32::try_from(start).map_err(|_| {
+            StoreError::Unknown(format_err!(
+                "the start {} of the {} bucket for {} does not fit into an Int `{}`",
+                start,
+                interval,
+                aggregation.name,
+                Aggregation::TIMESTAMP
+            ))
+        })?),
+    };
+
+    let mut entity = Entity::new();
+    entity.set("id", Aggregation::bucket_id(interval, start));
+    entity.set(Aggregation::TIMESTAMP, timestamp);
+    entity.set(Aggregation::INTERVAL, interval.as_str());
+    Ok(entity)
+}
+
+/// Fold the values of the timeseries entity `source` into the aggregation
+/// entity `bucket`
+fn fold(aggregation: &Aggregation, bucket: &mut Entity, source: &Entity) -> Result<(), StoreError> {
+    for aggregate in &aggregation.aggregates {
+        let overflow = || {
+            StoreError::Unknown(format_err!(
+                "the aggregate {}.{} overflows an Int; use a BigInt for it instead",
+                aggregation.name,
+                aggregate.name
+            ))
+        };
+
+        let current = bucket.get(&aggregate.name).cloned().unwrap_or(Value::Null);
+        let value = aggregate
+            .arg
+            .as_ref()
+            .and_then(|arg| source.get(arg))
+            .cloned()
+            .unwrap_or(Value::Null);
+
+        let next = match (aggregate.func, current, value) {
+            (AggregateFn::Count, Value::Null, _) => match aggregate.value_type {
+                ValueType::BigInt => Value::BigInt(1.into()),
+                _ => Value::Int(1),
+            },
+            (AggregateFn::Count, Value::Int(n), _) => {
+                Value::Int(n.checked_add(1).ok_or_else(overflow)?)
+            }
+            (AggregateFn::Count, Value::BigInt(n), _) => Value::BigInt(n + 1.into()),
+            // Null values in the timeseries do not change the aggregate
+            (_, current, Value::Null) => current,
+            (_, Value::Null, value) => value,
+            (AggregateFn::Sum, Value::Int(a), Value::Int(b)) => {
+                Value::Int(a.checked_add(b).ok_or_else(overflow)?)
+            }
+            (AggregateFn::Sum, Value::BigInt(a), Value::BigInt(b)) => Value::BigInt(a + b),
+            (AggregateFn::Sum, Value::BigDecimal(a), Value::BigDecimal(b)) => {
+                Value::BigDecimal(a + b)
+            }
+            (AggregateFn::Min, Value::Int(a), Value::Int(b)) => Value::Int(a.min(b)),
+            (AggregateFn::Min, Value::BigInt(a), Value::BigInt(b)) => Value::BigInt(a.min(b)),
+            (AggregateFn::Min, Value::BigDecimal(a), Value::BigDecimal(b)) => {
+                Value::BigDecimal(a.min(b))
+            }
+            (AggregateFn::Max, Value::Int(a), Value::Int(b)) => Value::Int(a.max(b)),
+            (AggregateFn::Max, Value::BigInt(a), Value::BigInt(b)) => Value::BigInt(a.max(b)),
+            (AggregateFn::Max, Value::BigDecimal(a), Value::BigDecimal(b)) => {
+                Value::BigDecimal(a.max(b))
+            }
+            (_, current, value) => {
+                return Err(StoreError::Unknown(format_err!(
+                    "can not aggregate {} and {} for {}.{}",
+                    current,
+                    value,
+                    aggregation.name,
+                    aggregate.name
+                )))
+            }
+        };
+        bucket.set(aggregate.name.as_str(), next);
+    }
+    Ok(())
+}
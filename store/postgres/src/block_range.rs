@@ -92,8 +92,6 @@ pub struct BlockRangeContainsClause<'a> {
 
 impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
-        out.unsafe_to_cache_prepared();
-
         out.push_sql(self.table_prefix);
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql(" @> ");
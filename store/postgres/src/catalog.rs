@@ -1,37 +1,49 @@
 use diesel::pg::PgConnection;
 use diesel::prelude::RunQueryDsl;
-use diesel::sql_types::Text;
+use diesel::sql_types::{Integer, Text};
 use std::collections::{HashMap, HashSet};
 
 use graph::prelude::StoreError;
 
 use crate::relational::SqlName;
 
+/// The oldest version of Postgres that we support, in the format of the
+/// `server_version_num` setting
+pub const MIN_SERVER_VERSION: i32 = 90600;
+
 /// Information about what tables and columns we have in the database
 #[derive(Debug, Clone)]
 pub struct Catalog {
     pub schema: String,
     text_columns: HashMap<String, HashSet<String>>,
+    /// The version of the database server in which `schema` lives, in
+    /// the format of the `server_version_num` setting
+    pub(crate) server_version: i32,
 }
 
 impl Catalog {
     pub fn new(conn: &PgConnection, schema: String) -> Result<Self, StoreError> {
         SqlName::check_valid_identifier(&schema, "database schema")?;
         let text_columns = get_text_columns(conn, &schema)?;
+        let server_version = server_version(conn)?;
         Ok(Catalog {
             schema,
             text_columns,
+            server_version,
         })
     }
 
     /// Make a catalog as if the given `schema` did not exist in the database
     /// yet. This function should only be used in situations where a database
-    /// connection is definitely not available, such as in unit tests
+    /// connection is definitely not available, such as in unit tests. The
+    /// catalog assumes that the database runs the oldest version of
+    /// Postgres that we support
     pub fn make_empty(schema: String) -> Result<Self, StoreError> {
         SqlName::check_valid_identifier(&schema, "database schema")?;
         Ok(Catalog {
             schema,
             text_columns: HashMap::default(),
+            server_version: MIN_SERVER_VERSION,
         })
     }
 
@@ -43,6 +55,31 @@ impl Catalog {
             .map(|cols| cols.contains(column.as_str()))
             .unwrap_or(false)
     }
+
+    /// Return `true` if the database can build indexes on columns that
+    /// hold arrays of enums, which Postgres can do starting with version 11
+    pub fn can_index_enum_arrays(&self) -> bool {
+        self.server_version >= 110000
+    }
+}
+
+/// Return the version of the database server `conn` is connected to in the
+/// format of the `server_version_num` setting, e.g., `110009` for 11.9.
+/// Different databases may run different versions, for example while they
+/// are being upgraded one after the other, and anything that depends on
+/// the version must therefore ask the database it is about to use
+pub fn server_version(conn: &PgConnection) -> Result<i32, StoreError> {
+    #[derive(QueryableByName)]
+    struct Version {
+        #[sql_type = "Integer"]
+        version: i32,
+    }
+
+    Ok(
+        diesel::sql_query("select current_setting('server_version_num')::int as version")
+            .get_result::<Version>(conn)?
+            .version,
+    )
 }
 
 fn get_text_columns(
@@ -1,21 +1,170 @@
 use diesel::pg::PgConnection;
-use diesel::r2d2::{self, event as e, ConnectionManager, HandleEvent, Pool};
+use diesel::r2d2::{
+    self, event as e, Builder, ConnectionManager, HandleEvent, Pool, PooledConnection, State,
+};
 
 use graph::prelude::*;
 use graph::util::security::SafeDisplay;
 
 use std::fmt;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, sync::RwLock};
 
+use crate::catalog::{self, MIN_SERVER_VERSION};
+use crate::statement_cache::StatementCache;
+
+type PgPool = Pool<ConnectionManager<PgConnection>>;
+
+/// A pool of connections to one database. All clones of a pool share the
+/// same connections, and `resize` changes the size for all of them
 #[derive(Clone)]
 pub struct ConnectionPool {
-    pool: Pool<ConnectionManager<PgConnection>>,
+    pool: Arc<RwLock<PgPool>>,
+    /// Sets up a pool with the given size the same way as the first one
+    builder: Arc<dyn Fn(u32) -> Builder<ConnectionManager<PgConnection>> + Send + Sync>,
     pub(crate) wait_stats: PoolWaitStats,
+    /// The error from the last attempt to open a connection, unless a
+    /// connection was opened after it
+    connection_error: Arc<Mutex<Option<String>>>,
+    cancelers: Arc<Cancelers>,
+    postgres_url: String,
+    logger: Logger,
+    registry: Arc<dyn MetricsRegistry>,
+}
+
+/// How often `close` checks whether connections are still in use
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often we check whether the queries that statements belong to have
+/// been canceled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The process id of the Postgres backend serving a pooled connection. It
+/// is kept with the connection so that we only look it up once
+#[derive(Clone, Copy)]
+pub(crate) struct BackendPid(pub i32);
+
+/// Look up the process id of the Postgres backend serving `conn`
+pub(crate) fn backend_pid(conn: &PgConnection) -> Result<i32, diesel::result::Error> {
+    #[derive(QueryableByName)]
+    struct BackendPid {
+        #[sql_type = "diesel::sql_types::Integer"]
+        pid: i32,
+    }
+
+    diesel::sql_query("select pg_backend_pid() as pid")
+        .get_result::<BackendPid>(conn)
+        .map(|row| row.pid)
+}
+
+/// The statements that are running for queries that can be canceled, and
+/// the connection we cancel them with. All clones of a pool share them,
+/// and a background thread cancels the statements of canceled queries
+struct Cancelers {
+    statements: Mutex<HashMap<u64, (CancelHandle, i32)>>,
+    next_id: AtomicU64,
+    /// Connected outside of the pool: waiting for a pooled connection
+    /// could take forever when the pool is exhausted
+    conn: Mutex<Option<postgres::Connection>>,
+    postgres_url: String,
+    logger: Logger,
+}
+
+impl Cancelers {
+    fn new(postgres_url: String, logger: Logger) -> Arc<Self> {
+        let cancelers = Arc::new(Cancelers {
+            statements: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            conn: Mutex::new(None),
+            postgres_url,
+            logger,
+        });
+
+        // The thread stops once the pool is gone
+        let weak: Weak<Cancelers> = Arc::downgrade(&cancelers);
+        std::thread::Builder::new()
+            .name("statement-canceler".to_owned())
+            .spawn(move || loop {
+                std::thread::sleep(CANCEL_POLL_INTERVAL);
+                match weak.upgrade() {
+                    Some(cancelers) => cancelers.cancel_canceled(),
+                    None => break,
+                }
+            })
+            .expect("failed to start the statement canceler");
+        cancelers
+    }
+
+    fn cancel_canceled(&self) {
+        let canceled = |(cancel, _): &(CancelHandle, i32)| cancel.is_canceled();
+        if !self.statements.lock().unwrap().values().any(canceled) {
+            return;
+        }
+
+        // Connect before we lock the statements so that dropping a
+        // `CancelableStatement` only ever waits for the cancelation itself
+        let mut conn = self.conn.lock().unwrap();
+        if conn.is_none() {
+            let url = self.postgres_url.as_str();
+            match postgres::Connection::connect(url, postgres::TlsMode::None) {
+                Ok(new_conn) => *conn = Some(new_conn),
+                Err(e) => {
+                    warn!(self.logger, "Failed to connect to cancel database queries";
+                          "error" => e.to_string());
+                    return;
+                }
+            }
+        }
+
+        // Holding the lock makes dropping a `CancelableStatement` wait for
+        // us, so that we never cancel a backend that serves somebody else
+        let mut statements = self.statements.lock().unwrap();
+        let ids: Vec<_> = statements
+            .iter()
+            .filter(|(_, statement)| canceled(*statement))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            let pid = statements[&id].1;
+            // Unwrap: we made sure there is a connection above
+            match conn
+                .as_ref()
+                .unwrap()
+                .execute("select pg_cancel_backend($1)", &[&pid])
+            {
+                Ok(_) => {
+                    debug!(self.logger, "Canceled database query"; "pid" => pid);
+                    statements.remove(&id);
+                }
+                Err(e) => {
+                    // Try again with a new connection the next time
+                    warn!(self.logger, "Failed to cancel database query";
+                          "pid" => pid, "error" => e.to_string());
+                    *conn = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// A statement that gets canceled when the query it belongs to is. Once
+/// this is dropped, the connection running it is left alone so that it can
+/// safely go back to the pool
+pub(crate) struct CancelableStatement {
+    id: u64,
+    cancelers: Arc<Cancelers>,
 }
 
-struct ErrorHandler(Logger, Counter);
+impl Drop for CancelableStatement {
+    fn drop(&mut self) {
+        self.cancelers.statements.lock().unwrap().remove(&self.id);
+    }
+}
+
+struct ErrorHandler(Logger, Counter, Arc<Mutex<Option<String>>>);
 
 impl Debug for ErrorHandler {
     fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
@@ -26,6 +175,7 @@ impl Debug for ErrorHandler {
 impl r2d2::HandleError<r2d2::Error> for ErrorHandler {
     fn handle_error(&self, error: r2d2::Error) {
         self.1.inc();
+        *self.2.lock().unwrap() = Some(error.to_string());
         error!(self.0, "Postgres connection error"; "error" => error.to_string());
     }
 }
@@ -35,6 +185,7 @@ struct EventHandler {
     count_gauge: Gauge,
     wait_gauge: Gauge,
     wait_stats: PoolWaitStats,
+    connection_error: Arc<Mutex<Option<String>>>,
 }
 
 impl EventHandler {
@@ -42,6 +193,7 @@ impl EventHandler {
         logger: Logger,
         registry: Arc<dyn MetricsRegistry>,
         wait_stats: PoolWaitStats,
+        connection_error: Arc<Mutex<Option<String>>>,
         const_labels: HashMap<String, String>,
     ) -> Self {
         let count_gauge = registry
@@ -63,6 +215,7 @@ impl EventHandler {
             count_gauge,
             wait_gauge,
             wait_stats,
+            connection_error,
         }
     }
 
@@ -84,7 +237,9 @@ impl Debug for EventHandler {
 }
 
 impl HandleEvent for EventHandler {
-    fn handle_acquire(&self, _: e::AcquireEvent) {}
+    fn handle_acquire(&self, _: e::AcquireEvent) {
+        *self.connection_error.lock().unwrap() = None;
+    }
     fn handle_release(&self, _: e::ReleaseEvent) {}
     fn handle_checkout(&self, event: e::CheckoutEvent) {
         self.count_gauge.inc();
@@ -100,15 +255,135 @@ impl HandleEvent for EventHandler {
     }
 }
 
-impl std::ops::Deref for ConnectionPool {
-    type Target = Pool<ConnectionManager<PgConnection>>;
+impl ConnectionPool {
+    /// The pool we currently use. We never hold the lock while waiting for
+    /// a connection since that can take a long time
+    fn current(&self) -> PgPool {
+        self.pool.read().unwrap().clone()
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.pool
+    pub fn get(
+        &self,
+    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, r2d2::PoolError> {
+        self.current().get().map(|conn| self.checked_out(conn))
+    }
+
+    pub fn get_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, r2d2::PoolError> {
+        self.current()
+            .get_timeout(timeout)
+            .map(|conn| self.checked_out(conn))
+    }
+
+    /// Set up the state we keep with each connection when it is checked out
+    /// for the first time
+    fn checked_out(
+        &self,
+        conn: PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> PooledConnection<ConnectionManager<PgConnection>> {
+        let mut conn = Self::remember_backend_pid(conn);
+        StatementCache::attach(&mut conn, &self.registry);
+        conn
+    }
+
+    /// Look up the backend pid of connections the first time they are
+    /// checked out so that canceling statements does not need to
+    fn remember_backend_pid(
+        mut conn: PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> PooledConnection<ConnectionManager<PgConnection>> {
+        if PooledConnection::extensions(&conn)
+            .get::<BackendPid>()
+            .is_none()
+        {
+            // If this fails, `Connection::backend_pid` tries again
+            if let Ok(pid) = backend_pid(&conn) {
+                PooledConnection::extensions_mut(&mut conn).insert(BackendPid(pid));
+            }
+        }
+        conn
+    }
+
+    /// Cancel the statement that the backend `pid` is running when `cancel`
+    /// is canceled, until the returned `CancelableStatement` is dropped
+    pub(crate) fn cancelable(&self, pid: i32, cancel: &CancelHandle) -> CancelableStatement {
+        let id = self.cancelers.next_id.fetch_add(1, Ordering::SeqCst);
+        self.cancelers
+            .statements
+            .lock()
+            .unwrap()
+            .insert(id, (cancel.clone(), pid));
+        CancelableStatement {
+            id,
+            cancelers: self.cancelers.clone(),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.current().state()
+    }
+
+    pub fn max_size(&self) -> u32 {
+        self.current().max_size()
+    }
+
+    /// The error from the last attempt to open a connection to the
+    /// database, unless a connection has been opened since
+    pub fn connection_error(&self) -> Option<String> {
+        self.connection_error.lock().unwrap().clone()
+    }
+
+    /// Change the number of connections in the pool to `pool_size`. We
+    /// start a new pool with that size that opens its connections in the
+    /// background. The old pool closes its idle connections right away and
+    /// the ones that are in use once they are returned, so that the node
+    /// briefly uses more connections than either size while it switches
+    /// over. `close` only waits for connections from the new pool
+    pub fn resize(&self, pool_size: u32) {
+        let mut pool = self.pool.write().unwrap();
+        if pool.max_size() == pool_size {
+            return;
+        }
+        info!(self.logger, "Resizing connection pool";
+              "from" => pool.max_size(), "to" => pool_size);
+        *pool = (self.builder)(pool_size)
+            .build_unchecked(ConnectionManager::new(self.postgres_url.clone()));
+    }
+
+    /// Open a connection outside of the pool with the `postgres` crate,
+    /// for the few things that Diesel can not do, like `copy`
+    pub(crate) fn raw_connection(&self) -> Result<postgres::Connection, StoreError> {
+        postgres::Connection::connect(self.postgres_url.as_str(), postgres::TlsMode::None)
+            .map_err(|e| StoreError::Unknown(e.into()))
+    }
+
+    /// Wait until all connections have been returned to the pool, but not
+    /// past `deadline`, and close them. Returns whether all connections
+    /// were idle. We only watch the pool rather than check connections out,
+    /// since that would open new connections for a pool that is not full
+    /// yet. The pool is replaced with one that only opens connections when
+    /// they are needed; the old one closes its idle connections right away
+    /// and the ones that are still in use once they are returned
+    pub fn close(&self, deadline: Instant) -> bool {
+        let idle = loop {
+            let state = self.state();
+            if state.idle_connections == state.connections {
+                break true;
+            }
+            if Instant::now() >= deadline {
+                break false;
+            }
+            std::thread::sleep(CLOSE_POLL_INTERVAL);
+        };
+
+        let mut pool = self.pool.write().unwrap();
+        *pool = (self.builder)(pool.max_size())
+            .min_idle(Some(0))
+            .build_unchecked(ConnectionManager::new(self.postgres_url.clone()));
+        idle
     }
-}
 
-impl ConnectionPool {
     pub fn create(
         pool_name: &str,
         postgres_url: String,
@@ -130,17 +405,10 @@ impl ConnectionPool {
                 HashMap::new(),
             )
             .expect("failed to create `store_connection_error_count` counter");
-        let error_handler = Box::new(ErrorHandler(logger_pool.clone(), error_counter));
         let wait_stats = Arc::new(RwLock::new(MovingStats::default()));
-        let event_handler = Box::new(EventHandler::new(
-            logger_pool.clone(),
-            registry,
-            wait_stats.clone(),
-            const_labels,
-        ));
+        let connection_error = Arc::new(Mutex::new(None));
+        let pool_registry = registry.clone();
 
-        // Connect to Postgres
-        let conn_manager = ConnectionManager::new(postgres_url.clone());
         // Set the time we wait for a connection to 6h. The default is 30s
         // which can be too little if database connections are highly
         // contended; if we don't get a connection within the timeout,
@@ -154,19 +422,91 @@ impl ConnectionPool {
         // to be hanging but really there is just no connection to postgres
         // available.
         let timeout_seconds = if cfg!(test) { 30 } else { 6 * 60 * 60 };
-        let pool = Pool::builder()
-            .error_handler(error_handler)
-            .event_handler(event_handler)
-            .connection_timeout(Duration::from_secs(timeout_seconds))
-            .max_size(pool_size)
-            .build(conn_manager)
-            .unwrap();
+        let builder = {
+            let logger_pool = logger_pool.clone();
+            let wait_stats = wait_stats.clone();
+            let connection_error = connection_error.clone();
+            move |pool_size| {
+                let error_handler = Box::new(ErrorHandler(
+                    logger_pool.clone(),
+                    error_counter.clone(),
+                    connection_error.clone(),
+                ));
+                // The metrics are registered globally, and all the pools
+                // we create for this database report to the same ones
+                let event_handler = Box::new(EventHandler::new(
+                    logger_pool.clone(),
+                    registry.clone(),
+                    wait_stats.clone(),
+                    connection_error.clone(),
+                    const_labels.clone(),
+                ));
+                Pool::builder()
+                    .error_handler(error_handler)
+                    .event_handler(event_handler)
+                    .connection_timeout(Duration::from_secs(timeout_seconds))
+                    .max_size(pool_size)
+            }
+        };
+
+        // Connect to Postgres
+        let conn_manager = ConnectionManager::new(postgres_url.clone());
+        let pool = builder(pool_size).build(conn_manager).unwrap();
+
+        // Each database reports its own version since the main database
+        // and the replicas do not have to run the same version, e.g., while
+        // they are upgraded one at a time
+        let server_version = pool
+            .get()
+            .map_err(|e| StoreError::from(Error::from(e)))
+            .and_then(|conn| catalog::server_version(&conn))
+            .unwrap_or_else(|e| panic!("failed to determine the Postgres version: {}", e));
+        if server_version < MIN_SERVER_VERSION {
+            panic!(
+                "the database for pool `{}` runs Postgres {} but at least {} is needed",
+                pool_name,
+                format_version(server_version),
+                format_version(MIN_SERVER_VERSION)
+            );
+        }
         info!(
             logger_store,
             "Connected to Postgres";
             "pool_name" => pool_name,
-            "url" => SafeDisplay(postgres_url.as_str())
+            "url" => SafeDisplay(postgres_url.as_str()),
+            "server_version" => format_version(server_version)
         );
-        ConnectionPool { pool, wait_stats }
+        ConnectionPool {
+            pool: Arc::new(RwLock::new(pool)),
+            builder: Arc::new(builder),
+            wait_stats,
+            connection_error,
+            cancelers: Cancelers::new(postgres_url.clone(), logger_pool.clone()),
+            postgres_url,
+            logger: logger_pool,
+            registry: pool_registry,
+        }
     }
 }
+
+/// Format a `server_version_num` like `110009` as `11.9`, and one from
+/// before Postgres 10 like `90624` as `9.6.24`
+fn format_version(version: i32) -> String {
+    if version >= 100000 {
+        format!("{}.{}", version / 10000, version % 10000)
+    } else {
+        format!(
+            "{}.{}.{}",
+            version / 10000,
+            version / 100 % 100,
+            version % 100
+        )
+    }
+}
+
+#[test]
+fn format_server_version() {
+    assert_eq!("11.9", format_version(110009));
+    assert_eq!("13.0", format_version(130000));
+    assert_eq!("9.6.24", format_version(90624));
+}
@@ -0,0 +1,101 @@
+//! Look up ENS names in the `ens_names` rainbow table. The table can live
+//! in the main database, or in a separate database that several
+//! installations share
+use diesel::connection::SimpleConnection;
+use diesel::dsl::insert_into;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+
+use graph::components::ens::EnsLookup;
+use graph::prelude::{format_err, tiny_keccak::keccak256, StoreError};
+
+use crate::connection_pool::ConnectionPool;
+use crate::db_schema::ens_names;
+
+/// How many names we insert with one statement when importing names
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+pub struct EnsNames {
+    pool: ConnectionPool,
+}
+
+impl EnsNames {
+    /// Look names up in the `ens_names` table of the database that `pool`
+    /// connects to
+    pub fn new(pool: ConnectionPool) -> Self {
+        EnsNames { pool }
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>, StoreError> {
+        self.pool.get().map_err(|e| StoreError::Unknown(e.into()))
+    }
+
+    /// Add `names` to the rainbow table, creating the table if it does not
+    /// exist yet. Names that are already known are skipped. Return how many
+    /// names were added
+    pub fn import(&self, names: &[String]) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            conn.batch_execute(
+                "create table if not exists public.ens_names(
+                   hash varchar primary key,
+                   name varchar not null
+                 )",
+            )?;
+
+            let mut count = 0;
+            for chunk in names.chunks(IMPORT_BATCH_SIZE) {
+                let rows: Vec<_> = chunk
+                    .iter()
+                    .map(|name| {
+                        (
+                            ens_names::hash.eq(Self::hash(name)),
+                            ens_names::name.eq(name),
+                        )
+                    })
+                    .collect();
+                count += insert_into(ens_names::table)
+                    .values(&rows)
+                    .on_conflict_do_nothing()
+                    .execute(&conn)?;
+            }
+            Ok(count)
+        })
+    }
+
+    /// The hash under which `name` is stored; this is what the
+    /// `ens.nameByHash` host function gets passed
+    fn hash(name: &str) -> String {
+        format!("0x{}", hex::encode(keccak256(name.as_bytes())))
+    }
+}
+
+impl EnsLookup for EnsNames {
+    fn find_name(&self, hash: &str) -> Result<Option<String>, StoreError> {
+        let conn = self.get_conn()?;
+        ens_names::table
+            .select(ens_names::name)
+            .find(hash)
+            .get_result::<String>(&conn)
+            .optional()
+            .map_err(|e| {
+                StoreError::Unknown(format_err!(
+                    "error looking up ens_name for hash {}: {}",
+                    hash,
+                    e
+                ))
+            })
+    }
+}
+
+#[test]
+fn hashes_names_with_keccak() {
+    assert_eq!(
+        "0x4f5b812789fc606be1b3b16908db13fc7a9adf7ca72641f84d75b47069d3d7f0",
+        EnsNames::hash("eth")
+    );
+    assert_eq!(
+        "0x7f0c1b04d1a4926f9c635a030eeb611d4c26e5e73291b32a1c7a4ac56935b5b3",
+        EnsNames::hash("dealdrafts")
+    );
+}
@@ -22,32 +22,38 @@
 use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
-use diesel::sql_types::{Integer, Text};
+use diesel::sql_types::{Binary, Bool, Integer, Text};
 use diesel::Connection as _;
 use diesel::ExpressionMethods;
 use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
 use lazy_static::lazy_static;
 use maybe_owned::MaybeOwned;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryInto;
 use std::hash::{Hash, Hasher};
 use std::ops::Deref as _;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::data::schema::Schema as SubgraphSchema;
 use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE, SUBGRAPHS_ID};
 use graph::prelude::{
-    debug, format_err, info, serde_json, warn, BlockNumber, Entity, EntityCollection, EntityFilter,
-    EntityKey, EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger, QueryExecutionError,
-    StoreError, StoreEvent, SubgraphDeploymentId, BLOCK_NUMBER_MAX,
+    debug, format_err, info, serde_json, warn, BlockNumber, Entity, EntityChange,
+    EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityRange,
+    EntityTypeCount, EntityVersion, Error, EthereumBlockPointer, Logger, QueryExecutionError,
+    StoreError, StoreEvent, SubgraphDeploymentId, SubgraphName, BLOCK_NUMBER_MAX,
 };
 
-use crate::block_range::block_number;
+use crate::advisory_lock::MOVE_LOCK;
+use crate::block_range::{block_number, BLOCK_RANGE_COLUMN};
+use crate::connection_pool::{self, BackendPid};
+use crate::jobs::BLOCK_POI_HISTORY;
 use crate::metadata;
 use crate::notification_listener::JsonNotification;
-use crate::relational::{Catalog, Layout};
+use crate::relational::{Catalog, Layout, SqlName, Table};
+use crate::statement_cache::StatementCache;
 
 #[cfg(debug_assertions)]
 lazy_static! {
@@ -55,8 +61,14 @@ lazy_static! {
 /// of each event sent in `EVENT_TAP`
 pub static ref EVENT_TAP_ENABLED: Mutex<bool> = Mutex::new(false);
     pub static ref EVENT_TAP: Mutex<Vec<StoreEvent>> = Mutex::new(Vec::new());
+    /// Tests set this to run code while a deployment is being moved, after
+    /// its tables were copied but before the copy takes their place
+    pub static ref MOVE_COPY_HOOK: Mutex<Option<MoveCopyHook>> = Mutex::new(None);
 }
 
+#[cfg(debug_assertions)]
+pub type MoveCopyHook = Box<dyn FnMut() -> Result<(), StoreError> + Send>;
+
 /// The size of string prefixes that we index. This is chosen so that we
 /// will index strings that people will do string comparisons like
 /// `=` or `!=` on; if text longer than this is stored in a String attribute
@@ -246,6 +258,13 @@ impl Connection<'_> {
                 if let Some((base, block)) = graft {
                     let base = &Connection::layout(&self.conn, &base)?;
                     layout.copy_from(logger, &self.conn, &base, block, &self.metadata)?;
+                    copy_block_proofs_of_indexing(
+                        &self.conn,
+                        logger,
+                        layout,
+                        &base.subgraph,
+                        &block,
+                    )?;
                 }
                 diesel::update(dsl::table)
                     .set(dsl::state.eq(State::Ready))
@@ -292,6 +311,24 @@ impl Connection<'_> {
         self.storage.find_many(&self.conn, ids_for_type, block)
     }
 
+    pub(crate) fn find_changes(
+        &self,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        self.storage.find_changes(&self.conn, entity_types, block)
+    }
+
+    pub(crate) fn find_change_blocks(
+        &self,
+        entity_types: &[String],
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError> {
+        self.storage
+            .find_change_blocks(&self.conn, entity_types, from, to)
+    }
+
     pub(crate) fn query<T: crate::relational_queries::FromEntityData>(
         &self,
         logger: &Logger,
@@ -303,10 +340,40 @@ impl Connection<'_> {
         query_id: Option<String>,
     ) -> Result<Vec<T>, QueryExecutionError> {
         self.storage.query(
-            logger, &self.conn, collection, filter, order, range, block, query_id,
+            logger,
+            &self.conn,
+            collection,
+            filter,
+            order,
+            range,
+            block,
+            query_id,
+            StatementCache::of(&self.conn),
         )
     }
 
+    /// Make Postgres abort statements that take longer than `timeout`
+    /// until the end of the current transaction
+    pub(crate) fn set_statement_timeout(&self, timeout: Duration) -> Result<(), StoreError> {
+        // A timeout of 0 turns the timeout off
+        let millis = timeout.as_millis().max(1);
+        self.conn
+            .batch_execute(&format!("set local statement_timeout = {}", millis))?;
+        Ok(())
+    }
+
+    /// The process id of the Postgres backend serving this connection. The
+    /// pool remembers it when the connection is first checked out
+    pub(crate) fn backend_pid(&self) -> Result<i32, StoreError> {
+        match PooledConnection::extensions(&*self.conn).get::<BackendPid>() {
+            Some(BackendPid(pid)) => Ok(*pid),
+            None => {
+                let conn: &PgConnection = &self.conn;
+                Ok(connection_pool::backend_pid(conn)?)
+            }
+        }
+    }
+
     pub(crate) fn conflicting_entity(
         &self,
         entity_id: &String,
@@ -387,7 +454,15 @@ impl Connection<'_> {
             .number
             .try_into()
             .expect("block numbers fit into an i32");
+        self.revert_blocks_from(block)
+    }
 
+    /// Revert all changes that were made at `block` or any later block.
+    /// The storage scheme makes that no harder than reverting one block
+    pub(crate) fn revert_blocks_from(
+        &self,
+        block: BlockNumber,
+    ) -> Result<(StoreEvent, i32), StoreError> {
         // Revert the block in the subgraph itself
         let (event, count) = self.storage.revert_block(&self.conn, block)?;
         // Revert the meta data changes that correspond to this subgraph.
@@ -401,6 +476,20 @@ impl Connection<'_> {
         Ok((event.extend(meta_event), count))
     }
 
+    pub(crate) fn entity_counts(&self) -> Result<Vec<EntityTypeCount>, StoreError> {
+        self.storage.entity_counts(&self.conn)
+    }
+
+    pub(crate) fn entity_history(
+        &self,
+        entity_type: &str,
+        entity_id: &str,
+        range: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError> {
+        self.storage
+            .entity_history(&self.conn, entity_type, entity_id, range)
+    }
+
     pub(crate) fn update_entity_count(&self, count: i32) -> Result<(), StoreError> {
         if count == 0 {
             return Ok(());
@@ -592,14 +681,7 @@ impl Connection<'_> {
     }
 
     pub(crate) fn send_store_event(&self, event: &StoreEvent) -> Result<(), StoreError> {
-        let v = serde_json::to_value(event)?;
-        #[cfg(debug_assertions)]
-        {
-            if *EVENT_TAP_ENABLED.lock().unwrap() {
-                EVENT_TAP.lock().unwrap().push(event.clone());
-            }
-        }
-        JsonNotification::send("store_events", &v, &self.conn)
+        send_store_event(&self.conn, event)
     }
 
     pub(crate) fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
@@ -683,7 +765,7 @@ impl Connection<'_> {
         use public::DeploymentSchemaVersion as V;
 
         let schema = find_schema(conn, subgraph)?
-            .ok_or_else(|| StoreError::Unknown(format_err!("unknown subgraph {}", subgraph)))?;
+            .ok_or_else(|| StoreError::DeploymentNotFound(subgraph.to_string()))?;
         let layout = match schema.version {
             V::Split => {
                 return Err(StoreError::ConstraintViolation(format!(
@@ -702,6 +784,100 @@ impl Connection<'_> {
     }
 }
 
+/// Record proofs of indexing in the grafted deployment `layout` for the
+/// blocks up to `block` that `base` recorded them for. Proofs of indexing
+/// depend on the deployment, and we therefore compute them again from the
+/// digests of the causality regions that were copied from `base`
+fn copy_block_proofs_of_indexing(
+    conn: &PgConnection,
+    logger: &Logger,
+    layout: &Layout,
+    base: &SubgraphDeploymentId,
+    block: &EthereumBlockPointer,
+) -> Result<(), StoreError> {
+    /// How many proofs of indexing we compute and insert at once
+    const BATCH_SIZE: BlockNumber = 10_000;
+
+    #[derive(QueryableByName)]
+    struct Digest {
+        #[sql_type = "Text"]
+        id: String,
+        #[sql_type = "Binary"]
+        digest: Vec<u8>,
+        #[sql_type = "Integer"]
+        start: BlockNumber,
+        #[sql_type = "Integer"]
+        end: BlockNumber,
+    }
+
+    let table = match layout.tables.get(POI_OBJECT) {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+
+    let start = Instant::now();
+    let last: BlockNumber = block
+        .number
+        .try_into()
+        .expect("block numbers fit into an i32");
+    let first = BLOCK_POI_HISTORY
+        .map(|history| (last - history).max(0))
+        .unwrap_or(0);
+
+    // All versions of the digests of the causality regions, in the order
+    // in which they were written
+    let query = format!(
+        "select id, digest, lower({range}) as start,
+                coalesce(upper({range}), {max}) as \"end\"
+           from {table}
+          order by lower({range})",
+        range = BLOCK_RANGE_COLUMN,
+        max = BLOCK_NUMBER_MAX,
+        table = table.qualified_name
+    );
+    let mut versions = diesel::sql_query(query)
+        .load::<Digest>(conn)?
+        .into_iter()
+        .peekable();
+
+    // The digest of each causality region as of the block we are looking
+    // at, together with the block at which that digest was replaced
+    let mut digests: BTreeMap<String, (BlockNumber, Vec<u8>)> = BTreeMap::new();
+    let mut count = 0;
+    let mut batch_start = first;
+    while batch_start <= last {
+        let batch_end = (batch_start + BATCH_SIZE - 1).min(last);
+        let mut pois = metadata::block_proofs_of_indexing(conn, base, batch_start, batch_end)?;
+        for poi in pois.iter_mut() {
+            let number = poi.block.number as BlockNumber;
+            while versions
+                .peek()
+                .map(|version| version.start <= number)
+                .unwrap_or(false)
+            {
+                let version = versions.next().unwrap();
+                digests.insert(version.id, (version.end, version.digest));
+            }
+
+            let mut finisher = ProofOfIndexingFinisher::new(&poi.block, &layout.subgraph, &None);
+            for (region, (end, digest)) in &digests {
+                if number < *end {
+                    finisher.add_causality_region(region, digest);
+                }
+            }
+            poi.digest = finisher.finish();
+        }
+        if !pois.is_empty() {
+            metadata::insert_block_proofs_of_indexing(conn, &layout.subgraph, &pois)?;
+        }
+        count += pois.len();
+        batch_start = batch_end + 1;
+    }
+    info!(logger, "Copied {} proofs of indexing", count;
+          "time_ms" => start.elapsed().as_millis());
+    Ok(())
+}
+
 // Find the database schema for `subgraph`. If no explicit schema exists,
 // return `None`.
 fn find_schema(
@@ -773,16 +949,473 @@ pub fn delete_all_entities_for_test_use_only(
         delete from subgraphs.ethereum_contract_data_source_template;
         delete from subgraphs.ethereum_contract_data_source_template_source;
         delete from subgraphs.ethereum_contract_event_handler;
+        delete from subgraphs.job_run;
+        delete from subgraphs.unused_deployment;
     ";
     conn.batch_execute(query)?;
     store.clear_storage_cache();
     Ok(())
 }
 
+/// The entity type of the store events that tell all nodes that the schema
+/// of a deployment was renamed, and that they have to forget any layout
+/// for it that they cached. The id of the change is the deployment
+pub(crate) const DEPLOYMENT_SCHEMA_RENAMED: &str = "DeploymentSchemaRenamed";
+
+/// Move all tables and indexes of the deployment `subgraph` into
+/// `tablespace` and/or into a new schema `new_name`. Tables stay in their
+/// tablespace if `tablespace` is `None`, and the schema keeps its name if
+/// `new_name` is `None`. The deployment keeps indexing and can be queried
+/// while its data is copied; writes are only blocked while the changes
+/// that were made during the copy are applied and the copy replaces the
+/// original. A schema that keeps its name keeps layouts that other nodes
+/// cached valid; for a renamed schema, the record in `deployment_schemas`
+/// is updated and a store event tells other nodes to forget their layouts
+pub(crate) fn move_schema(
+    conn: &PgConnection,
+    logger: &Logger,
+    subgraph: &SubgraphDeploymentId,
+    tablespace: Option<&str>,
+    new_name: Option<&str>,
+) -> Result<(), StoreError> {
+    use public::DeploymentSchemaState as State;
+
+    #[derive(QueryableByName)]
+    struct Flag {
+        #[sql_type = "Bool"]
+        flag: bool,
+    }
+
+    if subgraph.is_meta() {
+        return Err(StoreError::Unknown(format_err!(
+            "the metadata subgraph can not be moved"
+        )));
+    }
+    if tablespace.is_none() && new_name.is_none() {
+        return Err(StoreError::Unknown(format_err!(
+            "moving deployment {} needs a tablespace or a new schema name",
+            subgraph
+        )));
+    }
+    if let Some(tablespace) = tablespace {
+        SqlName::check_valid_identifier(tablespace, "tablespace")?;
+        let tablespace_exists = diesel::sql_query(
+            "select exists (select 1 from pg_tablespace where spcname = $1) as flag",
+        )
+        .bind::<Text, _>(tablespace)
+        .get_result::<Flag>(conn)?
+        .flag;
+        if !tablespace_exists {
+            return Err(StoreError::Unknown(format_err!(
+                "there is no tablespace `{}`",
+                tablespace
+            )));
+        }
+    }
+    if let Some(new_name) = new_name {
+        SqlName::check_valid_identifier(new_name, "schema")?;
+        let schema_exists = diesel::sql_query(
+            "select exists (select 1 from pg_namespace where nspname = $1) as flag",
+        )
+        .bind::<Text, _>(new_name)
+        .get_result::<Flag>(conn)?
+        .flag;
+        if schema_exists {
+            return Err(StoreError::Unknown(format_err!(
+                "there already is a schema `{}`",
+                new_name
+            )));
+        }
+    }
+
+    let schema = find_schema(conn, subgraph)?
+        .ok_or_else(|| StoreError::DeploymentNotFound(subgraph.to_string()))?;
+    match schema.state {
+        State::Ready => (),
+        State::Init | State::Tables => {
+            return Err(StoreError::Unknown(format_err!(
+                "deployment {} can not be moved until it has been initialized",
+                subgraph
+            )))
+        }
+    }
+
+    let locked = diesel::sql_query("select pg_try_advisory_lock($1, $2) as flag")
+        .bind::<Integer, _>(MOVE_LOCK)
+        .bind::<Integer, _>(schema.id)
+        .get_result::<Flag>(conn)?
+        .flag;
+    if !locked {
+        return Err(StoreError::Unknown(format_err!(
+            "deployment {} is already being moved",
+            subgraph
+        )));
+    }
+    let result = move_locked_schema(conn, logger, subgraph, &schema.name, tablespace, new_name);
+    diesel::sql_query("select pg_advisory_unlock($1, $2)")
+        .bind::<Integer, _>(MOVE_LOCK)
+        .bind::<Integer, _>(schema.id)
+        .execute(conn)?;
+    result
+}
+
+/// The columns of `table` that need to be copied when the table is moved
+fn move_columns(table: &Table) -> String {
+    let mut columns = vec!["vid".to_owned(), BLOCK_RANGE_COLUMN.to_owned()];
+    columns.extend(table.columns.iter().map(|column| column.name.quoted()));
+    columns.join(", ")
+}
+
+/// The tables of `layout` ordered by their name. A move always locks
+/// tables in that order, rather than in the arbitrary order of
+/// `Layout.tables`, so that it does not deadlock with anything else that
+/// locks several of them in a fixed order
+fn sorted_tables(layout: &Layout) -> Vec<&Table> {
+    let mut tables: Vec<_> = layout.tables.values().map(|table| table.as_ref()).collect();
+    tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+    tables
+}
+
+/// The tablespace that the tables in `schema` are in, or `None` if they
+/// are in the default tablespace of the database
+fn schema_tablespace(conn: &PgConnection, schema: &str) -> Result<Option<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Tablespace {
+        #[sql_type = "Text"]
+        spcname: String,
+    }
+
+    Ok(diesel::sql_query(
+        "select t.spcname
+           from pg_class c, pg_namespace n, pg_tablespace t
+          where c.relnamespace = n.oid
+            and c.reltablespace = t.oid
+            and n.nspname = $1
+            and c.relkind = 'r'
+          limit 1",
+    )
+    .bind::<Text, _>(schema)
+    .get_result::<Tablespace>(conn)
+    .optional()?
+    .map(|tablespace| tablespace.spcname))
+}
+
+fn move_locked_schema(
+    conn: &PgConnection,
+    logger: &Logger,
+    subgraph: &SubgraphDeploymentId,
+    name: &str,
+    tablespace: Option<&str>,
+    new_name: Option<&str>,
+) -> Result<(), StoreError> {
+    let start = Instant::now();
+    let src = Connection::layout(conn, subgraph)?;
+    let tablespace = match tablespace {
+        Some(tablespace) => Some(tablespace.to_owned()),
+        None => schema_tablespace(conn, name)?,
+    };
+    // The copy is built in its own schema. That schema takes the place of
+    // the original schema at the very end, unless the schema is renamed
+    let mut catalog = src.catalog.clone();
+    catalog.schema = match new_name {
+        Some(new_name) => new_name.to_owned(),
+        None => format!("{}_move", name),
+    };
+    let schema = metadata::subgraph_schema(conn, subgraph.clone())?;
+    let dst = Layout::new(&schema, catalog, src.tables.contains_key(POI_OBJECT))?;
+    let ddl = dst
+        .as_ddl()
+        .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
+    let tmp = dst.catalog.schema.as_str();
+
+    // Create the tables for the copy, and record the `vid` of every row
+    // that changes in the original tables from now on. A copy that was
+    // left behind by a move that did not finish is removed first; a new
+    // name was checked to not be in use already
+    conn.transaction(|| -> Result<(), StoreError> {
+        if new_name.is_none() {
+            conn.batch_execute(&format!("drop schema if exists {} cascade", tmp))?;
+        }
+        if let Some(tablespace) = &tablespace {
+            conn.batch_execute(&format!("set local default_tablespace = {}", tablespace))?;
+        }
+        conn.batch_execute(&format!(
+            "create schema {tmp};
+             create table {tmp}.changed_rows(tbl text not null, vid int8 not null);
+             create function {tmp}.record_changed_row() returns trigger as $$
+             begin
+               if tg_op = 'DELETE' then
+                 insert into {tmp}.changed_rows values (tg_table_name, old.vid);
+               else
+                 insert into {tmp}.changed_rows values (tg_table_name, new.vid);
+               end if;
+               return null;
+             end;
+             $$ language plpgsql;",
+            tmp = tmp
+        ))?;
+        conn.batch_execute(&ddl)?;
+        for table in sorted_tables(&src) {
+            conn.batch_execute(&format!(
+                "create trigger record_changed_row
+                   after insert or update or delete on {table}
+                   for each row execute procedure {tmp}.record_changed_row()",
+                table = table.qualified_name.as_str(),
+                tmp = tmp
+            ))?;
+        }
+        Ok(())
+    })?;
+
+    // The triggers that record changes go away together with the copy. If
+    // the move fails from here on, remove the copy so that writes to the
+    // deployment do not keep recording changes that nobody will apply
+    let result = copy_and_swap_schema(conn, logger, subgraph, &src, &dst, name, new_name);
+    if result.is_err() {
+        if let Err(e) = conn.batch_execute(&format!("drop schema if exists {} cascade", tmp)) {
+            warn!(logger, "Failed to remove the copy of a deployment that could not be moved";
+                  "schema" => tmp, "error" => e.to_string());
+        }
+    }
+    result?;
+
+    info!(logger, "Moved deployment {}", subgraph;
+          "schema" => new_name.unwrap_or(name),
+          "tablespace" => tablespace.as_deref().unwrap_or("pg_default"),
+          "time_ms" => start.elapsed().as_millis());
+    Ok(())
+}
+
+/// Copy the tables of `src`, which lives in the schema `name`, into the
+/// tables of `dst`, then apply the changes that happened in the meantime
+/// and put `dst` in place of `src`, under `new_name` if that is given
+fn copy_and_swap_schema(
+    conn: &PgConnection,
+    logger: &Logger,
+    subgraph: &SubgraphDeploymentId,
+    src: &Layout,
+    dst: &Layout,
+    name: &str,
+    new_name: Option<&str>,
+) -> Result<(), StoreError> {
+    let tmp = dst.catalog.schema.as_str();
+
+    // Copy each table in one statement so that the copy of each table is
+    // consistent, which the exclusion constraint on `id` and
+    // `block_range` requires
+    for table in sorted_tables(dst) {
+        let table_start = Instant::now();
+        let columns = move_columns(table);
+        let count = conn.execute(&format!(
+            "insert into {dst}({columns}) select {columns} from {src}",
+            dst = table.qualified_name.as_str(),
+            src = SqlName::qualified_name(name, &table.name).as_str(),
+            columns = columns
+        ))?;
+        info!(logger, "Copied {} rows of {}", count, table.object;
+              "time_ms" => table_start.elapsed().as_millis());
+    }
+
+    #[cfg(debug_assertions)]
+    {
+        if let Some(hook) = MOVE_COPY_HOOK.lock().unwrap().as_mut() {
+            hook()?;
+        }
+    }
+
+    // Block writes, but not queries, while the changes that happened during
+    // the copy are applied to it, and put the copy in place of the original
+    let swap_start = Instant::now();
+    conn.transaction(|| -> Result<(), StoreError> {
+        // Lock the tables one at a time in a fixed order
+        for table in sorted_tables(src) {
+            conn.batch_execute(&format!(
+                "lock table {} in exclusive mode",
+                table.qualified_name.as_str()
+            ))?;
+        }
+        for table in sorted_tables(dst) {
+            let columns = move_columns(table);
+            conn.batch_execute(&format!(
+                "delete from {dst}
+                  where vid in (select vid from {tmp}.changed_rows where tbl = '{name}');
+                 insert into {dst}({columns})
+                 select {columns} from {src}
+                  where vid in (select vid from {tmp}.changed_rows where tbl = '{name}');
+                 select setval(pg_get_serial_sequence('{dst}', 'vid'),
+                               nextval(pg_get_serial_sequence('{src}', 'vid')));",
+                dst = table.qualified_name.as_str(),
+                src = SqlName::qualified_name(name, &table.name).as_str(),
+                tmp = tmp,
+                name = table.name.as_str(),
+                columns = columns
+            ))?;
+        }
+        conn.batch_execute(&format!(
+            "drop schema {name} cascade;
+             drop table {tmp}.changed_rows;
+             drop function {tmp}.record_changed_row();",
+            name = name,
+            tmp = tmp
+        ))?;
+        match new_name {
+            None => conn.batch_execute(&format!("alter schema {} rename to {}", tmp, name))?,
+            Some(new_name) => {
+                use public::deployment_schemas as ds;
+
+                diesel::update(ds::table.filter(ds::subgraph.eq(subgraph.as_str())))
+                    .set(ds::name.eq(new_name))
+                    .execute(conn)?;
+                send_store_event(
+                    conn,
+                    &StoreEvent::new(vec![EntityChange {
+                        subgraph_id: SUBGRAPHS_ID.clone(),
+                        entity_type: DEPLOYMENT_SCHEMA_RENAMED.to_owned(),
+                        entity_id: subgraph.to_string(),
+                        operation: EntityChangeOperation::Set,
+                    }]),
+                )?;
+            }
+        }
+        Ok(())
+    })?;
+    debug!(logger, "Put the copy of {} in place", name;
+           "schema" => tmp,
+           "swap_ms" => swap_start.elapsed().as_millis());
+    Ok(())
+}
+
+/// Send `event` to everybody listening for store events
+pub(crate) fn send_store_event(conn: &PgConnection, event: &StoreEvent) -> Result<(), StoreError> {
+    let v = serde_json::to_value(event)?;
+    #[cfg(debug_assertions)]
+    {
+        if *EVENT_TAP_ENABLED.lock().unwrap() {
+            EVENT_TAP.lock().unwrap().push(event.clone());
+        }
+    }
+    JsonNotification::send("store_events", &v, conn)
+}
+
+/// Remove the deployment `subgraph` with its schema and all its metadata.
+/// Only unused deployments can be removed; see `metadata::remove_deployment`.
+/// Return the changes to the metadata
+pub(crate) fn remove_deployment(
+    conn: &PgConnection,
+    subgraph: &SubgraphDeploymentId,
+) -> Result<Vec<EntityChange>, StoreError> {
+    let changes = metadata::remove_deployment(conn, subgraph)?;
+    drop_schema(conn, subgraph)?;
+    Ok(changes)
+}
+
+/// Check whether a new deployment of the subgraph `name` with `schema` can
+/// adopt the data of the subgraph's current version instead of indexing
+/// from scratch. That is possible if `schema` only adds entity types and
+/// nullable attributes to the schema of the current version, and both
+/// index the same network. Return the layouts of the current version and
+/// of the new deployment if it is possible
+pub(crate) fn adoptable_layouts(
+    conn: &PgConnection,
+    logger: &Logger,
+    name: &SubgraphName,
+    schema: &SubgraphSchema,
+) -> Result<Option<(Layout, Layout)>, StoreError> {
+    use public::DeploymentSchemaState as State;
+
+    if metadata::deployment_graft(conn, &schema.id)?.is_some() {
+        return Ok(None);
+    }
+    let base = match metadata::adoptable_deployment(conn, name)? {
+        Some(base) if base != schema.id => base,
+        _ => return Ok(None),
+    };
+    match find_schema(conn, &base)? {
+        Some(Schema {
+            state: State::Ready,
+            ..
+        }) => (),
+        _ => return Ok(None),
+    }
+    if metadata::subgraph_network(conn, &base)? != metadata::subgraph_network(conn, &schema.id)? {
+        return Ok(None);
+    }
+
+    let base = Connection::layout(conn, &base)?;
+    let has_poi = base.tables.contains_key(POI_OBJECT);
+    let layout = Layout::new(schema, base.catalog.clone(), has_poi)?;
+    let errors = base.can_extend_to(&layout);
+    if !errors.is_empty() {
+        info!(logger, "New deployment can not adopt the data of the current version";
+              "subgraph" => name.as_str(),
+              "current" => base.subgraph.as_str(),
+              "reasons" => errors.join("; "));
+        return Ok(None);
+    }
+    Ok(Some((base, layout)))
+}
+
+/// Change the tables of `base` so that they match `layout` and hand them
+/// over to the deployment for `layout`, which carries on indexing from
+/// where `base` left off. The deployment `base` is removed. Return the
+/// changes to the metadata
+pub(crate) fn adopt_schema(
+    conn: &PgConnection,
+    base: &Layout,
+    layout: &Layout,
+) -> Result<Vec<EntityChange>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Locked {
+        #[sql_type = "Bool"]
+        locked: bool,
+    }
+
+    let schema = find_schema(conn, &base.subgraph)?
+        .ok_or_else(|| StoreError::DeploymentNotFound(base.subgraph.to_string()))?;
+    let locked = diesel::sql_query("select pg_try_advisory_xact_lock($1, $2) as locked")
+        .bind::<Integer, _>(MOVE_LOCK)
+        .bind::<Integer, _>(schema.id)
+        .get_result::<Locked>(conn)?
+        .locked;
+    if !locked {
+        return Err(StoreError::Unknown(format_err!(
+            "the data of deployment {} can not be adopted while it is being moved",
+            base.subgraph
+        )));
+    }
+
+    // Block writes to the tables of `base` before we wait for the block it
+    // might be writing in `adopt_deployment`; otherwise, we would deadlock
+    // with that write when we change the tables
+    let tables = base
+        .tables
+        .values()
+        .map(|table| table.qualified_name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    conn.batch_execute(&format!("lock table {} in access exclusive mode", tables))?;
+
+    let ddl = base
+        .extension_ddl(layout)
+        .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
+    conn.batch_execute(&ddl)?;
+
+    let changes = metadata::adopt_deployment(conn, &base.subgraph, &layout.subgraph)?;
+    diesel::update(deployment_schemas::table.filter(deployment_schemas::id.eq(schema.id)))
+        .set(deployment_schemas::subgraph.eq(layout.subgraph.as_str()))
+        .execute(conn)?;
+
+    // The proof of indexing of `base` was computed with other mappings and
+    // says nothing about the new deployment; start over from here
+    if let Some(table) = base.tables.get(POI_OBJECT) {
+        conn.batch_execute(&format!("delete from {}", table.qualified_name))?;
+    }
+    Ok(changes)
+}
+
 /// Drop the schema for `subgraph`. This deletes all data for the subgraph,
 /// and can not be reversed. It does not remove any of the metadata in
 /// `subgraphs.entities` associated with the subgraph
-#[cfg(debug_assertions)]
 fn drop_schema(
     conn: &diesel::pg::PgConnection,
     subgraph: &SubgraphDeploymentId,
@@ -0,0 +1,261 @@
+//! Export the entities of a deployment as of one block into CSV files, one
+//! per entity type, and import such an export into another deployment.
+//! Postgres' `copy` reads and writes the files, so that every attribute is
+//! in the text format of its column and can be imported without loss
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use graph::prelude::{
+    format_err, serde_json, web3::types::H256, BlockNumber, Deserialize, EthereumBlockPointer,
+    Serialize, StoreError,
+};
+use postgres::Connection;
+
+use crate::relational::{Layout, Table};
+
+/// The name of the file that describes an export
+pub const EXPORT_FILE: &str = "export.json";
+
+/// The description of an export, stored in `EXPORT_FILE`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Export {
+    /// The deployment the entities were exported from
+    pub deployment: String,
+    /// The block as of which the entities were exported
+    pub block_number: BlockNumber,
+    /// The hash of that block as a hex string
+    pub block_hash: String,
+    /// How many dynamic data sources the deployment had created up to
+    /// that block; they are not part of the export
+    pub dynamic_data_sources: usize,
+    pub entity_types: Vec<ExportedEntityType>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedEntityType {
+    /// The name of the entity type in the GraphQL schema
+    pub name: String,
+    /// The CSV file with the entities, relative to the export directory
+    pub file: String,
+    /// The columns of the CSV file, in order
+    pub columns: Vec<String>,
+    pub count: u64,
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> StoreError {
+    StoreError::Unknown(format_err!("can not access `{}`: {}", path.display(), e))
+}
+
+fn pg_error(e: postgres::Error) -> StoreError {
+    StoreError::Unknown(e.into())
+}
+
+fn column_list(columns: &[String]) -> String {
+    columns
+        .iter()
+        .map(|column| format!("\"{}\"", column))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Write the entities in `layout` as they were at `block` into `dir`,
+/// which is created if it does not exist
+pub(crate) fn export(
+    conn: &Connection,
+    layout: &Layout,
+    block: EthereumBlockPointer,
+    dynamic_data_sources: usize,
+    dir: &Path,
+) -> Result<Export, StoreError> {
+    fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+
+    let mut tables: Vec<&Table> = layout.tables.values().map(|table| table.as_ref()).collect();
+    tables.sort_by(|a, b| a.object.cmp(&b.object));
+
+    // Read all tables from the same snapshot
+    let tx = conn.transaction().map_err(pg_error)?;
+    tx.batch_execute("set transaction isolation level repeatable read, read only")
+        .map_err(pg_error)?;
+
+    let mut entity_types = Vec::new();
+    for table in tables {
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| column.name.as_str().to_owned())
+            .collect();
+        let file = format!("{}.csv", table.name);
+        let path = dir.join(&file);
+        let mut out = BufWriter::new(File::create(&path).map_err(|e| io_error(&path, e))?);
+
+        let query = format!(
+            "copy (select {} from {} where block_range @> {} order by id) \
+             to stdout with (format csv, header)",
+            column_list(&columns),
+            table.qualified_name,
+            block.number
+        );
+        let count = tx
+            .prepare(&query)
+            .and_then(|stmt| stmt.copy_out(&[], &mut out))
+            .map_err(pg_error)?;
+        out.flush().map_err(|e| io_error(&path, e))?;
+
+        entity_types.push(ExportedEntityType {
+            name: table.object.clone(),
+            file,
+            columns,
+            count,
+        });
+    }
+    tx.commit().map_err(pg_error)?;
+
+    let export = Export {
+        deployment: layout.subgraph.to_string(),
+        block_number: block.number as BlockNumber,
+        block_hash: block.hash_hex(),
+        dynamic_data_sources,
+        entity_types,
+    };
+    let path = dir.join(EXPORT_FILE);
+    let file = File::create(&path).map_err(|e| io_error(&path, e))?;
+    serde_json::to_writer_pretty(file, &export).map_err(|e| {
+        StoreError::Unknown(format_err!("can not write `{}`: {}", path.display(), e))
+    })?;
+    Ok(export)
+}
+
+/// Load the export in `dir` into the tables of `layout`, which must all be
+/// empty. The entities are visible from the block of the export on, and
+/// the block pointer of the deployment is set to that block so that
+/// indexing continues right after it
+pub(crate) fn import(conn: &Connection, layout: &Layout, dir: &Path) -> Result<Export, StoreError> {
+    let path = dir.join(EXPORT_FILE);
+    let file = File::open(&path).map_err(|e| io_error(&path, e))?;
+    let export: Export = serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+        StoreError::Unknown(format_err!("invalid export in `{}`: {}", path.display(), e))
+    })?;
+
+    // Without its dynamic data sources, the deployment would miss the
+    // triggers for them once it continues indexing
+    if export.dynamic_data_sources > 0 {
+        return Err(StoreError::Unknown(format_err!(
+            "can not import the export of {} since the deployment had created {} dynamic \
+             data sources by block {}, and they are not exported",
+            export.deployment,
+            export.dynamic_data_sources,
+            export.block_number
+        )));
+    }
+    let hash = H256::from_str(export.block_hash.trim_start_matches("0x")).map_err(|e| {
+        StoreError::Unknown(format_err!(
+            "invalid block hash `{}`: {}",
+            export.block_hash,
+            e
+        ))
+    })?;
+
+    let tx = conn.transaction().map_err(pg_error)?;
+    for exported in &export.entity_types {
+        let table = layout.tables.get(&exported.name).ok_or_else(|| {
+            StoreError::Unknown(format_err!(
+                "deployment {} has no entity type {}",
+                layout.subgraph,
+                exported.name
+            ))
+        })?;
+        if let Some(column) = exported.columns.iter().find(|column| {
+            !table
+                .columns
+                .iter()
+                .any(|col| col.name.as_str() == column.as_str())
+        }) {
+            return Err(StoreError::Unknown(format_err!(
+                "entity type {} of deployment {} has no column {}",
+                exported.name,
+                layout.subgraph,
+                column
+            )));
+        }
+        let not_empty: bool = tx
+            .query(
+                &format!("select exists (select 1 from {})", table.qualified_name),
+                &[],
+            )
+            .map_err(pg_error)?
+            .get(0)
+            .get(0);
+        if not_empty {
+            return Err(StoreError::Unknown(format_err!(
+                "deployment {} already has entities of type {}",
+                layout.subgraph,
+                exported.name
+            )));
+        }
+
+        // `copy` can only fill in the block range through a default
+        tx.batch_execute(&format!(
+            "alter table {} alter column block_range set default int4range({}, null)",
+            table.qualified_name, export.block_number
+        ))
+        .map_err(pg_error)?;
+        let path = dir.join(&exported.file);
+        let mut input = BufReader::new(File::open(&path).map_err(|e| io_error(&path, e))?);
+        let query = format!(
+            "copy {} ({}) from stdin with (format csv, header)",
+            table.qualified_name,
+            column_list(&exported.columns)
+        );
+        let count = tx
+            .prepare(&query)
+            .and_then(|stmt| stmt.copy_in(&[], &mut input))
+            .map_err(pg_error)?;
+        tx.batch_execute(&format!(
+            "alter table {} alter column block_range drop default",
+            table.qualified_name
+        ))
+        .map_err(pg_error)?;
+
+        if count != exported.count {
+            return Err(StoreError::Unknown(format_err!(
+                "expected {} entities of type {} in `{}` but found {}",
+                exported.count,
+                exported.name,
+                path.display(),
+                count
+            )));
+        }
+    }
+
+    // The tables were empty before, so the imported entities are all the
+    // entities the deployment has
+    let entity_count: u64 = export.entity_types.iter().map(|et| et.count).sum();
+    let updated = tx
+        .execute(
+            "update subgraphs.subgraph_deployment
+                set latest_ethereum_block_hash = $1,
+                    latest_ethereum_block_number = $2::int8::numeric,
+                    entity_count = $4::int8::numeric
+              where id = $3
+                and latest_ethereum_block_hash is null",
+            &[
+                &hash.as_bytes(),
+                &(export.block_number as i64),
+                &layout.subgraph.as_str(),
+                &(entity_count as i64),
+            ],
+        )
+        .map_err(pg_error)?;
+    if updated != 1 {
+        return Err(StoreError::Unknown(format_err!(
+            "deployment {} started indexing while the export was imported",
+            layout.subgraph
+        )));
+    }
+    tx.commit().map_err(pg_error)?;
+    Ok(export)
+}
@@ -0,0 +1,611 @@
+//! Background jobs that maintain the database and the state of each
+//! node. Every node checks periodically which database jobs are due and
+//! runs them; the `subgraphs.job_run` table records each run and is used to
+//! make sure that a job runs on only one node at a time, that it does not
+//! run more often than its interval allows, and that no more than
+//! `GRAPH_STORE_JOB_CONCURRENCY` jobs run against each database at once.
+//! A run holds a lease that it renews with heartbeats while it is going;
+//! once the lease runs out, the run counts as abandoned. Node jobs run on
+//! every node on their own schedule and are not recorded
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::{Connection, RunQueryDsl};
+use rand::{thread_rng, Rng};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::components::ethereum::REORG_THRESHOLD;
+use graph::prelude::*;
+
+use crate::catalog;
+use crate::connection_pool::ConnectionPool;
+use crate::entities;
+use crate::metadata;
+use crate::notification_listener::{JsonNotification, LARGE_NOTIFICATION_CLEANUP_INTERVAL};
+use crate::store_events::SubscriptionManager;
+
+lazy_static! {
+    /// Set to `false` on nodes that should not run background jobs
+    static ref STORE_JOBS: bool = std::env::var("GRAPH_STORE_JOBS")
+        .map(|s| s != "false")
+        .unwrap_or(true);
+
+    /// How many background jobs may run against each database at the same
+    /// time, across all nodes
+    static ref STORE_JOB_CONCURRENCY: u32 = std::env::var("GRAPH_STORE_JOB_CONCURRENCY")
+        .unwrap_or("1".into())
+        .parse::<u32>()
+        .expect("invalid GRAPH_STORE_JOB_CONCURRENCY");
+
+    /// How long deployments have to be unused before they are removed
+    /// together with their data. Unused deployments are never removed
+    /// automatically if this is not set
+    static ref UNUSED_DEPLOYMENT_RETENTION: Option<Duration> =
+        std::env::var("GRAPH_UNUSED_DEPLOYMENT_RETENTION")
+            .ok()
+            .map(|s| {
+                let hours = s
+                    .parse::<u64>()
+                    .expect("invalid GRAPH_UNUSED_DEPLOYMENT_RETENTION");
+                Duration::from_secs(hours * 60 * 60)
+            });
+
+    /// How many blocks behind the latest block of each deployment proofs of
+    /// indexing are kept. The chain verifier checks the blocks just below
+    /// the reorg threshold, and proofs of indexing therefore have to be
+    /// kept for at least that many blocks. Setting it to 0 keeps them
+    /// forever
+    pub(crate) static ref BLOCK_POI_HISTORY: Option<BlockNumber> = {
+        let history = std::env::var("GRAPH_BLOCK_POI_HISTORY")
+            .ok()
+            .map(|s| s.parse::<BlockNumber>().expect("invalid GRAPH_BLOCK_POI_HISTORY"))
+            .unwrap_or(DEFAULT_BLOCK_POI_HISTORY);
+        match history {
+            0 => None,
+            history if history < 0 || (history as u64) < *REORG_THRESHOLD => panic!(
+                "GRAPH_BLOCK_POI_HISTORY must be at least the reorg threshold of {} blocks",
+                *REORG_THRESHOLD
+            ),
+            history => Some(history),
+        }
+    };
+}
+
+/// How many blocks of proofs of indexing we keep by default, about two
+/// weeks of Ethereum mainnet
+const DEFAULT_BLOCK_POI_HISTORY: BlockNumber = 100_000;
+
+/// How often each node checks whether jobs are due
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs that have not sent a heartbeat for this long are assumed to have
+/// been abandoned by a node that died while running them
+const LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// How often a running job renews its lease
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the records of finished runs are kept
+const JOB_HISTORY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often node jobs remove subscriptions that nobody listens to anymore
+const STALE_SUBSCRIPTION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The name under which runs against the main database are recorded
+const MAIN_DATABASE: &str = "main";
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+type PooledPgConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+trait Job: Send + Sync {
+    /// The name under which runs of the job are recorded
+    fn name(&self) -> &'static str;
+
+    /// The minimum time between the starts of two runs
+    fn interval(&self) -> Duration;
+
+    /// Run the job. Jobs get connections from `pool` only for as long as
+    /// they need them so that long-running jobs do not tie up a connection
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError>;
+}
+
+fn get_conn(pool: &ConnectionPool) -> Result<PooledPgConnection, StoreError> {
+    pool.get().map_err(|e| StoreError::from(Error::from(e)))
+}
+
+/// Update the planner statistics for the metadata and for the tables of
+/// all deployments. Autovacuum does that, too, but often too late for
+/// tables that grow quickly during indexing
+struct Analyze;
+
+impl Job for Analyze {
+    fn name(&self) -> &'static str {
+        "analyze"
+    }
+
+    fn interval(&self) -> Duration {
+        DAY
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        use diesel::sql_types::Text;
+
+        const QUERY: &str = "
+        select t.schemaname as schema, t.tablename as table
+          from pg_tables t
+         where t.schemaname = 'subgraphs'
+            or t.schemaname in (select ds.name
+                                  from public.deployment_schemas ds
+                                 where ds.state = 'ready')
+         order by t.schemaname, t.tablename";
+        #[derive(QueryableByName)]
+        struct Table {
+            #[sql_type = "Text"]
+            schema: String,
+            #[sql_type = "Text"]
+            table: String,
+        }
+
+        // Starting with Postgres 12, tables that are locked, e.g., by a
+        // long-running migration, can be skipped instead of making the
+        // job wait for them
+        let (tables, analyze) = {
+            let conn = get_conn(pool)?;
+            let analyze = if catalog::server_version(&conn)? >= 120000 {
+                "analyze (skip_locked)"
+            } else {
+                "analyze"
+            };
+            (diesel::sql_query(QUERY).load::<Table>(&conn)?, analyze)
+        };
+        for table in &tables {
+            let conn = get_conn(pool)?;
+            diesel::sql_query(format!(
+                "{} \"{}\".\"{}\"",
+                analyze, table.schema, table.table
+            ))
+            .execute(&conn)?;
+        }
+        debug!(logger, "Analyzed tables"; "count" => tables.len());
+        Ok(())
+    }
+}
+
+/// Rebuild indexes that are invalid because building them concurrently
+/// failed, e.g., because the node that built them was restarted. Postgres
+/// keeps invalid indexes up to date, but never uses them for queries
+struct RebuildInvalidIndexes;
+
+impl Job for RebuildInvalidIndexes {
+    fn name(&self) -> &'static str {
+        "rebuild-invalid-indexes"
+    }
+
+    fn interval(&self) -> Duration {
+        HOUR
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        use diesel::sql_types::Text;
+
+        // Indexes ending in `_ccnew` or `_ccold` are left over from a
+        // failed concurrent reindex and are not rebuilt
+        const QUERY: &str = "
+        select n.nspname as schema, c.relname as index
+          from pg_index i
+               join pg_class c on c.oid = i.indexrelid
+               join pg_namespace n on n.oid = c.relnamespace
+         where not i.indisvalid
+           and c.relname !~ '_cc(new|old)[0-9]*$'
+           and (n.nspname = 'subgraphs'
+                or n.nspname in (select ds.name from public.deployment_schemas ds))
+         order by n.nspname, c.relname";
+        #[derive(QueryableByName)]
+        struct Index {
+            #[sql_type = "Text"]
+            schema: String,
+            #[sql_type = "Text"]
+            index: String,
+        }
+
+        // Starting with Postgres 12, indexes can be rebuilt without
+        // blocking writes to their table
+        let (indexes, reindex) = {
+            let conn = get_conn(pool)?;
+            let reindex = if catalog::server_version(&conn)? >= 120000 {
+                "reindex index concurrently"
+            } else {
+                "reindex index"
+            };
+            (diesel::sql_query(QUERY).load::<Index>(&conn)?, reindex)
+        };
+        for index in &indexes {
+            let conn = get_conn(pool)?;
+            diesel::sql_query(format!(
+                "{} \"{}\".\"{}\"",
+                reindex, index.schema, index.index
+            ))
+            .execute(&conn)?;
+            info!(logger, "Rebuilt invalid index";
+                  "schema" => &index.schema, "index" => &index.index);
+        }
+        Ok(())
+    }
+}
+
+/// Refresh the materialized views `info.table_sizes` and
+/// `info.subgraph_sizes` that show how much space tables and deployments
+/// take up
+struct RefreshSizeViews;
+
+impl Job for RefreshSizeViews {
+    fn name(&self) -> &'static str {
+        "refresh-size-views"
+    }
+
+    fn interval(&self) -> Duration {
+        DAY
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        for view in &["info.table_sizes", "info.subgraph_sizes"] {
+            let conn = get_conn(pool)?;
+            diesel::sql_query(format!("refresh materialized view {}", view)).execute(&conn)?;
+        }
+        debug!(logger, "Refreshed size views");
+        Ok(())
+    }
+}
+
+/// Delete the payloads of large notifications, which listeners read right
+/// after they are notified
+struct PruneLargeNotifications;
+
+impl Job for PruneLargeNotifications {
+    fn name(&self) -> &'static str {
+        "prune-large-notifications"
+    }
+
+    fn interval(&self) -> Duration {
+        *LARGE_NOTIFICATION_CLEANUP_INTERVAL
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        let conn = get_conn(pool)?;
+        let count = JsonNotification::prune(&conn, *LARGE_NOTIFICATION_CLEANUP_INTERVAL)?;
+        debug!(logger, "Deleted large notifications"; "count" => count);
+        Ok(())
+    }
+}
+
+/// Delete old records of job runs
+struct PruneJobHistory;
+
+impl Job for PruneJobHistory {
+    fn name(&self) -> &'static str {
+        "prune-job-history"
+    }
+
+    fn interval(&self) -> Duration {
+        DAY
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        let count = metadata::prune_job_runs(&get_conn(pool)?, JOB_HISTORY)?;
+        debug!(logger, "Deleted records of old job runs"; "count" => count);
+        Ok(())
+    }
+}
+
+/// Delete the proofs of indexing of blocks that are more than `history`
+/// blocks behind the latest block of their deployment
+struct PruneBlockProofsOfIndexing {
+    history: BlockNumber,
+}
+
+impl Job for PruneBlockProofsOfIndexing {
+    fn name(&self) -> &'static str {
+        "prune-block-proofs-of-indexing"
+    }
+
+    fn interval(&self) -> Duration {
+        HOUR
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        let count = metadata::prune_block_proofs_of_indexing(&get_conn(pool)?, self.history)?;
+        debug!(logger, "Deleted old proofs of indexing"; "count" => count);
+        Ok(())
+    }
+}
+
+/// Record which deployments became unused so that operators can see how
+/// long they have been unused and how much space they take up
+struct RecordUnusedDeployments;
+
+impl Job for RecordUnusedDeployments {
+    fn name(&self) -> &'static str {
+        "record-unused-deployments"
+    }
+
+    fn interval(&self) -> Duration {
+        HOUR
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        let conn = get_conn(pool)?;
+        let count = conn.transaction(|| metadata::record_unused_deployments(&conn))?;
+        debug!(logger, "Recorded unused deployments"; "count" => count);
+        Ok(())
+    }
+}
+
+/// Remove deployments that have been unused for longer than `retention`
+struct RemoveUnusedDeployments {
+    retention: Duration,
+}
+
+impl Job for RemoveUnusedDeployments {
+    fn name(&self) -> &'static str {
+        "remove-unused-deployments"
+    }
+
+    fn interval(&self) -> Duration {
+        HOUR
+    }
+
+    fn run(&self, logger: &Logger, pool: &ConnectionPool) -> Result<(), StoreError> {
+        remove_expired_unused_deployments(logger, pool, self.retention)?;
+        Ok(())
+    }
+}
+
+/// Remove the deployments that have been unused for longer than
+/// `retention` and return the ones that were removed. A deployment that
+/// is used again since it was recorded can not be removed, and stays
+pub(crate) fn remove_expired_unused_deployments(
+    logger: &Logger,
+    pool: &ConnectionPool,
+    retention: Duration,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    let expired = metadata::expired_unused_deployments(&get_conn(pool)?, retention)?;
+    let mut removed = Vec::new();
+    for id in expired {
+        let id = SubgraphDeploymentId::new(id.clone())
+            .map_err(|_| StoreError::Unknown(format_err!("illegal deployment id {}", id)))?;
+        let conn = get_conn(pool)?;
+        let result = conn.transaction(|| -> Result<(), StoreError> {
+            let changes = entities::remove_deployment(&conn, &id)?;
+            entities::send_store_event(&conn, &StoreEvent::new(changes))
+        });
+        match result {
+            Ok(()) => {
+                info!(logger, "Removed unused deployment"; "deployment" => id.as_str());
+                removed.push(id);
+            }
+            Err(e) => warn!(logger, "Failed to remove unused deployment";
+                            "deployment" => id.as_str(), "error" => e.to_string()),
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove subscriptions whose receiving end has gone. This runs on every
+/// node since each node has its own subscriptions
+struct CleanUpStaleSubscriptions {
+    subscriptions: Arc<SubscriptionManager>,
+}
+
+impl Job for CleanUpStaleSubscriptions {
+    fn name(&self) -> &'static str {
+        "clean-up-stale-subscriptions"
+    }
+
+    fn interval(&self) -> Duration {
+        STALE_SUBSCRIPTION_INTERVAL
+    }
+
+    fn run(&self, _: &Logger, _: &ConnectionPool) -> Result<(), StoreError> {
+        self.subscriptions.clean_up_stale_subscriptions();
+        Ok(())
+    }
+}
+
+/// A database that jobs maintain, and how many jobs may run against it at
+/// the same time
+#[derive(Clone)]
+struct Database {
+    name: &'static str,
+    pool: ConnectionPool,
+    concurrency: u32,
+    jobs: Vec<Arc<dyn Job>>,
+}
+
+#[derive(Clone)]
+pub struct JobRunner {
+    logger: Logger,
+    /// The database that records the runs of all database jobs
+    pool: ConnectionPool,
+    node_id: NodeId,
+    databases: Vec<Database>,
+    /// Jobs that run on every node. They run on the async runtime and
+    /// must therefore be quick
+    node_jobs: Vec<Arc<dyn Job>>,
+}
+
+impl JobRunner {
+    pub(crate) fn new(
+        logger: &Logger,
+        pool: ConnectionPool,
+        subscriptions: Arc<SubscriptionManager>,
+        node_id: NodeId,
+    ) -> Self {
+        let logger = logger.new(o!("component" => "JobRunner"));
+
+        let mut databases = Vec::new();
+        if *STORE_JOBS {
+            let mut jobs: Vec<Arc<dyn Job>> = vec![
+                Arc::new(Analyze),
+                Arc::new(RebuildInvalidIndexes),
+                Arc::new(RefreshSizeViews),
+                Arc::new(PruneJobHistory),
+                Arc::new(PruneLargeNotifications),
+                Arc::new(RecordUnusedDeployments),
+            ];
+            if let Some(retention) = *UNUSED_DEPLOYMENT_RETENTION {
+                jobs.push(Arc::new(RemoveUnusedDeployments { retention }));
+            }
+            if let Some(history) = *BLOCK_POI_HISTORY {
+                jobs.push(Arc::new(PruneBlockProofsOfIndexing { history }));
+            }
+            databases.push(Database {
+                name: MAIN_DATABASE,
+                pool: pool.clone(),
+                concurrency: *STORE_JOB_CONCURRENCY,
+                jobs,
+            });
+        }
+        let node_jobs: Vec<Arc<dyn Job>> =
+            vec![Arc::new(CleanUpStaleSubscriptions { subscriptions })];
+
+        JobRunner {
+            logger,
+            pool,
+            node_id,
+            databases,
+            node_jobs,
+        }
+    }
+
+    pub async fn run(self) {
+        let runner = Arc::new(self);
+
+        for job in &runner.node_jobs {
+            let job = job.clone();
+            let logger = runner.logger.new(o!("job" => job.name()));
+            let pool = runner.pool.clone();
+            graph::spawn(async move {
+                let mut interval = tokio::time::interval(job.interval());
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = job.run(&logger, &pool) {
+                        error!(logger, "Failed to run node job"; "error" => e.to_string());
+                    }
+                }
+            });
+        }
+
+        if runner.databases.is_empty() {
+            return;
+        }
+        loop {
+            // Spread the checks of nodes that were started together out a
+            // little so that they do not all compete for the lock at once
+            let jitter = thread_rng().gen_range(0, CHECK_INTERVAL.as_millis() as u64 / 10);
+            tokio::time::delay_for(CHECK_INTERVAL + Duration::from_millis(jitter)).await;
+
+            let runner = runner.clone();
+            graph::spawn_blocking_allow_panic(move || runner.run_due_jobs())
+                .await
+                .unwrap(); // Propagate panics.
+        }
+    }
+
+    /// Run the database jobs that are due one after the other and return
+    /// the names of the jobs that were run. Failures are recorded with the
+    /// run and logged
+    pub fn run_due_jobs(&self) -> Vec<&'static str> {
+        let mut ran = Vec::new();
+        for database in &self.databases {
+            for job in &database.jobs {
+                let logger = self
+                    .logger
+                    .new(o!("job" => job.name(), "database" => database.name));
+                match self.run_job(&logger, database, job.as_ref()) {
+                    Ok(true) => ran.push(job.name()),
+                    Ok(false) => (),
+                    Err(e) => {
+                        error!(logger, "Failed to run background job"; "error" => e.to_string())
+                    }
+                }
+            }
+        }
+        ran
+    }
+
+    /// Run `job` against `database` if it is due. Return whether it was run
+    fn run_job(
+        &self,
+        logger: &Logger,
+        database: &Database,
+        job: &dyn Job,
+    ) -> Result<bool, StoreError> {
+        let id = {
+            let conn = get_conn(&self.pool)?;
+            conn.transaction(|| {
+                metadata::claim_job(
+                    &conn,
+                    job.name(),
+                    database.name,
+                    &self.node_id,
+                    job.interval(),
+                    database.concurrency,
+                    LEASE,
+                )
+            })?
+        };
+        let id = match id {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+
+        info!(logger, "Starting background job");
+        let start = Instant::now();
+        let result = self.with_heartbeat(logger, id, || job.run(logger, &database.pool));
+        let error = result.as_ref().err().map(|e| e.to_string());
+        metadata::finish_job(&get_conn(&self.pool)?, id, error.as_deref())?;
+        match error {
+            None => info!(logger, "Finished background job";
+                          "time_ms" => start.elapsed().as_millis()),
+            Some(e) => error!(logger, "Background job failed"; "error" => e,
+                              "time_ms" => start.elapsed().as_millis()),
+        }
+        Ok(true)
+    }
+
+    /// Call `f` while renewing the lease of the job run `id` every
+    /// `HEARTBEAT_INTERVAL` from a separate thread
+    fn with_heartbeat<T>(&self, logger: &Logger, id: i64, f: impl FnOnce() -> T) -> T {
+        let (stop, stopped) = channel::<()>();
+        let heartbeat = {
+            let pool = self.pool.clone();
+            let logger = logger.clone();
+            std::thread::Builder::new()
+                .name("job-heartbeat".to_owned())
+                .spawn(move || {
+                    // Dropping `stop` ends the wait with `Disconnected`
+                    while let Err(RecvTimeoutError::Timeout) =
+                        stopped.recv_timeout(HEARTBEAT_INTERVAL)
+                    {
+                        let renewed =
+                            get_conn(&pool).and_then(|conn| metadata::job_heartbeat(&conn, id));
+                        if let Err(e) = renewed {
+                            warn!(logger, "Failed to renew the lease of a background job";
+                                  "error" => e.to_string());
+                        }
+                    }
+                })
+                .expect("failed to start the heartbeat thread for a background job")
+        };
+
+        let result = f();
+        drop(stop);
+        // The heartbeat thread does not panic
+        heartbeat.join().ok();
+        result
+    }
+}
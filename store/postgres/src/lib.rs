@@ -20,14 +20,19 @@ extern crate postgres;
 extern crate serde;
 extern crate uuid;
 
+mod advisory_lock;
+mod aggregation;
 mod block_range;
 mod catalog;
 mod chain_head_listener;
 mod chain_store;
 pub mod connection_pool;
 mod db_schema;
+mod ens;
 mod entities;
+mod export;
 mod functions;
+mod jobs;
 mod jsonb;
 mod metadata;
 mod network_store;
@@ -36,6 +41,7 @@ pub mod query_store;
 pub mod relational;
 mod relational_queries;
 mod sql_value;
+mod statement_cache;
 pub mod store;
 mod store_events;
 
@@ -48,12 +54,22 @@ pub mod db_schema_for_tests {
 #[cfg(debug_assertions)]
 pub mod layout_for_tests {
     pub use crate::block_range::*;
-    pub use crate::entities::{EVENT_TAP, EVENT_TAP_ENABLED, STRING_PREFIX_SIZE};
+    pub use crate::entities::{EVENT_TAP, EVENT_TAP_ENABLED, MOVE_COPY_HOOK, STRING_PREFIX_SIZE};
     pub use crate::relational::*;
+    pub use crate::statement_cache::StatementCache;
+}
+
+#[cfg(debug_assertions)]
+pub mod metadata_for_tests {
+    pub use crate::metadata::{handler_logs, record_handler_logs};
 }
 
 pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
+pub use self::ens::EnsNames;
+pub use self::export::{Export, ExportedEntityType, EXPORT_FILE};
+pub use self::jobs::JobRunner;
+pub use self::metadata::{ApiKeyDailyUsage, JobRun, UnusedDeployment};
 pub use self::network_store::NetworkStore;
-pub use self::store::{Store, StoreConfig};
+pub use self::store::{Store, StoreConfig, StoreHealthCheck};
 pub use self::store_events::SubscriptionManager;
@@ -5,19 +5,21 @@ use diesel::prelude::{
     ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, QueryDsl,
     RunQueryDsl,
 };
-use diesel::sql_types::Text;
+use diesel::sql_types::{Bool, Text};
 use graph::data::subgraph::schema::{
     generate_entity_id, SubgraphDeploymentAssignmentEntity, SubgraphManifestEntity, SUBGRAPHS_ID,
 };
 use graph::prelude::{
-    bigdecimal::ToPrimitive, entity, format_err, web3::types::H256, BigDecimal, BlockNumber,
-    DeploymentState, EntityChange, EntityChangeOperation, EthereumBlockPointer, MetadataOperation,
-    NodeId, Schema, StoreError, StoreEvent, SubgraphDeploymentEntity, SubgraphDeploymentId,
-    SubgraphName, SubgraphVersionSwitchingMode, TypedEntity,
+    bigdecimal::ToPrimitive, entity, format_err, web3::types::H256, ApiKey, ApiKeyUsage,
+    BigDecimal, BlockNumber, BlockProofOfIndexing, DeploymentInfo, DeploymentState, EntityChange,
+    EntityChangeOperation, EthereumBlockPointer, Failover, HandlerLog, MetadataOperation, NodeId,
+    NonCanonicalBlock, PlacementRules, Schema, StoreError, StoreEvent, SubgraphDeploymentEntity,
+    SubgraphDeploymentId, SubgraphName, SubgraphVersionSwitchingMode, TypedEntity,
 };
-use std::convert::TryFrom;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::convert::{TryFrom, TryInto};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::advisory_lock::{FAILOVER_LOCK, JOB_LOCK};
 use crate::block_range::UNVERSIONED_RANGE;
 
 // Diesel tables for some of the metadata
@@ -77,6 +79,10 @@ table! {
         reorg_count -> Integer,
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
+        sync_rate_block_number -> Nullable<Numeric>,
+        sync_rate_sampled_at -> Nullable<Timestamptz>,
+        blocks_per_minute -> Nullable<Double>,
+        poi_version -> Integer,
         block_range -> Range<Integer>,
     }
 }
@@ -132,6 +138,9 @@ table! {
         schema -> Text,
         data_sources -> Array<Text>,
         templates -> Nullable<Array<Text>>,
+        location -> Nullable<Text>,
+        overrides -> Nullable<Text>,
+        features -> Nullable<Array<Text>>,
         block_range -> Range<Integer>,
     }
 }
@@ -150,6 +159,49 @@ table! {
     }
 }
 
+table! {
+    subgraphs.api_key (id) {
+        id -> Text,
+        secret_hash -> Text,
+        description -> Nullable<Text>,
+        deployments -> Nullable<Array<Text>>,
+        queries_per_minute -> Nullable<Integer>,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    subgraphs.non_canonical_block (deployment) {
+        deployment -> Text,
+        block_number -> BigInt,
+        block_hash -> Binary,
+        canonical_hash -> Nullable<Binary>,
+    }
+}
+
+table! {
+    subgraphs.block_proof_of_indexing (deployment, block_number) {
+        deployment -> Text,
+        block_number -> Integer,
+        block_hash -> Binary,
+        poi_version -> Integer,
+        digest -> Binary,
+    }
+}
+
+table! {
+    subgraphs.handler_log (vid) {
+        vid -> BigInt,
+        deployment -> Text,
+        block_number -> Integer,
+        block_hash -> Binary,
+        data_source -> Text,
+        level -> Text,
+        message -> Text,
+        logged_at -> BigInt,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(subgraph, subgraph_version, subgraph_deployment);
 
 /// Look up the graft point for the given subgraph in the database and
@@ -246,6 +298,17 @@ fn block_ptr_store_event(id: &SubgraphDeploymentId) -> StoreEvent {
     StoreEvent::new(vec![change])
 }
 
+/// Check that updating the block pointer of `id` changed a row. Once a
+/// deployment's data has been adopted by another deployment, the deployment
+/// is gone, and a transaction that still writes a block for it must fail
+fn block_ptr_updated(id: &SubgraphDeploymentId, count: usize) -> Result<StoreEvent, StoreError> {
+    if count == 0 {
+        Err(StoreError::DeploymentNotFound(id.to_string()))
+    } else {
+        Ok(block_ptr_store_event(id))
+    }
+}
+
 pub fn forward_block_ptr(
     conn: &PgConnection,
     id: &SubgraphDeploymentId,
@@ -256,15 +319,37 @@ pub fn forward_block_ptr(
     // Work around a Diesel issue with serializing BigDecimals to numeric
     let number = format!("{}::numeric", ptr.number);
 
+    // Take a new sample for the sync rate if the last one is more than a
+    // minute old. Postgres evaluates all expressions against the row as it
+    // was before the update, so the rate is computed from the old sample
+    let resample = "sync_rate_sampled_at is null \
+                    or sync_rate_sampled_at < now() - interval '1 minute'";
+    let blocks_per_minute = format!(
+        "case when sync_rate_sampled_at < now() - interval '1 minute' \
+              then greatest(0, {} - sync_rate_block_number)::float8 * 60 \
+                   / extract(epoch from now() - sync_rate_sampled_at) \
+              else blocks_per_minute end",
+        number
+    );
+
     update(d::table.filter(d::id.eq(id.as_str())))
         .set((
             d::latest_ethereum_block_number.eq(sql(&number)),
             d::latest_ethereum_block_hash.eq(ptr.hash.as_bytes()),
             d::current_reorg_depth.eq(0),
+            d::blocks_per_minute.eq(sql(&blocks_per_minute)),
+            d::sync_rate_block_number.eq(sql(&format!(
+                "case when {} then {} else sync_rate_block_number end",
+                resample, number
+            ))),
+            d::sync_rate_sampled_at.eq(sql(&format!(
+                "case when {} then now() else sync_rate_sampled_at end",
+                resample
+            ))),
         ))
         .execute(conn)
-        .map(|_| block_ptr_store_event(id))
-        .map_err(|e| e.into())
+        .map_err(StoreError::from)
+        .and_then(|count| block_ptr_updated(id, count))
 }
 
 pub fn revert_block_ptr(
@@ -286,8 +371,36 @@ pub fn revert_block_ptr(
             d::max_reorg_depth.eq(sql("greatest(current_reorg_depth + 1, max_reorg_depth)")),
         ))
         .execute(conn)
-        .map(|_| block_ptr_store_event(id))
-        .map_err(|e| e.into())
+        .map_err(StoreError::from)
+        .and_then(|count| block_ptr_updated(id, count))
+}
+
+/// Return the sync rate of the deployment in blocks per minute, and the
+/// hash of the latest block it processed. A rate that was sampled more than
+/// five minutes ago is stale, and we report `0` for it since the deployment
+/// has evidently stopped processing blocks
+pub fn deployment_sync_rate(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(Option<f64>, Option<H256>), StoreError> {
+    use diesel::sql_types::{Double, Nullable};
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(id.as_str()))
+        .select((
+            sql::<Nullable<Double>>(
+                "case when sync_rate_sampled_at < now() - interval '5 minutes' \
+                      then 0 else blocks_per_minute end",
+            ),
+            d::latest_ethereum_block_hash,
+        ))
+        .first::<(Option<f64>, Option<Vec<u8>>)>(conn)
+        .optional()?
+        .map(|(rate, hash)| (rate, hash.map(|hash| H256::from_slice(&hash))))
+        .ok_or_else(|| {
+            StoreError::QueryExecutionError(format!("No data found for subgraph {}", id))
+        })
 }
 
 fn convert_to_u32(number: Option<i32>, field: &str, subgraph: &str) -> Result<u32, StoreError> {
@@ -592,6 +705,8 @@ pub fn create_subgraph_version(
         .unwrap()
         .as_secs();
 
+    forget_unused_deployment(conn, id)?;
+
     // Check the current state of the the subgraph. If no subgraph with the
     // name exists, create one
     let info = s::table
@@ -749,6 +864,468 @@ pub fn reassign_subgraph(
     }
 }
 
+pub fn unassign_subgraph(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<EntityChange>, StoreError> {
+    use subgraph_deployment_assignment as a;
+
+    match delete(a::table.filter(a::id.eq(id.as_str()))).execute(conn)? {
+        0 => Err(StoreError::DeploymentNotFound(id.to_string())),
+        _ => Ok(vec![MetadataOperation::Remove {
+            entity: SubgraphDeploymentAssignmentEntity::TYPENAME,
+            id: id.to_string(),
+        }
+        .into()]),
+    }
+}
+
+/// Whether the deployment `id` is assigned to a node
+pub fn deployment_assigned(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<bool, StoreError> {
+    use subgraph_deployment_assignment as a;
+
+    let count = a::table
+        .filter(a::id.eq(id.as_str()))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(count > 0)
+}
+
+/// The number of dynamic data sources that the deployment `id` created in
+/// blocks up to and including `block`
+pub fn dynamic_data_source_count(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<usize, StoreError> {
+    use dynamic_ethereum_contract_data_source as decds;
+
+    let count = decds::table
+        .filter(decds::deployment.eq(id.as_str()))
+        .filter(sql::<Bool>(&format!("ethereum_block_number <= {}", block)))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(count as usize)
+}
+
+/// The condition under which the deployment `d` in
+/// `subgraphs.subgraph_deployment` is unused: it is neither the current nor
+/// the pending version of any subgraph, it is not assigned to a node, and no
+/// graft still needs to copy from it
+const UNUSED_DEPLOYMENT: &str = "
+    not exists (select 1
+                  from subgraphs.subgraph s, subgraphs.subgraph_version v
+                 where v.id in (s.current_version, s.pending_version)
+                   and v.deployment = d.id)
+    and not exists (select 1
+                      from subgraphs.subgraph_deployment_assignment a
+                     where a.id = d.id)
+    -- Grafts copy their base when they start indexing
+    and not exists (select 1
+                      from subgraphs.subgraph_deployment g
+                     where g.graft_base = d.id
+                       and g.latest_ethereum_block_number is null)";
+
+/// The metadata tables that hold the parts of manifests and dynamic data
+/// sources. The id of each row in them starts with the id of the manifest
+/// or the dynamic data source it belongs to, followed by a `-`
+const NESTED_METADATA_TABLES: &[&str] = &[
+    "subgraph_manifest",
+    "ethereum_contract_data_source",
+    "ethereum_contract_data_source_template",
+    "ethereum_contract_data_source_template_source",
+    "ethereum_contract_source",
+    "ethereum_contract_mapping",
+    "ethereum_contract_abi",
+    "ethereum_contract_event_handler",
+    "ethereum_call_handler_entity",
+    "ethereum_block_handler_entity",
+    "ethereum_block_handler_filter_entity",
+];
+
+/// Delete all metadata for the deployment `id`. Only deployments that are
+/// neither the current nor the pending version of any subgraph, that are
+/// not assigned to a node and that no graft still needs to copy from can
+/// be removed; versions that point to the deployment are removed with it
+pub fn remove_deployment(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<EntityChange>, StoreError> {
+    use diesel::sql_types::Bool;
+
+    #[derive(QueryableByName)]
+    struct Used {
+        #[sql_type = "Bool"]
+        used: bool,
+    }
+
+    let used = diesel::sql_query(format!(
+        "select not ({}) as used from subgraphs.subgraph_deployment d where d.id = $1",
+        UNUSED_DEPLOYMENT
+    ))
+    .bind::<Text, _>(id.as_str())
+    .get_result::<Used>(conn)
+    .optional()?
+    .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))?
+    .used;
+    if used {
+        return Err(StoreError::Unknown(format_err!(
+            "deployment `{}` is still in use; it must be unassigned, neither the \
+             current nor the pending version of a subgraph, and not the base of \
+             a graft that has not started yet",
+            id
+        )));
+    }
+
+    delete_deployment(conn, id)
+}
+
+/// Delete all metadata for the deployment `id` without checking whether
+/// it is still used
+fn delete_deployment(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<EntityChange>, StoreError> {
+    use diesel::sql_types::Array;
+
+    #[derive(QueryableByName)]
+    struct Id {
+        #[sql_type = "Text"]
+        id: String,
+    }
+
+    let mut prefixes = vec![format!("{}-", id)];
+    prefixes.extend(
+        diesel::sql_query(
+            "select id || '-' as id
+               from subgraphs.dynamic_ethereum_contract_data_source
+              where deployment = $1",
+        )
+        .bind::<Text, _>(id.as_str())
+        .load::<Id>(conn)?
+        .into_iter()
+        .map(|row| row.id),
+    );
+    for table in NESTED_METADATA_TABLES {
+        diesel::sql_query(format!(
+            "delete from subgraphs.{} t
+              where exists (select 1 from unnest($1::text[]) p(prefix)
+                             where left(t.id, length(p.prefix)) = p.prefix)",
+            table
+        ))
+        .bind::<Array<Text>, _>(&prefixes)
+        .execute(conn)?;
+    }
+
+    for query in &[
+        "delete from subgraphs.dynamic_ethereum_contract_data_source where deployment = $1",
+        "delete from subgraphs.subgraph_version where deployment = $1",
+        "delete from subgraphs.subgraph_error where subgraph_id = $1",
+        "delete from subgraphs.non_canonical_block where deployment = $1",
+        "delete from subgraphs.block_proof_of_indexing where deployment = $1",
+        "delete from subgraphs.handler_log where deployment = $1",
+        "delete from subgraphs.subgraph_deployment where id = $1",
+        "delete from subgraphs.unused_deployment where deployment = $1",
+    ] {
+        diesel::sql_query(*query)
+            .bind::<Text, _>(id.as_str())
+            .execute(conn)?;
+    }
+    Ok(vec![MetadataOperation::Remove {
+        entity: SubgraphDeploymentEntity::TYPENAME,
+        id: id.to_string(),
+    }
+    .into()])
+}
+
+/// Find the deployment whose data a new deployment of the subgraph `name`
+/// could adopt. That is the current version of the subgraph, as long as
+/// no other subgraph uses it and no graft still needs to copy from it
+pub fn adoptable_deployment(
+    conn: &PgConnection,
+    name: &SubgraphName,
+) -> Result<Option<SubgraphDeploymentId>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Deployment {
+        #[sql_type = "Text"]
+        deployment: String,
+    }
+
+    const QUERY: &str = "
+    select v.deployment
+      from subgraphs.subgraph s, subgraphs.subgraph_version v
+     where s.name = $1
+       and v.id = s.current_version
+       and not exists (select 1
+                         from subgraphs.subgraph s2, subgraphs.subgraph_version v2
+                        where s2.id <> s.id
+                          and v2.id in (s2.current_version, s2.pending_version)
+                          and v2.deployment = v.deployment)
+       and not exists (select 1
+                         from subgraphs.subgraph_deployment g
+                        where g.graft_base = v.deployment
+                          and g.latest_ethereum_block_number is null)";
+
+    diesel::sql_query(QUERY)
+        .bind::<Text, _>(name.as_str())
+        .get_result::<Deployment>(conn)
+        .optional()?
+        .map(|row| {
+            SubgraphDeploymentId::new(row.deployment.clone()).map_err(|_| {
+                StoreError::Unknown(format_err!("illegal deployment id {}", row.deployment))
+            })
+        })
+        .transpose()
+}
+
+/// Check that the manifest of `id` can carry on from block `head` of
+/// `base`. Every data source of `id` that `base` does not have with the
+/// same address and start block would miss the blocks up to `head`, and
+/// the dynamic data sources of `base` need their templates in `id`
+fn check_adoptable_manifest(
+    conn: &PgConnection,
+    base: &SubgraphDeploymentId,
+    id: &SubgraphDeploymentId,
+    head: Option<BlockNumber>,
+) -> Result<(), StoreError> {
+    use diesel::sql_types::{BigInt, Integer};
+
+    #[derive(QueryableByName)]
+    struct DataSource {
+        #[sql_type = "Text"]
+        name: String,
+        #[sql_type = "BigInt"]
+        start_block: i64,
+    }
+    #[derive(QueryableByName)]
+    struct Template {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    const NEW_DATA_SOURCES_QUERY: &str = "
+    select ds.name, coalesce(s.start_block, 0)::int8 as start_block
+      from subgraphs.subgraph_manifest m
+           join subgraphs.ethereum_contract_data_source ds
+             on ds.id = any(m.data_sources)
+           join subgraphs.ethereum_contract_source s on s.id = ds.source
+     where m.id = $1
+       and coalesce(s.start_block, 0) <= $3
+       and not exists (select 1
+                         from subgraphs.subgraph_manifest bm
+                              join subgraphs.ethereum_contract_data_source bds
+                                on bds.id = any(bm.data_sources)
+                              join subgraphs.ethereum_contract_source bs
+                                on bs.id = bds.source
+                        where bm.id = $2
+                          and bds.name = ds.name
+                          and bs.address is not distinct from s.address
+                          and coalesce(bs.start_block, 0) = coalesce(s.start_block, 0))
+     order by ds.name";
+
+    const MISSING_TEMPLATES_QUERY: &str = "
+    select distinct dds.name
+      from subgraphs.dynamic_ethereum_contract_data_source dds
+     where dds.deployment = $2
+       and not exists (select 1
+                         from subgraphs.subgraph_manifest m
+                              join subgraphs.ethereum_contract_data_source_template t
+                                on t.id = any(m.templates)
+                        where m.id = $1
+                          and t.name = dds.name)
+     order by dds.name";
+
+    let manifest = SubgraphManifestEntity::id(id);
+    let base_manifest = SubgraphManifestEntity::id(base);
+
+    // Without a head, `base` has not processed any blocks and nothing
+    // can be missed
+    if let Some(head) = head {
+        let missed = diesel::sql_query(NEW_DATA_SOURCES_QUERY)
+            .bind::<Text, _>(manifest.as_str())
+            .bind::<Text, _>(base_manifest.as_str())
+            .bind::<Integer, _>(head)
+            .load::<DataSource>(conn)?;
+        if !missed.is_empty() {
+            let missed = missed
+                .iter()
+                .map(|ds| format!("{} (starts at block {})", ds.name, ds.start_block))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(StoreError::Unknown(format_err!(
+                "deployment {} can not adopt the data of {} since {} is at block {} \
+                 and these data sources of the new deployment would miss blocks: {}",
+                id,
+                base,
+                base,
+                head,
+                missed
+            )));
+        }
+    }
+
+    let missing = diesel::sql_query(MISSING_TEMPLATES_QUERY)
+        .bind::<Text, _>(manifest.as_str())
+        .bind::<Text, _>(base.as_str())
+        .load::<Template>(conn)?;
+    if !missing.is_empty() {
+        let missing = missing
+            .into_iter()
+            .map(|template| template.name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(StoreError::Unknown(format_err!(
+            "deployment {} can not adopt the data of {} since it lacks the templates \
+             for these dynamic data sources: {}",
+            id,
+            base,
+            missing
+        )));
+    }
+    Ok(())
+}
+
+/// Make the new deployment `id` carry on from where the deployment `base`
+/// left off, and remove `base`. The progress, dynamic data sources and
+/// versions of `base` are moved to `id`; if `base` was synced, `id` becomes
+/// the current version right away. Fails if the manifest of `id` can not
+/// carry on from where `base` is. Return the resulting changes
+pub fn adopt_deployment(
+    conn: &PgConnection,
+    base: &SubgraphDeploymentId,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<EntityChange>, StoreError> {
+    use subgraph_deployment as d;
+
+    // Wait for a block that is being written for `base` to be committed;
+    // once we are done, writing blocks for `base` fails since it is gone
+    let (synced, head) = d::table
+        .filter(d::id.eq(base.as_str()))
+        .select((d::synced, d::latest_ethereum_block_number))
+        .for_update()
+        .first::<(bool, Option<BigDecimal>)>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(base.to_string()))?;
+    let head = head
+        .map(|head| latest_as_block_number(Some(head), base.as_str()))
+        .transpose()?;
+    check_adoptable_manifest(conn, base, id, head)?;
+
+    diesel::sql_query(
+        "update subgraphs.subgraph_deployment n
+            set latest_ethereum_block_hash = b.latest_ethereum_block_hash,
+                latest_ethereum_block_number = b.latest_ethereum_block_number,
+                earliest_ethereum_block_hash = b.earliest_ethereum_block_hash,
+                earliest_ethereum_block_number = b.earliest_ethereum_block_number,
+                entity_count = b.entity_count,
+                reorg_count = b.reorg_count,
+                current_reorg_depth = b.current_reorg_depth,
+                max_reorg_depth = b.max_reorg_depth
+           from subgraphs.subgraph_deployment b
+          where n.id = $1 and b.id = $2",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<Text, _>(base.as_str())
+    .execute(conn)?;
+    for query in &[
+        "update subgraphs.dynamic_ethereum_contract_data_source
+            set deployment = $1 where deployment = $2",
+        "update subgraphs.subgraph_version set deployment = $1 where deployment = $2",
+        "update subgraphs.non_canonical_block set deployment = $1 where deployment = $2",
+    ] {
+        diesel::sql_query(*query)
+            .bind::<Text, _>(id.as_str())
+            .bind::<Text, _>(base.as_str())
+            .execute(conn)?;
+    }
+
+    let mut changes = delete_deployment(conn, base)?;
+    if synced {
+        changes.extend(deployment_synced(conn, id)?);
+    }
+    changes.extend(remove_unused_assignments(conn)?);
+    changes.extend(block_ptr_store_event(id).changes);
+    Ok(changes)
+}
+
+pub fn deployment_infos(
+    conn: &PgConnection,
+    name: Option<&SubgraphName>,
+    deployment: Option<&SubgraphDeploymentId>,
+) -> Result<Vec<DeploymentInfo>, StoreError> {
+    use diesel::sql_types::{BigInt, Bool, Nullable};
+
+    // Only versions that are current or pending are of interest; the
+    // parenthesized join makes sure that deployments that are not used by
+    // any such version are still listed
+    const QUERY: &str = "
+    select s.name as subgraph,
+           case when v.id = s.current_version then 'current'
+                else 'pending' end as version,
+           d.id as deployment, a.node_id, d.synced, d.health::text as health,
+           d.latest_ethereum_block_number::int8 as latest_block_number,
+           e.message as fatal_error,
+           exists (select 1 from subgraphs.non_canonical_block n
+                    where n.deployment = d.id) as needs_rewind
+      from subgraphs.subgraph_deployment d
+           left join (subgraphs.subgraph_version v
+                      join subgraphs.subgraph s
+                        on v.id in (s.current_version, s.pending_version))
+             on v.deployment = d.id
+           left join subgraphs.subgraph_deployment_assignment a on a.id = d.id
+           left join subgraphs.subgraph_error e on e.id = d.fatal_error
+     where ($1::text is null or s.name = $1)
+       and ($2::text is null or d.id = $2)
+     order by s.name, version, d.id
+    ";
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Nullable<Text>"]
+        subgraph: Option<String>,
+        #[sql_type = "Text"]
+        version: String,
+        #[sql_type = "Text"]
+        deployment: String,
+        #[sql_type = "Nullable<Text>"]
+        node_id: Option<String>,
+        #[sql_type = "Bool"]
+        synced: bool,
+        #[sql_type = "Text"]
+        health: String,
+        #[sql_type = "Nullable<BigInt>"]
+        latest_block_number: Option<i64>,
+        #[sql_type = "Nullable<Text>"]
+        fatal_error: Option<String>,
+        #[sql_type = "Bool"]
+        needs_rewind: bool,
+    }
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Nullable<Text>, _>(name.map(|name| name.as_str()))
+        .bind::<Nullable<Text>, _>(deployment.map(|id| id.as_str()))
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(|row| DeploymentInfo {
+            version: if row.subgraph.is_some() {
+                Some(row.version)
+            } else {
+                None
+            },
+            subgraph: row.subgraph,
+            deployment: row.deployment,
+            node_id: row.node_id,
+            synced: row.synced,
+            health: row.health,
+            latest_block_number: row.latest_block_number,
+            fatal_error: row.fatal_error,
+            needs_rewind: row.needs_rewind,
+        })
+        .collect())
+}
+
 /// Clear the `SubgraphHealth::Failed` status of a subgraph and mark it as
 /// healthy or unhealthy depending on whether it also had non-fatal errors
 pub fn unfail_deployment(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
@@ -784,3 +1361,1132 @@ pub fn unfail_deployment(conn: &PgConnection, id: &SubgraphDeploymentId) -> Resu
     .execute(conn)?;
     Ok(())
 }
+
+pub fn deployment_failed(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<bool, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::failed)
+        .first::<bool>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))
+}
+
+/// Move the block pointer of the deployment back to `ptr`. Unlike
+/// `revert_block_ptr`, this is not a reorg, and we therefore leave the
+/// reorg statistics alone. The sync rate is sampled afresh once the
+/// deployment moves forward again
+pub fn rewind_block_ptr(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    ptr: EthereumBlockPointer,
+) -> Result<StoreEvent, StoreError> {
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set((
+            d::latest_ethereum_block_number.eq(sql(&number)),
+            d::latest_ethereum_block_hash.eq(ptr.hash.as_bytes()),
+            d::current_reorg_depth.eq(0),
+            d::sync_rate_sampled_at.eq(sql("null")),
+        ))
+        .execute(conn)
+        .map(|_| block_ptr_store_event(id))
+        .map_err(|e| e.into())
+}
+
+/// The changes that make the node to which the deployment is assigned
+/// stop it and start it again, without changing the assignment
+pub fn restart_assignment_changes(id: &SubgraphDeploymentId) -> Vec<EntityChange> {
+    let change = |operation| EntityChange {
+        entity_type: SubgraphDeploymentAssignmentEntity::TYPENAME.to_string(),
+        entity_id: id.to_string(),
+        subgraph_id: SUBGRAPHS_ID.to_owned(),
+        operation,
+    };
+    vec![
+        change(EntityChangeOperation::Removed),
+        change(EntityChangeOperation::Set),
+    ]
+}
+
+/// How many queries were made with an API key against a deployment on
+/// one day
+#[derive(Clone, Debug, QueryableByName)]
+pub struct ApiKeyDailyUsage {
+    #[sql_type = "Text"]
+    pub api_key: String,
+    #[sql_type = "Text"]
+    pub deployment: String,
+    /// The day in `YYYY-MM-DD` format
+    #[sql_type = "Text"]
+    pub day: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub queries: i64,
+}
+
+fn api_key_secret_hash(secret: &str) -> String {
+    blake3::hash(secret.as_bytes()).to_hex().to_string()
+}
+
+type ApiKeyColumns = (
+    api_key::id,
+    api_key::description,
+    api_key::deployments,
+    api_key::queries_per_minute,
+    diesel::dsl::IsNotNull<api_key::revoked_at>,
+);
+
+type ApiKeyRow = (
+    String,
+    Option<String>,
+    Option<Vec<String>>,
+    Option<i32>,
+    bool,
+);
+
+fn api_key_columns() -> ApiKeyColumns {
+    use api_key as k;
+    (
+        k::id,
+        k::description,
+        k::deployments,
+        k::queries_per_minute,
+        k::revoked_at.is_not_null(),
+    )
+}
+
+fn api_key_from_row(row: ApiKeyRow) -> ApiKey {
+    let (id, description, deployments, queries_per_minute, revoked) = row;
+    ApiKey {
+        id,
+        description,
+        deployments,
+        queries_per_minute: queries_per_minute.map(|n| n as u32),
+        revoked,
+    }
+}
+
+pub fn api_key(conn: &PgConnection, secret: &str) -> Result<Option<ApiKey>, StoreError> {
+    use api_key as k;
+
+    Ok(k::table
+        .filter(k::secret_hash.eq(api_key_secret_hash(secret)))
+        .select(api_key_columns())
+        .first::<ApiKeyRow>(conn)
+        .optional()?
+        .map(api_key_from_row))
+}
+
+pub fn api_keys(conn: &PgConnection) -> Result<Vec<ApiKey>, StoreError> {
+    use api_key as k;
+
+    Ok(k::table
+        .select(api_key_columns())
+        .order_by(k::id)
+        .load::<ApiKeyRow>(conn)?
+        .into_iter()
+        .map(api_key_from_row)
+        .collect())
+}
+
+/// Create a new API key and return it together with its secret. The
+/// secret is not stored and can not be recovered later
+pub fn create_api_key(
+    conn: &PgConnection,
+    description: Option<String>,
+    deployments: Option<Vec<SubgraphDeploymentId>>,
+    queries_per_minute: Option<u32>,
+) -> Result<(ApiKey, String), StoreError> {
+    use api_key as k;
+    use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+    let deployments = deployments
+        .map(|deployments| {
+            deployments
+                .into_iter()
+                .map(|id| {
+                    if deployment_exists(conn, id.as_str())? {
+                        Ok(id.to_string())
+                    } else {
+                        Err(StoreError::DeploymentNotFound(id.to_string()))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
+    let id = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .collect::<String>()
+        .to_lowercase();
+    let secret = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .collect::<String>();
+
+    insert_into(k::table)
+        .values((
+            k::id.eq(&id),
+            k::secret_hash.eq(api_key_secret_hash(&secret)),
+            k::description.eq(&description),
+            k::deployments.eq(&deployments),
+            k::queries_per_minute.eq(queries_per_minute.map(|n| n as i32)),
+        ))
+        .execute(conn)?;
+
+    let key = ApiKey {
+        id,
+        description,
+        deployments,
+        queries_per_minute,
+        revoked: false,
+    };
+    Ok((key, secret))
+}
+
+/// Revoke the API key `id` so that it can not be used anymore. Its usage
+/// is kept. Return `false` if there is no such key or if it had already
+/// been revoked
+pub fn revoke_api_key(conn: &PgConnection, id: &str) -> Result<bool, StoreError> {
+    use api_key as k;
+    use diesel::sql_types::{Nullable, Timestamptz};
+
+    let revoked = update(
+        k::table
+            .filter(k::id.eq(id))
+            .filter(k::revoked_at.is_null()),
+    )
+    .set(k::revoked_at.eq(sql::<Nullable<Timestamptz>>("now()")))
+    .execute(conn)?;
+    Ok(revoked > 0)
+}
+
+pub fn record_api_key_usage(conn: &PgConnection, usage: &[ApiKeyUsage]) -> Result<(), StoreError> {
+    use diesel::sql_types::BigInt;
+
+    const QUERY: &str = "
+        insert into subgraphs.api_key_usage(api_key, deployment, day, queries)
+        values ($1, $2, current_date, $3)
+        on conflict (api_key, deployment, day)
+        do update set queries = api_key_usage.queries + excluded.queries";
+
+    for usage in usage {
+        diesel::sql_query(QUERY)
+            .bind::<Text, _>(&usage.api_key)
+            .bind::<Text, _>(usage.deployment.as_str())
+            .bind::<BigInt, _>(usage.queries as i64)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// The daily usage of the API key `id`, or of all keys if `id` is `None`,
+/// over the last `days` days, most recent day first
+pub fn api_key_usage(
+    conn: &PgConnection,
+    id: Option<&str>,
+    days: u32,
+) -> Result<Vec<ApiKeyDailyUsage>, StoreError> {
+    use diesel::sql_types::{Integer, Nullable};
+
+    const QUERY: &str = "
+        select api_key, deployment, day::text as day, queries
+          from subgraphs.api_key_usage
+         where ($1 is null or api_key = $1)
+           and day > current_date - $2
+         order by day desc, api_key, deployment";
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Nullable<Text>, _>(id)
+        .bind::<Integer, _>(days as i32)
+        .load::<ApiKeyDailyUsage>(conn)?)
+}
+
+pub fn deployment_block_ptrs(
+    conn: &PgConnection,
+    network: &str,
+) -> Result<Vec<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError> {
+    use diesel::sql_types::{BigInt, Binary};
+
+    // Like `subgraph_network`, this takes the network of the first data
+    // source as the network of the deployment
+    const QUERY: &str = "
+    select d.id as deployment, d.latest_ethereum_block_hash as hash,
+           d.latest_ethereum_block_number::int8 as number
+      from subgraphs.subgraph_deployment d
+           join subgraphs.subgraph_manifest m on m.id = d.manifest
+           join subgraphs.ethereum_contract_data_source ds
+             on ds.id = m.data_sources[1]
+     where ds.network = $1
+       and d.latest_ethereum_block_hash is not null
+       and d.latest_ethereum_block_number is not null
+    ";
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Text"]
+        deployment: String,
+        #[sql_type = "Binary"]
+        hash: Vec<u8>,
+        #[sql_type = "BigInt"]
+        number: i64,
+    }
+
+    diesel::sql_query(QUERY)
+        .bind::<Text, _>(network)
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(|row| {
+            let id = SubgraphDeploymentId::new(row.deployment.clone()).map_err(|_| {
+                StoreError::ConstraintViolation(format!(
+                    "invalid deployment id `{}`",
+                    row.deployment
+                ))
+            })?;
+            let ptr = EthereumBlockPointer::from((H256::from_slice(&row.hash), row.number));
+            Ok((id, ptr))
+        })
+        .collect()
+}
+
+/// Remember that the deployment `id` indexed a block that is not part of
+/// the canonical chain. If we already knew that, only the block is updated
+/// so that we keep track of when the problem was first detected
+pub fn set_non_canonical_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: &NonCanonicalBlock,
+) -> Result<(), StoreError> {
+    use non_canonical_block as n;
+
+    let hash = block.block.hash.as_bytes();
+    let canonical_hash = block.canonical_hash.as_ref().map(|hash| hash.as_bytes());
+    insert_into(n::table)
+        .values((
+            n::deployment.eq(id.as_str()),
+            n::block_number.eq(block.block.number as i64),
+            n::block_hash.eq(hash),
+            n::canonical_hash.eq(canonical_hash),
+        ))
+        .on_conflict(n::deployment)
+        .do_update()
+        .set((
+            n::block_number.eq(block.block.number as i64),
+            n::block_hash.eq(hash),
+            n::canonical_hash.eq(canonical_hash),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget that the deployment `id` needed a rewind. Return `true` if it did
+pub fn clear_non_canonical_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<bool, StoreError> {
+    use non_canonical_block as n;
+
+    let deleted = delete(n::table.filter(n::deployment.eq(id.as_str()))).execute(conn)?;
+    Ok(deleted > 0)
+}
+
+pub fn non_canonical_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<NonCanonicalBlock>, StoreError> {
+    use non_canonical_block as n;
+
+    Ok(n::table
+        .filter(n::deployment.eq(id.as_str()))
+        .select((n::block_number, n::block_hash, n::canonical_hash))
+        .first::<(i64, Vec<u8>, Option<Vec<u8>>)>(conn)
+        .optional()?
+        .map(|(number, hash, canonical_hash)| NonCanonicalBlock {
+            block: EthereumBlockPointer::from((H256::from_slice(&hash), number)),
+            canonical_hash: canonical_hash.map(|hash| H256::from_slice(&hash)),
+        }))
+}
+
+/// Record the version of the proof of indexing algorithm that the
+/// deployment `id` uses
+pub fn set_proof_of_indexing_version(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    version: i32,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::poi_version.eq(version))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn proof_of_indexing_version(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<i32, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::poi_version)
+        .first::<i32>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))
+}
+
+/// Remember the proof of indexing of the deployment `id` for a block it
+/// just processed
+pub fn record_block_proof_of_indexing(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    poi: &BlockProofOfIndexing,
+) -> Result<(), StoreError> {
+    use block_proof_of_indexing as p;
+
+    let number: BlockNumber = poi
+        .block
+        .number
+        .try_into()
+        .expect("block numbers fit into an i32");
+    insert_into(p::table)
+        .values((
+            p::deployment.eq(id.as_str()),
+            p::block_number.eq(number),
+            p::block_hash.eq(poi.block.hash.as_bytes()),
+            p::poi_version.eq(poi.version),
+            p::digest.eq(&poi.digest[..]),
+        ))
+        .on_conflict((p::deployment, p::block_number))
+        .do_update()
+        .set((
+            p::block_hash.eq(poi.block.hash.as_bytes()),
+            p::poi_version.eq(poi.version),
+            p::digest.eq(&poi.digest[..]),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget the proofs of indexing of the deployment `id` for all blocks
+/// after `block`
+pub fn revert_block_proofs_of_indexing(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use block_proof_of_indexing as p;
+
+    delete(
+        p::table
+            .filter(p::deployment.eq(id.as_str()))
+            .filter(p::block_number.gt(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The proof of indexing of the deployment `id` for the last block it
+/// processed that is not after `block`
+pub fn block_proof_of_indexing(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<Option<BlockProofOfIndexing>, StoreError> {
+    use block_proof_of_indexing as p;
+
+    Ok(p::table
+        .filter(p::deployment.eq(id.as_str()))
+        .filter(p::block_number.le(block))
+        .order(p::block_number.desc())
+        .select((p::block_number, p::block_hash, p::poi_version, p::digest))
+        .first::<BlockProofOfIndexingRow>(conn)
+        .optional()?
+        .map(block_proof_of_indexing_from_row))
+}
+
+/// The proofs of indexing that the deployment `id` recorded for the blocks
+/// from `first` to `last`
+pub fn block_proofs_of_indexing(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    first: BlockNumber,
+    last: BlockNumber,
+) -> Result<Vec<BlockProofOfIndexing>, StoreError> {
+    use block_proof_of_indexing as p;
+
+    Ok(p::table
+        .filter(p::deployment.eq(id.as_str()))
+        .filter(p::block_number.between(first, last))
+        .order(p::block_number)
+        .select((p::block_number, p::block_hash, p::poi_version, p::digest))
+        .load::<BlockProofOfIndexingRow>(conn)?
+        .into_iter()
+        .map(block_proof_of_indexing_from_row)
+        .collect())
+}
+
+type BlockProofOfIndexingRow = (i32, Vec<u8>, i32, Vec<u8>);
+
+fn block_proof_of_indexing_from_row(
+    (number, hash, version, digest): BlockProofOfIndexingRow,
+) -> BlockProofOfIndexing {
+    let mut poi = BlockProofOfIndexing {
+        block: EthereumBlockPointer::from((H256::from_slice(&hash), number as u64)),
+        version,
+        digest: [0u8; 32],
+    };
+    poi.digest.copy_from_slice(&digest);
+    poi
+}
+
+/// Add proofs of indexing for blocks that the deployment `id` has not
+/// recorded one for yet
+pub fn insert_block_proofs_of_indexing(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    pois: &[BlockProofOfIndexing],
+) -> Result<(), StoreError> {
+    use block_proof_of_indexing as p;
+
+    let rows: Vec<_> = pois
+        .iter()
+        .map(|poi| {
+            let number: BlockNumber = poi
+                .block
+                .number
+                .try_into()
+                .expect("block numbers fit into an i32");
+            (
+                p::deployment.eq(id.as_str()),
+                p::block_number.eq(number),
+                p::block_hash.eq(poi.block.hash.as_bytes()),
+                p::poi_version.eq(poi.version),
+                p::digest.eq(&poi.digest[..]),
+            )
+        })
+        .collect();
+    insert_into(p::table).values(&rows).execute(conn)?;
+    Ok(())
+}
+
+/// Delete the proofs of indexing for blocks that are more than `history`
+/// blocks behind the latest block of their deployment
+pub fn prune_block_proofs_of_indexing(
+    conn: &PgConnection,
+    history: BlockNumber,
+) -> Result<usize, StoreError> {
+    Ok(diesel::sql_query(
+        "delete from subgraphs.block_proof_of_indexing p
+          using subgraphs.subgraph_deployment d
+          where p.deployment = d.id
+            and p.block_number < d.latest_ethereum_block_number - $1",
+    )
+    .bind::<diesel::sql_types::Integer, _>(history)
+    .execute(conn)?)
+}
+
+/// The blocks from `first` to `last` that the deployment `id` recorded a
+/// proof of indexing for
+pub fn indexed_block_ptrs(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    first: BlockNumber,
+    last: BlockNumber,
+) -> Result<Vec<EthereumBlockPointer>, StoreError> {
+    use block_proof_of_indexing as p;
+
+    Ok(p::table
+        .filter(p::deployment.eq(id.as_str()))
+        .filter(p::block_number.between(first, last))
+        .order(p::block_number)
+        .select((p::block_number, p::block_hash))
+        .load::<(i32, Vec<u8>)>(conn)?
+        .into_iter()
+        .map(|(number, hash)| EthereumBlockPointer::from((H256::from_slice(&hash), number as u64)))
+        .collect())
+}
+
+/// The number of the block with hash `hash` if the deployment `id`
+/// processed it
+pub fn proof_of_indexing_block_number(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    hash: &H256,
+) -> Result<Option<BlockNumber>, StoreError> {
+    use block_proof_of_indexing as p;
+
+    Ok(p::table
+        .filter(p::deployment.eq(id.as_str()))
+        .filter(p::block_hash.eq(hash.as_bytes()))
+        .select(p::block_number)
+        .first::<i32>(conn)
+        .optional()?)
+}
+
+/// The number of bind parameters Postgres accepts for one statement
+const POSTGRES_MAX_PARAMETERS: usize = u16::MAX as usize;
+
+/// Add the messages that the mappings of the deployment `id` logged, and
+/// remove all but the `retain` most recent ones
+pub fn record_handler_logs(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    logs: &[HandlerLog],
+    retain: usize,
+) -> Result<(), StoreError> {
+    use handler_log as l;
+
+    // Each row binds one parameter for each of these columns
+    const COLUMNS: usize = 7;
+
+    // Older messages would be removed right away
+    let logs = &logs[logs.len().saturating_sub(retain)..];
+    for chunk in logs.chunks(POSTGRES_MAX_PARAMETERS / COLUMNS) {
+        let rows: Vec<_> = chunk
+            .iter()
+            .map(|log| {
+                (
+                    l::deployment.eq(id.as_str()),
+                    l::block_number.eq(log.block_number as BlockNumber),
+                    l::block_hash.eq(log.block_hash.as_bytes()),
+                    l::data_source.eq(log.data_source.as_str()),
+                    l::level.eq(log.level.as_str()),
+                    l::message.eq(log.message.as_str()),
+                    l::logged_at.eq(log.timestamp as i64),
+                )
+            })
+            .collect();
+        insert_into(l::table).values(&rows).execute(conn)?;
+    }
+
+    diesel::sql_query(
+        "delete from subgraphs.handler_log
+          where deployment = $1
+            and vid <= (select vid from subgraphs.handler_log
+                         where deployment = $1
+                         order by vid desc
+                         offset $2 limit 1)",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<diesel::sql_types::BigInt, _>(retain as i64)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The `first` most recent messages that the mappings of the deployment
+/// `id` logged, newest first. If `block` is given, only messages from that
+/// block are returned, and if `levels` is not empty, only messages with
+/// one of these levels
+pub fn handler_logs(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: Option<BlockNumber>,
+    levels: &[String],
+    first: usize,
+) -> Result<Vec<HandlerLog>, StoreError> {
+    use handler_log as l;
+
+    let mut query = l::table
+        .filter(l::deployment.eq(id.as_str()))
+        .order(l::vid.desc())
+        .select((
+            l::block_number,
+            l::block_hash,
+            l::data_source,
+            l::level,
+            l::message,
+            l::logged_at,
+        ))
+        .limit(first as i64)
+        .into_boxed();
+    if let Some(block) = block {
+        query = query.filter(l::block_number.eq(block));
+    }
+    if !levels.is_empty() {
+        query = query.filter(l::level.eq_any(levels));
+    }
+    Ok(query
+        .load::<(i32, Vec<u8>, String, String, String, i64)>(conn)?
+        .into_iter()
+        .map(
+            |(block_number, block_hash, data_source, level, message, logged_at)| HandlerLog {
+                block_number: block_number as u64,
+                block_hash: H256::from_slice(&block_hash),
+                data_source,
+                level,
+                message,
+                timestamp: logged_at as u64,
+            },
+        )
+        .collect())
+}
+
+pub fn record_heartbeat(
+    conn: &PgConnection,
+    node_id: &NodeId,
+    failover_target: bool,
+    networks: &[String],
+) -> Result<(), StoreError> {
+    use diesel::sql_types::{Array, Bool};
+
+    const QUERY: &str = "
+        insert into subgraphs.node_heartbeat(node_id, last_seen, failover_target, networks)
+        values ($1, now(), $2, $3)
+        on conflict (node_id)
+        do update set last_seen = now(), failover_target = excluded.failover_target,
+                      networks = excluded.networks, stopped_at = null";
+
+    diesel::sql_query(QUERY)
+        .bind::<Text, _>(node_id.as_str())
+        .bind::<Bool, _>(failover_target)
+        .bind::<Array<Text>, _>(networks)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Record that `node_id` shut down cleanly
+pub fn record_stop(conn: &PgConnection, node_id: &NodeId) -> Result<(), StoreError> {
+    const QUERY: &str = "
+        update subgraphs.node_heartbeat
+           set last_seen = now(), stopped_at = now()
+         where node_id = $1";
+
+    diesel::sql_query(QUERY)
+        .bind::<Text, _>(node_id.as_str())
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Assign the deployments of nodes whose last heartbeat is older than
+/// `timeout`, or `planned_downtime` for nodes that shut down cleanly, to
+/// live failover targets. A deployment only goes to nodes that index its
+/// network and that the placement rules allow for it, and of those to the
+/// one with the fewest deployments; when there is no such node, it stays
+/// where it is. Only nodes that record heartbeats are ever considered
+/// dead, and the heartbeats of dead nodes that have no deployments left
+/// are removed. The caller must send the returned changes as a
+/// `StoreEvent` so that the nodes pick up their new deployments
+pub fn fail_over_dead_nodes(
+    conn: &PgConnection,
+    timeout: Duration,
+    planned_downtime: Duration,
+) -> Result<(Vec<Failover>, Vec<EntityChange>), StoreError> {
+    use diesel::sql_types::{Array, BigInt, Bool, Double, Nullable};
+
+    // Keep two nodes from reassigning the same deployments at the same
+    // time; the lock is released when the transaction ends
+    #[derive(QueryableByName)]
+    struct Locked {
+        #[sql_type = "Bool"]
+        locked: bool,
+    }
+    let locked = diesel::sql_query(format!(
+        "select pg_try_advisory_xact_lock({}) as locked",
+        FAILOVER_LOCK
+    ))
+    .get_result::<Locked>(conn)?
+    .locked;
+    if !locked {
+        return Ok((vec![], vec![]));
+    }
+
+    // Like `subgraph_network`, this takes the network of the first data
+    // source as the network of the deployment
+    const ORPHANED_QUERY: &str = "
+    select a.id as deployment, a.node_id, ds.network,
+           array(select s.name
+                   from subgraphs.subgraph_version v
+                        join subgraphs.subgraph s
+                          on v.id in (s.current_version, s.pending_version)
+                  where v.deployment = a.id
+                  order by s.name) as names
+      from subgraphs.subgraph_deployment_assignment a
+           join subgraphs.node_heartbeat h on h.node_id = a.node_id
+           left join (subgraphs.subgraph_deployment d
+                      join subgraphs.subgraph_manifest m on m.id = d.manifest)
+             on d.id = a.id
+           left join subgraphs.ethereum_contract_data_source ds
+             on ds.id = m.data_sources[1]
+     where h.last_seen < now() - make_interval(secs =>
+             case when h.stopped_at is null then $1 else $2 end)
+     order by a.id";
+    #[derive(QueryableByName)]
+    struct Orphaned {
+        #[sql_type = "Text"]
+        deployment: String,
+        #[sql_type = "Text"]
+        node_id: String,
+        #[sql_type = "Nullable<Text>"]
+        network: Option<String>,
+        #[sql_type = "Array<Text>"]
+        names: Vec<String>,
+    }
+
+    const TARGETS_QUERY: &str = "
+    select h.node_id, h.networks, count(a.id) as deployments
+      from subgraphs.node_heartbeat h
+           left join subgraphs.subgraph_deployment_assignment a
+             on a.node_id = h.node_id
+     where h.failover_target
+       and h.stopped_at is null
+       and h.last_seen >= now() - make_interval(secs => $1)
+     group by h.node_id
+     order by h.node_id";
+    #[derive(QueryableByName)]
+    struct Target {
+        #[sql_type = "Text"]
+        node_id: String,
+        #[sql_type = "Array<Text>"]
+        networks: Vec<String>,
+        #[sql_type = "BigInt"]
+        deployments: i64,
+    }
+
+    const PRUNE_QUERY: &str = "
+    delete from subgraphs.node_heartbeat h
+     where h.last_seen < now() - make_interval(secs => $1)
+       and not exists (select 1 from subgraphs.subgraph_deployment_assignment a
+                        where a.node_id = h.node_id)";
+
+    fn node_id(id: &str) -> Result<NodeId, StoreError> {
+        NodeId::new(id.to_owned())
+            .map_err(|_| StoreError::ConstraintViolation(format!("invalid node id `{}`", id)))
+    }
+
+    let secs = timeout.as_secs_f64();
+    let orphaned = diesel::sql_query(ORPHANED_QUERY)
+        .bind::<Double, _>(secs)
+        .bind::<Double, _>(planned_downtime.as_secs_f64())
+        .load::<Orphaned>(conn)?;
+    let mut targets = if orphaned.is_empty() {
+        vec![]
+    } else {
+        diesel::sql_query(TARGETS_QUERY)
+            .bind::<Double, _>(secs)
+            .load::<Target>(conn)?
+    };
+
+    let rules = PlacementRules::current();
+    let mut failovers = Vec::new();
+    let mut changes = Vec::new();
+    for orphan in orphaned {
+        // The nodes that the first rule matching one of the names of the
+        // deployment allows; without such a rule, any node will do
+        let allowed = orphan.network.as_deref().and_then(|network| {
+            orphan
+                .names
+                .iter()
+                .filter_map(|name| SubgraphName::new(name.as_str()).ok())
+                .find_map(|name| rules.nodes(&name, network).map(|nodes| nodes.to_vec()))
+        });
+        let target = targets
+            .iter_mut()
+            .filter(|target| match &orphan.network {
+                Some(network) => target.networks.contains(network),
+                None => true,
+            })
+            .filter(|target| match &allowed {
+                Some(nodes) => nodes.iter().any(|node| node.as_str() == target.node_id),
+                None => true,
+            })
+            .min_by_key(|target| target.deployments);
+        let to = match target {
+            Some(target) => {
+                target.deployments += 1;
+                Some(node_id(&target.node_id)?)
+            }
+            None => None,
+        };
+
+        let failover = Failover {
+            deployment: SubgraphDeploymentId::new(orphan.deployment.clone()).map_err(|_| {
+                StoreError::ConstraintViolation(format!(
+                    "invalid deployment id `{}`",
+                    orphan.deployment
+                ))
+            })?,
+            from: node_id(&orphan.node_id)?,
+            to,
+        };
+        if let Some(to) = &failover.to {
+            changes.extend(reassign_subgraph(conn, &failover.deployment, to)?);
+        }
+        failovers.push(failover);
+    }
+
+    diesel::sql_query(PRUNE_QUERY)
+        .bind::<Double, _>(secs)
+        .execute(conn)?;
+    Ok((failovers, changes))
+}
+
+/// A run of one of the background jobs that maintain the database
+#[derive(Clone, Debug, QueryableByName)]
+pub struct JobRun {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub id: i64,
+    #[sql_type = "Text"]
+    pub job: String,
+    #[sql_type = "Text"]
+    pub database: String,
+    #[sql_type = "Text"]
+    pub node_id: String,
+    #[sql_type = "Text"]
+    pub started_at: String,
+    #[sql_type = "diesel::sql_types::Nullable<Text>"]
+    pub finished_at: Option<String>,
+    #[sql_type = "diesel::sql_types::Nullable<Text>"]
+    pub error: Option<String>,
+}
+
+/// Record that `node_id` starts a run of `job` against `database` and
+/// return the id of the run, unless `job` was started less than `interval`
+/// ago or is still running, or `concurrency` jobs are running against
+/// `database` already. A run that has not sent a heartbeat for longer than
+/// `lease` is considered abandoned, e.g., because the node running it
+/// died, and is marked as finished. Must be called in a transaction
+pub fn claim_job(
+    conn: &PgConnection,
+    job: &str,
+    database: &str,
+    node_id: &NodeId,
+    interval: Duration,
+    concurrency: u32,
+    lease: Duration,
+) -> Result<Option<i64>, StoreError> {
+    use diesel::sql_types::{BigInt, Bool, Double};
+
+    // Keep other nodes from starting jobs until this transaction has
+    // recorded the new run
+    #[derive(QueryableByName)]
+    struct Locked {
+        #[sql_type = "Bool"]
+        locked: bool,
+    }
+    let locked = diesel::sql_query(format!(
+        "select pg_try_advisory_xact_lock({}) as locked",
+        JOB_LOCK
+    ))
+    .get_result::<Locked>(conn)?
+    .locked;
+    if !locked {
+        return Ok(None);
+    }
+
+    const ABANDON_QUERY: &str = "
+    update subgraphs.job_run
+       set finished_at = heartbeat_at,
+           error = 'abandoned since the node running it stopped sending heartbeats'
+     where finished_at is null
+       and heartbeat_at < now() - make_interval(secs => $1)";
+    diesel::sql_query(ABANDON_QUERY)
+        .bind::<Double, _>(lease.as_secs_f64())
+        .execute(conn)?;
+
+    const BUSY_QUERY: &str = "
+    select count(*) filter (where finished_at is null) as running,
+           count(*) filter (where job = $1) as recent
+      from subgraphs.job_run
+     where database = $2
+       and (finished_at is null
+            or started_at > now() - make_interval(secs => $3))";
+    #[derive(QueryableByName)]
+    struct Busy {
+        #[sql_type = "BigInt"]
+        running: i64,
+        #[sql_type = "BigInt"]
+        recent: i64,
+    }
+    let busy = diesel::sql_query(BUSY_QUERY)
+        .bind::<Text, _>(job)
+        .bind::<Text, _>(database)
+        .bind::<Double, _>(interval.as_secs_f64())
+        .get_result::<Busy>(conn)?;
+    if busy.recent > 0 || busy.running >= concurrency as i64 {
+        return Ok(None);
+    }
+
+    const INSERT_QUERY: &str = "
+    insert into subgraphs.job_run(job, database, node_id)
+    values ($1, $2, $3)
+    returning id";
+    #[derive(QueryableByName)]
+    struct Id {
+        #[sql_type = "BigInt"]
+        id: i64,
+    }
+    let id = diesel::sql_query(INSERT_QUERY)
+        .bind::<Text, _>(job)
+        .bind::<Text, _>(database)
+        .bind::<Text, _>(node_id.as_str())
+        .get_result::<Id>(conn)?
+        .id;
+    Ok(Some(id))
+}
+
+/// Renew the lease of the job run `id`
+pub fn job_heartbeat(conn: &PgConnection, id: i64) -> Result<(), StoreError> {
+    diesel::sql_query(
+        "update subgraphs.job_run set heartbeat_at = now()
+          where id = $1 and finished_at is null",
+    )
+    .bind::<diesel::sql_types::BigInt, _>(id)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Record that the job run `id` finished, with `error` if it failed
+pub fn finish_job(conn: &PgConnection, id: i64, error: Option<&str>) -> Result<(), StoreError> {
+    use diesel::sql_types::{BigInt, Nullable};
+
+    diesel::sql_query("update subgraphs.job_run set finished_at = now(), error = $2 where id = $1")
+        .bind::<BigInt, _>(id)
+        .bind::<Nullable<Text>, _>(error)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The `limit` most recent runs of `job`, or of all jobs
+pub fn job_runs(
+    conn: &PgConnection,
+    job: Option<&str>,
+    limit: u32,
+) -> Result<Vec<JobRun>, StoreError> {
+    use diesel::sql_types::{BigInt, Nullable};
+
+    const QUERY: &str = "
+    select id, job, database, node_id,
+           to_char(started_at, 'YYYY-MM-DD HH24:MI:SS') as started_at,
+           to_char(finished_at, 'YYYY-MM-DD HH24:MI:SS') as finished_at,
+           error
+      from subgraphs.job_run
+     where ($1 is null or job = $1)
+     order by started_at desc, id desc
+     limit $2";
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Nullable<Text>, _>(job)
+        .bind::<BigInt, _>(limit as i64)
+        .load::<JobRun>(conn)?)
+}
+
+/// Delete the records of job runs that finished more than `max_age` ago
+pub fn prune_job_runs(conn: &PgConnection, max_age: Duration) -> Result<usize, StoreError> {
+    Ok(diesel::sql_query(
+        "delete from subgraphs.job_run
+          where finished_at < now() - make_interval(secs => $1)",
+    )
+    .bind::<diesel::sql_types::Double, _>(max_age.as_secs_f64())
+    .execute(conn)?)
+}
+
+/// A deployment that is unused, see `UNUSED_DEPLOYMENT`, and since when
+/// it is unused
+#[derive(Clone, Debug, QueryableByName)]
+pub struct UnusedDeployment {
+    #[sql_type = "Text"]
+    pub deployment: String,
+    /// When the deployment was first found to be unused
+    #[sql_type = "Text"]
+    pub unused_at: String,
+    /// The size of the deployment's tables and indexes in bytes when it
+    /// was found to be unused
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub size: i64,
+}
+
+/// Record the deployments that became unused since the last call, and
+/// forget those that are used again. Return how many deployments were newly
+/// recorded
+pub fn record_unused_deployments(conn: &PgConnection) -> Result<usize, StoreError> {
+    diesel::sql_query(format!(
+        "delete from subgraphs.unused_deployment u
+          where not exists (select 1 from subgraphs.subgraph_deployment d
+                             where d.id = u.deployment and {})",
+        UNUSED_DEPLOYMENT
+    ))
+    .execute(conn)?;
+
+    Ok(diesel::sql_query(format!(
+        "insert into subgraphs.unused_deployment(deployment, size)
+         select d.id,
+                (select coalesce(sum(pg_total_relation_size(c.oid)), 0)::int8
+                   from pg_class c, pg_namespace n
+                  where c.relnamespace = n.oid
+                    and n.nspname = ds.name
+                    and c.relkind = 'r')
+           from subgraphs.subgraph_deployment d, public.deployment_schemas ds
+          where ds.subgraph = d.id and {}
+         on conflict (deployment) do nothing",
+        UNUSED_DEPLOYMENT
+    ))
+    .execute(conn)?)
+}
+
+/// Forget that the deployment `id` and the base it is grafted onto were
+/// unused, since `id` is about to be used. Otherwise, a deployment that
+/// is used again only briefly between two runs of
+/// `record_unused_deployments` keeps its old `unused_at` and is removed
+/// too early once it is unused again
+fn forget_unused_deployment(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    diesel::sql_query(
+        "delete from subgraphs.unused_deployment u
+          where u.deployment = $1
+             or u.deployment = (select d.graft_base
+                                  from subgraphs.subgraph_deployment d
+                                 where d.id = $1)",
+    )
+    .bind::<Text, _>(id.as_str())
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The deployments that were recorded as unused, see
+/// `record_unused_deployments`, the oldest first
+pub fn unused_deployments(conn: &PgConnection) -> Result<Vec<UnusedDeployment>, StoreError> {
+    const QUERY: &str = "
+    select deployment,
+           to_char(unused_at, 'YYYY-MM-DD HH24:MI:SS') as unused_at,
+           size
+      from subgraphs.unused_deployment
+     order by unused_at, deployment";
+
+    Ok(diesel::sql_query(QUERY).load::<UnusedDeployment>(conn)?)
+}
+
+/// The deployments that were recorded as unused more than `retention` ago
+pub fn expired_unused_deployments(
+    conn: &PgConnection,
+    retention: Duration,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Unused {
+        #[sql_type = "Text"]
+        deployment: String,
+    }
+
+    Ok(diesel::sql_query(
+        "select deployment
+           from subgraphs.unused_deployment
+          where unused_at < now() - make_interval(secs => $1)
+          order by unused_at",
+    )
+    .bind::<diesel::sql_types::Double, _>(retention.as_secs_f64())
+    .load::<Unused>(conn)?
+    .into_iter()
+    .map(|unused| unused.deployment)
+    .collect())
+}
@@ -1,12 +1,16 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use graph::prelude::{
     ethabi,
     web3::types::{Address, H256},
-    BlockNumber, ChainHeadUpdateStream, ChainStore as ChainStoreTrait, CheapClone, Error,
-    EthereumBlock, EthereumBlockPointer, EthereumCallCache, Future, LightEthereumBlock, NodeId,
-    Schema, Store as StoreTrait, StoreError, Stream, SubgraphDeploymentEntity,
-    SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphName, SubgraphVersionSwitchingMode,
+    ApiKey, ApiKeyUsage, BlockNumber, BlockProofOfIndexing, ChainHeadStatus, ChainHeadUpdateStream,
+    ChainStore as ChainStoreTrait, CheapClone, DeploymentInfo, DeploymentSyncRate,
+    EntityAccessStats, EntityRange, EntityTypeCount, EntityVersion, Error, EthereumBlock,
+    EthereumBlockPointer, EthereumCallCache, Failover, Future, HandlerLog, LightEthereumBlock,
+    NodeId, NonCanonicalBlock, Schema, Store as StoreTrait, StoreError, Stream,
+    SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphName,
+    SubgraphVersionSwitchingMode,
 };
 
 use crate::chain_store::ChainStore;
@@ -73,6 +77,15 @@ impl StoreTrait for NetworkStore {
             .get_proof_of_indexing(subgraph_id, indexer, block_hash)
     }
 
+    fn get_block_proof_of_indexing(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockProofOfIndexing>, StoreError> {
+        self.store
+            .get_block_proof_of_indexing(subgraph_id, block_number)
+    }
+
     fn get(
         &self,
         key: graph::prelude::EntityKey,
@@ -105,13 +118,6 @@ impl StoreTrait for NetworkStore {
         self.store.find_one(query)
     }
 
-    fn find_ens_name(
-        &self,
-        hash: &str,
-    ) -> Result<Option<String>, graph::prelude::QueryExecutionError> {
-        self.store.find_ens_name(hash)
-    }
-
     fn transact_block_operations(
         &self,
         subgraph_id: graph::prelude::SubgraphDeploymentId,
@@ -208,6 +214,81 @@ impl StoreTrait for NetworkStore {
         self.store.cheap_clone().query_store(for_subscription)
     }
 
+    fn entity_counts(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityTypeCount>, StoreError> {
+        self.store.entity_counts(subgraph_id)
+    }
+
+    fn entity_access_stats(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityAccessStats>, StoreError> {
+        self.store.entity_access_stats(subgraph_id)
+    }
+
+    fn entity_history(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity_id: &str,
+        range: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError> {
+        self.store
+            .entity_history(subgraph_id, entity_type, entity_id, range)
+    }
+
+    fn deployment_sync_rate(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<DeploymentSyncRate, StoreError> {
+        self.store.deployment_sync_rate(subgraph_id)
+    }
+
+    fn chain_head_statuses(&self) -> Result<Vec<ChainHeadStatus>, StoreError> {
+        self.store.chain_head_statuses()
+    }
+
+    fn record_handler_logs(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        logs: Vec<HandlerLog>,
+    ) -> Result<(), StoreError> {
+        self.store.record_handler_logs(subgraph_id, logs)
+    }
+
+    fn handler_logs(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        levels: Vec<String>,
+        first: usize,
+    ) -> Result<Vec<HandlerLog>, StoreError> {
+        self.store.handler_logs(subgraph_id, block, levels, first)
+    }
+
+    fn changed_entities(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        block: BlockNumber,
+    ) -> Result<std::collections::BTreeMap<String, Vec<graph::prelude::Entity>>, StoreError> {
+        self.store
+            .changed_entities(subgraph_id, entity_types, block)
+    }
+
+    fn entity_change_blocks(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<std::collections::BTreeSet<BlockNumber>, StoreError> {
+        self.store
+            .entity_change_blocks(subgraph_id, entity_types, from, to)
+    }
+
     fn deployment_synced(&self, id: &graph::prelude::SubgraphDeploymentId) -> Result<(), Error> {
         self.store.deployment_synced(id)
     }
@@ -224,6 +305,87 @@ impl StoreTrait for NetworkStore {
         self.store.reassign_subgraph(id, node)
     }
 
+    fn unassign_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        self.store.unassign_subgraph(id)
+    }
+
+    fn rewind_failed_deployment(
+        &self,
+        id: &SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        self.store.rewind_failed_deployment(id, block_ptr_to)
+    }
+
+    fn deployment_infos(
+        &self,
+        name: Option<&SubgraphName>,
+        deployment: Option<&SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, StoreError> {
+        self.store.deployment_infos(name, deployment)
+    }
+
+    fn api_key(&self, secret: &str) -> Result<Option<ApiKey>, StoreError> {
+        self.store.api_key(secret)
+    }
+
+    fn record_api_key_usage(&self, usage: Vec<ApiKeyUsage>) -> Result<(), StoreError> {
+        self.store.record_api_key_usage(usage)
+    }
+
+    fn deployment_block_ptrs(
+        &self,
+        network: &str,
+    ) -> Result<Vec<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError> {
+        self.store.deployment_block_ptrs(network)
+    }
+
+    fn indexed_block_ptrs(
+        &self,
+        id: &SubgraphDeploymentId,
+        first: BlockNumber,
+        last: BlockNumber,
+    ) -> Result<Vec<EthereumBlockPointer>, StoreError> {
+        self.store.indexed_block_ptrs(id, first, last)
+    }
+
+    fn set_non_canonical_block(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: Option<NonCanonicalBlock>,
+    ) -> Result<(), StoreError> {
+        self.store.set_non_canonical_block(id, block)
+    }
+
+    fn non_canonical_block(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Option<NonCanonicalBlock>, StoreError> {
+        self.store.non_canonical_block(id)
+    }
+
+    fn record_heartbeat(
+        &self,
+        node_id: &NodeId,
+        failover_target: bool,
+        networks: &[String],
+    ) -> Result<(), StoreError> {
+        self.store
+            .record_heartbeat(node_id, failover_target, networks)
+    }
+
+    fn record_stop(&self, node_id: &NodeId) -> Result<(), StoreError> {
+        self.store.record_stop(node_id)
+    }
+
+    fn fail_over_dead_nodes(
+        &self,
+        timeout: Duration,
+        planned_downtime: Duration,
+    ) -> Result<Vec<Failover>, StoreError> {
+        self.store.fail_over_dead_nodes(timeout, planned_downtime)
+    }
+
     fn create_subgraph(&self, name: SubgraphName) -> Result<String, StoreError> {
         self.store.create_subgraph(name)
     }
@@ -250,6 +412,14 @@ impl SubgraphDeploymentStore for NetworkStore {
     ) -> Result<Option<String>, failure::Error> {
         self.store.network_name(subgraph_id)
     }
+
+    fn graft_preview(
+        &self,
+        base: &graph::prelude::SubgraphDeploymentId,
+        schema: &graph::prelude::Schema,
+    ) -> Result<graph::prelude::GraftPreview, failure::Error> {
+        self.store.graft_preview(base, schema)
+    }
 }
 
 impl EthereumCallCache for NetworkStore {
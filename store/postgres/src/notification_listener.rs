@@ -8,16 +8,18 @@ use postgres::{Connection, TlsMode};
 use std::env;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::{Arc, Barrier};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use futures::sync::mpsc::{channel, Receiver};
 use graph::prelude::serde_json;
 use graph::prelude::*;
 
 lazy_static! {
-    static ref LARGE_NOTIFICATION_CLEANUP_INTERVAL: Duration =
+    /// How long large notifications are kept. The `prune-large-notifications`
+    /// background job deletes older ones about this often
+    pub(crate) static ref LARGE_NOTIFICATION_CLEANUP_INTERVAL: Duration =
         env::var("LARGE_NOTIFICATION_CLEANUP_INTERVAL")
             .ok()
             .map(
@@ -306,35 +308,21 @@ impl JsonNotification {
 
             // Use the large_notifications row ID as the payload for NOTIFY
             select(pg_notify(channel, &payload_id.to_string())).execute(conn)?;
-
-            // Prune old large_notifications. We want to keep the size of the
-            // table manageable, but there's a lot of latitude in how often
-            // we need to clean up before old notifications slow down
-            // data access.
-            //
-            // To avoid checking whether cleanup is needed too often, we
-            // only check once every LARGE_NOTIFICATION_CLEANUP_INTERVAL
-            // (per graph-node process) It would be even better to do this
-            // cleanup only once for all graph-node processes accessing the
-            // same database, but that requires a lot more infrastructure
-            lazy_static! {
-                static ref LAST_CLEANUP_CHECK: Mutex<Instant> = Mutex::new(Instant::now());
-            }
-
-            // If we can't get the lock, another thread in this process is
-            // already checking, and we can just skip checking
-            if let Some(mut last_check) = LAST_CLEANUP_CHECK.try_lock().ok() {
-                if last_check.elapsed() > *LARGE_NOTIFICATION_CLEANUP_INTERVAL {
-                    diesel::sql_query(format!(
-                        "delete from large_notifications
-                         where created_at < current_timestamp - interval '{}s'",
-                        LARGE_NOTIFICATION_CLEANUP_INTERVAL.as_secs()
-                    ))
-                    .execute(conn)?;
-                    *last_check = Instant::now();
-                }
-            }
         }
         Ok(())
     }
+
+    /// Delete large notifications that were sent more than `max_age` ago
+    /// and return how many were deleted. Listeners read the payload right
+    /// after they are notified and do not need it any more after that
+    pub(crate) fn prune(conn: &PgConnection, max_age: Duration) -> Result<usize, StoreError> {
+        use diesel::RunQueryDsl;
+
+        Ok(diesel::sql_query(
+            "delete from large_notifications
+              where created_at < now() - make_interval(secs => $1)",
+        )
+        .bind::<diesel::sql_types::Double, _>(max_age.as_secs_f64())
+        .execute(conn)?)
+    }
 }
@@ -1,8 +1,9 @@
 use std::collections::BTreeMap;
+use std::time::Instant;
 
 use web3::types::H256;
 
-use crate::store::ReplicaId;
+use crate::store::{EntityAccess, ReplicaId};
 use graph::components::store::QueryStore as QueryStoreTrait;
 use graph::prelude::{Store as _, *};
 
@@ -35,7 +36,36 @@ impl QueryStoreTrait for QueryStore {
             .store
             .get_entity_conn(&query.subgraph_id, self.replica_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.store.execute_query(&conn, query)
+        let subgraph_id = query.subgraph_id.clone();
+        let entity_types: Vec<String> = match &query.collection {
+            EntityCollection::All(entity_types) => entity_types.clone(),
+            EntityCollection::Window(windows) => windows
+                .iter()
+                .map(|window| window.child_type.clone())
+                .collect(),
+        };
+        let _canceler = match &query.cancel {
+            Some(cancel) => {
+                cancel
+                    .check_cancel()
+                    .map_err(|_| QueryExecutionError::Timeout)?;
+                Some(
+                    self.store
+                        .pool(self.replica_id)
+                        .cancelable(conn.backend_pid()?, cancel),
+                )
+            }
+            None => None,
+        };
+        let start = Instant::now();
+        let values = self.store.execute_query(&conn, query);
+        self.store.record_entity_access(
+            &subgraph_id,
+            entity_types.iter().map(String::as_str),
+            EntityAccess::Query,
+            start.elapsed(),
+        );
+        values
     }
 
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
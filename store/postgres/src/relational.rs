@@ -7,6 +7,7 @@
 //! The pivotal struct in this module is the `Layout` which handles all the
 //! information about mapping a GraphQL schema to database tables
 use diesel::connection::SimpleConnection;
+use diesel::result::Error as DieselError;
 use diesel::{
     debug_query, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
 };
@@ -23,10 +24,12 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::relational_queries::{
-    self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
+    self as rq, ChangeBlockData, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
     DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-    FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+    FindChangeBlocksQuery, FindChangesQuery, FindManyQuery, FindQuery, InsertQuery,
+    RevertClampQuery, RevertRemoveQuery, UpdateQuery,
 };
+use crate::statement_cache::StatementCache;
 use graph::data::graphql::ext::{DocumentExt, ObjectTypeExt};
 use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
 use graph::data::store::BYTES_SCALAR;
@@ -35,9 +38,9 @@ use graph::data::subgraph::schema::{
 };
 use graph::prelude::{
     format_err, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
-    BLOCK_NUMBER_MAX,
+    EntityFilter, EntityKey, EntityOrder, EntityRange, EntityTypeCount, EntityVersion,
+    EthereumBlockPointer, GraftPreview, GraftTableAction, Logger, QueryExecutionError, StoreError,
+    StoreEvent, SubgraphDeploymentId, Value, ValueType, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::{BLOCK_RANGE_COLUMN, BLOCK_UNVERSIONED};
@@ -454,6 +457,29 @@ impl Layout {
             .collect()
     }
 
+    /// Describe what copying the data of `base` into `self` for a graft
+    /// will do with each entity type, without copying anything
+    pub fn graft_preview(&self, base: &Layout) -> GraftPreview {
+        let mut entity_types = BTreeMap::new();
+        for dst in self.tables.values().filter(|dst| dst.object != POI_OBJECT) {
+            let action = match base.table(&dst.name) {
+                None => GraftTableAction::Create,
+                Some(src) => dst.graft_action(src),
+            };
+            entity_types.insert(dst.object.clone(), action);
+        }
+        for src in base.tables.values().filter(|src| src.object != POI_OBJECT) {
+            if self.table(&src.name).is_none() {
+                entity_types.insert(src.object.clone(), GraftTableAction::Drop);
+            }
+        }
+        GraftPreview {
+            base: base.subgraph.clone(),
+            entity_types,
+            errors: self.can_copy_from(base),
+        }
+    }
+
     /// Generate the DDL for the entire layout, i.e., all `create table`
     /// and `create index` etc. statements needed in the database schema
     ///
@@ -464,19 +490,7 @@ impl Layout {
 
         // Output enums first
         for (name, values) in &self.enums {
-            let mut sep = "";
-            let name = SqlName::from(name.as_str());
-            write!(
-                out,
-                "create type {}.{}\n    as enum (",
-                self.catalog.schema,
-                name.quoted()
-            )?;
-            for value in values.iter() {
-                write!(out, "{}'{}'", sep, value)?;
-                sep = ", "
-            }
-            writeln!(out, ");")?;
+            self.enum_as_ddl(&mut out, name, values)?;
         }
         // We sort tables here solely because the unit tests rely on
         // 'create table' statements appearing in a fixed order
@@ -490,6 +504,70 @@ impl Layout {
         Ok(out)
     }
 
+    fn enum_as_ddl(&self, out: &mut String, name: &str, values: &BTreeSet<String>) -> fmt::Result {
+        let mut sep = "";
+        let name = SqlName::from(name);
+        write!(
+            out,
+            "create type {}.{}\n    as enum (",
+            self.catalog.schema,
+            name.quoted()
+        )?;
+        for value in values.iter() {
+            write!(out, "{}'{}'", sep, value)?;
+            sep = ", "
+        }
+        writeln!(out, ");")
+    }
+
+    /// Determine whether `self` can be turned into `new` without touching
+    /// any of the existing data, i.e., whether `new` only adds entity
+    /// types, enums, and nullable attributes to `self`. Returns a list of
+    /// the changes that make that impossible. An empty vector indicates
+    /// that `self` can be extended to `new` with `extension_ddl`
+    pub fn can_extend_to(&self, new: &Layout) -> Vec<String> {
+        let mut errors = vec![];
+        for (name, values) in &self.enums {
+            match new.enums.get(name) {
+                Some(new_values) if new_values == values => (),
+                Some(_) => errors.push(format!("The values of the enum {} changed", name)),
+                None => errors.push(format!("The enum {} was removed", name)),
+            }
+        }
+        let mut tables = self.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        for table in tables {
+            match new.table(&table.name) {
+                Some(new_table) => errors.extend(table.can_extend_to(new_table)),
+                None => errors.push(format!("The entity type {} was removed", table.object)),
+            }
+        }
+        errors
+    }
+
+    /// Generate the DDL that turns the tables for `self` into those for
+    /// `new`. That is only possible if `can_extend_to` does not report any
+    /// errors for `new`
+    pub fn extension_ddl(&self, new: &Layout) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+
+        for (name, values) in &new.enums {
+            if !self.enums.contains_key(name) {
+                new.enum_as_ddl(&mut out, name, values)?;
+            }
+        }
+        let mut tables = new.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        for table in tables {
+            match self.table(&table.name) {
+                Some(old) => table.extension_ddl(&mut out, new, old)?,
+                None => table.as_ddl(&mut out, new)?,
+            }
+        }
+
+        Ok(out)
+    }
+
     /// Find the table with the provided `name`. The name must exactly match
     /// the name of an existing table. No conversions of the name are done
     pub fn table(&self, name: &SqlName) -> Option<&Table> {
@@ -520,6 +598,23 @@ impl Layout {
             .transpose()
     }
 
+    /// Find the versions of the entity of type `entity` with id `id` that
+    /// fall into `range`, the oldest first
+    pub fn entity_history(
+        &self,
+        conn: &PgConnection,
+        entity: &str,
+        id: &str,
+        range: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError> {
+        let table = self.table_for_entity(entity)?;
+        rq::EntityHistoryQuery::new(table.as_ref(), id, range)
+            .get_results::<rq::EntityVersionData>(conn)?
+            .into_iter()
+            .map(|version| version.deserialize_with_layout(self))
+            .collect()
+    }
+
     pub fn find_many(
         &self,
         conn: &PgConnection,
@@ -546,6 +641,63 @@ impl Layout {
         Ok(entities_for_type)
     }
 
+    /// Find the versions of entities of the given types that were written
+    /// at exactly `block`. The entities for each type are sorted by id
+    pub fn find_changes(
+        &self,
+        conn: &PgConnection,
+        entity_types: &[String],
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        let mut tables = Vec::new();
+        for entity_type in entity_types {
+            tables.push(self.table_for_entity(entity_type)?.as_ref());
+        }
+        if tables.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let mut entities_for_type: BTreeMap<String, Vec<(String, Entity)>> = BTreeMap::new();
+        for data in FindChangesQuery::new(tables, block).load::<EntityData>(conn)? {
+            let entity_type = data.entity_type();
+            let entity: Entity = data.deserialize_with_layout(self)?;
+            entities_for_type
+                .entry(entity_type)
+                .or_default()
+                .push((entity.id()?, entity));
+        }
+        Ok(entities_for_type
+            .into_iter()
+            .map(|(entity_type, mut entities)| {
+                entities.sort_by(|(a, _), (b, _)| a.cmp(b));
+                let entities = entities.into_iter().map(|(_, entity)| entity).collect();
+                (entity_type, entities)
+            })
+            .collect())
+    }
+
+    pub fn find_change_blocks(
+        &self,
+        conn: &PgConnection,
+        entity_types: &[String],
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError> {
+        let mut tables = Vec::new();
+        for entity_type in entity_types {
+            tables.push(self.table_for_entity(entity_type)?.as_ref());
+        }
+        if tables.is_empty() || from > to {
+            return Ok(BTreeSet::new());
+        }
+
+        Ok(FindChangeBlocksQuery { tables, from, to }
+            .load::<ChangeBlockData>(conn)?
+            .into_iter()
+            .map(|data| data.block)
+            .collect())
+    }
+
     pub fn insert(
         &self,
         conn: &PgConnection,
@@ -594,6 +746,7 @@ impl Layout {
         range: EntityRange,
         block: BlockNumber,
         query_id: Option<String>,
+        statements: Option<&StatementCache>,
     ) -> Result<Vec<T>, QueryExecutionError> {
         fn log_query_timing(
             logger: &Logger,
@@ -625,8 +778,8 @@ impl Layout {
             );
         }
 
-        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref())?;
-        let query = FilterQuery::new(
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
+        let mut query = FilterQuery::new(
             &filter_collection,
             filter.as_ref(),
             order,
@@ -634,15 +787,25 @@ impl Layout {
             block,
             query_id,
         )?;
+        if let Some(statements) = statements {
+            statements.prepare(&self.subgraph, &mut query)?;
+        }
         let query_clone = query.clone();
 
         let start = Instant::now();
-        let values = query.load::<EntityData>(conn).map_err(|e| {
-            QueryExecutionError::ResolveEntitiesError(format!(
+        let values = query.load::<EntityData>(conn).map_err(|e| match e {
+            // Postgres cancels the statement when it runs into the
+            // statement timeout or when the GraphQL query was canceled
+            DieselError::DatabaseError(_, ref info)
+                if info.message().starts_with("canceling statement") =>
+            {
+                QueryExecutionError::Timeout
+            }
+            e => QueryExecutionError::ResolveEntitiesError(format!(
                 "{}, query = {:?}",
                 e,
                 debug_query(&query_clone).to_string()
-            ))
+            )),
         })?;
         log_query_timing(logger, &query_clone, start.elapsed(), values.len());
         values
@@ -772,6 +935,44 @@ impl Layout {
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Count the current entities and the entity versions for each entity
+    /// type. This requires a scan of every table in the deployment
+    pub fn entity_counts(&self, conn: &PgConnection) -> Result<Vec<EntityTypeCount>, StoreError> {
+        #[derive(QueryableByName)]
+        struct Counts {
+            #[sql_type = "diesel::sql_types::BigInt"]
+            current: i64,
+            #[sql_type = "diesel::sql_types::BigInt"]
+            versions: i64,
+        }
+
+        let mut tables: Vec<_> = self
+            .tables
+            .values()
+            .filter(|table| table.object != POI_OBJECT)
+            .collect();
+        tables.sort_by(|a, b| a.object.cmp(&b.object));
+
+        tables
+            .into_iter()
+            .map(|table| {
+                let query = format!(
+                    "select count(*) filter (where upper_inf({block_range})) as current, \
+                            count(*) as versions \
+                       from {table}",
+                    block_range = BLOCK_RANGE_COLUMN,
+                    table = table.qualified_name
+                );
+                let counts = diesel::sql_query(query).get_result::<Counts>(conn)?;
+                Ok(EntityTypeCount {
+                    entity_type: table.object.clone(),
+                    current: counts.current as u64,
+                    versions: counts.versions as u64,
+                })
+            })
+            .collect()
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`. This function can only be called on the `Layout`
     /// for the metadata subgraph.
@@ -1244,6 +1445,80 @@ impl Table {
             .collect()
     }
 
+    /// Determine how the data in `source` needs to be changed when it is
+    /// copied into `self`
+    fn graft_action(&self, source: &Self) -> GraftTableAction {
+        // Fulltext columns are computed from other columns and never copied
+        let dropped: Vec<_> = source
+            .columns
+            .iter()
+            .filter(|scol| !scol.is_fulltext() && self.column(&scol.name).is_none())
+            .map(|scol| scol.field.clone())
+            .collect();
+        let mut added = vec![];
+        let mut converted = vec![];
+        for dcol in self.columns.iter().filter(|dcol| !dcol.is_fulltext()) {
+            match source.column(&dcol.name) {
+                None => added.push(dcol.field.clone()),
+                Some(scol) if scol.column_type != dcol.column_type => {
+                    converted.push(dcol.field.clone())
+                }
+                Some(_) => { /* copied as-is */ }
+            }
+        }
+        if dropped.is_empty() && added.is_empty() && converted.is_empty() {
+            GraftTableAction::Copy
+        } else {
+            GraftTableAction::Transform {
+                dropped,
+                added,
+                converted,
+            }
+        }
+    }
+
+    /// Check that `new` keeps all our attributes as they are, and that
+    /// the attributes it adds are nullable
+    fn can_extend_to(&self, new: &Self) -> Vec<String> {
+        let mut errors = vec![];
+        for col in &self.columns {
+            match new.columns.iter().find(|ncol| ncol.name == col.name) {
+                None => errors.push(format!(
+                    "The attribute {}.{} was removed",
+                    self.object, col.field
+                )),
+                Some(ncol)
+                    if ncol.field_type != col.field_type
+                        || ncol.column_type != col.column_type
+                        || ncol.fulltext_fields != col.fulltext_fields =>
+                {
+                    errors.push(format!(
+                        "The attribute {}.{} has type {}, but used to have type {}",
+                        self.object, col.field, ncol.field_type, col.field_type
+                    ))
+                }
+                Some(_) => (),
+            }
+        }
+        for ncol in &new.columns {
+            if self.columns.iter().any(|col| col.name == ncol.name) {
+                continue;
+            }
+            if ncol.is_fulltext() {
+                errors.push(format!(
+                    "The fulltext field {} can not be added to the existing entity type {}",
+                    ncol.field, self.object
+                ))
+            } else if !ncol.is_nullable() {
+                errors.push(format!(
+                    "The attribute {}.{} is new, but non-nullable",
+                    self.object, ncol.field
+                ))
+            }
+        }
+        errors
+    }
+
     pub fn primary_key(&self) -> &Column {
         self.columns
             .iter()
@@ -1318,54 +1593,97 @@ impl Table {
             block_max = BLOCK_NUMBER_MAX
         )?;
 
-        // Create indexes. Skip columns whose type is an array of enum,
-        // since there is no good way to index them with Postgres 9.6.
-        // Once we move to Postgres 11, we can enable that
-        // (tracked in graph-node issue #1330)
-        for (i, column) in self
-            .columns
+        // Create indexes
+        for (i, column) in self.indexed_columns(layout) {
+            self.index_as_ddl(out, layout, i, column)?;
+        }
+        writeln!(out)
+    }
+
+    /// Generate the DDL that adds the attributes of `self` that `old` does
+    /// not have to the existing table for `old`, together with their
+    /// indexes
+    fn extension_ddl(&self, out: &mut String, layout: &Layout, old: &Table) -> fmt::Result {
+        let is_new = |column: &Column| !old.columns.iter().any(|col| col.name == column.name);
+
+        for column in self.columns.iter().filter(|column| is_new(column)) {
+            let mut column_ddl = String::new();
+            column.as_ddl(&mut column_ddl)?;
+            writeln!(
+                out,
+                "alter table {}.{} add column {};",
+                layout.catalog.schema,
+                self.name.quoted(),
+                column_ddl.trim()
+            )?;
+        }
+        for (i, column) in self.indexed_columns(layout) {
+            if is_new(column) {
+                self.index_as_ddl(out, layout, i, column)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The columns that get an attribute index, together with their
+    /// position amongst them. Skip columns whose type is an array of enum
+    /// unless the database runs Postgres 11 or later, since there is no
+    /// good way to index them with Postgres 9.6 (see graph-node
+    /// issue #1330)
+    fn indexed_columns<'a>(
+        &'a self,
+        layout: &Layout,
+    ) -> impl Iterator<Item = (usize, &'a Column)> + 'a {
+        let index_enum_arrays = layout.catalog.can_index_enum_arrays();
+        self.columns
             .iter()
-            .filter(|col| !(col.is_list() && col.is_enum()))
+            .filter(move |col| index_enum_arrays || !(col.is_list() && col.is_enum()))
             .enumerate()
-        {
-            let (method, index_expr) = if column.is_reference() && !column.is_list() {
-                // For foreign keys, index the key together with the block range
-                // since we almost always also have a block_range clause in
-                // queries that look for specific foreign keys
-                let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
-                ("gist", index_expr)
+    }
+
+    fn index_as_ddl(
+        &self,
+        out: &mut String,
+        layout: &Layout,
+        i: usize,
+        column: &Column,
+    ) -> fmt::Result {
+        let (method, index_expr) = if column.is_reference() && !column.is_list() {
+            // For foreign keys, index the key together with the block range
+            // since we almost always also have a block_range clause in
+            // queries that look for specific foreign keys
+            let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
+            ("gist", index_expr)
+        } else {
+            // Attributes that are plain strings are indexed with a BTree; but
+            // they can be too large for Postgres' limit on values that can go
+            // into a BTree. For those attributes, only index the first
+            // STRING_PREFIX_SIZE characters
+            let index_expr = if column.is_text() {
+                format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
             } else {
-                // Attributes that are plain strings are indexed with a BTree; but
-                // they can be too large for Postgres' limit on values that can go
-                // into a BTree. For those attributes, only index the first
-                // STRING_PREFIX_SIZE characters
-                let index_expr = if column.is_text() {
-                    format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
-                } else {
-                    column.name.quoted()
-                };
-
-                let method = if column.is_list() || column.is_fulltext() {
-                    "gin"
-                } else {
-                    "btree"
-                };
-
-                (method, index_expr)
+                column.name.quoted()
             };
-            write!(
-                out,
-                "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
-                table_index = self.position,
-                table_name = self.name,
-                column_index = i,
-                column_name = column.name,
-                schema_name = layout.catalog.schema,
-                method = method,
-                index_expr = index_expr,
-            )?;
-        }
-        writeln!(out)
+
+            let method = if column.is_list() || column.is_fulltext() {
+                "gin"
+            } else {
+                "btree"
+            };
+
+            (method, index_expr)
+        };
+        write!(
+            out,
+            "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
+            table_index = self.position,
+            table_name = self.name,
+            column_index = i,
+            column_name = column.name,
+            schema_name = layout.catalog.schema,
+            method = method,
+            index_expr = index_expr,
+        )
     }
 }
 
@@ -1466,6 +1784,27 @@ mod tests {
         assert!(column.is_enum());
     }
 
+    #[test]
+    fn enum_array_index() {
+        const GQL: &str = "
+            type Thing @entity { id: ID!, orientations: [Orientation!]! }
+            enum Orientation { UP, DOWN }";
+        const INDEX: &str = "create index attr_0_1_thing_orientations\n    \
+                             on rel.\"thing\" using gin(\"orientations\");\n";
+
+        let layout = test_layout(GQL);
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+        assert!(!sql.contains(INDEX));
+
+        let subgraph = SubgraphDeploymentId::new("subgraph").unwrap();
+        let schema = Schema::parse(GQL, subgraph).expect("Test schema invalid");
+        let mut catalog = Catalog::make_empty("rel".to_owned()).expect("Can not create catalog");
+        catalog.server_version = 110009;
+        let layout = Layout::new(&schema, catalog, false).expect("Failed to construct Layout");
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+        assert!(sql.contains(INDEX));
+    }
+
     #[test]
     fn can_copy_from() {
         let source = test_layout(THING_GQL);
@@ -1529,6 +1868,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn graft_preview() {
+        let source = test_layout(THING_GQL);
+        let preview = source.graft_preview(&source);
+        assert!(preview.errors.is_empty());
+        assert!(preview
+            .entity_types
+            .values()
+            .all(|action| action == &GraftTableAction::Copy));
+
+        let dest = test_layout(
+            "enum Color { yellow, red, BLUE, green }
+             type Scalar { id: ID, int: Int, color: Color, extra: String }
+             type Other { id: ID }",
+        );
+        let preview = dest.graft_preview(&source);
+        assert!(preview.errors.is_empty());
+
+        let strings =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|name| name.to_string()).collect() };
+        let mut expected = BTreeMap::new();
+        expected.insert("Thing".to_owned(), GraftTableAction::Drop);
+        expected.insert("Other".to_owned(), GraftTableAction::Create);
+        expected.insert(
+            "Scalar".to_owned(),
+            GraftTableAction::Transform {
+                dropped: strings(&["bool", "bigDecimal", "string", "bytes", "bigInt"]),
+                added: strings(&["extra"]),
+                converted: strings(&["color"]),
+            },
+        );
+        assert_eq!(expected, preview.entity_types);
+    }
+
+    #[test]
+    fn can_extend_to() {
+        let base = test_layout(THING_GQL);
+        // An identical layout does not need any changes
+        assert!(base.can_extend_to(&base).is_empty());
+        assert_eq!(
+            "",
+            base.extension_ddl(&base).expect("Failed to generate DDL")
+        );
+
+        // We allow adding types and nullable attributes
+        let new = test_layout(&format!(
+            "{} type Other @entity {{ id: ID!, size: Size }}",
+            THING_GQL.replace("color: Color,", "color: Color, extra: String,")
+        ));
+        assert!(base.can_extend_to(&new).is_empty());
+        let sql = base.extension_ddl(&new).expect("Failed to generate DDL");
+        assert!(sql.contains("alter table rel.\"scalar\" add column \"extra\""));
+        assert!(sql.contains("on rel.\"scalar\" using btree(left(\"extra\", 256));"));
+        assert!(sql.contains("create table rel.\"other\" ("));
+        assert!(!sql.contains("create table rel.\"thing\""));
+
+        // We can not remove types or attributes, or change their types
+        let new = test_layout(
+            "enum Color { yellow, red, BLUE }
+             type Scalar @entity { id: ID, bool: Boolean, int: Int!, color: Color }",
+        );
+        assert_eq!(
+            vec![
+                "The enum Size was removed",
+                "The entity type Thing was removed",
+                "The attribute Scalar.int has type Int!, but used to have type Int",
+                "The attribute Scalar.bigDecimal was removed",
+                "The attribute Scalar.string was removed",
+                "The attribute Scalar.bytes was removed",
+                "The attribute Scalar.bigInt was removed",
+            ],
+            base.can_extend_to(&new)
+        );
+
+        // New attributes must be nullable
+        let new = test_layout(&THING_GQL.replace("color: Color,", "color: Color, extra: String!,"));
+        assert_eq!(
+            vec!["The attribute Scalar.extra is new, but non-nullable"],
+            base.can_extend_to(&new)
+        );
+    }
+
     const THING_GQL: &str = "
         type Thing @entity {
             id: ID!
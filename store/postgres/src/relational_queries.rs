@@ -9,7 +9,7 @@ use diesel::pg::{Pg, PgConnection};
 use diesel::query_builder::{AstPass, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Nullable, Range, Text};
 use diesel::Connection;
 use lazy_static::lazy_static;
 use std::collections::{BTreeMap, HashSet};
@@ -21,9 +21,9 @@ use std::str::FromStr;
 
 use graph::data::{schema::FulltextAlgorithm, store::scalar};
 use graph::prelude::{
-    format_err, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
-    EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
-    QueryExecutionError, StoreError, Value,
+    format_err, serde_json, Attribute, BlockNumber, ChildFilter, ChildMultiplicity, Entity,
+    EntityCollection, EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityVersion,
+    EntityWindow, ParentLink, QueryExecutionError, StoreError, Value,
 };
 
 use crate::block_range::{
@@ -460,7 +460,6 @@ struct QueryValue<'a>(&'a Value, &'a ColumnType);
 
 impl<'a> QueryFragment<Pg> for QueryValue<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
-        out.unsafe_to_cache_prepared();
         let column_type = self.1;
 
         match self.0 {
@@ -714,29 +713,57 @@ impl<'a> QueryFragment<Pg> for PrefixComparison<'a> {
     }
 }
 
+/// Push `{alias}.{column}`
+fn push_column(alias: &str, column: &Column, out: &mut AstPass<Pg>) -> QueryResult<()> {
+    out.push_sql(alias);
+    out.push_sql(".");
+    out.push_identifier(column.name.as_str())
+}
+
 /// A `QueryFilter` adds the conditions represented by the `filter` to
 /// the `where` clause of a SQL query. The attributes mentioned in
 /// the `filter` must all come from the given `table`, which is used to
 /// map GraphQL names to column names, and to determine the type of the
-/// column an attribute refers to
+/// column an attribute refers to. Filters on child entities are checked
+/// against the tables for those entities in `layout`
 #[derive(Debug, Clone)]
 pub struct QueryFilter<'a> {
     filter: &'a EntityFilter,
     table: &'a Table,
+    layout: &'a Layout,
+    block: BlockNumber,
+    /// How many child filters enclose this filter; used to give each
+    /// nested subquery its own table alias
+    depth: usize,
 }
 
 impl<'a> QueryFilter<'a> {
-    pub fn new(filter: &'a EntityFilter, table: &'a Table) -> Result<Self, StoreError> {
-        Self::valid_attributes(filter, table)?;
-        Ok(QueryFilter { filter, table })
+    pub fn new(
+        filter: &'a EntityFilter,
+        table: &'a Table,
+        layout: &'a Layout,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        Self::valid_attributes(filter, table, layout)?;
+        Ok(QueryFilter {
+            filter,
+            table,
+            layout,
+            block,
+            depth: 0,
+        })
     }
 
-    fn valid_attributes(filter: &'a EntityFilter, table: &'a Table) -> Result<(), StoreError> {
+    fn valid_attributes(
+        filter: &'a EntityFilter,
+        table: &'a Table,
+        layout: &'a Layout,
+    ) -> Result<(), StoreError> {
         use EntityFilter::*;
         match filter {
             And(filters) | Or(filters) => {
                 for filter in filters {
-                    Self::valid_attributes(filter, table)?;
+                    Self::valid_attributes(filter, table, layout)?;
                 }
             }
 
@@ -756,6 +783,16 @@ impl<'a> QueryFilter<'a> {
             | NotEndsWith(attr, _) => {
                 table.column_for_field(attr)?;
             }
+
+            Child(child) => {
+                let child_table = layout.table_for_entity(&child.entity_type)?;
+                if child.derived {
+                    child_table.column_for_field(&child.attr)?;
+                } else {
+                    table.column_for_field(&child.attr)?;
+                }
+                Self::valid_attributes(&child.filter, child_table, layout)?;
+            }
         }
         Ok(())
     }
@@ -764,6 +801,18 @@ impl<'a> QueryFilter<'a> {
         QueryFilter {
             filter,
             table: self.table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth,
+        }
+    }
+
+    /// The alias under which the table for this filter appears in the
+    /// query. The outermost table is always aliased as `c`
+    fn alias(&self) -> String {
+        match self.depth {
+            0 => "c".to_owned(),
+            depth => format!("i{}", depth),
         }
     }
 
@@ -773,6 +822,72 @@ impl<'a> QueryFilter<'a> {
             .expect("the constructor already checked that all attribute names are valid")
     }
 
+    /// Generate
+    ///     exists (select 1
+    ///               from {child_table} i
+    ///              where {join condition}
+    ///                and i.block_range @> $block
+    ///                and {child filter})
+    /// where the join condition connects the child table `i` to the
+    /// table `c` of this filter. For a reference stored in the parent, it
+    /// is `i.id = c.{attr}` or `i.id = any(c.{attr})`; for a derived field
+    /// it is `i.{attr} = c.id` or `c.id = any(i.{attr})`
+    fn child(&self, child: &ChildFilter, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let child_table = self
+            .layout
+            .table_for_entity(&child.entity_type)
+            .expect("the constructor already checked that all child entity types are valid");
+        let child_filter = QueryFilter {
+            filter: &child.filter,
+            table: child_table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth + 1,
+        };
+        let parent_alias = self.alias();
+        let child_alias = child_filter.alias();
+
+        out.push_sql("exists (select 1 from ");
+        out.push_sql(child_table.qualified_name.as_str());
+        out.push_sql(" ");
+        out.push_sql(&child_alias);
+        out.push_sql(" where ");
+        if child.derived {
+            let column = child_table
+                .column_for_field(&child.attr)
+                .expect("the constructor already checked that all attribute names are valid");
+            let primary_key = self.table.primary_key();
+            if column.is_list() {
+                push_column(&parent_alias, primary_key, &mut out)?;
+                out.push_sql(" = any(");
+                push_column(&child_alias, column, &mut out)?;
+                out.push_sql(")");
+            } else {
+                push_column(&child_alias, column, &mut out)?;
+                out.push_sql(" = ");
+                push_column(&parent_alias, primary_key, &mut out)?;
+            }
+        } else {
+            let column = self.column(&child.attr);
+            push_column(&child_alias, child_table.primary_key(), &mut out)?;
+            if column.is_list() {
+                out.push_sql(" = any(");
+                push_column(&parent_alias, column, &mut out)?;
+                out.push_sql(")");
+            } else {
+                out.push_sql(" = ");
+                push_column(&parent_alias, column, &mut out)?;
+            }
+        }
+        out.push_sql(" and ");
+        let prefix = format!("{}.", child_alias);
+        BlockRangeContainsClause::new(child_table, &prefix, self.block).walk_ast(out.reborrow())?;
+        out.push_sql(" and ");
+        child_filter.walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn binary_op(
         &self,
         filters: &Vec<EntityFilter>,
@@ -1055,8 +1170,6 @@ impl<'a> QueryFilter<'a> {
 
 impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
-        out.unsafe_to_cache_prepared();
-
         use Comparison as c;
         use EntityFilter::*;
         match &self.filter {
@@ -1087,6 +1200,8 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", false, out)?
             }
+
+            Child(child) => self.child(child, out)?,
         }
         Ok(())
     }
@@ -1132,6 +1247,87 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindQuery<'a> {}
 
+/// One version of an entity as returned by `EntityHistoryQuery`
+#[derive(QueryableByName, Debug)]
+pub struct EntityVersionData {
+    #[sql_type = "Text"]
+    entity: String,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+    #[sql_type = "Integer"]
+    block_start: i32,
+    #[sql_type = "Nullable<Integer>"]
+    block_end: Option<i32>,
+}
+
+impl EntityVersionData {
+    /// Map the version using the schema information in `Layout`
+    pub fn deserialize_with_layout(self, layout: &Layout) -> Result<EntityVersion, StoreError> {
+        let entity_data = EntityData {
+            entity: self.entity,
+            data: self.data,
+        };
+        Ok(EntityVersion {
+            block_start: self.block_start,
+            block_end: self.block_end,
+            data: entity_data.deserialize_with_layout(layout)?,
+        })
+    }
+}
+
+/// A query that returns the versions of one entity in `range`, the oldest
+/// first
+#[derive(Debug, Clone, Constructor)]
+pub struct EntityHistoryQuery<'a> {
+    table: &'a Table,
+    id: &'a str,
+    range: EntityRange,
+}
+
+impl<'a> QueryFragment<Pg> for EntityHistoryQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select '..' as entity, to_jsonb(e.*) as data,
+        //           lower(e.block_range) as block_start,
+        //           upper(e.block_range) as block_end
+        //      from schema.table e where id = $1
+        //     order by lower(e.block_range)
+        //    [limit $first] [offset $skip]
+        out.push_sql("select ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(" as entity, to_jsonb(e.*) as data,\n");
+        out.push_sql("       lower(e.");
+        out.push_sql(BLOCK_RANGE_COLUMN);
+        out.push_sql(") as block_start, upper(e.");
+        out.push_sql(BLOCK_RANGE_COLUMN);
+        out.push_sql(") as block_end\n");
+        out.push_sql("  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" e\n where ");
+        self.table.primary_key().eq(&self.id, &mut out)?;
+        out.push_sql("\n order by lower(e.");
+        out.push_sql(BLOCK_RANGE_COLUMN);
+        out.push_sql(")");
+        BoundFilterRange(&self.range).walk_ast(out)
+    }
+}
+
+impl<'a> QueryId for EntityHistoryQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityVersionData> for EntityHistoryQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityVersionData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for EntityHistoryQuery<'a> {}
+
 #[derive(Debug, Clone, Constructor)]
 pub struct FindManyQuery<'a> {
     pub(crate) schema: &'a str,
@@ -1188,6 +1384,114 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindManyQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindManyQuery<'a> {}
 
+/// A query that finds all entity versions that were written at exactly
+/// `block`, i.e., the entities that were inserted or updated at that block
+#[derive(Debug, Clone, Constructor)]
+pub struct FindChangesQuery<'a> {
+    pub(crate) tables: Vec<&'a Table>,
+    pub(crate) block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for FindChangesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select $object0 as entity, to_jsonb(e.*) as data
+        //      from schema.<table0> e where lower(e.block_range) = $block
+        //    union all
+        //    ...
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            out.push_sql("select ");
+            out.push_bind_param::<Text, _>(&table.object)?;
+            out.push_sql(" as entity, to_jsonb(e.*) as data\n");
+            out.push_sql("  from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" e\n where lower(e.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") = ");
+            out.push_bind_param::<Integer, _>(&self.block)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for FindChangesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityData> for FindChangesQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for FindChangesQuery<'a> {}
+
+#[derive(QueryableByName)]
+pub struct ChangeBlockData {
+    #[sql_type = "Integer"]
+    pub block: BlockNumber,
+}
+
+/// Find the numbers of the blocks in the range `from..=to` at which
+/// entities in any of `tables` were inserted or updated
+#[derive(Debug, Clone)]
+pub struct FindChangeBlocksQuery<'a> {
+    pub(crate) tables: Vec<&'a Table>,
+    pub(crate) from: BlockNumber,
+    pub(crate) to: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for FindChangeBlocksQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select lower(e.block_range) as block
+        //      from schema.<table0> e
+        //     where lower(e.block_range) between $from and $to
+        //    union
+        //    ...
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion\n");
+            }
+            out.push_sql("select lower(e.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") as block\n");
+            out.push_sql("  from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" e\n where lower(e.");
+            out.push_identifier(BLOCK_RANGE_COLUMN)?;
+            out.push_sql(") between ");
+            out.push_bind_param::<Integer, _>(&self.from)?;
+            out.push_sql(" and ");
+            out.push_bind_param::<Integer, _>(&self.to)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for FindChangeBlocksQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, ChangeBlockData> for FindChangeBlocksQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ChangeBlockData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for FindChangeBlocksQuery<'a> {}
+
 #[derive(Debug, Clone)]
 pub struct InsertQuery<'a> {
     table: &'a Table,
@@ -1604,6 +1908,7 @@ impl<'a> FilterWindow<'a> {
         layout: &'a Layout,
         window: EntityWindow,
         query_filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         let EntityWindow {
             child_type,
@@ -1612,7 +1917,7 @@ impl<'a> FilterWindow<'a> {
         } = window;
         let table = layout.table_for_entity(&child_type).map(|rc| rc.as_ref())?;
         let query_filter = query_filter
-            .map(|filter| QueryFilter::new(filter, table))
+            .map(|filter| QueryFilter::new(filter, table, layout, block))
             .transpose()?;
         let link = TableLink::new(table, link)?;
         Ok(FilterWindow {
@@ -1913,6 +2218,7 @@ impl<'a> FilterCollection<'a> {
         layout: &'a Layout,
         collection: EntityCollection,
         filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         match collection {
             EntityCollection::All(entities) => {
@@ -1928,7 +2234,7 @@ impl<'a> FilterCollection<'a> {
                             .map(|rc| rc.as_ref())
                             .and_then(|table| {
                                 filter
-                                    .map(|filter| QueryFilter::new(filter, table))
+                                    .map(|filter| QueryFilter::new(filter, table, layout, block))
                                     .transpose()
                                     .map(|filter| (table, filter))
                             })
@@ -1939,7 +2245,7 @@ impl<'a> FilterCollection<'a> {
             EntityCollection::Window(windows) => {
                 let windows = windows
                     .into_iter()
-                    .map(|window| FilterWindow::new(layout, window, filter))
+                    .map(|window| FilterWindow::new(layout, window, filter, block))
                     .collect::<Result<Vec<_>, _>>()?;
                 let collection = if windows.len() == 1 {
                     let mut windows = windows;
@@ -2142,6 +2448,26 @@ impl<'a> SortKey<'a> {
 #[derive(Debug, Clone)]
 pub struct FilterRange(EntityRange);
 
+/// Generate `[limit $first] [offset $skip]`, i.e., a range whose values
+/// are passed as bind variables so that the SQL text of the query does not
+/// depend on them
+struct BoundFilterRange<'a>(&'a EntityRange);
+
+impl<'a> QueryFragment<Pg> for BoundFilterRange<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let range = self.0;
+        if let Some(first) = &range.first {
+            out.push_sql("\n limit ");
+            out.push_bind_param::<BigInt, _>(&(*first as i64))?;
+        }
+        if range.skip > 0 {
+            out.push_sql("\noffset ");
+            out.push_bind_param::<BigInt, _>(&(range.skip as i64))?;
+        }
+        Ok(())
+    }
+}
+
 impl QueryFragment<Pg> for FilterRange {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         let range = &self.0;
@@ -2157,6 +2483,35 @@ impl QueryFragment<Pg> for FilterRange {
     }
 }
 
+impl FilterRange {
+    /// Generate `limit {first + skip}`, the most rows that one branch of a
+    /// `union all` can contribute to the result of applying this range to
+    /// the rows of all branches
+    fn branch_limit(&self, prepared: bool, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        let range = &self.0;
+        if let Some(first) = &range.first {
+            let limit = *first as u64 + range.skip as u64;
+            out.push_sql("\n limit ");
+            if prepared {
+                out.push_bind_param::<BigInt, _>(&(limit as i64))?;
+            } else {
+                out.push_sql(&limit.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate the range, with bind variables for its values if the query
+    /// it is part of will be prepared
+    fn walk_ast_for(&self, prepared: bool, out: AstPass<Pg>) -> QueryResult<()> {
+        if prepared {
+            BoundFilterRange(&self.0).walk_ast(out)
+        } else {
+            self.walk_ast(out)
+        }
+    }
+}
+
 /// The parallel to `EntityQuery`.
 ///
 /// Details of how query generation for `FilterQuery` works can be found
@@ -2168,6 +2523,9 @@ pub struct FilterQuery<'a> {
     range: FilterRange,
     block: BlockNumber,
     query_id: Option<String>,
+    /// Whether Diesel may prepare this query and cache the prepared
+    /// statement on the connection
+    prepared: bool,
 }
 
 impl<'a> FilterQuery<'a> {
@@ -2194,9 +2552,28 @@ impl<'a> FilterQuery<'a> {
             range: FilterRange(range),
             block,
             query_id,
+            prepared: false,
         })
     }
 
+    /// Whether this query can be prepared. Queries that need windowing put
+    /// the ids of the parents into their SQL text, which makes them useless
+    /// as prepared statements
+    pub fn can_prepare(&self) -> bool {
+        match self.collection {
+            FilterCollection::All(_) => true,
+            FilterCollection::SingleWindow(_) | FilterCollection::MultiWindow(_, _) => false,
+        }
+    }
+
+    /// Allow or forbid Diesel to prepare this query and to cache the
+    /// prepared statement on the connection. A prepared query passes
+    /// `first` and `skip` as bind variables and omits the query id so that
+    /// its SQL text only depends on the shape of the query
+    pub fn set_prepared(&mut self, prepared: bool) {
+        self.prepared = prepared && self.can_prepare();
+    }
+
     /// Generate
     ///     from schema.table c
     ///    where block_range @> $block
@@ -2251,7 +2628,7 @@ impl<'a> FilterQuery<'a> {
         self.filtered_rows(table, filter, out.reborrow())?;
         out.push_sql("\n ");
         self.sort_key.order_by(&mut out)?;
-        self.range.walk_ast(out.reborrow())?;
+        self.range.walk_ast_for(self.prepared, out.reborrow())?;
         out.push_sql(") c");
         Ok(())
     }
@@ -2297,9 +2674,11 @@ impl<'a> FilterQuery<'a> {
         // Overall, we generate a query
         //
         // with matches as (
-        //   select '...' as entity, id, vid, {sort_key}
-        //     from {table} c
-        //    where {query_filter}
+        //   (select '...' as entity, id, vid, {sort_key}
+        //      from {table} c
+        //     where {query_filter}
+        //     order by {sort_key}
+        //     limit n + m)
         //    union all
         //    ...
         //    order by {sort_key}
@@ -2311,25 +2690,34 @@ impl<'a> FilterQuery<'a> {
         //  ...
         //  order by c.{sort_key}
 
-        // Step 1: build matches CTE
+        // Step 1: build matches CTE. Each table can contribute at most
+        // `first + skip` rows to the overall result; pushing the order and
+        // that limit into each branch of the union lets Postgres use the
+        // indexes of each table instead of sorting all matching rows
         out.push_sql("with matches as (");
         for (i, (table, filter)) in entities.iter().enumerate() {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            // select '..' as entity,
-            //        c.id,
-            //        c.vid,
-            //        c.${sort_key}
-            out.push_sql("select '");
+            // (select '..' as entity,
+            //         c.id,
+            //         c.vid,
+            //         c.${sort_key}
+            //    ...
+            //   limit n + m)
+            out.push_sql("(select '");
             out.push_sql(&table.object);
             out.push_sql("' as entity, c.id, c.vid");
             self.sort_key.select(&mut out)?;
             self.filtered_rows(table, filter, out.reborrow())?;
+            out.push_sql(" ");
+            self.sort_key.order_by(&mut out)?;
+            self.range.branch_limit(self.prepared, &mut out)?;
+            out.push_sql(")");
         }
         out.push_sql("\n ");
         self.sort_key.order_by(&mut out)?;
-        self.range.walk_ast(out.reborrow())?;
+        self.range.walk_ast_for(self.prepared, out.reborrow())?;
 
         out.push_sql(")\n");
 
@@ -2435,12 +2823,16 @@ impl<'a> FilterQuery<'a> {
 
 impl<'a> QueryFragment<Pg> for FilterQuery<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
-        out.unsafe_to_cache_prepared();
+        if !self.prepared {
+            out.unsafe_to_cache_prepared();
+        }
         if self.collection.is_empty() {
             return Ok(());
         }
 
-        if let Some(qid) = &self.query_id {
+        // The query id differs for every GraphQL query, and would keep
+        // Diesel from ever reusing a prepared statement
+        if let (Some(qid), false) = (&self.query_id, self.prepared) {
             out.push_sql("/* qid: ");
             out.push_sql(qid);
             out.push_sql(" */\n");
@@ -0,0 +1,140 @@
+//! Decide which GraphQL queries Diesel may run as prepared statements.
+//!
+//! Diesel keeps the statements it prepares in a cache on each connection,
+//! keyed by the SQL text of the statement, and never evicts anything from
+//! that cache. We therefore only let Diesel prepare a bounded number of
+//! distinct statements on each connection; once that many have been
+//! prepared, queries with new SQL text are run without preparing them, just
+//! like they are when the cache is turned off.
+//!
+//! Each pooled connection carries its own `StatementCache` in its
+//! extensions, which records the SQL text of exactly the statements that
+//! Diesel cached on that connection. The pool closes connections after 30
+//! minutes at the latest, and with them both caches; statements that are
+//! not used anymore are therefore forgotten eventually.
+use diesel::pg::{PgConnection, PgQueryBuilder};
+use diesel::query_builder::{QueryBuilder, QueryFragment};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use graph::prelude::{
+    format_err, MetricsRegistry, QueryExecutionError, StoreError, SubgraphDeploymentId,
+};
+
+use crate::relational_queries::FilterQuery;
+
+lazy_static! {
+    /// How many distinct SQL statements for GraphQL queries we let Diesel
+    /// prepare and cache on each connection. Setting this to 0 turns
+    /// prepared statements for GraphQL queries off
+    static ref STATEMENT_CACHE_SIZE: usize = {
+        env::var("GRAPH_SQL_STATEMENT_CACHE_SIZE")
+            .ok()
+            .map(|s| {
+                usize::from_str(&s).unwrap_or_else(|_| {
+                    panic!("GRAPH_SQL_STATEMENT_CACHE_SIZE must be a number, but is `{}`", s)
+                })
+            })
+            .unwrap_or(0)
+    };
+}
+
+/// The statements that Diesel prepared for GraphQL queries on one
+/// connection
+pub struct StatementCache {
+    capacity: usize,
+    /// The SQL text of the statements that Diesel cached on the connection
+    statements: Mutex<HashSet<String>>,
+    registry: Arc<dyn MetricsRegistry>,
+}
+
+impl StatementCache {
+    pub fn new(capacity: usize, registry: Arc<dyn MetricsRegistry>) -> Self {
+        StatementCache {
+            capacity,
+            statements: Mutex::new(HashSet::new()),
+            registry,
+        }
+    }
+
+    /// Give `conn` a statement cache unless it already has one or the
+    /// cache is turned off through `GRAPH_SQL_STATEMENT_CACHE_SIZE`
+    pub(crate) fn attach(
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        registry: &Arc<dyn MetricsRegistry>,
+    ) {
+        let extensions = PooledConnection::extensions_mut(conn);
+        if *STATEMENT_CACHE_SIZE > 0 && extensions.get::<StatementCache>().is_none() {
+            extensions.insert(StatementCache::new(*STATEMENT_CACHE_SIZE, registry.clone()));
+        }
+    }
+
+    /// The statement cache of `conn`, if it has one
+    pub(crate) fn of(
+        conn: &PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Option<&StatementCache> {
+        PooledConnection::extensions(conn).get::<StatementCache>()
+    }
+
+    /// Mark `query` as prepared if it can be prepared and Diesel either
+    /// already cached a statement with its SQL text, or there is still room
+    /// for another statement
+    pub(crate) fn prepare(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        query: &mut FilterQuery,
+    ) -> Result<(), QueryExecutionError> {
+        if !query.can_prepare() {
+            return Ok(());
+        }
+
+        // Diesel keys its cache on this SQL text, and on the types of the
+        // bind variables, which follow from the columns that they are
+        // compared with and therefore from the SQL text, too
+        query.set_prepared(true);
+        let mut sql = PgQueryBuilder::default();
+        query
+            .to_sql(&mut sql)
+            .map_err(|e| StoreError::Unknown(format_err!("failed to generate SQL: {}", e)))?;
+        let sql = sql.finish();
+
+        let (hit, prepared) = {
+            let mut statements = self.statements.lock().unwrap();
+            if statements.contains(&sql) {
+                (true, true)
+            } else if statements.len() < self.capacity {
+                statements.insert(sql);
+                (false, true)
+            } else {
+                (false, false)
+            }
+        };
+        query.set_prepared(prepared);
+
+        let (name, help) = if hit {
+            (
+                "deployment_sql_statement_cache_hits",
+                "queries that used a statement that was already cached",
+            )
+        } else {
+            (
+                "deployment_sql_statement_cache_misses",
+                "queries whose statement was not cached yet",
+            )
+        };
+        self.registry
+            .global_deployment_counter(name, help, subgraph.as_str())
+            .map_err(|e| StoreError::Unknown(e.into()))?
+            .inc();
+        Ok(())
+    }
+
+    /// How many statements Diesel cached on the connection
+    pub fn statement_count(&self) -> usize {
+        self.statements.lock().unwrap().len()
+    }
+}
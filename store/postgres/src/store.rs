@@ -1,4 +1,5 @@
 use diesel::connection::SimpleConnection;
+use diesel::migration::MigrationConnection;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
@@ -11,33 +12,50 @@ use graph::prelude::{
 use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
 use rand::{seq::SliceRandom, thread_rng};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
 use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Semaphore;
 
+use graph::components::ethereum::REORG_THRESHOLD;
+use graph::components::server::health::{
+    HealthCheck, Subsystem, SubsystemHealth, CHAIN_HEAD_MAX_AGE, HEALTH_CHECK_INTERVAL,
+    HEALTH_CHECK_TIMEOUT,
+};
 use graph::components::store::{EntityCollection, QueryStore, Store as StoreTrait};
-use graph::components::subgraph::ProofOfIndexingFinisher;
+use graph::components::subgraph::{
+    ProofOfIndexingFinisher, HANDLER_LOG_RETENTION, PROOF_OF_INDEXING_VERSION,
+};
+use graph::data::schema::Aggregation;
+use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
     SubgraphDeploymentEntity, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
 };
 use graph::prelude::{
-    debug, ethabi, format_err, futures03, info, o, tiny_keccak, tokio, trace, warn, web3,
-    ApiSchema, BigInt, BlockNumber, CheapClone, DeploymentState, DynTryFuture, Entity, EntityKey,
-    EntityModification, EntityOrder, EntityQuery, EntityRange, Error, EthereumBlockPointer,
-    EthereumCallCache, Logger, MetadataOperation, MetricsRegistry, QueryExecutionError, Schema,
-    StopwatchMetrics, StoreError, StoreEvent, StoreEventStreamBox, SubgraphDeploymentId,
-    SubgraphDeploymentStore, SubgraphEntityPair, SubgraphName, TransactionAbortError, Value,
-    BLOCK_NUMBER_MAX,
+    debug, ethabi, format_err, futures03, info, o, tiny_keccak, tokio, trace, warn, web3, ApiKey,
+    ApiKeyUsage, ApiSchema, BigInt, BlockNumber, BlockProofOfIndexing, ChainHeadStatus, CheapClone,
+    DeploymentInfo, DeploymentState, DeploymentSyncRate, DynTryFuture, Entity, EntityAccessStats,
+    EntityKey, EntityModification, EntityOrder, EntityQuery, EntityRange, EntityTypeCount,
+    EntityVersion, Error, EthereumBlockPointer, EthereumCallCache, Failover, Future01CompatExt,
+    GraftPreview, HandlerLog, Logger, MetadataOperation, MetricsRegistry, NonCanonicalBlock,
+    QueryExecutionError, Schema, StopwatchMetrics, StoreError, StoreEvent, StoreEventStreamBox,
+    Stream, SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphEntityPair, SubgraphName,
+    TransactionAbortError, Value, BLOCK_NUMBER_MAX,
 };
 
 use graph_graphql::prelude::api_schema;
 use web3::types::{Address, H256};
 
-use crate::metadata;
+use crate::aggregation;
+use crate::catalog::Catalog;
+use crate::export::{self, Export};
+use crate::jobs::{self, JobRunner};
+use crate::metadata::{self, ApiKeyDailyUsage, JobRun, UnusedDeployment};
 use crate::relational::Layout;
 use crate::relational_queries::FromEntityData;
 use crate::store_events::SubscriptionManager;
@@ -52,28 +70,55 @@ lazy_static! {
 
         Semaphore::new(db_conn_pool_size)
     };
+
+    /// How long the per-type entity counts for a deployment are cached
+    /// before they are recomputed
+    static ref ENTITY_COUNTS_CACHE_TTL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ENTITY_COUNTS_CACHE_TTL")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_ENTITY_COUNTS_CACHE_TTL")
+    );
+
+    /// Whether a new deployment whose schema only adds to the schema of
+    /// the current version of its subgraph takes over the data of the
+    /// current version instead of indexing from scratch
+    static ref ADDITIVE_SCHEMA_MIGRATION: bool =
+        std::env::var("GRAPH_ADDITIVE_SCHEMA_MIGRATION")
+            .map(|s| s == "true")
+            .unwrap_or(false);
 }
 
 embed_migrations!("./migrations");
 
+/// The version of the newest migration in `./migrations`. A node only runs
+/// against a database whose schema has exactly this version. The test
+/// `latest_migration_is_current` makes sure this is updated together with
+/// the migrations
+const LATEST_MIGRATION: &str = "20201211120000";
+
 /// Run all schema migrations.
 ///
-/// When multiple `graph-node` processes start up at the same time, we ensure
-/// that they do not run migrations in parallel by using `blocking_conn` to
-/// serialize them. The `conn` is used to run the actual migration.
+/// When multiple `graph-node` processes start up at the same time, they
+/// serialize migrations by locking the table `__graph_node_global_lock`
+/// through `blocking_conn` while the migrations run on `conn`. The first
+/// process to get the lock runs the migrations, and the others wait for it
+/// and then find that there is nothing left to do. If the migrating process
+/// dies, its lock is released and the next process picks up where it left
+/// off.
+///
+/// Afterwards, every process checks that the schema has the version of its
+/// newest migration, and refuses to start if it does not, for example, when
+/// a newer version of `graph-node` has already migrated the database
 fn initiate_schema(logger: &Logger, conn: &PgConnection, blocking_conn: &PgConnection) {
-    // Collect migration logging output
-    let mut output = vec![];
-
-    // Make sure the locking table exists so we have something
-    // to lock. We intentionally ignore errors here, because they are most
-    // likely caused by us losing a race to create the table against another
-    // graph-node. If this truly is an error, we will trip over it when
-    // we try to lock the table and report it to the user
-    if let Err(e) = blocking_conn.batch_execute(
-        "create table if not exists \
-         __graph_node_global_lock(id int)",
-    ) {
+    // Make sure the locking table exists so we have something to lock. We
+    // intentionally ignore errors here, because they are most likely caused
+    // by us losing a race to create the table against another graph-node.
+    // If this truly is an error, we will trip over it when we try to lock
+    // the table and report it to the user
+    if let Err(e) =
+        blocking_conn.batch_execute("create table if not exists __graph_node_global_lock(id int)")
+    {
         debug!(
             logger,
             "Creating lock table failed, this is most likely harmless";
@@ -81,33 +126,41 @@ fn initiate_schema(logger: &Logger, conn: &PgConnection, blocking_conn: &PgConne
         );
     }
 
-    // blocking_conn holds the lock on the migrations table for the duration
-    // of the migration on conn. Since all nodes execute this code, only one
-    // of them can run this code at the same time. We need to use two
-    // connections for this because diesel will run each migration in its
-    // own txn, which makes it impossible to hold a lock across all of them
-    // on that connection
-    info!(
-        logger,
-        "Waiting for other graph-node instances to finish migrating"
-    );
+    // Collect migration logging output
+    let mut output = vec![];
     let result = blocking_conn.transaction(|| {
         diesel::sql_query("lock table __graph_node_global_lock in exclusive mode")
             .execute(blocking_conn)?;
         info!(logger, "Running migrations");
         embedded_migrations::run_with_output(conn, &mut output)
     });
-    info!(logger, "Migrations finished");
 
-    match result {
-        Ok(_) => info!(logger, "Completed pending Postgres schema migrations"),
-        Err(e) => panic!(
+    if let Err(e) = result {
+        panic!(
             "Error setting up Postgres database: \
              You may need to drop and recreate your database to work with the \
              latest version of graph-node. Error information: {:?}",
             e
+        )
+    }
+    match conn.latest_run_migration_version() {
+        Ok(Some(version)) if version == LATEST_MIGRATION => info!(
+            logger,
+            "Completed pending Postgres schema migrations";
+            "schema_version" => version
         ),
-    };
+        Ok(version) => panic!(
+            "Error setting up Postgres database: the database schema has version {} \
+             but this version of graph-node needs version {}. The database may have \
+             been migrated by a newer version of graph-node",
+            version.unwrap_or_else(|| "none".to_owned()),
+            LATEST_MIGRATION
+        ),
+        Err(e) => panic!(
+            "Error setting up Postgres database: can not determine the schema version: {}",
+            e
+        ),
+    }
     // If there was any migration output, log it now
     if !output.is_empty() {
         debug!(
@@ -153,10 +206,12 @@ struct SubgraphInfo {
     /// The block number at which this subgraph was grafted onto
     /// another one. We do not allow reverting past this block
     graft_block: Option<BlockNumber>,
+    /// The aggregations declared in `input`
+    aggregations: Arc<Vec<Aggregation>>,
 }
 
 pub struct StoreInner {
-    logger: Logger,
+    pub(crate) logger: Logger,
     subscriptions: Arc<SubscriptionManager>,
 
     conn: ConnectionPool,
@@ -172,9 +227,33 @@ pub struct StoreInner {
     /// the entities module
     pub(crate) storage_cache: e::StorageCache,
 
+    /// Per-type entity counts for deployments. Computing them requires
+    /// scanning all tables of a deployment, so they are only refreshed
+    /// every `ENTITY_COUNTS_CACHE_TTL`
+    entity_counts_cache: Mutex<LruCache<SubgraphDeploymentId, Vec<EntityTypeCount>>>,
+
+    /// How often each entity type of each deployment was read since this
+    /// node started
+    entity_access: Mutex<HashMap<SubgraphDeploymentId, BTreeMap<String, EntityAccessStats>>>,
+
+    /// The digests of the causality regions of each deployment as of the
+    /// block it processed last, so that recording the proof of indexing
+    /// for the next block does not have to read all of them again
+    poi_digest_cache: Mutex<HashMap<SubgraphDeploymentId, (EthereumBlockPointer, PoiDigests)>>,
+
     registry: Arc<dyn MetricsRegistry>,
 }
 
+/// The digest of each causality region of a deployment
+type PoiDigests = BTreeMap<String, Bytes>;
+
+/// What an entity was read for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EntityAccess {
+    Query,
+    Mapping,
+}
+
 /// A Store based on Diesel and Postgres.
 #[derive(Clone)]
 pub struct Store(Arc<StoreInner>);
@@ -234,10 +313,41 @@ impl Store {
             conn_round_robin_counter: AtomicUsize::new(0),
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
             storage_cache: e::make_storage_cache(),
+            entity_counts_cache: Mutex::new(LruCache::with_expiry_duration_and_capacity(
+                *ENTITY_COUNTS_CACHE_TTL,
+                100,
+            )),
+            entity_access: Mutex::new(HashMap::new()),
+            poi_digest_cache: Mutex::new(HashMap::new()),
             registry,
         };
         let store = Store(Arc::new(store));
 
+        // Forget the layouts of deployments whose schema was renamed,
+        // possibly by another node, since they refer to the old schema
+        let inner = Arc::downgrade(&store.0);
+        let renames = store.subscriptions.subscribe(vec![(
+            SUBGRAPHS_ID.clone(),
+            e::DEPLOYMENT_SCHEMA_RENAMED.to_owned(),
+        )]);
+        graph::spawn(
+            renames
+                .for_each(move |event| {
+                    // Stop listening once the store is gone
+                    let inner = inner.upgrade().ok_or(())?;
+                    let mut storage_cache = inner.storage_cache.lock().unwrap();
+                    for change in &event.changes {
+                        if change.entity_type == e::DEPLOYMENT_SCHEMA_RENAMED {
+                            if let Ok(id) = SubgraphDeploymentId::new(change.entity_id.clone()) {
+                                storage_cache.remove(&id);
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+                .compat(),
+        );
+
         // Return the store
         store
     }
@@ -264,6 +374,42 @@ impl Store {
         })
     }
 
+    /// Record that entities of `entity_types` in `subgraph_id` were read
+    /// for `access` and that reading them took `elapsed`
+    pub(crate) fn record_entity_access<'a>(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: impl IntoIterator<Item = &'a str>,
+        access: EntityAccess,
+        elapsed: Duration,
+    ) {
+        // Reads of metadata are not interesting for tuning deployments
+        if subgraph_id == &*SUBGRAPHS_ID {
+            return;
+        }
+
+        let mut entity_access = self.entity_access.lock().unwrap();
+        let stats_for_type = entity_access.entry(subgraph_id.clone()).or_default();
+        for entity_type in entity_types {
+            let stats = stats_for_type
+                .entry(entity_type.to_owned())
+                .or_insert_with(|| EntityAccessStats {
+                    entity_type: entity_type.to_owned(),
+                    ..Default::default()
+                });
+            match access {
+                EntityAccess::Query => {
+                    stats.query_reads += 1;
+                    stats.query_time += elapsed;
+                }
+                EntityAccess::Mapping => {
+                    stats.mapping_reads += 1;
+                    stats.mapping_time += elapsed;
+                }
+            }
+        }
+    }
+
     pub(crate) fn execute_query<T: FromEntityData>(
         &self,
         conn: &e::Connection,
@@ -271,15 +417,38 @@ impl Store {
     ) -> Result<Vec<T>, QueryExecutionError> {
         // Process results; deserialize JSON data
         let logger = query.logger.unwrap_or(self.logger.clone());
-        conn.query(
-            &logger,
-            query.collection,
-            query.filter,
-            query.order,
-            query.range,
-            query.block,
-            query.query_id,
-        )
+        let deadline = match query.deadline {
+            Some(deadline) => deadline,
+            None => {
+                return conn.query(
+                    &logger,
+                    query.collection,
+                    query.filter,
+                    query.order,
+                    query.range,
+                    query.block,
+                    query.query_id,
+                )
+            }
+        };
+
+        // Have Postgres abort the query when it runs past the deadline
+        // rather than letting it finish while nobody wants its result
+        let timeout = deadline
+            .checked_duration_since(Instant::now())
+            .ok_or(QueryExecutionError::Timeout)?;
+        conn.transaction::<_, StoreError, _>(|| {
+            conn.set_statement_timeout(timeout)?;
+            Ok(conn.query(
+                &logger,
+                query.collection,
+                query.filter,
+                query.order,
+                query.range,
+                query.block,
+                query.query_id,
+            ))
+        })?
     }
 
     fn check_interface_entity_uniqueness(
@@ -651,13 +820,17 @@ impl Store {
         Ok(e::Connection::new(conn.into(), storage, metadata))
     }
 
-    pub(crate) fn wait_stats(&self, replica: ReplicaId) -> &PoolWaitStats {
+    pub(crate) fn pool(&self, replica: ReplicaId) -> &ConnectionPool {
         match replica {
-            ReplicaId::Main => &self.conn.wait_stats,
-            ReplicaId::ReadOnly(idx) => &self.read_only_pools[idx].wait_stats,
+            ReplicaId::Main => &self.conn,
+            ReplicaId::ReadOnly(idx) => &self.read_only_pools[idx],
         }
     }
 
+    pub(crate) fn wait_stats(&self, replica: ReplicaId) -> &PoolWaitStats {
+        &self.pool(replica).wait_stats
+    }
+
     /// Return the storage for the subgraph. Since constructing a `Storage`
     /// object takes a bit of computation, we cache storage objects that do
     /// not have a pending migration in the Store, i.e., for the lifetime of
@@ -709,6 +882,7 @@ impl Store {
         schema.add_subgraph_id_directives(subgraph_id.clone());
 
         let info = SubgraphInfo {
+            aggregations: Arc::new(input_schema.aggregations()),
             input: Arc::new(input_schema),
             api: Arc::new(
                 ApiSchema::from_api_schema(schema)
@@ -725,6 +899,85 @@ impl Store {
         Ok(cache.get(&subgraph_id).unwrap().clone())
     }
 
+    /// Check that the block `hash` with number `number` is on the chain
+    /// that ends at the current head of `subgraph_id`, and return
+    /// `StoreError::BlockReorged` if it is not.
+    ///
+    /// Walking the chain is expensive, and we only do it if our block cache
+    /// has other blocks with the same number, i.e., if there was a reorg at
+    /// that block number that we know of. We can't tell whether a block is
+    /// on the chain if the cache is missing some of the blocks between the
+    /// subgraph head and the block; in that case we accept the block. We
+    /// also accept blocks that are more than `REORG_THRESHOLD` blocks behind
+    /// the subgraph head since they are final
+    fn check_block_on_subgraph_chain(
+        &self,
+        conn: &PgConnection,
+        subgraph_id: &SubgraphDeploymentId,
+        network_name: &str,
+        hash: H256,
+        number: BlockNumber,
+    ) -> Result<(), StoreError> {
+        use crate::db_schema::ethereum_blocks::dsl;
+        use diesel::sql_types::{BigInt, Text};
+
+        let siblings = dsl::ethereum_blocks
+            .filter(dsl::network_name.eq(network_name))
+            .filter(dsl::number.eq(number as i64))
+            .filter(dsl::hash.ne(format!("{:x}", hash)))
+            .count()
+            .get_result::<i64>(conn)?;
+        if siblings == 0 {
+            return Ok(());
+        }
+
+        let head = match self.block_ptr(subgraph_id.clone())? {
+            Some(head)
+                if head.number >= number as u64
+                    && head.number - number as u64 <= *REORG_THRESHOLD =>
+            {
+                head
+            }
+            // The subgraph has not gotten to the block yet, or the block
+            // is final
+            _ => return Ok(()),
+        };
+        // Walk the chain back from the subgraph head to `number`, which
+        // takes at most `REORG_THRESHOLD` steps
+        #[derive(QueryableByName)]
+        struct Ancestor {
+            #[sql_type = "Text"]
+            hash: String,
+        }
+        let query = "
+            with recursive ancestors(hash, parent_hash, number) as (
+                select hash, parent_hash, number
+                  from ethereum_blocks
+                 where hash = $1
+                   and network_name = $3
+                union all
+                select b.hash, b.parent_hash, b.number
+                  from ethereum_blocks b, ancestors a
+                 where b.hash = a.parent_hash
+                   and b.network_name = $3
+                   and b.number >= $2
+                   and a.number > $2)
+            select hash from ancestors where number = $2";
+        let ancestor = diesel::sql_query(query)
+            .bind::<Text, _>(head.hash_hex())
+            .bind::<BigInt, _>(number as i64)
+            .bind::<Text, _>(network_name)
+            .get_result::<Ancestor>(conn)
+            .optional()?;
+
+        match ancestor {
+            Some(ancestor) if ancestor.hash != format!("{:x}", hash) => {
+                Err(StoreError::BlockReorged(format!("0x{:x}", hash), number))
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn block_ptr_with_conn(
         subgraph_id: &SubgraphDeploymentId,
         conn: &e::Connection,
@@ -763,6 +1016,106 @@ impl Store {
         }
     }
 
+    /// The digest of each causality region of the deployment as of `block`
+    fn poi_digests_with_conn(
+        logger: &Logger,
+        conn: &e::Connection,
+        block: BlockNumber,
+    ) -> Result<PoiDigests, StoreError> {
+        let entities = conn.query::<Entity>(
+            logger,
+            EntityCollection::All(vec![POI_OBJECT.to_owned()]),
+            None,
+            EntityOrder::Default,
+            EntityRange {
+                first: None,
+                skip: 0,
+            },
+            block,
+            None,
+            None,
+        )?;
+        entities
+            .into_iter()
+            .map(|entity| {
+                let causality_region = entity.id()?;
+                match entity.get("digest") {
+                    Some(Value::Bytes(digest)) => Ok((causality_region, digest.clone())),
+                    other => Err(StoreError::Unknown(format_err!(
+                        "Entity has non-bytes digest attribute: {:?}",
+                        other
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    /// Compute the proof of indexing of the deployment at `block` from the
+    /// digests of its causality regions as of that block
+    fn finish_proof_of_indexing(
+        subgraph_id: &SubgraphDeploymentId,
+        block: &EthereumBlockPointer,
+        indexer: &Option<Address>,
+        digests: &PoiDigests,
+    ) -> [u8; 32] {
+        let mut finisher = ProofOfIndexingFinisher::new(block, subgraph_id, indexer);
+        for (causality_region, digest) in digests {
+            finisher.add_causality_region(causality_region, digest);
+        }
+        finisher.finish()
+    }
+
+    fn proof_of_indexing_with_conn(
+        logger: &Logger,
+        conn: &e::Connection,
+        subgraph_id: &SubgraphDeploymentId,
+        block: &EthereumBlockPointer,
+        indexer: &Option<Address>,
+    ) -> Result<[u8; 32], StoreError> {
+        let digests = Self::poi_digests_with_conn(logger, conn, block.number.try_into().unwrap())?;
+        Ok(Self::finish_proof_of_indexing(
+            subgraph_id,
+            block,
+            indexer,
+            &digests,
+        ))
+    }
+
+    /// The digests of the causality regions of the deployment as of
+    /// `block_ptr_to`, which was just written on top of `block_ptr_from`
+    /// with the given `changes` to the digests. Unless the deployment has
+    /// been reverted or was written by another node since, we can update
+    /// the digests we remembered for `block_ptr_from` instead of reading
+    /// all of them again
+    fn block_poi_digests(
+        &self,
+        conn: &e::Connection,
+        subgraph_id: &SubgraphDeploymentId,
+        block_ptr_from: Option<EthereumBlockPointer>,
+        block_ptr_to: &EthereumBlockPointer,
+        changes: Vec<(String, Option<Bytes>)>,
+    ) -> Result<PoiDigests, StoreError> {
+        // Whatever we remembered is useless once we write `block_ptr_to`,
+        // and nothing must be left behind if writing it fails
+        let remembered = self.poi_digest_cache.lock().unwrap().remove(subgraph_id);
+        match remembered {
+            Some((ptr, mut digests)) if Some(ptr) == block_ptr_from => {
+                for (causality_region, digest) in changes {
+                    match digest {
+                        Some(digest) => digests.insert(causality_region, digest),
+                        None => digests.remove(&causality_region),
+                    };
+                }
+                Ok(digests)
+            }
+            _ => Self::poi_digests_with_conn(
+                &self.logger,
+                conn,
+                block_ptr_to.number.try_into().unwrap(),
+            ),
+        }
+    }
+
     fn create_deployment_internal(
         &self,
         name: SubgraphName,
@@ -778,16 +1131,27 @@ impl Store {
         assert!(!replace);
 
         let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
-        econn.transaction(|| -> Result<(), StoreError> {
+        let adopted = econn.transaction(|| -> Result<_, StoreError> {
             let exists = metadata::deployment_exists(&econn.conn, &schema.id)?;
             let mut event = if replace || !exists {
                 let ops = deployment.create_operations(&schema.id);
-                self.apply_metadata_operations_with_conn(&econn, ops)?
+                let event = self.apply_metadata_operations_with_conn(&econn, ops)?;
+                metadata::set_proof_of_indexing_version(
+                    &econn.conn,
+                    &schema.id,
+                    PROOF_OF_INDEXING_VERSION,
+                )?;
+                event
             } else {
                 StoreEvent::new(vec![])
             };
 
-            if !exists {
+            let adopted = if !exists && *ADDITIVE_SCHEMA_MIGRATION {
+                e::adoptable_layouts(&econn.conn, &self.logger, &name, schema)?
+            } else {
+                None
+            };
+            if !exists && adopted.is_none() {
                 econn.create_schema(schema)?;
             }
 
@@ -796,8 +1160,30 @@ impl Store {
                 metadata::create_subgraph_version(&econn.conn, name, &schema.id, node_id, mode)?;
             event.changes.extend(changes);
 
-            econn.send_store_event(&event)
-        })
+            if let Some((base, layout)) = &adopted {
+                info!(self.logger, "New deployment adopts the data of the current version";
+                      "deployment" => schema.id.as_str(),
+                      "current" => base.subgraph.as_str());
+                event
+                    .changes
+                    .extend(e::adopt_schema(&econn.conn, base, layout)?);
+            }
+
+            econn.send_store_event(&event)?;
+            Ok(adopted)
+        })?;
+
+        if let Some((base, _)) = adopted {
+            self.storage_cache.lock().unwrap().remove(&base.subgraph);
+            self.subgraph_cache.lock().unwrap().remove(&base.subgraph);
+            self.entity_counts_cache
+                .lock()
+                .unwrap()
+                .remove(&base.subgraph);
+            self.entity_access.lock().unwrap().remove(&base.subgraph);
+            self.poi_digest_cache.lock().unwrap().remove(&base.subgraph);
+        }
+        Ok(())
     }
 
     // Only for tests to simplify their handling of test fixtures, so that
@@ -813,6 +1199,454 @@ impl Store {
     ) -> Result<(), StoreError> {
         self.create_deployment_internal(name, schema, deployment, node_id, mode, true)
     }
+
+    /// Create an API key for querying `deployments`, or all deployments if
+    /// that is `None`, and return it together with its secret
+    pub fn create_api_key(
+        &self,
+        description: Option<String>,
+        deployments: Option<Vec<SubgraphDeploymentId>>,
+        queries_per_minute: Option<u32>,
+    ) -> Result<(ApiKey, String), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            metadata::create_api_key(&conn, description, deployments, queries_per_minute)
+        })
+    }
+
+    /// Revoke the API key `id`. Return `false` if there is no such key or
+    /// if it had already been revoked
+    pub fn revoke_api_key(&self, id: &str) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::revoke_api_key(&conn, id)
+    }
+
+    pub fn api_keys(&self) -> Result<Vec<ApiKey>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::api_keys(&conn)
+    }
+
+    /// The daily usage of the API key `id`, or of all keys, over the last
+    /// `days` days
+    pub fn api_key_usage(
+        &self,
+        id: Option<&str>,
+        days: u32,
+    ) -> Result<Vec<ApiKeyDailyUsage>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::api_key_usage(&conn, id, days)
+    }
+
+    /// Wait until the store events of all changes that were committed so
+    /// far have been passed on to subscriptions, but at most until
+    /// `deadline`. Returns `false` if that had not happened by the deadline
+    pub fn flush_store_events(&self, deadline: Instant) -> Result<bool, StoreError> {
+        self.subscriptions.flush(deadline, |marker| {
+            let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+            econn.send_store_event(marker)
+        })
+    }
+
+    /// Wait until the connection pools for the main database and all
+    /// replicas have no connections in use anymore, but at most until
+    /// `deadline`, and close their connections. Returns `false` if some
+    /// connections were still in use at the deadline
+    pub fn close(&self, deadline: Instant) -> bool {
+        let mut closed = true;
+        for pool in self.read_only_pools.iter().chain(Some(&self.conn)) {
+            closed = pool.close(deadline) && closed;
+        }
+        closed
+    }
+
+    /// The runner for the background jobs that maintain this database and
+    /// the state of the node `node_id`, to be run on that node
+    pub fn job_runner(&self, node_id: NodeId) -> JobRunner {
+        JobRunner::new(
+            &self.logger,
+            self.conn.clone(),
+            self.subscriptions.clone(),
+            node_id,
+        )
+    }
+
+    /// The `limit` most recent runs of the background job `job`, or of all
+    /// background jobs
+    pub fn job_runs(&self, job: Option<&str>, limit: u32) -> Result<Vec<JobRun>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::job_runs(&conn, job, limit)
+    }
+
+    /// Move the tables and indexes of the deployment `id` into
+    /// `tablespace`, for example, to put a heavily used deployment on
+    /// faster disks. The deployment keeps indexing and serving queries
+    /// while it is moved
+    pub fn move_deployment(
+        &self,
+        id: &SubgraphDeploymentId,
+        tablespace: &str,
+    ) -> Result<(), StoreError> {
+        self.move_deployment_schema(id, Some(tablespace), None)
+    }
+
+    /// Move the tables and indexes of the deployment `id` into
+    /// `tablespace` and/or into a new schema `schema`; at least one of
+    /// them must be given. The deployment keeps indexing and serving
+    /// queries while it is moved
+    pub fn move_deployment_schema(
+        &self,
+        id: &SubgraphDeploymentId,
+        tablespace: Option<&str>,
+        schema: Option<&str>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        e::move_schema(&conn, &self.logger, id, tablespace, schema)?;
+        self.storage_cache.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /// Return the deployments that are unused, the ones that have been
+    /// unused the longest first. Deployments that became unused since a
+    /// node last checked are recorded first
+    pub fn unused_deployments(&self) -> Result<Vec<UnusedDeployment>, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| metadata::record_unused_deployments(&conn))?;
+        metadata::unused_deployments(&conn)
+    }
+
+    /// Remove the deployment `id` with all its data and metadata. Only
+    /// deployments that are not used by any subgraph can be removed; see
+    /// `metadata::remove_deployment`
+    pub fn remove_deployment(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let changes = e::remove_deployment(&econn.conn, id)?;
+            econn.send_store_event(&StoreEvent::new(changes))
+        })?;
+        self.storage_cache.lock().unwrap().remove(id);
+        self.subgraph_cache.lock().unwrap().remove(id);
+        self.entity_counts_cache.lock().unwrap().remove(id);
+        self.entity_access.lock().unwrap().remove(id);
+        self.poi_digest_cache.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /// Remove the deployments that have been unused for longer than
+    /// `retention`, just like the background job does when
+    /// `GRAPH_UNUSED_DEPLOYMENT_RETENTION` is set. Return the deployments
+    /// that were removed
+    pub fn remove_expired_unused_deployments(
+        &self,
+        retention: Duration,
+    ) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+        let removed = jobs::remove_expired_unused_deployments(&self.logger, &self.conn, retention)?;
+        for id in &removed {
+            self.storage_cache.lock().unwrap().remove(id);
+            self.subgraph_cache.lock().unwrap().remove(id);
+            self.entity_counts_cache.lock().unwrap().remove(id);
+            self.entity_access.lock().unwrap().remove(id);
+            self.poi_digest_cache.lock().unwrap().remove(id);
+        }
+        Ok(removed)
+    }
+
+    /// Write the entities of the deployment `id` as they were at `block`,
+    /// or at its latest block, into CSV files in `dir`
+    pub fn export_entities(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        dir: &Path,
+    ) -> Result<Export, StoreError> {
+        let conn = self.get_conn()?;
+        let latest = self.block_ptr(id.clone())?.ok_or_else(|| {
+            StoreError::Unknown(format_err!(
+                "deployment {} has not processed any blocks",
+                id
+            ))
+        })?;
+        let block = match block {
+            None => latest,
+            Some(number) if number as u64 == latest.number => latest,
+            Some(number) if number as u64 > latest.number => {
+                return Err(StoreError::Unknown(format_err!(
+                    "deployment {} has only processed block {}",
+                    id,
+                    latest.number
+                )))
+            }
+            Some(number) => {
+                let network = metadata::subgraph_network(&conn, id)?.unwrap_or_default();
+                let hash = block_hash(&conn, &network, number)?.ok_or_else(|| {
+                    StoreError::Unknown(format_err!(
+                        "block {} is not in the block cache for {} or is not unique",
+                        number,
+                        network
+                    ))
+                })?;
+                EthereumBlockPointer::from((hash, number as u64))
+            }
+        };
+        let layout = self.storage(&conn, id)?;
+        let dynamic_data_sources =
+            metadata::dynamic_data_source_count(&conn, id, block.number as BlockNumber)?;
+        export::export(
+            &self.conn.raw_connection()?,
+            &layout,
+            block,
+            dynamic_data_sources,
+            dir,
+        )
+    }
+
+    /// Load the entities that `export_entities` wrote into `dir` into the
+    /// deployment `id`. The deployment must not have processed any blocks
+    /// and must not be assigned to a node; it continues indexing after the
+    /// block of the export once it is assigned again
+    pub fn import_entities(
+        &self,
+        id: &SubgraphDeploymentId,
+        dir: &Path,
+    ) -> Result<Export, StoreError> {
+        let conn = self.get_conn()?;
+        if metadata::deployment_assigned(&conn, id)? {
+            return Err(StoreError::Unknown(format_err!(
+                "deployment {} must be unassigned before entities can be imported into it",
+                id
+            )));
+        }
+        if self.block_ptr(id.clone())?.is_some() {
+            return Err(StoreError::Unknown(format_err!(
+                "deployment {} has already processed blocks",
+                id
+            )));
+        }
+        let layout = self.storage(&conn, id)?;
+        let export = export::import(&self.conn.raw_connection()?, &layout, dir)?;
+        self.entity_counts_cache.lock().unwrap().remove(id);
+        Ok(export)
+    }
+
+    /// Return the digest of the proof of indexing for each causality region
+    /// of the deployment as of `block`, or `None` if the deployment does not
+    /// keep a proof of indexing. Unlike the finished proof of indexing, the
+    /// digests do not depend on the block hash or the indexer, and can
+    /// therefore be compared directly between different installations
+    pub fn poi_digests(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<Option<BTreeMap<String, Bytes>>, StoreError> {
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        if !econn.supports_proof_of_indexing() {
+            return Ok(None);
+        }
+
+        Self::poi_digests_with_conn(&self.logger, &econn, block).map(Some)
+    }
+}
+
+/// The hash of the block with number `number` on `network` if the block
+/// cache has exactly one such block
+fn block_hash(
+    conn: &PgConnection,
+    network: &str,
+    number: BlockNumber,
+) -> Result<Option<H256>, StoreError> {
+    use crate::db_schema::ethereum_blocks as b;
+
+    let hashes = b::table
+        .select(b::hash)
+        .filter(b::network_name.eq(network))
+        .filter(b::number.eq(number as i64))
+        .load::<String>(conn)?;
+    match hashes.as_slice() {
+        [hash] => H256::from_str(hash)
+            .map(Some)
+            .map_err(|e| StoreError::Unknown(format_err!("invalid block hash `{}`: {}", hash, e))),
+        _ => Ok(None),
+    }
+}
+
+/// The timestamp of the block with the given hash if the block is in the
+/// block cache
+fn block_timestamp(conn: &PgConnection, hash: &H256) -> Result<Option<u64>, StoreError> {
+    use crate::db_schema::ethereum_blocks as b;
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Nullable, Text};
+
+    let timestamp = b::table
+        .select(sql::<Nullable<Text>>("data -> 'block' ->> 'timestamp'"))
+        .filter(b::hash.eq(format!("{:x}", hash)))
+        .first::<Option<String>>(conn)
+        .optional()?
+        .and_then(|timestamp| timestamp);
+    Ok(timestamp
+        .and_then(|timestamp| u64::from_str_radix(timestamp.trim_start_matches("0x"), 16).ok()))
+}
+
+fn chain_head_statuses(conn: &PgConnection) -> Result<Vec<ChainHeadStatus>, StoreError> {
+    use crate::db_schema::ethereum_networks as n;
+
+    n::table
+        .select((n::name, n::head_block_hash, n::head_block_number))
+        .order_by(n::name)
+        .load::<(String, Option<String>, Option<i64>)>(conn)?
+        .into_iter()
+        .map(|(network, hash, number)| -> Result<_, StoreError> {
+            let head_block = match (hash, number) {
+                (Some(hash), Some(number)) => Some(EthereumBlockPointer::from((
+                    hash.parse::<H256>().map_err(|e| {
+                        format_err!("invalid head block hash for {}: {}", network, e)
+                    })?,
+                    number,
+                ))),
+                _ => None,
+            };
+            let head_block_timestamp = match &head_block {
+                Some(ptr) => block_timestamp(conn, &ptr.hash)?,
+                None => None,
+            };
+            Ok(ChainHeadStatus {
+                network,
+                head_block,
+                head_block_timestamp,
+            })
+        })
+        .collect()
+}
+
+/// Checks that the main database and its replicas accept connections,
+/// and that blocks are ingested for the networks that a node ingests. The
+/// state of the connection pools is reported as it is, and the chain heads
+/// are read in the background every `HEALTH_CHECK_INTERVAL`
+pub struct StoreHealthCheck {
+    store: Arc<Store>,
+    chain_heads: Arc<Mutex<Vec<SubsystemHealth>>>,
+}
+
+impl StoreHealthCheck {
+    pub fn new(store: Arc<Store>, networks: Vec<String>) -> Self {
+        let chain_heads = Arc::new(Mutex::new(
+            networks
+                .iter()
+                .map(|network| {
+                    SubsystemHealth::unhealthy(
+                        Subsystem::BlockIngestor,
+                        network.as_str(),
+                        "the chain head has not been checked yet".to_owned(),
+                    )
+                })
+                .collect(),
+        ));
+
+        // The thread stops once the check is gone
+        let weak = Arc::downgrade(&chain_heads);
+        let store2 = store.clone();
+        std::thread::Builder::new()
+            .name("chain-head-health".to_owned())
+            .spawn(move || loop {
+                let health = Self::chain_heads_health(&store2, &networks);
+                match weak.upgrade() {
+                    Some(chain_heads) => {
+                        if let Some(health) = health {
+                            *chain_heads.lock().unwrap() = health;
+                        }
+                    }
+                    None => break,
+                }
+                std::thread::sleep(*HEALTH_CHECK_INTERVAL);
+            })
+            .expect("failed to start the chain head health check");
+
+        StoreHealthCheck { store, chain_heads }
+    }
+
+    /// Read the chain heads of `networks`. If the pool has no connection
+    /// for us, the node is busy, and we keep what we found the last time
+    fn chain_heads_health(store: &Store, networks: &[String]) -> Option<Vec<SubsystemHealth>> {
+        if networks.is_empty() {
+            return Some(vec![]);
+        }
+        let conn = store.conn.get_timeout(*HEALTH_CHECK_TIMEOUT).ok()?;
+        Some(match chain_head_statuses(&conn) {
+            Ok(statuses) => networks
+                .iter()
+                .map(|network| {
+                    let status = statuses.iter().find(|status| &status.network == network);
+                    Self::chain_head_health(network, status)
+                })
+                .collect(),
+            Err(e) => networks
+                .iter()
+                .map(|network| {
+                    SubsystemHealth::unhealthy(
+                        Subsystem::BlockIngestor,
+                        network.as_str(),
+                        format!("can not read the chain head: {}", e),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    fn chain_head_health(network: &str, status: Option<&ChainHeadStatus>) -> SubsystemHealth {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+        match status.and_then(|status| status.head_block.as_ref().map(|ptr| (ptr, status))) {
+            None => SubsystemHealth::unhealthy(
+                Subsystem::BlockIngestor,
+                network,
+                "no blocks have been ingested yet".to_owned(),
+            ),
+            Some((ptr, status)) => match status.head_block_timestamp {
+                Some(timestamp) if now.saturating_sub(timestamp) > CHAIN_HEAD_MAX_AGE.as_secs() => {
+                    SubsystemHealth::unhealthy(
+                        Subsystem::BlockIngestor,
+                        network,
+                        format!(
+                            "head block {} is {}s old",
+                            ptr.number,
+                            now.saturating_sub(timestamp)
+                        ),
+                    )
+                }
+                _ => SubsystemHealth::healthy(Subsystem::BlockIngestor, network)
+                    .with_message(format!("head block {}", ptr.number)),
+            },
+        }
+    }
+}
+
+impl HealthCheck for StoreHealthCheck {
+    fn check(&self) -> Vec<SubsystemHealth> {
+        // Looking at the pools does not take connections from them, and
+        // probes can not exhaust them or put load on the database
+        let pool_health = |name: String, pool: &ConnectionPool| {
+            let state = pool.state();
+            match pool.connection_error() {
+                Some(e) => SubsystemHealth::unhealthy(
+                    Subsystem::Store,
+                    name,
+                    format!("can not connect: {}", e),
+                ),
+                None => SubsystemHealth::healthy(Subsystem::Store, name).with_message(format!(
+                    "{} of {} connections in use",
+                    state.connections - state.idle_connections,
+                    pool.max_size()
+                )),
+            }
+        };
+
+        let mut health = vec![pool_health("main".to_owned(), &self.store.conn)];
+        for (i, pool) in self.store.read_only_pools.iter().enumerate() {
+            health.push(pool_health(format!("replica{}", i), pool));
+        }
+        health.extend(self.chain_heads.lock().unwrap().iter().cloned());
+        health
+    }
 }
 
 impl StoreTrait for Store {
@@ -851,113 +1685,118 @@ impl StoreTrait for Store {
         let self_inner = self.cheap_clone();
 
         async move {
-            let entities_blocknumber = self
-                .with_entity_conn(subgraph_id, move |conn, cancel| {
-                    cancel.check_cancel()?;
+            self.with_entity_conn(subgraph_id, move |conn, cancel| {
+                cancel.check_cancel()?;
 
-                    if !conn.supports_proof_of_indexing() {
-                        return Ok(None);
-                    }
+                if !conn.supports_proof_of_indexing() {
+                    return Ok(None);
+                }
 
-                    conn.transaction::<_, CancelableError, _>(move || {
-                        let latest_block_ptr =
-                            match Self::block_ptr_with_conn(&subgraph_id_inner, conn)? {
-                                Some(inner) => inner,
-                                None => return Ok(None),
-                            };
-
-                        cancel.check_cancel()?;
-
-                        // FIXME: (Determinism)
-                        // There is no guarantee that we are able to look up the block number
-                        // even if we have indexed beyond it, because the cache is sparse and
-                        // only populated by blocks with triggers.
-                        //
-                        // This is labeled as a determinism bug because the PoI needs to be
-                        // submitted to the network reliably. Strictly speaking, that's not
-                        // indeterminism to miss an opportunity to claim a reward, but it's very
-                        // similar to most determinism bugs in that money is on the line.
-                        let block_number = self_inner
-                            .block_number(&subgraph_id_inner, block_hash)
-                            .map_err(|e| CancelableError::Error(e.into()))?;
-                        let block_number = match block_number {
-                            Some(n) => n.try_into().unwrap(),
+                conn.transaction::<_, CancelableError, _>(move || {
+                    let latest_block_ptr =
+                        match Self::block_ptr_with_conn(&subgraph_id_inner, conn)? {
+                            Some(inner) => inner,
                             None => return Ok(None),
                         };
-                        cancel.check_cancel()?;
-
-                        // FIXME: (Determinism)
-                        // It is vital to ensure that the block hash given in the query
-                        // is a parent of the latest block indexed for the subgraph.
-                        // Unfortunately the machinery needed to do this is not yet in place.
-                        // The best we can do right now is just to make sure that the block number
-                        // is high enough.
-                        if latest_block_ptr.number < block_number {
-                            return Ok(None);
-                        }
 
-                        let entities = conn
-                            .query::<Entity>(
-                                &logger,
-                                EntityCollection::All(vec![POI_OBJECT.to_owned()]),
-                                None,
-                                EntityOrder::Default,
-                                EntityRange {
-                                    first: None,
-                                    skip: 0,
-                                },
-                                block_number.try_into().unwrap(),
-                                None,
-                            )
-                            .map_err(Error::from)?;
-
-                        Ok(Some((entities, block_number)))
-                    })
-                })
-                .await?;
+                    cancel.check_cancel()?;
 
-            let (entities, block_number) = if let Some(entities_blocknumber) = entities_blocknumber
-            {
-                entities_blocknumber
-            } else {
-                return Ok(None);
-            };
+                    // We can only compute proofs with the algorithm that
+                    // this node implements
+                    let version =
+                        metadata::proof_of_indexing_version(&conn.conn, &subgraph_id_inner)
+                            .map_err(|e| CancelableError::Error(e.into()))?;
+                    if version != PROOF_OF_INDEXING_VERSION {
+                        return Err(CancelableError::Error(format_err!(
+                            "subgraph `{}` uses version {} of the proof of indexing, \
+                             but this node computes version {}",
+                            subgraph_id_inner,
+                            version,
+                            PROOF_OF_INDEXING_VERSION
+                        )));
+                    }
 
-            let mut by_causality_region = entities
-                .into_iter()
-                .map(|e| {
-                    let causality_region = e.id()?;
-                    let digest = match e.get("digest") {
-                        Some(Value::Bytes(b)) => Ok(b.to_owned()),
-                        other => Err(format_err!(
-                            "Entity has non-bytes digest attribute: {:?}",
-                            other
-                        )),
-                    }?;
-
-                    Ok((causality_region, digest))
-                })
-                .collect::<Result<HashMap<_, _>, Error>>()?;
+                    // FIXME: (Determinism)
+                    // There is no guarantee that we are able to look up the block number
+                    // even if we have indexed beyond it, because the cache is sparse and
+                    // only populated by blocks with triggers. Blocks that the subgraph
+                    // processed are also recorded with their proof of indexing, which
+                    // covers the blocks that matter most.
+                    //
+                    // This is labeled as a determinism bug because the PoI needs to be
+                    // submitted to the network reliably. Strictly speaking, that's not
+                    // indeterminism to miss an opportunity to claim a reward, but it's very
+                    // similar to most determinism bugs in that money is on the line.
+                    let block_number = match self_inner
+                        .block_number(&subgraph_id_inner, block_hash)
+                        .map_err(|e| CancelableError::Error(e.into()))?
+                    {
+                        Some(n) => Some(n),
+                        None => metadata::proof_of_indexing_block_number(
+                            &conn.conn,
+                            &subgraph_id_inner,
+                            &block_hash,
+                        )
+                        .map_err(|e| CancelableError::Error(e.into()))?,
+                    };
+                    let block_number: u64 = match block_number {
+                        Some(n) => n.try_into().unwrap(),
+                        None => return Ok(None),
+                    };
+                    cancel.check_cancel()?;
 
-            let block = EthereumBlockPointer {
-                number: block_number,
-                hash: block_hash,
-            };
-            let mut finisher = ProofOfIndexingFinisher::new(&block, &subgraph_id, &indexer);
-            for (name, region) in by_causality_region.drain() {
-                finisher.add_causality_region(&name, &region);
-            }
+                    // FIXME: (Determinism)
+                    // It is vital to ensure that the block hash given in the query
+                    // is a parent of the latest block indexed for the subgraph.
+                    // Unfortunately the machinery needed to do this is not yet in place.
+                    // The best we can do right now is just to make sure that the block number
+                    // is high enough.
+                    if latest_block_ptr.number < block_number {
+                        return Ok(None);
+                    }
 
-            Ok(Some(finisher.finish()))
+                    let block = EthereumBlockPointer {
+                        number: block_number,
+                        hash: block_hash,
+                    };
+                    let digest = Self::proof_of_indexing_with_conn(
+                        &logger,
+                        conn,
+                        &subgraph_id_inner,
+                        &block,
+                        &indexer,
+                    )
+                    .map_err(|e| CancelableError::Error(e.into()))?;
+                    Ok(Some(digest))
+                })
+            })
+            .await
         }
         .boxed()
     }
 
+    fn get_block_proof_of_indexing(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_number: BlockNumber,
+    ) -> Result<Option<BlockProofOfIndexing>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::block_proof_of_indexing(&conn, subgraph_id, block_number)
+    }
+
     fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
         let conn = self
             .get_entity_conn(&key.subgraph_id, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.get_entity(&conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+        let start = Instant::now();
+        let entity = self.get_entity(&conn, &key.subgraph_id, &key.entity_type, &key.entity_id);
+        self.record_entity_access(
+            &key.subgraph_id,
+            Some(key.entity_type.as_str()),
+            EntityAccess::Mapping,
+            start.elapsed(),
+        );
+        entity
     }
 
     fn get_many(
@@ -971,7 +1810,16 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(subgraph_id, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        conn.find_many(ids_for_type, BLOCK_NUMBER_MAX)
+        let entity_types: Vec<&str> = ids_for_type.keys().cloned().collect();
+        let start = Instant::now();
+        let entities = conn.find_many(ids_for_type, BLOCK_NUMBER_MAX);
+        self.record_entity_access(
+            subgraph_id,
+            entity_types,
+            EntityAccess::Mapping,
+            start.elapsed(),
+        );
+        entities
     }
 
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
@@ -995,25 +1843,6 @@ impl StoreTrait for Store {
         }
     }
 
-    fn find_ens_name(&self, hash: &str) -> Result<Option<String>, QueryExecutionError> {
-        use crate::db_schema::ens_names as dsl;
-
-        let conn = self
-            .get_conn()
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-
-        dsl::table
-            .select(dsl::name)
-            .find(hash)
-            .get_result::<String>(&conn)
-            .optional()
-            .map_err(|e| {
-                QueryExecutionError::StoreError(
-                    format_err!("error looking up ens_name for hash {}: {}", hash, e).into(),
-                )
-            })
-    }
-
     fn transact_block_operations(
         &self,
         subgraph_id: SubgraphDeploymentId,
@@ -1034,9 +1863,21 @@ impl StoreTrait for Store {
             );
         }
 
-        let econn = self.get_entity_conn(&subgraph_id, ReplicaId::Main)?;
+        // Once another deployment adopted its data, `subgraph_id` is gone,
+        // and callers need to see that as `DeploymentNotFound`
+        let econn = self
+            .get_entity_conn(&subgraph_id, ReplicaId::Main)
+            .map_err(|e| match e.downcast::<StoreError>() {
+                Ok(e) => e,
+                Err(e) => StoreError::Unknown(e),
+            })?;
+        let aggregations = if subgraph_id.is_meta() {
+            None
+        } else {
+            Some(self.subgraph_info(&subgraph_id)?.aggregations)
+        };
 
-        let (event, metadata_event, should_migrate) =
+        let (event, metadata_event, should_migrate, poi_digests) =
             econn.transaction(|| -> Result<_, StoreError> {
                 let block_ptr_from = Self::block_ptr_with_conn(&subgraph_id, &econn)?;
                 if let Some(ref block_ptr_from) = block_ptr_from {
@@ -1050,22 +1891,103 @@ impl StoreTrait for Store {
 
                 let should_migrate = econn.should_migrate(&subgraph_id, &block_ptr_to)?;
 
+                // Update the rollups for any aggregations over timeseries
+                // entities that are inserted in this block
+                let mut mods = mods;
+                if let Some(aggregations) = &aggregations {
+                    let section = stopwatch.start_section("update_aggregations");
+                    let rollups = aggregation::rollups(&econn, aggregations, &subgraph_id, &mods)?;
+                    mods.extend(rollups);
+                    section.end();
+                }
+
                 // Emit a store event for the changes we are about to make. We
                 // wait with sending it until we have done all our other work
                 // so that we do not hold a lock on the notification queue
                 // for longer than we have to
                 let event: StoreEvent = mods.iter().collect();
 
+                // The new digests of the causality regions whose proof of
+                // indexing changed in this block
+                let poi_changes: Vec<_> = mods
+                    .iter()
+                    .filter(|modification| modification.entity_key().entity_type == POI_OBJECT)
+                    .map(|modification| {
+                        use EntityModification::*;
+                        match modification {
+                            Insert { key, data } | Overwrite { key, data } => {
+                                let digest = match data.get("digest") {
+                                    Some(Value::Bytes(digest)) => Some(digest.clone()),
+                                    other => {
+                                        return Err(StoreError::Unknown(format_err!(
+                                            "Entity has non-bytes digest attribute: {:?}",
+                                            other
+                                        )))
+                                    }
+                                };
+                                Ok((key.entity_id.clone(), digest))
+                            }
+                            Remove { key } => Ok((key.entity_id.clone(), None)),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+
                 // Make the changes
                 let section = stopwatch.start_section("apply_entity_modifications");
-                self.apply_entity_modifications(&econn, mods, Some(&block_ptr_to), stopwatch)?;
+                self.apply_entity_modifications(
+                    &econn,
+                    mods,
+                    Some(&block_ptr_to),
+                    stopwatch.clone(),
+                )?;
                 section.end();
 
+                // Remember the proof of indexing for this block so that it
+                // can still be retrieved once the block has left the block
+                // cache, or after later blocks changed it
+                let poi_digests = if econn.supports_proof_of_indexing() {
+                    let section = stopwatch.start_section("record_proof_of_indexing");
+                    let digests = self.block_poi_digests(
+                        &econn,
+                        &subgraph_id,
+                        block_ptr_from,
+                        &block_ptr_to,
+                        poi_changes,
+                    )?;
+                    let digest = Self::finish_proof_of_indexing(
+                        &subgraph_id,
+                        &block_ptr_to,
+                        &None,
+                        &digests,
+                    );
+                    metadata::record_block_proof_of_indexing(
+                        &econn.conn,
+                        &subgraph_id,
+                        &BlockProofOfIndexing {
+                            block: block_ptr_to,
+                            version: PROOF_OF_INDEXING_VERSION,
+                            digest,
+                        },
+                    )?;
+                    section.end();
+                    Some(digests)
+                } else {
+                    None
+                };
+
                 let metadata_event =
                     metadata::forward_block_ptr(&econn.conn, &subgraph_id, block_ptr_to)?;
-                Ok((event, metadata_event, should_migrate))
+                Ok((event, metadata_event, should_migrate, poi_digests))
             })?;
 
+        // Only remember the digests once they have been committed
+        if let Some(digests) = poi_digests {
+            self.poi_digest_cache
+                .lock()
+                .unwrap()
+                .insert(subgraph_id.clone(), (block_ptr_to, digests));
+        }
+
         // Send the events separately, because NOTIFY uses a global DB lock.
         econn.transaction(|| {
             econn.send_store_event(&metadata_event)?;
@@ -1124,6 +2046,11 @@ impl StoreTrait for Store {
             );
             let metadata_event =
                 metadata::revert_block_ptr(&econn.conn, &subgraph_id, block_ptr_to)?;
+            metadata::revert_block_proofs_of_indexing(
+                &econn.conn,
+                &subgraph_id,
+                block_ptr_to.number.try_into().unwrap(),
+            )?;
 
             let (event, count) = econn.revert_block(&block_ptr_from)?;
             econn.update_entity_count(count)?;
@@ -1196,6 +2123,173 @@ impl StoreTrait for Store {
             let changes = metadata::reassign_subgraph(&econn.conn, id, node)?;
             let event = StoreEvent::new(changes);
             econn.send_store_event(&event)
+        })?;
+        // Another node may index the deployment from now on
+        self.poi_digest_cache.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn unassign_subgraph(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<(), StoreError> {
+            let changes = metadata::unassign_subgraph(&econn.conn, id)?;
+            let event = StoreEvent::new(changes);
+            econn.send_store_event(&event)
+        })?;
+        // Nothing indexes the deployment anymore
+        self.poi_digest_cache.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn rewind_failed_deployment(
+        &self,
+        id: &SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        let info = self.subgraph_info(id)?;
+        if let Some(graft_block) = info.graft_block {
+            if graft_block as u64 > block_ptr_to.number {
+                return Err(format_err!(
+                    "Can not rewind subgraph `{}` to block {} as it was \
+                    grafted at block {} and reverting past a graft point \
+                    is not possible",
+                    id,
+                    block_ptr_to.number,
+                    graft_block
+                )
+                .into());
+            }
+        }
+
+        let econn = self.get_entity_conn(id, ReplicaId::Main)?;
+        let (event, metadata_event) = econn.transaction(|| -> Result<_, StoreError> {
+            let failed = metadata::deployment_failed(&econn.conn, id)?;
+            let needed_rewind = metadata::clear_non_canonical_block(&econn.conn, id)?;
+            if !failed && !needed_rewind {
+                return Err(StoreError::DeploymentNotFailed(id.to_string()));
+            }
+            match Self::block_ptr_with_conn(id, &econn)? {
+                Some(ptr) if ptr.number >= block_ptr_to.number => (),
+                ptr => {
+                    return Err(format_err!(
+                        "Can not rewind subgraph `{}` to block {} since it is at block {:?}",
+                        id,
+                        block_ptr_to.number,
+                        ptr.map(|ptr| ptr.number)
+                    )
+                    .into())
+                }
+            }
+
+            let metadata_event = metadata::rewind_block_ptr(&econn.conn, id, block_ptr_to)?;
+            let block: BlockNumber = block_ptr_to
+                .number
+                .try_into()
+                .expect("block numbers fit into an i32");
+            metadata::revert_block_proofs_of_indexing(&econn.conn, id, block)?;
+            let (event, count) = econn.revert_blocks_from(block + 1)?;
+            econn.update_entity_count(count)?;
+            if failed {
+                metadata::unfail_deployment(&econn.conn, id)?;
+            }
+            Ok((event, metadata_event))
+        })?;
+
+        // Send the events separately, because NOTIFY uses a global DB lock.
+        // The assignment changes go out last so that the deployment is
+        // restarted from the rewound block pointer
+        econn.transaction(|| {
+            econn.send_store_event(&metadata_event)?;
+            econn.send_store_event(&event)?;
+            econn.send_store_event(&StoreEvent::new(metadata::restart_assignment_changes(id)))
+        })
+    }
+
+    fn deployment_infos(
+        &self,
+        name: Option<&SubgraphName>,
+        deployment: Option<&SubgraphDeploymentId>,
+    ) -> Result<Vec<DeploymentInfo>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::deployment_infos(&conn, name, deployment)
+    }
+
+    fn api_key(&self, secret: &str) -> Result<Option<ApiKey>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::api_key(&conn, secret)
+    }
+
+    fn record_api_key_usage(&self, usage: Vec<ApiKeyUsage>) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| metadata::record_api_key_usage(&conn, &usage))
+    }
+
+    fn deployment_block_ptrs(
+        &self,
+        network: &str,
+    ) -> Result<Vec<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::deployment_block_ptrs(&conn, network)
+    }
+
+    fn indexed_block_ptrs(
+        &self,
+        id: &SubgraphDeploymentId,
+        first: BlockNumber,
+        last: BlockNumber,
+    ) -> Result<Vec<EthereumBlockPointer>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::indexed_block_ptrs(&conn, id, first, last)
+    }
+
+    fn set_non_canonical_block(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: Option<NonCanonicalBlock>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        match block {
+            Some(block) => metadata::set_non_canonical_block(&conn, id, &block),
+            None => metadata::clear_non_canonical_block(&conn, id).map(|_| ()),
+        }
+    }
+
+    fn non_canonical_block(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Option<NonCanonicalBlock>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::non_canonical_block(&conn, id)
+    }
+
+    fn record_heartbeat(
+        &self,
+        node_id: &NodeId,
+        failover_target: bool,
+        networks: &[String],
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        metadata::record_heartbeat(&conn, node_id, failover_target, networks)
+    }
+
+    fn record_stop(&self, node_id: &NodeId) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        metadata::record_stop(&conn, node_id)
+    }
+
+    fn fail_over_dead_nodes(
+        &self,
+        timeout: Duration,
+        planned_downtime: Duration,
+    ) -> Result<Vec<Failover>, StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        econn.transaction(|| -> Result<_, StoreError> {
+            let (failovers, changes) =
+                metadata::fail_over_dead_nodes(&econn.conn, timeout, planned_downtime)?;
+            if !changes.is_empty() {
+                econn.send_store_event(&StoreEvent::new(changes))?;
+            }
+            Ok(failovers)
         })
     }
 
@@ -1246,34 +2340,32 @@ impl StoreTrait for Store {
     ) -> Result<Option<BlockNumber>, StoreError> {
         use crate::db_schema::ethereum_blocks::dsl;
 
-        // We should also really check that the block with the given hash is
-        // on the chain starting at the subgraph's current head. That check is
-        // very expensive though with the data structures we have currently
-        // available. Ideally, we'd have the last REORG_THRESHOLD blocks in
-        // memory so that we can check against them, and then mark in the
-        // database the blocks on the main chain that we consider final
+        let conn = self.get_conn()?;
         let block: Option<(i64, String)> = dsl::ethereum_blocks
             .select((dsl::number, dsl::network_name))
             .filter(dsl::hash.eq(format!("{:x}", hash)))
-            .first(&*self.get_conn()?)
+            .first(&*conn)
             .optional()?;
+        let (number, network_name) = match block {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+
         let subgraph_network = self.network_name(subgraph_id)?;
-        block
-            .map(|(number, network_name)| {
-                if subgraph_network.is_none() || Some(&network_name) == subgraph_network.as_ref() {
-                    BlockNumber::try_from(number)
-                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
-                } else {
-                    Err(StoreError::QueryExecutionError(format!(
-                        "subgraph {} belongs to network {} but block {:x} belongs to network {}",
-                        subgraph_id,
-                        subgraph_network.unwrap_or("(none)".to_owned()),
-                        hash,
-                        network_name
-                    )))
-                }
-            })
-            .transpose()
+        if subgraph_network.is_some() && Some(&network_name) != subgraph_network.as_ref() {
+            return Err(StoreError::QueryExecutionError(format!(
+                "subgraph {} belongs to network {} but block {:x} belongs to network {}",
+                subgraph_id,
+                subgraph_network.unwrap_or("(none)".to_owned()),
+                hash,
+                network_name
+            )));
+        }
+        let number = BlockNumber::try_from(number)
+            .map_err(|e| StoreError::QueryExecutionError(e.to_string()))?;
+
+        self.check_block_on_subgraph_chain(&conn, subgraph_id, &network_name, hash, number)?;
+        Ok(Some(number))
     }
 
     fn query_store(
@@ -1302,6 +2394,119 @@ impl StoreTrait for Store {
         ))
     }
 
+    fn entity_counts(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityTypeCount>, StoreError> {
+        if let Some(counts) = self.entity_counts_cache.lock().unwrap().get(subgraph_id) {
+            return Ok(counts.clone());
+        }
+
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        let counts = econn.entity_counts()?;
+        self.entity_counts_cache
+            .lock()
+            .unwrap()
+            .insert(subgraph_id.clone(), counts.clone());
+        Ok(counts)
+    }
+
+    fn entity_access_stats(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityAccessStats>, StoreError> {
+        Ok(self
+            .entity_access
+            .lock()
+            .unwrap()
+            .get(subgraph_id)
+            .map(|stats| stats.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn entity_history(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_type: &str,
+        entity_id: &str,
+        range: EntityRange,
+    ) -> Result<Vec<EntityVersion>, StoreError> {
+        if subgraph_id.is_meta() {
+            return Err(StoreError::Unknown(format_err!(
+                "the history of metadata entities is not available"
+            )));
+        }
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        econn.entity_history(entity_type, entity_id, range)
+    }
+
+    fn deployment_sync_rate(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<DeploymentSyncRate, StoreError> {
+        let conn = self.get_conn()?;
+        let (blocks_per_minute, latest) = metadata::deployment_sync_rate(&conn, subgraph_id)?;
+        let latest_block_timestamp = match latest {
+            Some(hash) => block_timestamp(&conn, &hash)?,
+            None => None,
+        };
+        Ok(DeploymentSyncRate {
+            blocks_per_minute,
+            latest_block_timestamp,
+        })
+    }
+
+    fn chain_head_statuses(&self) -> Result<Vec<ChainHeadStatus>, StoreError> {
+        let conn = self.get_conn()?;
+        chain_head_statuses(&conn)
+    }
+
+    fn record_handler_logs(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        logs: Vec<HandlerLog>,
+    ) -> Result<(), StoreError> {
+        if logs.is_empty() || *HANDLER_LOG_RETENTION == 0 {
+            return Ok(());
+        }
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            metadata::record_handler_logs(&conn, subgraph_id, &logs, *HANDLER_LOG_RETENTION)
+        })
+    }
+
+    fn handler_logs(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        levels: Vec<String>,
+        first: usize,
+    ) -> Result<Vec<HandlerLog>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::handler_logs(&conn, subgraph_id, block, &levels, first)
+    }
+
+    fn changed_entities(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        econn.find_changes(&entity_types, block)
+    }
+
+    fn entity_change_blocks(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_types: Vec<String>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeSet<BlockNumber>, StoreError> {
+        let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
+        econn.find_change_blocks(&entity_types, from, to)
+    }
+
     fn deployment_synced(&self, id: &SubgraphDeploymentId) -> Result<(), Error> {
         let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
         econn.transaction(|| {
@@ -1324,6 +2529,20 @@ impl SubgraphDeploymentStore for Store {
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
         Ok(self.subgraph_info(subgraph_id)?.network)
     }
+
+    fn graft_preview(
+        &self,
+        base: &SubgraphDeploymentId,
+        schema: &Schema,
+    ) -> Result<GraftPreview, Error> {
+        let conn = self.get_conn()?;
+        let base = self.storage(&conn, base)?;
+        // The layout for `schema` only exists in memory, so that there is
+        // nothing in the database that we need to know about
+        let catalog = Catalog::make_empty("graft_preview".to_owned())?;
+        let layout = Layout::new(schema, catalog, false)?;
+        Ok(layout.graft_preview(&base))
+    }
 }
 
 impl EthereumCallCache for Store {
@@ -1459,3 +2678,19 @@ fn contract_call_id(
 /// it very hard to export items just for testing
 #[cfg(debug_assertions)]
 pub use crate::entities::delete_all_entities_for_test_use_only;
+
+#[test]
+fn latest_migration_is_current() {
+    let migrations = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    // Diesel uses the digits of the part of the directory name before the
+    // first `_` as the version
+    let latest = std::fs::read_dir(migrations)
+        .unwrap()
+        .map(|entry| {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            name.split('_').next().unwrap().replace('-', "")
+        })
+        .max()
+        .unwrap();
+    assert_eq!(LATEST_MIGRATION, latest);
+}
@@ -1,6 +1,7 @@
 use futures::sync::mpsc::{channel, Sender};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use uuid::Uuid;
 
 use crate::notification_listener::{NotificationListener, SafeChannelName};
@@ -50,11 +51,17 @@ impl EventProducer<StoreEvent> for StoreEventListener {
     }
 }
 
+/// How often `flush` checks whether its marker event has been passed on
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Manage subscriptions to the `StoreEvent` stream. Keep a list of
 /// currently active subscribers and forward new events to each of them
 pub struct SubscriptionManager {
     subscriptions: Arc<RwLock<HashMap<String, Sender<Arc<StoreEvent>>>>>,
 
+    /// The tags of the marker events that `flush` is waiting for
+    flushes: Arc<Mutex<HashSet<usize>>>,
+
     /// listen to StoreEvents generated when applying entity operations
     listener: Mutex<StoreEventListener>,
 }
@@ -68,12 +75,12 @@ impl SubscriptionManager {
 
         let manager = SubscriptionManager {
             subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            flushes: Arc::new(Mutex::new(HashSet::new())),
             listener: Mutex::new(listener),
         };
 
         // Deal with store subscriptions
         manager.handle_store_events(store_events);
-        manager.periodically_clean_up_stale_subscriptions();
 
         let mut listener = manager.listener.lock().unwrap();
         listener.start();
@@ -90,6 +97,7 @@ impl SubscriptionManager {
         store_events: Box<dyn Stream<Item = StoreEvent, Error = ()> + Send>,
     ) {
         let subscriptions = self.subscriptions.clone();
+        let flushes = self.flushes.clone();
 
         // This channel is constantly receiving things and there are locks involved,
         // so it's best to use a blocking task.
@@ -98,56 +106,87 @@ impl SubscriptionManager {
                 .for_each(move |event| {
                     let senders = subscriptions.read().unwrap().clone();
                     let subscriptions = subscriptions.clone();
+                    let flushes = flushes.clone();
                     let event = Arc::new(event);
+                    let tag = event.tag;
+                    let is_marker = event.changes.is_empty();
 
                     // Write change to all matching subscription streams; remove subscriptions
                     // whose receiving end has been dropped
-                    stream::iter_ok::<_, ()>(senders).for_each(move |(id, sender)| {
-                        let subscriptions = subscriptions.clone();
-
-                        sender.send(event.cheap_clone()).then(move |result| {
-                            match result {
-                                Err(_send_error) => {
-                                    // Receiver was dropped
-                                    subscriptions.write().unwrap().remove(&id);
-                                    Ok(())
+                    stream::iter_ok::<_, ()>(senders)
+                        .for_each(move |(id, sender)| {
+                            let subscriptions = subscriptions.clone();
+
+                            sender.send(event.cheap_clone()).then(move |result| {
+                                match result {
+                                    Err(_send_error) => {
+                                        // Receiver was dropped
+                                        subscriptions.write().unwrap().remove(&id);
+                                        Ok(())
+                                    }
+                                    Ok(_sender) => Ok(()),
                                 }
-                                Ok(_sender) => Ok(()),
+                            })
+                        })
+                        .map(move |()| {
+                            // Everything that was sent before the marker has
+                            // been passed on now
+                            if is_marker {
+                                flushes.lock().unwrap().remove(&tag);
                             }
                         })
-                    })
                 })
                 .compat(),
         );
     }
 
-    fn periodically_clean_up_stale_subscriptions(&self) {
-        use futures03::stream::StreamExt;
+    /// Remove the subscriptions whose receiving end has gone. The
+    /// `clean-up-stale-subscriptions` background job calls this every few
+    /// seconds
+    pub(crate) fn clean_up_stale_subscriptions(&self) {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+
+        // Obtain IDs of subscriptions whose receiving end has gone
+        let stale_ids = subscriptions
+            .iter_mut()
+            .filter_map(|(id, sender)| match sender.poll_ready() {
+                Err(_) => Some(id.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Remove all stale subscriptions
+        for id in stale_ids {
+            subscriptions.remove(&id);
+        }
+    }
 
-        let subscriptions = self.subscriptions.clone();
+    /// Wait until all events that were sent before this call have been
+    /// passed on to subscriptions, but not past `deadline`. We send a
+    /// marker event without changes with `send` and wait for it to come
+    /// back, since Postgres delivers notifications in the order in which
+    /// they were committed. Returns `false` if the marker did not come back
+    /// by the deadline
+    pub fn flush(
+        &self,
+        deadline: Instant,
+        send: impl FnOnce(&StoreEvent) -> Result<(), StoreError>,
+    ) -> Result<bool, StoreError> {
+        let marker = StoreEvent::new(vec![]);
+        self.flushes.lock().unwrap().insert(marker.tag);
+        if let Err(e) = send(&marker) {
+            self.flushes.lock().unwrap().remove(&marker.tag);
+            return Err(e);
+        }
 
-        // Clean up stale subscriptions every 5s
-        graph::spawn(
-            tokio::time::interval(Duration::from_secs(5)).for_each(move |_| {
-                let mut subscriptions = subscriptions.write().unwrap();
-
-                // Obtain IDs of subscriptions whose receiving end has gone
-                let stale_ids = subscriptions
-                    .iter_mut()
-                    .filter_map(|(id, sender)| match sender.poll_ready() {
-                        Err(_) => Some(id.clone()),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>();
-
-                // Remove all stale subscriptions
-                for id in stale_ids {
-                    subscriptions.remove(&id);
-                }
-
-                futures03::future::ready(())
-            }),
-        );
+        while self.flushes.lock().unwrap().contains(&marker.tag) {
+            if Instant::now() >= deadline {
+                self.flushes.lock().unwrap().remove(&marker.tag);
+                return Ok(false);
+            }
+            std::thread::sleep(FLUSH_POLL_INTERVAL);
+        }
+        Ok(true)
     }
 
     pub fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
@@ -0,0 +1,317 @@
+//! Tests for a new deployment adopting the data of the current version of
+//! its subgraph. They are separate from the other store tests because
+//! adoption is only turned on through the environment, which is read once
+//! per process
+use graph_mock::MockMetricsRegistry;
+use hex_literal::hex;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use test_store::*;
+
+use graph::data::store::scalar;
+use graph::data::subgraph::schema::*;
+use graph::data::subgraph::{Mapping, Source, TemplateSource};
+use graph::prelude::*;
+use web3::types::{Address, H256};
+
+const BASE_GQL: &str = "
+    type Thing @entity {
+        id: ID!,
+        name: String
+    }
+";
+
+/// Adds a nullable attribute and an entity type to `BASE_GQL`
+const EXTENDED_GQL: &str = "
+    type Thing @entity {
+        id: ID!,
+        name: String,
+        color: String
+    }
+
+    type Other @entity {
+        id: ID!
+    }
+";
+
+const SUBGRAPH_NAME: &str = "adopt/test";
+
+lazy_static! {
+    static ref BLOCK_TWO: EthereumBlockPointer = (
+        H256::from(hex!(
+            "b98fb783b49de5652097a989414c767824dff7e7fd765a63b493772511db81c1"
+        )),
+        2u64
+    )
+        .into();
+}
+
+fn create_deployment_with(
+    id: &SubgraphDeploymentId,
+    gql: &str,
+    data_sources: Vec<DataSource>,
+    templates: Vec<DataSourceTemplate>,
+) -> Result<(), StoreError> {
+    let schema = Schema::parse(gql, id.clone()).unwrap();
+    let manifest = SubgraphManifest {
+        id: id.clone(),
+        location: String::new(),
+        spec_version: "1".to_owned(),
+        description: None,
+        repository: None,
+        schema: schema.clone(),
+        data_sources,
+        graft: None,
+        templates,
+        independent_data_sources: false,
+        features: BTreeSet::new(),
+        overrides: None,
+    };
+    let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
+    STORE.create_subgraph_deployment(
+        SubgraphName::new(SUBGRAPH_NAME).unwrap(),
+        &schema,
+        deployment,
+        NodeId::new("test").unwrap(),
+        SubgraphVersionSwitchingMode::Instant,
+    )
+}
+
+fn create_deployment(id: &SubgraphDeploymentId, gql: &str) {
+    create_deployment_with(id, gql, vec![], vec![]).unwrap()
+}
+
+fn mapping() -> Mapping {
+    Mapping {
+        kind: "ethereum/events".to_owned(),
+        api_version: "0.0.4".to_owned(),
+        language: "wasm/assemblyscript".to_owned(),
+        entities: vec![],
+        abis: vec![],
+        block_handlers: vec![],
+        call_handlers: vec![],
+        event_handlers: vec![],
+        entity_handlers: vec![],
+        runtime: Arc::new(Vec::new()),
+        link: Link::from("link".to_owned()),
+    }
+}
+
+fn data_source(name: &str, start_block: u64) -> DataSource {
+    DataSource {
+        kind: "ethereum/contract".to_owned(),
+        network: Some("mainnet".to_owned()),
+        name: name.to_owned(),
+        source: Source {
+            address: Some(Address::from_low_u64_be(start_block + 1)),
+            abi: "Abi".to_owned(),
+            start_block,
+            deployment: None,
+        },
+        mapping: mapping(),
+        context: None,
+        templates: vec![],
+    }
+}
+
+fn template(name: &str) -> DataSourceTemplate {
+    DataSourceTemplate {
+        kind: "ethereum/contract".to_owned(),
+        network: Some("mainnet".to_owned()),
+        name: name.to_owned(),
+        source: TemplateSource {
+            abi: "Abi".to_owned(),
+        },
+        mapping: mapping(),
+    }
+}
+
+/// Pretend that `deployment` created a data source from the template
+/// `name` at block 1
+fn add_dynamic_data_source(deployment: &SubgraphDeploymentId, name: &str) {
+    use diesel::connection::SimpleConnection as _;
+    use diesel::{Connection, PgConnection};
+
+    let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+    conn.batch_execute(&format!(
+        "insert into subgraphs.dynamic_ethereum_contract_data_source
+                (id, kind, name, network, source, mapping,
+                 ethereum_block_hash, ethereum_block_number, deployment, block_range)
+         values ('{id}-dynamic', 'ethereum/contract', '{name}', 'mainnet',
+                 '{id}-dynamic-source', '{id}-dynamic-mapping',
+                 '\\x00', 1, '{id}', '[1,)')",
+        id = deployment,
+        name = name
+    ))
+    .unwrap();
+}
+
+fn thing(id: &SubgraphDeploymentId, key: &str, name: &str) -> EntityOperation {
+    EntityOperation::Set {
+        key: EntityKey::data(id.clone(), "Thing".to_owned(), key.to_owned()),
+        data: Entity::from(vec![("id", Value::from(key)), ("name", Value::from(name))]),
+    }
+}
+
+#[test]
+fn adopt_deployment() {
+    std::env::set_var("GRAPH_ADDITIVE_SCHEMA_MIGRATION", "true");
+
+    let base = SubgraphDeploymentId::new("adoptBase").unwrap();
+    let id = SubgraphDeploymentId::new("adoptExtended").unwrap();
+
+    run_test_sequentially(remove_subgraphs, move |store, _| async move {
+        create_deployment(&base, BASE_GQL);
+        transact_entity_operations(
+            &store,
+            base.clone(),
+            *GENESIS_PTR,
+            vec![thing(&base, "1", "one")],
+        )
+        .unwrap();
+        transact_entity_operations(
+            &store,
+            base.clone(),
+            *BLOCK_ONE,
+            vec![thing(&base, "2", "two")],
+        )
+        .unwrap();
+
+        create_deployment(&id, EXTENDED_GQL);
+
+        // The new deployment carries on from the block of the base
+        assert_eq!(Some(*BLOCK_ONE), store.block_ptr(id.clone()).unwrap());
+        let key = EntityKey::data(id.clone(), "Thing".to_owned(), "2".to_owned());
+        let entity = store.get(key).unwrap().expect("the entity was adopted");
+        assert_eq!(Some(&Value::from("two")), entity.get("name"));
+        assert_eq!(None, entity.get("color"));
+
+        // The versions of the base now use the new deployment, and the
+        // base is gone
+        let infos = store
+            .deployment_infos(Some(&SubgraphName::new(SUBGRAPH_NAME).unwrap()), None)
+            .unwrap();
+        assert!(!infos.is_empty());
+        assert!(infos.iter().all(|info| info.deployment == id.as_str()));
+        assert!(store
+            .deployment_infos(None, Some(&base))
+            .unwrap()
+            .is_empty());
+
+        // Whoever was still writing the base can not do that anymore
+        let insert = EntityModification::Insert {
+            key: EntityKey::data(base.clone(), "Thing".to_owned(), "3".to_owned()),
+            data: Entity::from(vec![("id", Value::from("3"))]),
+        };
+        let stopwatch = StopwatchMetrics::new(
+            LOGGER.clone(),
+            base.clone(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+        let err = store
+            .transact_block_operations(base.clone(), *BLOCK_TWO, vec![insert], stopwatch)
+            .unwrap_err();
+        match err {
+            StoreError::DeploymentNotFound(deployment) => assert_eq!(base.as_str(), deployment),
+            e => panic!("expected DeploymentNotFound but got {}", e),
+        }
+
+        // The new deployment can be written to, including the new attribute
+        let mut entity = thing(&id, "3", "three");
+        if let EntityOperation::Set { data, .. } = &mut entity {
+            data.set("color", "red");
+        }
+        transact_entity_operations(&store, id.clone(), *BLOCK_TWO, vec![entity]).unwrap();
+    })
+}
+
+fn poi(id: &SubgraphDeploymentId) -> EntityOperation {
+    EntityOperation::Set {
+        key: EntityKey::data(id.clone(), POI_OBJECT.to_owned(), "mainnet".to_owned()),
+        data: Entity::from(vec![
+            ("id", Value::from("mainnet")),
+            ("digest", Value::from(scalar::Bytes::from(&[1u8, 2, 3][..]))),
+        ]),
+    }
+}
+
+#[test]
+fn adopt_deployment_checks_manifest() {
+    std::env::set_var("GRAPH_ADDITIVE_SCHEMA_MIGRATION", "true");
+
+    let base = SubgraphDeploymentId::new("adoptCheckedBase").unwrap();
+    let early = SubgraphDeploymentId::new("adoptEarlyDataSource").unwrap();
+    let untemplated = SubgraphDeploymentId::new("adoptMissingTemplate").unwrap();
+    let id = SubgraphDeploymentId::new("adoptChecked").unwrap();
+
+    run_test_sequentially(remove_subgraphs, move |store, _| async move {
+        create_deployment_with(
+            &base,
+            BASE_GQL,
+            vec![data_source("Token", 0)],
+            vec![template("Pair")],
+        )
+        .unwrap();
+        transact_entity_operations(
+            &store,
+            base.clone(),
+            *GENESIS_PTR,
+            vec![thing(&base, "1", "one"), poi(&base)],
+        )
+        .unwrap();
+        transact_entity_operations(
+            &store,
+            base.clone(),
+            *BLOCK_ONE,
+            vec![thing(&base, "2", "two")],
+        )
+        .unwrap();
+        add_dynamic_data_source(&base, "Pair");
+
+        // A new data source that starts before the head of the base would
+        // miss blocks
+        let err = create_deployment_with(
+            &early,
+            EXTENDED_GQL,
+            vec![data_source("Token", 0), data_source("Other", 1)],
+            vec![template("Pair")],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Other (starts at block 1)"));
+
+        // The dynamic data sources of the base need their template
+        let err = create_deployment_with(
+            &untemplated,
+            EXTENDED_GQL,
+            vec![data_source("Token", 0)],
+            vec![],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Pair"));
+
+        // Nothing of the failed attempts is left, and the base is untouched
+        assert!(store
+            .deployment_infos(None, Some(&early))
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .deployment_infos(None, Some(&untemplated))
+            .unwrap()
+            .is_empty());
+        assert_eq!(Some(*BLOCK_ONE), store.block_ptr(base.clone()).unwrap());
+
+        // Data sources that start after the head are fine
+        create_deployment_with(
+            &id,
+            EXTENDED_GQL,
+            vec![data_source("Token", 0), data_source("Other", 2)],
+            vec![template("Pair")],
+        )
+        .unwrap();
+        assert_eq!(Some(*BLOCK_ONE), store.block_ptr(id.clone()).unwrap());
+
+        // The proof of indexing of the base does not carry over
+        let key = EntityKey::data(id.clone(), POI_OBJECT.to_owned(), "mainnet".to_owned());
+        assert_eq!(None, store.get(key).unwrap());
+    })
+}
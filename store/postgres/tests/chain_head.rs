@@ -6,7 +6,7 @@ use std::fmt::Debug;
 use std::sync::Arc;
 
 use graph::components::store::{ChainStore, Store as _};
-use graph::prelude::{Future01CompatExt, SubgraphDeploymentId};
+use graph::prelude::{EthereumBlockPointer, Future01CompatExt, StoreError, SubgraphDeploymentId};
 use graph_store_postgres::NetworkStore as DieselStore;
 
 use test_store::block_store::{
@@ -179,6 +179,37 @@ fn block_number() {
     })
 }
 
+#[test]
+fn block_number_detects_reorged_block() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+    ];
+    let subgraph = SubgraphDeploymentId::new("reorgedBlockSubgraph").unwrap();
+
+    create_test_subgraph(&subgraph, "type Dummy @entity { id: ID! }");
+
+    run_test(chain, move |store| -> Result<(), ()> {
+        let head = EthereumBlockPointer::from((BLOCK_TWO.block_hash(), BLOCK_TWO.number));
+        transact_entity_operations(&store, subgraph.clone(), head, vec![])
+            .expect("Moved subgraph to block 2");
+
+        let block = store
+            .block_number(&subgraph, BLOCK_ONE.block_hash())
+            .expect("Found block 1");
+        assert_eq!(Some(1), block);
+
+        match store.block_number(&subgraph, BLOCK_ONE_SIBLING.block_hash()) {
+            Err(StoreError::BlockReorged(_, 1)) => { /* expected */ }
+            other => panic!("expected block 1 to be reorged, got {:?}", other),
+        }
+
+        Ok(())
+    })
+}
+
 #[test]
 fn block_hashes_by_number() {
     let chain = vec![
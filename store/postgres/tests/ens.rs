@@ -0,0 +1,48 @@
+//! Tests for importing names into the `ens_names` rainbow table and
+//! looking them up again
+use diesel::connection::SimpleConnection as _;
+use diesel::{Connection, PgConnection};
+
+use graph::components::ens::EnsLookup;
+use test_store::*;
+
+/// The names the tests import, with their keccak hashes
+const ETH: (&str, &str) = (
+    "eth",
+    "0x4f5b812789fc606be1b3b16908db13fc7a9adf7ca72641f84d75b47069d3d7f0",
+);
+const VITALIK: (&str, &str) = (
+    "vitalik",
+    "0xaf2caa1c2ca1d027f1ac823b529d0a67cd144264b2789fa2ea4d63a67c7103cc",
+);
+
+fn remove_names() {
+    let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+    conn.batch_execute(&format!(
+        "delete from public.ens_names where name in ('{}', '{}')",
+        ETH.0, VITALIK.0
+    ))
+    .unwrap();
+}
+
+#[test]
+fn import_names() {
+    run_test_sequentially(remove_names, |_, _| async move {
+        let ens = ENS_LOOKUP.clone();
+
+        assert_eq!(None, ens.find_name(ETH.1).unwrap());
+        assert_eq!(1, ens.import(&[ETH.0.to_owned()]).unwrap());
+        assert_eq!(Some(ETH.0.to_owned()), ens.find_name(ETH.1).unwrap());
+
+        // Names that are already known are skipped
+        let names = vec![ETH.0.to_owned(), VITALIK.0.to_owned()];
+        assert_eq!(1, ens.import(&names).unwrap());
+        assert_eq!(0, ens.import(&names).unwrap());
+        assert_eq!(
+            Some(VITALIK.0.to_owned()),
+            ens.find_name(VITALIK.1).unwrap()
+        );
+
+        remove_names();
+    })
+}
@@ -2,6 +2,7 @@ use diesel::pg::PgConnection;
 use diesel::*;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use test_store::*;
 
@@ -128,6 +129,9 @@ fn insert_test_data(store: Arc<DieselStore>) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        independent_data_sources: false,
+        features: BTreeSet::new(),
+        overrides: None,
     };
 
     // Create SubgraphDeploymentEntity
@@ -186,7 +190,11 @@ fn insert_test_data(store: Arc<DieselStore>) {
         &store,
         TEST_SUBGRAPH_ID.clone(),
         BLOCKS[1],
-        vec![test_entity_2, test_entity_3_1],
+        vec![
+            test_entity_2,
+            test_entity_3_1,
+            poi_entity(&TEST_SUBGRAPH_ID, &BLOCKS[1]),
+        ],
     )
     .unwrap();
 
@@ -204,7 +212,7 @@ fn insert_test_data(store: Arc<DieselStore>) {
         &store,
         TEST_SUBGRAPH_ID.clone(),
         BLOCKS[2],
-        vec![test_entity_3_2],
+        vec![test_entity_3_2, poi_entity(&TEST_SUBGRAPH_ID, &BLOCKS[2])],
     )
     .unwrap();
 }
@@ -324,3 +332,82 @@ fn graft() {
         Ok(())
     })
 }
+
+#[test]
+fn graft_block_proofs_of_indexing() {
+    let setup = || {
+        remove_test_data(STORE.clone());
+        insert_test_data(STORE.clone());
+    };
+    run_test_sequentially(setup, |store, _| async move {
+        let subgraph_id = SubgraphDeploymentId::new("graftedpoi").unwrap();
+        test_store::create_grafted_subgraph(
+            &subgraph_id,
+            GRAFT_GQL,
+            TEST_SUBGRAPH_ID.as_str(),
+            BLOCKS[1],
+        )
+        .unwrap();
+
+        // The grafted deployment has proofs of indexing for the blocks up
+        // to the graft point. Since they depend on the deployment, they
+        // are not the same as those of the base
+        for block in &BLOCKS[0..2] {
+            let number = block.number as BlockNumber;
+            let poi = store
+                .get_block_proof_of_indexing(&subgraph_id, number)
+                .unwrap()
+                .expect("proofs of indexing are copied when grafting");
+            assert_eq!(*block, poi.block);
+            let computed = store
+                .get_proof_of_indexing(&subgraph_id, &None, block.hash)
+                .await
+                .unwrap();
+            assert_eq!(Some(poi.digest), computed);
+
+            let base = store
+                .get_block_proof_of_indexing(&TEST_SUBGRAPH_ID, number)
+                .unwrap()
+                .unwrap();
+            assert_ne!(base.digest, poi.digest);
+        }
+
+        // Nothing after the graft point is copied
+        let poi = store
+            .get_block_proof_of_indexing(&subgraph_id, BLOCKS[2].number as BlockNumber)
+            .unwrap()
+            .unwrap();
+        assert_eq!(BLOCKS[1], poi.block);
+    })
+}
+
+#[test]
+fn poi_digests() {
+    run_test(move |store| -> Result<(), ()> {
+        let store = store.store();
+        let digest = |block: &EthereumBlockPointer| match poi_entity(&TEST_SUBGRAPH_ID, block) {
+            EntityOperation::Set { data, .. } => data.get("digest").cloned(),
+            _ => unreachable!("poi_entity sets the proof of indexing"),
+        };
+        let digests = |block: &EthereumBlockPointer| {
+            store
+                .poi_digests(&TEST_SUBGRAPH_ID, block.number as BlockNumber)
+                .unwrap()
+                .expect("the deployment keeps a proof of indexing")
+                .into_iter()
+                .map(|(region, digest)| (region, Value::Bytes(digest)))
+                .collect::<Vec<_>>()
+        };
+
+        // Nothing was written to the proof of indexing in the first block
+        assert_eq!(Vec::<(String, Value)>::new(), digests(&BLOCKS[0]));
+        for block in &BLOCKS[1..3] {
+            assert_eq!(
+                vec![("mainnet".to_owned(), digest(block).unwrap())],
+                digests(block)
+            );
+        }
+        assert_ne!(digests(&BLOCKS[1]), digests(&BLOCKS[2]));
+        Ok(())
+    })
+}
@@ -7,14 +7,16 @@ use hex_literal::hex;
 use lazy_static::lazy_static;
 use std::fmt::Debug;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use graph::data::store::scalar::{BigDecimal, BigInt, Bytes};
 use graph::prelude::{
-    web3::types::H256, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
-    EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value, ValueType,
+    web3::types::H256, ChildFilter, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder,
+    EntityQuery, EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value, ValueType,
     BLOCK_NUMBER_MAX,
 };
-use graph_store_postgres::layout_for_tests::{Layout, STRING_PREFIX_SIZE};
+use graph_mock::MockMetricsRegistry;
+use graph_store_postgres::layout_for_tests::{Layout, StatementCache, STRING_PREFIX_SIZE};
 
 use test_store::*;
 
@@ -109,6 +111,20 @@ const THINGS_GQL: &str = r#"
         description: String,
         test: String
     }
+
+    type Owner @entity {
+        id: ID!,
+        name: String!,
+        favorite: Cat,
+        cats: [Cat!],
+        toys: [Toy!] @derivedFrom(field: "owner")
+    }
+
+    type Toy @entity {
+        id: ID!,
+        name: String!,
+        owner: Owner!
+    }
 "#;
 
 const SCHEMA_NAME: &str = "layout";
@@ -334,6 +350,29 @@ fn insert_pets(conn: &PgConnection, layout: &Layout) {
     insert_pet(conn, layout, "Cat", "garfield", "Garfield");
 }
 
+fn insert_owners(conn: &PgConnection, layout: &Layout) {
+    let mut alice = Entity::new();
+    alice.set("id", "alice");
+    alice.set("name", "Alice");
+    alice.set("favorite", "garfield");
+    alice.set("cats", vec!["garfield"]);
+    insert_entity(conn, layout, "Owner", alice);
+
+    let mut bob = Entity::new();
+    bob.set("id", "bob");
+    bob.set("name", "Bob");
+    bob.set("cats", Vec::<String>::new());
+    insert_entity(conn, layout, "Owner", bob);
+
+    for (id, name, owner) in &[("ball", "Ball", "alice"), ("rope", "Rope", "bob")] {
+        let mut toy = Entity::new();
+        toy.set("id", *id);
+        toy.set("name", *name);
+        toy.set("owner", *owner);
+        insert_entity(conn, layout, "Toy", toy);
+    }
+}
+
 fn insert_test_data(conn: &PgConnection) -> Layout {
     let schema = Schema::parse(THINGS_GQL, THINGS_SUBGRAPH_ID.clone()).unwrap();
 
@@ -550,6 +589,7 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
             },
             BLOCK_NUMBER_MAX,
             None,
+            None,
         )
         .expect("Count query failed")
         .len()
@@ -582,6 +622,75 @@ fn delete() {
     });
 }
 
+#[test]
+fn entity_counts() {
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+        let mut two = SCALAR_ENTITY.clone();
+        two.set("id", "two");
+        insert_entity(&conn, &layout, "Scalar", two);
+
+        // Deleting entity two leaves its version behind
+        let key = EntityKey {
+            subgraph_id: THINGS_SUBGRAPH_ID.clone(),
+            entity_type: "Scalar".to_owned(),
+            entity_id: "two".to_owned(),
+        };
+        layout.delete(&conn, &key, 1).expect("Failed to delete");
+
+        let counts = layout
+            .entity_counts(&conn)
+            .expect("Failed to count entities");
+        let count = |entity_type: &str| {
+            counts
+                .iter()
+                .find(|count| count.entity_type == entity_type)
+                .map(|count| (count.current, count.versions))
+        };
+        assert_eq!(Some((1, 2)), count("Scalar"));
+        assert_eq!(Some((0, 0)), count("Cat"));
+        Ok(())
+    });
+}
+
+#[test]
+fn find_changes() {
+    run_test(|conn, layout| -> Result<(), ()> {
+        let mut two = SCALAR_ENTITY.clone();
+        two.set("id", "two");
+        insert_entity(&conn, &layout, "Scalar", two.clone());
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+        two.set("int", 17);
+        update_entity(&conn, &layout, "Scalar", two);
+
+        let ids = |block| {
+            let changes = layout
+                .find_changes(&conn, &["Scalar".to_owned(), "Cat".to_owned()], block)
+                .expect("Failed to find changes");
+            changes
+                .into_iter()
+                .map(|(entity_type, entities)| {
+                    let ids = entities
+                        .into_iter()
+                        .map(|entity| entity.id().unwrap())
+                        .collect::<Vec<_>>();
+                    (entity_type, ids)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![(
+                "Scalar".to_owned(),
+                vec!["one".to_owned(), "two".to_owned()]
+            )],
+            ids(0)
+        );
+        assert_eq!(vec![("Scalar".to_owned(), vec!["two".to_owned()])], ids(1));
+        assert!(ids(2).is_empty());
+        Ok(())
+    });
+}
+
 #[test]
 fn conflicting_entity() {
     run_test(|conn, layout| -> Result<(), ()> {
@@ -636,36 +745,59 @@ fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
             None,
         );
         insert_pets(conn, layout);
+        insert_owners(conn, layout);
 
         let unordered = matches!(query.order, EntityOrder::Unordered);
-        let entities = layout
-            .query::<Entity>(
-                &*LOGGER,
-                conn,
-                query.collection,
-                query.filter,
-                query.order,
-                query.range,
-                BLOCK_NUMBER_MAX,
-                None,
-            )
-            .expect("layout.query failed to execute query");
-
-        let mut entity_ids: Vec<_> = entities
-            .into_iter()
-            .map(|entity| match entity.get("id") {
-                Some(Value::String(id)) => id.to_owned(),
-                Some(_) => panic!("layout.query returned entity with non-string ID attribute"),
-                None => panic!("layout.query returned entity with no ID attribute"),
-            })
-            .collect();
+        let find = |statements: Option<&StatementCache>| {
+            let query = query.clone();
+            let entities = layout
+                .query::<Entity>(
+                    &*LOGGER,
+                    conn,
+                    query.collection,
+                    query.filter,
+                    query.order,
+                    query.range,
+                    BLOCK_NUMBER_MAX,
+                    None,
+                    statements,
+                )
+                .expect("layout.query failed to execute query");
+
+            let mut entity_ids: Vec<_> = entities
+                .into_iter()
+                .map(|entity| match entity.get("id") {
+                    Some(Value::String(id)) => id.to_owned(),
+                    Some(_) => panic!("layout.query returned entity with non-string ID attribute"),
+                    None => panic!("layout.query returned entity with no ID attribute"),
+                })
+                .collect();
+            if unordered {
+                entity_ids.sort();
+            }
+            entity_ids
+        };
 
         if unordered {
-            entity_ids.sort();
             expected_entity_ids.sort();
         }
 
-        assert_eq!(entity_ids, expected_entity_ids);
+        assert_eq!(find(None), expected_entity_ids);
+
+        // Running the query as a prepared statement, both when it gets
+        // prepared and when the prepared statement is reused, must not
+        // change the result
+        let statements = StatementCache::new(100, Arc::new(MockMetricsRegistry::new()));
+        assert_eq!(find(Some(&statements)), expected_entity_ids);
+        let count = statements.statement_count();
+        assert!(count <= 1);
+        assert_eq!(find(Some(&statements)), expected_entity_ids);
+        assert_eq!(count, statements.statement_count());
+
+        // Once the cache is full, new statements are not prepared anymore
+        let statements = StatementCache::new(0, Arc::new(MockMetricsRegistry::new()));
+        assert_eq!(find(Some(&statements)), expected_entity_ids);
+        assert_eq!(0, statements.statement_count());
 
         Ok(())
     })
@@ -736,6 +868,119 @@ fn find_interface() {
         vec!["garfield", "pluto"],
         query(vec!["Cat", "Dog"]).unordered(),
     );
+
+    // Test that first and skip apply to the combined entities
+    test_find(
+        vec!["pluto"],
+        query(vec!["Cat", "Dog"]).desc("name").first(1),
+    );
+
+    test_find(
+        vec!["pluto"],
+        query(vec!["Cat", "Dog"]).asc("name").skip(1).first(1),
+    );
+
+    test_find(
+        vec!["garfield"],
+        query(vec!["Cat", "Dog"])
+            .filter(EntityFilter::StartsWith("name".into(), Value::from("Gar")))
+            .asc("name")
+            .first(1),
+    );
+}
+
+fn child_filter(
+    attr: &str,
+    entity_type: &str,
+    filter: EntityFilter,
+    derived: bool,
+) -> EntityFilter {
+    EntityFilter::Child(ChildFilter {
+        attr: attr.to_owned(),
+        entity_type: entity_type.to_owned(),
+        filter: Box::new(filter),
+        derived,
+    })
+}
+
+#[test]
+fn find_child_filter() {
+    // Reference to a single child
+    test_find(
+        vec!["alice"],
+        query(vec!["Owner"]).filter(child_filter(
+            "favorite",
+            "Cat",
+            EntityFilter::new_equal("name", "Garfield"),
+            false,
+        )),
+    );
+
+    // Reference to a list of children
+    test_find(
+        vec!["alice"],
+        query(vec!["Owner"]).filter(child_filter(
+            "cats",
+            "Cat",
+            EntityFilter::StartsWith("name".into(), Value::from("Gar")),
+            false,
+        )),
+    );
+
+    // Derived field; the reference is stored in the child
+    test_find(
+        vec!["bob"],
+        query(vec!["Owner"]).filter(child_filter(
+            "owner",
+            "Toy",
+            EntityFilter::new_equal("name", "Rope"),
+            true,
+        )),
+    );
+
+    test_find(
+        vec!["ball"],
+        query(vec!["Toy"]).filter(child_filter(
+            "owner",
+            "Owner",
+            EntityFilter::new_equal("name", "Alice"),
+            false,
+        )),
+    );
+
+    // Child filters can be nested and combined with other filters
+    test_find(
+        vec!["ball"],
+        query(vec!["Toy"]).filter(EntityFilter::And(vec![
+            EntityFilter::new_equal("name", "Ball"),
+            child_filter(
+                "owner",
+                "Owner",
+                child_filter(
+                    "favorite",
+                    "Cat",
+                    EntityFilter::new_equal("name", "Garfield"),
+                    false,
+                ),
+                false,
+            ),
+        ])),
+    );
+
+    test_find(
+        vec![],
+        query(vec!["Toy"]).filter(child_filter(
+            "owner",
+            "Owner",
+            child_filter(
+                "favorite",
+                "Cat",
+                EntityFilter::new_equal("name", "Pluto"),
+                false,
+            ),
+            false,
+        )),
+    );
 }
 
 #[test]
@@ -1385,6 +1630,7 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
                 query.range,
                 BLOCK_NUMBER_MAX,
                 None,
+                None,
             )
             .expect("layout.query failed to execute query");
 
@@ -1,20 +1,22 @@
+use diesel::connection::SimpleConnection as _;
 use diesel::pg::PgConnection;
 use diesel::*;
 use graph_mock::MockMetricsRegistry;
 use graphql_parser::schema as s;
 use hex_literal::hex;
 use lazy_static::lazy_static;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use test_store::*;
 
 use graph::components::store::{EntityFilter, EntityKey, EntityOrder, EntityQuery};
+use graph::components::subgraph::PROOF_OF_INDEXING_VERSION;
 use graph::data::store::scalar;
 use graph::data::subgraph::schema::*;
 use graph::data::subgraph::*;
 use graph::prelude::*;
-use graph_store_postgres::layout_for_tests::STRING_PREFIX_SIZE;
+use graph_store_postgres::layout_for_tests::{MOVE_COPY_HOOK, STRING_PREFIX_SIZE};
 use graph_store_postgres::NetworkStore as DieselStore;
 use web3::types::{Address, H256};
 
@@ -146,11 +148,8 @@ where
         .unwrap_or_else(|e| panic!("Failed to run Store test: {:?}", e));
 }
 
-/// Inserts test data into the store.
-///
-/// Inserts data in test blocks `GENESIS_PTR`, `TEST_BLOCK_1_PTR`, and
-/// `TEST_BLOCK_2_PTR`
-fn insert_test_data(store: Arc<DieselStore>) {
+/// Deploy the test subgraph as `test/store` on the node `test`
+fn deploy_test_subgraph(store: &DieselStore) {
     let manifest = SubgraphManifest {
         id: TEST_SUBGRAPH_ID.clone(),
         location: "/ipfs/test".to_owned(),
@@ -161,6 +160,9 @@ fn insert_test_data(store: Arc<DieselStore>) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        independent_data_sources: false,
+        features: BTreeSet::new(),
+        overrides: None,
     };
 
     // Create SubgraphDeploymentEntity
@@ -176,6 +178,14 @@ fn insert_test_data(store: Arc<DieselStore>) {
             SubgraphVersionSwitchingMode::Instant,
         )
         .unwrap();
+}
+
+/// Inserts test data into the store.
+///
+/// Inserts data in test blocks `GENESIS_PTR`, `TEST_BLOCK_1_PTR`, and
+/// `TEST_BLOCK_2_PTR`
+fn insert_test_data(store: Arc<DieselStore>) {
+    deploy_test_subgraph(&store);
 
     let test_entity_1 = create_test_entity(
         "1",
@@ -552,6 +562,147 @@ fn partially_update_existing() {
     })
 }
 
+#[test]
+fn entity_history() {
+    run_test(|store| -> Result<(), ()> {
+        let versions = store
+            .entity_history(&TEST_SUBGRAPH_ID, USER, "3", EntityRange::first(100))
+            .expect("history can be loaded");
+
+        let ranges: Vec<_> = versions
+            .iter()
+            .map(|version| (version.block_start, version.block_end))
+            .collect();
+        assert_eq!(vec![(1, Some(2)), (2, None)], ranges);
+
+        let emails: Vec<_> = versions
+            .iter()
+            .map(|version| version.data.get("email").cloned())
+            .collect();
+        assert_eq!(
+            vec![
+                Some(Value::from("queensha@email.com")),
+                Some(Value::from("teeko@email.com"))
+            ],
+            emails
+        );
+
+        // The history can be read in pages
+        let range = EntityRange {
+            first: Some(1),
+            skip: 1,
+        };
+        let versions = store
+            .entity_history(&TEST_SUBGRAPH_ID, USER, "3", range)
+            .expect("history can be loaded");
+        assert_eq!(1, versions.len());
+        assert_eq!((2, None), (versions[0].block_start, versions[0].block_end));
+
+        let versions = store
+            .entity_history(
+                &TEST_SUBGRAPH_ID,
+                USER,
+                "no such user",
+                EntityRange::first(100),
+            )
+            .expect("history can be loaded");
+        assert!(versions.is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn entity_change_blocks() {
+    run_test(|store| -> Result<(), ()> {
+        let change_blocks = |from, to| {
+            store
+                .entity_change_blocks(&TEST_SUBGRAPH_ID, vec![USER.to_owned()], from, to)
+                .expect("change blocks can be loaded")
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![0, 1, 2], change_blocks(0, 10));
+        assert_eq!(vec![1], change_blocks(1, 1));
+        assert_eq!(vec![1, 2], change_blocks(1, 2));
+        assert!(change_blocks(3, 10).is_empty());
+
+        // The blocks are those for which `changed_entities` finds changes
+        let changes = store
+            .changed_entities(&TEST_SUBGRAPH_ID, vec![USER.to_owned()], 2)
+            .expect("changes can be loaded");
+        let ids: Vec<_> = changes[USER]
+            .iter()
+            .map(|entity| entity.id().unwrap())
+            .collect();
+        assert_eq!(vec!["3"], ids);
+
+        let change_blocks = store
+            .entity_change_blocks(&TEST_SUBGRAPH_ID, vec![], 0, 10)
+            .expect("change blocks can be loaded");
+        assert!(change_blocks.is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn handler_logs() {
+    use graph_store_postgres::metadata_for_tests::{handler_logs, record_handler_logs};
+
+    run_test(|_| -> Result<(), ()> {
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        let id = SubgraphDeploymentId::new("handlerLogsTest").unwrap();
+        diesel::sql_query("delete from subgraphs.handler_log where deployment = $1")
+            .bind::<diesel::sql_types::Text, _>(id.as_str())
+            .execute(&conn)
+            .unwrap();
+
+        let logs: Vec<_> = (0..10u64)
+            .map(|block_number| {
+                let level = ["info", "error"][block_number as usize % 2];
+                HandlerLog {
+                    block_number,
+                    block_hash: H256::from_low_u64_be(block_number),
+                    data_source: "Token".to_owned(),
+                    level: level.to_owned(),
+                    message: format!("block {}", block_number),
+                    timestamp: 0,
+                }
+            })
+            .collect();
+        let blocks = |block: Option<BlockNumber>, levels: &[&str], first: usize| {
+            let levels: Vec<_> = levels.iter().map(|level| level.to_string()).collect();
+            handler_logs(&conn, &id, block, &levels, first)
+                .unwrap()
+                .into_iter()
+                .map(|log| log.block_number)
+                .collect::<Vec<_>>()
+        };
+
+        // Only the most recent messages are kept, across calls
+        record_handler_logs(&conn, &id, &logs[..4], 6).unwrap();
+        record_handler_logs(&conn, &id, &logs[4..], 6).unwrap();
+        assert_eq!(vec![9, 8, 7, 6, 5, 4], blocks(None, &[], 100));
+
+        // Filters
+        assert_eq!(vec![9, 7, 5], blocks(None, &["error"], 100));
+        assert_eq!(
+            vec![9, 8, 7, 6, 5, 4],
+            blocks(None, &["error", "info"], 100)
+        );
+        assert_eq!(vec![6], blocks(Some(6), &[], 100));
+        assert!(blocks(Some(6), &["error"], 100).is_empty());
+        assert_eq!(vec![9, 8], blocks(None, &[], 2));
+
+        // Recording more messages than are kept at once
+        record_handler_logs(&conn, &id, &logs, 3).unwrap();
+        assert_eq!(vec![9, 8, 7], blocks(None, &[], 100));
+        Ok(())
+    })
+}
+
 fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
     let expected_entity_ids: Vec<String> =
         expected_entity_ids.into_iter().map(str::to_owned).collect();
@@ -1425,6 +1576,7 @@ fn mock_data_source() -> DataSource {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
             abi: String::from("123123"),
             start_block: 0,
+            deployment: None,
         },
         mapping: Mapping {
             kind: String::from("ethereum/events"),
@@ -1435,6 +1587,7 @@ fn mock_data_source() -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1456,6 +1609,7 @@ fn mock_data_source() -> DataSource {
                 event_handlers: vec![],
                 call_handlers: vec![],
                 block_handlers: vec![],
+                entity_handlers: vec![],
                 link: Link {
                     link: "link".to_owned(),
                 },
@@ -1619,6 +1773,9 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            independent_data_sources: false,
+            features: BTreeSet::new(),
+            overrides: None,
         };
 
         // Create SubgraphDeploymentEntity
@@ -2368,3 +2525,953 @@ fn reorg_tracking() {
         Ok(())
     })
 }
+
+#[test]
+fn sync_rate() {
+    run_test(|store| -> Result<(), ()> {
+        // The first block we process only starts the first sampling
+        // interval, and the test blocks are not in the block cache
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            TEST_BLOCK_4_PTR.clone(),
+            vec![],
+        )
+        .unwrap();
+        let rate = store
+            .deployment_sync_rate(&TEST_SUBGRAPH_ID)
+            .expect("can get sync rate");
+        assert_eq!(DeploymentSyncRate::default(), rate);
+
+        // Pretend the current sample was taken two minutes ago at block 0;
+        // moving to block 5 then means we processed 2.5 blocks per minute
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        let backdate_sample = |number: u64, age: &str| {
+            conn.batch_execute(&format!(
+                "update subgraphs.subgraph_deployment
+                    set sync_rate_block_number = {},
+                        sync_rate_sampled_at = now() - interval '{}'
+                  where id = '{}'",
+                number,
+                age,
+                TEST_SUBGRAPH_ID.as_str()
+            ))
+            .unwrap()
+        };
+        let blocks_per_minute = || {
+            store
+                .deployment_sync_rate(&TEST_SUBGRAPH_ID)
+                .expect("can get sync rate")
+                .blocks_per_minute
+                .expect("the sync rate is known")
+        };
+
+        backdate_sample(0, "2 minutes");
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            TEST_BLOCK_5_PTR.clone(),
+            vec![],
+        )
+        .unwrap();
+        assert!((blocks_per_minute() - 2.5).abs() < 0.01);
+
+        // A deployment that has not processed a block in a while is not
+        // syncing at all
+        backdate_sample(TEST_BLOCK_5_PTR.number, "6 minutes");
+        assert_eq!(0.0, blocks_per_minute());
+
+        // Make sure the query for chain heads is syntactically correct
+        store
+            .chain_head_statuses()
+            .expect("can get chain head statuses");
+        Ok(())
+    })
+}
+
+#[test]
+fn rewind_failed_deployment() {
+    run_test(|store| -> Result<(), ()> {
+        let health = |store: &DieselStore| {
+            store
+                .deployment_infos(None, Some(&*TEST_SUBGRAPH_ID))
+                .unwrap()[0]
+                .health
+                .clone()
+        };
+        let user = |id: &str| EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: USER.to_owned(),
+            entity_id: id.to_owned(),
+        };
+
+        // Only failed deployments can be rewound
+        match store.rewind_failed_deployment(&TEST_SUBGRAPH_ID, *GENESIS_PTR) {
+            Err(StoreError::DeploymentNotFailed(_)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let error = SubgraphError {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            message: "bad provider data".to_owned(),
+            block_ptr: Some(*TEST_BLOCK_3_PTR),
+            handler: None,
+            deterministic: false,
+        };
+        store
+            .apply_metadata_operations(
+                &TEST_SUBGRAPH_ID,
+                SubgraphDeploymentEntity::fail_operations(&TEST_SUBGRAPH_ID, error),
+            )
+            .unwrap();
+        assert_eq!("failed", health(&store));
+        assert_eq!(3, get_entity_count(store.clone(), &TEST_SUBGRAPH_ID));
+
+        store
+            .rewind_failed_deployment(&TEST_SUBGRAPH_ID, *GENESIS_PTR)
+            .unwrap();
+
+        assert_eq!(
+            Some(*GENESIS_PTR),
+            store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+        assert_eq!("healthy", health(&store));
+        assert!(store.get(user("1")).unwrap().is_some());
+        assert!(store.get(user("2")).unwrap().is_none());
+        assert!(store.get(user("3")).unwrap().is_none());
+        assert_eq!(1, get_entity_count(store.clone(), &TEST_SUBGRAPH_ID));
+        Ok(())
+    })
+}
+
+#[test]
+fn api_keys() {
+    run_test(|store| -> Result<(), ()> {
+        let store = store.store();
+
+        let (open, open_secret) = store.create_api_key(None, None, Some(5)).unwrap();
+        let (scoped, scoped_secret) = store
+            .create_api_key(
+                Some("dashboard".to_owned()),
+                Some(vec![TEST_SUBGRAPH_ID.clone()]),
+                None,
+            )
+            .unwrap();
+        let other = SubgraphDeploymentId::new("doesNotExist").unwrap();
+        match store.create_api_key(None, Some(vec![other.clone()]), None) {
+            Err(StoreError::DeploymentNotFound(_)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let key = store.api_key(&open_secret).unwrap().unwrap();
+        assert_eq!(open, key);
+        assert!(key.allows(&other));
+        let key = store.api_key(&scoped_secret).unwrap().unwrap();
+        assert_eq!(scoped, key);
+        assert!(key.allows(&TEST_SUBGRAPH_ID));
+        assert!(!key.allows(&other));
+        assert_eq!(None, store.api_key("guess").unwrap());
+
+        let usage = |queries| ApiKeyUsage {
+            api_key: scoped.id.clone(),
+            deployment: TEST_SUBGRAPH_ID.clone(),
+            queries,
+        };
+        store.record_api_key_usage(vec![usage(3)]).unwrap();
+        store.record_api_key_usage(vec![usage(4)]).unwrap();
+        let recorded = store.api_key_usage(Some(&scoped.id), 1).unwrap();
+        assert_eq!(1, recorded.len());
+        assert_eq!(7, recorded[0].queries);
+        assert!(store.api_key_usage(Some(&open.id), 1).unwrap().is_empty());
+
+        assert!(store.revoke_api_key(&scoped.id).unwrap());
+        assert!(!store.revoke_api_key(&scoped.id).unwrap());
+        assert!(store.api_key(&scoped_secret).unwrap().unwrap().revoked);
+        Ok(())
+    })
+}
+
+#[test]
+fn entity_access_stats() {
+    run_test(|store| -> Result<(), ()> {
+        // Other tests also read users, so only look at how the counts change
+        let reads = |store: &DieselStore| {
+            store
+                .entity_access_stats(&TEST_SUBGRAPH_ID)
+                .unwrap()
+                .into_iter()
+                .find(|stats| stats.entity_type == USER)
+                .map(|stats| (stats.query_reads, stats.mapping_reads))
+                .unwrap_or((0, 0))
+        };
+
+        let (query_reads, mapping_reads) = reads(&store);
+        store
+            .get(EntityKey {
+                subgraph_id: TEST_SUBGRAPH_ID.clone(),
+                entity_type: USER.to_owned(),
+                entity_id: "1".to_owned(),
+            })
+            .unwrap();
+        assert_eq!((query_reads, mapping_reads + 1), reads(&store));
+
+        store
+            .clone()
+            .query_store(false)
+            .find_query_values(user_query())
+            .unwrap();
+        assert_eq!((query_reads + 1, mapping_reads + 1), reads(&store));
+
+        // Reads of metadata are not recorded
+        store
+            .get(SubgraphDeploymentEntity::key(TEST_SUBGRAPH_ID.clone()))
+            .unwrap();
+        assert!(store
+            .entity_access_stats(&*SUBGRAPHS_ID)
+            .unwrap()
+            .is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn query_deadline_and_cancel() {
+    run_test(|store| -> Result<(), ()> {
+        let query_store = store.clone().query_store(false);
+
+        let mut query = user_query();
+        query.deadline = Some(Instant::now() + Duration::from_secs(60));
+        let guard = CancelGuard::new();
+        query.cancel = Some(guard.handle());
+        let users = query_store
+            .find_query_values(query)
+            .expect("queries within their deadline run");
+        assert_eq!(3, users.len());
+
+        // Queries that are past their deadline are not sent to the database
+        let mut query = user_query();
+        query.deadline = Some(Instant::now() - Duration::from_secs(1));
+        match query_store.find_query_values(query) {
+            Err(QueryExecutionError::Timeout) => (),
+            other => panic!("expected a timeout but got {:?}", other),
+        }
+
+        // Neither are queries that were canceled
+        let mut query = user_query();
+        query.cancel = Some(guard.handle());
+        drop(guard);
+        match query_store.find_query_values(query) {
+            Err(QueryExecutionError::Timeout) => (),
+            other => panic!("expected a timeout but got {:?}", other),
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn background_jobs() {
+    run_test(|store| -> Result<(), ()> {
+        let store = store.store();
+        let runner = store.job_runner(NodeId::new("test").unwrap());
+
+        let mut ran = runner.run_due_jobs();
+        ran.sort();
+        assert_eq!(
+            vec![
+                "analyze",
+                "prune-job-history",
+                "prune-large-notifications",
+                "rebuild-invalid-indexes",
+                "record-unused-deployments",
+                "refresh-size-views"
+            ],
+            ran
+        );
+
+        let runs = store.job_runs(None, 10).unwrap();
+        assert_eq!(6, runs.len());
+        for run in &runs {
+            assert_eq!("test", run.node_id);
+            assert_eq!("main", run.database);
+            assert!(run.finished_at.is_some());
+            assert_eq!(None, run.error);
+        }
+
+        // Jobs do not run again before their interval has passed
+        assert!(runner.run_due_jobs().is_empty());
+        assert_eq!(1, store.job_runs(Some("analyze"), 10).unwrap().len());
+        Ok(())
+    })
+}
+
+#[test]
+fn background_job_leases() {
+    run_test(|store| -> Result<(), ()> {
+        let store = store.store();
+        let runner = store.job_runner(NodeId::new("test").unwrap());
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+
+        // A run of `analyze` on another node that is still going keeps
+        // `analyze` from running, and since only one job may run at a time,
+        // all other jobs, too
+        conn.batch_execute(
+            "insert into subgraphs.job_run(job, node_id, started_at, heartbeat_at)
+             values ('analyze', 'other', now() - interval '2 days', now())",
+        )
+        .unwrap();
+        assert!(runner.run_due_jobs().is_empty());
+
+        // Once the other node stops renewing its lease, the run counts as
+        // abandoned, and jobs run again
+        conn.batch_execute(
+            "update subgraphs.job_run
+                set heartbeat_at = now() - interval '1 hour'
+              where node_id = 'other'",
+        )
+        .unwrap();
+        assert!(runner.run_due_jobs().contains(&"analyze"));
+        let abandoned = store
+            .job_runs(Some("analyze"), 10)
+            .unwrap()
+            .into_iter()
+            .find(|run| run.node_id == "other")
+            .unwrap();
+        assert!(abandoned.finished_at.is_some());
+        assert!(abandoned.error.unwrap().starts_with("abandoned"));
+        Ok(())
+    })
+}
+
+#[test]
+fn non_canonical_block() {
+    run_test(|store| -> Result<(), ()> {
+        let needs_rewind = |store: &DieselStore| {
+            store
+                .deployment_infos(None, Some(&*TEST_SUBGRAPH_ID))
+                .unwrap()[0]
+                .needs_rewind
+        };
+
+        assert_eq!(None, store.non_canonical_block(&TEST_SUBGRAPH_ID).unwrap());
+        assert!(!needs_rewind(&store));
+
+        let block = NonCanonicalBlock {
+            block: *TEST_BLOCK_3_PTR,
+            canonical_hash: Some(H256::zero()),
+        };
+        store
+            .set_non_canonical_block(&TEST_SUBGRAPH_ID, Some(block))
+            .unwrap();
+        assert_eq!(
+            Some(block),
+            store.non_canonical_block(&TEST_SUBGRAPH_ID).unwrap()
+        );
+        assert!(needs_rewind(&store));
+
+        // Deployments that need a rewind can be rewound without having
+        // failed, and rewinding them clears the flag
+        store
+            .rewind_failed_deployment(&TEST_SUBGRAPH_ID, *TEST_BLOCK_1_PTR)
+            .unwrap();
+        assert_eq!(None, store.non_canonical_block(&TEST_SUBGRAPH_ID).unwrap());
+        assert!(!needs_rewind(&store));
+        assert_eq!(
+            Some(*TEST_BLOCK_1_PTR),
+            store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+
+        store
+            .set_non_canonical_block(&TEST_SUBGRAPH_ID, Some(block))
+            .unwrap();
+        store
+            .set_non_canonical_block(&TEST_SUBGRAPH_ID, None)
+            .unwrap();
+        assert!(!needs_rewind(&store));
+        Ok(())
+    })
+}
+
+#[test]
+fn remove_deployment() {
+    run_test(|store| -> Result<(), ()> {
+        let infos = |store: &DieselStore| {
+            store
+                .deployment_infos(None, Some(&*TEST_SUBGRAPH_ID))
+                .unwrap()
+        };
+
+        // The deployment is the current version of `test/store`
+        assert!(store.store().remove_deployment(&TEST_SUBGRAPH_ID).is_err());
+
+        store
+            .remove_subgraph(SubgraphName::new("test/store").unwrap())
+            .unwrap();
+        let info = &infos(&store)[0];
+        assert_eq!(None, info.subgraph);
+        assert_eq!(None, info.node_id);
+
+        store.store().remove_deployment(&TEST_SUBGRAPH_ID).unwrap();
+        assert!(infos(&store).is_empty());
+        assert!(store.store().remove_deployment(&TEST_SUBGRAPH_ID).is_err());
+        Ok(())
+    })
+}
+
+#[test]
+fn move_deployment() {
+    run_test(|store| -> Result<(), ()> {
+        assert!(store
+            .store()
+            .move_deployment(&TEST_SUBGRAPH_ID, "no_such_tablespace")
+            .is_err());
+
+        store
+            .store()
+            .move_deployment(&TEST_SUBGRAPH_ID, "pg_default")
+            .unwrap();
+
+        // The entities are still there after the move, and the deployment
+        // can still be written to
+        assert_eq!(3, store.find(user_query()).unwrap().len());
+        let key = EntityKey::data(TEST_SUBGRAPH_ID.clone(), USER.to_owned(), "3".to_owned());
+        assert_eq!(
+            Some(Value::from("teeko@email.com")),
+            store
+                .get(key.clone())
+                .unwrap()
+                .unwrap()
+                .get("email")
+                .cloned()
+        );
+        let entity = create_test_entity(
+            "3",
+            USER,
+            "Shaqueeena",
+            "queensha@email.com",
+            28 as i32,
+            111.7,
+            false,
+            None,
+        );
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![entity],
+        )
+        .unwrap();
+        assert_eq!(
+            Some(Value::from("queensha@email.com")),
+            store.get(key).unwrap().unwrap().get("email").cloned()
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn move_deployment_to_renamed_schema() {
+    run_test(|store| -> Result<(), ()> {
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        let schema_exists = |name: &str| {
+            select(dsl::sql::<sql_types::Bool>(&format!(
+                "exists (select 1 from pg_namespace where nspname = '{}')",
+                name
+            )))
+            .get_result::<bool>(&conn)
+            .unwrap()
+        };
+        let old_name = select(dsl::sql::<sql_types::Text>(&format!(
+            "(select name from public.deployment_schemas where subgraph = '{}')",
+            TEST_SUBGRAPH_ID.as_str()
+        )))
+        .get_result::<String>(&conn)
+        .unwrap();
+
+        // The deployment can not be moved into a schema that exists already
+        assert!(store
+            .store()
+            .move_deployment_schema(&TEST_SUBGRAPH_ID, None, Some("public"))
+            .is_err());
+
+        let events = tap_store_events(|| {
+            store
+                .store()
+                .move_deployment_schema(&TEST_SUBGRAPH_ID, None, Some("moved_test_subgraph"))
+                .unwrap()
+        });
+        assert!(schema_exists("moved_test_subgraph"));
+        assert!(!schema_exists(&old_name));
+        // Other nodes learn that they have to forget the old layout
+        assert_eq!(1, events.len());
+        assert_eq!(TEST_SUBGRAPH_ID.as_str(), events[0].changes[0].entity_id);
+
+        // The entities are still there after the move, and the deployment
+        // can still be written to
+        assert_eq!(3, store.find(user_query()).unwrap().len());
+        let entity = create_test_entity(
+            "3",
+            USER,
+            "Shaqueeena",
+            "queensha@email.com",
+            28 as i32,
+            111.7,
+            false,
+            None,
+        );
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![entity],
+        )
+        .unwrap();
+        let key = EntityKey::data(TEST_SUBGRAPH_ID.clone(), USER.to_owned(), "3".to_owned());
+        assert_eq!(
+            Some(Value::from("queensha@email.com")),
+            store.get(key).unwrap().unwrap().get("email").cloned()
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn move_deployment_while_writing() {
+    run_test(|store| -> Result<(), ()> {
+        // Change an entity after the tables were copied so that the move
+        // has to apply that change to the copy
+        let writer = store.clone();
+        *MOVE_COPY_HOOK.lock().unwrap() = Some(Box::new(move || {
+            let entity = create_test_entity(
+                "3",
+                USER,
+                "Shaqueeena",
+                "queensha@email.com",
+                28 as i32,
+                111.7,
+                false,
+                None,
+            );
+            transact_entity_operations(
+                &writer,
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                vec![entity],
+            )
+            .map(|_| ())
+        }));
+        let result = store
+            .store()
+            .move_deployment(&TEST_SUBGRAPH_ID, "pg_default");
+        *MOVE_COPY_HOOK.lock().unwrap() = None;
+        result.unwrap();
+
+        assert_eq!(3, store.find(user_query()).unwrap().len());
+        let key = EntityKey::data(TEST_SUBGRAPH_ID.clone(), USER.to_owned(), "3".to_owned());
+        assert_eq!(
+            Some(Value::from("queensha@email.com")),
+            store.get(key).unwrap().unwrap().get("email").cloned()
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn failed_move_deployment_removes_copy() {
+    run_test(|store| -> Result<(), ()> {
+        *MOVE_COPY_HOOK.lock().unwrap() = Some(Box::new(|| {
+            Err(StoreError::Unknown(format_err!("the move was interrupted")))
+        }));
+        let result = store
+            .store()
+            .move_deployment(&TEST_SUBGRAPH_ID, "pg_default");
+        *MOVE_COPY_HOOK.lock().unwrap() = None;
+        assert!(result.is_err());
+
+        // Neither the copy nor the triggers that recorded changes for it
+        // are left behind
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        let leftovers = select(dsl::sql::<sql_types::BigInt>(
+            "(select count(*) from pg_namespace where nspname like '%\\_move')
+             + (select count(*) from pg_trigger where tgname = 'record_changed_row')",
+        ))
+        .get_result::<i64>(&conn)
+        .unwrap();
+        assert_eq!(0, leftovers);
+
+        // The deployment can still be written to
+        let entity = create_test_entity(
+            "3",
+            USER,
+            "Shaqueeena",
+            "queensha@email.com",
+            28 as i32,
+            111.7,
+            false,
+            None,
+        );
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![entity],
+        )
+        .unwrap();
+        assert_eq!(3, store.find(user_query()).unwrap().len());
+        Ok(())
+    })
+}
+
+#[test]
+fn unused_deployments() {
+    run_test(|store| -> Result<(), ()> {
+        assert!(store.store().unused_deployments().unwrap().is_empty());
+
+        store
+            .remove_subgraph(SubgraphName::new("test/store").unwrap())
+            .unwrap();
+        let unused = store.store().unused_deployments().unwrap();
+        assert_eq!(1, unused.len());
+        assert_eq!(TEST_SUBGRAPH_ID.as_str(), unused[0].deployment);
+        assert!(unused[0].size > 0);
+
+        store.store().remove_deployment(&TEST_SUBGRAPH_ID).unwrap();
+        assert!(store.store().unused_deployments().unwrap().is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn remove_expired_unused_deployments() {
+    run_test(|store| -> Result<(), ()> {
+        let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        let make_unused_for_two_hours = || {
+            conn.batch_execute(
+                "update subgraphs.unused_deployment
+                    set unused_at = now() - interval '2 hours'",
+            )
+            .unwrap()
+        };
+        let name = SubgraphName::new("test/store").unwrap();
+        let retention = Duration::from_secs(60 * 60);
+
+        store.remove_subgraph(name.clone()).unwrap();
+        assert_eq!(1, store.store().unused_deployments().unwrap().len());
+        make_unused_for_two_hours();
+
+        // Using the deployment again, even briefly, starts the retention
+        // period over
+        deploy_test_subgraph(&store);
+        store.remove_subgraph(name).unwrap();
+        assert_eq!(1, store.store().unused_deployments().unwrap().len());
+        assert!(store
+            .store()
+            .remove_expired_unused_deployments(retention)
+            .unwrap()
+            .is_empty());
+
+        // Once the retention period has passed, the deployment is removed
+        // and subscribers learn about that
+        make_unused_for_two_hours();
+        let mut removed = Vec::new();
+        let events = tap_store_events(|| {
+            removed = store
+                .store()
+                .remove_expired_unused_deployments(retention)
+                .unwrap()
+        });
+        assert_eq!(vec![TEST_SUBGRAPH_ID.clone()], removed);
+        assert_eq!(1, events.len());
+        assert!(events[0].changes.iter().any(|change| {
+            change.entity_type == SubgraphDeploymentEntity::TYPENAME
+                && change.entity_id == TEST_SUBGRAPH_ID.as_str()
+                && change.operation == EntityChangeOperation::Removed
+        }));
+        assert!(store.store().unused_deployments().unwrap().is_empty());
+        assert!(!store.is_deployed(&TEST_SUBGRAPH_ID).unwrap());
+        Ok(())
+    })
+}
+
+#[test]
+fn export_and_import_entities() {
+    run_test(|store| -> Result<(), ()> {
+        let dir =
+            std::env::temp_dir().join(format!("graph-node-export-test-{}", uuid::Uuid::new_v4()));
+        let target = SubgraphDeploymentId::new("exportImportTarget").unwrap();
+        create_test_subgraph(&target, USER_GQL);
+        store.unassign_subgraph(&target).unwrap();
+
+        let export = store
+            .store()
+            .export_entities(&TEST_SUBGRAPH_ID, None, &dir)
+            .unwrap();
+        assert_eq!(TEST_BLOCK_2_PTR.number as BlockNumber, export.block_number);
+        let users = export
+            .entity_types
+            .iter()
+            .find(|entity_type| entity_type.name == USER)
+            .unwrap();
+        assert_eq!(3, users.count);
+
+        let import = store.store().import_entities(&target, &dir).unwrap();
+        assert_eq!(TEST_SUBGRAPH_ID.as_str(), import.deployment);
+        assert_eq!(
+            Some(*TEST_BLOCK_2_PTR),
+            store.block_ptr(target.clone()).unwrap()
+        );
+        assert_eq!(3, get_entity_count(store.clone(), &target));
+
+        // The imported entities are the same as the exported ones
+        let query = |id: &SubgraphDeploymentId| {
+            EntityQuery::new(
+                id.clone(),
+                BLOCK_NUMBER_MAX,
+                EntityCollection::All(vec![USER.to_owned()]),
+            )
+            .asc("id")
+        };
+        assert_eq!(
+            store.find(query(&TEST_SUBGRAPH_ID)).unwrap(),
+            store.find(query(&target)).unwrap()
+        );
+
+        // The target has indexed blocks now and can not be imported into again
+        assert!(store.store().import_entities(&target, &dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        Ok(())
+    })
+}
+
+#[test]
+fn cancel_running_query() {
+    let setup = || {
+        remove_test_data(STORE.clone());
+        insert_test_data(STORE.clone());
+    };
+    run_test_sequentially(setup, |store, _| async move {
+        // Make queries against `User` wait by locking its table from
+        // another connection
+        let lock_conn = PgConnection::establish(&postgres_test_url()).unwrap();
+        lock_conn
+            .batch_execute(&format!(
+                "begin;
+                 do $$ begin
+                   execute format('lock table %I.\"user\" in access exclusive mode',
+                                  (select name from public.deployment_schemas
+                                    where subgraph = '{}'));
+                 end $$;",
+                TEST_SUBGRAPH_ID.as_str()
+            ))
+            .unwrap();
+
+        let guard = CancelGuard::new();
+        let mut query = user_query();
+        query.cancel = Some(guard.handle());
+        let query_store = store.clone().query_store(false);
+        let start = Instant::now();
+        let running =
+            graph::spawn_blocking_allow_panic(move || query_store.find_query_values(query));
+
+        // Give the query time to start waiting for the lock, then cancel it
+        tokio::time::delay_for(Duration::from_millis(500)).await;
+        guard.cancel();
+        let result = tokio::time::timeout(Duration::from_secs(10), running)
+            .await
+            .expect("canceling the query makes it stop waiting for the lock")
+            .unwrap();
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        lock_conn.batch_execute("commit").unwrap();
+
+        // Once the table is unlocked, queries work again
+        assert_eq!(3, store.find(user_query()).unwrap().len());
+    })
+}
+
+/// Check that the proof of indexing that the deployment recorded for
+/// `block`, which is what `proofOfIndexingAtBlock` reports, is the one that
+/// is computed from the digests of the causality regions as of that block
+async fn check_block_proof_of_indexing(
+    store: &DieselStore,
+    block: &EthereumBlockPointer,
+) -> [u8; 32] {
+    let poi = store
+        .get_block_proof_of_indexing(&TEST_SUBGRAPH_ID, block.number as BlockNumber)
+        .unwrap()
+        .expect("a proof of indexing was recorded for the block");
+    assert_eq!(*block, poi.block);
+    assert_eq!(PROOF_OF_INDEXING_VERSION, poi.version);
+    let computed = store
+        .get_proof_of_indexing(&TEST_SUBGRAPH_ID, &None, block.hash)
+        .await
+        .unwrap();
+    assert_eq!(Some(poi.digest), computed);
+    poi.digest
+}
+
+#[test]
+fn block_proofs_of_indexing() {
+    let setup = || {
+        remove_test_data(STORE.clone());
+        insert_test_data(STORE.clone());
+    };
+    run_test_sequentially(setup, |store, _| async move {
+        let user =
+            |email: &str| create_test_entity("1", USER, "Johnton", email, 67, 184.4, false, None);
+
+        // Every block that `insert_test_data` wrote has a proof of indexing
+        let mut digests = HashSet::new();
+        for block in &[*GENESIS_PTR, *TEST_BLOCK_1_PTR, *TEST_BLOCK_2_PTR] {
+            digests.insert(check_block_proof_of_indexing(&store, block).await);
+        }
+        assert_eq!(3, digests.len());
+
+        // Change the digest of a causality region
+        let op = poi_entity(&TEST_SUBGRAPH_ID, &TEST_BLOCK_3_PTR);
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![op],
+        )
+        .unwrap();
+        let poi3 = check_block_proof_of_indexing(&store, &TEST_BLOCK_3_PTR).await;
+
+        // A block that does not change any digests still includes them
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_4_PTR,
+            vec![user("block4@email.com")],
+        )
+        .unwrap();
+        check_block_proof_of_indexing(&store, &TEST_BLOCK_4_PTR).await;
+
+        // Reverting a block forgets its proof of indexing; asking for it
+        // now returns the one for the block before
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_4_PTR,
+                *TEST_BLOCK_3_PTR,
+            )
+            .unwrap();
+        let poi = store
+            .get_block_proof_of_indexing(&TEST_SUBGRAPH_ID, TEST_BLOCK_4_PTR.number as BlockNumber)
+            .unwrap()
+            .unwrap();
+        assert_eq!(*TEST_BLOCK_3_PTR, poi.block);
+        assert_eq!(poi3, poi.digest);
+
+        // The block that replaces the reverted one gets its own proof
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_4A_PTR,
+            vec![user("block4a@email.com")],
+        )
+        .unwrap();
+        check_block_proof_of_indexing(&store, &TEST_BLOCK_4A_PTR).await;
+        let poi = store
+            .get_block_proof_of_indexing(&TEST_SUBGRAPH_ID, BLOCK_NUMBER_MAX)
+            .unwrap()
+            .unwrap();
+        assert_eq!(*TEST_BLOCK_4A_PTR, poi.block);
+    })
+}
+
+#[test]
+fn aggregation_rollups() {
+    const GQL: &str = "
+        type Swap @entity(timeseries: true) {
+            id: ID!, timestamp: Int!, amount: BigInt!, fee: Int!
+        }
+        type SwapStats @entity @aggregation(source: \"Swap\", intervals: [\"hour\", \"day\"]) {
+            id: ID!, timestamp: Int!, interval: String!,
+            volume: BigInt! @aggregate(fn: \"sum\", arg: \"amount\")
+            fees: Int! @aggregate(fn: \"sum\", arg: \"fee\")
+            maxFee: Int! @aggregate(fn: \"max\", arg: \"fee\")
+            swaps: Int! @aggregate(fn: \"count\")
+        }";
+
+    run_test(|store| -> Result<(), ()> {
+        let subgraph_id = SubgraphDeploymentId::new("aggregationRollups").unwrap();
+        create_test_subgraph(&subgraph_id, GQL);
+
+        let key = |entity_type: &str, entity_id: &str| EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: entity_type.to_owned(),
+            entity_id: entity_id.to_owned(),
+        };
+        let swap = |id: &str, timestamp: i32, amount: i32, fee: i32| {
+            let mut data = Entity::new();
+            data.set("id", id);
+            data.set("timestamp", timestamp);
+            data.set("amount", BigInt::from(amount));
+            data.set("fee", fee);
+            EntityOperation::Set {
+                key: key("Swap", id),
+                data,
+            }
+        };
+        let stats = |id: &str| store.get(key("SwapStats", id)).unwrap();
+        let check = |id: &str, timestamp: i32, volume: i32, fees: i32, max_fee: i32, swaps: i32| {
+            let stats = stats(id).expect("the bucket exists");
+            assert_eq!(Some(&Value::Int(timestamp)), stats.get("timestamp"));
+            assert_eq!(Some(&Value::BigInt(volume.into())), stats.get("volume"));
+            assert_eq!(Some(&Value::Int(fees)), stats.get("fees"));
+            assert_eq!(Some(&Value::Int(max_fee)), stats.get("maxFee"));
+            assert_eq!(Some(&Value::Int(swaps)), stats.get("swaps"));
+        };
+
+        transact_entity_operations(
+            &store,
+            subgraph_id.clone(),
+            *TEST_BLOCK_1_PTR,
+            vec![swap("s1", 3600, 10, 1), swap("s2", 3700, 20, 5)],
+        )
+        .unwrap();
+        check("hour-3600", 3600, 30, 6, 5, 2);
+        check("day-0", 0, 30, 6, 5, 2);
+
+        transact_entity_operations(
+            &store,
+            subgraph_id.clone(),
+            *TEST_BLOCK_2_PTR,
+            vec![swap("s3", 7200, 5, 2)],
+        )
+        .unwrap();
+        check("hour-3600", 3600, 30, 6, 5, 2);
+        check("hour-7200", 7200, 5, 2, 2, 1);
+        check("day-0", 0, 35, 8, 5, 3);
+
+        // Reverting a block also reverts the rollups
+        store
+            .revert_block_operations(subgraph_id.clone(), *TEST_BLOCK_2_PTR, *TEST_BLOCK_1_PTR)
+            .unwrap();
+        assert!(stats("hour-7200").is_none());
+        check("day-0", 0, 30, 6, 5, 2);
+
+        // An Int aggregate that overflows is an error, not a wrapped value
+        let res = transact_entity_operations(
+            &store,
+            subgraph_id.clone(),
+            *TEST_BLOCK_2_PTR,
+            vec![swap("s4", 7200, 1, std::i32::MAX)],
+        );
+        assert!(res.is_err());
+        check("day-0", 0, 30, 6, 5, 2);
+
+        // Bucket timestamps that do not fit into an Int are rejected
+        let res = transact_entity_operations(
+            &store,
+            subgraph_id.clone(),
+            *TEST_BLOCK_2_PTR,
+            vec![swap("s5", std::i32::MIN, 1, 1)],
+        );
+        assert!(res.is_err());
+        Ok(())
+    })
+}
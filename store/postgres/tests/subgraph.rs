@@ -6,6 +6,7 @@ use graph::{
     prelude::EntityChange,
     prelude::EntityChangeOperation,
     prelude::EntityKey,
+    prelude::Failover,
     prelude::Schema,
     prelude::StoreEvent,
     prelude::SubgraphDeploymentEntity,
@@ -16,7 +17,8 @@ use graph::{
     prelude::{NodeId, Store as _, SubgraphDeploymentId},
 };
 use graph_store_postgres::NetworkStore;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
 use test_store::*;
 
 const SUBGRAPH_GQL: &str = "
@@ -83,6 +85,183 @@ fn reassign_subgraph() {
     })
 }
 
+/// Run the SQL statements in `query` against the test database
+fn execute(query: &str) {
+    use diesel::connection::SimpleConnection as _;
+    use diesel::{Connection, PgConnection};
+
+    let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+    conn.batch_execute(query).unwrap();
+}
+
+/// Whether `node` has a heartbeat in the database
+fn has_heartbeat(node: &NodeId) -> bool {
+    use diesel::dsl::sql;
+    use diesel::sql_types::Bool;
+    use diesel::{Connection, PgConnection, RunQueryDsl};
+
+    let conn = PgConnection::establish(&postgres_test_url()).unwrap();
+    diesel::select(sql::<Bool>(&format!(
+        "exists (select 1 from subgraphs.node_heartbeat where node_id = '{}')",
+        node
+    )))
+    .get_result::<bool>(&conn)
+    .unwrap()
+}
+
+#[test]
+fn fail_over_dead_nodes() {
+    fn setup() -> SubgraphDeploymentId {
+        let id = SubgraphDeploymentId::new("failOverDeadNodes").unwrap();
+        remove_subgraphs();
+        create_test_subgraph(&id, SUBGRAPH_GQL);
+        execute("delete from subgraphs.node_heartbeat");
+        // Give the deployment a data source on mainnet
+        execute(&format!(
+            "delete from subgraphs.ethereum_contract_data_source where id = '{id}-ds';
+             insert into subgraphs.ethereum_contract_data_source
+                    (id, kind, name, network, source, mapping, block_range)
+             values ('{id}-ds', 'ethereum/contract', 'ds', 'mainnet',
+                     '{id}-source', '{id}-mapping', '[0,)');
+             update subgraphs.subgraph_manifest
+                set data_sources = array['{id}-ds']
+              where id = '{id}-manifest'",
+            id = id
+        ));
+        id
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        let dead = NodeId::new("test").unwrap();
+        let alive = NodeId::new("failover_target").unwrap();
+        // Sorts before `alive` and has no deployments, but does not
+        // index mainnet
+        let other_network = NodeId::new("another_network").unwrap();
+        let gone = NodeId::new("gone").unwrap();
+        let timeout = Duration::from_secs(1);
+        let downtime = Duration::from_secs(3600);
+        let mainnet = vec!["mainnet".to_string()];
+
+        store.record_heartbeat(&dead, true, &mainnet).unwrap();
+        store.record_heartbeat(&alive, true, &mainnet).unwrap();
+        store
+            .record_heartbeat(&other_network, true, &["ropsten".to_string()])
+            .unwrap();
+        store.record_heartbeat(&gone, false, &[]).unwrap();
+        assert_eq!(
+            Vec::<Failover>::new(),
+            store.fail_over_dead_nodes(timeout, downtime).unwrap()
+        );
+
+        std::thread::sleep(timeout + Duration::from_millis(100));
+        store.record_heartbeat(&alive, true, &mainnet).unwrap();
+        store
+            .record_heartbeat(&other_network, true, &["ropsten".to_string()])
+            .unwrap();
+
+        let expected = vec![StoreEvent::new(vec![set(
+            MetadataType::SubgraphDeploymentAssignment,
+            id.as_str(),
+        )])];
+        let mut failovers = vec![];
+        let events =
+            tap_store_events(|| failovers = store.fail_over_dead_nodes(timeout, downtime).unwrap());
+        assert_eq!(expected, events);
+        assert_eq!(
+            vec![Failover {
+                deployment: id.clone(),
+                from: dead.clone(),
+                to: Some(alive.clone()),
+            }],
+            failovers
+        );
+
+        let infos = store.deployment_infos(None, Some(&id)).unwrap();
+        assert_eq!(Some("failover_target"), infos[0].node_id.as_deref());
+
+        // Dead nodes without deployments are forgotten
+        assert!(!has_heartbeat(&dead));
+        assert!(!has_heartbeat(&gone));
+        assert!(has_heartbeat(&alive));
+
+        // The dead node has nothing left to take away
+        assert!(store
+            .fail_over_dead_nodes(timeout, downtime)
+            .unwrap()
+            .is_empty());
+
+        // A node that stopped cleanly keeps its deployments during the
+        // planned downtime
+        store.record_stop(&alive).unwrap();
+        std::thread::sleep(timeout + Duration::from_millis(100));
+        store
+            .record_heartbeat(&other_network, true, &["ropsten".to_string()])
+            .unwrap();
+        assert!(store
+            .fail_over_dead_nodes(timeout, downtime)
+            .unwrap()
+            .is_empty());
+
+        // Once the downtime is over, the deployment has to move, but the
+        // only live node does not index mainnet
+        let events =
+            tap_store_events(|| failovers = store.fail_over_dead_nodes(timeout, timeout).unwrap());
+        assert!(events.is_empty());
+        assert_eq!(
+            vec![Failover {
+                deployment: id.clone(),
+                from: alive.clone(),
+                to: None,
+            }],
+            failovers
+        );
+        let infos = store.deployment_infos(None, Some(&id)).unwrap();
+        assert_eq!(Some("failover_target"), infos[0].node_id.as_deref());
+    })
+}
+
+#[test]
+fn unassign_subgraph() {
+    fn setup() -> SubgraphDeploymentId {
+        let id = SubgraphDeploymentId::new("unassignSubgraph").unwrap();
+        remove_subgraphs();
+        create_test_subgraph(&id, SUBGRAPH_GQL);
+        id
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        let infos = store.deployment_infos(None, Some(&id)).unwrap();
+        assert_eq!(1, infos.len());
+        assert_eq!(Some("unassignSubgraph"), infos[0].subgraph.as_deref());
+        assert_eq!(Some("current"), infos[0].version.as_deref());
+        assert_eq!(Some("test"), infos[0].node_id.as_deref());
+
+        // Name and deployment are separate filters that must both match
+        let name = SubgraphName::new("unassignSubgraph").unwrap();
+        let infos = store.deployment_infos(Some(&name), None).unwrap();
+        assert_eq!(1, infos.len());
+        let other = SubgraphName::new("noSuchSubgraph").unwrap();
+        assert!(store
+            .deployment_infos(Some(&other), Some(&id))
+            .unwrap()
+            .is_empty());
+
+        let expected = vec![StoreEvent::new(vec![removed(
+            MetadataType::SubgraphDeploymentAssignment,
+            id.as_str(),
+        )])];
+        let events = tap_store_events(|| store.unassign_subgraph(&id).unwrap());
+        assert_eq!(expected, events);
+
+        let infos = store.deployment_infos(None, None).unwrap();
+        assert_eq!(1, infos.len());
+        assert_eq!(None, infos[0].node_id);
+
+        // There is nothing left to unassign
+        assert!(store.unassign_subgraph(&id).is_err());
+    })
+}
+
 #[test]
 fn create_subgraph() {
     const SUBGRAPH_NAME: &str = "create/subgraph";
@@ -152,6 +331,9 @@ fn create_subgraph() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            independent_data_sources: false,
+            features: BTreeSet::new(),
+            overrides: None,
         };
         let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
         let node_id = NodeId::new("left").unwrap();
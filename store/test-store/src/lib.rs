@@ -2,7 +2,10 @@
 extern crate diesel;
 
 use crate::tokio::runtime::{Builder, Runtime};
+use graph::components::subgraph::{ProofOfIndexing, ProofOfIndexingEvent};
 use graph::data::graphql::effort::LoadManager;
+use graph::data::store::scalar;
+use graph::data::subgraph::schema::POI_OBJECT;
 use graph::log;
 use graph::prelude::{Store as _, *};
 use graph_graphql::prelude::{
@@ -11,10 +14,11 @@ use graph_graphql::prelude::{
 use graph_mock::MockMetricsRegistry;
 use graph_store_postgres::connection_pool::ConnectionPool;
 use graph_store_postgres::{
-    ChainHeadUpdateListener, ChainStore, NetworkStore, Store, SubscriptionManager,
+    ChainHeadUpdateListener, ChainStore, EnsNames, NetworkStore, Store, SubscriptionManager,
 };
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeSet;
 use std::env;
 use std::sync::Mutex;
 use std::time::Instant;
@@ -89,6 +93,15 @@ lazy_static! {
         }).join().unwrap()
     };
 
+    // ENS names are looked up in the test database
+    pub static ref ENS_LOOKUP: Arc<EnsNames> = Arc::new(EnsNames::new(ConnectionPool::create(
+        "ens",
+        postgres_test_url(),
+        2,
+        &*LOGGER,
+        Arc::new(MockMetricsRegistry::new()),
+    )));
+
     pub static ref GENESIS_PTR: EthereumBlockPointer = (
         H256::from(hex!("bd34884280958002c51d3f7b5f853e6febeba33de0f40d15b0363006533c924f")),
         0u64
@@ -154,6 +167,9 @@ fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        independent_data_sources: false,
+        features: BTreeSet::new(),
+        overrides: None,
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, None).graft(base);
@@ -211,6 +227,36 @@ pub fn transact_entity_operations(
     store.transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch_metrics)
 }
 
+/// An operation that sets the digest of the causality region `mainnet` of
+/// `subgraph_id` to one that is different for each block
+pub fn poi_entity(
+    subgraph_id: &SubgraphDeploymentId,
+    block: &EthereumBlockPointer,
+) -> EntityOperation {
+    let mut poi = ProofOfIndexing::new(block.number);
+    poi.write(
+        &*LOGGER,
+        "mainnet",
+        &ProofOfIndexingEvent::RemoveEntity {
+            entity_type: "User",
+            id: "1",
+        },
+    );
+    let digest = poi.take().remove("mainnet").unwrap().pause(None);
+
+    let mut data = Entity::new();
+    data.set("id", "mainnet");
+    data.set("digest", scalar::Bytes::from(&digest[..]));
+    EntityOperation::Set {
+        key: EntityKey {
+            subgraph_id: subgraph_id.clone(),
+            entity_type: POI_OBJECT.to_owned(),
+            entity_id: "mainnet".to_owned(),
+        },
+        data,
+    }
+}
+
 pub fn insert_ens_name(hash: &str, name: &str) {
     use diesel::insert_into;
     use diesel::prelude::*;